@@ -2,7 +2,7 @@ use framework::{renderer::renderer::Renderer, Framework};
 use wgpu::{Surface, SurfaceConfiguration, TextureView};
 use winit::{dpi::PhysicalSize, event::Event, window::Window};
 
-use crate::{ActionMap, InputState};
+use crate::{ActionMap, FrameSettings, InputState};
 
 pub struct AppContext<'a> {
     pub renderer: &'a mut Renderer,
@@ -40,6 +40,10 @@ pub trait AppLoop<T, A: Clone> {
     fn should_render(&self) -> bool {
         true
     }
+    // Read every update, changing the present mode reconfigures the surface
+    fn frame_settings(&self) -> FrameSettings {
+        FrameSettings::default()
+    }
 
     fn setup_action_map(&self, _action_map: &mut ActionMap<A>) {}
 
@@ -1,4 +1,4 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, time::Instant};
 
 use framework::{renderer::renderer::Renderer, Framework};
 use wgpu::{Surface, SurfaceConfiguration, TextureViewDescriptor};
@@ -11,7 +11,7 @@ use winit::{
 
 use crate::{
     app_loop::{AppContext, AppLoop},
-    ActionMap, AppBoot, InputState,
+    ActionMap, AppBoot, FrameSettings, InputState,
 };
 
 pub struct AppDescription {
@@ -29,6 +29,8 @@ struct AppState<E, A: Clone, T: AppLoop<E, A>> {
     action_map: ActionMap<A>,
     surface: Surface,
     surface_configuration: SurfaceConfiguration,
+    frame_settings: FrameSettings,
+    last_frame: Instant,
 }
 
 pub struct Application<U: 'static> {
@@ -51,7 +53,8 @@ impl<U: 'static> Application<U> {
     }
 
     pub fn run<A: Clone + 'static, T: AppLoop<U, A> + 'static>(self) -> anyhow::Result<()> {
-        let input_state = InputState::new();
+        let mut input_state = InputState::new();
+        input_state.set_window_metrics(self.window.inner_size(), self.window.scale_factor());
 
         let mut framework = Framework::new(&wgpu::DeviceDescriptor {
             label: Some("Image Editor framework"),
@@ -63,9 +66,11 @@ impl<U: 'static> Application<U> {
         })?;
 
         let surface = unsafe { framework.instance.create_surface(&self.window) };
+        let frame_settings = FrameSettings::default();
         let surface_configuration = application_functions::create_surface(
             &surface,
             self.window.inner_size(),
+            frame_settings.present_mode,
             &mut framework,
         );
 
@@ -89,6 +94,8 @@ impl<U: 'static> Application<U> {
             surface,
             action_map,
             surface_configuration,
+            frame_settings,
+            last_frame: Instant::now(),
         }));
 
         self.event_loop.run(move |event, _, control_flow| {
@@ -106,6 +113,7 @@ impl<U: 'static> Application<U> {
             );
 
             application_functions::update_application(state);
+            application_functions::update_frame_settings(state);
             match event {
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::CloseRequested if state.instance.can_shutdown() => {
@@ -119,6 +127,7 @@ impl<U: 'static> Application<U> {
                 },
 
                 Event::UserEvent(_) => {}
+                Event::LoopDestroyed => state.instance.shutdown(),
                 Event::MainEventsCleared => {}
                 Event::RedrawEventsCleared => {
                     if state.instance.should_render() {
@@ -127,6 +136,7 @@ impl<U: 'static> Application<U> {
                 }
                 Event::RedrawRequested(_) => {
                     application_functions::render_application(state);
+                    application_functions::limit_frame_rate(state);
                 }
                 _ => {}
             };
@@ -143,20 +153,30 @@ impl<U: 'static> Application<U> {
 }
 
 mod application_functions {
-    use crate::AppResized;
+    use crate::{select_present_mode, AppResized};
 
     use super::*;
     pub(super) fn create_surface(
         surface: &Surface,
         surface_size: PhysicalSize<u32>,
+        present_mode: wgpu::PresentMode,
         framework: &mut Framework,
     ) -> SurfaceConfiguration {
+        let supported_modes = surface.get_supported_present_modes(&framework.adapter);
+        let selected_mode = select_present_mode(present_mode, &supported_modes);
+        if selected_mode != present_mode {
+            log::warn!(
+                "Present mode {:?} is not supported, using {:?}",
+                present_mode,
+                selected_mode
+            );
+        }
         let surface_configuration = SurfaceConfiguration {
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface.get_supported_formats(&framework.adapter)[0],
             width: surface_size.width,
             height: surface_size.height,
-            present_mode: wgpu::PresentMode::Immediate,
+            present_mode: selected_mode,
             alpha_mode: wgpu::CompositeAlphaMode::Opaque,
         };
         surface.configure(&framework.device, &surface_configuration);
@@ -168,8 +188,36 @@ mod application_functions {
             framework: &mut state.framework,
             input_state: &mut state.input_state,
         });
+        state.framework.poll_device();
         state.framework.update_asset_maps();
     }
+    // Reconfigures the surface when the application asks for another present mode
+    pub(super) fn update_frame_settings<E, A: Clone, T: AppLoop<E, A>>(
+        state: &mut AppState<E, A, T>,
+    ) {
+        let frame_settings = state.instance.frame_settings();
+        if frame_settings.present_mode != state.frame_settings.present_mode {
+            state.surface_configuration = create_surface(
+                &state.surface,
+                PhysicalSize::new(
+                    state.surface_configuration.width,
+                    state.surface_configuration.height,
+                ),
+                frame_settings.present_mode,
+                &mut state.framework,
+            );
+        }
+        state.frame_settings = frame_settings;
+    }
+    pub(super) fn limit_frame_rate<E, A: Clone, T: AppLoop<E, A>>(state: &mut AppState<E, A, T>) {
+        if let Some(min_frame_time) = state.frame_settings.min_frame_time() {
+            let elapsed = state.last_frame.elapsed();
+            if elapsed < min_frame_time {
+                std::thread::sleep(min_frame_time - elapsed);
+            }
+        }
+        state.last_frame = Instant::now();
+    }
     pub(super) fn render_application<E, A: Clone, T: AppLoop<E, A>>(state: &mut AppState<E, A, T>) {
         if let Ok(next_texture) = state.surface.get_current_texture() {
             let texture_view = next_texture
@@ -185,6 +233,7 @@ mod application_functions {
             );
             next_texture.present();
         }
+        state.framework.end_frame();
     }
     pub(super) fn on_resized<E, A: Clone, T: AppLoop<E, A>>(
         state: &mut AppState<E, A, T>,
@@ -193,8 +242,12 @@ mod application_functions {
         if new_size.height == 0 || new_size.width == 0 {
             return;
         }
-        let surface_configuration =
-            application_functions::create_surface(&state.surface, new_size, &mut state.framework);
+        let surface_configuration = application_functions::create_surface(
+            &state.surface,
+            new_size,
+            state.frame_settings.present_mode,
+            &mut state.framework,
+        );
         state.surface_configuration = surface_configuration;
         state.instance.on_resized(AppResized {
             framework: &mut state.framework,
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use wgpu::PresentMode;
+
+// The present modes the user can pick from, Fifo is vsync
+pub const SELECTABLE_PRESENT_MODES: [PresentMode; 3] = [
+    PresentMode::Fifo,
+    PresentMode::Mailbox,
+    PresentMode::Immediate,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameSettings {
+    pub present_mode: PresentMode,
+    // When set, the application sleeps after each frame to stay below this frame rate
+    pub max_fps: Option<u32>,
+}
+
+impl Default for FrameSettings {
+    fn default() -> Self {
+        Self {
+            present_mode: PresentMode::Immediate,
+            max_fps: None,
+        }
+    }
+}
+
+impl FrameSettings {
+    pub fn min_frame_time(&self) -> Option<Duration> {
+        self.max_fps
+            .filter(|fps| *fps > 0)
+            .map(|fps| Duration::from_nanos(1_000_000_000 / fps as u64))
+    }
+}
+
+// Picks the requested present mode when the surface supports it, otherwise the closest one:
+// the non vsync modes fall back on each other, then everything falls back to Fifo,
+// which every surface must support
+pub fn select_present_mode(requested: PresentMode, supported: &[PresentMode]) -> PresentMode {
+    let fallbacks: &[PresentMode] = match requested {
+        PresentMode::Mailbox => &[PresentMode::Mailbox, PresentMode::Immediate],
+        PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox],
+        _ => &[requested],
+    };
+    fallbacks
+        .iter()
+        .chain(std::iter::once(&PresentMode::Fifo))
+        .find(|mode| supported.contains(mode))
+        .or_else(|| supported.first())
+        .copied()
+        .unwrap_or(PresentMode::Fifo)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use wgpu::PresentMode::*;
+
+    use super::{select_present_mode, FrameSettings};
+
+    #[test]
+    fn supported_modes_are_kept() {
+        let supported = [Fifo, Mailbox, Immediate];
+        for mode in supported {
+            assert_eq!(select_present_mode(mode, &supported), mode);
+        }
+    }
+
+    #[test]
+    fn unsupported_modes_fall_back() {
+        assert_eq!(select_present_mode(Mailbox, &[Fifo, Immediate]), Immediate);
+        assert_eq!(select_present_mode(Immediate, &[Fifo, Mailbox]), Mailbox);
+        assert_eq!(select_present_mode(Immediate, &[Fifo]), Fifo);
+        assert_eq!(select_present_mode(Mailbox, &[FifoRelaxed, Fifo]), Fifo);
+        // A surface without Fifo shouldn't exist, but don't pick an unsupported mode anyway
+        assert_eq!(select_present_mode(Fifo, &[Immediate]), Immediate);
+        assert_eq!(select_present_mode(Fifo, &[]), Fifo);
+    }
+
+    #[test]
+    fn frame_rate_cap() {
+        let uncapped = FrameSettings::default();
+        assert_eq!(uncapped.min_frame_time(), None);
+        let capped = FrameSettings {
+            max_fps: Some(50),
+            ..uncapped
+        };
+        assert_eq!(capped.min_frame_time(), Some(Duration::from_millis(20)));
+        let zero = FrameSettings {
+            max_fps: Some(0),
+            ..uncapped
+        };
+        assert_eq!(zero.min_frame_time(), None);
+    }
+}
@@ -355,6 +355,7 @@ pub enum Modifier {
     // Support for right modifiers will be added when winit does so
     LeftShift,
     LeftCtrl,
+    // Also held with the right Alt key, which the InputState tracks since winit doesn't
     LeftAlt,
 
     Meta,
@@ -366,6 +367,7 @@ pub struct ModifierSet {
 }
 
 impl ModifierSet {
+    // The order of the arguments is shift, alt, ctrl, meta (the Super/Logo key)
     pub fn new(left_shift: bool, left_alt: bool, left_ctrl: bool, meta: bool) -> Self {
         let mut set = ModifierSet::default();
         set.modifiers[Modifier::LeftShift as usize] = left_shift;
@@ -381,12 +383,37 @@ impl ModifierSet {
     pub fn left_alt(&self) -> bool {
         self.modifiers[Modifier::LeftAlt as usize]
     }
+    // Either Alt key is held
+    pub fn alt(&self) -> bool {
+        self.left_alt()
+    }
     pub fn left_shift(&self) -> bool {
         self.modifiers[Modifier::LeftShift as usize]
     }
     pub fn meta(&self) -> bool {
         self.modifiers[Modifier::Meta as usize]
     }
+
+    pub fn is_held(&self, modifier: Modifier) -> bool {
+        self.modifiers[modifier as usize]
+    }
+
+    pub(crate) fn set_held(&mut self, modifier: Modifier, held: bool) {
+        self.modifiers[modifier as usize] = held;
+    }
+
+    // True when exactly the same modifiers are held, e.g. Ctrl+Shift doesn't match Ctrl
+    pub fn matches_exactly(&self, other: &ModifierSet) -> bool {
+        self == other
+    }
+
+    // True when all the modifiers of other are held, even if other modifiers are held too
+    pub fn contains(&self, other: &ModifierSet) -> bool {
+        self.modifiers
+            .iter()
+            .zip(other.modifiers.iter())
+            .all(|(held, wanted)| *held || !*wanted)
+    }
 }
 
 impl From<u32> for ModifierSet {
@@ -1,15 +1,17 @@
 pub mod action_map;
 pub mod key;
+pub mod pressure_calibration;
 
 pub use action_map::*;
 pub use key::*;
+pub use pressure_calibration::*;
 
 use std::collections::HashMap;
 
 use cgmath::{Point2, Vector2};
 use strum::EnumCount;
 use winit::{
-    dpi::{PhysicalPosition, PhysicalSize},
+    dpi::{LogicalPosition, LogicalSize, PhysicalPosition, PhysicalSize},
     event::{ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta},
 };
 
@@ -21,6 +23,8 @@ pub struct InputState {
     last_update_cursor_position: PhysicalPosition<f32>,
     current_pointer_pressure: f32,
     window_size: PhysicalSize<u32>,
+    // Logical points per physical pixel, e.g. 2.0 on HiDPI displays
+    scale_factor: f64,
     current_wheel_delta: f32,
 
     pointer_button_state: HashMap<MouseButton, ElementState>,
@@ -33,12 +37,13 @@ pub struct InputState {
 }
 
 impl InputState {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             current_cursor_position: Default::default(),
             last_update_cursor_position: Default::default(),
             current_pointer_pressure: Default::default(),
             window_size: Default::default(),
+            scale_factor: 1.0,
             current_wheel_delta: 0.0,
             pointer_button_state: Default::default(),
             last_button_state: Default::default(),
@@ -49,7 +54,7 @@ impl InputState {
         }
     }
 
-    pub(crate) fn update<T: 'static>(&mut self, event: &winit::event::Event<T>) {
+    pub fn update<T: 'static>(&mut self, event: &winit::event::Event<T>) {
         self.last_button_state = self.pointer_button_state.clone();
         self.last_key_states = self.key_states.clone();
         self.last_modifiers = self.current_modifiers.clone();
@@ -57,6 +62,10 @@ impl InputState {
         match event {
             winit::event::Event::WindowEvent { event, .. } => match event {
                 winit::event::WindowEvent::Resized(new_size) => self.window_size = *new_size,
+                winit::event::WindowEvent::ScaleFactorChanged {
+                    scale_factor,
+                    new_inner_size,
+                } => self.set_window_metrics(**new_inner_size, *scale_factor),
                 winit::event::WindowEvent::Moved(_) => {}
                 winit::event::WindowEvent::CloseRequested => {}
                 winit::event::WindowEvent::DroppedFile(_) => {}
@@ -137,6 +146,13 @@ impl InputState {
         }
     }
 
+    // The window size and scale factor are only sent on changes, so they're
+    // read from the window when it's created
+    pub fn set_window_metrics(&mut self, window_size: PhysicalSize<u32>, scale_factor: f64) {
+        self.window_size = window_size;
+        self.scale_factor = scale_factor;
+    }
+
     fn set_cursor_button_state(&mut self, button: MouseButton, state: ElementState) {
         self.pointer_button_state
             .entry(button)
@@ -150,6 +166,15 @@ impl InputState {
             y: self.current_cursor_position.y,
         }
     }
+    // In the logical points used by egui, with the y axis going up like mouse_position
+    pub fn logical_mouse_position(&self) -> Point2<f32> {
+        let position: LogicalPosition<f32> =
+            self.current_cursor_position.to_logical(self.scale_factor);
+        Point2 {
+            x: position.x,
+            y: position.y,
+        }
+    }
     #[allow(dead_code)]
     pub fn last_position(&self) -> PhysicalPosition<f32> {
         self.last_update_cursor_position
@@ -224,6 +249,10 @@ impl InputState {
         self.current_pointer_pressure
     }
 
+    pub fn calibrated_pointer_pressure(&self, calibration: &PressureCalibration) -> f32 {
+        calibration.apply(self.current_pointer_pressure)
+    }
+
     pub fn window_size(&self) -> Vector2<u32> {
         Vector2 {
             x: self.window_size.width,
@@ -231,6 +260,18 @@ impl InputState {
         }
     }
 
+    pub fn logical_window_size(&self) -> Vector2<f32> {
+        let size: LogicalSize<f32> = self.window_size.to_logical(self.scale_factor);
+        Vector2 {
+            x: size.width,
+            y: size.height,
+        }
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
     pub fn is_key_just_pressed(&self, key: Key) -> bool {
         self.key_states[key as usize] && !self.last_key_states[key as usize]
     }
@@ -253,18 +294,60 @@ impl InputState {
         &self.current_modifiers
     }
 
+    // True when the chord becomes held in this update: the key is pressed and exactly
+    // the given modifiers are held. The modifiers may be reported right after the key,
+    // so the chord also starts when they change while the key is held
+    pub fn is_chord_just_pressed(&self, key: Key, modifiers: &ModifierSet) -> bool {
+        let held_now =
+            self.key_states[key as usize] && self.current_modifiers.matches_exactly(modifiers);
+        let held_before =
+            self.last_key_states[key as usize] && self.last_modifiers.matches_exactly(modifiers);
+        held_now && !held_before
+    }
+
     fn update_keyboard_state(&mut self, input: &KeyboardInput) {
         if let Some(virtual_key) = input.virtual_keycode {
             let key: Key = virtual_key.into();
             self.key_states[key as usize] = match input.state {
                 ElementState::Pressed => true,
                 ElementState::Released => false,
+            };
+            if key == Key::RAlt {
+                let alt_held = self.key_states[Key::LAlt as usize] || self.is_key_pressed(key);
+                self.current_modifiers.set_held(Modifier::LeftAlt, alt_held);
             }
         }
     }
 
+    // The right Alt key (AltGr) isn't reported as Alt on every platform, so its key is checked too
     fn update_modifiers_state(&mut self, modifiers: &ModifiersState) {
         self.current_modifiers = modifiers.bits().into();
+        if self.is_key_pressed(Key::RAlt) {
+            self.current_modifiers.set_held(Modifier::LeftAlt, true);
+        }
+    }
+}
+
+// The event winit sends when the key is pressed or released, without modifiers:
+// lets the tests drive an InputState
+#[cfg(any(test, feature = "test-utils"))]
+#[allow(deprecated)] // We need ModifiersState::empty() to construct a KeyboardInput
+pub fn key_event(
+    key: winit::event::VirtualKeyCode,
+    state: ElementState,
+) -> winit::event::Event<'static, ()> {
+    winit::event::Event::WindowEvent {
+        window_id: unsafe { winit::window::WindowId::dummy() },
+        event: winit::event::WindowEvent::KeyboardInput {
+            device_id: unsafe { winit::event::DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        },
     }
 }
 
@@ -335,6 +418,27 @@ mod tests {
             &ModifierSet::new(true, true, true, false)
         );
 
+        // Ctrl+Shift contains Ctrl, but doesn't match it exactly
+        let ctrl = ModifierSet::new(false, false, true, false);
+        assert!(input_state.current_modifiers().contains(&ctrl));
+        assert!(!input_state.current_modifiers().matches_exactly(&ctrl));
+        assert!(!ctrl.contains(input_state.current_modifiers()));
+
+        input_state.update::<()>(&Event::WindowEvent {
+            window_id: unsafe { winit::window::WindowId::dummy() },
+            event: WindowEvent::ModifiersChanged(ModifiersState::from_bits_truncate(
+                ModifiersState::LOGO.bits() | ModifiersState::ALT.bits(),
+            )),
+        });
+
+        let modifiers = input_state.current_modifiers();
+        assert!(modifiers.meta() && modifiers.left_alt());
+        assert!(!modifiers.left_shift() && !modifiers.left_ctrl());
+        assert!(modifiers.is_held(Modifier::Meta));
+        assert!(modifiers.matches_exactly(&ModifierSet::new(false, true, false, true)));
+        assert!(modifiers.contains(&ModifierSet::new(false, false, false, true)));
+        assert!(!modifiers.contains(&ModifierSet::new(false, true, true, true)));
+
         input_state.update::<()>(&Event::WindowEvent {
             window_id: unsafe { winit::window::WindowId::dummy() },
             event: WindowEvent::ModifiersChanged(ModifiersState::empty()),
@@ -349,4 +453,120 @@ mod tests {
             &ModifierSet::new(true, false, false, false)
         );
     }
+
+    fn modifiers_event(modifiers: ModifiersState) -> Event<'static, ()> {
+        Event::WindowEvent {
+            window_id: unsafe { winit::window::WindowId::dummy() },
+            event: WindowEvent::ModifiersChanged(modifiers),
+        }
+    }
+
+    #[test]
+    pub fn test_chords() {
+        let ctrl_s = ModifierSet::new(false, false, true, false);
+        let ctrl_shift_s = ModifierSet::new(true, false, true, false);
+        let mut input_state = InputState::new();
+
+        input_state.update(&modifiers_event(ModifiersState::CTRL));
+        assert!(!input_state.is_chord_just_pressed(Key::S, &ctrl_s));
+        input_state.update(&key_event(VirtualKeyCode::S, ElementState::Pressed));
+        assert!(input_state.is_chord_just_pressed(Key::S, &ctrl_s));
+        assert!(!input_state.is_chord_just_pressed(Key::S, &ctrl_shift_s));
+
+        // Key repeats don't trigger the chord again
+        input_state.update(&key_event(VirtualKeyCode::S, ElementState::Pressed));
+        assert!(!input_state.is_chord_just_pressed(Key::S, &ctrl_s));
+        input_state.update(&key_event(VirtualKeyCode::S, ElementState::Released));
+        assert!(!input_state.is_chord_just_pressed(Key::S, &ctrl_s));
+
+        // With an extra modifier held only the exact chord matches
+        input_state.update(&modifiers_event(
+            ModifiersState::CTRL | ModifiersState::SHIFT,
+        ));
+        input_state.update(&key_event(VirtualKeyCode::S, ElementState::Pressed));
+        assert!(!input_state.is_chord_just_pressed(Key::S, &ctrl_s));
+        assert!(input_state.is_chord_just_pressed(Key::S, &ctrl_shift_s));
+        input_state.update(&key_event(VirtualKeyCode::S, ElementState::Released));
+        input_state.update(&modifiers_event(ModifiersState::empty()));
+
+        // The modifiers are reported right after the key
+        input_state.update(&key_event(VirtualKeyCode::S, ElementState::Pressed));
+        assert!(!input_state.is_chord_just_pressed(Key::S, &ctrl_s));
+        input_state.update(&modifiers_event(ModifiersState::CTRL));
+        assert!(input_state.is_chord_just_pressed(Key::S, &ctrl_s));
+    }
+
+    #[test]
+    pub fn test_either_alt_key() {
+        let mut input_state = InputState::new();
+
+        // AltGr may come without a modifiers event
+        input_state.update(&key_event(VirtualKeyCode::RAlt, ElementState::Pressed));
+        assert!(input_state.current_modifiers().alt());
+        input_state.update(&modifiers_event(ModifiersState::empty()));
+        assert!(input_state.current_modifiers().alt());
+        input_state.update(&key_event(VirtualKeyCode::RAlt, ElementState::Released));
+        assert!(!input_state.current_modifiers().alt());
+
+        input_state.update(&key_event(VirtualKeyCode::LAlt, ElementState::Pressed));
+        input_state.update(&modifiers_event(ModifiersState::ALT));
+        assert!(input_state.current_modifiers().alt());
+        // Releasing the right key keeps Alt held while the left one is
+        input_state.update(&key_event(VirtualKeyCode::RAlt, ElementState::Pressed));
+        input_state.update(&key_event(VirtualKeyCode::RAlt, ElementState::Released));
+        assert!(input_state.current_modifiers().alt());
+        input_state.update(&key_event(VirtualKeyCode::LAlt, ElementState::Released));
+        input_state.update(&modifiers_event(ModifiersState::empty()));
+        assert!(!input_state.current_modifiers().alt());
+    }
+
+    #[test]
+    pub fn test_scale_factor() {
+        let mut input_state = InputState::new();
+        input_state.set_window_metrics(PhysicalSize::new(800, 600), 1.0);
+
+        let mut new_inner_size = PhysicalSize::new(1600, 1200);
+        input_state.update::<()>(&Event::WindowEvent {
+            window_id: unsafe { winit::window::WindowId::dummy() },
+            event: WindowEvent::ScaleFactorChanged {
+                scale_factor: 2.0,
+                new_inner_size: &mut new_inner_size,
+            },
+        });
+        assert_eq!(input_state.scale_factor(), 2.0);
+        assert_eq!(input_state.window_size(), Vector2::new(1600, 1200));
+        assert_eq!(
+            input_state.logical_window_size(),
+            Vector2::new(800.0, 600.0)
+        );
+
+        // winit reports the cursor in physical pixels from the top left corner
+        input_state.update::<()>(&Event::WindowEvent {
+            window_id: unsafe { winit::window::WindowId::dummy() },
+            event: WindowEvent::CursorMoved {
+                device_id: unsafe { DeviceId::dummy() },
+                position: PhysicalPosition::new(400.0, 300.0),
+                modifiers: ModifiersState::empty(),
+            },
+        });
+        assert_eq!(input_state.mouse_position(), Point2::new(400.0, 900.0));
+        assert_eq!(
+            input_state.logical_mouse_position(),
+            Point2::new(200.0, 450.0)
+        );
+
+        // The document is reached through the normalized position, which is the same
+        // whether it's computed from the physical or from the logical coordinates
+        let logical = input_state.logical_mouse_position();
+        let logical_size = input_state.logical_window_size();
+        let from_logical = Point2::new(
+            logical.x / logical_size.x * 2.0 - 1.0,
+            logical.y / logical_size.y * 2.0 - 1.0,
+        );
+        assert_eq!(input_state.normalized_mouse_position(), from_logical);
+        assert_eq!(
+            input_state.normalized_mouse_position(),
+            Point2::new(-0.5, 0.5)
+        );
+    }
 }
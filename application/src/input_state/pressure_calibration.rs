@@ -0,0 +1,62 @@
+// Maps the raw pointer pressure (0..1) into the pressure fed to the tools:
+// pressures below min count as no pressure, pressures above max as full pressure
+// and gamma shapes the response curve in between
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PressureCalibration {
+    pub min: f32,
+    pub max: f32,
+    pub gamma: f32,
+}
+
+impl Default for PressureCalibration {
+    fn default() -> Self {
+        Self {
+            min: 0.0,
+            max: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl PressureCalibration {
+    pub fn apply(&self, raw_pressure: f32) -> f32 {
+        if self.max <= self.min {
+            return if raw_pressure >= self.max { 1.0 } else { 0.0 };
+        }
+        let pressure = ((raw_pressure - self.min) / (self.max - self.min)).clamp(0.0, 1.0);
+        pressure.powf(self.gamma.max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PressureCalibration;
+
+    #[test]
+    pub fn default_calibration_does_nothing() {
+        let calibration = PressureCalibration::default();
+        for raw in [0.0, 0.25, 0.5, 1.0] {
+            assert_eq!(calibration.apply(raw), raw);
+        }
+    }
+
+    #[test]
+    pub fn gamma_and_range_shape_the_pressure() {
+        let calibration = PressureCalibration {
+            min: 0.0,
+            max: 1.0,
+            gamma: 2.0,
+        };
+        assert!((calibration.apply(0.5) - 0.25).abs() < 0.0001);
+
+        // 0.5 is halfway between 0.1 and 0.9
+        let calibration = PressureCalibration {
+            min: 0.1,
+            max: 0.9,
+            gamma: 2.0,
+        };
+        assert!((calibration.apply(0.5) - 0.25).abs() < 0.0001);
+        assert_eq!(calibration.apply(0.05), 0.0);
+        assert_eq!(calibration.apply(0.95), 1.0);
+    }
+}
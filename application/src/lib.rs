@@ -1,7 +1,9 @@
 pub mod app_loop;
 pub mod application;
+pub mod frame_settings;
 pub mod input_state;
 
 pub use app_loop::*;
 pub use application::*;
+pub use frame_settings::*;
 pub use input_state::*;
@@ -92,6 +92,28 @@ impl<T> AssetMap<T> {
         id
     }
 
+    // Drops the reference held by the id: the asset is removed right away when no other id
+    // references it, otherwise once the last one is dropped. Returns the removed asset
+    pub(crate) fn remove(&mut self, id: AssetId<T>) -> Option<T> {
+        let index = id.index;
+        drop(id);
+        // The pending events include the ones of the clones of the id, which must be counted
+        // before the asset can be removed
+        let mut removed = None;
+        while let Ok(update) = self.event_receiver.try_recv() {
+            if let Some((freed, asset)) = self.apply_ref_event(update) {
+                if freed == index {
+                    removed = Some(asset);
+                }
+            }
+        }
+        removed
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.map.len()
+    }
+
     pub(crate) fn get(&self, id: &AssetId<T>) -> &T {
         &self
             .map
@@ -109,14 +131,22 @@ impl<T> AssetMap<T> {
     }
     pub(crate) fn update(&mut self) {
         while let Ok(update) = self.event_receiver.try_recv() {
-            match update {
-                RefEvent::IncrementRef(index) => self.increment_ref(index),
-                RefEvent::DecrementRef(index) => self.decremente_ref(index),
-            }
+            self.apply_ref_event(update);
         }
         self.taken_this_update.clear();
     }
 
+    // Returns the asset removed when its last reference was dropped
+    fn apply_ref_event(&mut self, update: RefEvent<Uuid>) -> Option<(Uuid, T)> {
+        match update {
+            RefEvent::IncrementRef(index) => {
+                self.increment_ref(index);
+                None
+            }
+            RefEvent::DecrementRef(index) => self.decremente_ref(index).map(|asset| (index, asset)),
+        }
+    }
+
     fn increment_ref(&mut self, index: Uuid) {
         let asset = self
             .map
@@ -125,9 +155,9 @@ impl<T> AssetMap<T> {
         asset.refs.fetch_add(1, Ordering::Relaxed);
     }
 
-    fn decremente_ref(&mut self, index: Uuid) {
+    fn decremente_ref(&mut self, index: Uuid) -> Option<T> {
         if self.taken_this_update.contains(&index) {
-            return;
+            return None;
         }
         let refs_before_sub = {
             let asset = self
@@ -137,7 +167,9 @@ impl<T> AssetMap<T> {
             asset.refs.fetch_sub(1, Ordering::Relaxed)
         };
         if refs_before_sub == 1 {
-            self.map.remove(&index);
+            self.map.remove(&index).map(|asset| asset.value)
+        } else {
+            None
         }
     }
 }
@@ -155,3 +187,65 @@ impl<T> std::fmt::Debug for AssetId<T> {
         f.debug_tuple("Asset ID").field(&self.index).finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::AssetMap;
+
+    #[test]
+    fn remove_shrinks_map() {
+        let mut map = AssetMap::new();
+        let first = map.insert(1);
+        let second = map.insert(2);
+        let _third = map.insert(3);
+        assert_eq!(map.len(), 3);
+
+        assert_eq!(map.remove(first), Some(1));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.remove(second), Some(2));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn removed_assets_live_while_referenced() {
+        let mut map = AssetMap::new();
+        let id = map.insert(1);
+        let clone = id.clone();
+        assert_eq!(map.remove(id), None);
+        assert_eq!(map.len(), 1);
+        assert_eq!(*map.get(&clone), 1);
+
+        let other_clone = clone.clone();
+        assert_eq!(map.remove(clone), None);
+        assert_eq!(map.remove(other_clone), Some(1));
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unknown_ids_are_caught() {
+        let mut map = AssetMap::new();
+        let id = map.insert(1);
+        let clone = id.clone();
+        map.update();
+        // Forcibly drops the asset, the pending decrement of the clone can't find it
+        map.map.clear();
+        drop(clone);
+        map.update();
+        drop(id);
+    }
+
+    #[test]
+    fn dropping_all_ids_frees_asset() {
+        let mut map = AssetMap::new();
+        let id = map.insert(1);
+        let clone = id.clone();
+        map.update();
+        drop(id);
+        map.update();
+        assert_eq!(map.len(), 1);
+        drop(clone);
+        map.update();
+        assert_eq!(map.len(), 0);
+    }
+}
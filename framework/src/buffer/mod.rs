@@ -1,8 +1,10 @@
+use std::future::Future;
+
 use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, BufferSlice, BufferUsages};
 
 use super::framework::Framework;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum BufferType {
     // A buffer meant to be used as an input for the Vertex Shader
     Vertex,
@@ -30,6 +32,8 @@ pub struct InnerBufferConfiguration {
 pub struct Buffer {
     pub(crate) buffer: BufferInfo,
     pub(crate) bind_group: Option<BindGroup>,
+    // Storage buffers can be bound both as read only and as read write
+    pub(crate) read_only_bind_group: Option<BindGroup>,
     pub(crate) config: InnerBufferConfiguration,
 }
 
@@ -51,6 +55,59 @@ impl Buffer {
                 }],
             })
     }
+
+    pub fn storage_bind_group_layout(framework: &Framework, read_only: bool) -> BindGroupLayout {
+        framework
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Storage Buffer BindGroup Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    // The vertex shaders can't write to storage buffers
+                    visibility: if read_only {
+                        wgpu::ShaderStages::VERTEX_FRAGMENT
+                    } else {
+                        wgpu::ShaderStages::FRAGMENT
+                    },
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            })
+    }
+
+    fn create_bind_group(
+        framework: &Framework,
+        layout: &BindGroupLayout,
+        buffer: &BufferInfo,
+    ) -> BindGroup {
+        framework
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Buffer BindGroup"),
+                layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(
+                        buffer.buffer.as_entire_buffer_binding(),
+                    ),
+                }],
+            })
+    }
+
+    pub(crate) fn storage_bind_group(&self, read_only: bool) -> &BindGroup {
+        let bind_group = if read_only {
+            &self.read_only_bind_group
+        } else {
+            &self.bind_group
+        };
+        bind_group
+            .as_ref()
+            .expect("Buffer: only storage buffers can be bound as storage")
+    }
 }
 
 impl From<BufferType> for BufferUsages {
@@ -168,27 +225,34 @@ impl Buffer {
             &initial_configuration.initial_setup,
             &configuration,
         );
-        let bind_group = if configuration.buffer_type == BufferType::Uniform {
-            let bind_group = framework
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("Buffer BindGroup"),
-                    layout: &Buffer::bind_group_layout(framework),
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer(
-                            buffer.buffer.as_entire_buffer_binding(),
-                        ),
-                    }],
-                });
-            Some(bind_group)
-        } else {
-            None
+        let (bind_group, read_only_bind_group) = match configuration.buffer_type {
+            BufferType::Uniform => (
+                Some(Self::create_bind_group(
+                    framework,
+                    &Buffer::bind_group_layout(framework),
+                    &buffer,
+                )),
+                None,
+            ),
+            BufferType::Storage => (
+                Some(Self::create_bind_group(
+                    framework,
+                    &Buffer::storage_bind_group_layout(framework, false),
+                    &buffer,
+                )),
+                Some(Self::create_bind_group(
+                    framework,
+                    &Buffer::storage_bind_group_layout(framework, true),
+                    &buffer,
+                )),
+            ),
+            _ => (None, None),
         };
         Buffer {
             buffer: buffer,
             config: configuration,
             bind_group,
+            read_only_bind_group,
         }
     }
 
@@ -210,6 +274,23 @@ impl Buffer {
         data.iter().map(|b| *b).collect()
     }
 
+    // Maps the buffer right away, the returned future resolves once the device
+    // gets polled and the mapping completes. None when the mapping fails, or when the
+    // callback is dropped without being called, e.g. because the device was lost
+    pub(crate) fn into_read_all_async(self) -> impl Future<Output = Option<Vec<u8>>> {
+        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
+        self.entire_slice()
+            .map_async(wgpu::MapMode::Read, move |result| {
+                // Nobody is waiting anymore if the future was dropped
+                let _ = tx.send(result);
+            });
+        async move {
+            rx.receive().await?.ok()?;
+            let data = self.entire_slice().get_mapped_range();
+            Some(data.iter().map(|b| *b).collect())
+        }
+    }
+
     pub(crate) fn read_region(
         &self,
         framework: &'_ Framework,
@@ -1,3 +1,5 @@
+use std::future::Future;
+
 use anyhow::Result;
 use log::*;
 
@@ -5,10 +7,16 @@ use wgpu::*;
 
 use crate::{
     buffer::BufferInitialSetup,
-    shader::{Shader, ShaderCompiler, ShaderCreationInfo},
+    padded_bytes_per_row,
+    renderer::draw_command::BindableResource,
+    shader::{
+        ComputeShader, ComputeShaderCreationInfo, ComputeShaderError, Shader, ShaderCompiler,
+        ShaderCreationInfo,
+    },
+    transient_pool::{TransientBufferKey, TransientPool, TransientTextureKey},
     AssetId, AssetMap, AssetsLibrary, DepthStencilTexture2D, GpuDepthStencilTexture2D,
-    GpuRgbaTexture2D, GpuTexture, Mesh, MeshConstructionDetails, RgbaTexture2D, RgbaU8, Texel,
-    TextureConfiguration,
+    GpuR8Texture2D, GpuRgbaTexture2D, GpuTexture, Mesh, MeshConstructionDetails, R8Texture2D,
+    RgbaTexture2D, RgbaU8, Texel, TexelConversionError, Texture, TextureConfiguration,
 };
 
 use super::buffer::{Buffer, BufferConfiguration};
@@ -19,6 +27,9 @@ type TextureMap = AssetMap<GpuRgbaTexture2D>;
 pub type DepthStencilTextureId = AssetId<GpuDepthStencilTexture2D>;
 type DepthStencilTextureMap = AssetMap<GpuDepthStencilTexture2D>;
 
+pub type MaskTextureId = AssetId<GpuR8Texture2D>;
+type MaskTextureMap = AssetMap<GpuR8Texture2D>;
+
 pub type BufferId = AssetId<Buffer>;
 type BufferMap = AssetMap<Buffer>;
 
@@ -28,6 +39,9 @@ type MeshMap = AssetMap<Mesh>;
 pub type ShaderId = AssetId<Shader>;
 type ShaderMap = AssetMap<Shader>;
 
+pub type ComputeShaderId = AssetId<ComputeShader>;
+type ComputeShaderMap = AssetMap<ComputeShader>;
+
 pub struct Framework {
     pub instance: wgpu::Instance,
     pub adapter: wgpu::Adapter,
@@ -38,9 +52,14 @@ pub struct Framework {
 
     pub(crate) allocated_textures: TextureMap,
     pub(crate) allocated_depth_stencil_textures: DepthStencilTextureMap,
+    pub(crate) allocated_mask_textures: MaskTextureMap,
     pub(crate) allocated_buffers: BufferMap,
     pub(crate) allocated_shaders: ShaderMap,
+    pub(crate) allocated_compute_shaders: ComputeShaderMap,
     pub(crate) allocated_meshes: MeshMap,
+
+    pub(crate) transient_textures: TransientPool<TransientTextureKey, TextureId>,
+    pub(crate) transient_buffers: TransientPool<TransientBufferKey, BufferId>,
 }
 
 impl Framework {
@@ -110,9 +129,13 @@ impl<'a> Framework {
             asset_library,
             allocated_textures: AssetMap::new(),
             allocated_depth_stencil_textures: AssetMap::new(),
+            allocated_mask_textures: AssetMap::new(),
             allocated_buffers: AssetMap::new(),
             allocated_shaders: AssetMap::new(),
+            allocated_compute_shaders: AssetMap::new(),
             allocated_meshes: AssetMap::new(),
+            transient_textures: TransientPool::new(),
+            transient_buffers: TransientPool::new(),
             shader_compiler,
         };
         Ok(framework)
@@ -127,6 +150,11 @@ impl<'a> Framework {
         self.allocated_buffers.insert(buffer)
     }
 
+    // Drops the reference of the id, the buffer is deallocated as soon as no other id references it
+    pub fn free_buffer(&mut self, id: BufferId) {
+        self.allocated_buffers.remove(id);
+    }
+
     pub(crate) fn buffer(&self, id: &BufferId) -> &Buffer {
         self.allocated_buffers.get(id)
     }
@@ -143,6 +171,20 @@ impl<'a> Framework {
         self.allocated_textures.insert(gpu_tex)
     }
 
+    // Drops the reference of the id, the texture is deallocated as soon as no other id references it
+    pub fn free_texture2d(&mut self, id: TextureId) {
+        self.allocated_textures.remove(id);
+    }
+
+    // How many textures and buffers are alive
+    pub fn allocated_textures_count(&self) -> usize {
+        self.allocated_textures.len()
+    }
+
+    pub fn allocated_buffers_count(&self) -> usize {
+        self.allocated_buffers.len()
+    }
+
     pub(crate) fn texture2d(&self, id: &TextureId) -> &GpuTexture<RgbaU8, RgbaTexture2D> {
         self.allocated_textures.get(id)
     }
@@ -165,11 +207,18 @@ impl<'a> Framework {
         info!("\tUsing backend {}", backend_string);
     }
 
+    // Lets the device make progress on pending work, e.g async readbacks, without blocking
+    pub fn poll_device(&self) {
+        self.device.poll(Maintain::Poll);
+    }
+
     pub fn update_asset_maps(&mut self) {
         self.allocated_buffers.update();
         self.allocated_shaders.update();
+        self.allocated_compute_shaders.update();
         self.allocated_textures.update();
         self.allocated_depth_stencil_textures.update();
+        self.allocated_mask_textures.update();
         self.allocated_meshes.update();
     }
     pub fn allocate_mesh(&mut self, construction_info: MeshConstructionDetails) -> MeshId {
@@ -192,6 +241,50 @@ impl<'a> Framework {
     pub(crate) fn shader(&self, id: &ShaderId) -> &Shader {
         self.allocated_shaders.get(id)
     }
+
+    // Downlevel adapters, e.g WebGL2, can't run compute shaders at all
+    pub fn supports_compute_shaders(&self) -> bool {
+        self.adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(DownlevelFlags::COMPUTE_SHADERS)
+    }
+
+    pub fn create_compute_shader(
+        &mut self,
+        info: ComputeShaderCreationInfo,
+    ) -> Result<ComputeShaderId, ComputeShaderError> {
+        let shader = ComputeShader::new(&self, info)?;
+        Ok(self.allocated_compute_shaders.insert(shader))
+    }
+
+    // The resources are bound in order, the first one to @group(0)
+    pub fn dispatch_compute(
+        &self,
+        id: &ComputeShaderId,
+        resources: &[BindableResource],
+        workgroups: (u32, u32, u32),
+    ) -> Result<(), ComputeShaderError> {
+        let shader = self.allocated_compute_shaders.get(id);
+        let bind_groups = shader.bind_groups(resources, self)?;
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Compute dispatch"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Compute pass"),
+            });
+            pass.set_pipeline(&shader.pipeline);
+            for (index, bind_group) in bind_groups.iter().enumerate() {
+                pass.set_bind_group(index as u32, bind_group, &[]);
+            }
+            pass.dispatch_workgroups(workgroups.0, workgroups.1, workgroups.2);
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+        Ok(())
+    }
 }
 // Buffer
 impl<'a> Framework {
@@ -238,8 +331,21 @@ impl<'a> Framework {
     pub fn texture2d_height(&self, id: &TextureId) -> u32 {
         self.texture2d(id).height()
     }
+    // Lets other renderers, like the ui, sample the texture
+    pub fn texture2d_view(&self, id: &TextureId) -> &wgpu::TextureView {
+        self.texture2d(id).texture_view(0)
+    }
+    // Unlike texture2d_view, the returned view doesn't keep the framework borrowed
+    pub fn texture2d_create_view(&self, id: &TextureId) -> wgpu::TextureView {
+        let texture = self.texture2d(id);
+        texture.texture().create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Texture2D external view"),
+            format: Some(texture.format()),
+            ..Default::default()
+        })
+    }
     pub fn texture2d_format(&self, id: &TextureId) -> TextureFormat {
-        RgbaU8::wgpu_texture_format()
+        self.texture2d(id).format()
     }
 
     pub fn texture2d_sample_pixel(&self, id: &TextureId, x: u32, y: u32) -> wgpu::Color {
@@ -248,8 +354,110 @@ impl<'a> Framework {
             .unwrap()
             .wgpu_color()
     }
-    pub fn texture2d_read_data(&self, id: &TextureId) -> RgbaTexture2D {
-        self.texture2d(id).read_data(self).unwrap()
+    pub fn texture2d_read_data(
+        &self,
+        id: &TextureId,
+    ) -> Result<RgbaTexture2D, TexelConversionError> {
+        self.texture2d(id).read_data(self)
+    }
+    // The returned future resolves after poll_device() is called once the copy is done,
+    // so it must be polled by something that doesn't block the event loop
+    pub fn texture2d_read_data_async(
+        &self,
+        id: &TextureId,
+    ) -> impl Future<Output = Result<RgbaTexture2D, TexelConversionError>> {
+        self.texture2d(id).read_data_async(self)
+    }
+    // The texels as tightly packed RGBA bytes: the padding added to the rows by the copy to the
+    // staging buffer is removed, so the texture can have any width
+    pub fn copy_texture_to_cpu(&self, id: &TextureId) -> Result<Vec<u8>, TexelConversionError> {
+        let texture = self.texture2d_read_data(id)?;
+        let texels = texture.data().ok_or(TexelConversionError::NotEnoughData)?;
+        Ok(bytemuck::cast_slice(texels).to_vec())
+    }
+    pub fn texture2d_write_data(&self, id: &TextureId, texels: &[RgbaU8]) {
+        self.texture2d(id).write_data(texels, self)
+    }
+    // The buffer holds the texels row by row, the texture rows must be a multiple of
+    // COPY_BYTES_PER_ROW_ALIGNMENT bytes wide
+    pub fn texture2d_copy_from_buffer(&self, id: &TextureId, buffer: &BufferId) {
+        let texture = self.texture2d(id);
+        let bytes_per_row = texture.width() * std::mem::size_of::<RgbaU8>() as u32;
+        assert_eq!(padded_bytes_per_row(bytes_per_row), bytes_per_row);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Buffer to texture copy"),
+            });
+        encoder.copy_buffer_to_texture(
+            ImageCopyBuffer {
+                buffer: self.buffer(buffer).inner_buffer(),
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            ImageCopyTexture {
+                texture: texture.texture(),
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            texture.size,
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+    // Reads a texel of a texture not owned by the framework, e.g. a frame rendered for the
+    // surface: the texture must be created with COPY_SRC. Only 8 bit RGBA and BGRA formats
+    // can be read, None is returned for the others.
+    // Like texture2d_sample_pixel, sRGB formats return the encoded values
+    pub fn read_texel(
+        &self,
+        texture: &wgpu::Texture,
+        format: TextureFormat,
+        x: u32,
+        y: u32,
+    ) -> Option<Color> {
+        texel_channel_order(format)?;
+        let oneshot_buffer = self.buffer_oneshot(BufferConfiguration::<u8> {
+            initial_setup: BufferInitialSetup::Size(padded_bytes_per_row(4) as u64),
+            buffer_type: crate::BufferType::Oneshot,
+            gpu_copy_dest: true,
+            gpu_copy_source: false,
+            cpu_copy_dest: false,
+            cpu_copy_source: true,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Texel readback"),
+            });
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d { x, y, z: 0 },
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: oneshot_buffer.inner_buffer(),
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row(4)),
+                    rows_per_image: std::num::NonZeroU32::new(1),
+                },
+            },
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+        let bytes = oneshot_buffer.read_region(self, (0, 4));
+        let order = texel_channel_order(format)?;
+        Some(RgbaU8(order.map(|channel| bytes[channel])).wgpu_color())
     }
     pub fn texture2d_copy_subregion(
         &mut self,
@@ -267,6 +475,82 @@ impl<'a> Framework {
     }
 }
 
+// Transient resources
+impl<'a> Framework {
+    // Returns a texture that's only valid until the next end_frame() call.
+    // Recycled textures are either filled with the texture's data or cleared
+    pub fn allocate_transient_texture2d(
+        &mut self,
+        texture: RgbaTexture2D,
+        config: TextureConfiguration,
+    ) -> TextureId {
+        let key = TransientTextureKey {
+            width: texture.width(),
+            height: texture.height(),
+            usage: config.usage,
+            mip_count: config.mip_count,
+            color_space: config.color_space,
+        };
+        if let Some(id) = self.transient_textures.acquire(&key) {
+            let gpu_texture = self.texture2d(&id);
+            match texture.data() {
+                Some(data) => gpu_texture.write_data(data, self),
+                None => gpu_texture.clear(self),
+            }
+            id
+        } else {
+            let id = self.allocate_texture2d(texture, config);
+            self.transient_textures.register(key, id.clone());
+            id
+        }
+    }
+
+    // Returns a buffer that's only valid until the next end_frame() call
+    pub fn allocate_transient_buffer<T: bytemuck::Pod + bytemuck::Zeroable>(
+        &mut self,
+        configuration: BufferConfiguration<T>,
+    ) -> BufferId {
+        let size_bytes = match &configuration.initial_setup {
+            BufferInitialSetup::Data(data) => (data.len() * std::mem::size_of::<T>()) as u64,
+            BufferInitialSetup::Size(size) => *size,
+            BufferInitialSetup::Count(count) => (count * std::mem::size_of::<T>()) as u64,
+        };
+        let key = TransientBufferKey {
+            size_bytes,
+            buffer_type: configuration.buffer_type,
+            gpu_copy_source: configuration.gpu_copy_source,
+            cpu_copy_dest: configuration.cpu_copy_dest,
+            cpu_copy_source: configuration.cpu_copy_source,
+        };
+        if let Some(id) = self.transient_buffers.acquire(&key) {
+            if let BufferInitialSetup::Data(data) = configuration.initial_setup {
+                self.buffer_write_sync(&id, data.clone());
+            }
+            id
+        } else {
+            // Recycled buffers get rewritten through the queue
+            let id = self.allocate_typed_buffer(BufferConfiguration {
+                gpu_copy_dest: true,
+                ..configuration
+            });
+            self.transient_buffers.register(key, id.clone());
+            id
+        }
+    }
+
+    // Hands back all the transient resources allocated during this frame
+    pub fn end_frame(&mut self) {
+        self.transient_textures.reclaim();
+        self.transient_buffers.reclaim();
+    }
+
+    // How many transient textures and buffers were created so far: once the work done in each
+    // frame stops changing, this stops growing too
+    pub fn transient_allocations(&self) -> usize {
+        self.transient_textures.allocations() + self.transient_buffers.allocations()
+    }
+}
+
 // DepthStencilTexture
 impl<'a> Framework {
     pub fn allocate_depth_stencil_texture(
@@ -278,7 +562,118 @@ impl<'a> Framework {
         self.allocated_depth_stencil_textures.insert(depth_stencil)
     }
 
+    // Drops the reference of the id, the texture is deallocated as soon as no other id references it
+    pub fn free_depth_stencil_texture(&mut self, id: DepthStencilTextureId) {
+        self.allocated_depth_stencil_textures.remove(id);
+    }
+
     pub fn depth_stencil_texture(&self, id: &DepthStencilTextureId) -> &GpuDepthStencilTexture2D {
         self.allocated_depth_stencil_textures.get(id)
     }
 }
+
+// MaskTexture: single channel textures, which can be rendered to and read back
+impl<'a> Framework {
+    pub fn allocate_mask_texture(
+        &mut self,
+        texture: R8Texture2D,
+        config: TextureConfiguration,
+    ) -> MaskTextureId {
+        let mask = GpuR8Texture2D::new(texture, config, self);
+        self.allocated_mask_textures.insert(mask)
+    }
+
+    // Drops the reference of the id, the texture is deallocated as soon as no other id references it
+    pub fn free_mask_texture(&mut self, id: MaskTextureId) {
+        self.allocated_mask_textures.remove(id);
+    }
+
+    pub fn mask_texture(&self, id: &MaskTextureId) -> &GpuR8Texture2D {
+        self.allocated_mask_textures.get(id)
+    }
+
+    pub fn mask_texture_read_data(
+        &self,
+        id: &MaskTextureId,
+    ) -> Result<R8Texture2D, TexelConversionError> {
+        self.mask_texture(id).read_data(self)
+    }
+}
+
+// Where the red, green, blue and alpha bytes are in a texel, for the formats read_texel supports
+fn texel_channel_order(format: TextureFormat) -> Option<[usize; 4]> {
+    match format {
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => Some([2, 1, 0, 3]),
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => Some([0, 1, 2, 3]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wgpu::TextureFormat;
+
+    use crate::{
+        buffer::{BufferConfiguration, BufferInitialSetup, BufferType},
+        ColorSpace, Framework, RgbaTexture2D, TextureConfiguration, TextureUsage,
+    };
+
+    use super::texel_channel_order;
+
+    #[test]
+    fn only_8_bit_texels_are_read() {
+        assert_eq!(
+            texel_channel_order(TextureFormat::Bgra8UnormSrgb),
+            Some([2, 1, 0, 3])
+        );
+        assert_eq!(
+            texel_channel_order(TextureFormat::Rgba8Unorm),
+            Some([0, 1, 2, 3])
+        );
+        assert_eq!(texel_channel_order(TextureFormat::Rgba16Float), None);
+        assert_eq!(texel_channel_order(TextureFormat::Rgb10a2Unorm), None);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn freed_resources_live_while_still_referenced() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let (textures, buffers) = (
+            framework.allocated_textures_count(),
+            framework.allocated_buffers_count(),
+        );
+        let texture = framework.allocate_texture2d(
+            RgbaTexture2D::empty((4, 4)),
+            TextureConfiguration {
+                label: Some("Freed texture"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+        let buffer = framework.allocate_typed_buffer(BufferConfiguration::<u32> {
+            initial_setup: BufferInitialSetup::Count(16),
+            buffer_type: BufferType::Uniform,
+            gpu_copy_dest: true,
+            gpu_copy_source: false,
+            cpu_copy_dest: false,
+            cpu_copy_source: false,
+        });
+        assert_eq!(framework.allocated_textures_count(), textures + 1);
+        assert_eq!(framework.allocated_buffers_count(), buffers + 1);
+
+        // Other ids, e.g. kept by a command, keep the resources alive until they're dropped
+        let (texture_clone, buffer_clone) = (texture.clone(), buffer.clone());
+        framework.free_texture2d(texture);
+        framework.free_buffer(buffer);
+        assert_eq!(framework.allocated_textures_count(), textures + 1);
+        assert_eq!(framework.allocated_buffers_count(), buffers + 1);
+        framework.texture2d(&texture_clone);
+
+        framework.free_texture2d(texture_clone);
+        framework.free_buffer(buffer_clone);
+        assert_eq!(framework.allocated_textures_count(), textures);
+        assert_eq!(framework.allocated_buffers_count(), buffers);
+    }
+}
@@ -11,6 +11,7 @@ pub mod renderer;
 pub mod scene;
 pub mod shader;
 pub mod texture;
+mod transient_pool;
 
 pub use asset_id::*;
 pub use asset_library::AssetsLibrary;
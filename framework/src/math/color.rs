@@ -0,0 +1,58 @@
+// wgpu colors are linear: the conversion from the sRGB bytes shown to the user must be done
+// before sending a color to the renderer, since the shaders tint in linear space
+pub trait SrgbColor {
+    // The alpha is already linear and is only normalized
+    fn from_srgb_u8(srgba: [u8; 4]) -> Self;
+}
+
+pub fn srgb_to_linear(value: u8) -> f64 {
+    let value = value as f64 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// The inverse of srgb_to_linear, the value is clamped to the 0..1 range
+pub fn linear_to_srgb(value: f64) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+impl SrgbColor for wgpu::Color {
+    fn from_srgb_u8([r, g, b, a]: [u8; 4]) -> Self {
+        wgpu::Color {
+            r: srgb_to_linear(r),
+            g: srgb_to_linear(g),
+            b: srgb_to_linear(b),
+            a: a as f64 / 255.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{linear_to_srgb, srgb_to_linear, SrgbColor};
+
+    #[test]
+    fn srgb_bytes_are_converted_to_linear() {
+        let color = wgpu::Color::from_srgb_u8([0, 128, 255, 128]);
+        assert_eq!(color.r, 0.0);
+        assert!((color.g - 0.2158).abs() < 0.0001);
+        assert_eq!(color.b, 1.0);
+        assert!((color.a - 128.0 / 255.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn srgb_conversion_round_trips() {
+        for value in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+}
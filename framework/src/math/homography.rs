@@ -0,0 +1,116 @@
+use cgmath::{point2, vec3, Matrix3, Point2};
+use nalgebra::{SMatrix, SVector};
+
+// The projective transform mapping each of the from points on the to point with the same index,
+// e.g. to pin the corners of a quad on four arbitrary points.
+// None when three of the points are on the same line
+pub fn homography_from_corners(
+    from: [Point2<f32>; 4],
+    to: [Point2<f32>; 4],
+) -> Option<Matrix3<f32>> {
+    if has_collinear_triple(&from) || has_collinear_triple(&to) {
+        return None;
+    }
+    // Each pair gives two rows of the system, solved for the first eight entries of the
+    // matrix with the last one fixed to 1
+    let mut a = SMatrix::<f64, 8, 8>::zeros();
+    let mut b = SVector::<f64, 8>::zeros();
+    for (i, (from, to)) in from.iter().zip(to.iter()).enumerate() {
+        let (x, y) = (from.x as f64, from.y as f64);
+        let (u, v) = (to.x as f64, to.y as f64);
+        let rows = [
+            [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y],
+            [0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y],
+        ];
+        for (r, row) in rows.iter().enumerate() {
+            for (c, value) in row.iter().enumerate() {
+                a[(i * 2 + r, c)] = *value;
+            }
+        }
+        b[i * 2] = u;
+        b[i * 2 + 1] = v;
+    }
+    let h = a.lu().solve(&b)?;
+    let h: Vec<f32> = h.iter().map(|v| *v as f32).collect();
+    if h.iter().any(|v| !v.is_finite()) {
+        return None;
+    }
+    // cgmath matrices are column major
+    Some(Matrix3::new(
+        h[0], h[3], h[6], h[1], h[4], h[7], h[2], h[5], 1.0,
+    ))
+}
+
+fn has_collinear_triple(points: &[Point2<f32>; 4]) -> bool {
+    (0..4).any(|skipped| {
+        let triple: Vec<_> = (0..4)
+            .filter(|i| *i != skipped)
+            .map(|i| points[i])
+            .collect();
+        (triple[1] - triple[0])
+            .perp_dot(triple[2] - triple[0])
+            .abs()
+            < f32::EPSILON
+    })
+}
+
+// None when the point is mapped to infinity
+pub fn apply_homography(homography: &Matrix3<f32>, point: Point2<f32>) -> Option<Point2<f32>> {
+    let mapped = homography * vec3(point.x, point.y, 1.0);
+    if mapped.z.abs() < f32::EPSILON {
+        None
+    } else {
+        Some(point2(mapped.x / mapped.z, mapped.y / mapped.z))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::{point2, InnerSpace, SquareMatrix};
+
+    use super::{apply_homography, homography_from_corners};
+
+    #[test]
+    fn corner_pin_maps_the_corners() {
+        let square = [
+            point2(0.0, 0.0),
+            point2(1.0, 0.0),
+            point2(1.0, 1.0),
+            point2(0.0, 1.0),
+        ];
+        let pins = [
+            point2(10.0, 20.0),
+            point2(110.0, 5.0),
+            point2(90.0, 130.0),
+            point2(-15.0, 100.0),
+        ];
+        let homography = homography_from_corners(square, pins).unwrap();
+        for (corner, pin) in square.iter().zip(pins.iter()) {
+            let mapped = apply_homography(&homography, *corner).unwrap();
+            assert!((mapped - pin).magnitude() < 1e-3, "{mapped:?} != {pin:?}");
+        }
+
+        // The inverse goes back to the unit square
+        let inverse = homography.invert().unwrap();
+        let center = apply_homography(&homography, point2(0.5, 0.5)).unwrap();
+        let back = apply_homography(&inverse, center).unwrap();
+        assert!((back - point2(0.5, 0.5)).magnitude() < 1e-4, "{back:?}");
+    }
+
+    #[test]
+    fn degenerate_pins_have_no_homography() {
+        let square = [
+            point2(0.0, 0.0),
+            point2(1.0, 0.0),
+            point2(1.0, 1.0),
+            point2(0.0, 1.0),
+        ];
+        let collinear = [
+            point2(0.0, 0.0),
+            point2(1.0, 1.0),
+            point2(2.0, 2.0),
+            point2(3.0, 3.0),
+        ];
+        assert!(homography_from_corners(square, collinear).is_none());
+    }
+}
@@ -1,5 +1,9 @@
 pub mod box2d;
+pub mod color;
 pub mod helpers;
+pub mod homography;
 
 pub use box2d::*;
-pub use helpers::*;
\ No newline at end of file
+pub use color::*;
+pub use helpers::*;
+pub use homography::*;
\ No newline at end of file
@@ -13,6 +13,28 @@ pub struct MeshConstructionDetails {
 unsafe impl bytemuck::Zeroable for Vertex {}
 unsafe impl bytemuck::Pod for Vertex {}
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum MeshEditError {
+    NotEditable,
+    // The vertex buffer can't grow, so at most the initial vertex count can be written
+    TooManyVertices { count: usize, capacity: usize },
+}
+
+impl std::fmt::Display for MeshEditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshEditError::NotEditable => {
+                f.write_str("The mesh wasn't created with allow_editing set")
+            }
+            MeshEditError::TooManyVertices { count, capacity } => f.write_fmt(format_args!(
+                "Cannot write {count} vertices in a mesh with room for {capacity}"
+            )),
+        }
+    }
+}
+
+impl std::error::Error for MeshEditError {}
+
 pub struct Mesh {
     vertices_vertex_buffer: Buffer,
     primitives: u32,
@@ -53,24 +75,14 @@ impl Mesh {
                 .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                     label: None,
                     contents: &bytemuck::cast_slice(&construction_details.vertices.0),
-                    usage: wgpu::BufferUsages::VERTEX
-                        | if construction_details.allow_editing {
-                            wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_DST
-                        } else {
-                            wgpu::BufferUsages::empty()
-                        },
+                    usage: wgpu::BufferUsages::VERTEX | Self::editing_usages(&construction_details),
                 });
         let index_buffer = framework
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: None,
                 contents: &bytemuck::cast_slice(&construction_details.indices.0),
-                usage: wgpu::BufferUsages::INDEX
-                    | if construction_details.allow_editing {
-                        wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_DST
-                    } else {
-                        wgpu::BufferUsages::empty()
-                    },
+                usage: wgpu::BufferUsages::INDEX | Self::editing_usages(&construction_details),
             });
         Mesh {
             vertices_vertex_buffer,
@@ -80,6 +92,41 @@ impl Mesh {
         }
     }
 
+    // Editable meshes are written through the queue: mapping vertex and index buffers
+    // needs a feature that most adapters don't have
+    fn editing_usages(construction_details: &MeshConstructionDetails) -> wgpu::BufferUsages {
+        if construction_details.allow_editing {
+            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
+        } else {
+            wgpu::BufferUsages::empty()
+        }
+    }
+
+    // Replaces the first vertices of the mesh, the indices are left as they are
+    pub fn update_vertices(
+        &mut self,
+        vertices: &[Vertex],
+        framework: &Framework,
+    ) -> Result<(), MeshEditError> {
+        if !self.construction_details.allow_editing {
+            return Err(MeshEditError::NotEditable);
+        }
+        let capacity = self.construction_details.vertices.0.len();
+        if vertices.len() > capacity {
+            return Err(MeshEditError::TooManyVertices {
+                count: vertices.len(),
+                capacity,
+            });
+        }
+        framework.queue.write_buffer(
+            &self.vertices_vertex_buffer,
+            0,
+            bytemuck::cast_slice(vertices),
+        );
+        self.construction_details.vertices.0[..vertices.len()].copy_from_slice(vertices);
+        Ok(())
+    }
+
     pub fn draw_instanced<'a, 'pass>(
         &'a self,
         render_pass: &mut RenderPass<'pass>,
@@ -114,3 +161,91 @@ impl Mesh {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use cgmath::{point2, point3};
+
+    use crate::Framework;
+
+    use super::{Mesh, MeshConstructionDetails, MeshEditError, Vertex};
+
+    fn quad(allow_editing: bool, framework: &Framework) -> Mesh {
+        let vertices = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(x, y)| Vertex {
+            position: point3(x, y, 0.0),
+            tex_coords: point2((x + 1.0) * 0.5, (y + 1.0) * 0.5),
+        });
+        Mesh::new(
+            framework,
+            MeshConstructionDetails {
+                vertices: vertices.into(),
+                indices: [0u16, 1, 2, 0, 2, 3].into(),
+                primitives: 1,
+                allow_editing,
+            },
+        )
+    }
+
+    fn read_vertices(mesh: &Mesh, framework: &Framework) -> Vec<f32> {
+        let size =
+            (mesh.construction_details.vertices.0.len() * std::mem::size_of::<Vertex>()) as u64;
+        let staging = framework.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Mesh readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = framework
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&mesh.vertices_vertex_buffer, 0, &staging, 0, size);
+        framework.queue.submit(std::iter::once(encoder.finish()));
+        let slice = staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        framework.device.poll(wgpu::Maintain::Wait);
+        let mapped = slice.get_mapped_range();
+        let floats = bytemuck::cast_slice::<u8, f32>(&mapped).to_vec();
+        drop(mapped);
+        floats
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn editable_meshes_write_the_new_vertices() {
+        let framework = Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let mut mesh = quad(true, &framework);
+        let moved = [Vertex {
+            position: point3(4.0, 5.0, 0.0),
+            tex_coords: point2(0.25, 0.75),
+        }];
+        mesh.update_vertices(&moved, &framework).unwrap();
+
+        let floats = read_vertices(&mesh, &framework);
+        assert_eq!(&floats[0..5], &[4.0, 5.0, 0.0, 0.25, 0.75]);
+        // The other vertices are untouched
+        assert_eq!(&floats[5..10], &[1.0, -1.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn only_editable_meshes_can_be_updated() {
+        let framework = Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let vertex = Vertex {
+            position: point3(0.0, 0.0, 0.0),
+            tex_coords: point2(0.0, 0.0),
+        };
+        let mut mesh = quad(false, &framework);
+        assert_eq!(
+            mesh.update_vertices(&[vertex], &framework),
+            Err(MeshEditError::NotEditable)
+        );
+        let mut mesh = quad(true, &framework);
+        assert_eq!(
+            mesh.update_vertices(&[vertex; 5], &framework),
+            Err(MeshEditError::TooManyVertices {
+                count: 5,
+                capacity: 4
+            })
+        );
+    }
+}
@@ -19,6 +19,19 @@ impl MeshInstance2D {
         flip_y: bool,
         multiply_color: wgpu::Color,
     ) -> Self {
+        // The tint is multiplied with the linear texture color, so it must be linear too
+        debug_assert!(
+            [
+                multiply_color.r,
+                multiply_color.g,
+                multiply_color.b,
+                multiply_color.a
+            ]
+            .iter()
+            .all(|c| (0.0..=1.0).contains(c)),
+            "multiply_color must be a linear color in the 0..1 range, got {:?}",
+            multiply_color
+        );
         Self {
             position_and_scale: vec4(position.x, position.y, scale.x, scale.y),
             rotation_flip: Vector4 {
@@ -1,7 +1,8 @@
 use cgmath::{Point2, Point3};
 
-pub type Index = u16;
-pub const INDEX_FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint16;
+// 32 bit, so that the polygons drawn from long paths can index all their points
+pub type Index = u32;
+pub const INDEX_FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint32;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -1,3 +1,5 @@
+use cgmath::Point2;
+
 use crate::{
     framework::{BufferId, DepthStencilTextureId, ShaderId, TextureId},
     Box2d, Transform2d,
@@ -21,17 +23,43 @@ pub enum PrimitiveType {
         texture_id: TextureId,
         instances: Vec<Transform2d>,
         flip_uv_y: bool,
+        // Linear color, the texture is sampled as linear before being tinted
         multiply_color: wgpu::Color,
     },
     Rect {
         rects: Vec<Box2d>,
+        // Linear color, use SrgbColor::from_srgb_u8 for the colors picked by the user
+        multiply_color: wgpu::Color,
+    },
+    // A convex polygon in world space, drawn as a triangle fan around the first point
+    Polygon {
+        points: Vec<Point2<f32>>,
+        multiply_color: wgpu::Color,
+    },
+    // A texture drawn with a perspective transform, e.g. to pin its corners on a quadrilateral.
+    // The corners are the world positions of the top left, top right, bottom right and bottom
+    // left corners of the texture. Nothing is drawn when three of them are on the same line
+    ProjectedTexture2D {
+        texture_id: TextureId,
+        corners: [Point2<f32>; 4],
+        // Linear color
         multiply_color: wgpu::Color,
     },
+    // An anti aliased circle outline, the thickness is centered on the radius.
+    // With a custom shader, the ring data is bound before the additional resources
+    Ring {
+        center: Point2<f32>,
+        radius: f32,
+        thickness: f32,
+        // Linear color
+        color: wgpu::Color,
+    },
 }
 
 #[derive(Clone)]
 pub enum BindableResource {
     UniformBuffer(BufferId),
+    StorageBuffer(BufferId),
     Texture(TextureId),
     StencilTexture(DepthStencilTextureId),
     DepthTexture(DepthStencilTextureId),
@@ -44,6 +72,10 @@ pub struct OptionalDrawData {
 
     // If none, an appropriate shader will be picked by the renderer based on the draw_type
     pub shader: Option<ShaderId>,
+
+    // Only the pixels inside this rect are written by the draw, in target pixels
+    // with the origin in the top left corner
+    pub scissor: Option<Box2d>,
 }
 
 impl OptionalDrawData {
@@ -1,4 +1,4 @@
-use cgmath::{point2, point3, vec2};
+use cgmath::{point2, point3, vec2, Matrix3, Point2};
 use wgpu::{
     BindGroup, BindGroupDescriptor, BindGroupLayoutDescriptor, Color, CommandEncoder,
     CommandEncoderDescriptor, LoadOp, Operations, RenderPass, RenderPassColorAttachment,
@@ -7,11 +7,12 @@ use wgpu::{
 
 use crate::{
     buffer::BufferInitialSetup,
-    framework::{BufferId, DepthStencilTextureId, MeshId, ShaderId, TextureId},
-    shader::{Shader, ShaderCreationInfo},
-    Buffer, BufferConfiguration, BufferType, Camera2d, Framework, GpuDepthStencilTexture2D,
-    GpuRgbaTexture2D, Mesh, MeshConstructionDetails, MeshInstance2D, RgbaTexture2D, Texture,
-    Vertex,
+    framework::{BufferId, DepthStencilTextureId, MaskTextureId, MeshId, ShaderId, TextureId},
+    homography_from_corners,
+    shader::{BindElement, Shader, ShaderCreationInfo},
+    Box2d, Buffer, BufferConfiguration, BufferType, Camera2d, Framework, GpuDepthStencilTexture2D,
+    GpuRgbaTexture2D, Index, Indices, Mesh, MeshConstructionDetails, MeshInstance2D, RgbaTexture2D,
+    Texture, Vertex, Vertices,
 };
 
 use super::{
@@ -19,14 +20,27 @@ use super::{
     RenderCallPerFrameData,
 };
 
+const RING_FRAGMENT: &str = include_str!("../shader/default_shaders/ring_fragment.wgsl");
+const PROJECTED_TEXTURE_FRAGMENT: &str =
+    include_str!("../shader/default_shaders/projected_texture_fragment.wgsl");
+
 #[derive(Clone, Debug, Copy, PartialEq, Eq)]
 pub enum DepthStencilUsage {
     Depth,
     Stencil,
 }
 
+// The textures a frame can be rendered to, the pipelines drawing on them must have a
+// matching output format
+#[derive(Clone, Copy)]
+enum RenderOutput<'a> {
+    Texture(&'a TextureId),
+    Mask(&'a MaskTextureId),
+}
+
 enum ResolvedResourceType<'a> {
     UniformBuffer(&'a Buffer),
+    StorageBuffer(&'a Buffer),
     EmptyBindGroup,
     Texture(&'a GpuRgbaTexture2D),
     DepthTexture(&'a GpuDepthStencilTexture2D),
@@ -49,6 +63,7 @@ struct ResolvedDrawCommand<'a> {
     shader: &'a Shader,
     vertex_buffers: Vec<&'a Buffer>,
     bindable_resources: Vec<(u32, ResolvedResourceType<'a>)>,
+    scissor: Option<Box2d>,
 }
 
 pub struct Renderer {
@@ -58,10 +73,15 @@ pub struct Renderer {
     clear_depth: Option<f32>,
     clear_stencil: Option<u32>,
     viewport: Option<(f32, f32, f32, f32)>,
+    scissor: Option<(u32, u32, u32, u32)>,
     empty_bind_group: BindGroup,
 
     texture2d_instanced_shader_id: ShaderId,
     texture2d_single_shader_id: ShaderId,
+    ring_instanced_shader_id: ShaderId,
+    ring_single_shader_id: ShaderId,
+    projected_texture_instanced_shader_id: ShaderId,
+    projected_texture_single_shader_id: ShaderId,
 
     render_pass_debug_name: Option<String>,
     depth_stencil_target: Option<TextureId>,
@@ -106,6 +126,25 @@ impl Renderer {
         framework.allocate_mesh(construction_info)
     }
 
+    // The mesh is dropped with the draw, so it's released on the next framework update
+    fn construct_polygon_fan(points: &[Point2<f32>], framework: &mut Framework) -> MeshId {
+        let vertices = points
+            .iter()
+            .map(|point| Vertex {
+                position: point3(point.x, point.y, 0.0),
+                tex_coords: point2(0.0, 0.0),
+            })
+            .collect();
+        let indices = fan_indices(points.len());
+        let construction_info = MeshConstructionDetails {
+            primitives: indices.len() as u32,
+            vertices: Vertices(vertices),
+            indices: Indices(indices),
+            allow_editing: false,
+        };
+        framework.allocate_mesh(construction_info)
+    }
+
     fn empty_bind_group(framework: &Framework) -> BindGroup {
         let layout = framework
             .device
@@ -137,6 +176,48 @@ impl Renderer {
             ShaderCreationInfo::using_default_vertex_fragment(&framework),
         );
 
+        let ring_instanced_info = ShaderCreationInfo::using_default_vertex_instanced(
+            framework
+                .shader_compiler
+                .compile_into_shader_description("Ring Fragment Shader", RING_FRAGMENT),
+            &framework,
+        )
+        .with_bind_element(BindElement::Texture)
+        .with_bind_element(BindElement::UniformBuffer); // 3: ring data
+        let ring_instanced_shader_id = framework.create_shader(ring_instanced_info);
+        let ring_single_info = ShaderCreationInfo::using_default_vertex(
+            framework
+                .shader_compiler
+                .compile_into_shader_description("Ring Fragment Shader", RING_FRAGMENT),
+            &framework,
+        )
+        .with_bind_element(BindElement::Texture)
+        .with_bind_element(BindElement::UniformBuffer); // 3: ring data
+        let ring_single_shader_id = framework.create_shader(ring_single_info);
+
+        let projected_texture_instanced_info = ShaderCreationInfo::using_default_vertex_instanced(
+            framework.shader_compiler.compile_into_shader_description(
+                "Projected Texture Fragment Shader",
+                PROJECTED_TEXTURE_FRAGMENT,
+            ),
+            &framework,
+        )
+        .with_bind_element(BindElement::Texture)
+        .with_bind_element(BindElement::UniformBuffer); // 3: projection data
+        let projected_texture_instanced_shader_id =
+            framework.create_shader(projected_texture_instanced_info);
+        let projected_texture_single_info = ShaderCreationInfo::using_default_vertex(
+            framework.shader_compiler.compile_into_shader_description(
+                "Projected Texture Fragment Shader",
+                PROJECTED_TEXTURE_FRAGMENT,
+            ),
+            &framework,
+        )
+        .with_bind_element(BindElement::Texture)
+        .with_bind_element(BindElement::UniformBuffer); // 3: projection data
+        let projected_texture_single_shader_id =
+            framework.create_shader(projected_texture_single_info);
+
         let quad_mesh_id = Renderer::construct_initial_quad(framework);
         let empty_bind_group = Renderer::empty_bind_group(framework);
 
@@ -147,6 +228,7 @@ impl Renderer {
                 label: Some("White texture"),
                 usage: crate::texture::TextureUsage::READ_WRITE,
                 mip_count: None,
+                color_space: crate::ColorSpace::Srgb,
             },
         );
 
@@ -157,6 +239,7 @@ impl Renderer {
             clear_depth: None,
             clear_stencil: None,
             viewport: None,
+            scissor: None,
             empty_bind_group,
             render_pass_debug_name: None,
             depth_stencil_target: None,
@@ -164,6 +247,10 @@ impl Renderer {
 
             texture2d_instanced_shader_id,
             texture2d_single_shader_id,
+            ring_instanced_shader_id,
+            ring_single_shader_id,
+            projected_texture_instanced_shader_id,
+            projected_texture_single_shader_id,
             white_texture_id,
             quad_mesh_id,
         }
@@ -190,6 +277,12 @@ impl Renderer {
         self.viewport = viewport;
     }
 
+    // Only the pixels in the (x, y, width, height) rect are written by the draws,
+    // the clear color is still applied to the whole target
+    pub fn set_scissor(&mut self, scissor: Option<(u32, u32, u32, u32)>) {
+        self.scissor = scissor;
+    }
+
     pub fn set_draw_debug_name(&mut self, name: &str) {
         self.render_pass_debug_name = Some(name.to_owned());
     }
@@ -219,8 +312,29 @@ impl Renderer {
         depth_stencil_output: Option<(&DepthStencilTextureId, DepthStencilUsage)>,
         framework: &mut Framework,
     ) {
-        // let texture = framework.allocated_textures.map.get(&output.index).unwrap();
-        // self.end(&texture.value.texture_view, None, framework);
+        self.end_on(
+            RenderOutput::Texture(output),
+            depth_stencil_output,
+            framework,
+        );
+    }
+
+    // Like end, but on a mask texture: the shaders of the draws must output R8Unorm
+    pub fn end_on_mask(
+        &mut self,
+        output: &MaskTextureId,
+        depth_stencil_output: Option<(&DepthStencilTextureId, DepthStencilUsage)>,
+        framework: &mut Framework,
+    ) {
+        self.end_on(RenderOutput::Mask(output), depth_stencil_output, framework);
+    }
+
+    fn end_on(
+        &mut self,
+        output: RenderOutput,
+        depth_stencil_output: Option<(&DepthStencilTextureId, DepthStencilUsage)>,
+        framework: &mut Framework,
+    ) {
         let command_encoder_description = CommandEncoderDescriptor {
             label: Some("Framework Renderer command descriptor"),
         };
@@ -231,19 +345,36 @@ impl Renderer {
         let draw_commands_with_buffers = self.generate_partial_draws(framework);
         let commands = self.resolve_draw_commands(framework, draw_commands_with_buffers);
 
-        let texture = &framework.texture2d(output).texture_view(0);
+        let (texture, output_size) = match output {
+            RenderOutput::Texture(id) => {
+                let gpu_texture = framework.texture2d(id);
+                let size = (gpu_texture.size.width, gpu_texture.size.height);
+                (gpu_texture.texture_view(0), size)
+            }
+            RenderOutput::Mask(id) => {
+                let gpu_texture = framework.mask_texture(id);
+                let size = (gpu_texture.size.width, gpu_texture.size.height);
+                (gpu_texture.texture_view(0), size)
+            }
+        };
         let depth_texture_view = depth_stencil_output
             .map(|tex_id| (framework.depth_stencil_texture(tex_id.0), tex_id.1));
         self.execute_draw_queue(
             &mut command_encoder,
             texture,
+            output_size,
             depth_texture_view,
             commands,
             framework,
         );
         self.submit_frame(command_encoder, framework);
     }
-    pub fn end_on_external_texture(&mut self, output: &TextureView, framework: &mut Framework) {
+    pub fn end_on_external_texture(
+        &mut self,
+        output: &TextureView,
+        output_size: (u32, u32),
+        framework: &mut Framework,
+    ) {
         // let texture = framework.allocated_textures.map.get(&output.index).unwrap();
         // self.end(&texture.value.texture_view, None, framework);
         let command_encoder_description = CommandEncoderDescriptor {
@@ -256,18 +387,75 @@ impl Renderer {
         let draw_commands_with_buffers = self.generate_partial_draws(framework);
         let commands = self.resolve_draw_commands(framework, draw_commands_with_buffers);
 
-        self.execute_draw_queue(&mut command_encoder, output, None, commands, framework);
+        self.execute_draw_queue(
+            &mut command_encoder,
+            output,
+            output_size,
+            None,
+            commands,
+            framework,
+        );
         self.submit_frame(command_encoder, framework);
     }
 
     fn generate_partial_draws(
         &mut self,
         framework: &mut Framework,
-    ) -> Vec<(DrawType, DrawCommand)> {
-        let mut partial_draws: Vec<(DrawType, DrawCommand)> = vec![];
+    ) -> Vec<(DrawType, MeshId, DrawCommand)> {
+        let mut partial_draws: Vec<(DrawType, MeshId, DrawCommand)> = vec![];
         for draw in self.draw_queue.iter() {
+            if let PrimitiveType::Polygon { points, .. } = &draw.primitives {
+                if points.len() < 3 {
+                    continue;
+                }
+            }
+            let mut draw = draw.clone();
+            if let PrimitiveType::Ring {
+                radius, thickness, ..
+            } = &draw.primitives
+            {
+                let ring_data = vec![RingData {
+                    radius_thickness: [*radius, *thickness, 0.0, 0.0],
+                }];
+                let ring_buffer = framework.allocate_transient_buffer(BufferConfiguration {
+                    initial_setup: BufferInitialSetup::Data(&ring_data),
+                    buffer_type: BufferType::Uniform,
+                    gpu_copy_dest: false,
+                    gpu_copy_source: true,
+                    cpu_copy_dest: false,
+                    cpu_copy_source: false,
+                });
+                draw.additional_data
+                    .additional_bindable_resource
+                    .insert(0, BindableResource::UniformBuffer(ring_buffer));
+            }
+            if let PrimitiveType::ProjectedTexture2D { corners, .. } = &draw.primitives {
+                let homography = match projection_homography(corners) {
+                    Some(homography) => homography,
+                    None => continue,
+                };
+                let projection_data = vec![ProjectionData {
+                    homography: [
+                        homography.x.extend(0.0).into(),
+                        homography.y.extend(0.0).into(),
+                        homography.z.extend(0.0).into(),
+                    ],
+                }];
+                let projection_buffer = framework.allocate_transient_buffer(BufferConfiguration {
+                    initial_setup: BufferInitialSetup::Data(&projection_data),
+                    buffer_type: BufferType::Uniform,
+                    gpu_copy_dest: false,
+                    gpu_copy_source: true,
+                    cpu_copy_dest: false,
+                    cpu_copy_source: false,
+                });
+                draw.additional_data
+                    .additional_bindable_resource
+                    .insert(0, BindableResource::UniformBuffer(projection_buffer));
+            }
             let draw_type = self.generate_draw_type(&draw, framework);
-            partial_draws.push((draw_type, draw.clone()))
+            let mesh = self.pick_mesh_from_draw_type(&draw.primitives, framework);
+            partial_draws.push((draw_type, mesh, draw))
         }
         partial_draws
     }
@@ -284,16 +472,17 @@ impl Renderer {
     fn resolve_draw_commands<'f>(
         &self,
         framework: &'f Framework,
-        partial_draws: Vec<(DrawType, DrawCommand)>,
+        partial_draws: Vec<(DrawType, MeshId, DrawCommand)>,
     ) -> Vec<ResolvedDrawCommand<'f>> {
         let mut commands: Vec<ResolvedDrawCommand> = vec![];
-        for (draw, command) in partial_draws.into_iter() {
+        for (draw, mesh, command) in partial_draws.into_iter() {
             commands.push(ResolvedDrawCommand {
-                mesh: self.pick_mesh_from_draw_type(&command.primitives, framework),
+                mesh: framework.mesh(&mesh),
                 draw_type: self.resolve_draw_type(draw, framework),
                 shader: self.pick_shader_from_command(&command, framework),
                 vertex_buffers: self.resolve_vertex_buffers(&command, framework),
                 bindable_resources: self.resolve_bindable_resources(&command, framework),
+                scissor: command.additional_data.scissor.clone(),
             });
         }
         commands
@@ -303,6 +492,7 @@ impl Renderer {
         &mut self,
         command_encoder: &mut CommandEncoder,
         output: &TextureView,
+        output_size: (u32, u32),
         depth_output: Option<(&GpuDepthStencilTexture2D, DepthStencilUsage)>,
         commands: Vec<ResolvedDrawCommand>,
         framework: &Framework,
@@ -361,18 +551,32 @@ impl Renderer {
         if let Some(viewport) = self.viewport.take() {
             render_pass.set_viewport(viewport.0, viewport.1, viewport.2, viewport.3, 0.0, 1.0);
         }
+        let pass_scissor = self
+            .scissor
+            .take()
+            .unwrap_or((0, 0, output_size.0, output_size.1));
         let camera_buffer =
             ResolvedResourceType::UniformBuffer(framework.buffer(&self.camera_buffer_id));
-        self.execute_commands(render_pass, &camera_buffer, &commands);
+        self.execute_commands(render_pass, pass_scissor, &camera_buffer, &commands);
     }
 
     fn execute_commands<'a>(
         &'a self,
         mut render_pass: RenderPass<'a>,
+        pass_scissor: (u32, u32, u32, u32),
         camera_buffer: &'a ResolvedResourceType<'a>,
         commands: &'a Vec<ResolvedDrawCommand<'a>>,
     ) {
         for command in commands.iter() {
+            // The scissor is set again for each command, so that it doesn't leak to the next ones
+            let (x, y, width, height) = match &command.scissor {
+                Some(scissor) => match clip_scissor(scissor, pass_scissor) {
+                    Some(rect) => rect,
+                    None => continue,
+                },
+                None => pass_scissor,
+            };
+            render_pass.set_scissor_rect(x, y, width, height);
             render_pass.set_pipeline(&command.shader.render_pipeline);
 
             self.bind_resource(0, camera_buffer, &mut render_pass);
@@ -386,7 +590,15 @@ impl Renderer {
             }
 
             for (idx, resource) in command.bindable_resources.iter() {
-                self.bind_resource(*idx, resource, &mut render_pass);
+                match resource {
+                    // The same buffer can be bound as read only or read write, as the shader wants
+                    ResolvedResourceType::StorageBuffer(buffer) => render_pass.set_bind_group(
+                        *idx,
+                        buffer.storage_bind_group(command.shader.is_read_only_storage_buffer(*idx)),
+                        &[],
+                    ),
+                    _ => self.bind_resource(*idx, resource, &mut render_pass),
+                }
             }
 
             match &command.draw_type {
@@ -427,6 +639,7 @@ impl Renderer {
     ) {
         let bind_group = match resource {
             ResolvedResourceType::UniformBuffer(buffer) => buffer.bind_group.as_ref().unwrap(),
+            ResolvedResourceType::StorageBuffer(buffer) => buffer.storage_bind_group(false),
             ResolvedResourceType::Texture(texture) => texture.bind_group(0),
             ResolvedResourceType::EmptyBindGroup => &self.empty_bind_group,
             ResolvedResourceType::DepthTexture(gpu_texture) => gpu_texture.depth_bind_group(),
@@ -435,19 +648,21 @@ impl Renderer {
         render_pass.set_bind_group(idx, bind_group, &[])
     }
 
-    fn pick_mesh_from_draw_type<'f, 'b>(
+    fn pick_mesh_from_draw_type(
         &self,
         draw_type: &PrimitiveType,
-        framework: &'f Framework,
-    ) -> &'b Mesh
-    where
-        'f: 'b,
-    {
-        let mesh_id = match draw_type {
+        framework: &mut Framework,
+    ) -> MeshId {
+        match draw_type {
             PrimitiveType::Noop => unreachable!(),
-            PrimitiveType::Texture2D { .. } | PrimitiveType::Rect { .. } => &self.quad_mesh_id, // Pick quad mesh
-        };
-        framework.mesh(&mesh_id)
+            PrimitiveType::Texture2D { .. }
+            | PrimitiveType::ProjectedTexture2D { .. }
+            | PrimitiveType::Rect { .. }
+            | PrimitiveType::Ring { .. } => self.quad_mesh_id.clone(), // Pick quad mesh
+            PrimitiveType::Polygon { points, .. } => {
+                Renderer::construct_polygon_fan(points, framework)
+            }
+        }
     }
 
     fn pick_shader_from_command<'f, 'b>(
@@ -463,12 +678,20 @@ impl Renderer {
         } else {
             match command.primitives {
                 PrimitiveType::Noop => unreachable!(),
-                PrimitiveType::Texture2D { .. } | PrimitiveType::Rect { .. } => {
-                    match command.draw_mode {
-                        DrawMode::Instanced => &self.texture2d_instanced_shader_id,
-                        DrawMode::Single => &self.texture2d_single_shader_id,
-                    }
-                } // Pick quad mesh
+                PrimitiveType::Texture2D { .. }
+                | PrimitiveType::Rect { .. }
+                | PrimitiveType::Polygon { .. } => match command.draw_mode {
+                    DrawMode::Instanced => &self.texture2d_instanced_shader_id,
+                    DrawMode::Single => &self.texture2d_single_shader_id,
+                },
+                PrimitiveType::Ring { .. } => match command.draw_mode {
+                    DrawMode::Instanced => &self.ring_instanced_shader_id,
+                    DrawMode::Single => &self.ring_single_shader_id,
+                },
+                PrimitiveType::ProjectedTexture2D { .. } => match command.draw_mode {
+                    DrawMode::Instanced => &self.projected_texture_instanced_shader_id,
+                    DrawMode::Single => &self.projected_texture_single_shader_id,
+                },
             }
         };
 
@@ -520,6 +743,13 @@ impl Renderer {
                                 buffer
                             })
                         }
+                        BindableResource::StorageBuffer(buf_id) => {
+                            ResolvedResourceType::StorageBuffer({
+                                let buffer = framework.buffer(buf_id);
+                                debug_assert!(buffer.config.buffer_type == BufferType::Storage);
+                                buffer
+                            })
+                        }
                         BindableResource::Texture(tex_id) => {
                             ResolvedResourceType::Texture(framework.texture2d(tex_id))
                         }
@@ -548,7 +778,8 @@ impl Renderer {
     ) -> Vec<(u32, ResolvedResourceType<'a>)> {
         match &command.primitives {
             PrimitiveType::Noop => unreachable!(),
-            PrimitiveType::Texture2D { texture_id, .. } => {
+            PrimitiveType::Texture2D { texture_id, .. }
+            | PrimitiveType::ProjectedTexture2D { texture_id, .. } => {
                 vec![
                     (1, ResolvedResourceType::EmptyBindGroup),
                     (
@@ -557,7 +788,9 @@ impl Renderer {
                     ),
                 ]
             }
-            PrimitiveType::Rect { .. } => {
+            PrimitiveType::Rect { .. }
+            | PrimitiveType::Polygon { .. }
+            | PrimitiveType::Ring { .. } => {
                 vec![
                     (1, ResolvedResourceType::EmptyBindGroup),
                     (
@@ -618,7 +851,7 @@ impl Renderer {
                         )
                     })
                     .collect();
-                let buffer_id = framework.allocate_typed_buffer(BufferConfiguration {
+                let buffer_id = framework.allocate_transient_buffer(BufferConfiguration {
                     initial_setup: BufferInitialSetup::Data(&mesh_instances_2d),
                     buffer_type: BufferType::Vertex,
                     gpu_copy_dest: false,
@@ -647,7 +880,7 @@ impl Renderer {
                         )
                     })
                     .collect();
-                let buffer_id = framework.allocate_typed_buffer(BufferConfiguration {
+                let buffer_id = framework.allocate_transient_buffer(BufferConfiguration {
                     initial_setup: BufferInitialSetup::Data(&mesh_instances_2d),
                     buffer_type: BufferType::Vertex,
                     gpu_copy_dest: false,
@@ -660,6 +893,67 @@ impl Renderer {
                     elements: rects.len() as u32,
                 }
             }
+            PrimitiveType::Polygon { multiply_color, .. } => {
+                // The points are already in world space
+                let mesh_instances_2d = vec![MeshInstance2D::new(
+                    point2(0.0, 0.0),
+                    vec2(1.0, 1.0),
+                    0.0,
+                    false,
+                    multiply_color.clone(),
+                )];
+                let buffer_id = framework.allocate_transient_buffer(BufferConfiguration {
+                    initial_setup: BufferInitialSetup::Data(&mesh_instances_2d),
+                    buffer_type: BufferType::Vertex,
+                    gpu_copy_dest: false,
+                    gpu_copy_source: true,
+                    cpu_copy_dest: false,
+                    cpu_copy_source: false,
+                });
+                DrawType::Instanced {
+                    buffer: buffer_id,
+                    elements: 1,
+                }
+            }
+            PrimitiveType::ProjectedTexture2D {
+                corners,
+                multiply_color,
+                ..
+            } => {
+                let mesh_instances_2d = vec![projected_texture_instance(corners, *multiply_color)];
+                let buffer_id = framework.allocate_transient_buffer(BufferConfiguration {
+                    initial_setup: BufferInitialSetup::Data(&mesh_instances_2d),
+                    buffer_type: BufferType::Vertex,
+                    gpu_copy_dest: false,
+                    gpu_copy_source: true,
+                    cpu_copy_dest: false,
+                    cpu_copy_source: false,
+                });
+                DrawType::Instanced {
+                    buffer: buffer_id,
+                    elements: 1,
+                }
+            }
+            PrimitiveType::Ring {
+                center,
+                radius,
+                thickness,
+                color,
+            } => {
+                let mesh_instances_2d = vec![ring_instance(*center, *radius, *thickness, *color)];
+                let buffer_id = framework.allocate_transient_buffer(BufferConfiguration {
+                    initial_setup: BufferInitialSetup::Data(&mesh_instances_2d),
+                    buffer_type: BufferType::Vertex,
+                    gpu_copy_dest: false,
+                    gpu_copy_source: true,
+                    cpu_copy_dest: false,
+                    cpu_copy_source: false,
+                });
+                DrawType::Instanced {
+                    buffer: buffer_id,
+                    elements: 1,
+                }
+            }
         }
     }
 
@@ -687,7 +981,7 @@ impl Renderer {
                 });
                 let mut buffer_ids: Vec<BufferId> = vec![];
                 for instance in instances {
-                    let buffer_id = framework.allocate_typed_buffer(BufferConfiguration {
+                    let buffer_id = framework.allocate_transient_buffer(BufferConfiguration {
                         initial_setup: BufferInitialSetup::Data(&vec![instance]),
                         buffer_type: BufferType::Uniform,
                         gpu_copy_dest: false,
@@ -714,7 +1008,7 @@ impl Renderer {
                 });
                 let mut buffer_ids: Vec<BufferId> = vec![];
                 for instance in instances {
-                    let buffer_id = framework.allocate_typed_buffer(BufferConfiguration {
+                    let buffer_id = framework.allocate_transient_buffer(BufferConfiguration {
                         initial_setup: BufferInitialSetup::Data(&vec![instance]),
                         buffer_type: BufferType::Uniform,
                         gpu_copy_dest: false,
@@ -726,6 +1020,513 @@ impl Renderer {
                 }
                 DrawType::Separate(buffer_ids)
             }
+            PrimitiveType::Polygon { multiply_color, .. } => {
+                let instance = MeshInstance2D::new(
+                    point2(0.0, 0.0),
+                    vec2(1.0, 1.0),
+                    0.0,
+                    false,
+                    multiply_color.clone(),
+                );
+                let buffer_id = framework.allocate_transient_buffer(BufferConfiguration {
+                    initial_setup: BufferInitialSetup::Data(&vec![instance]),
+                    buffer_type: BufferType::Uniform,
+                    gpu_copy_dest: false,
+                    gpu_copy_source: true,
+                    cpu_copy_dest: false,
+                    cpu_copy_source: false,
+                });
+                DrawType::Separate(vec![buffer_id])
+            }
+            PrimitiveType::ProjectedTexture2D {
+                corners,
+                multiply_color,
+                ..
+            } => {
+                let instance = projected_texture_instance(corners, *multiply_color);
+                let buffer_id = framework.allocate_transient_buffer(BufferConfiguration {
+                    initial_setup: BufferInitialSetup::Data(&vec![instance]),
+                    buffer_type: BufferType::Uniform,
+                    gpu_copy_dest: false,
+                    gpu_copy_source: true,
+                    cpu_copy_dest: false,
+                    cpu_copy_source: false,
+                });
+                DrawType::Separate(vec![buffer_id])
+            }
+            PrimitiveType::Ring {
+                center,
+                radius,
+                thickness,
+                color,
+            } => {
+                let instance = ring_instance(*center, *radius, *thickness, *color);
+                let buffer_id = framework.allocate_transient_buffer(BufferConfiguration {
+                    initial_setup: BufferInitialSetup::Data(&vec![instance]),
+                    buffer_type: BufferType::Uniform,
+                    gpu_copy_dest: false,
+                    gpu_copy_source: true,
+                    cpu_copy_dest: false,
+                    cpu_copy_source: false,
+                });
+                DrawType::Separate(vec![buffer_id])
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct RingData {
+    radius_thickness: [f32; 4],
+}
+
+// The quad covers the ring with a margin of half its thickness, leaving room for the faded edge
+fn ring_instance(
+    center: Point2<f32>,
+    radius: f32,
+    thickness: f32,
+    color: wgpu::Color,
+) -> MeshInstance2D {
+    let extent = radius + thickness;
+    MeshInstance2D::new(center, vec2(extent, extent), 0.0, false, color)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ProjectionData {
+    // The columns of a mat3x3, each one padded to a vec4
+    homography: [[f32; 4]; 3],
+}
+
+fn corners_bounds(corners: &[Point2<f32>; 4]) -> Box2d {
+    let mut min = corners[0];
+    let mut max = corners[0];
+    for corner in &corners[1..] {
+        min = point2(min.x.min(corner.x), min.y.min(corner.y));
+        max = point2(max.x.max(corner.x), max.y.max(corner.y));
+    }
+    Box2d::from_points(min, max)
+}
+
+// The quad covers the box around the corners, with the uvs going down from its top left corner
+fn projected_texture_instance(corners: &[Point2<f32>; 4], color: wgpu::Color) -> MeshInstance2D {
+    let bounds = corners_bounds(corners);
+    MeshInstance2D::new(bounds.center, bounds.extents, 0.0, true, color)
+}
+
+// Maps the uvs of the quad drawn by projected_texture_instance on the uvs of the texture
+fn projection_homography(corners: &[Point2<f32>; 4]) -> Option<Matrix3<f32>> {
+    let bounds = corners_bounds(corners);
+    let size = bounds.extents * 2.0;
+    let (left, top) = (
+        bounds.center.x - bounds.extents.x,
+        bounds.center.y + bounds.extents.y,
+    );
+    let quad_uvs =
+        corners.map(|corner| point2((corner.x - left) / size.x, (top - corner.y) / size.y));
+    let texture_uvs = [
+        point2(0.0, 0.0),
+        point2(1.0, 0.0),
+        point2(1.0, 1.0),
+        point2(0.0, 1.0),
+    ];
+    homography_from_corners(quad_uvs, texture_uvs)
+}
+
+// The triangles of a fan around the first point, none when there are less than 3 points
+fn fan_indices(point_count: usize) -> Vec<Index> {
+    (1..point_count.saturating_sub(1) as Index)
+        .flat_map(|i| [0, i, i + 1])
+        .collect()
+}
+
+// Converts the scissor to an (x, y, width, height) rect inside the bounds,
+// returns None when the scissor doesn't overlap them
+fn clip_scissor(
+    scissor: &Box2d,
+    (x, y, width, height): (u32, u32, u32, u32),
+) -> Option<(u32, u32, u32, u32)> {
+    let left = scissor.left().floor().max(x as f32);
+    let right = scissor.right().ceil().min((x + width) as f32);
+    let top = scissor.top().floor().max(y as f32);
+    let bottom = scissor.bottom().ceil().min((y + height) as f32);
+    if right <= left || bottom <= top {
+        return None;
+    }
+    Some((
+        left as u32,
+        top as u32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::{point2, vec2};
+
+    use crate::{
+        buffer::BufferInitialSetup,
+        renderer::draw_command::{
+            BindableResource, DrawCommand, DrawMode, OptionalDrawData, PrimitiveType,
+        },
+        shader::{BindElement, ShaderCreationInfo},
+        Box2d, BufferConfiguration, BufferType, Camera2d, ColorSpace, Framework, RgbaTexture2D,
+        RgbaU8, SrgbColor, Texture, TextureConfiguration, TextureUsage, Transform2d,
+    };
+
+    use super::{clip_scissor, fan_indices, Renderer};
+
+    const STORAGE_FRAGMENT: &str = "
+//@include :common_definitions
+//@include :2d_definitions
+
+@group(2) @binding(0) var diffuse: texture_2d<f32>;
+@group(2) @binding(1) var s_diffuse: sampler;
+
+struct Colors {
+    colors: array<vec4<f32>>,
+};
+
+@group(3) @binding(0) var<storage, ACCESS> palette: Colors;
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    return palette.colors[1];
+}
+";
+
+    fn draw_with_storage_buffer(framework: &mut Framework, read_only: bool) -> RgbaTexture2D {
+        let access = if read_only { "read" } else { "read_write" };
+        let source = STORAGE_FRAGMENT.replace("ACCESS", access);
+        let fragment = framework
+            .shader_compiler
+            .compile_into_shader_description("Storage test fragment", &source);
+        let info = ShaderCreationInfo::using_default_vertex(fragment, framework)
+            .with_bind_element(BindElement::Texture)
+            .with_bind_element(BindElement::StorageBuffer { read_only });
+        let shader = framework.create_shader(info);
+
+        let colors = vec![[0.0, 0.0, 1.0, 1.0], [1.0, 0.0, 0.0, 1.0]];
+        let buffer = framework.allocate_typed_buffer(BufferConfiguration::<[f32; 4]> {
+            initial_setup: BufferInitialSetup::Data(&colors),
+            buffer_type: BufferType::Storage,
+            gpu_copy_dest: false,
+            gpu_copy_source: false,
+            cpu_copy_dest: false,
+            cpu_copy_source: false,
+        });
+        let target = framework.allocate_texture2d(
+            RgbaTexture2D::empty((4, 4)),
+            TextureConfiguration {
+                label: Some("Storage test target"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+
+        let mut renderer = Renderer::new(framework);
+        renderer.begin(&Camera2d::unit(), Some(wgpu::Color::TRANSPARENT), framework);
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Rect {
+                rects: vec![Box2d {
+                    center: point2(0.0, 0.0),
+                    extents: vec2(1.0, 1.0),
+                }],
+                multiply_color: wgpu::Color::WHITE,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData {
+                additional_vertex_buffers: vec![],
+                additional_bindable_resource: vec![BindableResource::StorageBuffer(buffer)],
+                shader: Some(shader),
+                scissor: None,
+            },
+        });
+        renderer.end(&target, None, framework);
+        framework.texture2d_read_data(&target).unwrap()
+    }
+
+    #[test]
+    fn fans_index_every_point_of_long_paths() {
+        assert!(fan_indices(0).is_empty());
+        assert!(fan_indices(2).is_empty());
+        assert_eq!(fan_indices(4), vec![0, 1, 2, 0, 2, 3]);
+
+        // More points than a 16 bit index can reach
+        let indices = fan_indices(70_000);
+        assert_eq!(indices.len(), 3 * (70_000 - 2));
+        assert_eq!(indices[indices.len() - 3..], [0, 69_998, 69_999]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn storage_buffers_are_bound() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        for read_only in [true, false] {
+            let result = draw_with_storage_buffer(&mut framework, read_only);
+            assert!(result
+                .data()
+                .unwrap()
+                .iter()
+                .all(|texel| texel.0 == [255, 0, 0, 255]));
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn linear_textures_keep_their_bytes() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let texel = [128, 64, 200, 255];
+        let linear = |label| TextureConfiguration {
+            label: Some(label),
+            usage: TextureUsage::RWRT,
+            mip_count: None,
+            color_space: ColorSpace::Linear,
+        };
+        let source = framework.allocate_texture2d(
+            RgbaTexture2D::from_repeated_texel(RgbaU8(texel), (4, 4)).unwrap(),
+            linear("Linear source"),
+        );
+        assert_eq!(
+            framework.texture2d_format(&source),
+            wgpu::TextureFormat::Rgba8Unorm
+        );
+        let read_back = framework.texture2d_read_data(&source).unwrap();
+        assert!(read_back.data().unwrap().iter().all(|t| t.0 == texel));
+
+        // Sampling and rendering don't go through the sRGB transfer function either
+        let target =
+            framework.allocate_texture2d(RgbaTexture2D::empty((4, 4)), linear("Linear target"));
+        let info = ShaderCreationInfo::using_default_vertex_fragment(&framework)
+            .with_output_format(wgpu::TextureFormat::Rgba8Unorm);
+        let shader = framework.create_shader(info);
+        let mut renderer = Renderer::new(&mut framework);
+        renderer.begin(
+            &Camera2d::unit(),
+            Some(wgpu::Color::TRANSPARENT),
+            &mut framework,
+        );
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Texture2D {
+                texture_id: source,
+                instances: vec![Transform2d::default()],
+                flip_uv_y: false,
+                multiply_color: wgpu::Color::WHITE,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData::just_shader(Some(shader)),
+        });
+        renderer.end(&target, None, &mut framework);
+        let rendered = framework.texture2d_read_data(&target).unwrap();
+        assert!(rendered.data().unwrap().iter().all(|t| t.0 == texel));
+    }
+
+    #[test]
+    fn scissors_are_clipped_to_the_bounds() {
+        let scissor = Box2d {
+            center: point2(2.5, 3.0),
+            extents: vec2(1.0, 1.5),
+        };
+        assert_eq!(clip_scissor(&scissor, (0, 0, 10, 10)), Some((1, 1, 3, 4)));
+        assert_eq!(clip_scissor(&scissor, (2, 0, 1, 3)), Some((2, 1, 1, 2)));
+        assert_eq!(clip_scissor(&scissor, (5, 5, 10, 10)), None);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn draws_outside_the_scissor_are_discarded() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let target = framework.allocate_texture2d(
+            RgbaTexture2D::empty((4, 4)),
+            TextureConfiguration {
+                label: Some("Scissor test target"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+        let full_screen_rect = |scissor| DrawCommand {
+            primitives: PrimitiveType::Rect {
+                rects: vec![Box2d {
+                    center: point2(0.0, 0.0),
+                    extents: vec2(1.0, 1.0),
+                }],
+                multiply_color: wgpu::Color::WHITE,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData {
+                scissor,
+                ..Default::default()
+            },
+        };
+
+        let mut renderer = Renderer::new(&mut framework);
+        renderer.begin(
+            &Camera2d::unit(),
+            Some(wgpu::Color::TRANSPARENT),
+            &mut framework,
+        );
+        renderer.draw(full_screen_rect(Some(Box2d {
+            center: point2(1.0, 2.0),
+            extents: vec2(1.0, 2.0),
+        })));
+        renderer.end(&target, None, &mut framework);
+
+        let result = framework.texture2d_read_data(&target).unwrap();
+        for (i, texel) in result.data().unwrap().iter().enumerate() {
+            let expected = if i % 4 < 2 { [255; 4] } else { [0; 4] };
+            assert_eq!(texel.0, expected);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn tinting_happens_in_linear_space() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let target = framework.allocate_texture2d(
+            RgbaTexture2D::empty((4, 4)),
+            TextureConfiguration {
+                label: Some("Tint test target"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+
+        let mut renderer = Renderer::new(&mut framework);
+        renderer.begin(
+            &Camera2d::unit(),
+            Some(wgpu::Color::TRANSPARENT),
+            &mut framework,
+        );
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Rect {
+                rects: vec![Box2d {
+                    center: point2(0.0, 0.0),
+                    extents: vec2(1.0, 1.0),
+                }],
+                multiply_color: wgpu::Color::from_srgb_u8([128, 128, 128, 255]),
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData::default(),
+        });
+        renderer.end(&target, None, &mut framework);
+
+        // White tinted by a gray must give back the same gray once encoded to sRGB
+        let result = framework.texture2d_read_data(&target).unwrap();
+        for texel in result.data().unwrap() {
+            let [r, g, b, a] = texel.0;
+            for channel in [r, g, b] {
+                assert!((channel as i32 - 128).abs() <= 1);
+            }
+            assert_eq!(a, 255);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn polygons_are_filled_only_inside() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let target = framework.allocate_texture2d(
+            RgbaTexture2D::empty((8, 8)),
+            TextureConfiguration {
+                label: Some("Polygon test target"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+
+        let mut renderer = Renderer::new(&mut framework);
+        renderer.begin(
+            &Camera2d::unit(),
+            Some(wgpu::Color::TRANSPARENT),
+            &mut framework,
+        );
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Polygon {
+                points: vec![
+                    point2(0.0, 1.0),
+                    point2(1.0, 0.0),
+                    point2(0.0, -1.0),
+                    point2(-1.0, 0.0),
+                ],
+                multiply_color: wgpu::Color::WHITE,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData::default(),
+        });
+        renderer.end(&target, None, &mut framework);
+
+        // The texels crossed by the diamond edges can go either way
+        let result = framework.texture2d_read_data(&target).unwrap();
+        for (i, texel) in result.data().unwrap().iter().enumerate() {
+            let x = (i % 8) as f32 / 4.0 - 0.875;
+            let y = (i / 8) as f32 / 4.0 - 0.875;
+            let distance = x.abs() + y.abs();
+            if distance < 0.8 {
+                assert_eq!(texel.0, [255; 4]);
+            } else if distance > 1.2 {
+                assert_eq!(texel.0, [0; 4]);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn rings_are_drawn_around_the_radius() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let target = framework.allocate_texture2d(
+            RgbaTexture2D::empty((16, 16)),
+            TextureConfiguration {
+                label: Some("Ring test target"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+
+        let mut renderer = Renderer::new(&mut framework);
+        for draw_mode in [DrawMode::Single, DrawMode::Instanced] {
+            renderer.begin(
+                &Camera2d::unit(),
+                Some(wgpu::Color::TRANSPARENT),
+                &mut framework,
+            );
+            renderer.draw(DrawCommand {
+                primitives: PrimitiveType::Ring {
+                    center: point2(0.0, 0.0),
+                    radius: 0.5,
+                    thickness: 0.25,
+                    color: wgpu::Color::WHITE,
+                },
+                draw_mode,
+                additional_data: OptionalDrawData::default(),
+            });
+            renderer.end(&target, None, &mut framework);
+
+            // A texel is 0.125 wide, the texels on the edges of the ring are partially covered
+            let result = framework.texture2d_read_data(&target).unwrap();
+            for (i, texel) in result.data().unwrap().iter().enumerate() {
+                let x = (i % 16) as f32 / 8.0 - 0.9375;
+                let y = (i / 16) as f32 / 8.0 - 0.9375;
+                let distance = (x * x + y * y).sqrt();
+                let alpha = texel.0[3];
+                if (distance - 0.5).abs() < 0.06 {
+                    assert!(alpha > 200, "{} at distance {}", alpha, distance);
+                } else if distance < 0.25 || distance > 0.75 {
+                    assert_eq!(alpha, 0, "{} at distance {}", alpha, distance);
+                }
+            }
         }
     }
 }
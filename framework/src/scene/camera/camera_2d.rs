@@ -1,5 +1,5 @@
 use cgmath::{
-    point2, point3, vec2, vec3, Matrix4, Point2, SquareMatrix, Transform, Vector2, Vector3,
+    point2, point3, vec2, vec3, Matrix4, Point2, Rad, SquareMatrix, Transform, Vector2, Vector3,
 };
 
 use crate::Transform2d;
@@ -80,6 +80,28 @@ impl Camera2d {
         self.transform.scale.x
     }
 
+    pub fn rotation(&self) -> Rad<f32> {
+        self.transform.rotation_radians
+    }
+
+    pub fn set_rotation(&mut self, rotation: Rad<f32>) {
+        self.transform.rotation_radians = rotation;
+    }
+
+    // Changes the scale, moving the camera so that the world point under ndc_point stays in place
+    pub fn zoom_around(&mut self, ndc_point: Point2<f32>, new_scale: f32) {
+        let point_before = self.ndc_into_world(ndc_point);
+        self.set_scale(new_scale);
+        let point_after = self.ndc_into_world(ndc_point);
+        // The view translates by the camera position, so moving the camera moves the world the same way
+        self.translate(point_after - point_before);
+    }
+
+    // At 100% a world unit covers a pixel, assuming the camera bounds match the viewport size
+    pub fn zoom_percent(&self) -> f32 {
+        100.0 / self.current_scale()
+    }
+
     pub fn view(&self) -> Matrix4<f32> {
         Matrix4::from_nonuniform_scale(
             1.0 / self.transform.scale.x,
@@ -133,6 +155,27 @@ impl Camera2d {
     pub fn height(&self) -> f32 {
         (self.left_right_top_bottom[2] - self.left_right_top_bottom[3]).abs()
     }
+
+    // Shows only the (x, y, width, height) rect of what this camera renders on a target_size
+    // texture, the rect is in target pixels with the origin in the top left corner
+    pub fn cropped(
+        &self,
+        (x, y, width, height): (u32, u32, u32, u32),
+        (target_width, target_height): (u32, u32),
+    ) -> Self {
+        let [left, right, top, bottom] = self.left_right_top_bottom;
+        let horizontal = |px: u32| left + (right - left) * px as f32 / target_width as f32;
+        let vertical = |py: u32| top + (bottom - top) * py as f32 / target_height as f32;
+        Self {
+            left_right_top_bottom: [
+                horizontal(x),
+                horizontal(x + width),
+                vertical(y),
+                vertical(y + height),
+            ],
+            ..*self
+        }
+    }
 }
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -150,3 +193,59 @@ impl From<&Camera2d> for Camera2dUniformBlock {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use cgmath::{point2, MetricSpace};
+
+    use super::Camera2d;
+
+    #[test]
+    fn zoom_percent() {
+        let mut camera = Camera2d::wh(800, 600);
+        assert_eq!(camera.zoom_percent(), 100.0);
+        camera.set_scale(2.0);
+        assert_eq!(camera.zoom_percent(), 50.0);
+        camera.set_scale(0.25);
+        assert_eq!(camera.zoom_percent(), 400.0);
+    }
+
+    #[test]
+    fn zoom_around_keeps_the_point_fixed() {
+        let mut camera = Camera2d::wh(800, 600);
+        camera.set_position(point2(30.0, -20.0));
+        let ndc_point = point2(0.5, -0.25);
+        let world_point = camera.ndc_into_world(ndc_point);
+        for new_scale in [0.5, 3.0, 0.01] {
+            camera.zoom_around(ndc_point, new_scale);
+            assert_eq!(camera.current_scale(), new_scale);
+            assert!(camera.ndc_into_world(ndc_point).distance(world_point) < 0.01);
+        }
+        // The center of the view moves towards the point when zooming in
+        assert!(
+            camera
+                .ndc_into_world(point2(0.0, 0.0))
+                .distance(world_point)
+                < 10.0
+        );
+    }
+
+    #[test]
+    fn zoom_scales_ndc_into_world() {
+        let mut camera = Camera2d::wh(800, 600);
+        let corner = camera.ndc_into_world(point2(1.0, 1.0));
+        assert!(corner.distance(point2(400.0, 300.0)) < 0.001);
+        camera.set_scale(0.5);
+        let corner = camera.ndc_into_world(point2(1.0, 1.0));
+        assert!(corner.distance(point2(200.0, 150.0)) < 0.001);
+    }
+
+    #[test]
+    fn cropped_camera_shows_a_part_of_the_target() {
+        let camera = Camera2d::wh(800, 600);
+        let cropped = camera.cropped((400, 0, 400, 300), (800, 600));
+        assert_eq!(cropped.left_right_top_bottom, [0.0, 400.0, 300.0, 0.0]);
+        let top_left = cropped.ndc_into_world(point2(-1.0, 1.0));
+        assert!(top_left.distance(point2(0.0, 300.0)) < 0.001);
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt::Display;
+
+use wgpu::{BindGroupLayout, ComputePipeline, ShaderModule, ShaderModuleDescriptor};
+
+use crate::{framework::BufferId, renderer::draw_command::BindableResource, Framework};
+
+use super::BindElement;
+
+// Like ShaderCreationInfo, each bind element is a bind group: the n-th element is @group(n)
+pub struct ComputeShaderCreationInfo {
+    module: ShaderModule,
+    label: Option<String>,
+    bind_elements: Vec<BindElement>,
+}
+
+impl ComputeShaderCreationInfo {
+    // The module must have a compute entry point named "main"
+    pub fn new(module: ShaderModuleDescriptor, framework: &Framework) -> Self {
+        let label = module.label.map(|l| l.to_owned());
+        Self {
+            module: framework.device.create_shader_module(module),
+            label,
+            bind_elements: vec![],
+        }
+    }
+
+    pub fn with_bind_element(mut self, element: BindElement) -> Self {
+        self.bind_elements.push(element);
+        self
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ComputeShaderError {
+    // wgpu can't bind depth and stencil textures to the compute stage
+    DepthStencilBinding,
+}
+
+impl Display for ComputeShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComputeShaderError::DepthStencilBinding => {
+                write!(
+                    f,
+                    "Depth stencil textures can't be bound to compute shaders"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ComputeShaderError {}
+
+pub struct ComputeShader {
+    pub(crate) pipeline: ComputePipeline,
+    bind_group_layouts: Vec<BindGroupLayout>,
+    bind_elements: Vec<BindElement>,
+}
+
+impl ComputeShader {
+    pub(crate) fn new(
+        framework: &Framework,
+        info: ComputeShaderCreationInfo,
+    ) -> Result<Self, ComputeShaderError> {
+        let bind_group_layouts: Vec<BindGroupLayout> = info
+            .bind_elements
+            .iter()
+            .map(|e| Self::bind_group_layout(framework, e))
+            .collect::<Result<_, _>>()?;
+        let layout_refs: Vec<&BindGroupLayout> = bind_group_layouts.iter().collect();
+        let pipeline_layout =
+            framework
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Compute shader pipeline layout"),
+                    bind_group_layouts: layout_refs.as_slice(),
+                    push_constant_ranges: &[],
+                });
+        let pipeline = framework
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: info.label.as_deref(),
+                layout: Some(&pipeline_layout),
+                module: &info.module,
+                entry_point: "main",
+            });
+        Ok(Self {
+            pipeline,
+            bind_group_layouts,
+            bind_elements: info.bind_elements,
+        })
+    }
+
+    // The bind groups of the render shaders aren't visible to the compute stage,
+    // so they're created again for each dispatch
+    pub(crate) fn bind_groups(
+        &self,
+        resources: &[BindableResource],
+        framework: &Framework,
+    ) -> Result<Vec<wgpu::BindGroup>, ComputeShaderError> {
+        assert_eq!(
+            resources.len(),
+            self.bind_elements.len(),
+            "A compute shader needs a resource for each of its bind elements"
+        );
+        resources
+            .iter()
+            .zip(self.bind_group_layouts.iter())
+            .map(|(resource, layout)| {
+                let buffer_entry = |id: &BufferId| {
+                    vec![wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: framework.buffer(id).inner_buffer().as_entire_binding(),
+                    }]
+                };
+                let entries = match resource {
+                    BindableResource::UniformBuffer(id) | BindableResource::StorageBuffer(id) => {
+                        buffer_entry(id)
+                    }
+                    BindableResource::Texture(id) => {
+                        let texture = framework.texture2d(id);
+                        vec![
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::TextureView(
+                                    texture.texture_view(0),
+                                ),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::Sampler(texture.sampler(0)),
+                            },
+                        ]
+                    }
+                    BindableResource::StencilTexture(_) | BindableResource::DepthTexture(_) => {
+                        return Err(ComputeShaderError::DepthStencilBinding)
+                    }
+                };
+                Ok(framework
+                    .device
+                    .create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("Compute shader bind group"),
+                        layout,
+                        entries: &entries,
+                    }))
+            })
+            .collect()
+    }
+
+    fn bind_group_layout(
+        framework: &Framework,
+        element: &BindElement,
+    ) -> Result<BindGroupLayout, ComputeShaderError> {
+        let buffer_entry = |ty| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let entries = match element {
+            BindElement::UniformBuffer => vec![buffer_entry(wgpu::BufferBindingType::Uniform)],
+            BindElement::StorageBuffer { read_only } => {
+                vec![buffer_entry(wgpu::BufferBindingType::Storage {
+                    read_only: *read_only,
+                })]
+            }
+            BindElement::Texture => vec![
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            BindElement::None => vec![],
+            BindElement::DepthTexture | BindElement::StencilTexture => {
+                return Err(ComputeShaderError::DepthStencilBinding)
+            }
+        };
+        Ok(framework
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Compute shader bind group layout"),
+                entries: &entries,
+            }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wgpu::{ShaderModuleDescriptor, ShaderSource};
+
+    use crate::{shader::BindElement, Framework};
+
+    use super::{ComputeShaderCreationInfo, ComputeShaderError};
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn depth_stencil_bindings_are_refused() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let info = ComputeShaderCreationInfo::new(
+            ShaderModuleDescriptor {
+                label: Some("Empty compute shader"),
+                source: ShaderSource::Wgsl("@compute @workgroup_size(1) fn main() {}".into()),
+            },
+            &framework,
+        )
+        .with_bind_element(BindElement::StencilTexture);
+        assert_eq!(
+            framework.create_compute_shader(info).err(),
+            Some(ComputeShaderError::DepthStencilBinding)
+        );
+    }
+}
@@ -0,0 +1,24 @@
+//@include :common_definitions
+//@include :2d_definitions
+
+struct ProjectionData {
+    // Maps the uvs of the quad on the uvs of the texture
+    homography: mat3x3<f32>,
+}
+
+@group(2) @binding(0) var diffuse: texture_2d<f32>;
+@group(2) @binding(1) var s_diffuse: sampler;
+
+@group(3) @binding(0) var<uniform> projection: ProjectionData;
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let projected = projection.homography * vec3<f32>(in.tex_uv, 1.0);
+    let uv = projected.xy / projected.z;
+    // The quad covers the whole box around the corners, the pixels outside the texture are left out
+    let color = textureSampleLevel(diffuse, s_diffuse, uv, 0.0);
+    if (projected.z <= 0.0 || any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+        discard;
+    }
+    return color * in.multiply_color;
+}
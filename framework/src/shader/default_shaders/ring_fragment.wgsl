@@ -0,0 +1,24 @@
+//@include :common_definitions
+//@include :2d_definitions
+
+struct RingData {
+    // x is the radius, y the thickness, both in world units
+    radius_thickness: vec4<f32>,
+}
+
+@group(2) @binding(0) var diffuse: texture_2d<f32>;
+@group(2) @binding(1) var s_diffuse: sampler;
+
+@group(3) @binding(0) var<uniform> ring: RingData;
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // The quad goes from -1 to 1, scaled by the extents
+    let center_distance = length(in.position.xy * in.scale.xy);
+    // Negative inside the ring, positive outside
+    let signed_distance = abs(center_distance - ring.radius_thickness.x) - ring.radius_thickness.y * 0.5;
+    // The edges fade over one pixel, whatever the zoom
+    let pixel_size = max(fwidth(center_distance), 0.0001);
+    let coverage = clamp(0.5 - signed_distance / pixel_size, 0.0, 1.0);
+    return in.multiply_color * coverage;
+}
@@ -1,5 +1,7 @@
+mod compute_shader;
 mod shader;
 mod shader_compiler;
 
+pub use compute_shader::*;
 pub use shader::*;
 pub use shader_compiler::*;
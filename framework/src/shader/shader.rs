@@ -12,6 +12,8 @@ pub trait ShaderLayout {
 
 pub enum BindElement {
     UniformBuffer,
+    // Writable storage buffers are only visible to the fragment shader
+    StorageBuffer { read_only: bool },
     Texture,
     DepthTexture,
     StencilTexture,
@@ -26,6 +28,7 @@ pub struct ShaderCreationInfo<'a> {
     blend_state: Option<BlendState>,
     depth_state: Option<DepthStencilState>,
     layouts: Vec<VertexBufferLayout<'a>>,
+    label: Option<String>,
 }
 
 impl<'a> ShaderCreationInfo<'a> {
@@ -37,19 +40,26 @@ impl<'a> ShaderCreationInfo<'a> {
             "Default Instanced Vertex Shader",
             include_str!("default_shaders/default_vertex_instanced.wgsl"),
         );
-        let default_vertex_instanced = framework
-            .device
-            .create_shader_module(default_vertex_instanced);
+        Self::using_vertex_instanced(default_vertex_instanced, fragment, framework)
+    }
 
+    // The vertex shader takes the same inputs as the default instanced one
+    pub fn using_vertex_instanced(
+        vertex: ShaderModuleDescriptor,
+        fragment: ShaderModuleDescriptor,
+        framework: &Framework,
+    ) -> Self {
+        let vertex_module = framework.device.create_shader_module(vertex);
         let fragment_module = framework.device.create_shader_module(fragment);
         Self {
-            vertex_module: default_vertex_instanced,
+            vertex_module,
             fragment_module,
             output_format: None,
             bind_elements: vec![],
             blend_state: None,
             depth_state: None,
             layouts: vec![],
+            label: None,
         }
         .with_layout::<Mesh>()
         .with_layout::<MeshInstance2D>()
@@ -72,6 +82,7 @@ impl<'a> ShaderCreationInfo<'a> {
             blend_state: None,
             depth_state: None,
             layouts: vec![],
+            label: None,
         }
         .with_layout::<Mesh>()
         .with_bind_element(BindElement::UniformBuffer) // 0 mesh info buffer
@@ -114,10 +125,18 @@ impl<'a> ShaderCreationInfo<'a> {
         self.depth_state = depth_state;
         self
     }
+    // Names the pipeline and its bind group layouts, so that they can be told apart in captures
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
 }
 
 pub struct Shader {
     pub(crate) render_pipeline: RenderPipeline,
+    label: Option<String>,
+    // The bind group indices of the read only storage buffers
+    read_only_storage_buffers: Vec<u32>,
 }
 
 impl Shader {
@@ -125,15 +144,16 @@ impl Shader {
         3
     }
     pub(crate) fn new(framework: &Framework, info: ShaderCreationInfo) -> Self {
+        let label = info.label.as_deref().unwrap_or("Shader");
         let bind_group_layouts =
-            Shader::bind_group_layouts_from_bind_elements(framework, &info.bind_elements);
+            Shader::bind_group_layouts_from_bind_elements(framework, label, &info.bind_elements);
         let bind_group_layouts: Vec<&BindGroupLayout> =
             bind_group_layouts.iter().map(|g| g).collect();
         let render_pipeline_layout =
             framework
                 .device
                 .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("Shader pipeline layout"),
+                    label: Some(format!("{label} pipeline layout").as_str()),
                     bind_group_layouts: bind_group_layouts.as_slice(),
                     push_constant_ranges: &[],
                 });
@@ -142,7 +162,7 @@ impl Shader {
             framework
                 .device
                 .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("Shader pipeline"),
+                    label: Some(format!("{label} pipeline").as_str()),
                     layout: Some(&render_pipeline_layout),
                     depth_stencil: info.depth_state,
                     vertex: VertexState {
@@ -178,23 +198,51 @@ impl Shader {
                         unclipped_depth: false,
                     },
                 });
-        Self { render_pipeline }
+        let read_only_storage_buffers = info
+            .bind_elements
+            .iter()
+            .enumerate()
+            .filter_map(|(index, element)| match element {
+                BindElement::StorageBuffer { read_only: true } => Some(index as u32),
+                _ => None,
+            })
+            .collect();
+        Self {
+            render_pipeline,
+            label: info.label,
+            read_only_storage_buffers,
+        }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub(crate) fn is_read_only_storage_buffer(&self, index: u32) -> bool {
+        self.read_only_storage_buffers.contains(&index)
     }
 
+    // The buffer and texture layouts are shared with their bind groups, so only the
+    // shader specific ones are labeled
     fn bind_group_layouts_from_bind_elements(
         framework: &Framework,
+        label: &str,
         elements: &Vec<BindElement>,
     ) -> Vec<BindGroupLayout> {
         elements
             .iter()
-            .map(|e| match e {
+            .enumerate()
+            .map(|(index, e)| match e {
                 BindElement::UniformBuffer => Buffer::bind_group_layout(framework),
+                BindElement::StorageBuffer { read_only } => {
+                    Buffer::storage_bind_group_layout(framework, *read_only)
+                }
                 BindElement::Texture => crate::texture2d_bind_group_layout(framework),
                 BindElement::None => {
                     framework
                         .device
                         .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                            label: None,
+                            label: Some(format!("{label} bind group {index}").as_str()),
                             entries: &[],
                         })
                 }
@@ -202,7 +250,9 @@ impl Shader {
                     framework
                         .device
                         .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                            label: Some("Depth Texture Bindg layout"),
+                            label: Some(
+                                format!("{label} depth texture bind group {index}").as_str(),
+                            ),
                             entries: &[
                                 wgpu::BindGroupLayoutEntry {
                                     binding: 0,
@@ -229,7 +279,9 @@ impl Shader {
                     framework
                         .device
                         .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                            label: Some("Stencil Texture Bindg layout"),
+                            label: Some(
+                                format!("{label} stencil texture bind group {index}").as_str(),
+                            ),
                             entries: &[wgpu::BindGroupLayoutEntry {
                                 binding: 0,
                                 visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
@@ -246,3 +298,24 @@ impl Shader {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::Framework;
+
+    use super::ShaderCreationInfo;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn shader_labels_are_kept() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let info = ShaderCreationInfo::using_default_vertex_fragment(&framework)
+            .with_label("Labeled shader");
+        let labeled = framework.create_shader(info);
+        let info = ShaderCreationInfo::using_default_vertex_fragment(&framework);
+        let unlabeled = framework.create_shader(info);
+        assert_eq!(framework.shader(&labeled).label(), Some("Labeled shader"));
+        assert_eq!(framework.shader(&unlabeled).label(), None);
+    }
+}
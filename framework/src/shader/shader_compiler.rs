@@ -1,6 +1,6 @@
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::{Debug, Display},
     fs::File,
     io::{BufRead, BufReader, Cursor, Error, Read},
@@ -16,6 +16,7 @@ enum PreprocessorCommand {
     Nothing(String),
     IncludeFile(String),
     IncludeDefinition(String),
+    Import(String),
 }
 
 fn store_compiled_module(module: naga::Module) -> Cow<'static, naga::Module> {
@@ -29,6 +30,9 @@ enum PreprocessError {
     EmptyInclude,
     FileNotAccessible(String, Error),
     DefinitionNotFound(String),
+    EmptyImport,
+    // The chain of imports, ending with the one that was already being expanded
+    CyclicImport(Vec<String>),
 }
 impl Debug for PreprocessError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -45,6 +49,10 @@ impl Debug for PreprocessError {
             PreprocessError::DefinitionNotFound(definition_name) => {
                 f.write_fmt(format_args!("Non-existent definition: {definition_name}"))
             }
+            PreprocessError::EmptyImport => f.write_str("Empty import statement!"),
+            PreprocessError::CyclicImport(chain) => {
+                f.write_fmt(format_args!("Cyclic import: {}", chain.join(" -> ")))
+            }
         }
     }
 }
@@ -70,7 +78,7 @@ impl ShaderCompiler {
     // compile calls
     pub fn define<T: Into<String>>(&mut self, name: T, source: T) -> anyhow::Result<()> {
         let source = source.into();
-        let compiled_module = self.compile(&source);
+        let compiled_module = self.try_compile(&source)?;
         let mut validator = Validator::new(ValidationFlags::all(), Capabilities::all());
         let _info = validator.validate(&compiled_module)?;
         // print info;
@@ -80,9 +88,17 @@ impl ShaderCompiler {
 
     // Preprocesses and compiles the given source
     pub fn compile(&self, source: &str) -> Cow<'static, naga::Module> {
-        let parsed_source = self.preprocess_source(source);
-        let module = parse_str(&parsed_source).expect("Failed  to compile a shader");
-        store_compiled_module(module)
+        match self.try_compile(source) {
+            Ok(module) => module,
+            Err(e) => panic!("Failed to compile a shader: {}", e),
+        }
+    }
+
+    // Like compile, but the preprocessing and parsing errors are returned
+    pub fn try_compile(&self, source: &str) -> anyhow::Result<Cow<'static, naga::Module>> {
+        let parsed_source = self.preprocess(source)?;
+        let module = parse_str(&parsed_source)?;
+        Ok(store_compiled_module(module))
     }
 
     pub fn compile_into_shader_description<'a>(
@@ -97,31 +113,66 @@ impl ShaderCompiler {
         }
     }
 
-    fn preprocess_source(&self, source: &str) -> String {
+    fn preprocess(&self, source: &str) -> Result<String, PreprocessError> {
+        self.preprocess_imports(source, &mut vec![], &mut HashSet::new())
+    }
+
+    // importing holds the definitions being expanded, to detect the cycles:
+    // a definition is only imported once, the next imports of the same definition are skipped
+    fn preprocess_imports(
+        &self,
+        source: &str,
+        importing: &mut Vec<String>,
+        imported: &mut HashSet<String>,
+    ) -> Result<String, PreprocessError> {
         let cursor = Cursor::new(source);
-        let lines = cursor.lines();
-        let lines: Vec<_> = lines
-            .map(|line| self.parse_command(line.unwrap()))
-            .map(|line| {
-                if let Err(e) = &line {
-                    panic!("While preprocessing, {}", e)
-                } else {
-                    line.unwrap()
+        let mut lines = vec![];
+        for line in cursor.lines() {
+            let line = match self.parse_command(line.unwrap())? {
+                PreprocessorCommand::Nothing(line) => line,
+                PreprocessorCommand::IncludeFile(file_path) => self.include_file_path(file_path)?,
+                PreprocessorCommand::IncludeDefinition(def) => self.include_definition(def)?,
+                PreprocessorCommand::Import(name) => {
+                    self.import_definition(name, importing, imported)?
                 }
-            })
-            .map(|command| self.execute_command(command))
-            .map(|line| {
-                if let Err(e) = &line {
-                    panic!("While preprocessing, {}", e)
-                } else {
-                    line.unwrap()
-                }
-            })
-            .collect();
-        lines.join("\n")
+            };
+            lines.push(line);
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn import_definition(
+        &self,
+        name: String,
+        importing: &mut Vec<String>,
+        imported: &mut HashSet<String>,
+    ) -> Result<String, PreprocessError> {
+        if importing.contains(&name) {
+            let mut chain = importing.clone();
+            chain.push(name);
+            return Err(PreprocessError::CyclicImport(chain));
+        }
+        if imported.contains(&name) {
+            return Ok(String::new());
+        }
+        let definition = match self.definitions.get(&name) {
+            Some(definition) => definition,
+            None => return Err(PreprocessError::DefinitionNotFound(name)),
+        };
+        importing.push(name.clone());
+        let expanded = self.preprocess_imports(definition, importing, imported)?;
+        importing.pop();
+        imported.insert(name);
+        Ok(expanded)
     }
 
     fn parse_command(&self, line: String) -> Result<PreprocessorCommand, PreprocessError> {
+        if let Some(import) = line.trim_start().strip_prefix("#import") {
+            return match import.trim() {
+                "" => Err(PreprocessError::EmptyImport),
+                name => Ok(PreprocessorCommand::Import(name.to_owned())),
+            };
+        }
         #[derive(Eq, PartialEq)]
         enum ParseState {
             Whitespaces,
@@ -192,14 +243,6 @@ impl ShaderCompiler {
         }
     }
 
-    fn execute_command(&self, command: PreprocessorCommand) -> Result<String, PreprocessError> {
-        match command {
-            PreprocessorCommand::Nothing(s) => Ok(s),
-            PreprocessorCommand::IncludeFile(file_path) => self.include_file_path(file_path),
-            PreprocessorCommand::IncludeDefinition(def) => self.include_definition(def),
-        }
-    }
-
     fn include_file_path(&self, file_path: String) -> Result<String, PreprocessError> {
         let file = File::open(&file_path);
         if let Err(e) = file {
@@ -291,4 +334,61 @@ pub mod test {
         );
         assert!(module.entry_points.iter().any(|e| e.name == "fragment"));
     }
+
+    #[test]
+    pub fn compile_import_definition() {
+        let mut compiler = ShaderCompiler::new();
+        compiler
+            .define(
+                "white",
+                "fn white() -> vec4<f32> { return vec4<f32>(1.0); }",
+            )
+            .unwrap();
+        compiler
+            .define(
+                "gray",
+                "#import white
+        fn gray() -> vec4<f32> { return white() * 0.5; }",
+            )
+            .unwrap();
+        // white is imported both directly and by gray, but it's only defined once
+        let module = compiler
+            .try_compile(
+                "#import white
+            #import gray
+            @fragment
+            fn fragment() -> @location(0) vec4<f32> {
+                return gray() + white();
+            }",
+            )
+            .unwrap();
+        assert!(module.entry_points.iter().any(|e| e.name == "fragment"));
+    }
+
+    #[test]
+    pub fn unknown_import_is_an_error() {
+        let compiler = ShaderCompiler::new();
+        let error = compiler.try_compile("#import missing").unwrap_err();
+        assert!(error.to_string().contains("missing"));
+        assert!(matches!(
+            compiler.preprocess("    #import"),
+            Err(PreprocessError::EmptyImport)
+        ));
+    }
+
+    #[test]
+    pub fn cyclic_import_is_detected() {
+        let mut compiler = ShaderCompiler::new();
+        // define would refuse these, the definitions can't be compiled
+        compiler
+            .definitions
+            .insert("a".to_owned(), "#import b".to_owned());
+        compiler
+            .definitions
+            .insert("b".to_owned(), "#import a".to_owned());
+        match compiler.preprocess("#import a") {
+            Err(PreprocessError::CyclicImport(chain)) => assert_eq!(chain, ["a", "b", "a"]),
+            _ => panic!("The cyclic import wasn't detected"),
+        }
+    }
 }
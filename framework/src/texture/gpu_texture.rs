@@ -1,10 +1,10 @@
-use std::{marker::PhantomData, num::NonZeroU32};
+use std::{future::Future, marker::PhantomData, num::NonZeroU32};
 
 use wgpu::{Extent3d, ImageCopyBuffer, ImageDataLayout, Origin3d, TextureDescriptor};
 
 use crate::{
-    BindingInfo, Framework, SamplingExtents, SamplingOrigin, TexelConversionError,
-    TextureConfiguration, TextureUsage,
+    BindingInfo, Buffer, ColorSpace, Framework, SamplingExtents, SamplingOrigin,
+    TexelConversionError, TextureConfiguration, TextureUsage,
 };
 
 use super::{Texel, Texture};
@@ -16,6 +16,7 @@ pub struct GpuTexture<L: Texel, T: Texture<L>> {
     pub(crate) wgpu_texture: wgpu::Texture,
     pub(crate) usage: TextureUsage,
     pub(crate) mip_count: Option<u32>,
+    pub(crate) color_space: ColorSpace,
 
     pub(crate) binding_infos: Vec<BindingInfo>,
 }
@@ -39,12 +40,13 @@ impl<L: Texel, T: Texture<L>> GpuTexture<L, T> {
             mip_level_count: config.mip_count.unwrap_or(1),
             sample_count: 1,
             dimension: T::wgpu_texture_dimension(),
-            format: L::wgpu_texture_format(),
+            format: config.color_space.texture_format(L::wgpu_texture_format()),
             usage: config.usage.to_wgpu_texture_usage(),
         };
 
         let wgpu_texture = framework.device.create_texture(&tex_descriptor);
-        let binding_infos = texture.create_binding_info(&wgpu_texture, &framework.device);
+        let binding_infos =
+            texture.create_binding_info(&wgpu_texture, config.color_space, &framework.device);
 
         let gpu_texture = GpuTexture {
             phant_data: PhantomData,
@@ -57,6 +59,7 @@ impl<L: Texel, T: Texture<L>> GpuTexture<L, T> {
             },
             usage: config.usage,
             mip_count: config.mip_count,
+            color_space: config.color_space,
             binding_infos,
         };
         if let Some(data) = texture.data() {
@@ -76,6 +79,10 @@ impl<L: Texel, T: Texture<L>> GpuTexture<L, T> {
         self.size.depth_or_array_layers
     }
 
+    pub(crate) fn format(&self) -> wgpu::TextureFormat {
+        self.color_space.texture_format(L::wgpu_texture_format())
+    }
+
     pub(crate) fn texture(&self) -> &wgpu::Texture {
         &self.wgpu_texture
     }
@@ -214,6 +221,33 @@ impl<L: Texel, T: Texture<L>> GpuTexture<L, T> {
         extents: T::SamplingExtentsType,
         framework: &Framework,
     ) -> Result<T, TexelConversionError> {
+        let (buffer, readback) = self.copy_region_to_buffer(origin, extents, framework);
+        let bytes = buffer.read_all_sync(framework);
+        readback.into_texture(bytes)
+    }
+
+    pub(crate) fn read_data_async(
+        &self,
+        framework: &Framework,
+    ) -> impl Future<Output = Result<T, TexelConversionError>> {
+        let (buffer, readback) = self.copy_region_to_buffer(
+            T::SamplingPointType::from_wgpu_origin(Origin3d::ZERO),
+            T::SamplingExtentsType::from_wgpu_extents(self.size.clone()),
+            framework,
+        );
+        let bytes = buffer.into_read_all_async();
+        async move {
+            let bytes = bytes.await.ok_or(TexelConversionError::ReadbackFailed)?;
+            readback.into_texture(bytes)
+        }
+    }
+
+    fn copy_region_to_buffer(
+        &self,
+        origin: T::SamplingPointType,
+        extents: T::SamplingExtentsType,
+        framework: &Framework,
+    ) -> (Buffer, TextureReadback<L, T>) {
         let mut encoder =
             framework
                 .device
@@ -229,13 +263,11 @@ impl<L: Texel, T: Texture<L>> GpuTexture<L, T> {
         wgpu_origin.y = self.convert_region_y_to_wgpu_y(wgpu_origin.y, wgpu_extents.height);
 
         let unpadded_width = wgpu_extents.width * L::total_texel_size_bytes() as u32;
-        let pad_bytes = (wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
-            - (unpadded_width % wgpu::COPY_BYTES_PER_ROW_ALIGNMENT))
-            % wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
-        let padded_width = unpadded_width + pad_bytes;
+        let padded_width = padded_bytes_per_row(unpadded_width);
+        let rows = wgpu_extents.height * wgpu_extents.depth_or_array_layers;
         let oneshot_buffer = framework.buffer_oneshot(crate::BufferConfiguration {
             initial_setup: crate::buffer::BufferInitialSetup::Size::<u8>(
-                (padded_width * wgpu_extents.height) as u64,
+                padded_width as u64 * rows as u64,
             ),
             buffer_type: crate::BufferType::Oneshot,
             gpu_copy_dest: true,
@@ -262,11 +294,15 @@ impl<L: Texel, T: Texture<L>> GpuTexture<L, T> {
         );
         framework.queue.submit(std::iter::once(encoder.finish()));
 
-        let mut bytes = oneshot_buffer.read_all_sync(framework);
-        if padded_width != unpadded_width {
-            bytes = correct_bytes_for_padding::<L>(bytes, padded_width, wgpu_extents);
-        }
-        T::from_bytes(&bytes, extents)
+        (
+            oneshot_buffer,
+            TextureReadback {
+                padded_width,
+                wgpu_extents,
+                extents,
+                phantom: PhantomData,
+            },
+        )
     }
 
     pub(crate) fn clone_subregion(
@@ -294,6 +330,7 @@ impl<L: Texel, T: Texture<L>> GpuTexture<L, T> {
                 label: self.label.clone().map(|label| label + " clone").as_deref(),
                 usage: self.usage.clone(),
                 mip_count: self.mip_count.clone(),
+                color_space: self.color_space,
             },
             framework,
         );
@@ -318,17 +355,168 @@ impl<L: Texel, T: Texture<L>> GpuTexture<L, T> {
     }
 }
 
-fn correct_bytes_for_padding<L: Texel>(
-    bytes: Vec<u8>,
+// Describes how to turn the bytes of a texture region copied into a buffer back into a texture
+struct TextureReadback<L: Texel, T: Texture<L>> {
     padded_width: u32,
     wgpu_extents: Extent3d,
-) -> Vec<u8> {
-    let padded_rows = bytes.chunks((padded_width) as usize);
-    let unpadded_rows = padded_rows
-        .into_iter()
-        .map(|c| c.chunks(wgpu_extents.width as usize * L::total_texel_size_bytes()));
-    unpadded_rows.fold(vec![], |vec, mut c| {
-        let row_bytes = c.next().unwrap().to_owned();
-        [vec, row_bytes].concat()
-    })
+    extents: T::SamplingExtentsType,
+    phantom: PhantomData<L>,
+}
+
+impl<L: Texel, T: Texture<L>> TextureReadback<L, T> {
+    fn into_texture(self, mut bytes: Vec<u8>) -> Result<T, TexelConversionError> {
+        let unpadded_width = self.wgpu_extents.width * L::total_texel_size_bytes() as u32;
+        if self.padded_width != unpadded_width {
+            let rows = self.wgpu_extents.height * self.wgpu_extents.depth_or_array_layers;
+            bytes = remove_row_padding(&bytes, self.padded_width, unpadded_width, rows)?;
+        }
+        T::from_bytes(&bytes, self.extents)
+    }
+}
+
+// The rows of the copies between textures and buffers must be a multiple of
+// COPY_BYTES_PER_ROW_ALIGNMENT bytes wide
+pub fn padded_bytes_per_row(unpadded_bytes_per_row: u32) -> u32 {
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (unpadded_bytes_per_row + alignment - 1) / alignment * alignment
+}
+
+// The rows copied into a buffer are padded as in padded_bytes_per_row,
+// only the first unpadded_width bytes of each row are texels
+fn remove_row_padding(
+    bytes: &[u8],
+    padded_width: u32,
+    unpadded_width: u32,
+    rows: u32,
+) -> Result<Vec<u8>, TexelConversionError> {
+    let (padded_width, unpadded_width) = (padded_width as usize, unpadded_width as usize);
+    // The padding of the last row may be missing
+    let needed_bytes = padded_width * (rows as usize).saturating_sub(1) + unpadded_width;
+    if rows > 0 && bytes.len() < needed_bytes {
+        return Err(TexelConversionError::NotEnoughData);
+    }
+    let mut unpadded = Vec::with_capacity(unpadded_width * rows as usize);
+    for row in bytes.chunks(padded_width).take(rows as usize) {
+        unpadded.extend_from_slice(&row[..unpadded_width]);
+    }
+    Ok(unpadded)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Framework, RgbaTexture2D, RgbaU8, Texture, TextureConfiguration, TextureUsage};
+
+    use super::{padded_bytes_per_row, remove_row_padding};
+
+    #[test]
+    fn rows_are_padded_to_the_copy_alignment() {
+        assert_eq!(padded_bytes_per_row(0), 0);
+        assert_eq!(padded_bytes_per_row(4), 256);
+        assert_eq!(padded_bytes_per_row(256), 256);
+        assert_eq!(padded_bytes_per_row(400), 512);
+        assert_eq!(padded_bytes_per_row(1024), 1024);
+    }
+
+    #[test]
+    fn row_padding_is_removed() {
+        let bytes = [1, 2, 0, 0, 3, 4, 0, 0, 5, 6];
+        assert_eq!(
+            remove_row_padding(&bytes, 4, 2, 3).unwrap(),
+            [1, 2, 3, 4, 5, 6]
+        );
+        assert!(remove_row_padding(&bytes[..8], 4, 2, 3).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn read_back_a_texture_with_unaligned_rows() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        // 400 bytes per row, the copy pads them to 512
+        let (width, height) = (100, 100);
+        let texels = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                RgbaU8([x as u8, y as u8, (x + y) as u8, 255])
+            })
+            .collect::<Vec<_>>();
+        let texture = framework.allocate_texture2d(
+            RgbaTexture2D::from_texels(texels.clone(), (width, height)).unwrap(),
+            TextureConfiguration {
+                label: Some("Unaligned readback"),
+                usage: TextureUsage::RWRT,
+                ..Default::default()
+            },
+        );
+
+        let read_back = framework.texture2d_read_data(&texture).unwrap();
+        assert_eq!((read_back.width(), read_back.height()), (width, height));
+        let read_back = read_back.data().unwrap();
+        assert_eq!(read_back.len(), texels.len());
+        for (read, written) in read_back.iter().zip(texels.iter()) {
+            assert_eq!(read.0, written.0);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn async_readback_matches_the_sync_one() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        let (width, height) = (37, 19);
+        let texels = (0..width * height)
+            .map(|i| RgbaU8([(i % 256) as u8, (i / 256) as u8, 128, 255]))
+            .collect::<Vec<_>>();
+        let texture = framework.allocate_texture2d(
+            RgbaTexture2D::from_texels(texels, (width, height)).unwrap(),
+            TextureConfiguration {
+                label: Some("Async readback"),
+                usage: TextureUsage::RWRT,
+                ..Default::default()
+            },
+        );
+
+        let pending = framework.texture2d_read_data_async(&texture);
+        // The future only resolves once the device gets polled
+        framework.device.poll(wgpu::Maintain::Wait);
+        let read_async = pollster::block_on(pending).unwrap();
+        let read_sync = framework.texture2d_read_data(&texture).unwrap();
+        assert_eq!(
+            (read_async.width(), read_async.height()),
+            (read_sync.width(), read_sync.height())
+        );
+        let texels = |texture: &RgbaTexture2D| {
+            texture
+                .data()
+                .unwrap()
+                .iter()
+                .map(|texel| texel.0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(texels(&read_async), texels(&read_sync));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn copy_to_cpu_gives_tight_rows() {
+        let mut framework =
+            Framework::new(&Default::default()).expect("This test needs a GPU adapter");
+        // 148 bytes per row
+        let (width, height) = (37, 5);
+        let bytes = (0..width * height * 4)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let texture = framework.allocate_texture2d(
+            RgbaTexture2D::from_bytes(&bytes, (width, height)).unwrap(),
+            TextureConfiguration {
+                label: Some("Copy to CPU"),
+                usage: TextureUsage::RWRT,
+                ..Default::default()
+            },
+        );
+
+        let copied = framework.copy_texture_to_cpu(&texture).unwrap();
+        assert_eq!(copied.len(), (width * height * 4) as usize);
+        assert_eq!(copied, bytes);
+    }
 }
@@ -7,18 +7,47 @@ pub use texels::*;
 pub use texture::*;
 use wgpu::{BindGroup, TextureView};
 
-#[derive(Debug, Default, Clone, Copy)]
+use crate::Framework;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct TextureUsage {
     pub cpu_write: bool,
     pub cpu_read: bool,
     pub use_as_render_target: bool,
 }
 
+// How the texels of the 8 bit RGBA textures are encoded: the sRGB ones are decoded to linear
+// when sampled and encoded back when rendered to, the linear ones are used as they are, which is
+// what masks and lookup tables need. The pipelines rendering to a linear texture must be created
+// with a matching output format, see ShaderCreationInfo::with_output_format
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    // The formats without an sRGB variant are left as they are
+    pub fn texture_format(self, format: wgpu::TextureFormat) -> wgpu::TextureFormat {
+        match (self, format) {
+            (ColorSpace::Linear, wgpu::TextureFormat::Rgba8UnormSrgb) => {
+                wgpu::TextureFormat::Rgba8Unorm
+            }
+            (ColorSpace::Linear, wgpu::TextureFormat::Bgra8UnormSrgb) => {
+                wgpu::TextureFormat::Bgra8Unorm
+            }
+            _ => format,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct TextureConfiguration<'a> {
     pub label: Option<&'a str>,
     pub usage: TextureUsage,
     pub mip_count: Option<u32>,
+    pub color_space: ColorSpace,
 }
 
 impl TextureUsage {
@@ -64,9 +93,42 @@ impl TextureUsage {
 pub type RgbaTexture2D = Texture2D<RgbaU8>;
 pub type GpuRgbaTexture2D = GpuTexture<RgbaU8, RgbaTexture2D>;
 
+pub type R8Texture2D = Texture2D<RU8>;
+pub type GpuR8Texture2D = GpuTexture<RU8, R8Texture2D>;
+
 pub type DepthStencilTexture2D = Texture2D<DepthStencilTexel>;
 pub type GpuDepthStencilTexture2D = GpuTexture<DepthStencilTexel, DepthStencilTexture2D>;
 
+impl GpuRgbaTexture2D {
+    // Resets the texture to transparent black, used when recycling textures
+    pub(crate) fn clear(&self, framework: &Framework) {
+        if self.usage.use_as_render_target {
+            let mut encoder =
+                framework
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("Texture clear encoder"),
+                    });
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Texture clear pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: self.texture_view(0),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            framework.queue.submit(std::iter::once(encoder.finish()));
+        } else if self.usage.cpu_write {
+            let texels = vec![RgbaU8([0, 0, 0, 0]); (self.width() * self.height()) as usize];
+            self.write_data(&texels, framework);
+        }
+    }
+}
+
 impl GpuDepthStencilTexture2D {
     pub(crate) fn depth_view(&self) -> &TextureView {
         self.texture_view(0)
@@ -81,3 +143,31 @@ impl GpuDepthStencilTexture2D {
         self.bind_group(1)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use wgpu::TextureFormat;
+
+    use super::ColorSpace;
+
+    #[test]
+    fn linear_textures_drop_the_srgb_encoding() {
+        assert_eq!(
+            ColorSpace::Srgb.texture_format(TextureFormat::Rgba8UnormSrgb),
+            TextureFormat::Rgba8UnormSrgb
+        );
+        assert_eq!(
+            ColorSpace::Linear.texture_format(TextureFormat::Rgba8UnormSrgb),
+            TextureFormat::Rgba8Unorm
+        );
+        assert_eq!(
+            ColorSpace::Linear.texture_format(TextureFormat::Bgra8UnormSrgb),
+            TextureFormat::Bgra8Unorm
+        );
+        // The formats without an sRGB variant are left alone
+        for format in [TextureFormat::R8Unorm, TextureFormat::Depth24PlusStencil8] {
+            assert_eq!(ColorSpace::Srgb.texture_format(format), format);
+            assert_eq!(ColorSpace::Linear.texture_format(format), format);
+        }
+    }
+}
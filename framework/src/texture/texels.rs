@@ -104,6 +104,54 @@ impl Texel for RgbaU8 {
     }
 }
 
+// A single channel texel, used for masks
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RU8(pub u8);
+
+impl Texel for RU8 {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, TexelConversionError> {
+        if bytes.len() < Self::total_texel_size_bytes() as usize {
+            return Err(TexelConversionError::NotEnoughData);
+        }
+
+        Ok(RU8(bytes[0]))
+    }
+
+    fn channels() -> &'static [ChannelType] {
+        static R_CHANNELS: &[ChannelType] = &[ChannelType::U8];
+        R_CHANNELS
+    }
+
+    fn wgpu_texture_format() -> wgpu::TextureFormat {
+        wgpu::TextureFormat::R8Unorm
+    }
+
+    fn supported_aspects() -> &'static [AspectInfo] {
+        static ASPECTS: &[AspectInfo] = &[AspectInfo {
+            aspect: TextureAspect::All,
+            format: wgpu::TextureFormat::R8Unorm,
+            sample_type: TextureSampleType::Float { filterable: true },
+            create_sampler: true,
+        }];
+        ASPECTS
+    }
+
+    fn wgpu_color(&self) -> wgpu::Color {
+        let value = self.0 as f64 / 255.0;
+        wgpu::Color {
+            r: value,
+            g: 0.0,
+            b: 0.0,
+            a: 1.0,
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        std::slice::from_ref(&self.0)
+    }
+}
+
 #[repr(C)]
 #[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
 pub struct DepthStencilTexel([u8; 4]);
@@ -2,7 +2,7 @@ use std::num::NonZeroU8;
 
 use wgpu::{BindGroupLayout, Extent3d, Origin3d, TextureDimension};
 
-use crate::{BindingInfo, Framework, Texel};
+use crate::{BindingInfo, ColorSpace, Framework, Texel};
 
 pub trait SamplingOrigin {
     fn origin(&self) -> Origin3d;
@@ -74,11 +74,28 @@ impl SamplingExtents for (u32, u32, u32) {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TexelConversionError {
     NotEnoughData,
+    // The staging buffer couldn't be mapped, or the device was lost before it was
+    ReadbackFailed,
 }
 
+impl std::fmt::Display for TexelConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TexelConversionError::NotEnoughData => {
+                f.write_str("There aren't enough bytes for the size of the texture")
+            }
+            TexelConversionError::ReadbackFailed => {
+                f.write_str("The texture couldn't be read back from the GPU")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TexelConversionError {}
+
 pub trait Texture<T: Texel> {
     type SamplingPointType: SamplingOrigin;
     type SamplingExtentsType: SamplingExtents;
@@ -106,9 +123,11 @@ pub trait Texture<T: Texel> {
     fn width(&self) -> u32;
     fn height(&self) -> u32;
     fn layers(&self) -> u32;
+    // The views must use the same format of the texture, so they need its color space
     fn create_binding_info(
         &self,
         texture: &wgpu::Texture,
+        color_space: ColorSpace,
         device: &wgpu::Device,
     ) -> Vec<BindingInfo>;
 }
@@ -157,6 +176,7 @@ impl<T: Texel> Texture<T> for Texture2D<T> {
     fn create_binding_info(
         &self,
         texture: &wgpu::Texture,
+        color_space: ColorSpace,
         device: &wgpu::Device,
     ) -> Vec<BindingInfo> {
         let aspects = T::supported_aspects();
@@ -164,7 +184,7 @@ impl<T: Texel> Texture<T> for Texture2D<T> {
         for aspect in aspects {
             let view = texture.create_view(&wgpu::TextureViewDescriptor {
                 label: Some(format!("Texture2D view aspect: {:?}", aspect).as_str()),
-                format: Some(aspect.format),
+                format: Some(color_space.texture_format(aspect.format)),
                 dimension: Some(wgpu::TextureViewDimension::D2),
                 aspect: aspect.aspect,
                 base_mip_level: 0,
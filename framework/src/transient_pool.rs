@@ -0,0 +1,134 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{BufferType, ColorSpace, TextureUsage};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct TransientTextureKey {
+    pub width: u32,
+    pub height: u32,
+    pub usage: TextureUsage,
+    pub mip_count: Option<u32>,
+    // The sRGB and linear textures have different formats, so they can't replace each other
+    pub color_space: ColorSpace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct TransientBufferKey {
+    pub size_bytes: u64,
+    pub buffer_type: BufferType,
+    pub gpu_copy_source: bool,
+    pub cpu_copy_dest: bool,
+    pub cpu_copy_source: bool,
+}
+
+// A pool of resources that only live for a frame: everything acquired during a frame
+// is handed back on reclaim(), and whatever wasn't reused during the last frame gets dropped
+pub(crate) struct TransientPool<K, T> {
+    free: HashMap<K, Vec<T>>,
+    in_use: Vec<(K, T)>,
+    allocations: usize,
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> TransientPool<K, T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            free: HashMap::new(),
+            in_use: vec![],
+            allocations: 0,
+        }
+    }
+
+    // Returns a free resource matching the key, if any, marking it as used for this frame
+    pub(crate) fn acquire(&mut self, key: &K) -> Option<T> {
+        let resource = self.free.get_mut(key).and_then(|free| free.pop())?;
+        self.in_use.push((key.clone(), resource.clone()));
+        Some(resource)
+    }
+
+    // Tracks a freshly allocated resource, it will be recycled starting from the next frame
+    pub(crate) fn register(&mut self, key: K, resource: T) {
+        self.allocations += 1;
+        self.in_use.push((key, resource));
+    }
+
+    pub(crate) fn reclaim(&mut self) {
+        self.free.clear();
+        for (key, resource) in self.in_use.drain(..) {
+            self.free.entry(key).or_default().push(resource);
+        }
+    }
+
+    pub(crate) fn allocations(&self) -> usize {
+        self.allocations
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ColorSpace, TextureUsage};
+
+    use super::{TransientPool, TransientTextureKey};
+
+    fn acquire_or_allocate(pool: &mut TransientPool<(u32, u32), u32>, key: (u32, u32)) -> u32 {
+        if let Some(resource) = pool.acquire(&key) {
+            resource
+        } else {
+            let resource = pool.allocations() as u32;
+            pool.register(key, resource);
+            resource
+        }
+    }
+
+    #[test]
+    fn allocations_stabilize_after_warmup() {
+        let mut pool = TransientPool::new();
+        let mut allocations_after_warmup = None;
+        for frame in 0..10 {
+            // Simulate a composite with a group: two AB targets for the document, two for the group
+            for _ in 0..4 {
+                acquire_or_allocate(&mut pool, (1800, 1024));
+            }
+            pool.reclaim();
+            if frame == 0 {
+                allocations_after_warmup = Some(pool.allocations());
+            }
+        }
+        assert_eq!(allocations_after_warmup, Some(4));
+        assert_eq!(pool.allocations(), 4);
+    }
+
+    #[test]
+    fn resources_are_not_shared_within_a_frame() {
+        let mut pool = TransientPool::new();
+        let first = acquire_or_allocate(&mut pool, (16, 16));
+        let second = acquire_or_allocate(&mut pool, (16, 16));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn unused_resources_are_dropped() {
+        let mut pool = TransientPool::new();
+        acquire_or_allocate(&mut pool, (16, 16));
+        pool.reclaim();
+        // Nothing was acquired during this frame, so the free resource goes away
+        pool.reclaim();
+        acquire_or_allocate(&mut pool, (16, 16));
+        assert_eq!(pool.allocations(), 2);
+    }
+
+    #[test]
+    fn textures_are_not_shared_across_color_spaces() {
+        let mut pool = TransientPool::new();
+        let key = |color_space| TransientTextureKey {
+            width: 16,
+            height: 16,
+            usage: TextureUsage::RWRT,
+            mip_count: None,
+            color_space,
+        };
+        pool.register(key(ColorSpace::Srgb), 0);
+        pool.reclaim();
+        assert_eq!(pool.acquire(&key(ColorSpace::Linear)), None);
+        assert_eq!(pool.acquire(&key(ColorSpace::Srgb)), Some(0));
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use application::PressureCalibration;
+use framework::SrgbColor;
+use serde::{Deserialize, Serialize};
+
+use crate::config_dir::config_path;
+
+const APP_SETTINGS_NAME: &str = "settings.json";
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    strum_macros::EnumIter,
+    strum_macros::Display,
+    Serialize,
+    Deserialize,
+)]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
+impl Theme {
+    // The color of the window around the document
+    pub fn clear_color(&self) -> wgpu::Color {
+        match self {
+            Theme::Dark => wgpu::Color::from_srgb_u8([38, 38, 38, 255]),
+            Theme::Light => wgpu::Color::from_srgb_u8([204, 204, 204, 255]),
+        }
+    }
+}
+
+// Mirrors PressureCalibration, since the application crate doesn't depend on serde
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "PressureCalibration")]
+struct PressureCalibrationDef {
+    min: f32,
+    max: f32,
+    gamma: f32,
+}
+
+// The preferences kept between the sessions, stored as json in app_settings_path
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppSettings {
+    #[serde(default)]
+    pub theme: Theme,
+    #[serde(default, with = "PressureCalibrationDef")]
+    pub pressure_calibration: PressureCalibration,
+    // The undo history drops the undone edits unless it's branching
+    #[serde(default)]
+    pub branching_undo: bool,
+}
+
+impl AppSettings {
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    // A missing file gives the default settings, e.g. on the first run
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+pub fn app_settings_path() -> PathBuf {
+    config_path(APP_SETTINGS_NAME)
+}
+
+#[cfg(test)]
+mod test {
+    use application::PressureCalibration;
+    use framework::SrgbColor;
+
+    use super::{AppSettings, Theme};
+
+    #[test]
+    fn themes_have_their_own_clear_color() {
+        assert_eq!(
+            Theme::Dark.clear_color(),
+            wgpu::Color::from_srgb_u8([38, 38, 38, 255])
+        );
+        assert_eq!(
+            Theme::Light.clear_color(),
+            wgpu::Color::from_srgb_u8([204, 204, 204, 255])
+        );
+        let (dark, light) = (Theme::Dark.clear_color(), Theme::Light.clear_color());
+        assert!(dark.r < light.r && dark.g < light.g && dark.b < light.b);
+        assert_eq!((dark.a, light.a), (1.0, 1.0));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("mochi_app_settings_{}.json", std::process::id()));
+        assert_eq!(AppSettings::load(&path).unwrap(), AppSettings::default());
+
+        let settings = AppSettings {
+            theme: Theme::Light,
+            pressure_calibration: PressureCalibration {
+                min: 0.1,
+                max: 0.8,
+                gamma: 2.0,
+            },
+            branching_undo: true,
+        };
+        settings.save(&path).unwrap();
+        assert_eq!(AppSettings::load(&path).unwrap(), settings);
+        std::fs::remove_file(&path).unwrap();
+
+        // The settings missing in older files are left to their default
+        let loaded: AppSettings = serde_json::from_str("{}").unwrap();
+        assert_eq!(loaded.theme, Theme::Dark);
+        assert_eq!(loaded.pressure_calibration, PressureCalibration::default());
+        assert!(!loaded.branching_undo);
+    }
+}
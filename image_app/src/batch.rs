@@ -0,0 +1,261 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context};
+use framework::{renderer::renderer::Renderer, Framework};
+use image::imageops::FilterType;
+use image_editor::{document::AlphaMode, layers::LayerItem, ImageEditor};
+
+pub const BATCH_FLAG: &str = "--batch";
+pub const BATCH_USAGE: &str = "Usage: app --batch <input glob> <operation> <output directory>
+Operations:
+    resize:<width>x<height>    Resizes the images, e.g. resize:640x480
+    blur:<sigma>               Applies a gaussian blur, e.g. blur:2.5
+    convert:<extension>        Changes the format, e.g. convert:jpg";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatchOperation {
+    Resize { width: u32, height: u32 },
+    Blur(f32),
+    Convert(String),
+}
+
+impl FromStr for BatchOperation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, argument) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("The operation {s} has no argument"))?;
+        match name {
+            "resize" => {
+                let (width, height) = argument
+                    .split_once('x')
+                    .ok_or_else(|| anyhow!("The size {argument} isn't <width>x<height>"))?;
+                let (width, height) = (width.parse()?, height.parse()?);
+                if width == 0 || height == 0 {
+                    bail!("Cannot resize to {argument}");
+                }
+                Ok(Self::Resize { width, height })
+            }
+            "blur" => Ok(Self::Blur(argument.parse()?)),
+            "convert" => {
+                let extension = argument.trim_start_matches('.').to_lowercase();
+                if image::ImageFormat::from_extension(&extension).is_none() {
+                    bail!("Unknown image format {argument}");
+                }
+                Ok(Self::Convert(extension))
+            }
+            _ => bail!("Unknown operation {name}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchArgs {
+    pub input_glob: String,
+    pub operation: BatchOperation,
+    pub output_directory: PathBuf,
+}
+
+impl BatchArgs {
+    // The arguments following --batch
+    pub fn parse(args: &[String]) -> anyhow::Result<Self> {
+        match args {
+            [input_glob, operation, output_directory] => Ok(Self {
+                input_glob: input_glob.clone(),
+                operation: operation.parse()?,
+                output_directory: PathBuf::from(output_directory),
+            }),
+            _ => bail!("Expected 3 arguments, got {}\n{BATCH_USAGE}", args.len()),
+        }
+    }
+}
+
+// Runs the operation on every file matching the glob, without opening a window.
+// Returns the written files, the ones that couldn't be processed are logged and skipped
+pub fn run_batch(args: &BatchArgs, framework: &mut Framework) -> anyhow::Result<Vec<PathBuf>> {
+    let inputs = glob::glob(&args.input_glob)?
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    if inputs.is_empty() {
+        bail!("No file matches {}", args.input_glob);
+    }
+    fs::create_dir_all(&args.output_directory)?;
+
+    let mut renderer = Renderer::new(framework);
+    let mut image_editor = ImageEditor::new(framework, &[1024.0, 1024.0]);
+    let mut outputs = vec![];
+    for input in inputs {
+        match process_file(&input, args, &mut image_editor, &mut renderer, framework) {
+            Ok(output) => {
+                log::info!("{} -> {}", input.display(), output.display());
+                outputs.push(output);
+            }
+            Err(e) => log::error!("While processing {}: {e}", input.display()),
+        }
+        // There's no application loop to end the frames and free the closed documents
+        framework.end_frame();
+        framework.update_asset_maps();
+    }
+    Ok(outputs)
+}
+
+fn process_file(
+    input: &Path,
+    args: &BatchArgs,
+    image_editor: &mut ImageEditor,
+    renderer: &mut Renderer,
+    framework: &mut Framework,
+) -> anyhow::Result<PathBuf> {
+    if !image_editor.open_image_file(input, framework) {
+        bail!("Cannot open the image");
+    }
+    if let BatchOperation::Blur(sigma) = args.operation {
+        // The image is in the bottom layer, under the empty layer added to the new documents
+        let image_layer = match image_editor.document().tree().items().last() {
+            Some(LayerItem::SingleLayer(id)) => *id,
+            _ => bail!("The image layer is missing"),
+        };
+        let mut result = Ok(());
+        image_editor.mutate_document(|document| {
+            result = document
+                .apply_gaussian_blur(&image_layer, sigma, None, None, framework)
+                .map(|_| ());
+        });
+        result?;
+    }
+    image_editor.update_layers(renderer, framework);
+    image_editor.render_document(renderer, framework);
+    let mut image = image_editor
+        .document()
+        .final_image_bytes(AlphaMode::Straight, framework)?;
+    // Only one document is kept open
+    image_editor.close_document(image_editor.active_document_index(), framework);
+
+    if let BatchOperation::Resize { width, height } = args.operation {
+        image = image.resize_exact(width, height, FilterType::Lanczos3);
+    }
+    let extension = match &args.operation {
+        BatchOperation::Convert(extension) => extension.clone(),
+        _ => input
+            .extension()
+            .map_or("png".to_owned(), |ext| ext.to_string_lossy().into_owned()),
+    };
+    let file_stem = input.file_stem().context("The input has no file name")?;
+    let output = args
+        .output_directory
+        .join(format!("{}.{extension}", file_stem.to_string_lossy()));
+    // Jpeg has no alpha channel
+    if matches!(
+        image::ImageFormat::from_path(&output),
+        Ok(image::ImageFormat::Jpeg)
+    ) {
+        image = image.into_rgb8().into();
+    }
+    image.save(&output)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use image::{GenericImageView, Rgba, RgbaImage};
+    use image_editor::headless_framework;
+
+    use super::{run_batch, BatchArgs, BatchOperation};
+
+    #[test]
+    fn parse_the_operations() {
+        assert_eq!(
+            "resize:640x480".parse::<BatchOperation>().unwrap(),
+            BatchOperation::Resize {
+                width: 640,
+                height: 480
+            }
+        );
+        assert_eq!(
+            "blur:2.5".parse::<BatchOperation>().unwrap(),
+            BatchOperation::Blur(2.5)
+        );
+        assert_eq!(
+            "convert:.JPG".parse::<BatchOperation>().unwrap(),
+            BatchOperation::Convert("jpg".to_owned())
+        );
+        for invalid in [
+            "resize:0x10",
+            "resize:640",
+            "blur",
+            "convert:doc",
+            "crop:1x1",
+        ] {
+            assert!(invalid.parse::<BatchOperation>().is_err(), "{invalid}");
+        }
+        assert!(BatchArgs::parse(&["*.png".to_owned()]).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn batch_resize_writes_the_outputs() {
+        let mut framework = headless_framework();
+
+        let directory = std::env::temp_dir().join(format!("mochi_batch_{}", std::process::id()));
+        let input_directory = directory.join("input");
+        std::fs::create_dir_all(&input_directory).unwrap();
+        RgbaImage::from_pixel(64, 32, Rgba([255, 0, 0, 255]))
+            .save(input_directory.join("red.png"))
+            .unwrap();
+
+        let args = BatchArgs::parse(&[
+            input_directory.join("*.png").to_string_lossy().into_owned(),
+            "resize:16x8".to_owned(),
+            directory.join("output").to_string_lossy().into_owned(),
+        ])
+        .unwrap();
+        let outputs = run_batch(&args, &mut framework).unwrap();
+
+        assert_eq!(outputs, vec![directory.join("output").join("red.png")]);
+        let output = image::open(&outputs[0]).unwrap();
+        assert_eq!(output.dimensions(), (16, 8));
+        assert_eq!(output.get_pixel(8, 4).0, [255, 0, 0, 255]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn batch_blur_changes_the_image() {
+        let mut framework = headless_framework();
+
+        let directory =
+            std::env::temp_dir().join(format!("mochi_batch_blur_{}", std::process::id()));
+        let input_directory = directory.join("input");
+        std::fs::create_dir_all(&input_directory).unwrap();
+        // Red on the left half, blue on the right one
+        RgbaImage::from_fn(64, 32, |x, _| {
+            if x < 32 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        })
+        .save(input_directory.join("halves.png"))
+        .unwrap();
+
+        let args = BatchArgs::parse(&[
+            input_directory.join("*.png").to_string_lossy().into_owned(),
+            "blur:4".to_owned(),
+            directory.join("output").to_string_lossy().into_owned(),
+        ])
+        .unwrap();
+        let outputs = run_batch(&args, &mut framework).unwrap();
+
+        let output = image::open(&outputs[0]).unwrap();
+        assert_eq!(output.dimensions(), (64, 32));
+        let [r, _, b, _] = output.get_pixel(32, 16).0;
+        assert!(r > 0 && b > 0, "The halves are not blended: {r} {b}");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
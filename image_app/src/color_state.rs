@@ -0,0 +1,81 @@
+use crate::tools::brush_engine::stamping_engine::StrokingEngine;
+
+pub const DEFAULT_FOREGROUND: [u8; 3] = [0, 0, 0];
+pub const DEFAULT_BACKGROUND: [u8; 3] = [255, 255, 255];
+
+// The brush paints with the foreground color, the background one is kept at hand
+// to be swapped with it or to fill the selection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColorState {
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+}
+
+impl Default for ColorState {
+    fn default() -> Self {
+        Self {
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+        }
+    }
+}
+
+impl ColorState {
+    pub fn swap(&mut self) {
+        std::mem::swap(&mut self.foreground, &mut self.background);
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    // The brush color is the foreground color, but it's also changed by the color pickers:
+    // it's read before changing the colors, then the new foreground is given to the brush
+    pub fn update_brush<F: FnOnce(&mut Self)>(&mut self, engine: &mut StrokingEngine, change: F) {
+        let mut settings = engine.settings();
+        self.foreground = settings.color_srgb;
+        change(self);
+        if settings.color_srgb != self.foreground {
+            settings.color_srgb = self.foreground;
+            engine.set_new_settings(settings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ColorState, DEFAULT_BACKGROUND, DEFAULT_FOREGROUND};
+
+    #[test]
+    fn swapping_exchanges_the_colors() {
+        let mut colors = ColorState {
+            foreground: [200, 30, 10],
+            background: [5, 90, 250],
+        };
+        colors.swap();
+        assert_eq!(colors.foreground, [5, 90, 250]);
+        assert_eq!(colors.background, [200, 30, 10]);
+
+        colors.swap();
+        assert_eq!(
+            colors,
+            ColorState {
+                foreground: [200, 30, 10],
+                background: [5, 90, 250],
+            }
+        );
+    }
+
+    #[test]
+    fn reset_goes_back_to_black_on_white() {
+        let mut colors = ColorState {
+            foreground: [200, 30, 10],
+            background: [5, 90, 250],
+        };
+        colors.reset();
+        assert_eq!(colors.foreground, DEFAULT_FOREGROUND);
+        assert_eq!(colors.background, DEFAULT_BACKGROUND);
+        assert_eq!(colors, ColorState::default());
+        assert_eq!((colors.foreground, colors.background), ([0; 3], [255; 3]));
+    }
+}
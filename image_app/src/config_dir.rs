@@ -0,0 +1,11 @@
+use std::path::PathBuf;
+
+// The settings, the recent files and the brush presets are kept in the per-user config
+// directory (e.g. ~/.config/mochi on Linux), so they don't depend on where mochi is started
+// from. Falls back to the working directory on the platforms without one
+pub fn config_path(name: &str) -> PathBuf {
+    match dirs::config_dir() {
+        Some(dir) => dir.join("mochi").join(name),
+        None => PathBuf::from(name),
+    }
+}
@@ -1,68 +1,35 @@
+use std::collections::HashMap;
 use std::ops::DerefMut;
+use std::path::Path;
+use std::time::{Duration, Instant};
 use std::{cell::RefCell, rc::Rc};
 
-use crate::toolbox::{ToolId, Toolbox};
+use crate::app_settings::{app_settings_path, AppSettings, Theme};
+use crate::color_state::ColorState;
+use crate::recent_files::{recent_files_path, RecentFiles};
+use crate::toolbox::{ToolKind, Toolbox};
 use crate::tools::brush_engine::stamping_engine::StrokingEngine;
 use crate::tools::{
-    BrushTool, ColorPicker, DebugSelectRegionTool, EditorCommand, EditorContext, HandTool,
-    RectSelectionTool, TransformLayerTool,
+    BrushTool, ColorPicker, DebugSelectRegionTool, EditorContext, EraserTool, HandTool, LassoTool,
+    MeasureTool, PolygonSelectTool, RectSelectionTool, ScreenColorPicker, TransformLayerTool,
 };
-use crate::ui::{self, ToolUiContext, Ui, UiContext};
+use crate::ui::{self, PaletteAction, ToolUiContext, Ui, UiContext};
+use crate::undo_tree::UndoTree;
 use application::{
     key::{Key, ModifierSet},
-    ActionMap, ActionState, AppContext, AppLoop, KeyBinding,
+    ActionMap, ActionState, AppContext, AppLoop, FrameSettings, KeyBinding,
 };
 use application::{AppBoot, AppResized};
 
-use image_editor::ImageEditor;
+use framework::Framework;
+use image_editor::{autosave, define_editor_shaders, DocumentId, ImageEditor};
 use log::warn;
+use strum::IntoEnumIterator;
 use wgpu::TextureView;
 use winit::dpi::LogicalSize;
 
-#[derive(Default)]
-pub struct UndoStack {
-    undo: Vec<Box<dyn EditorCommand>>,
-    redo: Vec<Box<dyn EditorCommand>>,
-}
-
-impl UndoStack {
-    pub fn push(&mut self, command: Box<dyn EditorCommand>) {
-        self.redo.clear();
-        self.undo.push(command);
-    }
-
-    pub fn do_undo(&mut self, context: &mut EditorContext) {
-        let command = self.undo.pop().expect("Empty undo stack!");
-        let redo_command = command.undo(context);
-        self.redo.push(redo_command);
-    }
-
-    pub fn do_redo(&mut self, context: &mut EditorContext) {
-        let command = self.redo.pop().expect("Empty redo stack!");
-        let undo_command = command.undo(context);
-        self.undo.push(undo_command);
-    }
-
-    pub fn has_undo(&self) -> bool {
-        !self.undo.is_empty()
-    }
-
-    pub fn has_redo(&self) -> bool {
-        !self.redo.is_empty()
-    }
-
-    fn try_undo(&mut self, context: &mut EditorContext) {
-        if self.has_undo() {
-            self.do_undo(context)
-        }
-    }
-
-    fn try_redo(&mut self, context: &mut EditorContext) {
-        if self.has_redo() {
-            self.do_redo(context)
-        }
-    }
-}
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+const AUTOSAVE_EDITS: u64 = 50;
 
 fn read_action_bindings(action_map: &mut ActionMap<String>) {
     // TODO: Action bindings aren't actually read from a file yet.
@@ -91,7 +58,66 @@ fn read_action_bindings(action_map: &mut ActionMap<String>) {
     );
     action_map.add_action_binding((Key::B, ActionState::Pressed), "pick_brush");
     action_map.add_action_binding((Key::M, ActionState::Pressed), "pick_move");
-    action_map.add_action_binding((Key::E, ActionState::Pressed), "toggle_eraser");
+    action_map.add_action_binding((Key::E, ActionState::Pressed), "pick_eraser");
+    action_map.add_action_binding((Key::Q, ActionState::Pressed), "toggle_quick_mask");
+    action_map.add_action_binding((Key::X, ActionState::Pressed), "swap_colors");
+    action_map.add_action_binding((Key::D, ActionState::Pressed), "reset_colors");
+    action_map.add_action_binding(
+        KeyBinding {
+            key: (Key::P, ActionState::Pressed),
+            modifiers: ModifierSet::new(false, false, true, false),
+        },
+        "command_palette",
+    );
+    action_map.add_action_binding(
+        KeyBinding {
+            key: (Key::A, ActionState::Pressed),
+            modifiers: ModifierSet::new(false, false, true, false),
+        },
+        "select_all",
+    );
+    action_map.add_action_binding(
+        KeyBinding {
+            key: (Key::D, ActionState::Pressed),
+            modifiers: ModifierSet::new(false, false, true, false),
+        },
+        "deselect",
+    );
+    action_map.add_action_binding(
+        KeyBinding {
+            key: (Key::Key0, ActionState::Pressed),
+            modifiers: ModifierSet::new(false, false, true, false),
+        },
+        "reset_view",
+    );
+}
+
+// The actions listed in the command palette, along with their display names
+fn palette_actions() -> Vec<PaletteAction> {
+    let mut actions = vec![
+        PaletteAction::new("save", "Export image"),
+        PaletteAction::new("undo", "Undo"),
+        PaletteAction::new("redo", "Redo"),
+    ];
+    actions.extend(
+        ToolKind::iter().map(|kind| PaletteAction::new(kind.action(), kind.display_name())),
+    );
+    actions.extend([
+        PaletteAction::new("toggle_quick_mask", "Toggle quick mask"),
+        PaletteAction::new("swap_colors", "Swap foreground and background colors"),
+        PaletteAction::new("reset_colors", "Reset the colors to black and white"),
+        PaletteAction::new("select_all", "Select all"),
+        PaletteAction::new("deselect", "Deselect"),
+        PaletteAction::new("define_brush", "Define brush from selection"),
+        PaletteAction::new("reset_view", "Reset view"),
+        PaletteAction::new("pick_screen_color", "Pick color from screen"),
+        PaletteAction::new("reopen_last", "Reopen last file"),
+        PaletteAction::new("theme_dark", "Dark theme"),
+        PaletteAction::new("theme_light", "Light theme"),
+        PaletteAction::new("undo_history_linear", "Linear undo history"),
+        PaletteAction::new("undo_history_branching", "Branching undo history"),
+    ]);
+    actions
 }
 
 pub struct ImageApplication {
@@ -100,43 +126,71 @@ pub struct ImageApplication {
     ui: Box<dyn Ui>,
     stamping_engine: Rc<RefCell<StrokingEngine>>,
     brush_tool: Rc<RefCell<BrushTool>>,
+    eraser_tool: Rc<RefCell<EraserTool>>,
+    polygon_select_tool: Rc<RefCell<PolygonSelectTool>>,
+    move_tool: Rc<RefCell<TransformLayerTool>>,
+    screen_color_picker: ScreenColorPicker,
     #[allow(dead_code)]
     hand_tool: Rc<RefCell<HandTool>>,
-    undo_stack: UndoStack,
-
-    brush_id: ToolId,
-    move_tool_id: ToolId,
-    #[allow(dead_code)]
-    color_picker_id: ToolId,
+    // Each open document has its own history
+    undo_trees: HashMap<DocumentId, UndoTree>,
+    frame_settings: FrameSettings,
+    surface_format: wgpu::TextureFormat,
+    surface_size: (u32, u32),
+    last_update: Instant,
+    recent_files: RecentFiles,
+    colors: ColorState,
+    settings: AppSettings,
 }
 
 impl AppLoop<(), String> for ImageApplication {
     fn boot(app_boot: AppBoot) -> Self {
         let framework = app_boot.framework;
 
-        framework
-            .shader_compiler
-            .define("blend_modes", include_str!("blend_modes.wgsl"))
-            .unwrap();
+        define_editor_shaders(framework);
 
-        let image_editor = ImageEditor::new(framework, &[1024.0, 1024.0]);
+        let mut image_editor = ImageEditor::new(framework, &[1024.0, 1024.0]);
+        let autosave_dir = autosave::default_autosave_dir();
+        let session = autosave::current_session();
+        for path in autosave::recoverable_autosaves(&autosave_dir, session) {
+            offer_autosave_recovery(&path, &mut image_editor, framework);
+        }
+        autosave::remove_other_sessions_autosaves(&autosave_dir, session);
+        image_editor.set_autosave(Some(AUTOSAVE_INTERVAL), Some(AUTOSAVE_EDITS), autosave_dir);
 
         let test_stamp = Toolbox::create_test_stamp(framework);
         let stamping_engine = StrokingEngine::new(test_stamp, framework);
         let stamping_engine = Rc::new(RefCell::new(stamping_engine));
-        let brush_tool = Rc::new(RefCell::new(BrushTool::new(stamping_engine.clone(), 1.0)));
-        let hand_tool = Rc::new(RefCell::new(HandTool::new()));
         let color_picker = Rc::new(RefCell::new(ColorPicker::new(stamping_engine.clone())));
+        let screen_color_picker = ScreenColorPicker::new(stamping_engine.clone());
+        let brush_tool = Rc::new(RefCell::new(BrushTool::new(
+            stamping_engine.clone(),
+            color_picker.clone(),
+            1.0,
+        )));
+        let eraser_tool = Rc::new(RefCell::new(EraserTool::new(
+            stamping_engine.clone(),
+            color_picker.clone(),
+            1.0,
+        )));
+        let hand_tool = Rc::new(RefCell::new(HandTool::new()));
         let move_tool = Rc::new(RefCell::new(TransformLayerTool::new()));
         let test_tool = Rc::new(RefCell::new(DebugSelectRegionTool::new()));
         let rect_select_tool = Rc::new(RefCell::new(RectSelectionTool::new()));
+        let lasso_tool = Rc::new(RefCell::new(LassoTool::new()));
+        let polygon_select_tool = Rc::new(RefCell::new(PolygonSelectTool::new()));
+        let measure_tool = Rc::new(RefCell::new(MeasureTool::new()));
 
-        let (mut toolbox, brush_id) = Toolbox::new(brush_tool.clone());
-        let _ = toolbox.add_tool(hand_tool.clone());
-        let color_picker_id = toolbox.add_tool(color_picker.clone());
-        let move_tool_id = toolbox.add_tool(move_tool);
-        let _ = toolbox.add_tool(test_tool);
-        let _ = toolbox.add_tool(rect_select_tool);
+        let (mut toolbox, _) = Toolbox::new(ToolKind::Brush, brush_tool.clone());
+        let _ = toolbox.add_tool(ToolKind::Eraser, eraser_tool.clone());
+        let _ = toolbox.add_tool(ToolKind::Hand, hand_tool.clone());
+        let _ = toolbox.add_tool(ToolKind::ColorPicker, color_picker.clone());
+        let _ = toolbox.add_tool(ToolKind::Move, move_tool.clone());
+        let _ = toolbox.add_tool(ToolKind::DebugSelectRegion, test_tool);
+        let _ = toolbox.add_tool(ToolKind::RectSelection, rect_select_tool);
+        let _ = toolbox.add_tool(ToolKind::Lasso, lasso_tool);
+        let _ = toolbox.add_tool(ToolKind::PolygonSelection, polygon_select_tool.clone());
+        let _ = toolbox.add_tool(ToolKind::Measure, measure_tool);
 
         let ui = Box::new(ui::create_ui(
             app_boot.surface_configuration,
@@ -144,18 +198,49 @@ impl AppLoop<(), String> for ImageApplication {
             framework,
         ));
 
+        let settings = AppSettings::load(&app_settings_path()).unwrap_or_else(|e| {
+            warn!("Could not load the settings: {e}");
+            AppSettings::default()
+        });
+        image_editor.set_canvas_clear_color(settings.theme.clear_color());
+        toolbox.pressure_calibration = settings.pressure_calibration;
+
         Self {
             image_editor,
             toolbox,
             ui,
             stamping_engine,
             brush_tool,
+            eraser_tool,
+            polygon_select_tool,
+            move_tool,
+            screen_color_picker,
             hand_tool,
-            undo_stack: UndoStack::default(),
-
-            brush_id,
-            color_picker_id,
-            move_tool_id,
+            undo_trees: HashMap::new(),
+            frame_settings: FrameSettings::default(),
+            surface_format: app_boot.surface_configuration.format,
+            surface_size: (
+                app_boot.surface_configuration.width,
+                app_boot.surface_configuration.height,
+            ),
+            last_update: Instant::now(),
+            recent_files: RecentFiles::load(&recent_files_path()).unwrap_or_else(|e| {
+                warn!("Could not load the recent files: {e}");
+                RecentFiles::default()
+            }),
+            colors: ColorState::default(),
+            settings,
+        }
+    }
+    // The autosaves of the documents closed with unsaved edits are kept, the next session offers
+    // to recover them
+    fn shutdown(&mut self) {
+        // The calibration changes while its sliders are dragged, so it's saved once on exit
+        if self.settings.pressure_calibration != self.toolbox.pressure_calibration {
+            self.settings.pressure_calibration = self.toolbox.pressure_calibration;
+            if let Err(e) = self.settings.save(&app_settings_path()) {
+                warn!("Could not save the settings: {e}");
+            }
         }
     }
     fn setup_action_map(&self, mut action_map: &mut ActionMap<String>) {
@@ -177,67 +262,184 @@ impl AppLoop<(), String> for ImageApplication {
         ];
         self.image_editor
             .on_resize(left_right_top_bottom, resized.framework);
+        self.surface_format = resized.surface_configuration.format;
+        self.surface_size = (
+            resized.surface_configuration.width,
+            resized.surface_configuration.height,
+        );
         self.ui.on_resized(resized);
     }
 
     fn on_winit_event(&mut self, event: &winit::event::Event<()>) {
         self.ui.on_new_winit_event(&event);
+        // The ui reacts to the window events, e.g. by highlighting the hovered widgets
+        if let winit::event::Event::WindowEvent { .. } = event {
+            self.image_editor.mark_dirty();
+        }
     }
 
     fn update(&mut self, mut app_context: AppContext) {
+        self.image_editor.update_pending_export();
+        let now = Instant::now();
+        let edit_count = self
+            .undo_trees
+            .get(&self.image_editor.active_document_id())
+            .map_or(0, |undo_tree| undo_tree.edit_count());
+        self.image_editor.update_autosave(
+            now - self.last_update,
+            edit_count,
+            &mut app_context.renderer,
+            app_context.framework,
+        );
+        self.last_update = now;
+        if !self.ui.wants_keyboard_input() {
+            // The brackets resize the eraser while it's selected
+            if self.toolbox.active_kind() == Some(ToolKind::Eraser) {
+                self.eraser_tool
+                    .borrow_mut()
+                    .update_size_from_keys(&app_context.input_state);
+            } else {
+                self.brush_tool
+                    .borrow_mut()
+                    .update_size_from_keys(&app_context.input_state);
+            }
+            self.polygon_select_tool
+                .borrow_mut()
+                .update_from_keys(&app_context.input_state);
+        }
+        if !self.ui.wants_keyboard_input() && self.toolbox.active_kind() == Some(ToolKind::Move) {
+            let mut context = EditorContext {
+                framework: &mut app_context.framework,
+                image_editor: &mut self.image_editor,
+                renderer: &mut app_context.renderer,
+            };
+            let nudge = self
+                .move_tool
+                .borrow()
+                .nudge_from_keys(&app_context.input_state, &mut context);
+            if let Some(command) = nudge {
+                self.undo_trees
+                    .entry(self.image_editor.active_document_id())
+                    .or_insert_with(|| UndoTree::new(self.settings.branching_undo))
+                    .push(command);
+            }
+        }
+        if self.screen_color_picker.is_active() {
+            // The click is picking a color, so it's not handled by the tools
+            self.screen_color_picker.update(&app_context.input_state);
+            return;
+        }
+        let undo_tree = self
+            .undo_trees
+            .entry(self.image_editor.active_document_id())
+            .or_insert_with(|| UndoTree::new(self.settings.branching_undo));
         let context = EditorContext {
             framework: app_context.framework,
             image_editor: &mut self.image_editor,
             renderer: &mut app_context.renderer,
         };
         self.toolbox
-            .update(&app_context.input_state, &mut self.undo_stack, context);
+            .update(&app_context.input_state, undo_tree, context);
     }
     fn render(&mut self, mut app_context: AppContext, app_surface: wgpu::TextureView) {
-        self.image_editor
-            .update_layers(&mut app_context.renderer, app_context.framework);
-        self.image_editor
-            .render_document(&mut app_context.renderer, app_context.framework);
+        // When only the ui changed, the last composited document can be reused
+        if self.image_editor.is_dirty() {
+            self.image_editor
+                .update_layers(&mut app_context.renderer, app_context.framework);
+            self.image_editor
+                .render_document(&mut app_context.renderer, app_context.framework);
+        }
         self.draw_editor(app_context, app_surface);
     }
     fn dispatch_actions(&mut self, actions: Vec<String>, mut context: AppContext) {
         for action in actions {
+            if let Some(kind) = ToolKind::from_action(&action) {
+                self.toolbox.select_by_kind(
+                    kind,
+                    EditorContext {
+                        framework: &mut context.framework,
+                        image_editor: &mut self.image_editor,
+                        renderer: &mut context.renderer,
+                    },
+                );
+                continue;
+            }
             match action.as_str() {
                 "save" => {
-                    self.image_editor.export_current_image(context.framework);
+                    if let Some(path) = self.image_editor.export_current_image(context.framework) {
+                        self.recent_files.remember(path);
+                    }
                 }
                 "undo" => {
-                    self.undo_stack.try_undo(&mut EditorContext {
+                    let undo_tree = self
+                        .undo_trees
+                        .entry(self.image_editor.active_document_id())
+                        .or_insert_with(|| UndoTree::new(self.settings.branching_undo));
+                    if let Err(e) = undo_tree.undo(&mut EditorContext {
                         framework: &mut context.framework,
                         image_editor: &mut self.image_editor,
                         renderer: &mut context.renderer,
-                    });
+                    }) {
+                        warn!("Could not undo the last edit: {e}");
+                    }
                 }
                 "redo" => {
-                    self.undo_stack.try_redo(&mut EditorContext {
+                    let undo_tree = self
+                        .undo_trees
+                        .entry(self.image_editor.active_document_id())
+                        .or_insert_with(|| UndoTree::new(self.settings.branching_undo));
+                    if let Err(e) = undo_tree.redo(&mut EditorContext {
                         framework: &mut context.framework,
                         image_editor: &mut self.image_editor,
                         renderer: &mut context.renderer,
+                    }) {
+                        warn!("Could not redo the last edit: {e}");
+                    }
+                }
+                "toggle_quick_mask" => {
+                    self.image_editor.toggle_quick_mask(context.framework);
+                }
+                "swap_colors" => self
+                    .colors
+                    .update_brush(&mut self.stamping_engine.borrow_mut(), ColorState::swap),
+                "reset_colors" => self
+                    .colors
+                    .update_brush(&mut self.stamping_engine.borrow_mut(), ColorState::reset),
+                "command_palette" => {
+                    self.ui.open_command_palette(palette_actions());
+                }
+                "select_all" => self.image_editor.mutate_document(|doc| doc.select_all()),
+                "deselect" => self.image_editor.mutate_document(|doc| doc.deselect()),
+                "define_brush" => {
+                    let mut engine = self.stamping_engine.borrow_mut();
+                    let mut stamp = None;
+                    self.image_editor.mutate_document(|doc| {
+                        stamp = engine.define_brush_from_selection(
+                            doc,
+                            context.renderer,
+                            context.framework,
+                        );
                     });
+                    if stamp.is_none() {
+                        warn!("Select some pixels of a layer to define a brush from them");
+                    }
                 }
-                "pick_brush" => self.toolbox.set_primary_tool(
-                    &self.brush_id,
-                    EditorContext {
-                        framework: &mut context.framework,
-                        image_editor: &mut self.image_editor,
-                        renderer: &mut context.renderer,
-                    },
-                ),
-                "pick_move" => self.toolbox.set_primary_tool(
-                    &self.move_tool_id,
-                    EditorContext {
-                        framework: &mut context.framework,
-                        image_editor: &mut self.image_editor,
-                        renderer: &mut context.renderer,
-                    },
-                ),
-                "toggle_eraser" => {
-                    self.stamping_engine.borrow_mut().toggle_eraser();
+                "reset_view" => self
+                    .image_editor
+                    .reset_view(context.input_state.window_size()),
+                "pick_screen_color" => self.screen_color_picker.start(),
+                "theme_dark" => self.set_theme(Theme::Dark),
+                "theme_light" => self.set_theme(Theme::Light),
+                "undo_history_linear" => self.set_branching_undo(false),
+                "undo_history_branching" => self.set_branching_undo(true),
+                "reopen_last" => {
+                    if let Some(path) = self.recent_files.paths().first().cloned() {
+                        if self.image_editor.open_image_file(&path, context.framework) {
+                            self.recent_files.remember(path);
+                        } else {
+                            self.recent_files.forget(&path);
+                        }
+                    }
                 }
                 _ => {
                     warn!("Unrecognised input action! {}", action);
@@ -245,14 +447,69 @@ impl AppLoop<(), String> for ImageApplication {
             }
         }
     }
+    fn should_render(&self) -> bool {
+        self.image_editor.is_dirty()
+            || self.ui.needs_repaint()
+            || self.screen_color_picker.wants_capture()
+    }
+    fn frame_settings(&self) -> FrameSettings {
+        self.frame_settings
+    }
     fn title(&self) -> &str {
         "Mochi Image Editor"
     }
 }
 
+// The autosave is opened as a new document: its layers are recovered with their names and labels,
+// while the groups, the masks, the blend settings and the selection are lost
+fn offer_autosave_recovery(path: &Path, image_editor: &mut ImageEditor, framework: &mut Framework) {
+    let recover = rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Info)
+        .set_title("Recover autosave")
+        .set_description(&format!(
+            "A document autosaved by a previous session was found in {}, do you want to open it? \
+             Its layers are recovered, but not their groups, masks and blend settings.",
+            path.display()
+        ))
+        .set_buttons(rfd::MessageButtons::YesNo)
+        .show();
+    if recover {
+        image_editor.open_image_file(path, framework);
+    }
+}
+
 impl ImageApplication {
+    // Changes the color around the document, the theme is kept for the next sessions
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.image_editor
+            .set_canvas_clear_color(theme.clear_color());
+        if self.settings.theme != theme {
+            self.settings.theme = theme;
+            if let Err(e) = self.settings.save(&app_settings_path()) {
+                warn!("Could not save the settings: {e}");
+            }
+        }
+    }
+
+    // The undo histories of all the open documents switch, the choice is kept for the next sessions
+    pub fn set_branching_undo(&mut self, branching: bool) {
+        for undo_tree in self.undo_trees.values_mut() {
+            undo_tree.set_branching(branching);
+        }
+        if self.settings.branching_undo != branching {
+            self.settings.branching_undo = branching;
+            if let Err(e) = self.settings.save(&app_settings_path()) {
+                warn!("Could not save the settings: {e}");
+            }
+        }
+    }
+
     fn draw_editor(&mut self, mut state: AppContext, out_surface: TextureView) {
         self.ui.begin();
+        let undo_tree = self
+            .undo_trees
+            .entry(self.image_editor.active_document_id())
+            .or_insert_with(|| UndoTree::new(self.settings.branching_undo));
         let ui_ctx = UiContext {
             framework: &mut state.framework,
             image_editor: &mut self.image_editor,
@@ -260,18 +517,40 @@ impl ImageApplication {
             input_state: &state.input_state,
             stamping_engine: self.stamping_engine.clone(),
             brush_tool: self.brush_tool.clone(),
-            undo_stack: &mut self.undo_stack,
+            undo_tree,
             renderer: &mut state.renderer,
+            frame_settings: &mut self.frame_settings,
+            recent_files: &mut self.recent_files,
+            colors: &mut self.colors,
+            theme: self.settings.theme,
         };
         let block_editor = self.ui.do_ui(ui_ctx);
+        if let Some(closed) = self.ui.take_closed_document() {
+            self.undo_trees.remove(&closed);
+        }
+        if let Some(action) = self.ui.take_palette_action() {
+            self.dispatch_actions(
+                vec![action],
+                AppContext {
+                    renderer: &mut *state.renderer,
+                    framework: &mut *state.framework,
+                    input_state: state.input_state,
+                },
+            );
+        }
+        let undo_tree = self
+            .undo_trees
+            .entry(self.image_editor.active_document_id())
+            .or_insert_with(|| UndoTree::new(self.settings.branching_undo));
         let ui_ctx = ToolUiContext {
             framework: &mut state.framework,
             image_editor: &mut self.image_editor,
             input_state: &state.input_state,
             stamping_engine: self.stamping_engine.clone(),
             brush_tool: self.brush_tool.clone(),
-            undo_stack: &mut self.undo_stack,
+            undo_tree,
             renderer: &mut state.renderer,
+            recent_files: &mut self.recent_files,
         };
         let block_editor = self
             .ui
@@ -284,6 +563,13 @@ impl ImageApplication {
             state.framework,
         );
         self.toolbox.draw(&mut state.renderer);
+        if let Some(cursor_position) = self
+            .image_editor
+            .transform_point_into_pixel_position(state.input_state.normalized_mouse_position())
+        {
+            self.toolbox
+                .draw_cursor(&mut state.renderer, cursor_position, &self.image_editor);
+        }
         state.renderer.end(
             &self.image_editor.document().render_result(),
             None,
@@ -291,7 +577,42 @@ impl ImageApplication {
         );
         self.image_editor
             .render_canvas(&mut state.renderer, &out_surface, state.framework);
-        let ui_command = self.ui.present(&out_surface, &state.framework);
-        state.framework.queue.submit(std::iter::once(ui_command));
+        if self.screen_color_picker.wants_capture() {
+            // The surface can't be read back, so the frame is drawn again on a capture
+            let capture = self.create_screen_capture(state.framework);
+            let capture_view = capture.create_view(&wgpu::TextureViewDescriptor::default());
+            self.image_editor
+                .render_canvas(&mut state.renderer, &capture_view, state.framework);
+            let ui_command = self
+                .ui
+                .present(&[&out_surface, &capture_view], &state.framework);
+            state.framework.queue.submit(std::iter::once(ui_command));
+            self.screen_color_picker.pick_from_capture(
+                &capture,
+                self.surface_format,
+                self.surface_size,
+                state.framework,
+            );
+        } else {
+            let ui_command = self.ui.present(&[&out_surface], &state.framework);
+            state.framework.queue.submit(std::iter::once(ui_command));
+        }
+    }
+
+    fn create_screen_capture(&self, framework: &Framework) -> wgpu::Texture {
+        let (width, height) = self.surface_size;
+        framework.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Screen color picker capture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.surface_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        })
     }
 }
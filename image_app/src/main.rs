@@ -1,15 +1,29 @@
+mod app_settings;
+mod batch;
+mod color_state;
+mod config_dir;
 mod image_editor_app_loop;
+mod recent_files;
+mod scripting;
 mod toolbox;
 pub mod tools;
 mod ui;
+mod undo_tree;
 
 use application::{AppDescription, Application};
+use batch::{BatchArgs, BATCH_FLAG};
+use framework::Framework;
 use image_editor_app_loop::ImageApplication;
 use tools::*;
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
 
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(String::as_str) == Some(BATCH_FLAG) {
+        return run_batch(&args[1..]);
+    }
+
     Application::<()>::new(AppDescription {
         initial_width: 800,
         initial_height: 600,
@@ -17,3 +31,16 @@ fn main() -> anyhow::Result<()> {
     .run::<String, ImageApplication>()?;
     Ok(())
 }
+
+fn run_batch(args: &[String]) -> anyhow::Result<()> {
+    let args = BatchArgs::parse(args)?;
+    let mut framework = Framework::new(&Default::default())?;
+    image_editor::define_editor_shaders(&mut framework);
+    let outputs = batch::run_batch(&args, &mut framework)?;
+    println!(
+        "Wrote {} files to {}",
+        outputs.len(),
+        args.output_directory.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::config_dir::config_path;
+
+const RECENT_FILES_NAME: &str = "recent_files.json";
+pub const MAX_RECENT_FILES: usize = 10;
+
+// The files opened or saved last, the most recent first
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecentFiles {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    // Moves the path to the front, dropping the oldest paths past MAX_RECENT_FILES
+    pub fn push(&mut self, path: PathBuf) {
+        self.paths.retain(|p| *p != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(MAX_RECENT_FILES);
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.paths.retain(|p| p != path);
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    // A missing file is an empty list, e.g. on the first run
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    // Pushes the path and writes the list to recent_files_path
+    pub fn remember(&mut self, path: PathBuf) {
+        self.push(path);
+        self.store();
+    }
+
+    // Removes a path that can't be opened anymore
+    pub fn forget(&mut self, path: &Path) {
+        self.remove(path);
+        self.store();
+    }
+
+    fn store(&self) {
+        if let Err(e) = self.save(&recent_files_path()) {
+            warn!("Could not save the recent files: {e}");
+        }
+    }
+}
+
+// Where the list is kept between the sessions
+pub fn recent_files_path() -> PathBuf {
+    config_path(RECENT_FILES_NAME)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{RecentFiles, MAX_RECENT_FILES};
+
+    #[test]
+    fn reopened_files_move_to_the_front() {
+        let mut recent = RecentFiles::default();
+        recent.push("a.png".into());
+        recent.push("b.png".into());
+        recent.push("c.png".into());
+        recent.push("a.png".into());
+        assert_eq!(
+            recent.paths(),
+            &[
+                PathBuf::from("a.png"),
+                PathBuf::from("c.png"),
+                PathBuf::from("b.png")
+            ]
+        );
+    }
+
+    #[test]
+    fn the_oldest_files_are_dropped() {
+        let mut recent = RecentFiles::default();
+        for i in 0..MAX_RECENT_FILES + 3 {
+            recent.push(format!("{i}.png").into());
+        }
+        assert_eq!(recent.paths().len(), MAX_RECENT_FILES);
+        assert_eq!(
+            recent.paths().first(),
+            Some(&PathBuf::from(format!("{}.png", MAX_RECENT_FILES + 2)))
+        );
+        assert_eq!(recent.paths().last(), Some(&PathBuf::from("3.png")));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("mochi_recent_files_{}.json", std::process::id()));
+        assert_eq!(RecentFiles::load(&path).unwrap(), RecentFiles::default());
+
+        let mut recent = RecentFiles::default();
+        recent.push("first.png".into());
+        recent.push("second.jpg".into());
+        recent.save(&path).unwrap();
+        assert_eq!(RecentFiles::load(&path).unwrap(), recent);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::{anyhow, bail};
+use framework::SrgbColor;
+use image_editor::{ImageEditor, LayerConstructionInfo};
+use rhai::{Array, Dynamic, Engine, Scope};
+
+use crate::tools::EditorContext;
+
+pub const SCRIPT_EXTENSION: &str = "rhai";
+
+// What the script asked to do, in order
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptCommand {
+    NewLayer(String),
+    SelectAll,
+    FillSelection([u8; 3]),
+    Blur(f32),
+    Export(PathBuf),
+}
+
+// The document as seen by the scripts, where it's called "document". The commands are
+// recorded while the script runs and applied to the editor once it's done, so the
+// layer names are the ones the document had before the script, plus the new layers
+#[derive(Clone)]
+pub struct ScriptContext {
+    commands: Rc<RefCell<Vec<ScriptCommand>>>,
+    layer_names: Rc<RefCell<Vec<String>>>,
+}
+
+impl ScriptContext {
+    fn new(image_editor: &ImageEditor) -> Self {
+        let mut layer_names = vec![];
+        image_editor
+            .document()
+            .for_each_layer(|layer, _| layer_names.push(layer.settings().name.clone()));
+        Self {
+            commands: Rc::new(RefCell::new(vec![])),
+            layer_names: Rc::new(RefCell::new(layer_names)),
+        }
+    }
+
+    fn push(&mut self, command: ScriptCommand) {
+        self.commands.borrow_mut().push(command);
+    }
+
+    fn new_layer(&mut self, name: &str) {
+        self.layer_names.borrow_mut().push(name.to_owned());
+        self.push(ScriptCommand::NewLayer(name.to_owned()));
+    }
+
+    fn layer_names(&mut self) -> Array {
+        self.layer_names
+            .borrow()
+            .iter()
+            .map(|name| Dynamic::from(name.clone()))
+            .collect()
+    }
+
+    fn fill_selection(&mut self, r: i64, g: i64, b: i64) {
+        let channel = |c: i64| c.clamp(0, 255) as u8;
+        self.push(ScriptCommand::FillSelection([
+            channel(r),
+            channel(g),
+            channel(b),
+        ]));
+    }
+}
+
+fn script_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .register_type_with_name::<ScriptContext>("Document")
+        .register_fn("new_layer", ScriptContext::new_layer)
+        .register_fn("layer_names", ScriptContext::layer_names)
+        .register_fn("select_all", |context: &mut ScriptContext| {
+            context.push(ScriptCommand::SelectAll)
+        })
+        .register_fn("fill_selection", ScriptContext::fill_selection)
+        .register_fn("blur", |context: &mut ScriptContext, sigma: f64| {
+            context.push(ScriptCommand::Blur(sigma as f32))
+        })
+        .register_fn("export", |context: &mut ScriptContext, path: &str| {
+            context.push(ScriptCommand::Export(PathBuf::from(path)))
+        });
+    engine
+}
+
+// Runs the script on the active document, e.g.
+//     document.new_layer("Red");
+//     document.select_all();
+//     document.fill_selection(255, 0, 0);
+// The changes made by the script can't be undone
+pub fn run_script(source: &str, context: &mut EditorContext) -> anyhow::Result<()> {
+    let script_context = ScriptContext::new(context.image_editor);
+    let mut scope = Scope::new();
+    scope.push("document", script_context.clone());
+    script_engine()
+        .run_with_scope(&mut scope, source)
+        .map_err(|e| anyhow!("The script failed: {e}"))?;
+    let commands = script_context.commands.take();
+    for command in commands {
+        apply_command(command, context)?;
+    }
+    Ok(())
+}
+
+pub fn run_script_file(path: &Path, context: &mut EditorContext) -> anyhow::Result<()> {
+    run_script(&fs::read_to_string(path)?, context)
+}
+
+fn apply_command(command: ScriptCommand, context: &mut EditorContext) -> anyhow::Result<()> {
+    let current_layer = context
+        .image_editor
+        .document()
+        .current_layer_index()
+        .copied();
+    match command {
+        ScriptCommand::NewLayer(name) => {
+            let layer = context.image_editor.add_layer_to_document(
+                // A raster layer
+                LayerConstructionInfo {
+                    name,
+                    ..Default::default()
+                },
+                context.framework,
+            );
+            context.image_editor.select_new_layer(layer);
+        }
+        ScriptCommand::SelectAll => context.image_editor.mutate_document(|doc| doc.select_all()),
+        ScriptCommand::FillSelection([r, g, b]) => {
+            let layer = match current_layer {
+                Some(layer) => layer,
+                None => bail!("There's no layer to fill"),
+            };
+            let color = wgpu::Color::from_srgb_u8([r, g, b, 255]);
+            context.image_editor.mutate_document(|doc| {
+                doc.fill_selection(&layer, color, false, context.renderer, context.framework);
+            });
+        }
+        ScriptCommand::Blur(sigma) => {
+            let layer = match current_layer {
+                Some(layer) => layer,
+                None => bail!("There's no layer to blur"),
+            };
+            let mut result = Ok(());
+            context.image_editor.mutate_document(|doc| {
+                result = doc
+                    .apply_gaussian_blur(&layer, sigma, None, None, context.framework)
+                    .map(|_| ());
+            });
+            result?;
+        }
+        ScriptCommand::Export(path) => {
+            context
+                .image_editor
+                .update_layers(context.renderer, context.framework);
+            context
+                .image_editor
+                .render_document(context.renderer, context.framework);
+            let document = context.image_editor.document();
+            document
+                .final_image_bytes(document.export_alpha_mode(), context.framework)?
+                .save(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use framework::renderer::renderer::Renderer;
+    use image::GenericImageView;
+    use image_editor::{document::AlphaMode, headless_framework, ImageEditor};
+
+    use crate::tools::EditorContext;
+
+    use super::run_script;
+
+    fn layer_count(image_editor: &ImageEditor) -> usize {
+        let mut count = 0;
+        image_editor.document().for_each_layer(|_, _| count += 1);
+        count
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn scripts_create_and_fill_layers() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let mut context = EditorContext {
+            framework: &mut framework,
+            image_editor: &mut image_editor,
+            renderer: &mut renderer,
+        };
+        let layers_before = layer_count(context.image_editor);
+
+        run_script(
+            r#"
+            let before = document.layer_names().len();
+            document.new_layer("Script layer");
+            if document.layer_names().len() != before + 1 {
+                throw "The new layer isn't listed";
+            }
+            document.select_all();
+            document.fill_selection(255, 0, 0);
+            "#,
+            &mut context,
+        )
+        .unwrap();
+
+        assert_eq!(layer_count(context.image_editor), layers_before + 1);
+        assert_eq!(
+            context.image_editor.selected_layer().settings().name,
+            "Script layer"
+        );
+
+        context
+            .image_editor
+            .update_layers(context.renderer, context.framework);
+        context
+            .image_editor
+            .render_document(context.renderer, context.framework);
+        let image = context
+            .image_editor
+            .document()
+            .final_image_bytes(AlphaMode::Straight, context.framework)
+            .unwrap();
+        let (width, height) = image.dimensions();
+        for (x, y) in [(0, 0), (width / 2, height / 2), (width - 1, height - 1)] {
+            assert_eq!(image.get_pixel(x, y).0, [255, 0, 0, 255]);
+        }
+
+        // The errors of the scripts are reported, nothing is changed
+        assert!(run_script("document.new_layer(", &mut context).is_err());
+        assert!(run_script("document.no_such_command();", &mut context).is_err());
+        assert_eq!(layer_count(context.image_editor), layers_before + 1);
+    }
+}
@@ -5,34 +5,93 @@ use std::{
 };
 
 use crate::tools::{EditorContext, PointerEvent, Tool};
-use crate::{image_editor_app_loop::UndoStack, stamping_engine::Stamp};
-use application::InputState;
-use cgmath::point2;
+use crate::{stamping_engine::Stamp, undo_tree::UndoTree};
+use application::{InputState, PressureCalibration};
+use cgmath::Point2;
 use framework::{
-    renderer::renderer::Renderer, Framework, RgbaTexture2D, Texture, TextureConfiguration,
-    TextureUsage,
+    renderer::renderer::Renderer, ColorSpace, Framework, RgbaTexture2D, Texture,
+    TextureConfiguration, TextureUsage,
 };
+use image_editor::ImageEditor;
+use strum::IntoEnumIterator;
 use winit::event::MouseButton;
 
 #[derive(Clone, Copy, Hash, Eq, PartialEq)]
 pub struct ToolId(usize);
 
+// The ToolIds depend on the order the tools are added in, the kinds are stable
+// and can be used e.g. by the shortcuts and the command palette
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, strum_macros::EnumIter)]
+pub enum ToolKind {
+    Brush,
+    Eraser,
+    Hand,
+    ColorPicker,
+    Move,
+    DebugSelectRegion,
+    RectSelection,
+    Lasso,
+    PolygonSelection,
+    Measure,
+}
+
+impl ToolKind {
+    // The action selecting the tool
+    pub fn action(&self) -> &'static str {
+        match self {
+            ToolKind::Brush => "pick_brush",
+            ToolKind::Eraser => "pick_eraser",
+            ToolKind::Hand => "pick_hand",
+            ToolKind::ColorPicker => "pick_color_picker",
+            ToolKind::Move => "pick_move",
+            ToolKind::DebugSelectRegion => "pick_debug_select_region",
+            ToolKind::RectSelection => "pick_rect_selection",
+            ToolKind::Lasso => "pick_lasso",
+            ToolKind::PolygonSelection => "pick_polygon_selection",
+            ToolKind::Measure => "pick_measure",
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            ToolKind::Brush => "Brush tool",
+            ToolKind::Eraser => "Eraser tool",
+            ToolKind::Hand => "Hand tool",
+            ToolKind::ColorPicker => "Color picker",
+            ToolKind::Move => "Move tool",
+            ToolKind::DebugSelectRegion => "Debug select region tool",
+            ToolKind::RectSelection => "Rectangle selection tool",
+            ToolKind::Lasso => "Lasso tool",
+            ToolKind::PolygonSelection => "Polygon selection tool",
+            ToolKind::Measure => "Measure tool",
+        }
+    }
+
+    pub fn from_action(action: &str) -> Option<Self> {
+        Self::iter().find(|kind| kind.action() == action)
+    }
+}
+
 pub struct Toolbox {
     tools: HashMap<ToolId, Rc<RefCell<dyn Tool>>>,
+    kinds: HashMap<ToolKind, ToolId>,
     primary_tool_id: ToolId,
     primary_tool: Rc<RefCell<dyn Tool>>,
     blocked: bool,
+    pub pressure_calibration: PressureCalibration,
 }
 
 impl Toolbox {
-    pub fn new(primary_tool: Rc<RefCell<dyn Tool>>) -> (Self, ToolId) {
+    pub fn new(kind: ToolKind, primary_tool: Rc<RefCell<dyn Tool>>) -> (Self, ToolId) {
         let mut new_toolbox = Self {
             tools: HashMap::new(),
+            kinds: HashMap::new(),
             primary_tool: primary_tool.clone(),
             blocked: false,
+            pressure_calibration: PressureCalibration::default(),
             primary_tool_id: ToolId(0),
         };
-        let primary_id = new_toolbox.add_tool(primary_tool);
+        let primary_id = new_toolbox.add_tool(kind, primary_tool);
         new_toolbox.primary_tool_id = primary_id.clone();
         (new_toolbox, primary_id)
     }
@@ -47,18 +106,43 @@ impl Toolbox {
                 label: Some("Test bitmap"),
                 usage: TextureUsage::WRITE_ONLY,
                 mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
         );
-        Stamp::new(brush_bitmap)
+        Stamp::new("Default", brush_bitmap)
     }
 
-    pub fn add_tool(&mut self, new_tool: Rc<RefCell<dyn Tool>>) -> ToolId {
+    // Adding another tool of the same kind replaces the one selected by the kind
+    pub fn add_tool(&mut self, kind: ToolKind, new_tool: Rc<RefCell<dyn Tool>>) -> ToolId {
         let id = self.tools.len();
         let id = ToolId(id);
         self.tools.insert(id, new_tool);
+        self.kinds.insert(kind, id);
         id
     }
 
+    pub fn tool_id(&self, kind: ToolKind) -> Option<ToolId> {
+        self.kinds.get(&kind).copied()
+    }
+
+    pub fn active_kind(&self) -> Option<ToolKind> {
+        self.kinds
+            .iter()
+            .find(|(_, id)| **id == self.primary_tool_id)
+            .map(|(kind, _)| *kind)
+    }
+
+    // False when no tool of the kind was added
+    pub fn select_by_kind(&mut self, kind: ToolKind, context: EditorContext) -> bool {
+        match self.tool_id(kind) {
+            Some(id) => {
+                self.set_primary_tool(&id, context);
+                true
+            }
+            None => false,
+        }
+    }
+
     // Panics if id is not a valid index
     #[allow(dead_code)]
     pub fn get_tool(&self, id: &ToolId) -> RefMut<dyn Tool> {
@@ -85,17 +169,19 @@ impl Toolbox {
     pub fn update(
         &mut self,
         input_state: &InputState,
-        undo_stack: &mut UndoStack,
+        undo_tree: &mut UndoTree,
         mut context: EditorContext,
     ) {
+        self.primary_tool().update(&mut context);
         if self.blocked {
             return;
         }
         let event = PointerEvent {
             new_pointer_location_normalized: input_state.normalized_mouse_position(),
             new_pointer_location: input_state.mouse_position(),
-            pressure: input_state.current_pointer_pressure(),
+            pressure: input_state.calibrated_pointer_pressure(&self.pressure_calibration),
             window_width: input_state.window_size(),
+            modifiers: *input_state.current_modifiers(),
         };
         let cmd = if input_state.is_mouse_button_just_pressed(MouseButton::Left) {
             self.primary_tool().on_pointer_click(event, &mut context)
@@ -105,18 +191,17 @@ impl Toolbox {
             self.primary_tool().on_pointer_move(event, &mut context)
         };
         if let Some(cmd) = cmd {
-            undo_stack.push(cmd);
+            undo_tree.push(cmd);
         }
         if input_state.is_mouse_button_just_pressed(MouseButton::Middle) {
-            context
-                .image_editor
-                .camera_mut()
-                .set_position(point2(0.0, 0.0));
+            context.image_editor.reset_view(input_state.window_size());
         }
         if input_state.mouse_wheel_delta().abs() > 0.0 {
-            context
-                .image_editor
-                .scale_view(input_state.mouse_wheel_delta());
+            context.image_editor.zoom_at(
+                input_state.mouse_position(),
+                input_state.mouse_wheel_delta(),
+                input_state.window_size(),
+            );
         }
     }
 
@@ -124,6 +209,19 @@ impl Toolbox {
         self.primary_tool().draw(renderer);
     }
 
+    // The cursor is hidden while the pointer is over the ui
+    pub fn draw_cursor(
+        &self,
+        renderer: &mut Renderer,
+        cursor_position: Point2<f32>,
+        image_editor: &ImageEditor,
+    ) {
+        if !self.blocked {
+            self.primary_tool()
+                .draw_cursor(renderer, cursor_position, image_editor);
+        }
+    }
+
     pub(crate) fn set_primary_tool(&mut self, new_tool_id: &ToolId, mut context: EditorContext) {
         self.primary_tool.borrow_mut().on_deselected(&mut context);
         self.primary_tool_id = new_tool_id.clone();
@@ -135,3 +233,86 @@ impl Toolbox {
         self.primary_tool.borrow_mut().on_selected(&mut context);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use framework::renderer::renderer::Renderer;
+    use image_editor::{headless_framework, ImageEditor};
+    use strum::IntoEnumIterator;
+
+    use crate::tools::{
+        brush_engine::stamping_engine::StrokingEngine, BrushTool, ColorPicker,
+        DebugSelectRegionTool, EditorContext, EraserTool, HandTool, LassoTool, MeasureTool,
+        PolygonSelectTool, RectSelectionTool, TransformLayerTool,
+    };
+
+    use super::{ToolKind, Toolbox};
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn tools_are_selected_by_kind() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[64.0, 64.0]);
+
+        let test_stamp = Toolbox::create_test_stamp(&mut framework);
+        let stamping_engine = Rc::new(RefCell::new(StrokingEngine::new(
+            test_stamp,
+            &mut framework,
+        )));
+        let color_picker = Rc::new(RefCell::new(ColorPicker::new(stamping_engine.clone())));
+        let brush_tool = Rc::new(RefCell::new(BrushTool::new(
+            stamping_engine.clone(),
+            color_picker.clone(),
+            1.0,
+        )));
+        let eraser_tool = Rc::new(RefCell::new(EraserTool::new(
+            stamping_engine,
+            color_picker.clone(),
+            1.0,
+        )));
+        let (mut toolbox, brush_id) = Toolbox::new(ToolKind::Brush, brush_tool);
+        toolbox.add_tool(ToolKind::Eraser, eraser_tool);
+        toolbox.add_tool(ToolKind::Hand, Rc::new(RefCell::new(HandTool::new())));
+        toolbox.add_tool(ToolKind::ColorPicker, color_picker);
+        toolbox.add_tool(
+            ToolKind::Move,
+            Rc::new(RefCell::new(TransformLayerTool::new())),
+        );
+        toolbox.add_tool(
+            ToolKind::DebugSelectRegion,
+            Rc::new(RefCell::new(DebugSelectRegionTool::new())),
+        );
+        toolbox.add_tool(
+            ToolKind::RectSelection,
+            Rc::new(RefCell::new(RectSelectionTool::new())),
+        );
+        toolbox.add_tool(ToolKind::Lasso, Rc::new(RefCell::new(LassoTool::new())));
+        toolbox.add_tool(
+            ToolKind::PolygonSelection,
+            Rc::new(RefCell::new(PolygonSelectTool::new())),
+        );
+        toolbox.add_tool(ToolKind::Measure, Rc::new(RefCell::new(MeasureTool::new())));
+
+        assert!(toolbox.tool_id(ToolKind::Brush) == Some(brush_id));
+        assert_eq!(toolbox.active_kind(), Some(ToolKind::Brush));
+        for kind in ToolKind::iter() {
+            let selected = toolbox.select_by_kind(
+                kind,
+                EditorContext {
+                    framework: &mut framework,
+                    image_editor: &mut image_editor,
+                    renderer: &mut renderer,
+                },
+            );
+            assert!(selected, "{kind:?} was not registered");
+            assert_eq!(toolbox.active_kind(), Some(kind));
+            assert!(toolbox.tool_id(kind).as_ref() == Some(toolbox.primary_tool_id()));
+            assert_eq!(ToolKind::from_action(kind.action()), Some(kind));
+        }
+        assert_eq!(toolbox.primary_tool().name(), "Measure tool");
+        assert_eq!(ToolKind::from_action("toggle_eraser"), None);
+    }
+}
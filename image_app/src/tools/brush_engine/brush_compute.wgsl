@@ -0,0 +1,117 @@
+// Computes all the stamps of a stroke on a chunk in a single dispatch, mirroring brush_fragment.wgsl:
+// the result is written as sRGB bytes, and then copied on the chunk texture
+struct ComputeBrushSettings {
+    color: vec4<f32>,
+    smoothness: f32,
+    samples_per_axis: u32,
+    stamp_count: u32,
+    is_eraser: u32,
+    is_alpha_locked: u32,
+    hardness: f32,
+    falloff: u32,
+}
+
+struct StampInstance {
+    position: vec2<f32>,
+    scale: vec2<f32>,
+    rotation: f32,
+    padding_0: f32,
+    padding_1: f32,
+    padding_2: f32,
+}
+
+@group(0) @binding(0) var<uniform> settings: ComputeBrushSettings;
+@group(1) @binding(0) var<storage, read> stamps: array<StampInstance>;
+@group(2) @binding(0) var diffuse: texture_2d<f32>;
+@group(2) @binding(1) var s_diffuse: sampler;
+@group(3) @binding(0) var chunk: texture_2d<f32>;
+@group(4) @binding(0) var<storage, read_write> result: array<u32>;
+
+fn smoothness(uv: vec2<f32>, theta: f32) -> f32 {
+    let x = distance(vec2<f32>(0.5), uv);
+
+    let r = 0.5 - 0.5 * x;
+    let b = 1.0 - ((x * (2.0 - 2.0 * r) + x * x * (2.0 * r - 1.0)));
+    return pow(clamp(b, 0.0, 1.0), theta);
+}
+
+// Scales the stamp from the edge of the flat area, where the distance from the center is hardness,
+// down to 0 on the edge of the stamp: 0 is linear, 1 gaussian and 2 smoothstep
+fn falloff(uv: vec2<f32>, hardness: f32, curve: u32) -> f32 {
+    let d = distance(vec2<f32>(0.5), uv) * 2.0;
+    let t = clamp((d - hardness) / max(1.0 - hardness, 0.0001), 0.0, 1.0);
+    let linear_falloff = 1.0 - t;
+    let gaussian_falloff = (exp(-4.0 * t * t) - exp(-4.0)) / (1.0 - exp(-4.0));
+    let smooth_falloff = 1.0 - smoothstep(0.0, 1.0, t);
+    return select(
+        select(linear_falloff, gaussian_falloff, curve == 1u),
+        smooth_falloff,
+        curve == 2u
+    );
+}
+
+fn stamp_alpha(uv: vec2<f32>) -> f32 {
+    let inside = all(uv >= vec2<f32>(0.0)) && all(uv <= vec2<f32>(1.0));
+    let alpha = textureSampleLevel(diffuse, s_diffuse, uv, 0.0).a * smoothness(uv, settings.smoothness)
+        * falloff(uv, settings.hardness, settings.falloff);
+    return select(0.0, alpha, inside);
+}
+
+// The inverse of the brush vertex shader transform, with the uvs flipped on the y axis
+fn stamp_uv(stamp: StampInstance, world: vec2<f32>) -> vec2<f32> {
+    let offset = world - stamp.position;
+    let ca = cos(stamp.rotation);
+    let sa = sin(stamp.rotation);
+    let rotated = vec2<f32>(ca * offset.x + sa * offset.y, -sa * offset.x + ca * offset.y);
+    let local = rotated / stamp.scale;
+    return vec2<f32>(local.x + 1.0, 1.0 - local.y) * 0.5;
+}
+
+fn linear_to_srgb(value: vec3<f32>) -> vec3<f32> {
+    let clamped = clamp(value, vec3<f32>(0.0), vec3<f32>(1.0));
+    let low = clamped * 12.92;
+    let high = 1.055 * pow(clamped, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, clamped <= vec3<f32>(0.0031308));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<u32>(textureDimensions(chunk));
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+    var color = textureLoad(chunk, vec2<i32>(id.xy), 0);
+    // The chunk camera has the origin in the center of the chunk, with the y axis going up
+    let world = vec2<f32>(
+        f32(id.x) + 0.5 - f32(size.x) * 0.5,
+        f32(size.y) * 0.5 - f32(id.y) - 0.5
+    );
+    let samples = max(settings.samples_per_axis, 1u);
+    for (var s = 0u; s < settings.stamp_count; s = s + 1u) {
+        let stamp = stamps[s];
+        let uv = stamp_uv(stamp, world);
+        // The uvs are affine, so the derivatives are the same on the whole stamp
+        let dx = stamp_uv(stamp, world + vec2<f32>(1.0, 0.0)) - uv;
+        let dy = stamp_uv(stamp, world - vec2<f32>(0.0, 1.0)) - uv;
+        if (any(abs(uv - 0.5) > vec2<f32>(0.5) + abs(dx) + abs(dy))) {
+            continue;
+        }
+        var alpha = 0.0;
+        for (var i = 0u; i < samples; i = i + 1u) {
+            for (var j = 0u; j < samples; j = j + 1u) {
+                let offset = (vec2<f32>(f32(i), f32(j)) + 0.5) / f32(samples) - 0.5;
+                alpha = alpha + stamp_alpha(uv + offset.x * dx + offset.y * dy);
+            }
+        }
+        let source = alpha / f32(samples * samples) * settings.color;
+        // Same as the brush, eraser and alpha locked blend states
+        if (settings.is_eraser == 1u) {
+            color = color * (1.0 - source.a);
+        } else if (settings.is_alpha_locked == 1u) {
+            color = vec4<f32>(source.rgb * color.a + color.rgb * (1.0 - source.a), color.a);
+        } else {
+            color = source + color * (1.0 - source.a);
+        }
+    }
+    result[id.y * size.x + id.x] = pack4x8unorm(vec4<f32>(linear_to_srgb(color.rgb), color.a));
+}
@@ -1,12 +1,15 @@
 //@include :common_definitions
 //@include :2d_definitions
-//@include :blend_modes
+#import blend_modes
 
 @group(2) @binding(0) var diffuse: texture_2d<f32>;
 @group(2) @binding(1) var s_diffuse: sampler;
 
 struct BrushSettings {
     smoothness: f32,
+    samples_per_axis: u32,
+    hardness: f32,
+    falloff: u32,
 }
 
 @group(3) @binding(0) var<uniform> brush_settings: BrushSettings;
@@ -16,11 +19,44 @@ fn smoothness(uv: vec2<f32>, theta: f32) -> f32 {
 
     let r = 0.5 - 0.5 * x;
     let b = 1.0 - ((x * (2.0 - 2.0 * r) + x * x * (2.0 * r - 1.0)));
-    return pow(b, theta);
+    return pow(clamp(b, 0.0, 1.0), theta);
+}
+
+// Scales the stamp from the edge of the flat area, where the distance from the center is hardness,
+// down to 0 on the edge of the stamp: 0 is linear, 1 gaussian and 2 smoothstep
+fn falloff(uv: vec2<f32>, hardness: f32, curve: u32) -> f32 {
+    let d = distance(vec2<f32>(0.5), uv) * 2.0;
+    let t = clamp((d - hardness) / max(1.0 - hardness, 0.0001), 0.0, 1.0);
+    let linear_falloff = 1.0 - t;
+    let gaussian_falloff = (exp(-4.0 * t * t) - exp(-4.0)) / (1.0 - exp(-4.0));
+    let smooth_falloff = 1.0 - smoothstep(0.0, 1.0, t);
+    return select(
+        select(linear_falloff, gaussian_falloff, curve == 1u),
+        smooth_falloff,
+        curve == 2u
+    );
+}
+
+fn stamp_alpha(uv: vec2<f32>) -> f32 {
+    // The samples that fall outside the stamp don't cover it
+    let inside = all(uv >= vec2<f32>(0.0)) && all(uv <= vec2<f32>(1.0));
+    let alpha = textureSampleLevel(diffuse, s_diffuse, uv, 0.0).a * smoothness(uv, brush_settings.smoothness)
+        * falloff(uv, brush_settings.hardness, brush_settings.falloff);
+    return select(0.0, alpha, inside);
 }
 
 @fragment
 fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
-    let s = smoothness(in.tex_uv, brush_settings.smoothness);
-    return textureSample(diffuse, s_diffuse, in.tex_uv).a * in.multiply_color * s;
-}
\ No newline at end of file
+    // Average a grid of samples spread over the pixel footprint
+    let dx = dpdx(in.tex_uv);
+    let dy = dpdy(in.tex_uv);
+    let samples = max(brush_settings.samples_per_axis, 1u);
+    var alpha = 0.0;
+    for (var i = 0u; i < samples; i = i + 1u) {
+        for (var j = 0u; j < samples; j = j + 1u) {
+            let offset = (vec2<f32>(f32(i), f32(j)) + 0.5) / f32(samples) - 0.5;
+            alpha = alpha + stamp_alpha(in.tex_uv + offset.x * dx + offset.y * dy);
+        }
+    }
+    return alpha / f32(samples * samples) * in.multiply_color;
+}
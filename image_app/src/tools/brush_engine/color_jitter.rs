@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+
+// How far the color of each stamp can move away from the brush color, as fractions of the hue
+// circle and of the saturation and value ranges. The offsets only depend on the seed and on
+// the index of the stamp in the stroke, so that the same stroke always gets the same colors
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColorJitter {
+    pub hue: f32,
+    pub saturation: f32,
+    pub value: f32,
+    pub seed: u32,
+}
+
+impl ColorJitter {
+    pub fn is_enabled(&self) -> bool {
+        self.hue > 0.0 || self.saturation > 0.0 || self.value > 0.0
+    }
+
+    pub fn jittered_color(&self, color_srgb: [u8; 3], stamp_index: u32) -> [u8; 3] {
+        if !self.is_enabled() {
+            return color_srgb;
+        }
+        let offset = |channel: u32, amount: f32| {
+            (random_unit(self.seed, stamp_index, channel) * 2.0 - 1.0) * amount
+        };
+        let [h, s, v] = rgb_to_hsv(color_srgb.map(|c| c as f32 / 255.0));
+        let h = (h + offset(0, self.hue)).rem_euclid(1.0);
+        let s = (s + offset(1, self.saturation)).clamp(0.0, 1.0);
+        let v = (v + offset(2, self.value)).clamp(0.0, 1.0);
+        hsv_to_rgb([h, s, v]).map(|c| (c * 255.0).round() as u8)
+    }
+}
+
+// A value in [0, 1), hashed from its inputs
+pub(super) fn random_unit(seed: u32, index: u32, channel: u32) -> f32 {
+    let mut x = seed
+        .wrapping_mul(0x9E37_79B9)
+        .wrapping_add(index.wrapping_mul(0x85EB_CA6B))
+        .wrapping_add(channel.wrapping_mul(0xC2B2_AE35));
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7FEB_352D);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846C_A68B);
+    x ^= x >> 16;
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
+
+// All the components go from 0 to 1, the hue included
+fn rgb_to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0) / 6.0
+    } else if max == g {
+        ((b - r) / delta + 2.0) / 6.0
+    } else {
+        ((r - g) / delta + 4.0) / 6.0
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    [hue, saturation, max]
+}
+
+fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let sector = h * 6.0;
+    let chroma = v * s;
+    let x = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+    let m = v - chroma;
+    let [r, g, b] = match sector as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    [r + m, g + m, b + m]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hsv_to_rgb, rgb_to_hsv, ColorJitter};
+
+    const BASE_COLOR: [u8; 3] = [200, 90, 40];
+
+    #[test]
+    fn without_jitter_all_stamps_have_the_brush_color() {
+        let jitter = ColorJitter {
+            seed: 42,
+            ..Default::default()
+        };
+        assert!((0..100).all(|i| jitter.jittered_color(BASE_COLOR, i) == BASE_COLOR));
+    }
+
+    #[test]
+    fn the_jitter_is_deterministic() {
+        let jitter = ColorJitter {
+            hue: 0.1,
+            saturation: 0.2,
+            value: 0.2,
+            seed: 7,
+        };
+        let colors = |jitter: ColorJitter| -> Vec<[u8; 3]> {
+            (0..32)
+                .map(|i| jitter.jittered_color(BASE_COLOR, i))
+                .collect()
+        };
+        let first = colors(jitter);
+        assert_eq!(first, colors(jitter));
+        assert!(first.iter().any(|color| *color != BASE_COLOR));
+        assert!(first.windows(2).any(|pair| pair[0] != pair[1]));
+
+        let other_seed = colors(ColorJitter { seed: 8, ..jitter });
+        assert_ne!(first, other_seed);
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        for color in [[1.0, 0.0, 0.0], [0.2, 0.8, 0.4], [0.3, 0.3, 0.9], [0.5; 3]] {
+            let back = hsv_to_rgb(rgb_to_hsv(color));
+            for c in 0..3 {
+                assert!((back[c] - color[c]).abs() < 1e-5, "{color:?} -> {back:?}");
+            }
+        }
+    }
+}
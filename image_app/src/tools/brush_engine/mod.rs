@@ -1,13 +1,17 @@
+pub mod color_jitter;
+mod pixel_operation;
 mod stamp_operation;
 pub mod stamping_engine;
 
-use cgmath::{vec2, InnerSpace, Point2};
+use cgmath::{point2, vec2, InnerSpace, Point2};
 
 use framework::{renderer::renderer::Renderer, Box2d, Framework};
 use image_editor::ImageEditor;
 
 use super::{EditorCommand, EditorContext};
 
+use color_jitter::random_unit;
+
 #[derive(Debug)]
 pub struct StrokePoint {
     pub position: Point2<f32>,
@@ -86,4 +90,133 @@ impl StrokePath {
     fn bounds(&self) -> Box2d {
         self.bounds
     }
+
+    // Moves each point in a random direction, by up to scatter times its size. Like the color
+    // jitter, the offsets only depend on the seed and on the index of the stamp in the stroke
+    pub(crate) fn scattered(mut self, scatter: f32, seed: u32, first_stamp: u32) -> Self {
+        let mut max_offset = 0.0f32;
+        for (i, point) in self.points.iter_mut().enumerate() {
+            let index = first_stamp + i as u32;
+            let angle = random_unit(seed, index, 3) * std::f32::consts::TAU;
+            let distance = random_unit(seed, index, 4).sqrt() * scatter * point.size;
+            point.position += vec2(angle.cos(), angle.sin()) * distance;
+            max_offset = max_offset.max(scatter * point.size);
+        }
+        self.bounds.extents += vec2(max_offset, max_offset);
+        self
+    }
+
+    // The smallest box containing all the stamps, which are squares as big as twice
+    // their size, rotated by rotation_radians around their position
+    pub(crate) fn stamp_bounds(&self, rotation_radians: f32) -> Option<Box2d> {
+        let rotated_extent = rotation_radians.cos().abs() + rotation_radians.sin().abs();
+        let mut points = self.points.iter().map(|pt| {
+            let extent = pt.size * rotated_extent;
+            (
+                pt.position - vec2(extent, extent),
+                pt.position + vec2(extent, extent),
+            )
+        });
+        let first = points.next()?;
+        let (min, max) = points.fold(first, |(min, max), (pt_min, pt_max)| {
+            (
+                point2(min.x.min(pt_min.x), min.y.min(pt_min.y)),
+                point2(max.x.max(pt_max.x), max.y.max(pt_max.y)),
+            )
+        });
+        Some(Box2d::from_points(min, max))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::{point2, vec2, InnerSpace};
+    use framework::Box2d;
+
+    use super::{StrokePath, StrokePoint};
+
+    fn path() -> StrokePath {
+        StrokePath::linear_start_to_end(
+            StrokePoint {
+                position: point2(0.0, 0.0),
+                size: 10.0,
+            },
+            StrokePoint {
+                position: point2(100.0, 0.0),
+                size: 10.0,
+            },
+            5.0,
+        )
+    }
+
+    #[test]
+    fn scattered_points_stay_within_the_scatter_and_the_bounds() {
+        let original = path();
+        let scattered = path().scattered(0.5, 7, 0);
+        let bounds = scattered.bounds();
+        let mut moved = 0;
+        for (a, b) in original.points.iter().zip(scattered.points.iter()) {
+            let offset = (b.position - a.position).magnitude();
+            assert!(offset <= 5.0 + 1e-4, "{offset}");
+            assert!(bounds.contains_point(b.position), "{:?}", b.position);
+            if offset > 0.0 {
+                moved += 1;
+            }
+        }
+        assert!(moved > original.points.len() / 2);
+
+        // The same stamps of the same stroke always land in the same place
+        let again = path().scattered(0.5, 7, 0);
+        assert_eq!(
+            scattered
+                .points
+                .iter()
+                .map(|p| p.position)
+                .collect::<Vec<_>>(),
+            again.points.iter().map(|p| p.position).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn stamp_bounds_contain_the_rotated_stamps() {
+        let path = StrokePath {
+            points: vec![
+                StrokePoint {
+                    position: point2(-20.0, 10.0),
+                    size: 3.0,
+                },
+                StrokePoint {
+                    position: point2(-10.0, 12.0),
+                    size: 5.0,
+                },
+            ],
+            bounds: Box2d {
+                center: point2(-15.0, 11.0),
+                extents: vec2(10.0, 6.0),
+            },
+        };
+        assert_eq!(
+            path.stamp_bounds(0.0),
+            Some(Box2d::from_points(point2(-23.0, 7.0), point2(-5.0, 17.0)))
+        );
+
+        // Rotated by 45 degrees, the corners of a stamp stick out by sqrt(2) times its size
+        let single = StrokePath {
+            points: vec![StrokePoint {
+                position: point2(4.0, -2.0),
+                size: 2.0,
+            }],
+            ..path
+        };
+        let rotated = single.stamp_bounds(std::f32::consts::FRAC_PI_4).unwrap();
+        assert!((rotated.center - point2(4.0, -2.0)).magnitude() < 1e-4);
+        let extent = 2.0 * std::f32::consts::SQRT_2;
+        assert!((rotated.extents - vec2(extent, extent)).magnitude() < 1e-4);
+
+        let empty = StrokePath {
+            points: vec![],
+            ..single
+        };
+        assert_eq!(empty.stamp_bounds(0.0), None);
+    }
 }
@@ -0,0 +1,243 @@
+use cgmath::{point2, point3, vec2, EuclideanSpace, Point2, SquareMatrix, Transform};
+use framework::{
+    framework::ShaderId,
+    renderer::{
+        draw_command::{DrawCommand, DrawMode, OptionalDrawData, PrimitiveType},
+        renderer::Renderer,
+    },
+    Box2d, Framework,
+};
+use image_editor::layers::{ChunkDiff, LayerOperation, OperationResult};
+use image_editor::PaintTarget;
+
+// The pixels crossed by the line going from start to end, both included
+pub(crate) fn bresenham_line(start: Point2<i32>, end: Point2<i32>) -> Vec<Point2<i32>> {
+    let (dx, dy) = ((end.x - start.x).abs(), -(end.y - start.y).abs());
+    let (step_x, step_y) = ((end.x - start.x).signum(), (end.y - start.y).signum());
+    let mut error = dx + dy;
+    let mut current = start;
+    let mut pixels = vec![start];
+    while current != end {
+        let doubled_error = 2 * error;
+        if doubled_error >= dy {
+            error += dy;
+            current.x += step_x;
+        }
+        if doubled_error <= dx {
+            error += dx;
+            current.y += step_y;
+        }
+        pixels.push(current);
+    }
+    pixels
+}
+
+// Where a stroke turns by going one pixel horizontally and one vertically, the pixel in the
+// corner is removed, so that the diagonal steps are only one pixel thick
+pub(crate) fn remove_double_corners(pixels: &[Point2<i32>]) -> Vec<Point2<i32>> {
+    let touches_side = |a: Point2<i32>, b: Point2<i32>| (a.x - b.x).abs() + (a.y - b.y).abs() == 1;
+    let mut thinned: Vec<Point2<i32>> = Vec::with_capacity(pixels.len());
+    for &pixel in pixels {
+        if thinned.last() == Some(&pixel) {
+            continue;
+        }
+        if let [.., before, corner] = thinned[..] {
+            let is_diagonal = (before.x - pixel.x).abs() == 1 && (before.y - pixel.y).abs() == 1;
+            if is_diagonal && touches_side(before, corner) && touches_side(corner, pixel) {
+                thinned.pop();
+            }
+        }
+        thinned.push(pixel);
+    }
+    thinned
+}
+
+// Paints the pixels crossed by the stroke with the brush color, without using the stamps
+pub(crate) struct PixelOperation {
+    // In world space
+    pub points: Vec<Point2<f32>>,
+    pub color: wgpu::Color,
+    pub is_eraser: bool,
+    // The pixels are only painted where the layer is already opaque
+    pub is_alpha_locked: bool,
+    // Layers without a mask are always painted on their pixels
+    pub target: PaintTarget,
+    pub eraser_shader_id: ShaderId,
+    pub alpha_locked_shader_id: ShaderId,
+    // The last pixels of the stroke in layer space: the last one is only painted once the
+    // next one is known, since it could turn out to be a corner
+    pub trail: Vec<Point2<i32>>,
+    pub ends_stroke: bool,
+
+    pub diff: ChunkDiff,
+    // The world space region covered by the pixels, set once the operation is executed
+    pub dirty_region: Option<Box2d>,
+}
+
+impl PixelOperation {
+    // Joins the new points to the trail, returning the pixels that can be painted
+    fn next_pixels(&mut self, layer_points: impl Iterator<Item = Point2<i32>>) -> Vec<Point2<i32>> {
+        let mut line = self.trail.clone();
+        for pixel in layer_points {
+            match line.last() {
+                Some(&last) => line.extend(bresenham_line(last, pixel).into_iter().skip(1)),
+                None => line.push(pixel),
+            }
+        }
+        let thinned = remove_double_corners(&line);
+        // Only the first trail pixel can't be removed, the others are yet to be painted
+        let painted = self.trail.len().saturating_sub(1).min(thinned.len());
+        let end = if self.ends_stroke {
+            thinned.len()
+        } else {
+            thinned.len().saturating_sub(1).max(painted)
+        };
+        self.trail = if self.ends_stroke {
+            vec![]
+        } else {
+            thinned[thinned.len().saturating_sub(2)..].to_vec()
+        };
+        thinned[painted..end].to_vec()
+    }
+}
+
+impl LayerOperation for PixelOperation {
+    fn execute(
+        &mut self,
+        layer: &mut image_editor::layers::Layer,
+        _bounds: Box2d,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> OperationResult {
+        let layer_transform = layer.transform();
+        let inv_layer_matrix = layer_transform.matrix().invert();
+        let layer_rendering_camera = layer.rendering_camera();
+        self.dirty_region = None;
+
+        if let (Some(inv_layer_matrix), Some(rendering_camera)) =
+            (inv_layer_matrix, layer_rendering_camera)
+        {
+            let layer_points: Vec<Point2<i32>> = self
+                .points
+                .iter()
+                .map(|pt| {
+                    let local = inv_layer_matrix.transform_point(point3(pt.x, pt.y, 0.0));
+                    point2(local.x.floor() as i32, local.y.floor() as i32)
+                })
+                .collect();
+            let pixels = self.next_pixels(layer_points.into_iter());
+            if pixels.is_empty() {
+                return OperationResult::None;
+            }
+            let rects: Vec<Box2d> = pixels
+                .iter()
+                .map(|pixel| Box2d {
+                    center: point2(pixel.x as f32 + 0.5, pixel.y as f32 + 0.5),
+                    extents: vec2(0.5, 0.5),
+                })
+                .collect();
+            let bounds = Box2d::many_union(rects.iter().copied());
+
+            let map = match (self.target, layer.mask().is_some()) {
+                (PaintTarget::LayerMask, true) => layer.mask_mut(),
+                _ => match &mut layer.layer_type {
+                    image_editor::layers::LayerType::Chonky(map) => Some(map),
+                    _ => None,
+                },
+            };
+            let map = match map {
+                Some(map) => map,
+                None => unreachable!(),
+            };
+            let chunk_size = map.chunk_size();
+            self.diff = map.edit(
+                bounds,
+                |chunk, _, chunk_world_position, framework| {
+                    let offset = chunk_world_position.to_vec();
+                    let chunk_rects = rects
+                        .iter()
+                        .map(|rect| Box2d {
+                            center: rect.center - offset,
+                            extents: rect.extents,
+                        })
+                        .collect();
+                    renderer.begin(&rendering_camera, None, framework);
+                    renderer.set_viewport(Some((0.0, 0.0, chunk_size as f32, chunk_size as f32)));
+                    renderer.draw(DrawCommand {
+                        primitives: PrimitiveType::Rect {
+                            rects: chunk_rects,
+                            multiply_color: self.color,
+                        },
+                        draw_mode: DrawMode::Instanced,
+                        additional_data: OptionalDrawData::just_shader(if self.is_eraser {
+                            Some(self.eraser_shader_id.clone())
+                        } else if self.is_alpha_locked {
+                            Some(self.alpha_locked_shader_id.clone())
+                        } else {
+                            None
+                        }),
+                    });
+                    renderer.end(chunk, None, framework);
+                },
+                framework,
+            );
+            self.dirty_region = Some(bounds.transformed(layer_transform.matrix()));
+        }
+        match self.dirty_region {
+            Some(region) => OperationResult::RerenderRegion(region),
+            None => OperationResult::None,
+        }
+    }
+
+    fn accept(&self, layer: &image_editor::layers::Layer) -> bool {
+        match &layer.layer_type {
+            image_editor::layers::LayerType::Chonky(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::point2;
+
+    use super::{bresenham_line, remove_double_corners};
+
+    #[test]
+    fn l_shaped_strokes_lose_the_corner_pixel() {
+        let mut stroke = bresenham_line(point2(0, 0), point2(0, 3));
+        stroke.extend(
+            bresenham_line(point2(0, 3), point2(3, 3))
+                .into_iter()
+                .skip(1),
+        );
+        assert_eq!(
+            stroke,
+            vec![
+                point2(0, 0),
+                point2(0, 1),
+                point2(0, 2),
+                point2(0, 3),
+                point2(1, 3),
+                point2(2, 3),
+                point2(3, 3)
+            ]
+        );
+
+        assert_eq!(
+            remove_double_corners(&stroke),
+            vec![
+                point2(0, 0),
+                point2(0, 1),
+                point2(0, 2),
+                point2(1, 3),
+                point2(2, 3),
+                point2(3, 3)
+            ]
+        );
+
+        // Straight lines and single diagonal steps have no corners to remove
+        let diagonal = bresenham_line(point2(0, 0), point2(3, -3));
+        assert_eq!(remove_double_corners(&diagonal), diagonal);
+    }
+}
@@ -1,34 +1,84 @@
-use cgmath::{point3, vec2, EuclideanSpace, Point2, SquareMatrix, Transform};
+use std::ops::Range;
+
+use cgmath::{
+    point2, point3, vec2, EuclideanSpace, Matrix4, Point2, Point3, Rad, SquareMatrix, Transform,
+};
 use framework::{
-    framework::{BufferId, ShaderId, TextureId},
+    buffer::BufferInitialSetup,
+    framework::{BufferId, ComputeShaderId, ShaderId, TextureId},
     renderer::{
         draw_command::{BindableResource, DrawCommand, DrawMode, OptionalDrawData, PrimitiveType},
         renderer::Renderer,
     },
-    Camera2d, Framework, Transform2d,
+    Box2d, BufferConfiguration, BufferType, Camera2d, Framework, SrgbColor, Transform2d,
 };
-use image_editor::layers::{ChunkDiff, LayerOperation, OperationResult};
+use image_editor::layers::{ChunkDiff, ChunkedLayer, LayerOperation, OperationResult};
+use image_editor::PaintTarget;
+
+use super::{stamping_engine::BrushUniformData, StrokePath, StrokePoint};
+
+// Set when the stamps are computed by the compute brush, instead of being drawn as quads
+pub(crate) struct ComputeStamp {
+    pub shader: ComputeShaderId,
+    pub settings: BrushUniformData,
+}
 
-use super::StrokePath;
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ComputeBrushUniformData {
+    color: [f32; 4],
+    smoothness: f32,
+    samples_per_axis: u32,
+    stamp_count: u32,
+    is_eraser: u32,
+    is_alpha_locked: u32,
+    hardness: f32,
+    falloff: u32,
+    padding: [u32; 1],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct StampInstance {
+    position: [f32; 2],
+    scale: [f32; 2],
+    rotation: f32,
+    padding: [f32; 3],
+}
 
 pub(crate) struct StampOperation {
     pub path: StrokePath,
     pub brush: TextureId,
     pub color: wgpu::Color,
+    // Set when the color of each stamp is jittered, replaces color
+    pub stamp_colors: Option<Vec<wgpu::Color>>,
+    // How much of the color under each stamp is mixed in the brush color
+    pub mix: f32,
+    // The rotation of the stamps on the layer, and how much they're flattened along their y axis
+    pub angle: f32,
+    pub roundness: f32,
     pub is_eraser: bool,
+    // The stamps are only painted where the layer is already opaque
+    pub is_alpha_locked: bool,
+    // Layers without a mask are always painted on their pixels
+    pub target: PaintTarget,
     pub brush_settings_buffer: BufferId,
 
     pub eraser_shader_id: ShaderId,
     pub brush_shader_id: ShaderId,
+    pub alpha_locked_shader_id: ShaderId,
+    pub compute_stamp: Option<ComputeStamp>,
 
     pub diff: ChunkDiff,
+    // The world space region covered by the stamps, set once the operation is executed
+    pub dirty_region: Option<Box2d>,
 }
 
 impl LayerOperation for StampOperation {
     fn execute(
         &mut self,
         layer: &mut image_editor::layers::Layer,
-        bounds: framework::Box2d,
+        bounds: Box2d,
         renderer: &mut Renderer,
         framework: &mut Framework,
     ) -> image_editor::layers::OperationResult {
@@ -39,31 +89,85 @@ impl LayerOperation for StampOperation {
         if let (Some(inv_layer_matrix), Some(rendering_camera)) =
             (inv_layer_matrix, layer_rendering_camera)
         {
-            match &mut layer.layer_type {
-                image_editor::layers::LayerType::Chonky(map) => {
+            let map = match (self.target, layer.mask().is_some()) {
+                (PaintTarget::LayerMask, true) => layer.mask_mut(),
+                _ => match &mut layer.layer_type {
+                    image_editor::layers::LayerType::Chonky(map) => Some(map),
+                    _ => None,
+                },
+            };
+            match map {
+                Some(map) => {
                     let chunk_size = map.chunk_size();
-                    let bounds = bounds.transformed(inv_layer_matrix);
-                    self.diff = map.edit(
-                        bounds,
-                        |chunk, _, chunk_world_position, framework| {
-                            self.stamp_on_texture(
-                                layer_transform,
-                                chunk_world_position,
-                                renderer,
-                                rendering_camera,
-                                framework,
-                                chunk_size,
-                                chunk_size,
-                                chunk,
-                            );
-                        },
-                        framework,
-                    );
+                    let point_count = self.path.points.len();
+                    // The mixed stamps sample the color painted by the ones before them,
+                    // and the jittered ones have different colors, so they're stamped one at a time
+                    let one_at_a_time = self.mix > 0.0 || self.stamp_colors.is_some();
+                    let batches: Vec<Range<usize>> = if one_at_a_time {
+                        (0..point_count).map(|i| i..i + 1).collect()
+                    } else {
+                        vec![0..point_count]
+                    };
+                    self.diff = ChunkDiff::new();
+                    for batch in batches {
+                        let (color, bounds) = if one_at_a_time {
+                            let point = &self.path.points[batch.start];
+                            let extent = point.size * std::f32::consts::SQRT_2;
+                            let point_bounds = Box2d {
+                                center: point.position,
+                                extents: vec2(extent, extent),
+                            };
+                            let stamp_color = match &self.stamp_colors {
+                                Some(colors) => colors[batch.start],
+                                None => self.color,
+                            };
+                            let color = if self.mix > 0.0 {
+                                self.mixed_color(
+                                    stamp_color,
+                                    map,
+                                    point,
+                                    inv_layer_matrix,
+                                    framework,
+                                )
+                            } else {
+                                stamp_color
+                            };
+                            (color, point_bounds.transformed(inv_layer_matrix))
+                        } else {
+                            (self.color, bounds.transformed(inv_layer_matrix))
+                        };
+                        let points = &self.path.points[batch];
+                        let diff = map.edit(
+                            bounds,
+                            |chunk, _, chunk_world_position, framework| {
+                                self.stamp_on_texture(
+                                    points,
+                                    color,
+                                    layer_transform,
+                                    chunk_world_position,
+                                    renderer,
+                                    rendering_camera,
+                                    framework,
+                                    chunk_size,
+                                    chunk_size,
+                                    chunk,
+                                );
+                            },
+                            framework,
+                        );
+                        self.diff.join(&diff);
+                    }
                 }
-                _ => unreachable!(),
+                None => unreachable!(),
             }
+            self.dirty_region = self
+                .path
+                .stamp_bounds(layer_transform.rotation_radians.0 + self.angle);
+        }
+        match self.dirty_region {
+            Some(region) => OperationResult::RerenderRegion(region),
+            None => OperationResult::None,
         }
-        OperationResult::Rerender
     }
 
     fn accept(&self, layer: &image_editor::layers::Layer) -> bool {
@@ -74,9 +178,66 @@ impl LayerOperation for StampOperation {
     }
 }
 
+// The roundness flattens the stamp along its own y axis, since the brush vertex shader scales
+// the stamps before rotating them
+pub(super) fn stamp_transform(
+    point: &StrokePoint,
+    origin: Point3<f32>,
+    layer_transform: Transform2d,
+    angle: f32,
+    roundness: f32,
+) -> Transform2d {
+    let inv_scale = 1.0 / layer_transform.scale;
+    Transform2d {
+        position: origin,
+        // Account for layer scale when stamping
+        scale: vec2(
+            point.size * inv_scale.x,
+            point.size * inv_scale.y * roundness,
+        ),
+        rotation_radians: layer_transform.rotation_radians + Rad(angle),
+    }
+}
+
 impl StampOperation {
+    // Lerps the stamp color towards the layer color under the stamp, in linear space
+    fn mixed_color(
+        &self,
+        color: wgpu::Color,
+        map: &ChunkedLayer,
+        point: &StrokePoint,
+        inv_layer_matrix: Matrix4<f32>,
+        framework: &Framework,
+    ) -> wgpu::Color {
+        let position =
+            inv_layer_matrix.transform_point(point3(point.position.x, point.position.y, 0.0));
+        match map.sample(point2(position.x, position.y), framework) {
+            // Transparent pixels have no color to mix with
+            Some(sampled) if sampled.a > 0.0 => {
+                let byte = |c: f64| (c * 255.0).round() as u8;
+                let sampled = wgpu::Color::from_srgb_u8([
+                    byte(sampled.r),
+                    byte(sampled.g),
+                    byte(sampled.b),
+                    byte(sampled.a),
+                ]);
+                let mix = self.mix as f64;
+                let lerp = |brush: f64, canvas: f64| brush + (canvas - brush) * mix;
+                wgpu::Color {
+                    r: lerp(color.r, sampled.r),
+                    g: lerp(color.g, sampled.g),
+                    b: lerp(color.b, sampled.b),
+                    a: color.a,
+                }
+            }
+            _ => color,
+        }
+    }
+
     fn stamp_on_texture(
         &self,
+        points: &[StrokePoint],
+        color: wgpu::Color,
         layer_transform: Transform2d,
         offset: Point2<f32>,
         renderer: &mut Renderer,
@@ -86,11 +247,8 @@ impl StampOperation {
         target_height: u32,
         stamp_texture: &TextureId,
     ) {
-        let inv_scale = 1.0 / layer_transform.scale;
         let inv_layer_matrix = layer_transform.matrix().invert().unwrap();
-        let transforms: Vec<Transform2d> = self
-            .path
-            .points
+        let transforms: Vec<Transform2d> = points
             .iter()
             .map(|pt| {
                 /*
@@ -101,13 +259,19 @@ impl StampOperation {
                 let stroke_origin =
                     inv_layer_matrix.transform_point(point3(pt.position.x, pt.position.y, 0.0));
                 let stroke_origin = stroke_origin - point3(offset.x, offset.y, 0.0).to_vec();
-                Transform2d {
-                    position: stroke_origin,
-                    scale: vec2(pt.size * inv_scale.x, pt.size * inv_scale.y), // Account for layer scale when stamping
-                    rotation_radians: layer_transform.rotation_radians,
-                }
+                stamp_transform(
+                    pt,
+                    stroke_origin,
+                    layer_transform,
+                    self.angle,
+                    self.roundness,
+                )
             })
             .collect();
+        if let Some(compute_stamp) = &self.compute_stamp {
+            self.stamp_with_compute(compute_stamp, &transforms, color, stamp_texture, framework);
+            return;
+        }
         // 2. Do draw
         let stamp = self.brush.clone();
         renderer.begin(&camera_to_use, None, framework);
@@ -117,12 +281,14 @@ impl StampOperation {
                 texture_id: stamp,
                 instances: transforms,
                 flip_uv_y: true,
-                multiply_color: self.color,
+                multiply_color: color,
             },
             draw_mode: DrawMode::Instanced,
             additional_data: OptionalDrawData {
                 shader: Some(if self.is_eraser {
                     self.eraser_shader_id.clone()
+                } else if self.is_alpha_locked {
+                    self.alpha_locked_shader_id.clone()
                 } else {
                     self.brush_shader_id.clone()
                 }),
@@ -135,7 +301,120 @@ impl StampOperation {
         renderer.end(stamp_texture, None, framework);
     }
 
+    // One dispatch computes all the stamps on the chunk, the result then replaces the chunk
+    fn stamp_with_compute(
+        &self,
+        compute_stamp: &ComputeStamp,
+        transforms: &[Transform2d],
+        color: wgpu::Color,
+        chunk: &TextureId,
+        framework: &mut Framework,
+    ) {
+        let (width, height) = framework.texture2d_dimensions(chunk);
+        let settings = ComputeBrushUniformData {
+            color: [
+                color.r as f32,
+                color.g as f32,
+                color.b as f32,
+                color.a as f32,
+            ],
+            smoothness: compute_stamp.settings.softness,
+            samples_per_axis: compute_stamp.settings.samples_per_axis,
+            stamp_count: transforms.len() as u32,
+            is_eraser: self.is_eraser as u32,
+            is_alpha_locked: self.is_alpha_locked as u32,
+            hardness: compute_stamp.settings.hardness,
+            falloff: compute_stamp.settings.falloff,
+            padding: [0; 1],
+        };
+        let stamps: Vec<StampInstance> = transforms
+            .iter()
+            .map(|t| StampInstance {
+                position: [t.position.x, t.position.y],
+                scale: [t.scale.x, t.scale.y],
+                rotation: t.rotation_radians.0,
+                padding: [0.0; 3],
+            })
+            .collect();
+        let settings_buffer = framework.allocate_transient_buffer(BufferConfiguration {
+            initial_setup: BufferInitialSetup::Data(&vec![settings]),
+            buffer_type: BufferType::Uniform,
+            gpu_copy_dest: true,
+            gpu_copy_source: false,
+            cpu_copy_dest: false,
+            cpu_copy_source: false,
+        });
+        let stamps_buffer = framework.allocate_transient_buffer(BufferConfiguration {
+            initial_setup: BufferInitialSetup::Data(&stamps),
+            buffer_type: BufferType::Storage,
+            gpu_copy_dest: true,
+            gpu_copy_source: false,
+            cpu_copy_dest: false,
+            cpu_copy_source: false,
+        });
+        let result_buffer = framework.allocate_transient_buffer(BufferConfiguration::<u32> {
+            initial_setup: BufferInitialSetup::Count((width * height) as usize),
+            buffer_type: BufferType::Storage,
+            gpu_copy_dest: false,
+            gpu_copy_source: true,
+            cpu_copy_dest: false,
+            cpu_copy_source: false,
+        });
+        let dispatch = framework.dispatch_compute(
+            &compute_stamp.shader,
+            &[
+                BindableResource::UniformBuffer(settings_buffer),
+                BindableResource::StorageBuffer(stamps_buffer),
+                BindableResource::Texture(self.brush.clone()),
+                BindableResource::Texture(chunk.clone()),
+                BindableResource::StorageBuffer(result_buffer.clone()),
+            ],
+            ((width + 7) / 8, (height + 7) / 8, 1),
+        );
+        match dispatch {
+            Ok(()) => framework.texture2d_copy_from_buffer(chunk, &result_buffer),
+            Err(e) => log::error!("Compute brush: {e}"),
+        }
+    }
+
     pub(crate) fn diff(self) -> ChunkDiff {
         self.diff
     }
 }
+
+#[cfg(test)]
+mod test {
+    use cgmath::{point2, point3, vec2, Rad};
+    use framework::Transform2d;
+
+    use crate::StrokePoint;
+
+    use super::stamp_transform;
+
+    #[test]
+    fn flattened_stamps_have_a_shorter_height() {
+        let point = StrokePoint {
+            position: point2(10.0, 5.0),
+            size: 8.0,
+        };
+        let transform = stamp_transform(
+            &point,
+            point3(10.0, 5.0, 0.0),
+            Transform2d::default(),
+            0.3,
+            0.5,
+        );
+        assert_eq!(transform.scale, vec2(8.0, 4.0));
+        assert_eq!(transform.rotation_radians, Rad(0.3));
+
+        // The layer scale is still compensated
+        let layer_transform = Transform2d {
+            scale: vec2(2.0, 2.0),
+            rotation_radians: Rad(0.5),
+            ..Default::default()
+        };
+        let transform = stamp_transform(&point, point3(5.0, 2.5, 0.0), layer_transform, 0.3, 0.5);
+        assert_eq!(transform.scale, vec2(4.0, 2.0));
+        assert!((transform.rotation_radians.0 - 0.8).abs() < 1e-6);
+    }
+}
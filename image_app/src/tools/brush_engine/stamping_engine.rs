@@ -1,50 +1,33 @@
-use framework::framework::{BufferId, ShaderId, TextureId};
-use framework::shader::{BindElement, ShaderCreationInfo};
-use framework::BufferConfiguration;
+use cgmath::Point2;
+use framework::framework::{BufferId, ComputeShaderId, ShaderId, TextureId};
+use framework::renderer::renderer::Renderer;
+use framework::shader::{BindElement, ComputeShaderCreationInfo, ShaderCreationInfo};
+use framework::{
+    Box2d, BufferConfiguration, ColorSpace, RgbaTexture2D, RgbaU8, SrgbColor, Texture,
+    TextureConfiguration, TextureUsage,
+};
 use framework::{Buffer, Framework};
-use image_editor::layers::{ChunkDiff, LayerId, LayerType};
+use image_editor::document::{Document, Palette};
+use image_editor::layers::{ChunkDiff, Layer};
+use image_editor::{ImageEditor, PaintTarget};
+use serde::{Deserialize, Serialize};
 use wgpu::{BlendComponent, ShaderModuleDescriptor, ShaderSource};
 
-use crate::tools::{EditorCommand, EditorContext};
+use crate::tools::{
+    EditorCommand, EditorContext, LayerMaskReplaceCommand, LayerReplaceCommand,
+    QuickMaskReplaceCommand,
+};
 use crate::{StrokeContext, StrokePath};
 
-use super::stamp_operation::StampOperation;
+use super::color_jitter::ColorJitter;
+use super::pixel_operation::PixelOperation;
+use super::stamp_operation::{ComputeStamp, StampOperation};
 use super::BrushEngine;
 
-struct LayerReplaceCommand {
-    chunk_diff: ChunkDiff,
-    modified_layer: LayerId,
-}
-impl LayerReplaceCommand {
-    pub fn new(modified_layer: LayerId, chunk_diff: ChunkDiff) -> Self {
-        Self {
-            chunk_diff,
-            modified_layer,
-        }
-    }
-}
-
-impl EditorCommand for LayerReplaceCommand {
-    fn undo(&self, context: &mut EditorContext) -> Box<dyn EditorCommand> {
-        let mut out_box: Option<Box<dyn EditorCommand>> = None;
-        context
-            .image_editor
-            .mutate_current_layer(|lay| match &mut lay.layer_type {
-                LayerType::Chonky(map) => {
-                    let inverted_diff = self.chunk_diff.apply_to_chunked_layer(map);
-                    out_box = Some(Box::new(LayerReplaceCommand::new(
-                        self.modified_layer,
-                        inverted_diff,
-                    )));
-                }
-                _ => unreachable!(),
-            });
-        out_box.unwrap()
-    }
-}
-
 pub struct Stamp {
     pub(crate) brush_texture: TextureId,
+    // Identifies the stamp in the brush presets, so it must not change between sessions
+    pub(crate) name: String,
 }
 
 pub struct StampCreationInfo<'framework> {
@@ -52,29 +35,134 @@ pub struct StampCreationInfo<'framework> {
 }
 
 impl Stamp {
-    pub fn new(brush_texture: TextureId) -> Self {
-        Self { brush_texture }
+    pub fn new(name: &str, brush_texture: TextureId) -> Self {
+        Self {
+            brush_texture,
+            name: name.to_owned(),
+        }
+    }
+}
+
+// Higher qualities sample each pixel of a stamp more times, smoothing the edges of small stamps
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    strum_macros::EnumIter,
+    strum_macros::Display,
+    Serialize,
+    Deserialize,
+)]
+pub enum StampQuality {
+    #[default]
+    Low,
+    Medium,
+    High,
+}
+
+impl StampQuality {
+    fn samples_per_axis(&self) -> u32 {
+        match self {
+            StampQuality::Low => 1,
+            StampQuality::Medium => 2,
+            StampQuality::High => 4,
+        }
     }
 }
 
+// The shape of the stamp alpha between the flat area set by the hardness and the stamp edge
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    strum_macros::EnumIter,
+    strum_macros::Display,
+    Serialize,
+    Deserialize,
+)]
+pub enum FalloffCurve {
+    #[default]
+    Linear,
+    Gaussian,
+    Smoothstep,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct StampConfiguration {
     pub color_srgb: [u8; 3],
     pub opacity: u8,
     pub flow: f32,
     pub softness: f32,
-    pub padding: [f32; 3],
+    // 1 keeps the stamps opaque up to their edge, 0 starts the falloff from their center
+    pub hardness: f32,
+    pub falloff: FalloffCurve,
+    pub quality: StampQuality,
     pub is_eraser: bool,
+    // 0 paints with the brush color, 1 with the color under each stamp
+    pub mix: f32,
+    // The stamps are flattened along their y axis by roundness, then rotated by angle
+    pub angle: f32,
+    pub roundness: f32,
+    // Paints one pixel for each one crossed by the stroke instead of the stamps, for pixel art
+    pub pixel_perfect: bool,
+    // Moves the color of each stamp around the brush color
+    pub color_jitter: ColorJitter,
+    // How far each stamp can move away from the stroke, as a fraction of its size
+    pub scatter: f32,
+}
+
+impl Default for StampConfiguration {
+    fn default() -> Self {
+        Self {
+            color_srgb: [0, 0, 0],
+            opacity: 255,
+            flow: 1.0,
+            softness: 0.2,
+            hardness: 1.0,
+            falloff: FalloffCurve::default(),
+            quality: StampQuality::default(),
+            is_eraser: false,
+            mix: 0.0,
+            angle: 0.0,
+            roundness: 1.0,
+            pixel_perfect: false,
+            color_jitter: ColorJitter::default(),
+            scatter: 0.0,
+        }
+    }
 }
 
 impl StampConfiguration {
     fn wgpu_color(&self) -> wgpu::Color {
-        wgpu::Color {
-            r: self.color_srgb[0] as f64 / 255.0,
-            g: self.color_srgb[1] as f64 / 255.0,
-            b: self.color_srgb[2] as f64 / 255.0,
-            a: self.opacity as f64 / 255.0,
+        let [r, g, b] = self.color_srgb;
+        wgpu::Color::from_srgb_u8([r, g, b, self.opacity])
+    }
+
+    fn for_target(mut self, target: PaintTarget, has_mask: bool) -> Self {
+        if target == PaintTarget::LayerMask && has_mask && self.is_eraser {
+            // Erasing on a mask hides the layer pixels, so black is painted on it
+            self.is_eraser = false;
+            self.color_srgb = [0, 0, 0];
+            self.opacity = 255;
+            self.mix = 0.0;
+            self.color_jitter = ColorJitter::default();
         }
+        self
+    }
+
+    // Indexed documents are painted opaque, with the nearest palette color
+    fn with_palette(mut self, palette: Option<&Palette>) -> Self {
+        if let Some(palette) = palette {
+            self.color_srgb = palette.nearest(self.color_srgb);
+            self.opacity = 255;
+        }
+        self
     }
 }
 
@@ -82,14 +170,18 @@ impl StampConfiguration {
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct BrushUniformData {
     pub softness: f32,
-    pub padding: [f32; 3],
+    pub samples_per_axis: u32,
+    pub hardness: f32,
+    pub falloff: u32,
 }
 
 impl From<StampConfiguration> for BrushUniformData {
     fn from(cfg: StampConfiguration) -> Self {
         Self {
             softness: cfg.softness,
-            padding: cfg.padding,
+            samples_per_axis: cfg.quality.samples_per_axis(),
+            hardness: cfg.hardness.clamp(0.0, 1.0),
+            falloff: cfg.falloff as u32,
         }
     }
 }
@@ -102,8 +194,18 @@ pub struct StrokingEngine {
     brush_shader_id: ShaderId,
     eraser_shader_id: ShaderId,
     brush_settings_buffer_id: BufferId,
+    pixel_eraser_shader_id: ShaderId,
+    // Used on the layers with a locked alpha
+    alpha_locked_shader_id: ShaderId,
+    pixel_alpha_locked_shader_id: ShaderId,
+    // Only created when the compute brush is enabled on an adapter supporting it
+    compute_brush_shader_id: Option<ComputeShaderId>,
 
     current_frame_chunk_diff: ChunkDiff,
+    // The last pixels of the current pixel perfect stroke
+    pixel_trail: Vec<Point2<i32>>,
+    // How many stamps were painted since the stroke began, the color jitter depends on it
+    stroke_stamp_count: u32,
 }
 
 impl StrokingEngine {
@@ -111,7 +213,8 @@ impl StrokingEngine {
         let brush_fragment = framework
             .shader_compiler
             .compile(include_str!("brush_fragment.wgsl"));
-        let brush_shader_info = ShaderCreationInfo::using_default_vertex_instanced(
+        let brush_shader_info = ShaderCreationInfo::using_vertex_instanced(
+            Self::brush_vertex(framework),
             ShaderModuleDescriptor {
                 label: Some("Brush shader"),
                 source: ShaderSource::Naga(brush_fragment),
@@ -119,7 +222,8 @@ impl StrokingEngine {
             framework,
         )
         .with_bind_element(BindElement::Texture) // 2: texture + sampler
-        .with_bind_element(BindElement::UniformBuffer); // 3: brush settings
+        .with_bind_element(BindElement::UniformBuffer) // 3: brush settings
+        .with_label("Brush shader");
 
         let eraser_blend_state = wgpu::BlendState {
             color: BlendComponent {
@@ -134,10 +238,25 @@ impl StrokingEngine {
             },
         };
 
+        // The stamps are multiplied by the alpha under them, which is left untouched
+        let alpha_locked_blend_state = wgpu::BlendState {
+            color: BlendComponent {
+                src_factor: wgpu::BlendFactor::DstAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+
         let brush_fragment = framework
             .shader_compiler
             .compile(include_str!("brush_fragment.wgsl"));
-        let eraser_shader_info = ShaderCreationInfo::using_default_vertex_instanced(
+        let eraser_shader_info = ShaderCreationInfo::using_vertex_instanced(
+            Self::brush_vertex(framework),
             ShaderModuleDescriptor {
                 label: Some("Eraser shader"),
                 source: ShaderSource::Naga(brush_fragment),
@@ -146,19 +265,41 @@ impl StrokingEngine {
         )
         .with_bind_element(BindElement::Texture) // 2: texture + sampler
         .with_bind_element(BindElement::UniformBuffer) // 3: brush settings
-        .with_blend_state(eraser_blend_state);
+        .with_blend_state(eraser_blend_state)
+        .with_label("Eraser shader");
 
-        let stamp_config = StampConfiguration {
-            color_srgb: [0, 0, 0],
-            opacity: 255,
-            flow: 1.0,
-            softness: 0.2,
-            padding: [0.0; 3],
-            is_eraser: false,
-        };
+        let brush_fragment = framework
+            .shader_compiler
+            .compile(include_str!("brush_fragment.wgsl"));
+        let alpha_locked_shader_info = ShaderCreationInfo::using_vertex_instanced(
+            Self::brush_vertex(framework),
+            ShaderModuleDescriptor {
+                label: Some("Alpha locked brush shader"),
+                source: ShaderSource::Naga(brush_fragment),
+            },
+            framework,
+        )
+        .with_bind_element(BindElement::Texture) // 2: texture + sampler
+        .with_bind_element(BindElement::UniformBuffer) // 3: brush settings
+        .with_blend_state(alpha_locked_blend_state)
+        .with_label("Alpha locked brush shader");
+
+        let stamp_config = StampConfiguration::default();
+
+        let pixel_eraser_shader_info =
+            ShaderCreationInfo::using_default_vertex_fragment_instanced(framework)
+                .with_blend_state(eraser_blend_state)
+                .with_label("Pixel eraser shader");
+        let pixel_alpha_locked_shader_info =
+            ShaderCreationInfo::using_default_vertex_fragment_instanced(framework)
+                .with_blend_state(alpha_locked_blend_state)
+                .with_label("Pixel alpha locked shader");
 
         let brush_shader_id = framework.create_shader(brush_shader_info);
         let eraser_shader_id = framework.create_shader(eraser_shader_info);
+        let pixel_eraser_shader_id = framework.create_shader(pixel_eraser_shader_info);
+        let alpha_locked_shader_id = framework.create_shader(alpha_locked_shader_info);
+        let pixel_alpha_locked_shader_id = framework.create_shader(pixel_alpha_locked_shader_info);
         let brush_settings_buffer_id =
             framework.allocate_typed_buffer(BufferConfiguration::<BrushUniformData> {
                 initial_setup: framework::buffer::BufferInitialSetup::Data(&vec![
@@ -179,12 +320,25 @@ impl StrokingEngine {
             brush_shader_id,
             brush_settings_buffer_id,
             eraser_shader_id,
+            pixel_eraser_shader_id,
+            alpha_locked_shader_id,
+            pixel_alpha_locked_shader_id,
+            compute_brush_shader_id: None,
             current_frame_chunk_diff: ChunkDiff::new(),
+            pixel_trail: vec![],
+            stroke_stamp_count: 0,
         }
     }
 
-    pub fn create_stamp(&self, brush_texture: TextureId) -> Stamp {
-        Stamp::new(brush_texture)
+    fn brush_vertex(framework: &Framework) -> ShaderModuleDescriptor {
+        framework.shader_compiler.compile_into_shader_description(
+            "Brush vertex shader",
+            include_str!("brush_vertex.wgsl"),
+        )
+    }
+
+    pub fn create_stamp(&self, name: &str, brush_texture: TextureId) -> Stamp {
+        Stamp::new(name, brush_texture)
     }
 
     pub fn settings(&self) -> StampConfiguration {
@@ -196,12 +350,112 @@ impl StrokingEngine {
         self.wants_update_brush_settings = true; // Defer updating brush settings until stroke
     }
 
+    pub fn current_stamp_index(&self) -> usize {
+        self.current_stamp
+    }
+
+    pub fn current_stamp_name(&self) -> &str {
+        &self.current_stamp().name
+    }
+
+    // Returns false when there's no stamp with the given name
+    pub fn select_stamp(&mut self, name: &str) -> bool {
+        match self.stamps.iter().position(|stamp| stamp.name == name) {
+            Some(index) => {
+                self.current_stamp = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Adds a stamp shaped like the selected pixels of the current layer and selects it: their
+    // alpha becomes the alpha of the stamp, which is padded to a square so that it isn't
+    // stretched. The stamp is named after its alpha, so that the same selection always gives
+    // the same stamp. Returns the index of the new stamp, None when nothing is selected
+    pub fn define_brush_from_selection(
+        &mut self,
+        document: &mut Document,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> Option<usize> {
+        let layer = *document.current_layer_index()?;
+        let image = document
+            .layer_selection_image(&layer, renderer, framework)?
+            .into_rgba8();
+        let side = image.width().max(image.height());
+        let offset = ((side - image.width()) / 2, (side - image.height()) / 2);
+        let mut texels = vec![RgbaU8([255, 255, 255, 0]); (side * side) as usize];
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let index = (y + offset.1) * side + x + offset.0;
+            texels[index as usize].0[3] = pixel.0[3];
+        }
+        let name = format!(
+            "Selection {:016x}",
+            stamp_hash(side, texels.iter().map(|texel| texel.0[3]))
+        );
+        if self.select_stamp(&name) {
+            return Some(self.current_stamp);
+        }
+        let brush_texture = framework.allocate_texture2d(
+            RgbaTexture2D::from_texels(texels, (side, side)).ok()?,
+            TextureConfiguration {
+                label: Some("Stamp from selection"),
+                usage: TextureUsage::READ_WRITE,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+        self.stamps.push(Stamp::new(&name, brush_texture));
+        self.current_stamp = self.stamps.len() - 1;
+        Some(self.current_stamp)
+    }
+
     fn current_stamp(&self) -> &Stamp {
         self.stamps
             .get(self.current_stamp)
             .expect("Could not find the given index in stamp array")
     }
 
+    pub fn uses_compute_brush(&self) -> bool {
+        self.compute_brush_shader_id.is_some()
+    }
+
+    // The compute brush computes all the stamps of a stroke on a chunk in a single dispatch.
+    // Returns whether it's in use: when the adapter can't run compute shaders, the stamps
+    // are still drawn as quads
+    pub fn set_compute_brush(&mut self, enabled: bool, framework: &mut Framework) -> bool {
+        if !enabled || !framework.supports_compute_shaders() {
+            self.compute_brush_shader_id = None;
+            return false;
+        }
+        if self.compute_brush_shader_id.is_none() {
+            let brush_compute = framework
+                .shader_compiler
+                .compile(include_str!("brush_compute.wgsl"));
+            let info = ComputeShaderCreationInfo::new(
+                ShaderModuleDescriptor {
+                    label: Some("Compute brush shader"),
+                    source: ShaderSource::Naga(brush_compute),
+                },
+                framework,
+            )
+            .with_bind_element(BindElement::UniformBuffer) // 0: brush settings
+            .with_bind_element(BindElement::StorageBuffer { read_only: true }) // 1: stamps
+            .with_bind_element(BindElement::Texture) // 2: brush texture + sampler
+            .with_bind_element(BindElement::Texture) // 3: chunk texture
+            .with_bind_element(BindElement::StorageBuffer { read_only: false }); // 4: result
+            match framework.create_compute_shader(info) {
+                Ok(shader) => self.compute_brush_shader_id = Some(shader),
+                Err(e) => {
+                    log::error!("Could not create the compute brush: {e}");
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
     pub fn toggle_eraser(&mut self) {
         self.stamp_configuration.is_eraser = !self.stamp_configuration.is_eraser;
     }
@@ -212,18 +466,115 @@ impl StrokingEngine {
             vec![BrushUniformData::from(self.stamp_configuration)],
         );
     }
+
+    // The quick mask is never indexed
+    fn paint_palette(editor: &ImageEditor) -> Option<Palette> {
+        if editor.document().is_quick_mask_active() {
+            None
+        } else {
+            editor.document().palette_mode().cloned()
+        }
+    }
+
+    fn layer_settings(
+        &self,
+        layer: &Layer,
+        target: PaintTarget,
+        palette: Option<&Palette>,
+    ) -> StampConfiguration {
+        let has_mask = layer.mask().is_some();
+        let paints_pixels = target == PaintTarget::LayerPixels || !has_mask;
+        self.settings()
+            .for_target(target, has_mask)
+            .with_palette(palette.filter(|_| paints_pixels))
+    }
+
+    // The alpha lock only affects the layer pixels, never its mask
+    fn is_alpha_locked(layer: &Layer, target: PaintTarget) -> bool {
+        let paints_pixels = target == PaintTarget::LayerPixels || layer.mask().is_none();
+        paints_pixels && layer.settings().is_alpha_locked
+    }
+
+    // The last pixel of the trail is painted once the stroke ends
+    fn paint_pixels(
+        &mut self,
+        points: Vec<Point2<f32>>,
+        ends_stroke: bool,
+        context: StrokeContext,
+    ) {
+        let StrokeContext {
+            framework,
+            editor,
+            renderer,
+        } = context;
+        let target = editor.paint_target();
+        let palette = Self::paint_palette(editor);
+        editor.mutate_paint_target(|layer| {
+            let settings = self.layer_settings(layer, target, palette.as_ref());
+            let is_alpha_locked = Self::is_alpha_locked(layer, target);
+            if is_alpha_locked && settings.is_eraser {
+                // Erasing would change the alpha of the layer
+                return;
+            }
+            let mut op = PixelOperation {
+                points,
+                color: settings.wgpu_color(),
+                is_eraser: settings.is_eraser,
+                is_alpha_locked,
+                target,
+                eraser_shader_id: self.pixel_eraser_shader_id.clone(),
+                alpha_locked_shader_id: self.pixel_alpha_locked_shader_id.clone(),
+                trail: std::mem::take(&mut self.pixel_trail),
+                ends_stroke,
+                diff: ChunkDiff::new(),
+                dirty_region: None,
+            };
+            // The operation computes the painted region from the pixels
+            layer.execute_operation(&mut op, Box2d::origin(), renderer, framework);
+            self.pixel_trail = std::mem::take(&mut op.trail);
+            self.current_frame_chunk_diff.join(&op.diff);
+        });
+    }
+}
+
+// FNV-1a, whose results don't change between builds unlike the ones of the std hashers
+fn stamp_hash(side: u32, alphas: impl Iterator<Item = u8>) -> u64 {
+    side.to_le_bytes()
+        .into_iter()
+        .chain(alphas)
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
 }
 
 impl BrushEngine for StrokingEngine {
+    fn begin_stroking(&mut self, _context: &mut EditorContext) -> Option<Box<dyn EditorCommand>> {
+        self.pixel_trail.clear();
+        self.stroke_stamp_count = 0;
+        None
+    }
+
     fn stroke(
         &mut self,
         path: StrokePath,
         context: StrokeContext,
     ) -> Option<Box<dyn EditorCommand>> {
+        if self.stamp_configuration.pixel_perfect {
+            let points = path.points.iter().map(|pt| pt.position).collect();
+            self.paint_pixels(points, false, context);
+            return None;
+        }
         if self.wants_update_brush_settings {
             self.update_brush_settings(context.framework);
             self.wants_update_brush_settings = false;
         }
+        let scatter = self.stamp_configuration.scatter;
+        let path = if scatter > 0.0 {
+            let seed = self.stamp_configuration.color_jitter.seed;
+            path.scattered(scatter, seed, self.stroke_stamp_count)
+        } else {
+            path
+        };
 
         let StrokeContext {
             framework,
@@ -231,16 +582,56 @@ impl BrushEngine for StrokingEngine {
             renderer,
         } = context;
         let path_bounds = path.bounds();
-        editor.mutate_current_layer(move |layer| {
+        let target = editor.paint_target();
+        let palette = Self::paint_palette(editor);
+        editor.mutate_paint_target(move |layer| {
+            let settings = self.layer_settings(layer, target, palette.as_ref());
+            let is_alpha_locked = Self::is_alpha_locked(layer, target);
+            if is_alpha_locked && settings.is_eraser {
+                // Erasing would change the alpha of the layer
+                return;
+            }
+            // Erasers only use the alpha of the stamps
+            let stamp_colors = if settings.color_jitter.is_enabled() && !settings.is_eraser {
+                let first_stamp = self.stroke_stamp_count;
+                Some(
+                    (0..path.points.len() as u32)
+                        .map(|i| {
+                            let [r, g, b] = settings
+                                .color_jitter
+                                .jittered_color(settings.color_srgb, first_stamp + i);
+                            wgpu::Color::from_srgb_u8([r, g, b, settings.opacity])
+                        })
+                        .collect(),
+                )
+            } else {
+                None
+            };
+            self.stroke_stamp_count += path.points.len() as u32;
             let mut op = StampOperation {
                 path,
                 brush: self.current_stamp().brush_texture.clone(),
-                color: self.settings().wgpu_color(),
-                is_eraser: self.settings().is_eraser,
+                color: settings.wgpu_color(),
+                stamp_colors,
+                mix: settings.mix,
+                angle: settings.angle,
+                roundness: settings.roundness.clamp(0.01, 1.0),
+                is_eraser: settings.is_eraser,
+                is_alpha_locked,
+                target,
                 brush_settings_buffer: self.brush_settings_buffer_id.clone(),
                 eraser_shader_id: self.eraser_shader_id.clone(),
                 brush_shader_id: self.brush_shader_id.clone(),
+                alpha_locked_shader_id: self.alpha_locked_shader_id.clone(),
+                compute_stamp: self
+                    .compute_brush_shader_id
+                    .clone()
+                    .map(|shader| ComputeStamp {
+                        shader,
+                        settings: BrushUniformData::from(settings),
+                    }),
                 diff: ChunkDiff::new(),
+                dirty_region: None,
             };
             layer.execute_operation(&mut op, path_bounds, renderer, framework);
             let this_stroke_diff = op.diff();
@@ -251,13 +642,692 @@ impl BrushEngine for StrokingEngine {
     }
 
     fn end_stroking(&mut self, context: &mut EditorContext) -> Option<Box<dyn EditorCommand>> {
-        if let Some(layer_index) = context.image_editor.document().current_layer_index() {
-            Some(Box::new(LayerReplaceCommand::new(
-                layer_index.clone(),
-                self.current_frame_chunk_diff.take(),
-            )))
+        if !self.pixel_trail.is_empty() {
+            self.paint_pixels(
+                vec![],
+                true,
+                StrokeContext {
+                    framework: context.framework,
+                    editor: context.image_editor,
+                    renderer: context.renderer,
+                },
+            );
+        }
+        let quick_mask = context
+            .image_editor
+            .document()
+            .quick_mask()
+            .map(|mask| *mask.id());
+        if let Some(quick_mask) = quick_mask {
+            let diff = self.current_frame_chunk_diff.take();
+            Some(Box::new(QuickMaskReplaceCommand::new(quick_mask, diff)))
+        } else if let Some(layer_index) = context
+            .image_editor
+            .document()
+            .current_layer_index()
+            .cloned()
+        {
+            let paints_mask = context.image_editor.paint_target() == PaintTarget::LayerMask
+                && context
+                    .image_editor
+                    .document()
+                    .get_layer(&layer_index)
+                    .mask()
+                    .is_some();
+            if !paints_mask && context.image_editor.document().palette_mode().is_some() {
+                // The soft edges of the stamps get new colors, so they're snapped after the stroke
+                let framework = &*context.framework;
+                context
+                    .image_editor
+                    .mutate_document(|doc| doc.snap_layer_to_palette(&layer_index, framework));
+            }
+            let diff = self.current_frame_chunk_diff.take();
+            if paints_mask {
+                Some(Box::new(LayerMaskReplaceCommand::new(layer_index, diff)))
+            } else {
+                Some(Box::new(LayerReplaceCommand::new(layer_index, diff)))
+            }
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use cgmath::{point2, vec2};
+    use framework::{
+        renderer::renderer::Renderer, Box2d, ColorSpace, Framework, RgbaTexture2D, RgbaU8, Texture,
+        TextureConfiguration, TextureUsage,
+    };
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use image_editor::{
+        document::{AlphaMode, Palette},
+        headless_framework,
+        layers::{LayerSettings, LayerType},
+        selection::{SelectionAddition, SelectionShape, Shape},
+        ImageEditor, PaintTarget,
+    };
+
+    use crate::{BrushEngine, EditorContext, StrokeContext, StrokePath, StrokePoint};
+
+    use super::{
+        BrushUniformData, FalloffCurve, Stamp, StampConfiguration, StampQuality, StrokingEngine,
+    };
+
+    // A hard edged circle, much bigger than the stamps drawn with it
+    fn circle_stamp(framework: &mut Framework) -> Stamp {
+        let size = 64;
+        let texels = (0..size * size)
+            .map(|i| {
+                let (x, y) = ((i % size) as f32 + 0.5, (i / size) as f32 + 0.5);
+                let inside = (x - 32.0).powi(2) + (y - 32.0).powi(2) < 30.0 * 30.0;
+                RgbaU8([255, 255, 255, if inside { 255 } else { 0 }])
+            })
+            .collect();
+        let texture = framework.allocate_texture2d(
+            RgbaTexture2D::from_texels(texels, (size, size)).unwrap(),
+            TextureConfiguration {
+                label: Some("Circle stamp"),
+                usage: TextureUsage::WRITE_ONLY,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+        Stamp::new("Circle", texture)
+    }
+
+    // Stamps a small black circle on the white document, returning the coverage of the pixels around it
+    fn stamp_coverage(quality: StampQuality) -> Vec<Vec<f32>> {
+        stamp_coverage_with(|settings| StampConfiguration {
+            quality,
+            ..settings
+        })
+    }
+
+    fn stamp_coverage_with(
+        configure: impl FnOnce(StampConfiguration) -> StampConfiguration,
+    ) -> Vec<Vec<f32>> {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let stamp = circle_stamp(&mut framework);
+        let mut engine = StrokingEngine::new(stamp, &mut framework);
+        engine.set_new_settings(configure(StampConfiguration {
+            softness: 0.01,
+            ..engine.settings()
+        }));
+
+        let (position, size) = (point2(0.3, 0.6), 5.0);
+        engine.stroke(
+            StrokePath {
+                points: vec![StrokePoint { position, size }],
+                bounds: Box2d {
+                    center: position,
+                    extents: vec2(size, size),
+                },
+            },
+            StrokeContext {
+                framework: &mut framework,
+                editor: &mut image_editor,
+                renderer: &mut renderer,
+            },
+        );
+        image_editor.update_layers(&mut renderer, &mut framework);
+        image_editor.render_document(&mut renderer, &mut framework);
+        let image = image_editor
+            .document()
+            .final_image_bytes(AlphaMode::Straight, &framework)
+            .unwrap()
+            .into_rgba8();
+
+        let (center_x, center_y) = (image.width() / 2, image.height() / 2);
+        let coverage = (center_y - 12..center_y + 12)
+            .map(|y| {
+                (center_x - 12..center_x + 12)
+                    .map(|x| 1.0 - image.get_pixel(x, y).0[0] as f32 / 255.0)
+                    .collect()
+            })
+            .collect();
+        coverage
+    }
+
+    // Sum of the squared differences between neighbouring pixels: hard edges make it bigger
+    fn edge_roughness(coverage: &[Vec<f32>]) -> f32 {
+        let mut roughness = 0.0;
+        for y in 0..coverage.len() {
+            for x in 0..coverage[y].len() {
+                if let Some(right) = coverage[y].get(x + 1) {
+                    roughness += (right - coverage[y][x]).powi(2);
+                }
+                if let Some(below) = coverage.get(y + 1) {
+                    roughness += (below[x] - coverage[y][x]).powi(2);
+                }
+            }
+        }
+        roughness
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn high_quality_stamps_have_smoother_edges() {
+        let low = stamp_coverage(StampQuality::Low);
+        let high = stamp_coverage(StampQuality::High);
+
+        // Both stamps cover the same area
+        assert!(low[12][12] > 0.9 && high[12][12] > 0.9);
+        assert!(low[0][0] < 0.1 && high[0][0] < 0.1);
+        let total = |coverage: &[Vec<f32>]| coverage.iter().flatten().sum::<f32>();
+        assert!((total(&low) - total(&high)).abs() < total(&low) * 0.25);
+
+        let edges = |coverage: &[Vec<f32>]| {
+            coverage
+                .iter()
+                .flatten()
+                .filter(|c| **c > 0.05 && **c < 0.95)
+                .count()
+        };
+        assert!(edges(&high) > edges(&low));
+        assert!(edge_roughness(&high) < edge_roughness(&low));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn hardness_moves_the_start_of_the_falloff() {
+        let hard = stamp_coverage_with(|settings| StampConfiguration {
+            hardness: 1.0,
+            ..settings
+        });
+        let soft = stamp_coverage_with(|settings| StampConfiguration {
+            hardness: 0.0,
+            falloff: FalloffCurve::Linear,
+            ..settings
+        });
+
+        // The stamp center is on the row 11, its radius is about 4.7 pixels
+        let profile = |coverage: &[Vec<f32>]| -> Vec<f32> { coverage[11][12..18].to_vec() };
+        let (hard, soft) = (profile(&hard), profile(&soft));
+
+        // Flat up to the edge of the stamp, then a sharp edge
+        assert!(hard[..4].iter().all(|c| *c > 0.9), "{hard:?}");
+        assert!(hard[5] < 0.1, "{hard:?}");
+
+        // Fading from the center
+        assert!(soft[0] > 0.8, "{soft:?}");
+        assert!(soft.windows(2).all(|w| w[1] <= w[0] + 0.01), "{soft:?}");
+        assert!(soft[3] < hard[3] - 0.3, "{soft:?}");
+        assert!(soft[3] > 0.05, "{soft:?}");
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn stroke_bounds_are_tight() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let stamp = circle_stamp(&mut framework);
+        let mut engine = StrokingEngine::new(stamp, &mut framework);
+        image_editor.update_layers(&mut renderer, &mut framework);
+        image_editor.render_document(&mut renderer, &mut framework);
+
+        let points = vec![
+            StrokePoint {
+                position: point2(-20.0, 10.0),
+                size: 3.0,
+            },
+            StrokePoint {
+                position: point2(-10.0, 12.0),
+                size: 5.0,
+            },
+        ];
+        engine.stroke(
+            StrokePath {
+                points,
+                bounds: Box2d {
+                    center: point2(-15.0, 11.0),
+                    extents: vec2(10.0, 6.0),
+                },
+            },
+            StrokeContext {
+                framework: &mut framework,
+                editor: &mut image_editor,
+                renderer: &mut renderer,
+            },
+        );
+        // The layer is drawn again only where the stroke painted
+        let bounds = image_editor.selected_layer().dirty_region().unwrap();
+        assert_eq!(
+            bounds,
+            Box2d::from_points(point2(-23.0, 7.0), point2(-5.0, 17.0))
+        );
+
+        // Only the pixels inside the bounds are painted
+        image_editor.update_layers(&mut renderer, &mut framework);
+        image_editor.render_document(&mut renderer, &mut framework);
+        let image = image_editor
+            .document()
+            .final_image_bytes(AlphaMode::Straight, &framework)
+            .unwrap()
+            .into_rgba8();
+        let (half_width, half_height) = (image.width() as f32 * 0.5, image.height() as f32 * 0.5);
+        let mut painted = 0;
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if pixel.0 != [255, 255, 255, 255] {
+                let world_x = x as f32 + 0.5 - half_width;
+                let world_y = half_height - y as f32 - 0.5;
+                assert!(bounds.contains_point(point2(world_x, world_y)));
+                painted += 1;
+            }
+        }
+        assert!(painted > 0);
+    }
+
+    // Paints a straight black stroke on the white document, returning the coverage of each pixel
+    fn straight_stroke_coverage(compute_brush: bool) -> Vec<f32> {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let stamp = circle_stamp(&mut framework);
+        let mut engine = StrokingEngine::new(stamp, &mut framework);
+        assert_eq!(
+            engine.set_compute_brush(compute_brush, &mut framework),
+            compute_brush,
+            "The adapter doesn't support the compute brush"
+        );
+
+        let path = StrokePath::linear_start_to_end(
+            StrokePoint {
+                position: point2(-300.0, -20.0),
+                size: 6.0,
+            },
+            StrokePoint {
+                position: point2(300.0, 40.0),
+                size: 10.0,
+            },
+            2.0,
+        );
+        engine.stroke(
+            path,
+            StrokeContext {
+                framework: &mut framework,
+                editor: &mut image_editor,
+                renderer: &mut renderer,
+            },
+        );
+        image_editor.update_layers(&mut renderer, &mut framework);
+        image_editor.render_document(&mut renderer, &mut framework);
+        let image = image_editor
+            .document()
+            .final_image_bytes(AlphaMode::Straight, &framework)
+            .unwrap()
+            .into_rgba8();
+        image
+            .pixels()
+            .map(|p| 1.0 - p.0[0] as f32 / 255.0)
+            .collect()
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn compute_brush_matches_the_quad_brush() {
+        let compute = straight_stroke_coverage(true);
+        let quads = straight_stroke_coverage(false);
+
+        let painted = quads.iter().filter(|c| **c > 0.5).count();
+        assert!(painted > 0);
+        // The quads blend each stamp at 8 bits of precision, so the edges can differ a little
+        let mismatches = quads
+            .iter()
+            .zip(compute.iter())
+            .filter(|(a, b)| (*a - *b).abs() > 0.05)
+            .count();
+        assert!(
+            mismatches < painted / 50,
+            "{mismatches} of {painted} pixels differ"
+        );
+        let total = |coverage: &[f32]| coverage.iter().sum::<f32>();
+        assert!((total(&quads) - total(&compute)).abs() < total(&quads) * 0.02);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn fully_mixed_strokes_keep_the_canvas_color() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let stamp = circle_stamp(&mut framework);
+        let mut engine = StrokingEngine::new(stamp, &mut framework);
+
+        let mut stroke = |engine: &mut StrokingEngine, size: f32| {
+            let path = StrokePath::linear_start_to_end(
+                StrokePoint {
+                    position: point2(-40.0, 0.0),
+                    size,
+                },
+                StrokePoint {
+                    position: point2(40.0, 0.0),
+                    size,
+                },
+                2.0,
+            );
+            engine.stroke(
+                path,
+                StrokeContext {
+                    framework: &mut framework,
+                    editor: &mut image_editor,
+                    renderer: &mut renderer,
+                },
+            );
+        };
+        engine.set_new_settings(StampConfiguration {
+            color_srgb: [200, 30, 30],
+            softness: 0.01,
+            ..engine.settings()
+        });
+        stroke(&mut engine, 30.0);
+        engine.set_new_settings(StampConfiguration {
+            color_srgb: [20, 40, 220],
+            mix: 1.0,
+            ..engine.settings()
+        });
+        stroke(&mut engine, 10.0);
+
+        image_editor.update_layers(&mut renderer, &mut framework);
+        image_editor.render_document(&mut renderer, &mut framework);
+        let image = image_editor
+            .document()
+            .final_image_bytes(AlphaMode::Straight, &framework)
+            .unwrap()
+            .into_rgba8();
+        let (center_x, center_y) = (image.width() / 2, image.height() / 2);
+        for x in center_x - 30..center_x + 30 {
+            let pixel = image.get_pixel(x, center_y).0;
+            assert!(
+                pixel
+                    .iter()
+                    .zip([200, 30, 30, 255].iter())
+                    .all(|(a, b)| (*a as i32 - *b as i32).abs() <= 3),
+                "{pixel:?} at {x}"
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn erasing_on_the_mask_keeps_the_layer_pixels() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let stamp = circle_stamp(&mut framework);
+        let mut engine = StrokingEngine::new(stamp, &mut framework);
+
+        let mut stroke = |engine: &mut StrokingEngine, editor: &mut ImageEditor| {
+            let position = point2(0.0, 0.0);
+            engine.stroke(
+                StrokePath {
+                    points: vec![StrokePoint {
+                        position,
+                        size: 20.0,
+                    }],
+                    bounds: Box2d {
+                        center: position,
+                        extents: vec2(20.0, 20.0),
+                    },
+                },
+                StrokeContext {
+                    framework: &mut framework,
+                    editor,
+                    renderer: &mut renderer,
+                },
+            );
+        };
+        engine.set_new_settings(StampConfiguration {
+            color_srgb: [200, 30, 30],
+            softness: 0.01,
+            ..engine.settings()
+        });
+        stroke(&mut engine, &mut image_editor);
+
+        image_editor.mutate_current_layer(|layer| layer.add_mask());
+        image_editor.set_paint_target(PaintTarget::LayerMask);
+        engine.toggle_eraser();
+        stroke(&mut engine, &mut image_editor);
+
+        let layer = image_editor.document().current_layer();
+        let pixels = match &layer.layer_type {
+            LayerType::Chonky(map) => map.sample(point2(0.0, 0.0), &framework).unwrap(),
+            _ => unreachable!(),
+        };
+        let byte = |c: f64| (c * 255.0).round() as u8;
+        assert_eq!(
+            [
+                byte(pixels.r),
+                byte(pixels.g),
+                byte(pixels.b),
+                byte(pixels.a)
+            ],
+            [200, 30, 30, 255]
+        );
+        let mask = layer
+            .mask()
+            .unwrap()
+            .sample(point2(0.0, 0.0), &framework)
+            .unwrap();
+        assert_eq!(
+            [byte(mask.r), byte(mask.g), byte(mask.b), byte(mask.a)],
+            [0, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn alpha_locked_layers_keep_their_transparent_pixels() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let stamp = circle_stamp(&mut framework);
+        let mut engine = StrokingEngine::new(stamp, &mut framework);
+
+        let mut stroke = |engine: &mut StrokingEngine, editor: &mut ImageEditor, size: f32| {
+            let position = point2(0.0, 0.0);
+            engine.stroke(
+                StrokePath {
+                    points: vec![StrokePoint { position, size }],
+                    bounds: Box2d {
+                        center: position,
+                        extents: vec2(size, size),
+                    },
+                },
+                StrokeContext {
+                    framework: &mut framework,
+                    editor,
+                    renderer: &mut renderer,
+                },
+            );
+        };
+        // A half transparent stamp, surrounded by transparent pixels
+        engine.set_new_settings(StampConfiguration {
+            color_srgb: [200, 30, 30],
+            opacity: 128,
+            softness: 0.01,
+            ..engine.settings()
+        });
+        stroke(&mut engine, &mut image_editor, 10.0);
+
+        image_editor.mutate_current_layer(|layer| {
+            layer.set_settings(LayerSettings {
+                is_alpha_locked: true,
+                ..layer.settings().clone()
+            })
+        });
+        engine.set_new_settings(StampConfiguration {
+            color_srgb: [30, 30, 200],
+            opacity: 255,
+            ..engine.settings()
+        });
+        stroke(&mut engine, &mut image_editor, 40.0);
+
+        let layer = image_editor.document().current_layer();
+        let map = match &layer.layer_type {
+            LayerType::Chonky(map) => map,
+            _ => unreachable!(),
+        };
+        let byte = |c: f64| (c * 255.0).round() as u8;
+        let painted = map.sample(point2(0.5, 0.5), &framework).unwrap();
+        assert!(byte(painted.a).abs_diff(128) <= 1, "{painted:?}");
+        assert!(painted.b > painted.r, "{painted:?}");
+        // Outside of the first stamp the layer is still transparent
+        for x in [12.5, 20.5, 30.5] {
+            let pixel = map.sample(point2(x, 0.5), &framework).unwrap();
+            assert_eq!(
+                [byte(pixel.r), byte(pixel.g), byte(pixel.b), byte(pixel.a)],
+                [0, 0, 0, 0],
+                "at {x}"
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn indexed_documents_are_painted_with_the_nearest_palette_color() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        image_editor.mutate_document(|doc| {
+            doc.set_palette_mode(Palette::new(vec![[0, 0, 0], [255, 0, 0], [0, 0, 255]]))
+        });
+        let stamp = circle_stamp(&mut framework);
+        let mut engine = StrokingEngine::new(stamp, &mut framework);
+        engine.set_new_settings(StampConfiguration {
+            color_srgb: [200, 30, 30],
+            opacity: 200,
+            softness: 2.0,
+            ..engine.settings()
+        });
+
+        let position = point2(0.0, 0.0);
+        engine.stroke(
+            StrokePath {
+                points: vec![StrokePoint {
+                    position,
+                    size: 20.0,
+                }],
+                bounds: Box2d {
+                    center: position,
+                    extents: vec2(20.0, 20.0),
+                },
+            },
+            StrokeContext {
+                framework: &mut framework,
+                editor: &mut image_editor,
+                renderer: &mut renderer,
+            },
+        );
+        engine.end_stroking(&mut EditorContext {
+            framework: &mut framework,
+            image_editor: &mut image_editor,
+            renderer: &mut renderer,
+        });
+
+        let layer = image_editor.document().current_layer();
+        let map = match &layer.layer_type {
+            LayerType::Chonky(map) => map,
+            _ => unreachable!(),
+        };
+        let byte = |c: f64| (c * 255.0).round() as u8;
+        // The soft edges are snapped too, leaving no colors outside of the palette
+        for x in 0..24 {
+            let pixel = map.sample(point2(x as f32 + 0.5, 0.5), &framework).unwrap();
+            let pixel = [byte(pixel.r), byte(pixel.g), byte(pixel.b), byte(pixel.a)];
+            let in_palette = [[0, 0, 0, 255], [255, 0, 0, 255], [0, 0, 255, 255]].contains(&pixel);
+            assert!(in_palette || pixel[3] == 0, "{pixel:?} at {x}");
+        }
+        let center = map.sample(point2(0.5, 0.5), &framework).unwrap();
+        assert_eq!(
+            [
+                byte(center.r),
+                byte(center.g),
+                byte(center.b),
+                byte(center.a)
+            ],
+            [255, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn define_a_brush_from_a_selection() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        // The alpha grows from left to right
+        let image = RgbaImage::from_fn(64, 64, |x, _| Rgba([10, 20, 30, (x * 4) as u8]));
+        assert!(
+            image_editor.new_document_from_image(&DynamicImage::ImageRgba8(image), &mut framework)
+        );
+        let mut engine = StrokingEngine::new(circle_stamp(&mut framework), &mut framework);
+
+        let mut stamp = None;
+        image_editor.mutate_document(|doc| {
+            stamp = engine.define_brush_from_selection(doc, &mut renderer, &mut framework);
+        });
+        assert_eq!(stamp, None);
+
+        // The pixels from (8, 8) to (23, 23), the document is centered on the origin with y up
+        image_editor.mutate_document(|doc| {
+            doc.mutate_selection(|selection| {
+                selection.set(SelectionShape {
+                    mode: SelectionAddition::Add,
+                    shape: Shape::Rectangle(Box2d {
+                        center: point2(-16.0, 16.0),
+                        extents: vec2(8.0, 8.0),
+                    }),
+                })
+            });
+            stamp = engine.define_brush_from_selection(doc, &mut renderer, &mut framework);
+        });
+        assert_eq!(stamp, Some(1));
+        assert_eq!(engine.current_stamp_index(), 1);
+        let name = engine.current_stamp_name().to_owned();
+        assert!(name.starts_with("Selection "), "{name}");
+
+        // Defining the same brush again selects the existing stamp
+        assert!(engine.select_stamp("Circle"));
+        image_editor.mutate_document(|doc| {
+            stamp = engine.define_brush_from_selection(doc, &mut renderer, &mut framework);
+        });
+        assert_eq!(stamp, Some(1));
+        assert_eq!(engine.current_stamp_name(), name);
+        assert!(!engine.select_stamp("Missing"));
+
+        let stamp_texture = framework
+            .texture2d_read_data(&engine.stamps[1].brush_texture)
+            .unwrap();
+        assert_eq!((stamp_texture.width(), stamp_texture.height()), (16, 16));
+        let texels = stamp_texture.data().unwrap();
+        for y in 0..16 {
+            for x in 0..16 {
+                let [r, g, b, a] = texels[y * 16 + x].0;
+                assert_eq!([r, g, b], [255, 255, 255]);
+                let expected = ((x + 8) * 4) as i32;
+                assert!(
+                    (a as i32 - expected).abs() <= 1,
+                    "{x}, {y}: {a} != {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn better_qualities_take_more_samples() {
+        let samples: Vec<u32> = [StampQuality::Low, StampQuality::Medium, StampQuality::High]
+            .iter()
+            .map(StampQuality::samples_per_axis)
+            .collect();
+        // Low quality samples the stamp once, like before the quality setting was added
+        assert_eq!(samples[0], 1);
+        assert!(samples.windows(2).all(|pair| pair[0] < pair[1]));
+        // The uniform is read as 4 scalars by the brush shader
+        assert_eq!(std::mem::size_of::<BrushUniformData>(), 16);
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::config_dir::config_path;
+
+use super::{
+    brush_engine::{
+        color_jitter::ColorJitter,
+        stamping_engine::{FalloffCurve, StampConfiguration, StampQuality, StrokingEngine},
+    },
+    BrushTool, MAX_BRUSH_SIZE, MIN_BRUSH_SIZE,
+};
+
+fn default_roundness() -> f32 {
+    1.0
+}
+
+fn default_hardness() -> f32 {
+    1.0
+}
+
+const BRUSH_PRESETS_DIRECTORY: &str = "brush_presets";
+const PRESET_EXTENSION: &str = "json";
+
+// A named snapshot of the stamping engine settings and of the brush tool settings,
+// stored as a json file named after the preset
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BrushPreset {
+    pub name: String,
+    pub color_srgb: [u8; 3],
+    pub opacity: u8,
+    pub flow: f32,
+    pub softness: f32,
+    // Missing in the presets saved before the brush hardness was added
+    #[serde(default = "default_hardness")]
+    pub hardness: f32,
+    #[serde(default)]
+    pub falloff: FalloffCurve,
+    // Missing in the presets saved before the stamp quality was added
+    #[serde(default)]
+    pub quality: StampQuality,
+    pub is_eraser: bool,
+    // Missing in the presets saved before the color mixer was added
+    #[serde(default)]
+    pub mix: f32,
+    // Missing in the presets saved before the calligraphic stamps were added
+    #[serde(default)]
+    pub angle: f32,
+    #[serde(default = "default_roundness")]
+    pub roundness: f32,
+    // Missing in the presets saved before the pixel perfect mode was added
+    #[serde(default)]
+    pub pixel_perfect: bool,
+    // Missing in the presets saved before the color jitter was added
+    #[serde(default)]
+    pub color_jitter: ColorJitter,
+    // Missing in the presets saved before the scatter was added
+    #[serde(default)]
+    pub scatter: f32,
+    // Name of the stamp used by the stamping engine. Missing in the presets saved before the
+    // stamps had names, which keep the current stamp
+    #[serde(default)]
+    pub stamp_name: Option<String>,
+    pub size: f32,
+    pub pressure_delta: f32,
+    pub spacing: f32,
+}
+
+impl BrushPreset {
+    pub fn capture(name: &str, engine: &StrokingEngine, brush_tool: &BrushTool) -> Self {
+        let settings = engine.settings();
+        Self {
+            name: name.to_owned(),
+            color_srgb: settings.color_srgb,
+            opacity: settings.opacity,
+            flow: settings.flow,
+            softness: settings.softness,
+            hardness: settings.hardness,
+            falloff: settings.falloff,
+            quality: settings.quality,
+            is_eraser: settings.is_eraser,
+            mix: settings.mix,
+            angle: settings.angle,
+            roundness: settings.roundness,
+            pixel_perfect: settings.pixel_perfect,
+            color_jitter: settings.color_jitter,
+            scatter: settings.scatter,
+            stamp_name: Some(engine.current_stamp_name().to_owned()),
+            size: brush_tool.size,
+            pressure_delta: brush_tool.pressure_delta,
+            spacing: brush_tool.step,
+        }
+    }
+
+    pub fn apply(&self, engine: &mut StrokingEngine, brush_tool: &mut BrushTool) {
+        engine.set_new_settings(StampConfiguration {
+            color_srgb: self.color_srgb,
+            opacity: self.opacity,
+            flow: self.flow,
+            softness: self.softness,
+            hardness: self.hardness.clamp(0.0, 1.0),
+            falloff: self.falloff,
+            quality: self.quality,
+            is_eraser: self.is_eraser,
+            mix: self.mix.clamp(0.0, 1.0),
+            angle: self.angle,
+            roundness: self.roundness.clamp(0.0, 1.0),
+            pixel_perfect: self.pixel_perfect,
+            color_jitter: self.color_jitter,
+            scatter: self.scatter.max(0.0),
+        });
+        if let Some(stamp_name) = &self.stamp_name {
+            if !engine.select_stamp(stamp_name) {
+                warn!(
+                    "Brush preset {}: stamp {} does not exist, keeping the current one",
+                    self.name, stamp_name
+                );
+            }
+        }
+        brush_tool.size = self.size.clamp(MIN_BRUSH_SIZE, MAX_BRUSH_SIZE);
+        brush_tool.pressure_delta = self.pressure_delta;
+        brush_tool.step = self.spacing.max(1.0);
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    // Writes the preset in the directory, replacing the preset with the same name
+    pub fn save(&self, directory: &Path) -> anyhow::Result<PathBuf> {
+        fs::create_dir_all(directory)?;
+        let path = preset_path(directory, &self.name);
+        fs::write(&path, self.to_json()?)?;
+        Ok(path)
+    }
+
+    pub fn load(directory: &Path, name: &str) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(preset_path(directory, name))?;
+        Self::from_json(&json)
+    }
+
+    // The names of the presets saved in the directory, sorted alphabetically
+    pub fn list(directory: &Path) -> anyhow::Result<Vec<String>> {
+        if !directory.exists() {
+            return Ok(vec![]);
+        }
+        let mut names = vec![];
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(PRESET_EXTENSION) {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|n| n.to_str()) {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+// Where the presets are saved and listed from by the ui
+pub fn brush_presets_directory() -> PathBuf {
+    config_path(BRUSH_PRESETS_DIRECTORY)
+}
+
+fn preset_path(directory: &Path, name: &str) -> PathBuf {
+    // Keep the name from escaping the directory
+    let file_name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '_',
+            c => c,
+        })
+        .collect();
+    directory.join(format!("{}.{}", file_name, PRESET_EXTENSION))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        color_jitter::ColorJitter,
+        stamping_engine::{FalloffCurve, StampQuality},
+    };
+
+    use super::BrushPreset;
+
+    fn test_preset(name: &str) -> BrushPreset {
+        BrushPreset {
+            name: name.to_owned(),
+            color_srgb: [12, 200, 77],
+            opacity: 128,
+            flow: 0.75,
+            softness: 2.5,
+            hardness: 0.3,
+            falloff: FalloffCurve::Gaussian,
+            quality: StampQuality::High,
+            is_eraser: true,
+            mix: 0.25,
+            angle: 0.5,
+            roundness: 0.4,
+            pixel_perfect: true,
+            color_jitter: ColorJitter {
+                hue: 0.1,
+                saturation: 0.05,
+                value: 0.2,
+                seed: 9,
+            },
+            scatter: 1.5,
+            stamp_name: Some("Default".to_owned()),
+            size: 42.0,
+            pressure_delta: 7.5,
+            spacing: 4.0,
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let preset = test_preset("Soft eraser");
+        let loaded = BrushPreset::from_json(&preset.to_json().unwrap()).unwrap();
+        assert_eq!(loaded.name, "Soft eraser");
+        assert_eq!(loaded.color_srgb, [12, 200, 77]);
+        assert_eq!(loaded.opacity, 128);
+        assert_eq!(loaded.flow, 0.75);
+        assert_eq!(loaded.softness, 2.5);
+        assert_eq!(loaded.hardness, 0.3);
+        assert_eq!(loaded.falloff, FalloffCurve::Gaussian);
+        assert_eq!(loaded.quality, StampQuality::High);
+        assert!(loaded.is_eraser);
+        assert_eq!(loaded.mix, 0.25);
+        assert_eq!((loaded.angle, loaded.roundness), (0.5, 0.4));
+        assert_eq!(loaded.color_jitter.seed, 9);
+        assert_eq!(loaded.scatter, 1.5);
+        assert_eq!(loaded.stamp_name.as_deref(), Some("Default"));
+        assert_eq!(loaded.size, 42.0);
+        assert_eq!(loaded.pressure_delta, 7.5);
+        assert_eq!(loaded.spacing, 4.0);
+        assert_eq!(loaded, preset);
+    }
+
+    #[test]
+    fn presets_saved_with_a_stamp_index_still_load() {
+        let mut json: serde_json::Value =
+            serde_json::from_str(&test_preset("Old").to_json().unwrap()).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.remove("scatter");
+        fields.remove("stamp_name");
+        fields.insert("stamp".to_owned(), serde_json::json!(3));
+        let loaded = BrushPreset::from_json(&json.to_string()).unwrap();
+        assert_eq!(loaded.scatter, 0.0);
+        assert_eq!(loaded.stamp_name, None);
+        assert_eq!(loaded.size, 42.0);
+    }
+
+    #[test]
+    fn save_load_and_list() {
+        let directory = std::env::temp_dir().join(format!("mochi_presets_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        assert!(BrushPreset::list(&directory).unwrap().is_empty());
+
+        test_preset("b").save(&directory).unwrap();
+        test_preset("a").save(&directory).unwrap();
+        std::fs::write(directory.join("notes.txt"), "not a preset").unwrap();
+        assert_eq!(BrushPreset::list(&directory).unwrap(), vec!["a", "b"]);
+        assert_eq!(
+            BrushPreset::load(&directory, "b").unwrap(),
+            test_preset("b")
+        );
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
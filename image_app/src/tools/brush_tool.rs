@@ -1,6 +1,14 @@
 use std::{cell::RefCell, rc::Rc};
 
-use cgmath::{point2, MetricSpace, Point2};
+use application::{key::Key, InputState};
+use cgmath::{point2, vec2, MetricSpace, Point2, Vector2};
+use framework::{
+    renderer::{
+        draw_command::{DrawCommand, DrawMode, OptionalDrawData, PrimitiveType},
+        renderer::Renderer,
+    },
+    Box2d,
+};
 use image_editor::ImageEditor;
 
 use crate::{
@@ -8,31 +16,59 @@ use crate::{
     StrokeContext, StrokePoint,
 };
 
-use super::{BrushEngine, EditorCommand, StrokePath, Tool};
+use super::{BrushEngine, ColorPicker, EditorCommand, StrokePath, Tool};
+
+pub const MIN_BRUSH_SIZE: f32 = 1.0;
+pub const MAX_BRUSH_SIZE: f32 = 1000.0;
+// Each step changes the size by this factor, so that small brushes change slowly
+const BRUSH_SIZE_STEP_FACTOR: f32 = 1.2;
+// The cursor ring sizes are in screen pixels, so that the ring looks the same at any zoom
+const CURSOR_RING_WIDTH: f32 = 1.5;
+const MIN_CURSOR_RING_RADIUS: f32 = 3.0;
 
 pub struct BrushTool {
     engine: Rc<RefCell<dyn BrushEngine>>,
+    // Used while Alt is held
+    eyedropper: Rc<RefCell<ColorPicker>>,
     is_active: bool,
+    is_picking_color: bool,
     last_mouse_position: Point2<f32>,
     last_pressure: f32,
     pub size: f32,
     pub pressure_delta: f32,
     pub step: f32,
+    // When set, the stamps are moved on a grid with cells as big as this many pixels
+    pub snap_to_grid: Option<u32>,
+    // The grid point stamped last in the current stroke, so that it's not stamped again
+    last_snapped_point: Option<Point2<f32>>,
 }
 
 impl BrushTool {
-    pub fn new(initial_engine: Rc<RefCell<dyn BrushEngine>>, step: f32) -> Self {
+    pub fn new(
+        initial_engine: Rc<RefCell<dyn BrushEngine>>,
+        eyedropper: Rc<RefCell<ColorPicker>>,
+        step: f32,
+    ) -> Self {
         Self {
             engine: initial_engine.clone(),
+            eyedropper,
             step,
             is_active: false,
+            is_picking_color: false,
             last_mouse_position: point2(0.0, 0.0),
             last_pressure: 0.0,
             size: 5.0,
             pressure_delta: 5.0,
+            snap_to_grid: None,
+            last_snapped_point: None,
         }
     }
 
+    // [ makes the brush smaller, ] makes it bigger
+    pub fn update_size_from_keys(&mut self, input_state: &InputState) {
+        self.size = brush_size_from_keys(self.size, input_state);
+    }
+
     fn reposition_point_for_draw(
         image_editor: &ImageEditor,
         point: Point2<f32>,
@@ -41,12 +77,89 @@ impl BrushTool {
     }
 }
 
+// Moves the world space point on the nearest corner of a grid with cells of grid_size pixels,
+// starting from the top left corner of the document
+fn snap_to_grid(point: Point2<f32>, grid_size: u32, document_size: Vector2<u32>) -> Point2<f32> {
+    let grid_size = grid_size.max(1) as f32;
+    let half_size = vec2(document_size.x as f32, document_size.y as f32) * 0.5;
+    // In document coordinates the y axis goes down
+    let x = ((point.x + half_size.x) / grid_size).round() * grid_size;
+    let y = ((half_size.y - point.y) / grid_size).round() * grid_size;
+    point2(x - half_size.x, half_size.y - y)
+}
+
+// Snaps all the points of the path, leaving out the ones on the same grid point as the point
+// before them, starting from last_point. None when no point is left
+fn snap_path_to_grid(
+    path: StrokePath,
+    grid_size: u32,
+    document_size: Vector2<u32>,
+    mut last_point: Option<Point2<f32>>,
+) -> Option<StrokePath> {
+    let mut points = vec![];
+    for point in path.points {
+        let position = snap_to_grid(point.position, grid_size, document_size);
+        if last_point != Some(position) {
+            last_point = Some(position);
+            points.push(StrokePoint {
+                position,
+                size: point.size,
+            });
+        }
+    }
+    let bounds = points
+        .iter()
+        .map(|point| Box2d {
+            center: point.position,
+            extents: vec2(point.size, point.size),
+        })
+        .reduce(|bounds, point| bounds.union(&point))?;
+    Some(StrokePath { points, bounds })
+}
+
+// The inner and outer radius of the cursor ring in document pixels: the stamps reach
+// as far as the brush size from their center, camera_scale is the size of a screen pixel
+fn cursor_ring_radii(brush_size: f32, camera_scale: f32) -> (f32, f32) {
+    let radius = brush_size.max(MIN_CURSOR_RING_RADIUS * camera_scale);
+    let half_width = CURSOR_RING_WIDTH * camera_scale * 0.5;
+    ((radius - half_width).max(0.0), radius + half_width)
+}
+
+fn brush_size_from_keys(size: f32, input_state: &InputState) -> f32 {
+    if input_state.is_key_just_pressed(Key::LBracket) {
+        smaller_brush_size(size)
+    } else if input_state.is_key_just_pressed(Key::RBracket) {
+        bigger_brush_size(size)
+    } else {
+        size
+    }
+}
+
+// The size always changes by at least one pixel
+fn bigger_brush_size(size: f32) -> f32 {
+    (size * BRUSH_SIZE_STEP_FACTOR)
+        .max(size + 1.0)
+        .clamp(MIN_BRUSH_SIZE, MAX_BRUSH_SIZE)
+}
+
+fn smaller_brush_size(size: f32) -> f32 {
+    (size / BRUSH_SIZE_STEP_FACTOR)
+        .min(size - 1.0)
+        .clamp(MIN_BRUSH_SIZE, MAX_BRUSH_SIZE)
+}
+
 impl Tool for BrushTool {
     fn on_pointer_click(
         &mut self,
         pointer_click: PointerEvent,
         context: &mut EditorContext,
     ) -> Option<Box<dyn EditorCommand>> {
+        if pointer_click.modifiers.alt() {
+            // Picking lasts until the pointer is released, even if Alt is released before
+            self.is_picking_color = true;
+            self.eyedropper.borrow().pick_color(&pointer_click, context);
+            return None;
+        }
         self.is_active = true;
         let pt = BrushTool::reposition_point_for_draw(
             &context.image_editor,
@@ -55,6 +168,7 @@ impl Tool for BrushTool {
         if let Some(pos) = pt {
             self.last_mouse_position = pos;
             self.last_pressure = pointer_click.pressure;
+            self.last_snapped_point = None;
             self.engine.borrow_mut().begin_stroking(context)
         } else {
             None
@@ -66,6 +180,14 @@ impl Tool for BrushTool {
         pointer_motion: PointerEvent,
         context: &mut EditorContext,
     ) -> Option<Box<dyn EditorCommand>> {
+        if self.is_picking_color {
+            if pointer_motion.modifiers.alt() {
+                self.eyedropper
+                    .borrow()
+                    .pick_color(&pointer_motion, context);
+            }
+            return None;
+        }
         if !self.is_active {
             return None;
         }
@@ -93,6 +215,18 @@ impl Tool for BrushTool {
             };
 
             let path = StrokePath::linear_start_to_end(start, end, self.step);
+            self.last_mouse_position = new_pointer_position;
+            self.last_pressure = pointer_motion.pressure;
+            let path = match self.snap_to_grid {
+                Some(grid_size) => {
+                    let document_size = context.image_editor.document().document_size();
+                    let snapped =
+                        snap_path_to_grid(path, grid_size, document_size, self.last_snapped_point)?;
+                    self.last_snapped_point = snapped.points.last().map(|point| point.position);
+                    snapped
+                }
+                None => path,
+            };
 
             let context = StrokeContext {
                 framework: context.framework,
@@ -100,9 +234,6 @@ impl Tool for BrushTool {
                 renderer: context.renderer,
             };
 
-            self.last_mouse_position = new_pointer_position;
-            self.last_pressure = pointer_motion.pressure;
-
             self.engine.borrow_mut().stroke(path, context)
         } else {
             None
@@ -114,10 +245,278 @@ impl Tool for BrushTool {
         _pointer_release: PointerEvent,
         context: &mut EditorContext,
     ) -> Option<Box<dyn EditorCommand>> {
+        if self.is_picking_color {
+            self.is_picking_color = false;
+            return None;
+        }
         self.is_active = false;
         self.engine.borrow_mut().end_stroking(context)
     }
+    fn draw_cursor(
+        &self,
+        renderer: &mut Renderer,
+        cursor_position: Point2<f32>,
+        image_editor: &ImageEditor,
+    ) {
+        let (inner_radius, outer_radius) =
+            cursor_ring_radii(self.size, image_editor.camera().current_scale());
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Ring {
+                center: cursor_position,
+                radius: (inner_radius + outer_radius) * 0.5,
+                thickness: outer_radius - inner_radius,
+                color: wgpu::Color::BLACK,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData::default(),
+        });
+    }
+
     fn name(&self) -> &'static str {
         "Brush tool"
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use application::{key_event, InputState, ModifierSet};
+    use cgmath::{point2, vec2};
+    use framework::renderer::renderer::Renderer;
+    use image_editor::{headless_framework, layers::LayerType, ImageEditor};
+    use winit::event::{ElementState, VirtualKeyCode};
+
+    use crate::{
+        stamping_engine::StrokingEngine,
+        toolbox::Toolbox,
+        tools::{ColorPicker, EditorContext, PointerEvent, Tool},
+        StrokePath, StrokePoint,
+    };
+
+    use super::{
+        brush_size_from_keys, cursor_ring_radii, snap_path_to_grid, BrushTool, MIN_BRUSH_SIZE,
+    };
+
+    fn pointer_event(alt: bool) -> PointerEvent {
+        PointerEvent {
+            new_pointer_location_normalized: point2(0.0, 0.0),
+            new_pointer_location: point2(512.0, 512.0),
+            pressure: 1.0,
+            window_width: vec2(1024, 1024),
+            modifiers: ModifierSet::new(false, alt, false, false),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn alt_click_picks_color_without_painting() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        // The background layer is white, the current layer is empty
+        image_editor.update_layers(&mut renderer, &mut framework);
+        image_editor.render_document(&mut renderer, &mut framework);
+
+        let test_stamp = Toolbox::create_test_stamp(&mut framework);
+        let stamping_engine = Rc::new(RefCell::new(StrokingEngine::new(
+            test_stamp,
+            &mut framework,
+        )));
+        let color_picker = Rc::new(RefCell::new(ColorPicker::new(stamping_engine.clone())));
+        let mut brush_tool = BrushTool::new(stamping_engine.clone(), color_picker, 1.0);
+        assert_eq!(stamping_engine.borrow().settings().color_srgb, [0, 0, 0]);
+
+        let mut context = EditorContext {
+            framework: &mut framework,
+            image_editor: &mut image_editor,
+            renderer: &mut renderer,
+        };
+        assert!(brush_tool
+            .on_pointer_click(pointer_event(true), &mut context)
+            .is_none());
+        // Releasing Alt before the pointer must not start a stroke
+        assert!(brush_tool
+            .on_pointer_move(pointer_event(false), &mut context)
+            .is_none());
+        assert!(brush_tool
+            .on_pointer_release(pointer_event(false), &mut context)
+            .is_none());
+
+        assert_eq!(
+            stamping_engine.borrow().settings().color_srgb,
+            [255, 255, 255]
+        );
+        match &image_editor.selected_layer().layer_type {
+            LayerType::Chonky(map) => {
+                let mut chunks = 0;
+                map.iterate(|_, _, _| chunks += 1);
+                assert_eq!(chunks, 0);
+            }
+            _ => panic!("The current layer should be a raster layer"),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn painting_the_quick_mask_selects_the_painted_pixels() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        image_editor.toggle_quick_mask(&mut framework);
+        assert!(image_editor.document().is_quick_mask_active());
+
+        let test_stamp = Toolbox::create_test_stamp(&mut framework);
+        let stamping_engine = Rc::new(RefCell::new(StrokingEngine::new(
+            test_stamp,
+            &mut framework,
+        )));
+        let color_picker = Rc::new(RefCell::new(ColorPicker::new(stamping_engine.clone())));
+        let mut brush_tool = BrushTool::new(stamping_engine.clone(), color_picker, 1.0);
+        brush_tool.size = 20.0;
+
+        let mut context = EditorContext {
+            framework: &mut framework,
+            image_editor: &mut image_editor,
+            renderer: &mut renderer,
+        };
+        // A horizontal stroke from the center of the document to 100 pixels on its right
+        brush_tool.on_pointer_click(pointer_event(false), &mut context);
+        let mut stroke_end = pointer_event(false);
+        stroke_end.new_pointer_location_normalized = point2(100.0 / 512.0, 0.0);
+        brush_tool.on_pointer_move(stroke_end, &mut context);
+        let stroke = brush_tool
+            .on_pointer_release(pointer_event(false), &mut context)
+            .expect("Quick mask strokes can be undone");
+        fn mask_chunks(image_editor: &ImageEditor) -> usize {
+            let mut chunks = 0;
+            if let Some(LayerType::Chonky(map)) = image_editor
+                .document()
+                .quick_mask()
+                .map(|mask| &mask.layer_type)
+            {
+                map.iterate(|_, _, _| chunks += 1);
+            }
+            chunks
+        }
+        assert!(mask_chunks(context.image_editor) > 0);
+        let redo = stroke.undo(&mut context).unwrap();
+        assert_eq!(mask_chunks(context.image_editor), 0);
+        redo.undo(&mut context).unwrap();
+        image_editor.toggle_quick_mask(&mut framework);
+        assert!(!image_editor.document().is_quick_mask_active());
+
+        let selection = image_editor.document().selection();
+        assert!(selection.contains(point2(10.5, 0.5)));
+        assert!(selection.contains(point2(50.5, -5.5)));
+        assert!(selection.contains(point2(90.5, 5.5)));
+        assert!(!selection.contains(point2(50.5, 100.5)));
+        assert!(!selection.contains(point2(-100.5, 0.5)));
+
+        // The layers weren't touched
+        match &image_editor.selected_layer().layer_type {
+            LayerType::Chonky(map) => {
+                let mut chunks = 0;
+                map.iterate(|_, _, _| chunks += 1);
+                assert_eq!(chunks, 0);
+            }
+            _ => panic!("The current layer should be a raster layer"),
+        }
+    }
+
+    #[test]
+    fn brackets_change_brush_size() {
+        let mut input_state = InputState::new();
+        let mut size = 10.0;
+        let mut press = |key: VirtualKeyCode| {
+            input_state.update(&key_event(key, ElementState::Pressed));
+            size = brush_size_from_keys(size, &input_state);
+            input_state.update(&key_event(key, ElementState::Released));
+            size = brush_size_from_keys(size, &input_state);
+            size
+        };
+
+        assert!((press(VirtualKeyCode::RBracket) - 12.0).abs() < 0.001);
+        assert!((press(VirtualKeyCode::LBracket) - 10.0).abs() < 0.001);
+        // Small brushes change by one pixel at a time
+        let mut size_before = press(VirtualKeyCode::LBracket);
+        while size_before > MIN_BRUSH_SIZE {
+            let new_size = press(VirtualKeyCode::LBracket);
+            assert!(size_before - new_size >= 1.0 || new_size == MIN_BRUSH_SIZE);
+            size_before = new_size;
+        }
+        assert_eq!(press(VirtualKeyCode::LBracket), MIN_BRUSH_SIZE);
+        assert_eq!(press(VirtualKeyCode::RBracket), MIN_BRUSH_SIZE + 1.0);
+    }
+
+    #[test]
+    fn snapped_strokes_are_on_the_grid() {
+        let document_size = vec2(64, 32);
+        let path = StrokePath::linear_start_to_end(
+            StrokePoint {
+                position: point2(-13.3, 7.9),
+                size: 2.0,
+            },
+            StrokePoint {
+                position: point2(17.6, -9.2),
+                size: 4.0,
+            },
+            1.0,
+        );
+        let points = path.points.len();
+        let snapped = snap_path_to_grid(path, 4, document_size, None).unwrap();
+        assert!(snapped.points.len() < points);
+        for point in snapped.points.iter() {
+            // The document coordinates start from the top left corner, with the y axis going down
+            let (x, y) = (point.position.x + 32.0, 16.0 - point.position.y);
+            assert_eq!((x % 4.0, y % 4.0), (0.0, 0.0), "{:?}", point.position);
+        }
+        for pair in snapped.points.windows(2) {
+            assert_ne!(pair[0].position, pair[1].position);
+        }
+        assert_eq!(snapped.points[0].position, point2(-12.0, 8.0));
+        assert_eq!(snapped.points.last().unwrap().position, point2(16.0, -8.0));
+        assert!(snapped.bounds.contains_point(point2(19.0, -11.0)));
+
+        // A path that doesn't leave the last stamped grid point has nothing to stamp
+        let path = StrokePath::linear_start_to_end(
+            StrokePoint {
+                position: point2(0.4, 0.3),
+                size: 2.0,
+            },
+            StrokePoint {
+                position: point2(1.2, -0.6),
+                size: 2.0,
+            },
+            0.5,
+        );
+        assert!(snap_path_to_grid(path, 4, document_size, Some(point2(0.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn cursor_ring_matches_the_brush_size() {
+        let assert_radii = |(inner, outer): (f32, f32), expected: (f32, f32)| {
+            assert!(
+                (inner - expected.0).abs() < 0.001,
+                "{} != {}",
+                inner,
+                expected.0
+            );
+            assert!(
+                (outer - expected.1).abs() < 0.001,
+                "{} != {}",
+                outer,
+                expected.1
+            );
+        };
+        // At 100% the ring is 1.5 document pixels wide around the brush edge
+        assert_radii(cursor_ring_radii(10.0, 1.0), (9.25, 10.75));
+        // Zooming out to 50% makes the ring thicker in document pixels, same on screen
+        assert_radii(cursor_ring_radii(10.0, 2.0), (8.5, 11.5));
+        // Zooming in to 400% keeps the radius, with a thinner ring
+        assert_radii(cursor_ring_radii(10.0, 0.25), (9.8125, 10.1875));
+        // Tiny brushes still get a visible ring
+        assert_radii(cursor_ring_radii(1.0, 4.0), (9.0, 15.0));
+        assert_radii(cursor_ring_radii(1.0, 0.25), (0.8125, 1.1875));
+    }
+}
@@ -16,46 +16,17 @@ impl ColorPicker {
             is_active: false,
         }
     }
-}
-
-impl Tool for ColorPicker {
-    fn on_pointer_click(
-        &mut self,
-        _: PointerEvent,
-        _: &mut EditorContext,
-    ) -> Option<Box<dyn EditorCommand>> {
-        self.is_active = true;
-        None
-    }
 
-    fn on_pointer_move(
-        &mut self,
-        pointer_motion: PointerEvent,
-        context: &mut EditorContext,
-    ) -> Option<Box<dyn EditorCommand>> {
-        if !self.is_active {
-            return None;
-        }
-        let position_into_canvas = context
+    // Sets the brush color to the color of the final image under the pointer
+    pub fn pick_color(&self, pointer_event: &PointerEvent, context: &mut EditorContext) {
+        let pixel_position = context
             .image_editor
-            .camera()
-            .ndc_into_world(pointer_motion.new_pointer_location_normalized);
-        let position_into_canvas = position_into_canvas.cast::<i32>().unwrap();
-        let half_document_size = (context.image_editor.document().document_size() / 2)
-            .cast::<i32>()
-            .unwrap();
-        let pixel_position = (position_into_canvas + half_document_size).cast::<u32>();
+            .document_pixel_position(pointer_event.new_pointer_location_normalized);
         if let Some(valid_position) = pixel_position {
-            if valid_position.x >= context.image_editor.document().document_size().x
-                || valid_position.y >= context.image_editor.document().document_size().y
-            {
-                return None;
-            }
-
             //TODO, FIXME: Final layer should not be flipped.
             let (x, y) = (
                 valid_position.x,
-                context.image_editor.document().document_size().y - valid_position.y,
+                context.image_editor.document().document_size().y - 1 - valid_position.y,
             );
 
             let final_texture_id = context.image_editor.document().render_result();
@@ -72,6 +43,28 @@ impl Tool for ColorPicker {
             settings.opacity = (pixel.a * 255.0) as u8;
             engine.set_new_settings(settings);
         }
+    }
+}
+
+impl Tool for ColorPicker {
+    fn on_pointer_click(
+        &mut self,
+        pointer_click: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        self.is_active = true;
+        self.pick_color(&pointer_click, context);
+        None
+    }
+
+    fn on_pointer_move(
+        &mut self,
+        pointer_motion: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        if self.is_active {
+            self.pick_color(&pointer_motion, context);
+        }
         None
     }
 
@@ -1,5 +1,823 @@
+use std::{cell::RefCell, collections::HashSet, fmt::Display};
+
+use cgmath::Vector2;
+use framework::RgbaTexture2D;
+use image_editor::{
+    document::{AlphaMode, DetachedLayer, Document, DocumentBackground, Palette},
+    layers::{ChunkDiff, ChunkedLayer, Layer, LayerId, LayerPosition, LayerSettings, LayerType},
+    DitherKind,
+};
+
 use super::EditorContext;
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandError {
+    // The layer was removed from the document after the command was recorded
+    MissingLayer(LayerId),
+    NotARasterLayer(LayerId),
+    MissingMask(LayerId),
+    // The quick mask was left, or entered again, after the command was recorded
+    MissingQuickMask,
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::MissingLayer(id) => write!(f, "The layer {id:?} is not in the document"),
+            CommandError::NotARasterLayer(id) => write!(f, "The layer {id:?} has no pixels"),
+            CommandError::MissingMask(id) => write!(f, "The layer {id:?} has no mask"),
+            CommandError::MissingQuickMask => write!(f, "The quick mask is not shown anymore"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
 pub trait EditorCommand {
-    fn undo(&self, context: &mut EditorContext) -> Box<dyn EditorCommand>;
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError>;
+}
+
+fn ensure_layer_exists(context: &EditorContext, layer: &LayerId) -> Result<(), CommandError> {
+    match context.image_editor.document().layer_position(layer) {
+        Some(_) => Ok(()),
+        None => Err(CommandError::MissingLayer(*layer)),
+    }
+}
+
+// Swaps the chunks of a raster layer with the ones stored in the diff
+pub struct LayerReplaceCommand {
+    chunk_diff: ChunkDiff,
+    modified_layer: LayerId,
+}
+impl LayerReplaceCommand {
+    pub fn new(modified_layer: LayerId, chunk_diff: ChunkDiff) -> Self {
+        Self {
+            chunk_diff,
+            modified_layer,
+        }
+    }
+}
+
+impl EditorCommand for LayerReplaceCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        ensure_layer_exists(context, &self.modified_layer)?;
+        let mut result = Err(CommandError::NotARasterLayer(self.modified_layer));
+        context.image_editor.mutate_document(|doc| {
+            doc.mutate_layer(&self.modified_layer, |lay| {
+                if let LayerType::Chonky(map) = &mut lay.layer_type {
+                    let inverted_diff = self.chunk_diff.apply_to_chunked_layer(map);
+                    result = Ok(Box::new(LayerReplaceCommand::new(
+                        self.modified_layer,
+                        inverted_diff,
+                    )) as Box<dyn EditorCommand>);
+                    lay.mark_dirty();
+                }
+            })
+        });
+        result
+    }
+}
+
+// Like LayerReplaceCommand, for the chunks of a layer mask
+pub struct LayerMaskReplaceCommand {
+    chunk_diff: ChunkDiff,
+    modified_layer: LayerId,
+}
+impl LayerMaskReplaceCommand {
+    pub fn new(modified_layer: LayerId, chunk_diff: ChunkDiff) -> Self {
+        Self {
+            chunk_diff,
+            modified_layer,
+        }
+    }
+}
+
+impl EditorCommand for LayerMaskReplaceCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        ensure_layer_exists(context, &self.modified_layer)?;
+        let mut result = Err(CommandError::MissingMask(self.modified_layer));
+        context.image_editor.mutate_document(|doc| {
+            doc.mutate_layer(&self.modified_layer, |lay| {
+                if let Some(mask) = lay.mask_mut() {
+                    let inverted_diff = self.chunk_diff.apply_to_chunked_layer(mask);
+                    result = Ok(Box::new(LayerMaskReplaceCommand::new(
+                        self.modified_layer,
+                        inverted_diff,
+                    )) as Box<dyn EditorCommand>);
+                    lay.mark_dirty();
+                }
+            })
+        });
+        result
+    }
+}
+
+// Like LayerReplaceCommand, for the chunks of the quick mask it was recorded on
+pub struct QuickMaskReplaceCommand {
+    chunk_diff: ChunkDiff,
+    quick_mask: LayerId,
+}
+impl QuickMaskReplaceCommand {
+    pub fn new(quick_mask: LayerId, chunk_diff: ChunkDiff) -> Self {
+        Self {
+            chunk_diff,
+            quick_mask,
+        }
+    }
+}
+
+impl EditorCommand for QuickMaskReplaceCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        let mut result = Err(CommandError::MissingQuickMask);
+        context
+            .image_editor
+            .mutate_document(|doc| match doc.quick_mask_mut() {
+                Some(mask) if mask.id() == &self.quick_mask => {
+                    if let LayerType::Chonky(map) = &mut mask.layer_type {
+                        let inverted_diff = self.chunk_diff.apply_to_chunked_layer(map);
+                        result = Ok(Box::new(QuickMaskReplaceCommand::new(
+                            self.quick_mask,
+                            inverted_diff,
+                        )) as Box<dyn EditorCommand>);
+                    }
+                }
+                _ => {}
+            });
+        result
+    }
+}
+
+// Writes back the pixels a layer had before being edited, e.g. by a filter
+pub struct LayerPixelsCommand {
+    pixels: RgbaTexture2D,
+    modified_layer: LayerId,
+}
+impl LayerPixelsCommand {
+    pub fn new(modified_layer: LayerId, pixels: RgbaTexture2D) -> Self {
+        Self {
+            pixels,
+            modified_layer,
+        }
+    }
+}
+
+impl EditorCommand for LayerPixelsCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        ensure_layer_exists(context, &self.modified_layer)?;
+        let mut result = Err(CommandError::NotARasterLayer(self.modified_layer));
+        context.image_editor.mutate_document(|doc| {
+            doc.mutate_layer(&self.modified_layer, |lay| {
+                if let Some(current_pixels) = lay.read_pixels(context.framework) {
+                    if lay.write_pixels(&self.pixels, context.framework) {
+                        result = Ok(Box::new(LayerPixelsCommand::new(
+                            self.modified_layer,
+                            current_pixels,
+                        )) as Box<dyn EditorCommand>);
+                    }
+                }
+            })
+        });
+        result
+    }
+}
+
+// Undoing removes the added layers from the document
+pub struct AddLayerCommand {
+    added_layers: Vec<LayerId>,
+}
+impl AddLayerCommand {
+    pub fn new(added_layers: Vec<LayerId>) -> Self {
+        Self { added_layers }
+    }
+}
+
+impl EditorCommand for AddLayerCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        for layer in &self.added_layers {
+            ensure_layer_exists(context, layer)?;
+        }
+        let mut deleted_layers = vec![];
+        context.image_editor.mutate_document(|doc| {
+            deleted_layers = self
+                .added_layers
+                .iter()
+                .map(|id| doc.delete_layer(*id, context.framework))
+                .collect();
+        });
+        Ok(Box::new(DeleteLayerCommand::new(deleted_layers)))
+    }
+}
+
+// Undoing puts the deleted layers back, with their pixels and stacking position
+pub struct DeleteLayerCommand {
+    // The layers are moved back into the document when undoing
+    deleted_layers: RefCell<Vec<DetachedLayer>>,
+}
+impl DeleteLayerCommand {
+    pub fn new(deleted_layers: Vec<DetachedLayer>) -> Self {
+        Self {
+            deleted_layers: RefCell::new(deleted_layers),
+        }
+    }
+}
+
+impl EditorCommand for DeleteLayerCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        let mut deleted_layers = Some(self.deleted_layers.take());
+        let mut restored_layers = vec![];
+        context.image_editor.mutate_document(|doc| {
+            // The positions were taken one deletion after the other, so they're restored backwards
+            for layer in deleted_layers.take().unwrap().into_iter().rev() {
+                restored_layers.push(*layer.id());
+                doc.restore_layer(layer, context.framework);
+            }
+        });
+        restored_layers.reverse();
+        Ok(Box::new(AddLayerCommand::new(restored_layers)))
+    }
+}
+
+// Undoing removes the added layers and puts the replaced ones back, like flattening does
+pub struct ReplaceLayersCommand {
+    added_layers: Vec<LayerId>,
+    // The layers are moved back into the document when undoing
+    replaced_layers: RefCell<Vec<DetachedLayer>>,
+}
+impl ReplaceLayersCommand {
+    pub fn new(added_layers: Vec<LayerId>, replaced_layers: Vec<DetachedLayer>) -> Self {
+        Self {
+            added_layers,
+            replaced_layers: RefCell::new(replaced_layers),
+        }
+    }
+}
+
+impl EditorCommand for ReplaceLayersCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        for layer in &self.added_layers {
+            ensure_layer_exists(context, layer)?;
+        }
+        let mut replaced_layers = Some(self.replaced_layers.take());
+        let mut removed_layers = vec![];
+        let mut restored_layers = vec![];
+        context.image_editor.mutate_document(|doc| {
+            removed_layers = self
+                .added_layers
+                .iter()
+                .map(|id| doc.delete_layer(*id, context.framework))
+                .collect();
+            for layer in replaced_layers.take().unwrap().into_iter().rev() {
+                restored_layers.push(*layer.id());
+                doc.restore_layer(layer, context.framework);
+            }
+        });
+        restored_layers.reverse();
+        Ok(Box::new(ReplaceLayersCommand::new(
+            restored_layers,
+            removed_layers,
+        )))
+    }
+}
+
+// Undoing moves the layer back to where it was
+pub struct ReorderLayerCommand {
+    moved_layer: LayerId,
+    previous_position: LayerPosition,
+}
+impl ReorderLayerCommand {
+    pub fn new(moved_layer: LayerId, previous_position: LayerPosition) -> Self {
+        Self {
+            moved_layer,
+            previous_position,
+        }
+    }
+}
+
+impl EditorCommand for ReorderLayerCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        let current_position = context
+            .image_editor
+            .document()
+            .layer_position(&self.moved_layer)
+            .ok_or(CommandError::MissingLayer(self.moved_layer))?;
+        context.image_editor.mutate_document(|doc| {
+            doc.move_layer(&self.moved_layer, self.previous_position);
+        });
+        Ok(Box::new(ReorderLayerCommand::new(
+            self.moved_layer,
+            current_position,
+        )))
+    }
+}
+
+// Undoing gives the layers back the settings they had, e.g. their opacity or color label
+pub struct LayerSettingsCommand {
+    previous_settings: Vec<(LayerId, LayerSettings)>,
+}
+impl LayerSettingsCommand {
+    pub fn new(previous_settings: Vec<(LayerId, LayerSettings)>) -> Self {
+        Self { previous_settings }
+    }
+}
+
+impl EditorCommand for LayerSettingsCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        for (layer, _) in &self.previous_settings {
+            ensure_layer_exists(context, layer)?;
+        }
+        let document = context.image_editor.document();
+        let current_settings = self
+            .previous_settings
+            .iter()
+            .map(|(layer, _)| (*layer, document.get_layer(layer).settings().clone()))
+            .collect();
+        context.image_editor.mutate_document(|doc| {
+            for (layer, settings) in &self.previous_settings {
+                doc.mutate_layer(layer, |layer| layer.set_settings(settings.clone()));
+            }
+        });
+        Ok(Box::new(LayerSettingsCommand::new(current_settings)))
+    }
+}
+
+// Undoing ungroups the layers and moves them back to where they were before being grouped
+pub struct GroupLayersCommand {
+    group: LayerId,
+    // From the topmost grouped layer
+    previous_positions: Vec<(LayerId, LayerPosition)>,
+}
+impl GroupLayersCommand {
+    pub fn new(group: LayerId, previous_positions: Vec<(LayerId, LayerPosition)>) -> Self {
+        Self {
+            group,
+            previous_positions,
+        }
+    }
+}
+
+impl EditorCommand for GroupLayersCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        let position = context
+            .image_editor
+            .document()
+            .layer_position(&self.group)
+            .ok_or(CommandError::MissingLayer(self.group))?;
+        for (layer, _) in &self.previous_positions {
+            ensure_layer_exists(context, layer)?;
+        }
+        let mut group = None;
+        context.image_editor.mutate_document(|doc| {
+            group = Some(doc.ungroup(self.group, context.framework));
+            doc.move_layers(&self.previous_positions);
+        });
+        let children = self.previous_positions.iter().map(|(id, _)| *id).collect();
+        Ok(Box::new(UngroupLayersCommand::new(
+            group.unwrap(),
+            position,
+            children,
+        )))
+    }
+}
+
+// Undoing groups the layers again, in the same group layer and in the same place
+pub struct UngroupLayersCommand {
+    // The group is moved back into the document when undoing
+    group: RefCell<Option<Layer>>,
+    position: LayerPosition,
+    children: Vec<LayerId>,
+}
+impl UngroupLayersCommand {
+    pub fn new(group: Layer, position: LayerPosition, children: Vec<LayerId>) -> Self {
+        Self {
+            group: RefCell::new(Some(group)),
+            position,
+            children,
+        }
+    }
+}
+
+impl EditorCommand for UngroupLayersCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        for layer in &self.children {
+            ensure_layer_exists(context, layer)?;
+        }
+        let document = context.image_editor.document();
+        let previous_positions = self
+            .children
+            .iter()
+            .map(|id| (*id, document.layer_position(id).unwrap()))
+            .collect();
+        let children: HashSet<LayerId> = self.children.iter().copied().collect();
+        let mut group = self.group.take();
+        let group_id = *group
+            .as_ref()
+            .expect("UngroupLayersCommand: the group was already restored")
+            .id();
+        context.image_editor.mutate_document(|doc| {
+            doc.restore_group(
+                &children,
+                group.take().unwrap(),
+                self.position,
+                context.framework,
+            );
+        });
+        Ok(Box::new(GroupLayersCommand::new(
+            group_id,
+            previous_positions,
+        )))
+    }
+}
+
+// Undoing swaps the mask of the layer with the one recorded, e.g. removes an added mask
+pub struct LayerMaskCommand {
+    layer: LayerId,
+    // The mask is moved into the layer when undoing
+    mask: RefCell<Option<ChunkedLayer>>,
+}
+impl LayerMaskCommand {
+    pub fn new(layer: LayerId, mask: Option<ChunkedLayer>) -> Self {
+        Self {
+            layer,
+            mask: RefCell::new(mask),
+        }
+    }
+}
+
+impl EditorCommand for LayerMaskCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        ensure_layer_exists(context, &self.layer)?;
+        let mut mask = Some(self.mask.take());
+        let mut previous_mask = None;
+        context.image_editor.mutate_document(|doc| {
+            doc.mutate_layer(&self.layer, |layer| {
+                previous_mask = layer.replace_mask(mask.take().unwrap());
+            })
+        });
+        Ok(Box::new(LayerMaskCommand::new(self.layer, previous_mask)))
+    }
+}
+
+// Undoing puts back the document settings recorded when the command was created
+pub struct DocumentSettingsCommand {
+    background: DocumentBackground,
+    export_background: bool,
+    export_alpha_mode: AlphaMode,
+    dpi: f32,
+    gif_dither: DitherKind,
+    linear_blending: bool,
+    palette: Option<Palette>,
+}
+impl DocumentSettingsCommand {
+    pub fn new(document: &Document) -> Self {
+        Self {
+            background: document.background(),
+            export_background: document.export_background(),
+            export_alpha_mode: document.export_alpha_mode(),
+            dpi: document.dpi(),
+            gif_dither: document.gif_dither(),
+            linear_blending: document.linear_blending(),
+            palette: document.palette_mode().cloned(),
+        }
+    }
+}
+
+impl EditorCommand for DocumentSettingsCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        let current = DocumentSettingsCommand::new(context.image_editor.document());
+        context.image_editor.mutate_document(|doc| {
+            doc.set_background(self.background);
+            doc.set_export_background(self.export_background);
+            doc.set_export_alpha_mode(self.export_alpha_mode);
+            doc.set_dpi(self.dpi);
+            doc.set_gif_dither(self.gif_dither);
+            doc.set_linear_blending(self.linear_blending);
+            doc.set_palette_mode(self.palette.clone());
+        });
+        Ok(Box::new(current))
+    }
+}
+
+// Undoing moves the layer back by the same amount
+pub struct TranslateLayerCommand {
+    moved_layer: LayerId,
+    delta: Vector2<f32>,
+}
+impl TranslateLayerCommand {
+    pub fn new(moved_layer: LayerId, delta: Vector2<f32>) -> Self {
+        Self { moved_layer, delta }
+    }
+}
+
+impl EditorCommand for TranslateLayerCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        ensure_layer_exists(context, &self.moved_layer)?;
+        context.image_editor.mutate_document(|doc| {
+            doc.mutate_layer(&self.moved_layer, |layer| layer.translate(-self.delta));
+        });
+        Ok(Box::new(TranslateLayerCommand::new(
+            self.moved_layer,
+            -self.delta,
+        )))
+    }
+}
+
+// Undoing moves the floating selection back, or the selection shapes when nothing is floating
+pub struct TranslateSelectionCommand {
+    delta: Vector2<f32>,
+}
+impl TranslateSelectionCommand {
+    pub fn new(delta: Vector2<f32>) -> Self {
+        Self { delta }
+    }
+}
+
+impl EditorCommand for TranslateSelectionCommand {
+    fn undo(&self, context: &mut EditorContext) -> Result<Box<dyn EditorCommand>, CommandError> {
+        context.image_editor.mutate_document(|doc| {
+            if let Some(selection) = doc.selection_layer_mut() {
+                selection.layer.translate(-self.delta);
+            } else {
+                doc.mutate_selection(|sel| sel.translate(-self.delta));
+            }
+        });
+        Ok(Box::new(TranslateSelectionCommand::new(-self.delta)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::vec2;
+    use framework::{renderer::renderer::Renderer, Framework, RgbaTexture2D, RgbaU8, Texture};
+    use image_editor::{
+        headless_framework,
+        layers::{ChunkDiff, LayerColorLabel, LayerSettings, LayerType},
+        test_support::background_layer_id,
+        ImageEditor, LayerConstructionInfo,
+    };
+
+    use crate::tools::EditorContext;
+
+    use super::{
+        CommandError, DeleteLayerCommand, DocumentSettingsCommand, EditorCommand,
+        GroupLayersCommand, LayerMaskCommand, LayerMaskReplaceCommand, LayerPixelsCommand,
+        LayerReplaceCommand, LayerSettingsCommand, TranslateLayerCommand,
+    };
+
+    fn layer_pixels(image_editor: &ImageEditor, framework: &Framework) -> Vec<u8> {
+        let layer = image_editor.selected_layer();
+        match &layer.layer_type {
+            LayerType::Image { texture, .. } => {
+                let texture = framework.texture2d_read_data(texture).unwrap();
+                bytemuck::cast_slice(texture.data().unwrap()).to_vec()
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn undo_layer_deletion() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let background_layer = background_layer_id(image_editor.document());
+        image_editor.select_new_layer(background_layer);
+        let original_pixels = layer_pixels(&image_editor, &framework);
+        let original_items = image_editor.document().tree().items().clone();
+
+        let mut context = EditorContext {
+            framework: &mut framework,
+            image_editor: &mut image_editor,
+            renderer: &mut renderer,
+        };
+        let deleted = context
+            .image_editor
+            .delete_layer(background_layer, context.framework);
+        assert_eq!(context.image_editor.document().tree().items().len(), 1);
+
+        let redo = DeleteLayerCommand::new(vec![deleted])
+            .undo(&mut context)
+            .unwrap();
+        assert_eq!(
+            context.image_editor.document().tree().items(),
+            &original_items
+        );
+        assert_eq!(
+            context.image_editor.document().current_layer_index(),
+            Some(&background_layer)
+        );
+        assert_eq!(
+            layer_pixels(context.image_editor, context.framework),
+            original_pixels
+        );
+
+        redo.undo(&mut context).unwrap();
+        assert_eq!(context.image_editor.document().tree().items().len(), 1);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn undoing_needs_the_edited_layer() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let background_layer = background_layer_id(image_editor.document());
+        let mut context = EditorContext {
+            framework: &mut framework,
+            image_editor: &mut image_editor,
+            renderer: &mut renderer,
+        };
+
+        // The background is an image layer without a mask
+        let replace = LayerReplaceCommand::new(background_layer, ChunkDiff::new());
+        assert_eq!(
+            replace.undo(&mut context).err(),
+            Some(CommandError::NotARasterLayer(background_layer))
+        );
+        let replace_mask = LayerMaskReplaceCommand::new(background_layer, ChunkDiff::new());
+        assert_eq!(
+            replace_mask.undo(&mut context).err(),
+            Some(CommandError::MissingMask(background_layer))
+        );
+
+        context
+            .image_editor
+            .delete_layer(background_layer, context.framework);
+        let translate = TranslateLayerCommand::new(background_layer, vec2(1.0, 0.0));
+        assert_eq!(
+            translate.undo(&mut context).err(),
+            Some(CommandError::MissingLayer(background_layer))
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn undo_layer_settings() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[64.0, 64.0]);
+        let background_layer = background_layer_id(image_editor.document());
+        let original = image_editor
+            .document()
+            .get_layer(&background_layer)
+            .settings()
+            .clone();
+        image_editor.mutate_document(|doc| {
+            doc.mutate_layer(&background_layer, |layer| {
+                layer.set_settings(LayerSettings {
+                    opacity: 0.25,
+                    label: Some(LayerColorLabel::Red),
+                    ..original.clone()
+                })
+            })
+        });
+
+        let mut context = EditorContext {
+            framework: &mut framework,
+            image_editor: &mut image_editor,
+            renderer: &mut renderer,
+        };
+        let settings = |context: &EditorContext| {
+            context
+                .image_editor
+                .document()
+                .get_layer(&background_layer)
+                .settings()
+                .clone()
+        };
+        let command = LayerSettingsCommand::new(vec![(background_layer, original.clone())]);
+        let redo = command.undo(&mut context).unwrap();
+        assert!(settings(&context) == original);
+        redo.undo(&mut context).unwrap();
+        assert_eq!(settings(&context).opacity, 0.25);
+        assert_eq!(settings(&context).label, Some(LayerColorLabel::Red));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn undo_grouping_masks_and_document_settings() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[64.0, 64.0]);
+        let [below, _, above] = ["below", "between", "above"].map(|name| {
+            image_editor.add_layer_to_document(
+                LayerConstructionInfo {
+                    name: name.to_owned(),
+                    ..Default::default()
+                },
+                &mut framework,
+            )
+        });
+        let original_items = image_editor.document().tree().items().clone();
+        let original_dpi = image_editor.document().dpi();
+        let positions: Vec<_> = [above, below]
+            .iter()
+            .map(|id| (*id, image_editor.document().layer_position(id).unwrap()))
+            .collect();
+
+        let mut group = None;
+        image_editor.mutate_document(|doc| {
+            doc.select_layer(above);
+            doc.toggle_layer_selection(below);
+            group = doc.group_selected(&mut framework);
+        });
+        let group = group.unwrap();
+        let grouped_items = image_editor.document().tree().items().clone();
+        image_editor.mutate_document(|doc| doc.mutate_layer(&below, |layer| layer.add_mask()));
+        let settings = DocumentSettingsCommand::new(image_editor.document());
+        image_editor.mutate_document(|doc| doc.set_dpi(300.0));
+
+        let mut context = EditorContext {
+            framework: &mut framework,
+            image_editor: &mut image_editor,
+            renderer: &mut renderer,
+        };
+        let redo_settings = settings.undo(&mut context).unwrap();
+        assert_eq!(context.image_editor.document().dpi(), original_dpi);
+        redo_settings.undo(&mut context).unwrap();
+        assert_eq!(context.image_editor.document().dpi(), 300.0);
+
+        let has_mask = |context: &EditorContext| {
+            let document = context.image_editor.document();
+            document.get_layer(&below).mask().is_some()
+        };
+        let redo_mask = LayerMaskCommand::new(below, None)
+            .undo(&mut context)
+            .unwrap();
+        assert!(!has_mask(&context));
+        redo_mask.undo(&mut context).unwrap();
+        assert!(has_mask(&context));
+
+        let regroup = GroupLayersCommand::new(group, positions)
+            .undo(&mut context)
+            .unwrap();
+        assert_eq!(
+            context.image_editor.document().tree().items(),
+            &original_items
+        );
+        // The group is put back with the same id
+        let ungroup = regroup.undo(&mut context).unwrap();
+        assert_eq!(
+            context.image_editor.document().tree().items(),
+            &grouped_items
+        );
+        ungroup.undo(&mut context).unwrap();
+        assert_eq!(
+            context.image_editor.document().tree().items(),
+            &original_items
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn undo_a_blur() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let background_layer = background_layer_id(image_editor.document());
+        image_editor.select_new_layer(background_layer);
+        let pixels = image_editor
+            .selected_layer()
+            .read_pixels(&framework)
+            .unwrap();
+        let (width, height) = (pixels.width(), pixels.height());
+        // Vertical stripes, so that the blur changes the pixels
+        let stripes = (0..width * height)
+            .map(|i| RgbaU8([if i % 2 == 0 { 255 } else { 0 }, 0, 0, 255]))
+            .collect();
+        let stripes = RgbaTexture2D::from_texels(stripes, (width, height)).unwrap();
+        image_editor.mutate_document(|doc| {
+            doc.mutate_layer(&background_layer, |layer| {
+                assert!(layer.write_pixels(&stripes, &mut framework))
+            })
+        });
+        let original_pixels = layer_pixels(&image_editor, &framework);
+
+        let mut previous = None;
+        image_editor.mutate_document(|doc| {
+            previous = doc
+                .apply_gaussian_blur(&background_layer, 2.0, None, None, &mut framework)
+                .unwrap();
+        });
+        let blurred_pixels = layer_pixels(&image_editor, &framework);
+        assert_ne!(blurred_pixels, original_pixels);
+
+        let mut context = EditorContext {
+            framework: &mut framework,
+            image_editor: &mut image_editor,
+            renderer: &mut renderer,
+        };
+        let redo = LayerPixelsCommand::new(background_layer, previous.unwrap())
+            .undo(&mut context)
+            .unwrap();
+        assert_eq!(
+            layer_pixels(context.image_editor, context.framework),
+            original_pixels
+        );
+        redo.undo(&mut context).unwrap();
+        assert_eq!(
+            layer_pixels(context.image_editor, context.framework),
+            blurred_pixels
+        );
+    }
 }
@@ -0,0 +1,248 @@
+use std::{cell::RefCell, rc::Rc};
+
+use application::InputState;
+use cgmath::Point2;
+use framework::renderer::renderer::Renderer;
+use image_editor::ImageEditor;
+
+use crate::stamping_engine::{StampConfiguration, StrokingEngine};
+
+use super::{
+    BrushTool, ColorPicker, DynamicToolUi, EditorCommand, EditorContext, PointerEvent, Tool,
+    MAX_BRUSH_SIZE, MIN_BRUSH_SIZE,
+};
+
+// Strokes like the brush with the stamping engine in eraser mode. The engine is shared
+// with the brush, so the eraser settings are swapped in while the eraser is selected
+pub struct EraserTool {
+    // Has its own size, separate from the one of the brush tool
+    pub brush: BrushTool,
+    engine: Rc<RefCell<StrokingEngine>>,
+    // The eraser settings while another tool is selected, the brush ones while the eraser is
+    swapped_settings: StampConfiguration,
+}
+
+impl EraserTool {
+    pub fn new(
+        engine: Rc<RefCell<StrokingEngine>>,
+        eyedropper: Rc<RefCell<ColorPicker>>,
+        step: f32,
+    ) -> Self {
+        let swapped_settings = StampConfiguration {
+            is_eraser: true,
+            ..engine.borrow().settings()
+        };
+        Self {
+            brush: BrushTool::new(engine.clone(), eyedropper, step),
+            engine,
+            swapped_settings,
+        }
+    }
+
+    // [ makes the eraser smaller, ] makes it bigger
+    pub fn update_size_from_keys(&mut self, input_state: &InputState) {
+        self.brush.update_size_from_keys(input_state);
+    }
+
+    fn swap_settings(&mut self) {
+        let mut engine = self.engine.borrow_mut();
+        let current = engine.settings();
+        engine.set_new_settings(swap_stamp_settings(current, &mut self.swapped_settings));
+    }
+}
+
+// Stores the current settings in swapped and returns the ones to use in their place.
+// The brush color is kept while erasing, so that it's still the foreground color
+fn swap_stamp_settings(
+    current: StampConfiguration,
+    swapped: &mut StampConfiguration,
+) -> StampConfiguration {
+    let incoming = StampConfiguration {
+        color_srgb: current.color_srgb,
+        ..*swapped
+    };
+    *swapped = current;
+    incoming
+}
+
+impl Tool for EraserTool {
+    fn on_selected(&mut self, _context: &mut EditorContext) -> Option<Box<dyn EditorCommand>> {
+        self.swap_settings();
+        None
+    }
+
+    fn on_deselected(&mut self, _context: &mut EditorContext) -> Option<Box<dyn EditorCommand>> {
+        self.swap_settings();
+        None
+    }
+
+    fn on_pointer_click(
+        &mut self,
+        pointer_click: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        self.brush.on_pointer_click(pointer_click, context)
+    }
+
+    fn on_pointer_move(
+        &mut self,
+        pointer_motion: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        self.brush.on_pointer_move(pointer_motion, context)
+    }
+
+    fn on_pointer_release(
+        &mut self,
+        pointer_release: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        self.brush.on_pointer_release(pointer_release, context)
+    }
+
+    fn draw_cursor(
+        &self,
+        renderer: &mut Renderer,
+        cursor_position: Point2<f32>,
+        image_editor: &ImageEditor,
+    ) {
+        self.brush
+            .draw_cursor(renderer, cursor_position, image_editor);
+    }
+
+    fn ui(&mut self, ui: &mut dyn DynamicToolUi, _context: &mut EditorContext) {
+        self.brush.size = ui.value_float_ranged(
+            "Eraser size",
+            self.brush.size,
+            MIN_BRUSH_SIZE..=MAX_BRUSH_SIZE,
+        );
+        self.brush.pressure_delta =
+            ui.value_float_ranged("Pressure delta", self.brush.pressure_delta, 0.0..=1000.0);
+    }
+
+    fn name(&self) -> &'static str {
+        "Eraser tool"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use framework::renderer::renderer::Renderer;
+    use image_editor::{headless_framework, ImageEditor};
+
+    use crate::{
+        stamping_engine::{StampConfiguration, StrokingEngine},
+        toolbox::{ToolKind, Toolbox},
+        tools::{BrushTool, ColorPicker, EditorContext},
+    };
+
+    use super::{swap_stamp_settings, EraserTool};
+
+    #[test]
+    fn swapping_twice_restores_the_settings_but_the_color() {
+        let brush = StampConfiguration {
+            color_srgb: [200, 10, 10],
+            softness: 0.5,
+            ..Default::default()
+        };
+        let mut swapped = StampConfiguration {
+            is_eraser: true,
+            softness: 3.0,
+            ..Default::default()
+        };
+
+        let eraser = swap_stamp_settings(brush, &mut swapped);
+        assert!(eraser.is_eraser);
+        assert_eq!(eraser.softness, 3.0);
+        assert_eq!(eraser.color_srgb, [200, 10, 10]);
+        assert_eq!(swapped, brush);
+
+        // The color picked while erasing is kept by the brush
+        let picked = StampConfiguration {
+            color_srgb: [0, 0, 255],
+            ..eraser
+        };
+        let restored = swap_stamp_settings(picked, &mut swapped);
+        assert_eq!(
+            restored,
+            StampConfiguration {
+                color_srgb: [0, 0, 255],
+                ..brush
+            }
+        );
+        assert_eq!(swapped, picked);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn brush_and_eraser_keep_their_own_settings() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[64.0, 64.0]);
+
+        let test_stamp = Toolbox::create_test_stamp(&mut framework);
+        let stamping_engine = Rc::new(RefCell::new(StrokingEngine::new(
+            test_stamp,
+            &mut framework,
+        )));
+        let color_picker = Rc::new(RefCell::new(ColorPicker::new(stamping_engine.clone())));
+        let brush_tool = Rc::new(RefCell::new(BrushTool::new(
+            stamping_engine.clone(),
+            color_picker.clone(),
+            1.0,
+        )));
+        let eraser_tool = Rc::new(RefCell::new(EraserTool::new(
+            stamping_engine.clone(),
+            color_picker,
+            1.0,
+        )));
+        let (mut toolbox, _) = Toolbox::new(ToolKind::Brush, brush_tool.clone());
+        toolbox.add_tool(ToolKind::Eraser, eraser_tool.clone());
+
+        brush_tool.borrow_mut().size = 12.0;
+        eraser_tool.borrow_mut().brush.size = 40.0;
+        let mut brush_settings = stamping_engine.borrow().settings();
+        brush_settings.softness = 0.5;
+        brush_settings.color_srgb = [200, 10, 10];
+        stamping_engine
+            .borrow_mut()
+            .set_new_settings(brush_settings);
+
+        let mut select = |kind: ToolKind| {
+            toolbox.select_by_kind(
+                kind,
+                EditorContext {
+                    framework: &mut framework,
+                    image_editor: &mut image_editor,
+                    renderer: &mut renderer,
+                },
+            );
+        };
+        select(ToolKind::Eraser);
+        let eraser_settings = stamping_engine.borrow().settings();
+        assert!(eraser_settings.is_eraser);
+        assert_eq!(eraser_settings.color_srgb, [200, 10, 10]);
+        let mut changed_eraser = eraser_settings;
+        changed_eraser.softness = 3.0;
+        stamping_engine
+            .borrow_mut()
+            .set_new_settings(changed_eraser);
+        eraser_tool.borrow_mut().brush.size = 60.0;
+
+        select(ToolKind::Brush);
+        assert_eq!(stamping_engine.borrow().settings(), brush_settings);
+        assert_eq!(brush_tool.borrow().size, 12.0);
+        brush_tool.borrow_mut().size = 8.0;
+
+        select(ToolKind::Eraser);
+        assert_eq!(stamping_engine.borrow().settings(), changed_eraser);
+        assert_eq!(eraser_tool.borrow().brush.size, 60.0);
+        assert_eq!(brush_tool.borrow().size, 8.0);
+
+        select(ToolKind::Brush);
+        assert!(!stamping_engine.borrow().settings().is_eraser);
+        assert_eq!(stamping_engine.borrow().settings().softness, 0.5);
+    }
+}
@@ -1,11 +1,26 @@
+use std::time::Instant;
+
 use crate::tools::{EditorContext, PointerEvent};
-use cgmath::{point2, InnerSpace, Point2};
+use cgmath::{point2, vec2, InnerSpace, Point2, Vector2};
+
+use super::{tool::Tool, DynamicToolUi, EditorCommand};
 
-use super::{tool::Tool, EditorCommand};
+// Below this speed (in pixels per second) the inertia stops
+const MIN_INERTIA_SPEED: f32 = 1.0;
+// If the pointer stood still for longer than this before being released, there's no inertia
+const MAX_RELEASE_DELAY_SECONDS: f32 = 0.1;
 
 pub struct HandTool {
     is_active: bool,
     last_frame_position: Point2<f32>,
+    last_move_time: Instant,
+    last_update_time: Instant,
+    // In pixels per second
+    velocity: Vector2<f32>,
+
+    pub inertia_enabled: bool,
+    // The fraction of the velocity lost every second
+    pub friction: f32,
 }
 
 impl HandTool {
@@ -13,10 +28,25 @@ impl HandTool {
         Self {
             is_active: false,
             last_frame_position: point2(0.0, 0.0),
+            last_move_time: Instant::now(),
+            last_update_time: Instant::now(),
+            velocity: vec2(0.0, 0.0),
+            inertia_enabled: true,
+            friction: 0.95,
         }
     }
 }
 
+// Slows down the velocity after delta_seconds, stopping it once it's negligible
+fn decay_velocity(velocity: Vector2<f32>, friction: f32, delta_seconds: f32) -> Vector2<f32> {
+    let velocity = velocity * (1.0 - friction.clamp(0.0, 1.0)).powf(delta_seconds);
+    if velocity.magnitude() < MIN_INERTIA_SPEED {
+        vec2(0.0, 0.0)
+    } else {
+        velocity
+    }
+}
+
 impl Tool for HandTool {
     fn on_pointer_click(
         &mut self,
@@ -25,6 +55,8 @@ impl Tool for HandTool {
     ) -> Option<Box<dyn EditorCommand>> {
         self.is_active = true;
         self.last_frame_position = pointer_event.new_pointer_location;
+        self.last_move_time = Instant::now();
+        self.velocity = vec2(0.0, 0.0);
         None
     }
 
@@ -40,6 +72,12 @@ impl Tool for HandTool {
         let delta = new_position - self.last_frame_position;
         if delta.magnitude2() > 0.5 {
             context.image_editor.pan_camera(delta);
+            let now = Instant::now();
+            let elapsed = (now - self.last_move_time).as_secs_f32();
+            if elapsed > 0.0 {
+                self.velocity = delta / elapsed;
+            }
+            self.last_move_time = now;
         }
         self.last_frame_position = new_position;
         None
@@ -51,9 +89,61 @@ impl Tool for HandTool {
         _context: &mut EditorContext,
     ) -> Option<Box<dyn EditorCommand>> {
         self.is_active = false;
+        let still_for = self.last_move_time.elapsed().as_secs_f32();
+        if !self.inertia_enabled || still_for > MAX_RELEASE_DELAY_SECONDS {
+            self.velocity = vec2(0.0, 0.0);
+        }
         None
     }
+
+    fn update(&mut self, context: &mut EditorContext) {
+        let now = Instant::now();
+        let delta_seconds = (now - self.last_update_time).as_secs_f32();
+        self.last_update_time = now;
+        if self.is_active || self.velocity.magnitude2() == 0.0 {
+            return;
+        }
+        context
+            .image_editor
+            .pan_camera(self.velocity * delta_seconds);
+        self.velocity = decay_velocity(self.velocity, self.friction, delta_seconds);
+    }
+
+    fn ui(&mut self, ui: &mut dyn DynamicToolUi, _context: &mut EditorContext) {
+        self.inertia_enabled = ui.checkbox("Inertia", self.inertia_enabled);
+        if self.inertia_enabled {
+            self.friction = ui.value_float_ranged("Friction", self.friction, 0.0..=1.0);
+        }
+    }
+
     fn name(&self) -> &'static str {
         "Hand tool"
     }
 }
+
+#[cfg(test)]
+mod test {
+    use cgmath::{vec2, InnerSpace};
+
+    use super::{decay_velocity, MIN_INERTIA_SPEED};
+
+    #[test]
+    fn velocity_decays_to_zero() {
+        let mut velocity = vec2(3000.0, -1500.0);
+        let mut steps = 0;
+        while velocity.magnitude() > 0.0 {
+            let new_velocity = decay_velocity(velocity, 0.95, 1.0 / 60.0);
+            assert!(new_velocity.magnitude() < velocity.magnitude());
+            velocity = new_velocity;
+            steps += 1;
+            // About 3 seconds at 60 fps
+            assert!(steps < 200);
+        }
+    }
+
+    #[test]
+    fn slow_velocity_stops_immediately() {
+        let velocity = vec2(MIN_INERTIA_SPEED * 0.5, 0.0);
+        assert_eq!(decay_velocity(velocity, 0.0, 1.0 / 60.0), vec2(0.0, 0.0));
+    }
+}
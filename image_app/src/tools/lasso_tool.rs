@@ -0,0 +1,122 @@
+use cgmath::{MetricSpace, Point2};
+use image_editor::selection::{snap_to_edge, EdgeMap, Selection, SelectionAddition};
+
+use super::{dynamic_tool_ui_helpers, tool::Tool, EditorCommand, EditorContext, PointerEvent};
+
+// Points closer than this to the last recorded one are skipped, in pixels
+const MIN_POINT_DISTANCE: f32 = 1.0;
+
+pub struct LassoTool {
+    is_active: bool,
+    path: Vec<Point2<f32>>,
+    selection_addition: SelectionAddition,
+    magnetic: bool,
+    snap_radius: f32,
+    // The edges of the selected layer, computed when a magnetic path begins
+    edge_map: Option<EdgeMap>,
+}
+
+impl LassoTool {
+    pub fn new() -> Self {
+        Self {
+            is_active: false,
+            path: vec![],
+            selection_addition: SelectionAddition::Add,
+            magnetic: false,
+            snap_radius: 8.0,
+            edge_map: None,
+        }
+    }
+
+    fn record_point(&mut self, event: PointerEvent, context: &mut EditorContext) {
+        let point = match context
+            .image_editor
+            .transform_point_into_pixel_position(event.new_pointer_location_normalized)
+        {
+            Some(point) => point,
+            None => return,
+        };
+        let point = match &self.edge_map {
+            Some(edge_map) => snap_to_edge(point, edge_map, self.snap_radius),
+            None => point,
+        };
+        match self.path.last() {
+            Some(last) if last.distance(point) < MIN_POINT_DISTANCE => {}
+            _ => self.path.push(point),
+        }
+    }
+}
+
+impl Tool for LassoTool {
+    fn on_pointer_click(
+        &mut self,
+        event: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        self.is_active = true;
+        self.path.clear();
+        self.edge_map = if self.magnetic {
+            EdgeMap::from_layer(context.image_editor.selected_layer(), context.framework)
+        } else {
+            None
+        };
+        self.record_point(event, context);
+        None
+    }
+
+    fn on_pointer_move(
+        &mut self,
+        event: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        if !self.is_active {
+            return None;
+        }
+        self.record_point(event, context);
+
+        // The path is shown closed while dragging
+        let partial = Selection::from_path(&self.path, self.selection_addition);
+        context.image_editor.mutate_document(|doc| {
+            doc.mutate_partial_selection(|selection| *selection = partial.clone())
+        });
+        None
+    }
+
+    fn on_pointer_release(
+        &mut self,
+        event: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        if !self.is_active {
+            return None;
+        }
+        self.is_active = false;
+        self.record_point(event, context);
+
+        let lasso = Selection::from_path(&self.path, self.selection_addition);
+        self.path.clear();
+        self.edge_map = None;
+        context.image_editor.mutate_document(|doc| {
+            doc.mutate_partial_selection(|selection| selection.clear());
+            doc.mutate_selection(|selection| {
+                for shape in lasso.shapes() {
+                    selection.extend(shape.clone());
+                }
+            });
+        });
+        None
+    }
+
+    fn ui(&mut self, ui: &mut dyn super::DynamicToolUi, _: &mut EditorContext) {
+        self.selection_addition =
+            dynamic_tool_ui_helpers::dropdown(ui, "Selection mode", self.selection_addition);
+        self.magnetic = ui.checkbox("Snap to edges", self.magnetic);
+        if self.magnetic {
+            self.snap_radius = ui.value_float_ranged("Snap radius", self.snap_radius, 1.0..=64.0);
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "Lasso tool"
+    }
+}
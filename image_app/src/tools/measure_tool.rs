@@ -0,0 +1,180 @@
+use cgmath::{vec2, InnerSpace, Point2};
+use framework::renderer::{
+    draw_command::{DrawCommand, DrawMode, OptionalDrawData, PrimitiveType},
+    renderer::Renderer,
+};
+use image_editor::document::StraightenAxis;
+
+use super::{tool::Tool, EditorCommand, EditorContext, PointerEvent};
+
+// In document pixels
+const LINE_WIDTH: f32 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    pub distance: f32,
+    // Counterclockwise from the x axis, in (-180, 180]: the y axis of the document goes up
+    pub angle_degrees: f32,
+}
+
+pub fn measure(start: Point2<f32>, end: Point2<f32>) -> Measurement {
+    let delta = end - start;
+    Measurement {
+        distance: delta.magnitude(),
+        angle_degrees: delta.y.atan2(delta.x).to_degrees(),
+    }
+}
+
+// Dragging measures the distance and the angle between the click and the pointer,
+// the last measured line stays on the canvas until the next click
+pub struct MeasureTool {
+    is_active: bool,
+    line: Option<(Point2<f32>, Point2<f32>)>,
+}
+
+impl MeasureTool {
+    pub fn new() -> Self {
+        Self {
+            is_active: false,
+            line: None,
+        }
+    }
+
+    pub fn measurement(&self) -> Option<Measurement> {
+        self.line.map(|(start, end)| measure(start, end))
+    }
+
+    // Rotates the current layer so that the measured line becomes parallel to the axis
+    fn straighten(&mut self, axis: StraightenAxis, context: &mut EditorContext) {
+        let (start, end) = match self.line.take() {
+            Some(line) => line,
+            None => return,
+        };
+        context.image_editor.mutate_document(|doc| {
+            if let Some(layer) = doc.current_layer_index().copied() {
+                doc.straighten(&layer, start, end, axis);
+            }
+        });
+    }
+
+    fn move_end(&mut self, event: PointerEvent, context: &mut EditorContext) {
+        let point = context
+            .image_editor
+            .transform_point_into_pixel_position(event.new_pointer_location_normalized);
+        if let (Some((_, end)), Some(point)) = (&mut self.line, point) {
+            *end = point;
+            context.image_editor.mark_dirty();
+        }
+    }
+}
+
+impl Tool for MeasureTool {
+    fn on_deselected(&mut self, context: &mut EditorContext) -> Option<Box<dyn EditorCommand>> {
+        self.is_active = false;
+        self.line = None;
+        context.image_editor.mark_dirty();
+        None
+    }
+
+    fn on_pointer_click(
+        &mut self,
+        event: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        let point = context
+            .image_editor
+            .transform_point_into_pixel_position(event.new_pointer_location_normalized)?;
+        self.is_active = true;
+        self.line = Some((point, point));
+        context.image_editor.mark_dirty();
+        None
+    }
+
+    fn on_pointer_move(
+        &mut self,
+        event: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        if self.is_active {
+            self.move_end(event, context);
+        }
+        None
+    }
+
+    fn on_pointer_release(
+        &mut self,
+        event: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        if self.is_active {
+            self.move_end(event, context);
+            self.is_active = false;
+        }
+        None
+    }
+
+    fn draw(&self, renderer: &mut Renderer) {
+        let (start, end) = match self.line {
+            Some(line) => line,
+            None => return,
+        };
+        let direction = end - start;
+        if direction.magnitude2() == 0.0 {
+            return;
+        }
+        let normal = direction.normalize() * LINE_WIDTH * 0.5;
+        let normal = vec2(-normal.y, normal.x);
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Polygon {
+                points: vec![start + normal, end + normal, end - normal, start - normal],
+                multiply_color: wgpu::Color::BLACK,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData::default(),
+        });
+    }
+
+    fn ui(&mut self, ui: &mut dyn super::DynamicToolUi, context: &mut EditorContext) {
+        match self.measurement() {
+            Some(measurement) => {
+                ui.label(&format!("Distance: {:.2} px", measurement.distance));
+                ui.label(&format!("Angle: {:.2}°", measurement.angle_degrees));
+                if ui.button("Straighten horizontally") {
+                    self.straighten(StraightenAxis::Horizontal, context);
+                }
+                if ui.button("Straighten vertically") {
+                    self.straighten(StraightenAxis::Vertical, context);
+                }
+                if ui.button("Clear") {
+                    self.line = None;
+                    context.image_editor.mark_dirty();
+                }
+            }
+            None => ui.label("Drag on the canvas to measure, or along a line to straighten"),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "Measure tool"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::point2;
+
+    use super::measure;
+
+    #[test]
+    fn distance_and_angle_of_a_known_vector() {
+        let measurement = measure(point2(1.0, 2.0), point2(4.0, 6.0));
+        assert_eq!(measurement.distance, 5.0);
+        assert!((measurement.angle_degrees - 53.13).abs() < 0.01);
+
+        // The angle is signed, pointing down is negative
+        let measurement = measure(point2(0.0, 0.0), point2(-3.0, -4.0));
+        assert_eq!(measurement.distance, 5.0);
+        assert!((measurement.angle_degrees + 126.87).abs() < 0.01);
+        assert_eq!(measure(point2(2.0, 2.0), point2(2.0, 2.0)).distance, 0.0);
+    }
+}
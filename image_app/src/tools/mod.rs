@@ -1,19 +1,31 @@
 pub mod brush_engine;
+mod brush_preset;
 mod brush_tool;
 mod color_picker;
 mod command;
 mod debug_select_region_tool;
+mod eraser_tool;
 mod hand_tool;
+mod lasso_tool;
+mod measure_tool;
+mod polygon_select_tool;
 mod rect_selection_tool;
+mod screen_color_picker;
 mod tool;
 mod transform_layer_tool;
 
 pub use brush_engine::*;
-pub use brush_tool::BrushTool;
+pub use brush_preset::{brush_presets_directory, BrushPreset};
+pub use brush_tool::{BrushTool, MAX_BRUSH_SIZE, MIN_BRUSH_SIZE};
 pub use color_picker::*;
 pub use command::*;
 pub use debug_select_region_tool::*;
+pub use eraser_tool::EraserTool;
 pub use hand_tool::HandTool;
+pub use lasso_tool::LassoTool;
+pub use measure_tool::MeasureTool;
+pub use polygon_select_tool::PolygonSelectTool;
 pub use rect_selection_tool::*;
+pub use screen_color_picker::ScreenColorPicker;
 pub use tool::*;
 pub use transform_layer_tool::TransformLayerTool;
@@ -0,0 +1,239 @@
+use std::time::{Duration, Instant};
+
+use application::{key::Key, InputState};
+use cgmath::{InnerSpace, Point2};
+use framework::renderer::{
+    draw_command::{DrawCommand, DrawMode, OptionalDrawData, PrimitiveType},
+    renderer::Renderer,
+};
+use image_editor::selection::{Selection, SelectionAddition};
+
+use super::{dynamic_tool_ui_helpers, tool::Tool, EditorCommand, EditorContext, PointerEvent};
+
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+
+// In document pixels
+const EDGE_WIDTH: f32 = 2.0;
+
+#[derive(Default)]
+struct PolygonBuilder {
+    vertices: Vec<Point2<f32>>,
+    last_click: Option<Instant>,
+}
+
+impl PolygonBuilder {
+    // A click adds a vertex, unless it's a double click: in that case the polygon is closed
+    fn click(&mut self, point: Point2<f32>, time: Instant) -> Option<Vec<Point2<f32>>> {
+        let is_double_click = self
+            .last_click
+            .map_or(false, |last| time.duration_since(last) < DOUBLE_CLICK_TIME);
+        if is_double_click {
+            return self.close();
+        }
+        self.last_click = Some(time);
+        self.vertices.push(point);
+        None
+    }
+
+    fn remove_last_vertex(&mut self) {
+        self.vertices.pop();
+        self.last_click = None;
+    }
+
+    // Polygons with less than three vertices don't enclose anything, so they're discarded
+    fn close(&mut self) -> Option<Vec<Point2<f32>>> {
+        self.last_click = None;
+        let vertices = std::mem::take(&mut self.vertices);
+        if vertices.len() >= 3 {
+            Some(vertices)
+        } else {
+            None
+        }
+    }
+
+    fn vertices(&self) -> &[Point2<f32>] {
+        &self.vertices
+    }
+}
+
+pub struct PolygonSelectTool {
+    polygon: PolygonBuilder,
+    pointer_position: Option<Point2<f32>>,
+    selection_addition: SelectionAddition,
+    closed_polygon: Option<Vec<Point2<f32>>>,
+    wants_redraw: bool,
+}
+
+impl PolygonSelectTool {
+    pub fn new() -> Self {
+        Self {
+            polygon: PolygonBuilder::default(),
+            pointer_position: None,
+            selection_addition: SelectionAddition::Add,
+            closed_polygon: None,
+            wants_redraw: false,
+        }
+    }
+
+    // Enter closes the polygon, Backspace removes the last vertex
+    pub fn update_from_keys(&mut self, input_state: &InputState) {
+        if self.polygon.vertices().is_empty() {
+            return;
+        }
+        if input_state.is_key_just_pressed(Key::Return)
+            || input_state.is_key_just_pressed(Key::NumpadEnter)
+        {
+            self.closed_polygon = self.polygon.close();
+            self.wants_redraw = true;
+        } else if input_state.is_key_just_pressed(Key::Backspace) {
+            self.polygon.remove_last_vertex();
+            self.wants_redraw = true;
+        }
+    }
+
+    fn edge(start: Point2<f32>, end: Point2<f32>) -> Option<Vec<Point2<f32>>> {
+        let direction = end - start;
+        if direction.magnitude2() == 0.0 {
+            return None;
+        }
+        let normal = direction.normalize() * EDGE_WIDTH * 0.5;
+        let normal = cgmath::vec2(-normal.y, normal.x);
+        Some(vec![
+            start + normal,
+            end + normal,
+            end - normal,
+            start - normal,
+        ])
+    }
+}
+
+impl Tool for PolygonSelectTool {
+    fn on_deselected(&mut self, context: &mut EditorContext) -> Option<Box<dyn EditorCommand>> {
+        self.polygon.close();
+        self.pointer_position = None;
+        context.image_editor.mark_dirty();
+        None
+    }
+
+    fn on_pointer_click(
+        &mut self,
+        event: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        let point = context
+            .image_editor
+            .transform_point_into_pixel_position(event.new_pointer_location_normalized)?;
+        self.closed_polygon = self.polygon.click(point, Instant::now());
+        context.image_editor.mark_dirty();
+        None
+    }
+
+    fn on_pointer_move(
+        &mut self,
+        event: PointerEvent,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        self.pointer_position = context
+            .image_editor
+            .transform_point_into_pixel_position(event.new_pointer_location_normalized);
+        if !self.polygon.vertices().is_empty() {
+            // The edge to the pointer follows it
+            context.image_editor.mark_dirty();
+        }
+        None
+    }
+
+    fn update(&mut self, context: &mut EditorContext) {
+        if std::mem::take(&mut self.wants_redraw) {
+            context.image_editor.mark_dirty();
+        }
+        let polygon = match self.closed_polygon.take() {
+            Some(polygon) => polygon,
+            None => return,
+        };
+        let new_selection = Selection::from_path(&polygon, self.selection_addition);
+        context.image_editor.mutate_document(|doc| {
+            doc.mutate_selection(|selection| {
+                for shape in new_selection.shapes() {
+                    selection.extend(shape.clone());
+                }
+            });
+        });
+    }
+
+    fn draw(&self, renderer: &mut Renderer) {
+        let vertices = self.polygon.vertices();
+        let pointer = match (vertices.last(), self.pointer_position) {
+            (Some(_), Some(pointer)) => Some(pointer),
+            _ => None,
+        };
+        let points = vertices.iter().chain(pointer.iter());
+        for (start, end) in points.clone().zip(points.skip(1)) {
+            if let Some(points) = PolygonSelectTool::edge(*start, *end) {
+                renderer.draw(DrawCommand {
+                    primitives: PrimitiveType::Polygon {
+                        points,
+                        multiply_color: wgpu::Color::BLACK,
+                    },
+                    draw_mode: DrawMode::Single,
+                    additional_data: OptionalDrawData::default(),
+                });
+            }
+        }
+    }
+
+    fn ui(&mut self, ui: &mut dyn super::DynamicToolUi, _: &mut EditorContext) {
+        self.selection_addition =
+            dynamic_tool_ui_helpers::dropdown(ui, "Selection mode", self.selection_addition);
+    }
+
+    fn name(&self) -> &'static str {
+        "Polygon Selection tool"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use cgmath::point2;
+
+    use super::{PolygonBuilder, DOUBLE_CLICK_TIME};
+
+    #[test]
+    fn vertices_are_added_removed_and_closed() {
+        let start = Instant::now();
+        let later = |clicks: u32| start + (DOUBLE_CLICK_TIME + Duration::from_millis(1)) * clicks;
+        let mut polygon = PolygonBuilder::default();
+
+        assert_eq!(polygon.click(point2(0.0, 0.0), later(0)), None);
+        assert_eq!(polygon.click(point2(10.0, 0.0), later(1)), None);
+        assert_eq!(polygon.click(point2(5.0, 5.0), later(2)), None);
+        polygon.remove_last_vertex();
+        assert_eq!(polygon.vertices(), &[point2(0.0, 0.0), point2(10.0, 0.0)]);
+
+        // Two vertices enclose nothing, so closing discards them
+        assert_eq!(polygon.close(), None);
+        assert!(polygon.vertices().is_empty());
+
+        for (i, point) in [point2(0.0, 0.0), point2(10.0, 0.0), point2(5.0, 5.0)]
+            .into_iter()
+            .enumerate()
+        {
+            assert_eq!(polygon.click(point, later(i as u32)), None);
+        }
+        // The double click closes the polygon without adding a vertex
+        let double_click = later(2) + Duration::from_millis(100);
+        assert_eq!(
+            polygon.click(point2(5.0, 5.0), double_click),
+            Some(vec![point2(0.0, 0.0), point2(10.0, 0.0), point2(5.0, 5.0)])
+        );
+        assert!(polygon.vertices().is_empty());
+
+        // Removing a vertex between two clicks doesn't make them a double click
+        assert_eq!(polygon.click(point2(0.0, 0.0), later(0)), None);
+        polygon.remove_last_vertex();
+        assert_eq!(polygon.click(point2(1.0, 1.0), later(0)), None);
+        assert_eq!(polygon.vertices(), &[point2(1.0, 1.0)]);
+    }
+}
@@ -0,0 +1,195 @@
+use std::{cell::RefCell, rc::Rc};
+
+use application::InputState;
+use cgmath::Point2;
+use framework::Framework;
+use winit::event::MouseButton;
+
+use super::brush_engine::stamping_engine::StrokingEngine;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PickState {
+    Idle,
+    WaitingForClick,
+    // The normalized position of the click, read once the next frame is captured
+    Capturing(Point2<f32>),
+}
+
+// Unlike the ColorPicker tool, it picks the color of the presented frame, so anything on
+// the screen can be picked, including the ui and the reference overlay
+pub struct ScreenColorPicker {
+    stamping_engine: Rc<RefCell<StrokingEngine>>,
+    state: PickState,
+}
+
+impl ScreenColorPicker {
+    pub fn new(stamping_engine: Rc<RefCell<StrokingEngine>>) -> Self {
+        Self {
+            stamping_engine,
+            state: PickState::Idle,
+        }
+    }
+
+    // The next click picks a color instead of being handled by the tools
+    pub fn start(&mut self) {
+        self.state = PickState::WaitingForClick;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state != PickState::Idle
+    }
+
+    pub fn update(&mut self, input_state: &InputState) {
+        if self.state == PickState::WaitingForClick
+            && input_state.is_mouse_button_just_pressed(MouseButton::Left)
+        {
+            self.state = PickState::Capturing(input_state.normalized_mouse_position());
+        }
+    }
+
+    pub fn wants_capture(&self) -> bool {
+        matches!(self.state, PickState::Capturing(_))
+    }
+
+    // The capture must be as big as the surface, with the frame drawn on it
+    pub fn pick_from_capture(
+        &mut self,
+        capture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        capture_size: (u32, u32),
+        framework: &Framework,
+    ) {
+        if let PickState::Capturing(position) = self.state {
+            if let Some((x, y)) = surface_texel(position, capture_size) {
+                let pixel = match framework.read_texel(capture, format, x, y) {
+                    Some(pixel) => pixel,
+                    None => {
+                        log::warn!("Can't pick colors from a {format:?} surface");
+                        self.state = PickState::Idle;
+                        return;
+                    }
+                };
+                let mut engine = self.stamping_engine.borrow_mut();
+                let mut settings = engine.settings();
+                settings.color_srgb = [
+                    (pixel.r * 255.0).round() as u8,
+                    (pixel.g * 255.0).round() as u8,
+                    (pixel.b * 255.0).round() as u8,
+                ];
+                engine.set_new_settings(settings);
+            }
+            self.state = PickState::Idle;
+        }
+    }
+}
+
+// The pointer position is normalized with the y axis going up, while the texel rows go down.
+// Being normalized, it doesn't depend on the window scale factor
+fn surface_texel(position: Point2<f32>, surface_size: (u32, u32)) -> Option<(u32, u32)> {
+    let (width, height) = surface_size;
+    if width == 0 || height == 0 || position.x.abs() > 1.0 || position.y.abs() > 1.0 {
+        return None;
+    }
+    let x = ((position.x + 1.0) * 0.5 * width as f32) as u32;
+    let y = ((1.0 - position.y) * 0.5 * height as f32) as u32;
+    Some((x.min(width - 1), y.min(height - 1)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, num::NonZeroU32, rc::Rc};
+
+    use cgmath::point2;
+    use framework::{ColorSpace, RgbaTexture2D, RgbaU8, TextureConfiguration, TextureUsage};
+    use image_editor::headless_framework;
+
+    use crate::tools::brush_engine::stamping_engine::{Stamp, StrokingEngine};
+
+    use super::{surface_texel, PickState, ScreenColorPicker};
+
+    #[test]
+    fn surface_texels_are_flipped_on_the_y_axis() {
+        assert_eq!(surface_texel(point2(-1.0, 1.0), (800, 600)), Some((0, 0)));
+        assert_eq!(
+            surface_texel(point2(1.0, -1.0), (800, 600)),
+            Some((799, 599))
+        );
+        assert_eq!(
+            surface_texel(point2(0.0, 0.5), (800, 600)),
+            Some((400, 150))
+        );
+        assert_eq!(surface_texel(point2(0.0, 1.5), (800, 600)), None);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn picked_color_is_read_from_the_captured_frame() {
+        let mut framework = headless_framework();
+        let brush = framework.allocate_texture2d(
+            RgbaTexture2D::from_texels(vec![RgbaU8([255; 4])], (1, 1)).unwrap(),
+            TextureConfiguration {
+                label: Some("Test stamp"),
+                usage: TextureUsage::WRITE_ONLY,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+        let engine = Rc::new(RefCell::new(StrokingEngine::new(
+            Stamp::new("Test stamp", brush),
+            &mut framework,
+        )));
+
+        // A headless frame like the ones presented on a Bgra surface: the top row is
+        // orange, the others are blue
+        let (width, height) = (64, 4);
+        let format = wgpu::TextureFormat::Bgra8UnormSrgb;
+        let capture = framework.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Test screen capture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+        });
+        let texels: Vec<u8> = (0..width * height)
+            .flat_map(|i| {
+                if i < width {
+                    [20, 140, 250, 255]
+                } else {
+                    [200, 60, 10, 255]
+                }
+            })
+            .collect();
+        framework.queue.write_texture(
+            capture.as_image_copy(),
+            &texels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(width * 4),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let mut picker = ScreenColorPicker::new(engine.clone());
+        picker.state = PickState::Capturing(point2(0.0, 0.9));
+        picker.pick_from_capture(&capture, format, (width, height), &framework);
+        assert!(!picker.is_active());
+        assert_eq!(engine.borrow().settings().color_srgb, [250, 140, 20]);
+
+        picker.state = PickState::Capturing(point2(0.0, -0.9));
+        picker.pick_from_capture(&capture, format, (width, height), &framework);
+        assert_eq!(engine.borrow().settings().color_srgb, [10, 60, 200]);
+    }
+}
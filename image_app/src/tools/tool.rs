@@ -1,5 +1,6 @@
 use std::ops::RangeInclusive;
 
+use application::ModifierSet;
 use cgmath::{Point2, Vector2};
 use framework::{renderer::renderer::Renderer, Framework};
 
@@ -18,6 +19,7 @@ pub struct PointerEvent {
     pub new_pointer_location: Point2<f32>,
     pub pressure: f32,
     pub window_width: Vector2<u32>,
+    pub modifiers: ModifierSet,
 }
 
 pub trait DynamicToolUi {
@@ -29,6 +31,7 @@ pub trait DynamicToolUi {
         values_fn: Box<dyn FnOnce() -> Vec<(usize, String)>>,
     ) -> usize;
     fn button(&mut self, label: &str) -> bool;
+    fn checkbox(&mut self, label: &str, current: bool) -> bool;
     fn value_float(&mut self, label: &str, current: f32) -> f32 {
         self.value_float_ranged(label, current, f32::MIN..=f32::MAX)
     }
@@ -98,6 +101,9 @@ pub trait Tool {
 
     fn ui(&mut self, _ui: &mut dyn DynamicToolUi, _context: &mut EditorContext) {}
 
+    // Called once per update, before the pointer events
+    fn update(&mut self, _context: &mut EditorContext) {}
+
     fn on_pointer_release(
         &mut self,
         _pointer_release: PointerEvent,
@@ -107,4 +113,13 @@ pub trait Tool {
     }
 
     fn draw(&self, _renderer: &mut Renderer) {}
+
+    // Drawn over the document after draw, the cursor position is in world space
+    fn draw_cursor(
+        &self,
+        _renderer: &mut Renderer,
+        _cursor_position: Point2<f32>,
+        _image_editor: &ImageEditor,
+    ) {
+    }
 }
@@ -1,15 +1,28 @@
 use std::f32::consts::PI;
 
 use crate::tools::EditorContext;
-use cgmath::{point2, InnerSpace, Point2};
+use application::{key::Key, InputState};
+use cgmath::{
+    point2, vec2, EuclideanSpace, InnerSpace, Matrix4, Point2, Rad, SquareMatrix, Vector2, Zero,
+};
+use image_editor::layers::LayerType;
 use strum_macros::{Display, EnumIter, EnumString};
 
-use super::{dynamic_tool_ui_helpers, tool::Tool, DynamicToolUi, EditorCommand, PointerEvent};
+use super::{
+    dynamic_tool_ui_helpers, tool::Tool, DynamicToolUi, EditorCommand, PointerEvent,
+    TranslateLayerCommand, TranslateSelectionCommand,
+};
+
+const NUDGE_STEP: f32 = 1.0;
+const NUDGE_STEP_WITH_SHIFT: f32 = 10.0;
 
 #[derive(Clone, Copy, Debug, EnumIter, EnumString, Display, PartialEq, Eq)]
 enum TransformItem {
     Layer = 0,
     Selection = 1,
+    Pivot = 2,
+    // Drags the corners of the layer one by one, distorting it with a perspective transform
+    Corners = 3,
 }
 
 impl From<usize> for TransformItem {
@@ -17,6 +30,8 @@ impl From<usize> for TransformItem {
         match v {
             0 => Self::Layer,
             1 => Self::Selection,
+            2 => Self::Pivot,
+            3 => Self::Corners,
             _ => unreachable!(),
         }
     }
@@ -32,6 +47,11 @@ pub struct TransformLayerTool {
     transform_item: TransformItem,
     extract_selection: bool,
     is_manipulating_selection: bool,
+    // The point the layer is rotated and scaled around, in the layer space so that it follows
+    // the layer when it's moved. The origin is the layer position
+    pivot: Point2<f32>,
+    // The index of the corner nearest to the pointer when the drag started, see Layer::corners
+    dragged_corner: Option<usize>,
 }
 
 impl TransformLayerTool {
@@ -42,18 +62,114 @@ impl TransformLayerTool {
             transform_item: TransformItem::Layer,
             extract_selection: false,
             is_manipulating_selection: false,
+            pivot: point2(0.0, 0.0),
+            dragged_corner: None,
+        }
+    }
+
+    // The arrow keys move the floating selection when there's one, otherwise the transform item
+    pub fn nudge_from_keys(
+        &self,
+        input_state: &InputState,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        let delta = nudge_delta_from_keys(input_state)?;
+        let document = context.image_editor.document();
+        if document.selection_layer().is_none() && self.transform_item == TransformItem::Layer {
+            let layer = document.current_layer_index().copied()?;
+            context.image_editor.mutate_document(|doc| {
+                doc.mutate_layer(&layer, |layer| layer.translate(delta));
+            });
+            Some(Box::new(TranslateLayerCommand::new(layer, delta)))
+        } else {
+            context.image_editor.mutate_document(|doc| {
+                if let Some(selection) = doc.selection_layer_mut() {
+                    selection.layer.translate(delta);
+                } else {
+                    doc.mutate_selection(|sel| sel.translate(delta));
+                }
+            });
+            Some(Box::new(TranslateSelectionCommand::new(delta)))
         }
     }
 }
 
+// Rotates and scales around the pivot: moves the pivot to the origin, rotates and scales in the
+// same order as Transform2d::matrix, then moves the pivot back
+fn pivoted_transform(pivot: Point2<f32>, rotation: Rad<f32>, scale: Vector2<f32>) -> Matrix4<f32> {
+    Matrix4::from_translation(pivot.to_vec().extend(0.0))
+        * Matrix4::from_nonuniform_scale(scale.x, scale.y, 1.0)
+        * Matrix4::from_angle_z(rotation)
+        * Matrix4::from_translation(-pivot.to_vec().extend(0.0))
+}
+
+fn transform_point(matrix: Matrix4<f32>, point: Point2<f32>) -> Point2<f32> {
+    let point = matrix * point.to_vec().extend(0.0).extend(1.0);
+    point2(point.x, point.y)
+}
+
+// The layer position keeping the pivot, given in world space, in place when the layer rotation
+// and scale go from old to new
+fn position_around_pivot(
+    position: Point2<f32>,
+    pivot: Point2<f32>,
+    old: (Rad<f32>, Vector2<f32>),
+    new: (Rad<f32>, Vector2<f32>),
+) -> Point2<f32> {
+    let undo_old = pivoted_transform(pivot, old.0, old.1)
+        .invert()
+        .unwrap_or_else(Matrix4::identity);
+    transform_point(pivoted_transform(pivot, new.0, new.1) * undo_old, position)
+}
+
+// One pixel for each arrow key pressed, ten while Shift is held
+fn nudge_delta_from_keys(input_state: &InputState) -> Option<Vector2<f32>> {
+    let step = if input_state.is_key_pressed(Key::LShift) || input_state.is_key_pressed(Key::RShift)
+    {
+        NUDGE_STEP_WITH_SHIFT
+    } else {
+        NUDGE_STEP
+    };
+    let mut delta = vec2(0.0, 0.0);
+    if input_state.is_key_just_pressed(Key::Left) {
+        delta.x -= step;
+    }
+    if input_state.is_key_just_pressed(Key::Right) {
+        delta.x += step;
+    }
+    // The world space y axis points up
+    if input_state.is_key_just_pressed(Key::Up) {
+        delta.y += step;
+    }
+    if input_state.is_key_just_pressed(Key::Down) {
+        delta.y -= step;
+    }
+    if delta.is_zero() {
+        None
+    } else {
+        Some(delta)
+    }
+}
+
 impl Tool for TransformLayerTool {
     fn on_pointer_click(
         &mut self,
         event: PointerEvent,
-        _: &mut EditorContext,
+        context: &mut EditorContext,
     ) -> Option<Box<dyn EditorCommand>> {
         self.is_active = true;
         self.last_frame_position = event.new_pointer_location;
+        let layer = context.image_editor.document().current_layer();
+        // Only the image layers can be pinned
+        if self.transform_item == TransformItem::Corners
+            && matches!(layer.layer_type, LayerType::Image { .. })
+        {
+            let corners = layer.corners();
+            self.dragged_corner = (0..corners.len()).min_by(|a, b| {
+                let distance = |i: &usize| (corners[*i] - event.new_pointer_location).magnitude2();
+                distance(a).total_cmp(&distance(b))
+            });
+        }
         None
     }
 
@@ -72,15 +188,30 @@ impl Tool for TransformLayerTool {
                 if doc.selection_layer().is_some() {
                     doc.apply_selection(context.renderer, context.framework);
                 } else {
-                    doc.extract_selection(context.renderer, context.framework);
-                    self.is_manipulating_selection = true;
+                    let extracted = doc.extract_selection(context.renderer, context.framework);
+                    if let Err(e) = extracted {
+                        log::warn!("{e}");
+                    }
+                    self.is_manipulating_selection = extracted.is_ok();
                 }
             });
         }
 
         let new_position = pointer_motion.new_pointer_location;
         let delta = new_position - self.last_frame_position;
-        if delta.magnitude2() > 0.5 {
+        if delta.magnitude2() > 0.5 && self.transform_item == TransformItem::Pivot {
+            // The pointer moves in world space, the pivot in the rotated and scaled layer space
+            let transform = context.image_editor.document().current_layer().transform();
+            let layer_space = pivoted_transform(
+                point2(0.0, 0.0),
+                transform.rotation_radians,
+                transform.scale,
+            )
+            .invert()
+            .unwrap_or_else(Matrix4::identity);
+            self.pivot += transform_point(layer_space, Point2::from_vec(delta)).to_vec();
+            self.last_frame_position = new_position;
+        } else if delta.magnitude2() > 0.5 {
             context
                 .image_editor
                 .mutate_document(|doc| match self.transform_item {
@@ -98,6 +229,17 @@ impl Tool for TransformLayerTool {
                             doc.mutate_selection(|sel| sel.translate(delta))
                         }
                     }
+                    TransformItem::Corners => {
+                        let layer = doc.current_layer_index().copied();
+                        if let (Some(layer), Some(corner)) = (layer, self.dragged_corner) {
+                            doc.mutate_layer(&layer, |layer| {
+                                let mut corners = layer.corners();
+                                corners[corner] += delta;
+                                layer.set_corner_pin(Some(corners));
+                            });
+                        }
+                    }
+                    TransformItem::Pivot => unreachable!(),
                 });
             self.last_frame_position = new_position;
         }
@@ -110,6 +252,7 @@ impl Tool for TransformLayerTool {
         _context: &mut EditorContext,
     ) -> Option<Box<dyn EditorCommand>> {
         self.is_active = false;
+        self.dragged_corner = None;
         None
     }
 
@@ -137,6 +280,17 @@ impl Tool for TransformLayerTool {
             }
         }
 
+        let mut pivot = self.pivot.to_vec();
+        ui.vec2_ranged(
+            "Rotation pivot",
+            &mut pivot,
+            f32::MIN..=f32::MAX,
+            f32::MIN..=f32::MAX,
+            1.0,
+        );
+        self.pivot = Point2::from_vec(pivot);
+
+        let pivot = self.pivot;
         context.image_editor.mutate_current_layer(|current_layer| {
             let current_layer_transform = current_layer.transform();
             let new_rotation = ui.value_float_ranged(
@@ -153,11 +307,138 @@ impl Tool for TransformLayerTool {
                 0.01,
             );
 
+            let interpolation = dynamic_tool_ui_helpers::dropdown(
+                ui,
+                "Interpolation",
+                current_layer.interpolation(),
+            );
+
+            let old = (
+                current_layer_transform.rotation_radians,
+                current_layer_transform.scale,
+            );
+            let new = (Rad(new_rotation), scale);
+            if old != new {
+                let position = point2(
+                    current_layer_transform.position.x,
+                    current_layer_transform.position.y,
+                );
+                let world_pivot = position
+                    + transform_point(pivoted_transform(point2(0.0, 0.0), old.0, old.1), pivot)
+                        .to_vec();
+                let new_position = position_around_pivot(position, world_pivot, old, new);
+                current_layer.translate(new_position - position);
+            }
             current_layer.set_rotation(new_rotation);
             current_layer.set_scale(scale);
+            current_layer.set_interpolation(interpolation);
+
+            if current_layer.corner_pin().is_some() && ui.button("Reset corners") {
+                current_layer.set_corner_pin(None);
+            }
         })
     }
     fn name(&self) -> &'static str {
         "Transform Tool"
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::FRAC_PI_2;
+
+    use application::{key_event, InputState};
+    use cgmath::{point2, vec2, InnerSpace, Rad};
+    use framework::renderer::renderer::Renderer;
+    use image_editor::{headless_framework, ImageEditor};
+    use winit::event::{ElementState, VirtualKeyCode};
+
+    use crate::tools::{EditorCommand, EditorContext};
+
+    use super::{pivoted_transform, position_around_pivot, transform_point, TransformLayerTool};
+
+    fn press(
+        key: VirtualKeyCode,
+        tool: &TransformLayerTool,
+        input_state: &mut InputState,
+        context: &mut EditorContext,
+    ) -> Option<Box<dyn EditorCommand>> {
+        input_state.update(&key_event(key, ElementState::Pressed));
+        let command = tool.nudge_from_keys(input_state, context);
+        input_state.update(&key_event(key, ElementState::Released));
+        command
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn arrow_keys_nudge_the_current_layer() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[1024.0, 1024.0]);
+        let mut context = EditorContext {
+            framework: &mut framework,
+            image_editor: &mut image_editor,
+            renderer: &mut renderer,
+        };
+        let tool = TransformLayerTool::new();
+        let mut input_state = InputState::new();
+        let position = |context: &EditorContext| {
+            context
+                .image_editor
+                .document()
+                .current_layer()
+                .transform()
+                .position
+        };
+        let start = position(&context);
+
+        assert!(press(VirtualKeyCode::Right, &tool, &mut input_state, &mut context).is_some());
+        assert_eq!(position(&context).x, start.x + 1.0);
+        assert!(press(VirtualKeyCode::Down, &tool, &mut input_state, &mut context).is_some());
+        assert_eq!(position(&context).y, start.y - 1.0);
+
+        // Shift is held while Up is pressed
+        input_state.update(&key_event(VirtualKeyCode::LShift, ElementState::Pressed));
+        let command = press(VirtualKeyCode::Up, &tool, &mut input_state, &mut context).unwrap();
+        assert_eq!(position(&context).y, start.y + 9.0);
+
+        // The nudge is undone by moving back
+        let redo = command.undo(&mut context).unwrap();
+        assert_eq!(position(&context).y, start.y - 1.0);
+        redo.undo(&mut context).unwrap();
+        assert_eq!(position(&context).y, start.y + 9.0);
+        assert_eq!(position(&context).x, start.x + 1.0);
+    }
+
+    #[test]
+    fn rotating_around_a_pivot() {
+        let pivot = point2(1.0, 1.0);
+        let quarter_turn = Rad(FRAC_PI_2);
+        let rotated = transform_point(
+            pivoted_transform(pivot, quarter_turn, vec2(1.0, 1.0)),
+            point2(3.0, 1.0),
+        );
+        assert!(
+            (rotated - point2(1.0, 3.0)).magnitude() < 1e-5,
+            "{rotated:?}"
+        );
+        // The pivot doesn't move
+        let pivot_image = transform_point(
+            pivoted_transform(pivot, quarter_turn, vec2(2.0, 3.0)),
+            pivot,
+        );
+        assert!((pivot_image - pivot).magnitude() < 1e-5);
+
+        // A layer at (3, 1) rotated around (1, 1) ends up at (1, 3)
+        let position = position_around_pivot(
+            point2(3.0, 1.0),
+            pivot,
+            (Rad(0.0), vec2(1.0, 1.0)),
+            (quarter_turn, vec2(1.0, 1.0)),
+        );
+        assert!(
+            (position - point2(1.0, 3.0)).magnitude() < 1e-5,
+            "{position:?}"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+// An action that can be run from the command palette, see ImageApplication::dispatch_actions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaletteAction {
+    pub action: String,
+    pub display_name: String,
+}
+
+impl PaletteAction {
+    pub fn new(action: &str, display_name: &str) -> Self {
+        Self {
+            action: action.to_owned(),
+            display_name: display_name.to_owned(),
+        }
+    }
+}
+
+const MATCH_SCORE: i32 = 1;
+const CONSECUTIVE_BONUS: i32 = 5;
+const WORD_START_BONUS: i32 = 10;
+
+// None when the characters of the query don't appear in order in the candidate, ignoring the case.
+// Otherwise the higher the score, the better the match: consecutive characters and characters
+// at the start of a word are worth more, while the characters skipped between two matches lower it
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let query: Vec<char> = query
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    // best[j] is the best score of the query matched so far, with its last character matched at j
+    let mut best: Vec<Option<i32>> = candidate
+        .iter()
+        .enumerate()
+        .map(|(j, c)| (*c == query[0]).then(|| MATCH_SCORE + word_start_bonus(&candidate, j)))
+        .collect();
+    for query_char in &query[1..] {
+        best = (0..candidate.len())
+            .map(|j| {
+                if candidate[j] != *query_char {
+                    return None;
+                }
+                (0..j)
+                    .filter_map(|k| {
+                        let skipped = (j - k - 1) as i32;
+                        let consecutive = if skipped == 0 { CONSECUTIVE_BONUS } else { 0 };
+                        Some(best[k]? + MATCH_SCORE + consecutive - skipped)
+                    })
+                    .max()
+                    .map(|score| score + word_start_bonus(&candidate, j))
+            })
+            .collect();
+    }
+    best.into_iter().flatten().max()
+}
+
+fn word_start_bonus(candidate: &[char], index: usize) -> i32 {
+    if index == 0 || !candidate[index - 1].is_alphanumeric() {
+        WORD_START_BONUS
+    } else {
+        0
+    }
+}
+
+// The actions matching the query, from the best match to the worst one
+pub fn rank_actions<'a>(query: &str, actions: &'a [PaletteAction]) -> Vec<&'a PaletteAction> {
+    let mut ranked: Vec<(i32, &PaletteAction)> = actions
+        .iter()
+        .filter_map(|action| Some((fuzzy_score(query, &action.display_name)?, action)))
+        .collect();
+    // The sort is stable, so equal matches keep their order
+    ranked.sort_by(|(a, _), (b, _)| b.cmp(a));
+    ranked.into_iter().map(|(_, action)| action).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fuzzy_score, rank_actions, PaletteAction};
+
+    #[test]
+    fn characters_must_appear_in_order() {
+        assert!(fuzzy_score("undo", "Undo").is_some());
+        assert!(fuzzy_score("UNDO", "undo").is_some());
+        assert!(fuzzy_score("tqm", "Toggle quick mask").is_some());
+        assert!(fuzzy_score("od", "Undo").is_none());
+        assert!(fuzzy_score("xyz", "Undo").is_none());
+        assert_eq!(fuzzy_score("", "Undo"), Some(0));
+    }
+
+    #[test]
+    fn better_matches_score_higher() {
+        // Consecutive characters
+        assert!(fuzzy_score("era", "Toggle eraser") > fuzzy_score("era", "Export image as"));
+        // Word starts
+        assert!(fuzzy_score("qm", "Toggle quick mask") > fuzzy_score("qm", "Aquamarine"));
+        // Fewer skipped characters
+        assert!(fuzzy_score("brs", "Brush") > fuzzy_score("brs", "Brightness"));
+    }
+
+    #[test]
+    fn actions_are_ranked_by_score() {
+        let actions = vec![
+            PaletteAction::new("save", "Export image"),
+            PaletteAction::new("undo", "Undo"),
+            PaletteAction::new("redo", "Redo"),
+            PaletteAction::new("toggle_eraser", "Toggle eraser"),
+        ];
+        let ranked: Vec<&str> = rank_actions("re", &actions)
+            .iter()
+            .map(|a| a.action.as_str())
+            .collect();
+        assert_eq!(ranked, vec!["redo", "toggle_eraser", "save"]);
+
+        // An empty query lists everything
+        assert_eq!(rank_actions("", &actions).len(), actions.len());
+    }
+}
@@ -1,5 +1,9 @@
-use std::ops::RangeInclusive;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+};
 
+use application::{InputState, SELECTABLE_PRESENT_MODES};
 use bytemuck::Zeroable;
 use egui::{
     color::Hsva, Align2, CollapsingHeader, Color32, FontDefinitions, Label, Pos2, RichText, Sense,
@@ -7,41 +11,142 @@ use egui::{
 };
 use egui_wgpu_backend::{RenderPass, ScreenDescriptor};
 use egui_winit_platform::PlatformDescriptor;
-use framework::Framework;
+use framework::{Framework, SrgbColor};
 use image_editor::{
     blend_settings::BlendMode,
-    document::Document,
-    layers::{LayerId, LayerItem, LayerSettings},
-    LayerConstructionInfo,
+    document::{
+        AlphaMode, Document, DocumentBackground, Palette, MAX_DPI, MAX_PALETTE_COLORS, MIN_DPI,
+    },
+    filters::{NoiseKind, NoiseParameters, UnsharpMaskParameters, MAX_UNSHARP_MASK_RADIUS},
+    layers::{LayerColorLabel, LayerId, LayerItem, LayerPosition, LayerSettings},
+    selection::SelectionDisplay,
+    DocumentId, LayerConstructionInfo, PaintTarget,
 };
 use log::warn;
 use strum::IntoEnumIterator;
-use wgpu::{CommandBuffer, SurfaceConfiguration, TextureView};
+use wgpu::{CommandBuffer, PresentMode, SurfaceConfiguration, TextureView};
 use winit::window::Window;
 
 use crate::{
+    app_settings::Theme,
+    color_state::ColorState,
+    scripting::{run_script_file, SCRIPT_EXTENSION},
     toolbox::ToolId,
-    tools::{DynamicToolUi, EditorContext, Tool},
+    tools::{
+        brush_presets_directory,
+        stamping_engine::{FalloffCurve, StampQuality},
+        AddLayerCommand, BrushPreset, DeleteLayerCommand, DocumentSettingsCommand, DynamicToolUi,
+        EditorContext, GroupLayersCommand, LayerMaskCommand, LayerPixelsCommand,
+        LayerReplaceCommand, LayerSettingsCommand, ReorderLayerCommand, ReplaceLayersCommand, Tool,
+        UngroupLayersCommand, MAX_BRUSH_SIZE, MIN_BRUSH_SIZE,
+    },
 };
 
-use super::{ToolUiContext, Ui, UiContext};
+use super::{command_palette, PaletteAction, ToolUiContext, Ui, UiContext};
 enum LayerAction {
     NewLayerRequest,
     CancelNewLayerRequest,
     CreateNewLayer,
     DeleteLayer(LayerId),
+    ShiftLayer(LayerId, bool),
     SelectLayer(LayerId),
+    ToggleLayerSelection(LayerId),
+    SelectLayerRange(LayerId),
+    DeleteSelectedLayers,
+    // Whether the opacity slider is still being dragged
+    SetSelectedLayersOpacity(f32, bool),
+    FinishSelectedLayersOpacity,
+    GroupSelectedLayers,
+    Ungroup(LayerId),
+    // Whether only the visible layers are flattened
+    Flatten(bool),
+    MergeDownPreview(LayerId),
+    ShowMergePreview(bool),
+    CommitMerge,
+    DiscardMerge,
     SetLayerSettings(LayerId, LayerSettings),
+    SetLayerLabel(LayerId, Option<LayerColorLabel>),
+    AddLayerMask,
+    SetPaintTarget(PaintTarget),
     SelectNewTool(ToolId),
+    // Fills with the background color when true, with the brush color otherwise
+    FillSelection(bool),
+    FillSelectionFromEdges,
+    NoiseFillRequest,
+    CancelNoiseFill,
+    FillWithNoise,
+    UnsharpMaskRequest,
+    CancelUnsharpMask,
+    ApplyUnsharpMask,
+    GaussianBlurRequest,
+    CancelGaussianBlur,
+    ApplyGaussianBlur,
+    DocumentSettingsRequest,
+    CancelDocumentSettings,
+    ApplyDocumentSettings,
+    RunPaletteAction(String),
+    CloseCommandPalette,
     None,
 }
 
+#[derive(Default)]
+struct NoiseFillSettings {
+    kind: NoiseKind,
+    seed: u32,
+    parameters: NoiseParameters,
+}
+
+struct DocumentSettings {
+    background: DocumentBackground,
+    // Remembered while switching to the other backgrounds
+    solid_color: [u8; 4],
+    export_background: bool,
+    export_alpha_mode: AlphaMode,
+    dpi: f32,
+    gif_dither: DitherKind,
+    linear_blending: bool,
+    // Some when the document is indexed
+    palette: Option<Vec<[u8; 3]>>,
+}
+
+struct CommandPalette {
+    query: String,
+    actions: Vec<PaletteAction>,
+}
+
+// The longest side of the navigator, in points
+const NAVIGATOR_SIZE: f32 = 200.0;
+// How far the colors spread when a selection is filled from its edges
+const EDGE_FILL_ITERATIONS: u32 = 64;
+const MAX_BLUR_SIGMA: f32 = 32.0;
+
 pub struct EguiUI {
     platform: egui_winit_platform::Platform,
     backend_pass: RenderPass,
     screen_descriptor: ScreenDescriptor,
 
     new_layer_in_creation: Option<LayerConstructionInfo>,
+    noise_fill_in_creation: Option<NoiseFillSettings>,
+    unsharp_mask_in_creation: Option<UnsharpMaskParameters>,
+    // The sigma of the blur
+    gaussian_blur_in_creation: Option<f32>,
+    document_settings_in_edit: Option<DocumentSettings>,
+    command_palette: Option<CommandPalette>,
+    palette_action: Option<String>,
+    closed_document: Option<DocumentId>,
+    brush_preset_name: String,
+    brush_presets: Vec<String>,
+    show_status_overlay: bool,
+    show_navigator: bool,
+    // Whether the selection edges are smoothed when it's filled, whatever is shown on screen
+    antialiased_fill: bool,
+    // The settings the selected layers had when their opacity slider started being dragged,
+    // so that the whole drag is undone at once
+    opacity_edit: Option<Vec<(LayerId, LayerSettings)>>,
+    // The composited document as seen by egui
+    navigator_texture: Option<egui::TextureId>,
+    // Set while egui is animating something
+    needs_repaint: bool,
 }
 
 pub struct DynamicEguiUi<'a> {
@@ -84,6 +189,10 @@ impl<'a> DynamicToolUi for DynamicEguiUi<'a> {
     fn button(&mut self, label: &str) -> bool {
         self.ui.button(label).clicked()
     }
+    fn checkbox(&mut self, label: &str, mut current: bool) -> bool {
+        self.ui.checkbox(&mut current, label);
+        current
+    }
     fn value_float_ranged(
         &mut self,
         label: &str,
@@ -132,6 +241,13 @@ impl<'a> DynamicToolUi for DynamicEguiUi<'a> {
     }
 }
 
+fn list_brush_presets() -> Vec<String> {
+    BrushPreset::list(&brush_presets_directory()).unwrap_or_else(|e| {
+        warn!("Failed to list the brush presets: {}", e);
+        vec![]
+    })
+}
+
 impl EguiUI {
     pub(crate) fn new(
         surface_configuration: &SurfaceConfiguration,
@@ -153,12 +269,37 @@ impl EguiUI {
             },
             backend_pass: RenderPass::new(&framework.device, surface_configuration.format, 1),
             new_layer_in_creation: None,
+            noise_fill_in_creation: None,
+            unsharp_mask_in_creation: None,
+            gaussian_blur_in_creation: None,
+            document_settings_in_edit: None,
+            command_palette: None,
+            palette_action: None,
+            closed_document: None,
+            brush_preset_name: String::new(),
+            brush_presets: list_brush_presets(),
+            show_status_overlay: true,
+            show_navigator: true,
+            antialiased_fill: true,
+            opacity_edit: None,
+            navigator_texture: None,
+            needs_repaint: true,
         }
     }
 
     fn do_ui_impl(&mut self, mut app_ctx: &mut UiContext) -> (bool, LayerAction) {
         if self.new_layer_in_creation.is_some() {
             self.new_layer_dialog()
+        } else if self.noise_fill_in_creation.is_some() {
+            self.noise_fill_dialog()
+        } else if self.unsharp_mask_in_creation.is_some() {
+            self.unsharp_mask_dialog()
+        } else if self.gaussian_blur_in_creation.is_some() {
+            self.gaussian_blur_dialog()
+        } else if self.document_settings_in_edit.is_some() {
+            self.document_settings_dialog()
+        } else if self.command_palette.is_some() {
+            self.command_palette_dialog()
         } else {
             let mut layer_action = LayerAction::None;
             let ctx = self.platform.context();
@@ -177,11 +318,158 @@ impl EguiUI {
                 egui::Window::new("Tools")
                     .show(&ctx, |ui| {
                         egui::menu::bar(ui, |ui| {
-                            egui::menu::menu_button(ui, "File", |ui| {});
+                            egui::menu::menu_button(ui, "File", |ui| {
+                                if ui.button("New from file...").clicked() {
+                                    if let Some(path) = app_ctx
+                                        .image_editor
+                                        .new_document_from_file(app_ctx.framework)
+                                    {
+                                        app_ctx.recent_files.remember(path);
+                                    }
+                                }
+                                ui.add_enabled_ui(!app_ctx.recent_files.paths().is_empty(), |ui| {
+                                    ui.menu_button("Open recent", |ui| {
+                                        let mut reopened = None;
+                                        for path in app_ctx.recent_files.paths() {
+                                            if ui.button(path.display().to_string()).clicked() {
+                                                reopened = Some(path.clone());
+                                            }
+                                        }
+                                        if let Some(path) = reopened {
+                                            if app_ctx
+                                                .image_editor
+                                                .open_image_file(&path, app_ctx.framework)
+                                            {
+                                                app_ctx.recent_files.remember(path);
+                                            } else {
+                                                app_ctx.recent_files.forget(&path);
+                                            }
+                                            ui.close_menu();
+                                        }
+                                    });
+                                });
+                                if ui.button("Export layers as TIFF...").clicked() {
+                                    if let Some(path) = app_ctx
+                                        .image_editor
+                                        .export_layers_to_tiff(app_ctx.renderer, app_ctx.framework)
+                                    {
+                                        app_ctx.recent_files.remember(path);
+                                    }
+                                }
+                                if ui.button("Export selection...").clicked() {
+                                    app_ctx.image_editor.export_selection(app_ctx.framework);
+                                }
+                                if ui.button("Run script...").clicked() {
+                                    let path = rfd::FileDialog::new()
+                                        .add_filter("Rhai script", &[SCRIPT_EXTENSION])
+                                        .pick_file();
+                                    if let Some(path) = path {
+                                        let mut context = EditorContext {
+                                            framework: app_ctx.framework,
+                                            image_editor: app_ctx.image_editor,
+                                            renderer: app_ctx.renderer,
+                                        };
+                                        if let Err(e) = run_script_file(&path, &mut context) {
+                                            warn!("While running {}: {e}", path.display());
+                                        }
+                                    }
+                                }
+                                if ui.button("Document settings...").clicked() {
+                                    layer_action = LayerAction::DocumentSettingsRequest;
+                                }
+                            });
+                            egui::menu::menu_button(ui, "View", |ui| {
+                                ui.checkbox(&mut self.show_status_overlay, "Status overlay");
+                                ui.checkbox(&mut self.show_navigator, "Navigator");
+                                let mut pixel_art_display =
+                                    app_ctx.image_editor.pixel_art_display();
+                                if ui
+                                    .checkbox(&mut pixel_art_display, "Pixel art display")
+                                    .on_hover_text("Keeps the pixels sharp when zoomed")
+                                    .changed()
+                                {
+                                    app_ctx
+                                        .image_editor
+                                        .set_pixel_art_display(pixel_art_display);
+                                }
+                                let mut selection_display =
+                                    app_ctx.image_editor.selection_display();
+                                egui::ComboBox::from_label("Selection")
+                                    .selected_text(selection_display.to_string())
+                                    .show_ui(ui, |ui| {
+                                        for display in SelectionDisplay::iter() {
+                                            ui.selectable_value(
+                                                &mut selection_display,
+                                                display,
+                                                display.to_string(),
+                                            );
+                                        }
+                                    });
+                                if selection_display != app_ctx.image_editor.selection_display() {
+                                    app_ctx
+                                        .image_editor
+                                        .set_selection_display(selection_display);
+                                }
+                                let mut theme = app_ctx.theme;
+                                egui::ComboBox::from_label("Theme")
+                                    .selected_text(theme.to_string())
+                                    .show_ui(ui, |ui| {
+                                        for option in Theme::iter() {
+                                            ui.selectable_value(
+                                                &mut theme,
+                                                option,
+                                                option.to_string(),
+                                            );
+                                        }
+                                    });
+                                // Applied by the application, like the command palette actions
+                                if theme != app_ctx.theme {
+                                    self.palette_action = Some(match theme {
+                                        Theme::Dark => "theme_dark".to_owned(),
+                                        Theme::Light => "theme_light".to_owned(),
+                                    });
+                                }
+                                let mut branching = app_ctx.undo_tree.is_branching();
+                                ui.checkbox(&mut branching, "Branching undo history")
+                                    .on_hover_text(
+                                        "Keep the undone edits when editing after an undo",
+                                    );
+                                if branching != app_ctx.undo_tree.is_branching() {
+                                    self.palette_action = Some(if branching {
+                                        "undo_history_branching".to_owned()
+                                    } else {
+                                        "undo_history_linear".to_owned()
+                                    });
+                                }
+                                ui.separator();
+                                let frame_settings = &mut *app_ctx.frame_settings;
+                                for mode in SELECTABLE_PRESENT_MODES {
+                                    let name = match mode {
+                                        PresentMode::Fifo => "VSync",
+                                        PresentMode::Mailbox => "Mailbox",
+                                        _ => "Immediate",
+                                    };
+                                    ui.radio_value(&mut frame_settings.present_mode, mode, name);
+                                }
+                                let mut capped = frame_settings.max_fps.is_some();
+                                ui.horizontal(|ui| {
+                                    ui.checkbox(&mut capped, "Limit FPS");
+                                    let mut max_fps = frame_settings.max_fps.unwrap_or(60);
+                                    ui.add_enabled(
+                                        capped,
+                                        egui::DragValue::new(&mut max_fps).clamp_range(1..=1000),
+                                    );
+                                    frame_settings.max_fps = capped.then_some(max_fps);
+                                });
+                            });
                             egui::menu::menu_button(ui, "Edit", |ui| {
                                 if ui.button("Selection to new layer").clicked() {
                                     app_ctx.image_editor.mutate_document(|doc| {
-                                        doc.extract_selection(app_ctx.renderer, app_ctx.framework);
+                                        if let Err(e) = doc
+                                            .extract_selection(app_ctx.renderer, app_ctx.framework)
+                                        {
+                                            warn!("{e}");
+                                        }
                                         doc.mutate_selection(|sel| sel.clear());
                                     });
                                 }
@@ -190,10 +478,59 @@ impl EguiUI {
                                         doc.mutate_selection(|sel| sel.invert());
                                     });
                                 }
+                                if ui.button("Select all").clicked() {
+                                    app_ctx.image_editor.mutate_document(|doc| doc.select_all());
+                                }
+                                // Disabled while there is no current layer
+                                let current_layer = app_ctx
+                                    .image_editor
+                                    .document()
+                                    .current_layer_index()
+                                    .copied();
+                                if ui
+                                    .add_enabled(
+                                        current_layer.is_some(),
+                                        egui::Button::new("Select opaque pixels"),
+                                    )
+                                    .clicked()
+                                {
+                                    if let Some(layer) = current_layer {
+                                        let framework = &*app_ctx.framework;
+                                        app_ctx.image_editor.mutate_document(|doc| {
+                                            doc.select_opaque(&layer, framework);
+                                        });
+                                    }
+                                }
                                 if ui.button("Clear selection").clicked() {
-                                    app_ctx.image_editor.mutate_document(|doc| {
-                                        doc.mutate_selection(|sel| sel.clear());
-                                    });
+                                    app_ctx.image_editor.mutate_document(|doc| doc.deselect());
+                                }
+                                if ui.button("Define brush from selection").clicked() {
+                                    self.palette_action = Some("define_brush".to_owned());
+                                }
+                                if ui.button("Fill selection with brush color").clicked() {
+                                    layer_action = LayerAction::FillSelection(false);
+                                }
+                                if ui.button("Fill selection with background color").clicked() {
+                                    layer_action = LayerAction::FillSelection(true);
+                                }
+                                ui.checkbox(&mut self.antialiased_fill, "Anti-aliased fill");
+                                if ui.button("Fill selection from its edges").clicked() {
+                                    layer_action = LayerAction::FillSelectionFromEdges;
+                                }
+                                if ui.button("Fill with noise...").clicked() {
+                                    layer_action = LayerAction::NoiseFillRequest;
+                                }
+                                if ui.button("Unsharp mask...").clicked() {
+                                    layer_action = LayerAction::UnsharpMaskRequest;
+                                }
+                                if ui.button("Gaussian blur...").clicked() {
+                                    layer_action = LayerAction::GaussianBlurRequest;
+                                }
+                                if ui.button("Flatten visible layers").clicked() {
+                                    layer_action = LayerAction::Flatten(true);
+                                }
+                                if ui.button("Flatten all layers").clicked() {
+                                    layer_action = LayerAction::Flatten(false);
                                 }
                                 if ui
                                     .button("Join current layer with previous layer")
@@ -227,38 +564,80 @@ impl EguiUI {
                             ui.separator();
 
                             let undo = egui::Button::new("Undo");
-                            if ui
-                                .add_enabled(app_ctx.undo_stack.has_undo(), undo)
-                                .clicked()
-                            {
-                                app_ctx.undo_stack.do_undo(&mut EditorContext {
+                            if ui.add_enabled(app_ctx.undo_tree.has_undo(), undo).clicked() {
+                                if let Err(e) = app_ctx.undo_tree.undo(&mut EditorContext {
                                     framework: app_ctx.framework,
                                     image_editor: app_ctx.image_editor,
                                     renderer: app_ctx.renderer,
-                                })
+                                }) {
+                                    warn!("Could not undo the last edit: {e}");
+                                }
                             }
                             let redo = egui::Button::new("Redo");
-                            if ui
-                                .add_enabled(app_ctx.undo_stack.has_redo(), redo)
-                                .clicked()
-                            {
-                                app_ctx.undo_stack.do_redo(&mut EditorContext {
+                            if ui.add_enabled(app_ctx.undo_tree.has_redo(), redo).clicked() {
+                                if let Err(e) = app_ctx.undo_tree.redo(&mut EditorContext {
                                     framework: app_ctx.framework,
                                     image_editor: app_ctx.image_editor,
                                     renderer: app_ctx.renderer,
-                                })
+                                }) {
+                                    warn!("Could not redo the last edit: {e}");
+                                }
+                            }
+                            let branches = app_ctx.undo_tree.branch_count();
+                            if branches > 1 {
+                                let active = app_ctx.undo_tree.active_branch().unwrap_or(0);
+                                let mut selected = active;
+                                egui::ComboBox::from_id_source("redo_branch")
+                                    .selected_text(format!("Branch {}/{}", active + 1, branches))
+                                    .show_ui(ui, |ui| {
+                                        for branch in 0..branches {
+                                            ui.selectable_value(
+                                                &mut selected,
+                                                branch,
+                                                format!("Branch {}", branch + 1),
+                                            );
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text(format!(
+                                        "The history splits in {} places",
+                                        app_ctx.undo_tree.branch_points().len()
+                                    ));
+                                if selected != active {
+                                    app_ctx.undo_tree.switch_branch(selected);
+                                }
                             }
                         });
                     })
                     .unwrap(),
             );
 
+            windows.push(
+                egui::Window::new("Documents")
+                    .title_bar(false)
+                    .anchor(Align2::CENTER_TOP, egui::vec2(0.0, 8.0))
+                    .show(&ctx, |ui| self.document_tabs(&mut app_ctx, ui))
+                    .unwrap(),
+            );
+
+            if self.show_navigator {
+                let texture = self.navigator_texture(app_ctx);
+                windows.push(
+                    egui::Window::new("Navigator")
+                        .anchor(Align2::RIGHT_TOP, egui::vec2(-8.0, 8.0))
+                        .show(&ctx, |ui| Self::navigator(&mut app_ctx, ui, texture))
+                        .unwrap(),
+                );
+            }
+
+            if self.show_status_overlay {
+                self.status_overlay(&app_ctx, &ctx);
+            }
+
             let window_hovered = windows.iter().any(|win| {
-                win.response.rect.contains(Pos2 {
-                    x: app_ctx.input_state.mouse_position().x,
-                    y: app_ctx.input_state.window_size().y as f32
-                        - app_ctx.input_state.mouse_position().y,
-                })
+                win.response
+                    .rect
+                    .contains(Self::egui_pointer_position(app_ctx.input_state))
             });
             (window_hovered, layer_action)
         }
@@ -266,6 +645,138 @@ impl EguiUI {
 }
 
 impl EguiUI {
+    // Records the opacity change of the selected layers, once the slider isn't dragged anymore
+    fn finish_opacity_edit(&mut self, app_ctx: &mut UiContext) {
+        if let Some(previous_settings) = self.opacity_edit.take() {
+            app_ctx
+                .undo_tree
+                .push(Box::new(LayerSettingsCommand::new(previous_settings)));
+        }
+    }
+
+    // egui rects are in logical points with the y axis going down
+    fn egui_pointer_position(input_state: &InputState) -> Pos2 {
+        let position = input_state.logical_mouse_position();
+        Pos2 {
+            x: position.x,
+            y: input_state.logical_window_size().y - position.y,
+        }
+    }
+
+    fn status_overlay(&self, app_ctx: &UiContext, ctx: &egui::Context) {
+        let editor = &app_ctx.image_editor;
+        let cursor =
+            match editor.document_pixel_position(app_ctx.input_state.normalized_mouse_position()) {
+                Some(position) => format!("{}, {}", position.x, position.y),
+                None => "-".to_owned(),
+            };
+        let document_size = editor.document().document_size();
+        let text = format!(
+            "Cursor: {} | Zoom: {:.0}% | Size: {}x{} | Layer: {}",
+            cursor,
+            editor.camera().zoom_percent(),
+            document_size.x,
+            document_size.y,
+            editor.selected_layer().settings().name
+        );
+        egui::Area::new("status_overlay")
+            .anchor(Align2::RIGHT_BOTTOM, egui::vec2(-8.0, -8.0))
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(text);
+                });
+            });
+    }
+
+    // The composited texture changes when switching documents or resizing them, so the view
+    // given to egui is refreshed every frame
+    fn navigator_texture(&mut self, app_ctx: &UiContext) -> egui::TextureId {
+        let framework = &*app_ctx.framework;
+        let view = framework.texture2d_view(app_ctx.image_editor.get_full_image_texture());
+        match self.navigator_texture {
+            Some(texture) => {
+                if let Err(e) = self.backend_pass.update_egui_texture_from_wgpu_texture(
+                    &framework.device,
+                    view,
+                    wgpu::FilterMode::Linear,
+                    texture,
+                ) {
+                    warn!("While updating the navigator: {e}");
+                }
+                texture
+            }
+            None => {
+                let texture = self.backend_pass.egui_texture_from_wgpu_texture(
+                    &framework.device,
+                    view,
+                    wgpu::FilterMode::Linear,
+                );
+                self.navigator_texture = Some(texture);
+                texture
+            }
+        }
+    }
+
+    // Shows the whole document with the visible part outlined, clicking or dragging moves the view
+    fn navigator(app_ctx: &mut UiContext, ui: &mut egui::Ui, texture: egui::TextureId) {
+        let document_size = app_ctx.image_editor.document().document_size();
+        let document_size = egui::vec2(document_size.x as f32, document_size.y as f32);
+        let fit = NAVIGATOR_SIZE / document_size.max_elem();
+        let (rect, response) = ui.allocate_exact_size(document_size * fit, Sense::click_and_drag());
+        // The composited texture is upside down, like when it's drawn on the canvas
+        let uv = egui::Rect::from_min_max(egui::pos2(0.0, 1.0), egui::pos2(1.0, 0.0));
+        ui.painter().image(texture, rect, uv, Color32::WHITE);
+
+        let visible = app_ctx
+            .image_editor
+            .viewport_rect_in_document(app_ctx.input_state.window_size());
+        let into_navigator = |x: f32, y: f32| rect.min + egui::vec2(x, y) * fit;
+        let indicator = egui::Rect::from_min_max(
+            into_navigator(visible.left(), visible.top()),
+            into_navigator(visible.right(), visible.bottom()),
+        );
+        ui.painter().rect_stroke(
+            indicator.intersect(rect),
+            0.0,
+            egui::Stroke::new(1.5, Color32::RED),
+        );
+
+        if let Some(pointer) = response.interact_pointer_pos() {
+            let point = (pointer - rect.min) / fit;
+            app_ctx
+                .image_editor
+                .navigate_to(cgmath::point2(point.x, point.y));
+        }
+    }
+
+    fn document_tabs(&mut self, app_ctx: &mut UiContext, ui: &mut egui::Ui) {
+        let editor = &mut app_ctx.image_editor;
+        let active = editor.active_document_index();
+        let can_close = editor.document_count() > 1;
+        let mut clicked_tab = None;
+        let mut closed_tab = None;
+        ui.horizontal(|ui| {
+            for (index, (_, document)) in editor.documents().enumerate() {
+                let size = document.document_size();
+                let label = format!("Document {} ({}x{})", index + 1, size.x, size.y);
+                if ui.selectable_label(index == active, label).clicked() {
+                    clicked_tab = Some(index);
+                }
+                if can_close && ui.small_button("x").clicked() {
+                    closed_tab = Some(index);
+                }
+                ui.separator();
+            }
+        });
+        if let Some(index) = clicked_tab {
+            editor.switch_to(index);
+        }
+        if let Some(index) = closed_tab {
+            self.closed_document = editor.close_document(index, app_ctx.framework);
+        }
+    }
+
     fn brush_settings(&mut self, app_ctx: &mut UiContext, ui: &mut egui::Ui) -> bool {
         ui.label(egui::RichText::new("Brush").heading());
         let event_handled = false;
@@ -286,6 +797,23 @@ impl EguiUI {
                 new_config.color_srgb = hsva.to_srgb();
             }
         });
+        // The brush color is the foreground color
+        let mut colors = ColorState {
+            foreground: new_config.color_srgb,
+            ..*app_ctx.colors
+        };
+        ui.horizontal(|ui| {
+            ui.label("Background color");
+            egui::color_picker::color_edit_button_srgb(ui, &mut colors.background);
+            if ui.button("Swap").on_hover_text("X").clicked() {
+                colors.swap();
+            }
+            if ui.button("Reset").on_hover_text("D").clicked() {
+                colors.reset();
+            }
+        });
+        new_config.color_srgb = colors.foreground;
+        *app_ctx.colors = colors;
         ui.horizontal(|ui| {
             ui.label("Brush opacity");
             ui.add(
@@ -298,9 +826,73 @@ impl EguiUI {
             ui.label("Brush smoothness");
             ui.add(egui::Slider::new(&mut new_config.softness, 0.0..=10.0));
         });
+        ui.horizontal(|ui| {
+            ui.label("Brush hardness");
+            ui.add(egui::Slider::new(&mut new_config.hardness, 0.0..=1.0));
+        });
+        egui::ComboBox::from_label("Falloff")
+            .selected_text(new_config.falloff.to_string())
+            .show_ui(ui, |ui| {
+                for falloff in FalloffCurve::iter() {
+                    ui.selectable_value(&mut new_config.falloff, falloff, falloff.to_string());
+                }
+            });
+        egui::ComboBox::from_label("Stamp quality")
+            .selected_text(new_config.quality.to_string())
+            .show_ui(ui, |ui| {
+                for quality in StampQuality::iter() {
+                    ui.selectable_value(&mut new_config.quality, quality, quality.to_string());
+                }
+            });
+
+        ui.horizontal(|ui| {
+            ui.label("Color mix");
+            ui.add(egui::Slider::new(&mut new_config.mix, 0.0..=1.0));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Angle");
+            ui.drag_angle(&mut new_config.angle);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Roundness");
+            ui.add(egui::Slider::new(&mut new_config.roundness, 0.05..=1.0));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Scatter");
+            ui.add(egui::Slider::new(&mut new_config.scatter, 0.0..=4.0))
+                .on_hover_text(
+                    "Moves the stamps away from the stroke, by up to their size times this",
+                );
+        });
+        CollapsingHeader::new("Color jitter").show(ui, |ui| {
+            let jitter = &mut new_config.color_jitter;
+            ui.add(egui::Slider::new(&mut jitter.hue, 0.0..=0.5).text("Hue"));
+            ui.add(egui::Slider::new(&mut jitter.saturation, 0.0..=1.0).text("Saturation"));
+            ui.add(egui::Slider::new(&mut jitter.value, 0.0..=1.0).text("Value"));
+            ui.horizontal(|ui| {
+                ui.label("Seed");
+                ui.add(egui::DragValue::new(&mut jitter.seed));
+            });
+        });
 
         ui.horizontal(|ui| {
-            ui.add(egui::Checkbox::new(&mut new_config.is_eraser, "Eraser"));
+            ui.add(egui::Checkbox::new(
+                &mut new_config.pixel_perfect,
+                "Pixel perfect",
+            ))
+            .on_hover_text("Paints single pixels, removing the corners of the diagonal strokes");
+        });
+        ui.horizontal(|ui| {
+            let mut compute_brush = stamping_engine.uses_compute_brush();
+            let checkbox = ui
+                .add_enabled(
+                    app_ctx.framework.supports_compute_shaders(),
+                    egui::Checkbox::new(&mut compute_brush, "Compute brush"),
+                )
+                .on_hover_text("Paints all the stamps of a stroke on a chunk at once");
+            if checkbox.changed() {
+                stamping_engine.set_compute_brush(compute_brush, app_ctx.framework);
+            }
         });
 
         if new_config != engine_config {
@@ -312,21 +904,88 @@ impl EguiUI {
         ui.label("Brush tool settings");
         ui.horizontal(|ui| {
             ui.label("Brush size");
-            ui.add(egui::DragValue::new(&mut brush_tool.size).clamp_range(1.0..=1000.0));
+            ui.add(
+                egui::DragValue::new(&mut brush_tool.size)
+                    .clamp_range(MIN_BRUSH_SIZE..=MAX_BRUSH_SIZE),
+            );
         });
         ui.horizontal(|ui| {
             ui.label("Pressure delta");
             ui.add(egui::DragValue::new(&mut brush_tool.pressure_delta).clamp_range(0.0..=1000.0));
         });
+        ui.horizontal(|ui| {
+            let mut snaps = brush_tool.snap_to_grid.is_some();
+            ui.checkbox(&mut snaps, "Snap to grid")
+                .on_hover_text("Moves the stamps on a grid of pixels");
+            let mut grid_size = brush_tool.snap_to_grid.unwrap_or(1);
+            ui.add_enabled(
+                snaps,
+                egui::DragValue::new(&mut grid_size)
+                    .clamp_range(1..=256)
+                    .suffix(" px"),
+            );
+            brush_tool.snap_to_grid = snaps.then_some(grid_size);
+        });
         ui.horizontal(|ui| {
             ui.label("Step");
             ui.add(egui::DragValue::new(&mut brush_tool.step).clamp_range(1.0..=1000.0));
         });
 
+        ui.collapsing("Presets", |ui| {
+            let presets_directory = brush_presets_directory();
+            let mut chosen_preset = None;
+            egui::ComboBox::from_label("Load preset")
+                .selected_text(self.brush_preset_name.clone())
+                .show_ui(ui, |ui| {
+                    for name in self.brush_presets.iter() {
+                        if ui.selectable_label(false, name).clicked() {
+                            chosen_preset = Some(name.clone());
+                        }
+                    }
+                });
+            if let Some(name) = chosen_preset {
+                match BrushPreset::load(&presets_directory, &name) {
+                    Ok(preset) => {
+                        preset.apply(&mut stamping_engine, &mut brush_tool);
+                        self.brush_preset_name = name;
+                    }
+                    Err(e) => warn!("Failed to load brush preset {}: {}", name, e),
+                }
+            }
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.brush_preset_name);
+                let can_save = !self.brush_preset_name.trim().is_empty();
+                if ui
+                    .add_enabled(can_save, egui::Button::new("Save preset"))
+                    .clicked()
+                {
+                    let preset = BrushPreset::capture(
+                        self.brush_preset_name.trim(),
+                        &stamping_engine,
+                        &brush_tool,
+                    );
+                    if let Err(e) = preset.save(&presets_directory) {
+                        warn!("Failed to save brush preset {}: {}", preset.name, e);
+                    }
+                    self.brush_presets = list_brush_presets();
+                }
+            });
+        });
+
+        let calibration = &mut app_ctx.toolbox.pressure_calibration;
+        ui.collapsing("Pen pressure", |ui| {
+            ui.add(egui::Slider::new(&mut calibration.min, 0.0..=1.0).text("Minimum"));
+            ui.add(egui::Slider::new(&mut calibration.max, 0.0..=1.0).text("Maximum"));
+            ui.add(egui::Slider::new(&mut calibration.gamma, 0.1..=5.0).text("Gamma"));
+        });
+
         if ui.button("Save").clicked() {
-            app_ctx
+            if let Some(path) = app_ctx
                 .image_editor
-                .export_current_image(&app_ctx.framework);
+                .export_current_image(&app_ctx.framework)
+            {
+                app_ctx.recent_files.remember(path);
+            }
         }
 
         event_handled
@@ -369,6 +1028,10 @@ impl EguiUI {
         ui.horizontal(|ui| {
             ui.checkbox(&mut current_layer_settings.is_enabled, "Enabled");
             ui.checkbox(&mut current_layer_settings.is_locked, "Locked");
+            ui.checkbox(
+                &mut current_layer_settings.is_alpha_locked,
+                "Lock transparent pixels",
+            );
         });
         ui.horizontal(|ui| {
             ui.checkbox(&mut current_layer_settings.is_mask, "Mask");
@@ -385,10 +1048,72 @@ impl EguiUI {
                 });
         });
 
+        if document.current_layer().mask().is_some() {
+            let mut paint_target = app_ctx.image_editor.paint_target();
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut paint_target, PaintTarget::LayerPixels, "Paint pixels");
+                ui.radio_value(&mut paint_target, PaintTarget::LayerMask, "Paint mask");
+            });
+            if paint_target != app_ctx.image_editor.paint_target() {
+                action = LayerAction::SetPaintTarget(paint_target);
+            }
+        } else if ui.button("Add mask").clicked() {
+            action = LayerAction::AddLayerMask;
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Move up").clicked() {
+                action = LayerAction::ShiftLayer(idx, true);
+            }
+            if ui.button("Move down").clicked() {
+                action = LayerAction::ShiftLayer(idx, false);
+            }
+            if ui.button("Delete").clicked() {
+                action = LayerAction::DeleteLayer(idx);
+            }
+        });
+
+        if document.pending_merge().is_some() {
+            let mut show_preview = document.is_merge_preview_shown();
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut show_preview, "Preview merge").changed() {
+                    action = LayerAction::ShowMergePreview(show_preview);
+                }
+                if ui.button("Apply merge").clicked() {
+                    action = LayerAction::CommitMerge;
+                }
+                if ui.button("Cancel merge").clicked() {
+                    action = LayerAction::DiscardMerge;
+                }
+            });
+        } else if ui.button("Merge down").clicked() {
+            action = LayerAction::MergeDownPreview(idx);
+        }
+
         if &current_layer_settings != original_settings {
             action = LayerAction::SetLayerSettings(idx, current_layer_settings);
         }
 
+        if document.selected_layers().len() > 1 {
+            ui.separator();
+            ui.heading("Selected layers");
+            let mut opacity = current_layer_settings.opacity;
+            let slider = ui.add(egui::Slider::new(&mut opacity, 0.0..=1.0).text("Opacity"));
+            if slider.changed() {
+                action = LayerAction::SetSelectedLayersOpacity(opacity, slider.dragged());
+            } else if slider.drag_released() {
+                action = LayerAction::FinishSelectedLayersOpacity;
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Group").clicked() {
+                    action = LayerAction::GroupSelectedLayers;
+                }
+                if ui.button("Delete").clicked() {
+                    action = LayerAction::DeleteSelectedLayers;
+                }
+            });
+        }
+
         ui.separator();
         ui.heading("Layer tree");
         self.items_ui(ui, document.tree().items(), document, &mut action);
@@ -425,32 +1150,87 @@ impl EguiUI {
                             .map_or(false, |idx| idx == layer.id())
                         {
                             Color32::LIGHT_BLUE
+                        } else if document.selected_layers().contains(layer.id()) {
+                            Color32::LIGHT_GREEN
                         } else {
                             Color32::WHITE
                         };
-                        if ui
-                            .add(
+                        ui.horizontal(|ui| {
+                            if let Some(label) = original_settings.label {
+                                ui.label(RichText::new("●").color(Self::label_color(label)));
+                            }
+                            let response = ui.add(
                                 Label::new(RichText::from(&original_settings.name).color(color))
                                     .sense(sense),
-                            )
-                            .clicked()
-                        {
-                            *action = LayerAction::SelectLayer(idx.clone());
-                        }
+                            );
+                            if response.clicked() {
+                                let modifiers = ui.input().modifiers;
+                                *action = if modifiers.command {
+                                    LayerAction::ToggleLayerSelection(idx.clone())
+                                } else if modifiers.shift {
+                                    LayerAction::SelectLayerRange(idx.clone())
+                                } else {
+                                    LayerAction::SelectLayer(idx.clone())
+                                };
+                            }
+                            response.context_menu(|ui| {
+                                Self::color_label_menu(ui, idx, original_settings.label, action)
+                            });
+                        });
                     });
                 }
                 LayerItem::Group(items, id) => {
                     let group_settings = document.get_layer(id).settings();
-                    CollapsingHeader::new(&group_settings.name)
+                    let header = match group_settings.label {
+                        Some(label) => RichText::new(format!("● {}", group_settings.name))
+                            .color(Self::label_color(label)),
+                        None => RichText::new(&group_settings.name),
+                    };
+                    let response = CollapsingHeader::new(header)
+                        .id_source(id)
                         .default_open(true)
                         .show(ui, |ui| {
+                            if ui.add(egui::Button::new("Ungroup").sense(sense)).clicked() {
+                                *action = LayerAction::Ungroup(id.clone());
+                            }
                             self.items_ui(ui, items, document, action);
                         });
+                    if sense.click {
+                        response.header_response.context_menu(|ui| {
+                            Self::color_label_menu(ui, id, group_settings.label, action)
+                        });
+                    }
                 }
             }
         }
     }
 
+    fn label_color(label: LayerColorLabel) -> Color32 {
+        let [r, g, b] = label.color_srgb();
+        Color32::from_rgb(r, g, b)
+    }
+
+    // Opened by right clicking a layer in the layer tree
+    fn color_label_menu(
+        ui: &mut egui::Ui,
+        id: &LayerId,
+        current: Option<LayerColorLabel>,
+        action: &mut LayerAction,
+    ) {
+        ui.label("Color label");
+        if ui.selectable_label(current.is_none(), "None").clicked() {
+            *action = LayerAction::SetLayerLabel(id.clone(), None);
+            ui.close_menu();
+        }
+        for label in LayerColorLabel::iter() {
+            let text = RichText::new(format!("● {label}")).color(Self::label_color(label));
+            if ui.selectable_label(current == Some(label), text).clicked() {
+                *action = LayerAction::SetLayerLabel(id.clone(), Some(label));
+                ui.close_menu();
+            }
+        }
+    }
+
     fn new_layer_dialog(&mut self) -> (bool, LayerAction) {
         let ctx = self.platform.context();
         let mut action = LayerAction::None;
@@ -479,6 +1259,212 @@ impl EguiUI {
             .unwrap();
         return (true, action);
     }
+
+    fn noise_fill_dialog(&mut self) -> (bool, LayerAction) {
+        let ctx = self.platform.context();
+        let mut action = LayerAction::None;
+        let _ = egui::Window::new("Fill with noise")
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(&ctx, |ui| {
+                let settings = self.noise_fill_in_creation.as_mut().unwrap();
+
+                egui::ComboBox::from_label("Noise")
+                    .selected_text(settings.kind.to_string())
+                    .show_ui(ui, |ui| {
+                        for kind in NoiseKind::iter() {
+                            ui.selectable_value(&mut settings.kind, kind, kind.to_string());
+                        }
+                    });
+                ui.add(egui::DragValue::new(&mut settings.seed).prefix("Seed: "));
+                ui.add_enabled(
+                    settings.kind != NoiseKind::White,
+                    egui::Slider::new(&mut settings.parameters.scale, 1.0..=512.0).text("Scale"),
+                );
+                ui.add_enabled(
+                    settings.kind == NoiseKind::Perlin,
+                    egui::Slider::new(&mut settings.parameters.octaves, 1..=8).text("Octaves"),
+                );
+                ui.checkbox(&mut settings.parameters.grayscale, "Grayscale");
+                if ui.button("Fill").clicked() {
+                    action = LayerAction::FillWithNoise
+                } else if ui.button("Cancel").clicked() {
+                    action = LayerAction::CancelNoiseFill
+                } else {
+                    action = LayerAction::None
+                }
+            })
+            .unwrap();
+        return (true, action);
+    }
+
+    fn unsharp_mask_dialog(&mut self) -> (bool, LayerAction) {
+        let ctx = self.platform.context();
+        let mut action = LayerAction::None;
+        let _ = egui::Window::new("Unsharp mask")
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(&ctx, |ui| {
+                let parameters = self.unsharp_mask_in_creation.as_mut().unwrap();
+
+                ui.add(egui::Slider::new(&mut parameters.amount, 0.0..=5.0).text("Amount"));
+                ui.add(
+                    egui::Slider::new(&mut parameters.radius, 1.0..=MAX_UNSHARP_MASK_RADIUS)
+                        .text("Radius"),
+                );
+                ui.add(egui::Slider::new(&mut parameters.threshold, 0..=255).text("Threshold"));
+                if ui.button("Apply").clicked() {
+                    action = LayerAction::ApplyUnsharpMask
+                } else if ui.button("Cancel").clicked() {
+                    action = LayerAction::CancelUnsharpMask
+                } else {
+                    action = LayerAction::None
+                }
+            })
+            .unwrap();
+        return (true, action);
+    }
+
+    fn gaussian_blur_dialog(&mut self) -> (bool, LayerAction) {
+        let ctx = self.platform.context();
+        let mut action = LayerAction::None;
+        let _ = egui::Window::new("Gaussian blur")
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(&ctx, |ui| {
+                let sigma = self.gaussian_blur_in_creation.as_mut().unwrap();
+
+                ui.add(egui::Slider::new(sigma, 0.5..=MAX_BLUR_SIGMA).text("Sigma"));
+                if ui.button("Apply").clicked() {
+                    action = LayerAction::ApplyGaussianBlur
+                } else if ui.button("Cancel").clicked() {
+                    action = LayerAction::CancelGaussianBlur
+                } else {
+                    action = LayerAction::None
+                }
+            })
+            .unwrap();
+        return (true, action);
+    }
+
+    fn document_settings_dialog(&mut self) -> (bool, LayerAction) {
+        let ctx = self.platform.context();
+        let mut action = LayerAction::None;
+        let _ = egui::Window::new("Document settings")
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(&ctx, |ui| {
+                let settings = self.document_settings_in_edit.as_mut().unwrap();
+
+                ui.label("Background");
+                ui.horizontal(|ui| {
+                    for background in [
+                        DocumentBackground::Transparent,
+                        DocumentBackground::Solid(settings.solid_color),
+                        DocumentBackground::Checker,
+                    ] {
+                        let selected = std::mem::discriminant(&settings.background)
+                            == std::mem::discriminant(&background);
+                        if ui
+                            .selectable_label(selected, background.to_string())
+                            .clicked()
+                        {
+                            settings.background = background;
+                        }
+                    }
+                });
+                if let DocumentBackground::Solid(color) = &mut settings.background {
+                    ui.color_edit_button_srgba_unmultiplied(color);
+                    settings.solid_color = *color;
+                }
+                ui.checkbox(
+                    &mut settings.export_background,
+                    "Include background in exports",
+                );
+                egui::ComboBox::from_label("Exported alpha")
+                    .selected_text(settings.export_alpha_mode.to_string())
+                    .show_ui(ui, |ui| {
+                        for alpha_mode in AlphaMode::iter() {
+                            ui.selectable_value(
+                                &mut settings.export_alpha_mode,
+                                alpha_mode,
+                                alpha_mode.to_string(),
+                            );
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    ui.label("DPI");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.dpi)
+                            .clamp_range(MIN_DPI..=MAX_DPI)
+                            .speed(1.0),
+                    );
+                });
+                ui.checkbox(&mut settings.linear_blending, "Blend in linear space");
+                egui::ComboBox::from_label("GIF dithering")
+                    .selected_text(settings.gif_dither.to_string())
+                    .show_ui(ui, |ui| {
+                        for dither in DitherKind::iter() {
+                            ui.selectable_value(
+                                &mut settings.gif_dither,
+                                dither,
+                                dither.to_string(),
+                            );
+                        }
+                    });
+                let mut indexed = settings.palette.is_some();
+                if ui.checkbox(&mut indexed, "Indexed colors").changed() {
+                    settings.palette = indexed.then(|| vec![[0, 0, 0], [255, 255, 255]]);
+                }
+                if let Some(palette) = &mut settings.palette {
+                    ui.horizontal_wrapped(|ui| {
+                        for color in palette.iter_mut() {
+                            ui.color_edit_button_srgb(color);
+                        }
+                        if palette.len() < MAX_PALETTE_COLORS && ui.button("+").clicked() {
+                            palette.push([0, 0, 0]);
+                        }
+                        if palette.len() > 1 && ui.button("-").clicked() {
+                            palette.pop();
+                        }
+                    });
+                }
+                if ui.button("Apply").clicked() {
+                    action = LayerAction::ApplyDocumentSettings
+                } else if ui.button("Cancel").clicked() {
+                    action = LayerAction::CancelDocumentSettings
+                } else {
+                    action = LayerAction::None
+                }
+            })
+            .unwrap();
+        return (true, action);
+    }
+
+    fn command_palette_dialog(&mut self) -> (bool, LayerAction) {
+        let ctx = self.platform.context();
+        let mut action = LayerAction::None;
+        let _ = egui::Window::new("Command palette")
+            .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 50.0))
+            .collapsible(false)
+            .show(&ctx, |ui| {
+                let palette = self.command_palette.as_mut().unwrap();
+                ui.text_edit_singleline(&mut palette.query).request_focus();
+                // Enter runs the best match
+                let ranked = command_palette::rank_actions(&palette.query, &palette.actions);
+                if ui.input().key_pressed(egui::Key::Enter) {
+                    if let Some(best) = ranked.first() {
+                        action = LayerAction::RunPaletteAction(best.action.clone());
+                    }
+                }
+                for entry in ranked.iter() {
+                    if ui.button(entry.display_name.as_str()).clicked() {
+                        action = LayerAction::RunPaletteAction(entry.action.clone());
+                    }
+                }
+                if ui.input().key_pressed(egui::Key::Escape) || ui.button("Cancel").clicked() {
+                    action = LayerAction::CloseCommandPalette;
+                }
+            })
+            .unwrap();
+        (true, action)
+    }
 }
 
 impl Ui for EguiUI {
@@ -488,6 +1474,9 @@ impl Ui for EguiUI {
     fn on_new_winit_event(&mut self, event: &winit::event::Event<()>) {
         self.platform.handle_event(&event);
     }
+    fn needs_repaint(&self) -> bool {
+        self.needs_repaint
+    }
     fn do_ui(&mut self, mut app_ctx: UiContext) -> bool {
         let (block_editor, layer_action) = self.do_ui_impl(&mut app_ctx);
         match layer_action {
@@ -501,18 +1490,185 @@ impl Ui for EguiUI {
                 self.new_layer_in_creation = None;
             }
             LayerAction::CreateNewLayer => {
-                app_ctx.image_editor.add_layer_to_document(
+                let new_layer = app_ctx.image_editor.add_layer_to_document(
                     self.new_layer_in_creation.take().unwrap(),
                     app_ctx.framework,
                 );
+                app_ctx
+                    .undo_tree
+                    .push(Box::new(AddLayerCommand::new(vec![new_layer])));
+            }
+            LayerAction::DeleteLayer(idx) => {
+                let deleted_layers = HashSet::from([idx]);
+                if app_ctx.image_editor.document().can_delete(&deleted_layers) {
+                    let deleted = app_ctx.image_editor.delete_layer(idx, app_ctx.framework);
+                    app_ctx
+                        .undo_tree
+                        .push(Box::new(DeleteLayerCommand::new(vec![deleted])));
+                } else {
+                    warn!("Cannot delete the last layer in the document");
+                }
             }
-            LayerAction::DeleteLayer(idx) => app_ctx.image_editor.delete_layer(idx),
+            LayerAction::ShiftLayer(idx, up) => {
+                let mut previous_position = None;
+                app_ctx
+                    .image_editor
+                    .mutate_document(|doc| previous_position = doc.shift_layer(&idx, up));
+                if let Some(previous_position) = previous_position {
+                    app_ctx
+                        .undo_tree
+                        .push(Box::new(ReorderLayerCommand::new(idx, previous_position)));
+                }
+            }
+            LayerAction::AddLayerMask => {
+                if let Some(layer) = app_ctx
+                    .image_editor
+                    .document()
+                    .current_layer_index()
+                    .copied()
+                {
+                    app_ctx
+                        .image_editor
+                        .mutate_current_layer(|layer| layer.add_mask());
+                    app_ctx
+                        .undo_tree
+                        .push(Box::new(LayerMaskCommand::new(layer, None)));
+                }
+            }
+            LayerAction::SetPaintTarget(target) => app_ctx.image_editor.set_paint_target(target),
             LayerAction::SelectLayer(idx) => app_ctx.image_editor.select_new_layer(idx),
+            LayerAction::ToggleLayerSelection(idx) => app_ctx
+                .image_editor
+                .mutate_document(|doc| doc.toggle_layer_selection(idx)),
+            LayerAction::SelectLayerRange(idx) => app_ctx
+                .image_editor
+                .mutate_document(|doc| doc.select_layer_range(idx)),
+            LayerAction::DeleteSelectedLayers => {
+                let mut deleted = vec![];
+                app_ctx
+                    .image_editor
+                    .mutate_document(|doc| deleted = doc.delete_selected(app_ctx.framework));
+                if !deleted.is_empty() {
+                    app_ctx
+                        .undo_tree
+                        .push(Box::new(DeleteLayerCommand::new(deleted)));
+                }
+            }
+            LayerAction::SetSelectedLayersOpacity(opacity, dragging) => {
+                if self.opacity_edit.is_none() {
+                    let document = app_ctx.image_editor.document();
+                    self.opacity_edit = Some(
+                        document
+                            .selected_layers()
+                            .iter()
+                            .map(|id| (*id, document.get_layer(id).settings().clone()))
+                            .collect(),
+                    );
+                }
+                app_ctx
+                    .image_editor
+                    .mutate_document(|doc| doc.set_opacity_all(opacity));
+                if !dragging {
+                    self.finish_opacity_edit(&mut app_ctx);
+                }
+            }
+            LayerAction::FinishSelectedLayersOpacity => self.finish_opacity_edit(&mut app_ctx),
+            LayerAction::GroupSelectedLayers => {
+                let document = app_ctx.image_editor.document();
+                let positions: HashMap<LayerId, LayerPosition> = document
+                    .selected_layers()
+                    .iter()
+                    .filter_map(|id| Some((*id, document.layer_position(id)?)))
+                    .collect();
+                let mut group = None;
+                app_ctx.image_editor.mutate_document(|doc| {
+                    group = doc.group_selected(app_ctx.framework);
+                });
+                if let Some(group) = group {
+                    // The selected layers inside a selected group stay in it
+                    let previous_positions = app_ctx
+                        .image_editor
+                        .document()
+                        .group_children(&group)
+                        .into_iter()
+                        .map(|id| (id, positions[&id]))
+                        .collect();
+                    app_ctx
+                        .undo_tree
+                        .push(Box::new(GroupLayersCommand::new(group, previous_positions)));
+                }
+            }
+            LayerAction::Ungroup(group) => {
+                let document = app_ctx.image_editor.document();
+                let position = document.layer_position(&group);
+                let children = document.group_children(&group);
+                if let Some(position) = position {
+                    let mut group_layer = None;
+                    app_ctx.image_editor.mutate_document(|doc| {
+                        group_layer = Some(doc.ungroup(group, app_ctx.framework));
+                    });
+                    app_ctx.undo_tree.push(Box::new(UngroupLayersCommand::new(
+                        group_layer.unwrap(),
+                        position,
+                        children,
+                    )));
+                }
+            }
+            LayerAction::Flatten(only_visible) => {
+                let mut flattened = None;
+                app_ctx.image_editor.mutate_document(|doc| {
+                    flattened = doc.flatten(only_visible, app_ctx.renderer, app_ctx.framework);
+                });
+                if let Some((layer, replaced)) = flattened {
+                    app_ctx
+                        .undo_tree
+                        .push(Box::new(ReplaceLayersCommand::new(vec![layer], replaced)));
+                }
+            }
+            LayerAction::MergeDownPreview(top) => app_ctx.image_editor.mutate_document(|doc| {
+                doc.merge_down_preview(&top, app_ctx.renderer, app_ctx.framework);
+            }),
+            LayerAction::ShowMergePreview(show) => app_ctx
+                .image_editor
+                .mutate_document(|doc| doc.show_merge_preview(show)),
+            LayerAction::CommitMerge => {
+                let mut merged = None;
+                app_ctx.image_editor.mutate_document(|doc| {
+                    merged = doc.commit_merge(app_ctx.framework);
+                });
+                if let Some((layer, replaced)) = merged {
+                    app_ctx
+                        .undo_tree
+                        .push(Box::new(ReplaceLayersCommand::new(vec![layer], replaced)));
+                }
+            }
+            LayerAction::DiscardMerge => app_ctx
+                .image_editor
+                .mutate_document(|doc| doc.discard_merge(app_ctx.framework)),
             LayerAction::SetLayerSettings(.., settings) => {
                 app_ctx.image_editor.mutate_current_layer(|l| {
                     l.set_settings(settings.clone());
                 });
             }
+            LayerAction::SetLayerLabel(id, label) => {
+                let previous = app_ctx
+                    .image_editor
+                    .document()
+                    .get_layer(&id)
+                    .settings()
+                    .clone();
+                app_ctx
+                    .undo_tree
+                    .push(Box::new(LayerSettingsCommand::new(vec![(id, previous)])));
+                app_ctx.image_editor.mutate_document(|doc| {
+                    doc.mutate_layer(&id, |layer| {
+                        layer.set_settings(LayerSettings {
+                            label,
+                            ..layer.settings().clone()
+                        })
+                    })
+                });
+            }
             LayerAction::SelectNewTool(new_tool_id) => {
                 app_ctx.toolbox.set_primary_tool(
                     &new_tool_id,
@@ -523,10 +1679,218 @@ impl Ui for EguiUI {
                     },
                 );
             }
+            LayerAction::NoiseFillRequest => {
+                self.noise_fill_in_creation = Some(NoiseFillSettings::default());
+            }
+            LayerAction::CancelNoiseFill => {
+                self.noise_fill_in_creation = None;
+            }
+            LayerAction::FillWithNoise => {
+                let settings = self.noise_fill_in_creation.take().unwrap();
+                if let Some(layer) = app_ctx
+                    .image_editor
+                    .document()
+                    .current_layer_index()
+                    .copied()
+                {
+                    let mut diff = None;
+                    app_ctx.image_editor.mutate_document(|doc| {
+                        diff = doc.fill_with_noise(
+                            &layer,
+                            settings.kind,
+                            settings.seed,
+                            settings.parameters,
+                            app_ctx.renderer,
+                            app_ctx.framework,
+                        );
+                    });
+                    if let Some(diff) = diff {
+                        app_ctx
+                            .undo_tree
+                            .push(Box::new(LayerReplaceCommand::new(layer, diff)));
+                    }
+                }
+            }
+            LayerAction::FillSelection(background) => {
+                let settings = app_ctx.stamping_engine.borrow().settings();
+                let color = if background {
+                    let [r, g, b] = app_ctx.colors.background;
+                    wgpu::Color::from_srgb_u8([r, g, b, 255])
+                } else {
+                    let [r, g, b] = settings.color_srgb;
+                    wgpu::Color::from_srgb_u8([r, g, b, settings.opacity])
+                };
+                if let Some(layer) = app_ctx
+                    .image_editor
+                    .document()
+                    .current_layer_index()
+                    .copied()
+                {
+                    let mut diff = None;
+                    let aa = self.antialiased_fill;
+                    app_ctx.image_editor.mutate_document(|doc| {
+                        diff = doc.fill_selection(
+                            &layer,
+                            color,
+                            aa,
+                            app_ctx.renderer,
+                            app_ctx.framework,
+                        );
+                    });
+                    if let Some(diff) = diff {
+                        app_ctx
+                            .undo_tree
+                            .push(Box::new(LayerReplaceCommand::new(layer, diff)));
+                    }
+                }
+            }
+            LayerAction::FillSelectionFromEdges => {
+                if let Some(layer) = app_ctx
+                    .image_editor
+                    .document()
+                    .current_layer_index()
+                    .copied()
+                {
+                    let mut diff = None;
+                    app_ctx.image_editor.mutate_document(|doc| {
+                        diff = doc.fill_selection_from_edges(
+                            &layer,
+                            EDGE_FILL_ITERATIONS,
+                            app_ctx.framework,
+                        );
+                    });
+                    if let Some(diff) = diff {
+                        app_ctx
+                            .undo_tree
+                            .push(Box::new(LayerReplaceCommand::new(layer, diff)));
+                    }
+                }
+            }
+            LayerAction::UnsharpMaskRequest => {
+                self.unsharp_mask_in_creation = Some(UnsharpMaskParameters::default());
+            }
+            LayerAction::CancelUnsharpMask => {
+                self.unsharp_mask_in_creation = None;
+            }
+            LayerAction::ApplyUnsharpMask => {
+                let parameters = self.unsharp_mask_in_creation.take().unwrap();
+                if let Some(layer) = app_ctx
+                    .image_editor
+                    .document()
+                    .current_layer_index()
+                    .copied()
+                {
+                    let mut diff = None;
+                    app_ctx.image_editor.mutate_document(|doc| {
+                        diff = doc.unsharp_mask(
+                            &layer,
+                            parameters.amount,
+                            parameters.radius,
+                            parameters.threshold,
+                            app_ctx.renderer,
+                            app_ctx.framework,
+                        );
+                    });
+                    if let Some(diff) = diff {
+                        app_ctx
+                            .undo_tree
+                            .push(Box::new(LayerReplaceCommand::new(layer, diff)));
+                    }
+                }
+            }
+            LayerAction::GaussianBlurRequest => {
+                self.gaussian_blur_in_creation = Some(2.0);
+            }
+            LayerAction::CancelGaussianBlur => {
+                self.gaussian_blur_in_creation = None;
+            }
+            LayerAction::ApplyGaussianBlur => {
+                let sigma = self.gaussian_blur_in_creation.take().unwrap();
+                if let Some(layer) = app_ctx
+                    .image_editor
+                    .document()
+                    .current_layer_index()
+                    .copied()
+                {
+                    let mut result = Ok(None);
+                    app_ctx.image_editor.mutate_document(|doc| {
+                        result =
+                            doc.apply_gaussian_blur(&layer, sigma, None, None, app_ctx.framework);
+                    });
+                    match result {
+                        Ok(Some(pixels)) => app_ctx
+                            .undo_tree
+                            .push(Box::new(LayerPixelsCommand::new(layer, pixels))),
+                        Ok(None) => {}
+                        Err(e) => warn!("Failed to blur the layer: {e}"),
+                    }
+                }
+            }
+            LayerAction::DocumentSettingsRequest => {
+                let document = app_ctx.image_editor.document();
+                let background = document.background();
+                self.document_settings_in_edit = Some(DocumentSettings {
+                    background,
+                    solid_color: match background {
+                        DocumentBackground::Solid(color) => color,
+                        _ => [255; 4],
+                    },
+                    export_background: document.export_background(),
+                    export_alpha_mode: document.export_alpha_mode(),
+                    dpi: document.dpi(),
+                    gif_dither: document.gif_dither(),
+                    linear_blending: document.linear_blending(),
+                    palette: document
+                        .palette_mode()
+                        .map(|palette| palette.colors().to_vec()),
+                });
+            }
+            LayerAction::CancelDocumentSettings => {
+                self.document_settings_in_edit = None;
+            }
+            LayerAction::RunPaletteAction(action) => {
+                self.command_palette = None;
+                self.palette_action = Some(action);
+            }
+            LayerAction::CloseCommandPalette => {
+                self.command_palette = None;
+            }
+            LayerAction::ApplyDocumentSettings => {
+                let settings = self.document_settings_in_edit.take().unwrap();
+                app_ctx
+                    .undo_tree
+                    .push(Box::new(DocumentSettingsCommand::new(
+                        app_ctx.image_editor.document(),
+                    )));
+                app_ctx.image_editor.mutate_document(|doc| {
+                    doc.set_background(settings.background);
+                    doc.set_export_background(settings.export_background);
+                    doc.set_export_alpha_mode(settings.export_alpha_mode);
+                    doc.set_dpi(settings.dpi);
+                    doc.set_gif_dither(settings.gif_dither);
+                    doc.set_linear_blending(settings.linear_blending);
+                    doc.set_palette_mode(settings.palette.clone().and_then(Palette::new));
+                });
+            }
             LayerAction::None => {}
         };
         block_editor
     }
+    fn wants_keyboard_input(&self) -> bool {
+        self.platform.context().wants_keyboard_input()
+    }
+    fn open_command_palette(&mut self, actions: Vec<PaletteAction>) {
+        self.command_palette = Some(CommandPalette {
+            query: String::new(),
+            actions,
+        });
+    }
+    fn take_palette_action(&mut self) -> Option<String> {
+        self.palette_action.take()
+    }
+    fn take_closed_document(&mut self) -> Option<DocumentId> {
+        self.closed_document.take()
+    }
     fn do_tool_ui(&mut self, app_ctx: ToolUiContext, tool: &mut dyn Tool) -> bool {
         let ctx = self.platform.context();
         let window = egui::Window::new(tool.name()).show(&ctx, |ui| {
@@ -540,20 +1904,26 @@ impl Ui for EguiUI {
                 },
             );
             // dynamic_ui.do_stuff();
+            if app_ctx.image_editor.current_layer_is_group() {
+                ui.colored_label(
+                    Color32::YELLOW,
+                    "The current layer is a group: select one of its layers to edit it",
+                );
+            }
         });
         if let Some(response) = window {
-            response.response.rect.contains(Pos2 {
-                x: app_ctx.input_state.mouse_position().x,
-                y: app_ctx.input_state.window_size().y as f32
-                    - app_ctx.input_state.mouse_position().y,
-            })
+            response
+                .response
+                .rect
+                .contains(Self::egui_pointer_position(app_ctx.input_state))
         } else {
             false
         }
     }
 
-    fn present(&mut self, output_view: &TextureView, framework: &Framework) -> CommandBuffer {
+    fn present(&mut self, output_views: &[&TextureView], framework: &Framework) -> CommandBuffer {
         let output = self.platform.end_frame(None);
+        self.needs_repaint = output.repaint_after.is_zero();
         let paint_jobs = self.platform.context().tessellate(output.shapes);
         let mut encoder =
             framework
@@ -573,15 +1943,17 @@ impl Ui for EguiUI {
         );
 
         // Record all render passes.
-        self.backend_pass
-            .execute(
-                &mut encoder,
-                &output_view,
-                &paint_jobs,
-                &self.screen_descriptor,
-                None,
-            )
-            .unwrap();
+        for output_view in output_views {
+            self.backend_pass
+                .execute(
+                    &mut encoder,
+                    output_view,
+                    &paint_jobs,
+                    &self.screen_descriptor,
+                    None,
+                )
+                .unwrap();
+        }
 
         if let Err(e) = self.backend_pass.remove_textures(tdelta) {
             warn!("While executing ui pass: {e}");
@@ -1,26 +1,37 @@
 use std::{cell::RefCell, rc::Rc};
 
-use application::{AppResized, InputState};
+use application::{AppResized, FrameSettings, InputState};
 use framework::{renderer::renderer::Renderer, Framework};
-use image_editor::ImageEditor;
+use image_editor::{DocumentId, ImageEditor};
 use wgpu::{CommandBuffer, SurfaceConfiguration, TextureView};
 use winit::window::Window;
 
 use crate::{
-    image_editor_app_loop::UndoStack,
+    app_settings::Theme,
+    color_state::ColorState,
+    recent_files::RecentFiles,
     toolbox::Toolbox,
     tools::{brush_engine::stamping_engine::StrokingEngine, BrushTool, Tool},
+    undo_tree::UndoTree,
 };
 
+pub mod command_palette;
 mod egui_ui;
 
+pub use command_palette::PaletteAction;
+
 pub struct UiContext<'app> {
     pub framework: &'app mut Framework,
     pub image_editor: &'app mut ImageEditor,
     pub renderer: &'app mut Renderer,
     pub toolbox: &'app mut Toolbox,
     pub input_state: &'app InputState,
-    pub undo_stack: &'app mut UndoStack,
+    pub undo_tree: &'app mut UndoTree,
+    pub frame_settings: &'app mut FrameSettings,
+    pub recent_files: &'app mut RecentFiles,
+    pub colors: &'app mut ColorState,
+    // Changed through the theme_dark and theme_light actions
+    pub theme: Theme,
 
     pub stamping_engine: Rc<RefCell<StrokingEngine>>,
     pub brush_tool: Rc<RefCell<BrushTool>>,
@@ -31,7 +42,8 @@ pub struct ToolUiContext<'app> {
     pub image_editor: &'app mut ImageEditor,
     pub renderer: &'app mut Renderer,
     pub input_state: &'app InputState,
-    pub undo_stack: &'app mut UndoStack,
+    pub undo_tree: &'app mut UndoTree,
+    pub recent_files: &'app mut RecentFiles,
 
     pub stamping_engine: Rc<RefCell<StrokingEngine>>,
     pub brush_tool: Rc<RefCell<BrushTool>>,
@@ -40,11 +52,20 @@ pub struct ToolUiContext<'app> {
 pub trait Ui {
     fn begin(&mut self);
     fn on_new_winit_event(&mut self, event: &winit::event::Event<()>);
+    fn needs_repaint(&self) -> bool;
 
     fn do_ui(&mut self, ctx: UiContext) -> bool;
     fn do_tool_ui(&mut self, app_ctx: ToolUiContext, tool: &mut dyn Tool) -> bool;
     fn on_resized(&mut self, resized: AppResized);
-    fn present(&mut self, output_view: &TextureView, framework: &Framework) -> CommandBuffer;
+    // When true, the keyboard shortcuts shouldn't be handled by the editor
+    fn wants_keyboard_input(&self) -> bool;
+    fn open_command_palette(&mut self, actions: Vec<PaletteAction>);
+    // The action picked in the command palette, if any, must be dispatched by the application
+    fn take_palette_action(&mut self) -> Option<String>;
+    // The document closed from its tab, whose undo history must be dropped by the application
+    fn take_closed_document(&mut self) -> Option<DocumentId>;
+    // The same frame is drawn on each view, e.g. on the surface and on a capture of the screen
+    fn present(&mut self, output_views: &[&TextureView], framework: &Framework) -> CommandBuffer;
 }
 
 pub fn create_ui(
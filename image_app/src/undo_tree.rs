@@ -0,0 +1,336 @@
+use crate::tools::{CommandError, EditorCommand, EditorContext};
+
+const ROOT: usize = 0;
+
+// While the node is applied its command brings the document back to the parent state,
+// once it's undone the command brings the document from the parent state to this node again
+struct UndoNode<C> {
+    parent: usize,
+    children: Vec<usize>,
+    // The branch followed by redo
+    active_child: Option<usize>,
+    // Only None in the root, or while the command is running
+    command: Option<C>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UndoNodeId(usize);
+
+// The undo history of a document. By default it behaves like a stack: an edit made after an undo
+// drops the undone commands. Once branching is enabled they're kept instead, the edit starts a new
+// branch and the discarded future can be redone again after picking its branch with switch_branch
+pub struct UndoTree<C = Box<dyn EditorCommand>> {
+    // The root is the state before the first edit
+    nodes: Vec<UndoNode<C>>,
+    current: usize,
+    // How many commands were pushed, undone or redone
+    edit_count: u64,
+    branching: bool,
+}
+
+impl<C> Default for UndoTree<C> {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl<C> UndoTree<C> {
+    pub fn new(branching: bool) -> Self {
+        Self {
+            nodes: vec![UndoNode {
+                parent: ROOT,
+                children: vec![],
+                active_child: None,
+                command: None,
+            }],
+            current: ROOT,
+            edit_count: 0,
+            branching,
+        }
+    }
+
+    pub fn is_branching(&self) -> bool {
+        self.branching
+    }
+
+    // Turning the branching off keeps the states reachable with undo and redo,
+    // the other branches are dropped
+    pub fn set_branching(&mut self, branching: bool) {
+        self.branching = branching;
+        if branching {
+            return;
+        }
+        // The active children of the states before the current one lead to it
+        let mut node = ROOT;
+        loop {
+            let active_child = self.nodes[node].active_child;
+            for child in std::mem::take(&mut self.nodes[node].children) {
+                if Some(child) != active_child {
+                    self.discard(child);
+                }
+            }
+            match active_child {
+                Some(child) => {
+                    self.nodes[node].children.push(child);
+                    node = child;
+                }
+                None => break,
+            }
+        }
+    }
+
+    // Drops the commands of the node and of the nodes after it, they can't be reached anymore
+    fn discard(&mut self, node: usize) {
+        let mut discarded = vec![node];
+        while let Some(node) = discarded.pop() {
+            let node = &mut self.nodes[node];
+            node.command = None;
+            node.active_child = None;
+            discarded.append(&mut node.children);
+        }
+    }
+
+    pub fn push(&mut self, command: C) {
+        if !self.branching {
+            for child in std::mem::take(&mut self.nodes[self.current].children) {
+                self.discard(child);
+            }
+        }
+        let new_node = self.nodes.len();
+        self.nodes.push(UndoNode {
+            parent: self.current,
+            children: vec![],
+            active_child: None,
+            command: Some(command),
+        });
+        let current = &mut self.nodes[self.current];
+        current.children.push(new_node);
+        current.active_child = Some(new_node);
+        self.current = new_node;
+        self.edit_count += 1;
+    }
+
+    // Grows with every change made through the history, see ImageEditor::update_autosave
+    pub fn edit_count(&self) -> u64 {
+        self.edit_count
+    }
+
+    pub fn has_undo(&self) -> bool {
+        self.current != ROOT
+    }
+
+    pub fn has_redo(&self) -> bool {
+        self.nodes[self.current].active_child.is_some()
+    }
+
+    // How many branches can be redone from the current state
+    pub fn branch_count(&self) -> usize {
+        self.nodes[self.current].children.len()
+    }
+
+    pub fn active_branch(&self) -> Option<usize> {
+        let node = &self.nodes[self.current];
+        node.active_child
+            .and_then(|child| node.children.iter().position(|c| *c == child))
+    }
+
+    // Picks the branch followed by the next redo, branches are numbered from the oldest one
+    pub fn switch_branch(&mut self, branch: usize) -> bool {
+        let node = &mut self.nodes[self.current];
+        match node.children.get(branch) {
+            Some(child) => {
+                node.active_child = Some(*child);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Only the tests need to tell the states apart
+    #[cfg(test)]
+    pub fn current_node(&self) -> UndoNodeId {
+        UndoNodeId(self.current)
+    }
+
+    // The states where the history splits into more than one branch
+    pub fn branch_points(&self) -> Vec<UndoNodeId> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.children.len() > 1)
+            .map(|(index, _)| UndoNodeId(index))
+            .collect()
+    }
+
+    // apply runs the command and returns its inverse, when it fails the history doesn't move
+    fn undo_with<E, F: FnOnce(&C) -> Result<C, E>>(&mut self, apply: F) -> Result<bool, E> {
+        if !self.has_undo() {
+            return Ok(false);
+        }
+        let node = &mut self.nodes[self.current];
+        let command = node
+            .command
+            .take()
+            .expect("UndoTree: node without a command");
+        match apply(&command) {
+            Ok(inverse) => node.command = Some(inverse),
+            Err(e) => {
+                node.command = Some(command);
+                return Err(e);
+            }
+        }
+        self.current = node.parent;
+        self.edit_count += 1;
+        Ok(true)
+    }
+
+    fn redo_with<E, F: FnOnce(&C) -> Result<C, E>>(&mut self, apply: F) -> Result<bool, E> {
+        let child = match self.nodes[self.current].active_child {
+            Some(child) => child,
+            None => return Ok(false),
+        };
+        let node = &mut self.nodes[child];
+        let command = node
+            .command
+            .take()
+            .expect("UndoTree: node without a command");
+        match apply(&command) {
+            Ok(inverse) => node.command = Some(inverse),
+            Err(e) => {
+                node.command = Some(command);
+                return Err(e);
+            }
+        }
+        self.current = child;
+        self.edit_count += 1;
+        Ok(true)
+    }
+}
+
+impl UndoTree {
+    pub fn undo(&mut self, context: &mut EditorContext) -> Result<bool, CommandError> {
+        self.undo_with(|command| command.undo(context))
+    }
+
+    pub fn redo(&mut self, context: &mut EditorContext) -> Result<bool, CommandError> {
+        self.redo_with(|command| command.undo(context))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UndoNodeId, UndoTree};
+
+    // The commands are the names of the edits, undoing "a" gives "-a" back and vice versa
+    fn run(command: &String, log: &mut Vec<String>) -> Result<String, String> {
+        log.push(command.clone());
+        match command.strip_prefix('-') {
+            Some(edit) => Ok(edit.to_owned()),
+            None => Ok(format!("-{command}")),
+        }
+    }
+
+    #[test]
+    fn linear_history() {
+        let mut log = vec![];
+        let mut tree = UndoTree::<String>::default();
+        assert!(!tree.has_undo() && !tree.has_redo());
+        tree.push("-a".to_owned());
+        tree.push("-b".to_owned());
+
+        assert_eq!(tree.undo_with(|c| run(c, &mut log)), Ok(true));
+        assert_eq!(tree.undo_with(|c| run(c, &mut log)), Ok(true));
+        assert_eq!(tree.undo_with(|c| run(c, &mut log)), Ok(false));
+        assert_eq!(tree.redo_with(|c| run(c, &mut log)), Ok(true));
+        assert_eq!(tree.redo_with(|c| run(c, &mut log)), Ok(true));
+        assert_eq!(tree.redo_with(|c| run(c, &mut log)), Ok(false));
+        assert_eq!(log, vec!["-b", "-a", "a", "b"]);
+    }
+
+    #[test]
+    fn linear_histories_drop_the_undone_edits() {
+        let mut log = vec![];
+        let mut tree = UndoTree::<String>::default();
+        tree.push("-a".to_owned());
+        tree.push("-b".to_owned());
+        tree.undo_with(|c| run(c, &mut log)).unwrap();
+
+        tree.push("-c".to_owned());
+        assert!(!tree.has_redo());
+        assert!(tree.branch_points().is_empty());
+        tree.undo_with(|c| run(c, &mut log)).unwrap();
+        assert_eq!(tree.branch_count(), 1);
+        assert_eq!(tree.redo_with(|c| run(c, &mut log)), Ok(true));
+        assert_eq!(log, vec!["-b", "-c", "c"]);
+    }
+
+    #[test]
+    fn turning_off_the_branching_keeps_the_undo_and_redo_states() {
+        let mut log = vec![];
+        let mut tree = UndoTree::<String>::new(true);
+        tree.push("-a".to_owned());
+        tree.push("-b".to_owned());
+        tree.undo_with(|c| run(c, &mut log)).unwrap();
+        tree.push("-c".to_owned());
+        tree.undo_with(|c| run(c, &mut log)).unwrap();
+        tree.switch_branch(0);
+        tree.undo_with(|c| run(c, &mut log)).unwrap();
+
+        // a can be redone, then b: c is dropped
+        tree.set_branching(false);
+        assert!(!tree.is_branching());
+        assert!(tree.branch_points().is_empty());
+        assert_eq!(tree.redo_with(|c| run(c, &mut log)), Ok(true));
+        assert_eq!(tree.branch_count(), 1);
+        assert_eq!(tree.redo_with(|c| run(c, &mut log)), Ok(true));
+        assert_eq!(tree.redo_with(|c| run(c, &mut log)), Ok(false));
+        assert_eq!(log, vec!["-b", "-c", "-a", "a", "b"]);
+    }
+
+    #[test]
+    fn new_edits_keep_the_old_branch() {
+        let mut log = vec![];
+        let mut tree = UndoTree::<String>::new(true);
+        tree.push("-a".to_owned());
+        let after_a = tree.current_node();
+        tree.push("-b".to_owned());
+        tree.undo_with(|c| run(c, &mut log)).unwrap();
+
+        // Editing after the undo starts a new branch, b isn't lost
+        tree.push("-c".to_owned());
+        assert_eq!(tree.branch_points(), vec![after_a]);
+        tree.undo_with(|c| run(c, &mut log)).unwrap();
+        assert_eq!(tree.current_node(), after_a);
+        assert_eq!(tree.branch_count(), 2);
+        assert_eq!(tree.active_branch(), Some(1));
+
+        assert!(!tree.switch_branch(2));
+        assert!(tree.switch_branch(0));
+        assert_eq!(tree.redo_with(|c| run(c, &mut log)), Ok(true));
+        assert!(!tree.has_redo());
+        assert_eq!(log, vec!["-b", "-c", "b"]);
+
+        // b can be undone again, and c is still reachable
+        tree.undo_with(|c| run(c, &mut log)).unwrap();
+        tree.switch_branch(1);
+        tree.redo_with(|c| run(c, &mut log)).unwrap();
+        assert_eq!(log, vec!["-b", "-c", "b", "-b", "c"]);
+        assert_ne!(tree.current_node(), UndoNodeId(0));
+    }
+
+    #[test]
+    fn failed_commands_keep_the_history() {
+        let mut tree = UndoTree::<String>::default();
+        tree.push("-a".to_owned());
+        let after_a = tree.current_node();
+        let fail = |_: &String| Err::<String, _>("the layer is gone");
+        assert_eq!(tree.undo_with(fail), Err("the layer is gone"));
+        assert_eq!(tree.current_node(), after_a);
+        assert_eq!(tree.edit_count(), 1);
+
+        // The command is still there to be tried again
+        let mut log = vec![];
+        assert_eq!(tree.undo_with(|c| run(c, &mut log)), Ok(true));
+        assert_eq!(log, vec!["-a"]);
+    }
+}
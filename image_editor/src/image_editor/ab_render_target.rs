@@ -1,5 +1,6 @@
 use framework::{
-    framework::TextureId, Framework, RgbaTexture2D, Texture, TextureConfiguration, TextureUsage,
+    framework::TextureId, ColorSpace, Framework, RgbaTexture2D, Texture, TextureConfiguration,
+    TextureUsage,
 };
 
 enum BufferingStep {
@@ -17,20 +18,22 @@ pub(crate) struct ABRenderTarget {
 
 impl ABRenderTarget {
     pub fn new(width: u32, height: u32, framework: &mut Framework) -> Self {
-        let target_1 = framework.allocate_texture2d(
+        let target_1 = framework.allocate_transient_texture2d(
             RgbaTexture2D::empty((width, height)),
             TextureConfiguration {
                 label: Some("AB Render Target 1"),
                 usage: TextureUsage::RWRT,
                 mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
         );
-        let target_2 = framework.allocate_texture2d(
+        let target_2 = framework.allocate_transient_texture2d(
             RgbaTexture2D::empty((width, height)),
             TextureConfiguration {
                 label: Some("AB Render Target 2"),
                 usage: TextureUsage::RWRT,
                 mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
         );
         Self {
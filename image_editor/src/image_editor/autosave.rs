@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use super::DocumentId;
+
+// How many autosaves are kept around for each document: each new one replaces the oldest
+pub const AUTOSAVE_BACKUPS: usize = 2;
+
+const AUTOSAVE_PREFIX: &str = "mochi_autosave";
+const AUTOSAVE_EXTENSION: &str = "tiff";
+
+// Decides when the active document is autosaved: once the interval has passed since it was
+// last saved (or since it was last unchanged), or once enough edits piled up.
+// The edits are counted by the caller, e.g. with the commands run by the undo history
+pub struct AutosaveScheduler {
+    interval: Duration,
+    max_edits: Option<u64>,
+    since_last_save: Duration,
+    saved_edit_count: u64,
+}
+
+impl AutosaveScheduler {
+    pub fn new(interval: Duration, max_edits: Option<u64>) -> Self {
+        Self {
+            interval,
+            max_edits,
+            since_last_save: Duration::ZERO,
+            saved_edit_count: 0,
+        }
+    }
+
+    // Advances the clock and returns whether a save is due. edit_count is the number of edits
+    // made so far: the document is dirty while it differs from the one given to saved
+    pub fn tick(&mut self, elapsed: Duration, edit_count: u64) -> bool {
+        let unsaved_edits = edit_count.abs_diff(self.saved_edit_count);
+        if unsaved_edits == 0 {
+            self.since_last_save = Duration::ZERO;
+            return false;
+        }
+        self.since_last_save += elapsed;
+        self.since_last_save >= self.interval
+            || self.max_edits.map_or(false, |max| unsaved_edits >= max)
+    }
+
+    pub fn saved(&mut self, edit_count: u64) {
+        self.since_last_save = Duration::ZERO;
+        self.saved_edit_count = edit_count;
+    }
+}
+
+// Where the application keeps its autosaves
+pub fn default_autosave_dir() -> PathBuf {
+    std::env::temp_dir().join("mochi_autosaves")
+}
+
+// The autosaves are named after the process writing them, so that the ones left by a
+// previous session are told apart from the ones of the running editor
+pub fn current_session() -> u32 {
+    std::process::id()
+}
+
+// The autosaves are layered TIFF images written by Document::export_tiff: the layers are kept
+// with their names and labels, the groups, masks, blend settings and the selection are not
+pub fn autosave_path(dir: &Path, session: u32, document: DocumentId, slot: usize) -> PathBuf {
+    dir.join(format!(
+        "{AUTOSAVE_PREFIX}_{session}_{}_{slot}.{AUTOSAVE_EXTENSION}",
+        document.0
+    ))
+}
+
+// The session and the document of a path built by autosave_path
+fn parse_autosave_path(path: &Path) -> Option<(u32, u64)> {
+    if path.extension()? != AUTOSAVE_EXTENSION {
+        return None;
+    }
+    let name = path.file_stem()?.to_str()?;
+    let mut parts = name
+        .strip_prefix(AUTOSAVE_PREFIX)?
+        .strip_prefix('_')?
+        .split('_');
+    let session = parts.next()?.parse().ok()?;
+    let document = parts.next()?.parse().ok()?;
+    let _slot: usize = parts.next()?.parse().ok()?;
+    match parts.next() {
+        None => Some((session, document)),
+        Some(_) => None,
+    }
+}
+
+fn autosave_files(dir: &Path) -> Vec<(PathBuf, u32, u64)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let (session, document) = parse_autosave_path(&path)?;
+            Some((path, session, document))
+        })
+        .collect()
+}
+
+// The most recent autosave of each document left by the other sessions, e.g. one that
+// crashed, from the oldest to the newest
+pub fn recoverable_autosaves(dir: &Path, current_session: u32) -> Vec<PathBuf> {
+    let mut latest: HashMap<(u32, u64), (SystemTime, PathBuf)> = HashMap::new();
+    for (path, session, document) in autosave_files(dir) {
+        if session == current_session {
+            continue;
+        }
+        let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(_) => continue,
+        };
+        match latest.get(&(session, document)) {
+            Some((newest, _)) if *newest >= modified => {}
+            _ => {
+                latest.insert((session, document), (modified, path));
+            }
+        }
+    }
+    let mut autosaves: Vec<_> = latest.into_values().collect();
+    autosaves.sort_by_key(|(modified, _)| *modified);
+    autosaves.into_iter().map(|(_, path)| path).collect()
+}
+
+// Once they've been offered for recovery, the autosaves of the other sessions aren't needed
+pub fn remove_other_sessions_autosaves(dir: &Path, current_session: u32) {
+    for (path, session, _) in autosave_files(dir) {
+        if session != current_session {
+            remove_autosave(&path);
+        }
+    }
+}
+
+// The autosaves of a document aren't needed anymore once it's saved or closed,
+// the ones of the other documents are left alone
+pub fn remove_autosaves(dir: &Path, session: u32, document: DocumentId) {
+    for slot in 0..AUTOSAVE_BACKUPS {
+        remove_autosave(&autosave_path(dir, session, document, slot));
+    }
+}
+
+fn remove_autosave(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            log::warn!("Could not remove {}: {e}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    use crate::DocumentId;
+
+    use super::{
+        autosave_path, recoverable_autosaves, remove_autosaves, remove_other_sessions_autosaves,
+        AutosaveScheduler, AUTOSAVE_BACKUPS,
+    };
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    // Each test gets its own directory, the autosaves of a running editor are left alone
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("mochi_autosave_test_{}_{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_autosaves(dir: &Path, session: u32, document: DocumentId) {
+        for slot in 0..AUTOSAVE_BACKUPS {
+            std::fs::write(autosave_path(dir, session, document, slot), []).unwrap();
+        }
+    }
+
+    #[test]
+    fn clean_documents_are_never_saved() {
+        let mut scheduler = AutosaveScheduler::new(SECOND * 10, Some(5));
+        for _ in 0..100 {
+            assert!(!scheduler.tick(SECOND, 0));
+        }
+    }
+
+    #[test]
+    fn dirty_documents_are_saved_after_the_interval() {
+        let mut scheduler = AutosaveScheduler::new(SECOND * 10, None);
+        // The time spent without edits doesn't count
+        assert!(!scheduler.tick(SECOND * 60, 0));
+        for _ in 0..9 {
+            assert!(!scheduler.tick(SECOND, 1));
+        }
+        assert!(scheduler.tick(SECOND, 1));
+
+        scheduler.saved(1);
+        assert!(!scheduler.tick(SECOND * 60, 1));
+        // Undoing the saved edit makes the document dirty again
+        assert!(!scheduler.tick(SECOND * 5, 2));
+        assert!(scheduler.tick(SECOND * 5, 2));
+    }
+
+    #[test]
+    fn enough_edits_trigger_a_save_before_the_interval() {
+        let mut scheduler = AutosaveScheduler::new(SECOND * 60, Some(3));
+        assert!(!scheduler.tick(SECOND, 2));
+        assert!(scheduler.tick(SECOND, 3));
+        scheduler.saved(3);
+        assert!(!scheduler.tick(SECOND, 5));
+        assert!(scheduler.tick(SECOND, 6));
+    }
+
+    #[test]
+    fn only_the_saved_document_autosaves_are_removed() {
+        let dir = test_dir("removed");
+        write_autosaves(&dir, 1, DocumentId(0));
+        write_autosaves(&dir, 1, DocumentId(1));
+
+        remove_autosaves(&dir, 1, DocumentId(0));
+        let recoverable = recoverable_autosaves(&dir, 2);
+        assert_eq!(recoverable.len(), 1);
+        assert!(recoverable[0].starts_with(&dir));
+        assert!(!autosave_path(&dir, 1, DocumentId(0), 0).exists());
+        assert!(autosave_path(&dir, 1, DocumentId(1), 0).exists());
+        // Nothing to remove is fine too
+        remove_autosaves(&dir, 1, DocumentId(0));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_running_session_autosaves_are_not_recovered() {
+        let dir = test_dir("sessions");
+        write_autosaves(&dir, 1, DocumentId(0));
+        write_autosaves(&dir, 2, DocumentId(0));
+        std::fs::write(dir.join("unrelated.tiff"), []).unwrap();
+
+        let recoverable = recoverable_autosaves(&dir, 2);
+        assert_eq!(recoverable.len(), 1);
+        assert!((0..AUTOSAVE_BACKUPS)
+            .any(|slot| recoverable[0] == autosave_path(&dir, 1, DocumentId(0), slot)));
+
+        remove_other_sessions_autosaves(&dir, 2);
+        assert!(recoverable_autosaves(&dir, 2).is_empty());
+        assert!(autosave_path(&dir, 2, DocumentId(0), 0).exists());
+        assert!(dir.join("unrelated.tiff").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_directories_have_nothing_to_recover() {
+        let dir = test_dir("missing");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(recoverable_autosaves(&dir, 1).is_empty());
+        remove_other_sessions_autosaves(&dir, 1);
+    }
+}
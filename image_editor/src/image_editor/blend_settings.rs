@@ -43,17 +43,48 @@ impl BlendMode {
     fn as_i32(&self) -> i32 {
         *self as i32
     }
+
+    // Mirrors select_blend_mode in blend_modes.wgsl, for the layers composited on the CPU.
+    // All the blend modes work on each linear channel on its own
+    pub(crate) fn blend(&self, bottom: f32, top: f32) -> f32 {
+        match self {
+            BlendMode::Normal => top,
+            BlendMode::Multiply => bottom * top,
+            BlendMode::Screen => 1.0 - (1.0 - bottom) * (1.0 - top),
+            BlendMode::Overlay => {
+                if bottom < 0.5 {
+                    2.0 * bottom * top
+                } else {
+                    1.0 - 2.0 * (1.0 - bottom) * (1.0 - top)
+                }
+            }
+            BlendMode::SoftLight => (1.0 - 2.0 * top) * bottom * bottom + 2.0 * top * bottom,
+            BlendMode::ColorDodge => bottom / (1.0 - top),
+            BlendMode::ColorBurn => 1.0 - bottom / top,
+            BlendMode::Add => bottom + top,
+            BlendMode::Divide => top / bottom,
+            BlendMode::Subtract => top - bottom,
+            BlendMode::Difference => (bottom - top).abs(),
+            BlendMode::Darken => bottom.min(top),
+            BlendMode::Lighten => bottom.max(top),
+        }
+    }
 }
 
 pub struct BlendSettings {
     pub blend_mode: BlendMode,
+    pub opacity: f32,
+    // See Document::set_linear_blending
+    pub linear: bool,
 }
 
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct BlendSettingsUniform {
     blend_mode: i32,
-    padding: [f32; 3],
+    opacity: f32,
+    linear: i32,
+    padding: f32,
 }
 
 unsafe impl bytemuck::Zeroable for BlendSettingsUniform {}
@@ -63,7 +94,9 @@ impl From<BlendSettings> for BlendSettingsUniform {
     fn from(settings: BlendSettings) -> Self {
         Self {
             blend_mode: settings.blend_mode.as_i32(),
-            padding: [0.0; 3],
+            opacity: settings.opacity,
+            linear: settings.linear as i32,
+            padding: 0.0,
         }
     }
 }
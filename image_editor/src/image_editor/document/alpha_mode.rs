@@ -0,0 +1,56 @@
+use image::DynamicImage;
+
+// How the alpha is stored in the exported images: the composite of the documents is straight,
+// the premultiplied images have their colors already multiplied by the alpha
+#[derive(
+    Clone, Copy, PartialEq, Eq, Default, Debug, strum_macros::EnumIter, strum_macros::Display,
+)]
+pub enum AlphaMode {
+    #[default]
+    Straight,
+    Premultiplied,
+}
+
+impl AlphaMode {
+    // The image must have straight alpha, like the composite
+    pub fn convert(&self, image: DynamicImage) -> DynamicImage {
+        match self {
+            AlphaMode::Straight => image,
+            AlphaMode::Premultiplied => {
+                let mut image = image.into_rgba8();
+                for pixel in image.pixels_mut() {
+                    pixel.0 = premultiply(pixel.0);
+                }
+                DynamicImage::ImageRgba8(image)
+            }
+        }
+    }
+}
+
+fn premultiply([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    let multiply = |c: u8| ((c as u32 * a as u32 + 127) / 255) as u8;
+    [multiply(r), multiply(g), multiply(b), a]
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    use super::AlphaMode;
+
+    #[test]
+    fn half_transparent_red() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 128])));
+        let convert = |mode: AlphaMode| mode.convert(image.clone()).into_rgba8().get_pixel(1, 1).0;
+        assert_eq!(convert(AlphaMode::Straight), [255, 0, 0, 128]);
+        assert_eq!(convert(AlphaMode::Premultiplied), [128, 0, 0, 128]);
+
+        // The opaque and the fully transparent pixels are the same in both modes
+        let image = RgbaImage::from_fn(2, 1, |x, _| Rgba([10, 20, 30, 255 * x as u8]));
+        let premultiplied = AlphaMode::Premultiplied
+            .convert(DynamicImage::ImageRgba8(image))
+            .into_rgba8();
+        assert_eq!(premultiplied.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(premultiplied.get_pixel(1, 0).0, [10, 20, 30, 255]);
+    }
+}
@@ -0,0 +1,31 @@
+use framework::SrgbColor;
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum DocumentBackground {
+    #[default]
+    Transparent,
+    Solid([u8; 4]),
+    // Drawn with the checker background shader
+    Checker,
+}
+
+impl DocumentBackground {
+    pub(crate) fn clear_color(&self) -> wgpu::Color {
+        match self {
+            DocumentBackground::Transparent => wgpu::Color::TRANSPARENT,
+            DocumentBackground::Solid(color) => wgpu::Color::from_srgb_u8(*color),
+            DocumentBackground::Checker => wgpu::Color::WHITE,
+        }
+    }
+}
+
+impl std::fmt::Display for DocumentBackground {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pretty_text = match self {
+            DocumentBackground::Transparent => "Transparent",
+            DocumentBackground::Solid(..) => "Solid color",
+            DocumentBackground::Checker => "Checkerboard",
+        };
+        f.write_str(pretty_text)
+    }
+}
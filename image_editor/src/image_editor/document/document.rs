@@ -1,17 +1,39 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    f32::consts::{FRAC_PI_2, PI},
+    fmt::Display,
+    future::Future,
+    path::Path,
+    sync::atomic::AtomicBool,
+};
 
+use crate::blend_settings::BlendMode;
+use crate::document::{AlphaMode, DocumentBackground, Palette};
+use crate::filters::{
+    self, FilterError, NoiseKind, NoiseParameters, NoiseSettingsUniform, UnsharpMaskParameters,
+    UnsharpMaskSettingsUniform,
+};
 use crate::image_editor::ab_render_target::ABRenderTarget;
+use crate::image_editor::exr_export;
+use crate::image_editor::gif_export::DitherKind;
 use crate::image_editor::image_editor::LayerCreationType;
-use crate::layers::{CanvasRenderingStrategy, Layer, LayerId, LayerItem, LayerRenderingStrategy};
+use crate::image_editor::tiff_io::{self, TiffPage};
+use crate::layers::{
+    CanvasRenderingStrategy, ChunkDiff, ChunkedLayer, CpuBlittingStrategy, Layer, LayerId,
+    LayerItem, LayerPosition, LayerRenderingStrategy, LayerSettings, RenderingStrategyKind,
+};
 use crate::{
     global_selection_data,
     layers::{LayerCreationInfo, LayerTree, LayerType},
-    selection::{Selection, SelectionAddition, SelectionShape},
-    LayerConstructionInfo,
+    selection::{Selection, SelectionAddition, SelectionDisplay, SelectionShape, Shape},
+    LayerConstructionInfo, SELECTED_STENCIL_VALUE,
+};
+use cgmath::{
+    point2, point3, vec2, EuclideanSpace, Matrix4, Point2, Rad, SquareMatrix, Transform, Vector2,
 };
-use cgmath::{point2, vec2, SquareMatrix, Vector2};
 use framework::{
-    framework::DepthStencilTextureId,
+    buffer::BufferInitialSetup,
+    framework::{DepthStencilTextureId, MaskTextureId, ShaderId},
     renderer::draw_command::{DrawCommand, DrawMode, OptionalDrawData, PrimitiveType},
     Framework,
 };
@@ -22,20 +44,110 @@ use framework::{
         renderer::{DepthStencilUsage, Renderer},
     },
     scene::Camera2d,
-    Box2d, DepthStencilTexture2D, RgbaTexture2D, Texture, TextureConfiguration, TextureUsage,
+    Box2d, BufferConfiguration, BufferType, ColorSpace, DepthStencilTexture2D, R8Texture2D,
+    RgbaTexture2D, TexelConversionError, Texture, TextureConfiguration, TextureUsage, Transform2d,
 };
 use framework::{math, RgbaU8};
-use image::{DynamicImage, ImageBuffer};
+use image::{DynamicImage, ImageBuffer, RgbaImage};
+
+// The selection masks are drawn either on an RGBA texture, whose alpha is the mask, or directly
+// on a single channel texture
+#[derive(Clone, Copy)]
+enum SelectionMaskTarget<'a> {
+    Rgba(&'a TextureId),
+    R8(&'a MaskTextureId),
+}
+
+impl SelectionMaskTarget<'_> {
+    // Draws on the pixels whose stencil value is the selected one, or on the other ones
+    fn masked_shader(self, selected: bool) -> ShaderId {
+        let globals = global_selection_data();
+        match (self, selected) {
+            (Self::Rgba(_), true) => globals.draw_masked_stencil_buffer_shader_id.clone(),
+            (Self::Rgba(_), false) => globals
+                .draw_masked_inverted_stencil_buffer_shader_id
+                .clone(),
+            (Self::R8(_), true) => globals.draw_masked_stencil_buffer_r8_shader_id.clone(),
+            (Self::R8(_), false) => globals
+                .draw_masked_inverted_stencil_buffer_r8_shader_id
+                .clone(),
+        }
+    }
+
+    fn coverage_shader(self, adds_coverage: bool) -> ShaderId {
+        let globals = global_selection_data();
+        match (self, adds_coverage) {
+            (Self::Rgba(_), true) => globals.selection_coverage_shader.clone(),
+            (Self::Rgba(_), false) => globals.selection_coverage_subtract_shader.clone(),
+            (Self::R8(_), true) => globals.selection_coverage_r8_shader.clone(),
+            (Self::R8(_), false) => globals.selection_coverage_subtract_r8_shader.clone(),
+        }
+    }
+
+    fn end(
+        self,
+        renderer: &mut Renderer,
+        depth_stencil_output: Option<(&DepthStencilTextureId, DepthStencilUsage)>,
+        framework: &mut Framework,
+    ) {
+        match self {
+            Self::Rgba(texture) => renderer.end(texture, depth_stencil_output, framework),
+            Self::R8(texture) => renderer.end_on_mask(texture, depth_stencil_output, framework),
+        }
+    }
+}
 
 pub struct SelectionLayer {
     pub layer: Layer,
     pub original_layer: LayerId,
 }
 
+// A layer removed from the document, along with its children if it's a group:
+// it can be put back with Document::restore_layer
+pub struct DetachedLayer {
+    item: LayerItem,
+    position: LayerPosition,
+    layers: Vec<Layer>,
+}
+
+impl DetachedLayer {
+    pub fn id(&self) -> &LayerId {
+        self.item.id()
+    }
+
+    pub fn position(&self) -> &LayerPosition {
+        &self.position
+    }
+}
+
+// A merged layer shown above the layers it was made from, see Document::merge_down_preview
+struct PendingMerge {
+    merged: LayerId,
+    // The source layers with their opacity: they're hidden while the preview is shown
+    sources: Vec<(LayerId, f32)>,
+    preview_shown: bool,
+}
+
+// The composite of a top level item and all the items below it
+struct CachedComposite {
+    item: LayerItem,
+    texture: TextureId,
+}
+
+// Where the document was looked at from, restored when switching back to it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewState {
+    pub position: Point2<f32>,
+    // The size of a screen pixel in document pixels
+    pub scale: f32,
+    pub rotation: Rad<f32>,
+}
+
 pub struct Document {
     document_size: Vector2<u32>,
     tree: LayerTree<Layer>,
-    rendering_strategy: CanvasRenderingStrategy,
+    rendering_strategy: Box<dyn LayerRenderingStrategy<Layer>>,
+    rendering_strategy_kind: RenderingStrategyKind,
     selection_layer: Option<SelectionLayer>,
 
     #[allow(dead_code)]
@@ -45,25 +157,226 @@ pub struct Document {
     wants_selection_update: bool,
     stencil_texture: DepthStencilTextureId,
     render_result: TextureId,
+    background: DocumentBackground,
+    export_background: bool,
+    export_alpha_mode: AlphaMode,
+    // Only used when the background must be left out of the exported image
+    export_result: Option<TextureId>,
+    dpi: f32,
+    // Only used when exporting to the GIF palette
+    gif_dither: DitherKind,
+    // Set when the document is indexed, see set_palette_mode
+    palette: Option<Palette>,
+    // While it's active the brush paints the selection mask, see toggle_quick_mask
+    quick_mask: Option<Layer>,
+    // One entry for each top level item from the bottom one up, see composite_layers
+    composite_cache: Vec<CachedComposite>,
+    // How many layers were blended by the last render
+    composited_layers: usize,
+    // See set_depth_compositing
+    depth_compositing: bool,
+    // See set_linear_blending
+    linear_blending: bool,
+    depth_texture: Option<DepthStencilTextureId>,
+    // Saved when another document replaces this one, see ImageEditor::switch_to
+    view: Option<ViewState>,
+    pending_merge: Option<PendingMerge>,
 }
 
+// Linear, the shader premultiplies it
+const SELECTION_OVERLAY_COLOR: wgpu::Color = wgpu::Color {
+    r: 1.0,
+    g: 0.0,
+    b: 0.0,
+    a: 0.5,
+};
+
+pub const DEFAULT_DPI: f32 = 72.0;
+pub const MIN_DPI: f32 = 1.0;
+pub const MAX_DPI: f32 = 9600.0;
+
 pub struct DocumentCreationInfo {
     pub width: u32,
     pub height: u32,
     pub first_layer_color: [f32; 4],
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentSizeError {
+    Empty {
+        width: u32,
+        height: u32,
+    },
+    // The layers are textures, so no side can be bigger than the device allows
+    TooLarge {
+        width: u32,
+        height: u32,
+        max_side: u32,
+    },
+}
+
+impl Display for DocumentSizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DocumentSizeError::Empty { width, height } => {
+                write!(f, "A document can't be {width}x{height} pixels")
+            }
+            DocumentSizeError::TooLarge {
+                width,
+                height,
+                max_side,
+            } => write!(
+                f,
+                "A document can't be {width}x{height} pixels, the sides can be at most {max_side} pixels"
+            ),
+        }
+    }
+}
+impl std::error::Error for DocumentSizeError {}
+
+// Why the selection couldn't be moved to a selection layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtractSelectionError {
+    NotAnImageLayer,
+    GroupLayer,
+}
+
+impl Display for ExtractSelectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractSelectionError::NotAnImageLayer => {
+                write!(f, "The selection can only be extracted from image layers")
+            }
+            ExtractSelectionError::GroupLayer => {
+                write!(f, "Cannot extract the selection of a group layer")
+            }
+        }
+    }
+}
+impl std::error::Error for ExtractSelectionError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StraightenAxis {
+    Horizontal,
+    Vertical,
+}
+
+// The rotation making the line from start to end parallel to the axis, in (-90°, 90°]:
+// the line is the same when its points are swapped. None when the points are the same
+pub fn straighten_angle(
+    start: Point2<f32>,
+    end: Point2<f32>,
+    axis: StraightenAxis,
+) -> Option<Rad<f32>> {
+    let delta = end - start;
+    if delta.x == 0.0 && delta.y == 0.0 {
+        return None;
+    }
+    let target = match axis {
+        StraightenAxis::Horizontal => 0.0,
+        StraightenAxis::Vertical => FRAC_PI_2,
+    };
+    let mut correction = (target - delta.y.atan2(delta.x)) % PI;
+    if correction <= -FRAC_PI_2 {
+        correction += PI;
+    } else if correction > FRAC_PI_2 {
+        correction -= PI;
+    }
+    Some(Rad(correction))
+}
+
+pub fn check_document_size(
+    width: u32,
+    height: u32,
+    framework: &Framework,
+) -> Result<(), DocumentSizeError> {
+    let max_side = framework.device.limits().max_texture_dimension_2d;
+    if width == 0 || height == 0 {
+        Err(DocumentSizeError::Empty { width, height })
+    } else if width > max_side || height > max_side {
+        Err(DocumentSizeError::TooLarge {
+            width,
+            height,
+            max_side,
+        })
+    } else {
+        Ok(())
+    }
+}
+
 impl Document {
-    pub fn new(config: DocumentCreationInfo, framework: &mut Framework) -> Self {
+    pub fn new(
+        config: DocumentCreationInfo,
+        framework: &mut Framework,
+    ) -> Result<Self, DocumentSizeError> {
+        check_document_size(config.width, config.height, framework)?;
+        let background =
+            RgbaTexture2D::from_repeated_texel(RgbaU8([255; 4]), (config.width, config.height))
+                .unwrap();
+        Ok(Self::with_background_layer(config, background, framework))
+    }
+
+    // The document has the same size as the image, which becomes the background layer.
+    // Grayscale and palette images are converted to RGBA
+    pub fn from_image(
+        image: &DynamicImage,
+        framework: &mut Framework,
+    ) -> Result<Self, DocumentSizeError> {
+        let (width, height) = (image.width(), image.height());
+        check_document_size(width, height, framework)?;
+        let background =
+            RgbaTexture2D::from_bytes(&image.to_rgba8().into_raw(), (width, height)).unwrap();
+        Ok(Self::with_background_layer(
+            DocumentCreationInfo {
+                width,
+                height,
+                first_layer_color: [0.0; 4],
+            },
+            background,
+            framework,
+        ))
+    }
+
+    fn with_background_layer(
+        config: DocumentCreationInfo,
+        background: RgbaTexture2D,
+        framework: &mut Framework,
+    ) -> Self {
+        let mut document = Self::without_layers(&config, framework);
+        let background_layer = Layer::new_image(
+            background,
+            LayerCreationInfo {
+                name: "Background Layer".into(),
+                position: point2(0.0, 0.0),
+                scale: vec2(1.0, 1.0),
+                rotation_radians: 0.0,
+            },
+            framework,
+        );
+        document.insert_layer(background_layer, framework);
+        document.add_layer(
+            LayerConstructionInfo {
+                initial_color: [0; 4],
+                layer_type: LayerCreationType::Raster,
+                name: "Layer 0".into(),
+            },
+            framework,
+        );
+
+        document
+    }
+
+    fn without_layers(config: &DocumentCreationInfo, framework: &mut Framework) -> Self {
         let stencil_texture = framework.allocate_depth_stencil_texture(
             DepthStencilTexture2D::empty((config.width, config.height)),
             TextureConfiguration {
                 label: Some("Selection stencil texture"),
                 usage: TextureUsage::RWRT,
                 mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
         );
-        let mut document = Self {
+        Self {
             document_size: vec2(config.width, config.height),
             selection_layer: None,
             buffer_texture: framework.allocate_texture2d(
@@ -72,6 +385,7 @@ impl Document {
                     label: Some("document buffer texture"),
                     usage: TextureUsage::RWRT,
                     mip_count: None,
+                    color_space: ColorSpace::Srgb,
                 },
             ),
             selection: Selection::default(),
@@ -79,35 +393,67 @@ impl Document {
             wants_selection_update: false,
             stencil_texture,
             tree: LayerTree::new(),
-            rendering_strategy: CanvasRenderingStrategy::new(framework, &config),
+            rendering_strategy: Self::make_rendering_strategy(
+                RenderingStrategyKind::default(),
+                config,
+                framework,
+            ),
+            rendering_strategy_kind: RenderingStrategyKind::default(),
             render_result: framework.allocate_texture2d(
                 RgbaTexture2D::empty((1, 1)),
                 TextureConfiguration {
                     label: None,
                     usage: TextureUsage::RWRT,
                     mip_count: None,
+                    color_space: ColorSpace::Srgb,
                 },
             ),
-        };
-
-        document.add_layer(
-            LayerConstructionInfo {
-                initial_color: [255; 4],
-                layer_type: LayerCreationType::Image,
-                name: "Background Layer".into(),
-            },
-            framework,
-        );
-        document.add_layer(
-            LayerConstructionInfo {
-                initial_color: [0; 4],
-                layer_type: LayerCreationType::Raster,
-                name: "Layer 0".into(),
-            },
-            framework,
-        );
+            background: DocumentBackground::default(),
+            export_background: true,
+            export_alpha_mode: AlphaMode::default(),
+            export_result: None,
+            dpi: DEFAULT_DPI,
+            gif_dither: DitherKind::default(),
+            palette: None,
+            quick_mask: None,
+            composite_cache: vec![],
+            composited_layers: 0,
+            depth_compositing: false,
+            linear_blending: true,
+            depth_texture: None,
+            view: None,
+            pending_merge: None,
+        }
+    }
 
-        document
+    // Each page becomes an image layer named after the page, the first page is the bottom layer.
+    // The document has the size of the first page, the unknown color labels are left out
+    pub fn import_tiff(path: &Path, framework: &mut Framework) -> tiff::TiffResult<Self> {
+        let pages = tiff_io::read_tiff(path)?;
+        let config = DocumentCreationInfo {
+            width: pages[0].width,
+            height: pages[0].height,
+            first_layer_color: [0.0; 4],
+        };
+        let mut document = Self::without_layers(&config, framework);
+        for (index, page) in pages.into_iter().enumerate() {
+            let mut layer = Layer::new_image(
+                RgbaTexture2D::from_texels(page.texels, (page.width, page.height)).unwrap(),
+                LayerCreationInfo {
+                    name: page.name.unwrap_or_else(|| format!("Page {index}")),
+                    position: point2(0.0, 0.0),
+                    scale: vec2(1.0, 1.0),
+                    rotation_radians: 0.0,
+                },
+                framework,
+            );
+            layer.set_settings(LayerSettings {
+                label: page.label.and_then(|label| label.parse().ok()),
+                ..layer.settings().clone()
+            });
+            document.insert_layer(layer, framework);
+        }
+        Ok(document)
     }
 
     pub fn current_layer(&self) -> &Layer {
@@ -122,6 +468,18 @@ impl Document {
         self.tree.select_layer(new_current_layer)
     }
 
+    pub fn selected_layers(&self) -> &HashSet<LayerId> {
+        self.tree.selected_layers()
+    }
+
+    pub fn toggle_layer_selection(&mut self, layer: LayerId) {
+        self.tree.toggle_layer_selection(layer)
+    }
+
+    pub fn select_layer_range(&mut self, last_layer: LayerId) {
+        self.tree.select_layer_range(last_layer)
+    }
+
     pub fn get_layer(&self, layer_index: &LayerId) -> &Layer {
         self.tree.get_layer(layer_index)
     }
@@ -131,6 +489,26 @@ impl Document {
         mutate_fn(layer);
     }
 
+    // Rotates the layer so that the line between two points in world space becomes parallel
+    // to the axis, e.g. to straighten a scan. Returns the rotation added to the layer
+    pub fn straighten(
+        &mut self,
+        layer_id: &LayerId,
+        start: Point2<f32>,
+        end: Point2<f32>,
+        axis: StraightenAxis,
+    ) -> Option<Rad<f32>> {
+        if matches!(self.get_layer(layer_id).layer_type, LayerType::Group) {
+            return None;
+        }
+        let correction = straighten_angle(start, end, axis)?;
+        self.mutate_layer(layer_id, |layer| {
+            let rotation = layer.transform().rotation_radians + correction;
+            layer.set_rotation(rotation.0);
+        });
+        Some(correction)
+    }
+
     pub fn mutate_selection<F: FnOnce(&mut Selection)>(&mut self, callback: F) {
         callback(&mut self.selection);
         self.wants_selection_update = true;
@@ -168,7 +546,7 @@ impl Document {
                 .as_str(),
             );
             renderer.set_stencil_clear(None);
-            renderer.set_stencil_reference(if additive { 255 } else { 0 });
+            renderer.set_stencil_reference(if additive { SELECTED_STENCIL_VALUE } else { 0 });
             match shape.shape {
                 crate::selection::Shape::Rectangle(rect) => {
                     renderer.draw(DrawCommand {
@@ -213,126 +591,555 @@ impl Document {
         &self.selection
     }
 
-    pub fn draw_selection(&self, renderer: &mut Renderer) {
-        let extents = self.document_size.cast::<f32>().unwrap() * 0.5;
-        renderer.draw(DrawCommand {
-            primitives: PrimitiveType::Rect {
-                rects: vec![Box2d {
-                    center: point2(0.0, 0.0),
-                    extents,
-                }],
-                multiply_color: wgpu::Color::RED,
-            },
-            draw_mode: DrawMode::Single,
-            additional_data: OptionalDrawData {
-                additional_vertex_buffers: vec![],
-                additional_bindable_resource: vec![BindableResource::StencilTexture(
-                    self.stencil_texture.clone(),
-                )],
-                shader: Some(global_selection_data().dotted_shader.clone()),
-            },
-        });
-    }
-
-    pub fn join_layers(
-        &mut self,
-        layer_below_idx: &LayerId,
-        layer_top_idx: &LayerId,
-        renderer: &mut Renderer,
-        framework: &mut Framework,
-    ) {
-        let layer_below = self.get_layer(layer_below_idx);
-        let layer_top = self.get_layer(layer_top_idx);
-
-        join_bitmaps(&layer_below, &layer_top, renderer, framework);
-    }
-
-    pub fn join_with_layer_below(
-        &mut self,
-        top: &LayerId,
+    // Rasterizes the selection (without the partial selection) into a mask as big as the document,
+    // where the selected pixels are 255 and the others are 0. The rows go from top to bottom.
+    // With aa the pixels crossed by the edges of the selection are partially selected
+    pub fn selection_mask_texture(
+        &self,
+        aa: bool,
         renderer: &mut Renderer,
         framework: &mut Framework,
-    ) {
-        let layer = self.tree.find_below(top);
-        if let Some(below) = layer {
-            self.join_layers(&below, top, renderer, framework)
-        }
-    }
-
-    pub fn extract_selection(&mut self, renderer: &mut Renderer, framework: &mut Framework) {
-        let current_layer = self.current_layer();
-        let dims = current_layer.size();
-        let dims = (dims.x, dims.y);
-
-        let tex = match current_layer.layer_type {
-            LayerType::Image { ref texture, .. } => texture.clone(),
-            LayerType::Chonky(..) => todo!(),
-            LayerType::Group => unreachable!(),
-        };
-        let new_texture = framework.allocate_texture2d(
-            RgbaTexture2D::empty(dims),
+    ) -> R8Texture2D {
+        let mask_texture = framework.allocate_mask_texture(
+            R8Texture2D::empty((self.document_size.x, self.document_size.y)),
             TextureConfiguration {
-                label: Some(&(current_layer.settings().clone().name + " clone texture")),
+                label: Some("Selection mask texture"),
                 usage: TextureUsage::RWRT,
                 mip_count: None,
+                color_space: ColorSpace::Linear,
             },
         );
-        let old_texture_copy = framework.allocate_texture2d(
-            RgbaTexture2D::empty(dims),
+        self.draw_selection_mask(
+            aa,
+            SelectionMaskTarget::R8(&mask_texture),
+            renderer,
+            framework,
+        );
+        let mask = framework
+            .mask_texture_read_data(&mask_texture)
+            .expect("Document: failed to read the selection mask");
+        framework.free_mask_texture(mask_texture);
+        mask
+    }
+
+    // The selection drawn in white on a transparent texture as big as the document, the alpha
+    // is the coverage of each pixel. The texture must be freed by the caller
+    fn render_selection_mask(
+        &self,
+        aa: bool,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> TextureId {
+        let mask_texture = framework.allocate_texture2d(
+            RgbaTexture2D::empty((self.document_size.x, self.document_size.y)),
             TextureConfiguration {
-                label: Some(&(current_layer.settings().clone().name + " texture")),
+                label: Some("Selection mask texture"),
                 usage: TextureUsage::RWRT,
                 mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
         );
+        self.draw_selection_mask(
+            aa,
+            SelectionMaskTarget::Rgba(&mask_texture),
+            renderer,
+            framework,
+        );
+        mask_texture
+    }
 
-        // 1. Draw layer using the rect stencil buffer, this is the selection. Store it into a new texture
+    fn draw_selection_mask(
+        &self,
+        aa: bool,
+        target: SelectionMaskTarget,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) {
+        if aa {
+            self.draw_selection_coverage(target, renderer, framework);
+            return;
+        }
+
+        self.clear_stencil_buffer(renderer, framework);
+        self.draw_shapes_on_stencil_buffer(&self.selection.shapes, renderer, framework);
         renderer.begin(
-            &Self::make_camera_for_layer(&current_layer),
+            &Camera2d::wh(self.document_size.x, self.document_size.y),
             Some(wgpu::Color::TRANSPARENT),
             framework,
         );
-        renderer.set_draw_debug_name("Selection tool: draw layer with stencil buffer");
+        renderer.set_draw_debug_name("Selection mask: draw document with stencil buffer");
         renderer.set_stencil_clear(None);
-        renderer.set_stencil_reference(255);
+        renderer.set_stencil_reference(SELECTED_STENCIL_VALUE);
         renderer.draw(DrawCommand {
-            primitives: PrimitiveType::Texture2D {
-                texture_id: tex.clone(),
-                instances: vec![current_layer.pixel_transform()],
-                flip_uv_y: true,
+            primitives: PrimitiveType::Rect {
+                rects: vec![Box2d {
+                    center: point2(0.0, 0.0),
+                    extents: self.document_size.cast::<f32>().unwrap() * 0.5,
+                }],
                 multiply_color: wgpu::Color::WHITE,
             },
             draw_mode: DrawMode::Single,
-            additional_data: OptionalDrawData::just_shader(Some(if self.selection.inverted {
-                global_selection_data()
-                    .draw_masked_inverted_stencil_buffer_shader_id
-                    .clone()
-            } else {
-                global_selection_data()
-                    .draw_masked_stencil_buffer_shader_id
-                    .clone()
-            })),
+            additional_data: OptionalDrawData::just_shader(Some(
+                target.masked_shader(!self.selection.inverted),
+            )),
         });
-        renderer.end(
-            &new_texture,
+        target.end(
+            renderer,
             Some((&self.stencil_texture, DepthStencilUsage::Stencil)),
             framework,
         );
+        // The stencil buffer also holds the partial selection, which is drawn on the canvas
+        self.update_selection_buffer(renderer, framework);
+    }
 
-        // 2. Draw the layer using the inverted stencil buffer: this is the remaining part of the texture
+    // Draws the selection shapes in order, with their coverage computed from their distance
+    // to each pixel. An inverted selection starts from a full mask and swaps the added and
+    // subtracted shapes, which gives the complement of the same coverage
+    fn draw_selection_coverage(
+        &self,
+        target: SelectionMaskTarget,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) {
+        let inverted = self.selection.inverted;
         renderer.begin(
-            &Self::make_camera_for_layer(&current_layer),
-            Some(wgpu::Color::TRANSPARENT),
+            &Camera2d::wh(self.document_size.x, self.document_size.y),
+            Some(if inverted {
+                wgpu::Color::WHITE
+            } else {
+                wgpu::Color::TRANSPARENT
+            }),
             framework,
         );
-        renderer.set_draw_debug_name("Selection tool: draw layer with inverted stencil buffer");
-        renderer.set_stencil_clear(None);
-        renderer.set_stencil_reference(255);
-        renderer.draw(DrawCommand {
-            primitives: PrimitiveType::Texture2D {
-                texture_id: tex,
-                instances: vec![current_layer.pixel_transform()],
-                flip_uv_y: true,
+        renderer.set_draw_debug_name("Selection mask: draw anti-aliased shapes");
+        for shape in self.selection.shapes.iter() {
+            let adds_coverage = (shape.mode == SelectionAddition::Add) != inverted;
+            let shader = target.coverage_shader(adds_coverage);
+            match shape.shape {
+                Shape::Rectangle(rect) => renderer.draw(DrawCommand {
+                    primitives: PrimitiveType::Rect {
+                        // The shader fades the edges in the extra pixel
+                        rects: vec![Box2d {
+                            center: rect.center,
+                            extents: rect.extents + vec2(1.0, 1.0),
+                        }],
+                        multiply_color: wgpu::Color::WHITE,
+                    },
+                    draw_mode: DrawMode::Single,
+                    additional_data: OptionalDrawData::just_shader(Some(shader)),
+                }),
+            }
+        }
+        target.end(renderer, None, framework);
+    }
+
+    // Paints the color on the selected pixels of the layer, see selection_mask_texture.
+    // Returns the diff of the modified chunks, so that the fill can be undone
+    pub fn fill_selection(
+        &mut self,
+        layer_id: &LayerId,
+        color: wgpu::Color,
+        aa: bool,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> Option<ChunkDiff> {
+        if self.selection_clip().is_none() {
+            log::warn!("Nothing is selected, there's nothing to fill");
+            return None;
+        }
+        let mask_texture = self.render_selection_mask(aa, renderer, framework);
+        let document_rect = Box2d {
+            center: point2(0.0, 0.0),
+            extents: self.document_size.cast::<f32>().unwrap() * 0.5,
+        };
+
+        let layer = self.tree.get_layer_mut(layer_id);
+        let (inv_layer_matrix, rendering_camera) = match (
+            layer.transform().matrix().invert(),
+            layer.rendering_camera(),
+        ) {
+            (Some(inv_layer_matrix), Some(rendering_camera)) => {
+                (inv_layer_matrix, rendering_camera)
+            }
+            _ => {
+                framework.free_texture2d(mask_texture);
+                return None;
+            }
+        };
+        let diff = match &mut layer.layer_type {
+            LayerType::Chonky(map) => {
+                let chunk_size = map.chunk_size() as f32;
+                let layer_rect = document_rect.transformed(inv_layer_matrix);
+                Some(map.edit(
+                    layer_rect.clone(),
+                    |chunk, _, chunk_world_position, framework| {
+                        renderer.begin(&rendering_camera, None, framework);
+                        renderer.set_draw_debug_name("Selection fill");
+                        renderer.set_viewport(Some((0.0, 0.0, chunk_size, chunk_size)));
+                        renderer.draw(DrawCommand {
+                            primitives: PrimitiveType::Texture2D {
+                                texture_id: mask_texture.clone(),
+                                instances: vec![Transform2d {
+                                    position: point3(
+                                        layer_rect.center.x - chunk_world_position.x,
+                                        layer_rect.center.y - chunk_world_position.y,
+                                        0.0,
+                                    ),
+                                    scale: layer_rect.extents,
+                                    ..Default::default()
+                                }],
+                                flip_uv_y: true,
+                                multiply_color: color,
+                            },
+                            draw_mode: DrawMode::Single,
+                            additional_data: OptionalDrawData::default(),
+                        });
+                        renderer.end(chunk, None, framework);
+                    },
+                    framework,
+                ))
+            }
+            LayerType::Image { .. } | LayerType::Group => {
+                log::warn!("Only raster layers can be filled");
+                None
+            }
+        };
+        framework.free_texture2d(mask_texture);
+        if diff.is_some() {
+            layer.mark_dirty();
+        }
+        diff
+    }
+
+    pub fn select_all(&mut self) {
+        let extents = self.document_size.cast::<f32>().unwrap() * 0.5;
+        self.mutate_selection(|s| {
+            s.set(SelectionShape {
+                mode: SelectionAddition::Add,
+                shape: Shape::Rectangle(Box2d {
+                    center: point2(0.0, 0.0),
+                    extents,
+                }),
+            })
+        });
+    }
+
+    pub fn deselect(&mut self) {
+        self.mutate_selection(|s| s.clear());
+    }
+
+    // Selects the pixels of the layer that aren't fully transparent,
+    // only raster layers have pixels, for the other layers the selection is cleared
+    pub fn select_opaque(&mut self, layer: &LayerId, framework: &Framework) {
+        let selection = match &self.get_layer(layer).layer_type {
+            LayerType::Chonky(map) => Self::chunked_layer_selection(map, framework, |a| a > 0),
+            _ => Selection::default(),
+        };
+        self.mutate_selection(|s| *s = selection);
+    }
+
+    pub fn draw_selection(&self, renderer: &mut Renderer) {
+        let extents = self.document_size.cast::<f32>().unwrap() * 0.5;
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Rect {
+                rects: vec![Box2d {
+                    center: point2(0.0, 0.0),
+                    extents,
+                }],
+                multiply_color: wgpu::Color::RED,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData {
+                additional_vertex_buffers: vec![],
+                additional_bindable_resource: vec![BindableResource::StencilTexture(
+                    self.stencil_texture.clone(),
+                )],
+                shader: Some(global_selection_data().dotted_shader.clone()),
+                scissor: None,
+            },
+        });
+    }
+
+    // The overlays tint the pixels with the selection stencil buffer, whose contents are
+    // the same whether the selection is inverted or not
+    pub fn draw_selection_overlay(&self, renderer: &mut Renderer, mode: SelectionDisplay) {
+        let tint_selected = match mode {
+            SelectionDisplay::Outline => return self.draw_selection(renderer),
+            SelectionDisplay::OverlaySelected => true,
+            SelectionDisplay::OverlayMasked => false,
+        };
+        let shader = if tint_selected != self.selection.inverted {
+            global_selection_data().selected_overlay_shader.clone()
+        } else {
+            global_selection_data().masked_overlay_shader.clone()
+        };
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Rect {
+                rects: vec![Box2d {
+                    center: point2(0.0, 0.0),
+                    extents: self.document_size.cast::<f32>().unwrap() * 0.5,
+                }],
+                multiply_color: SELECTION_OVERLAY_COLOR,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData {
+                additional_vertex_buffers: vec![],
+                additional_bindable_resource: vec![BindableResource::StencilTexture(
+                    self.stencil_texture.clone(),
+                )],
+                shader: Some(shader),
+                scissor: None,
+            },
+        });
+    }
+
+    pub fn is_quick_mask_active(&self) -> bool {
+        self.quick_mask.is_some()
+    }
+
+    pub fn quick_mask(&self) -> Option<&Layer> {
+        self.quick_mask.as_ref()
+    }
+
+    pub fn quick_mask_mut(&mut self) -> Option<&mut Layer> {
+        self.quick_mask.as_mut()
+    }
+
+    // Entering the quick mask starts from the current selection, leaving it replaces the
+    // selection with the pixels painted on the mask
+    pub fn toggle_quick_mask(&mut self, framework: &mut Framework) {
+        match self.quick_mask.take() {
+            Some(mask) => {
+                let selection = Self::quick_mask_selection(&mask, framework);
+                self.mutate_selection(|s| *s = selection);
+            }
+            None => {
+                let mut mask = Layer::new_chonky(LayerCreationInfo {
+                    name: "Quick mask".into(),
+                    position: point2(0.0, 0.0),
+                    scale: vec2(1.0, 1.0),
+                    rotation_radians: 0.0,
+                });
+                if self.selection_clip().is_some() {
+                    self.paint_selection_on_quick_mask(&mut mask, framework);
+                }
+                self.quick_mask = Some(mask);
+            }
+        }
+    }
+
+    // The selected pixels of the document are painted opaque, the mask isn't transformed
+    // so its pixels are the world ones
+    fn paint_selection_on_quick_mask(&self, mask: &mut Layer, framework: &mut Framework) {
+        let map = match &mut mask.layer_type {
+            LayerType::Chonky(map) => map,
+            _ => unreachable!(),
+        };
+        let chunk_size = map.chunk_size() as f32;
+        let half_size = self.document_size.cast::<f32>().unwrap() * 0.5;
+        // The chunk with index i covers the world from (i - 0.5) * size to (i + 0.5) * size
+        let first_chunk = point2(
+            (0.5 - half_size.x / chunk_size).floor() as i64,
+            (0.5 - half_size.y / chunk_size).floor() as i64,
+        );
+        let last_chunk = point2(
+            (half_size.x / chunk_size - 0.5).ceil() as i64,
+            (half_size.y / chunk_size - 0.5).ceil() as i64,
+        );
+        let width = (last_chunk.x - first_chunk.x + 1) as u32 * map.chunk_size();
+        let height = (last_chunk.y - first_chunk.y + 1) as u32 * map.chunk_size();
+        let top_left = point2(
+            (first_chunk.x as f32 - 0.5) * chunk_size,
+            (first_chunk.y as f32 - 0.5) * chunk_size + height as f32,
+        );
+        let texels = (0..width * height)
+            .map(|i| {
+                let x = top_left.x + (i % width) as f32 + 0.5;
+                let y = top_left.y - (i / width) as f32 - 0.5;
+                let in_document = x.abs() < half_size.x && y.abs() < half_size.y;
+                if in_document && self.selection.contains(point2(x, y)) {
+                    RgbaU8([255; 4])
+                } else {
+                    RgbaU8([0; 4])
+                }
+            })
+            .collect();
+        let texture = RgbaTexture2D::from_texels(texels, (width, height)).unwrap();
+        map.edit_from_texture(first_chunk, &texture, framework);
+    }
+
+    // A pixel is selected when the mask is at least half opaque
+    fn quick_mask_selection(mask: &Layer, framework: &Framework) -> Selection {
+        match &mask.layer_type {
+            LayerType::Chonky(map) => Self::chunked_layer_selection(map, framework, |a| a >= 128),
+            _ => unreachable!(),
+        }
+    }
+
+    fn chunked_layer_selection<F: Fn(u8) -> bool>(
+        map: &ChunkedLayer,
+        framework: &Framework,
+        is_selected: F,
+    ) -> Selection {
+        let (first_chunk, texture) = match map.read_into_texture(framework) {
+            Some(mask) => mask,
+            None => return Selection::default(),
+        };
+        let chunk_size = map.chunk_size() as f32;
+        let top_left = point2(
+            (first_chunk.x as f32 - 0.5) * chunk_size,
+            first_chunk.y as f32 * chunk_size + texture.height() as f32 - chunk_size * 0.5,
+        );
+        let selected: Vec<bool> = texture
+            .data()
+            .unwrap()
+            .iter()
+            .map(|RgbaU8([_, _, _, a])| is_selected(*a))
+            .collect();
+        Selection::from_mask(&selected, texture.width(), top_left)
+    }
+
+    pub fn draw_quick_mask(&self, renderer: &mut Renderer) {
+        let map = match &self.quick_mask {
+            Some(Layer {
+                layer_type: LayerType::Chonky(map),
+                ..
+            }) => map,
+            _ => return,
+        };
+        let chunk_extents = map.chunk_size() as f32 * 0.5;
+        map.iterate(|chunk, _, chunk_world_position| {
+            renderer.draw(DrawCommand {
+                primitives: PrimitiveType::Texture2D {
+                    texture_id: chunk.clone(),
+                    instances: vec![Transform2d {
+                        position: point3(chunk_world_position.x, chunk_world_position.y, 0.0),
+                        scale: vec2(chunk_extents, chunk_extents),
+                        ..Default::default()
+                    }],
+                    flip_uv_y: true,
+                    multiply_color: wgpu::Color {
+                        r: 1.0,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 0.5,
+                    },
+                },
+                draw_mode: DrawMode::Single,
+                additional_data: OptionalDrawData::just_shader(Some(
+                    global_selection_data().quick_mask_shader.clone(),
+                )),
+            });
+        });
+    }
+
+    pub fn join_layers(
+        &mut self,
+        layer_below_idx: &LayerId,
+        layer_top_idx: &LayerId,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) {
+        let layer_below = self.get_layer(layer_below_idx);
+        let layer_top = self.get_layer(layer_top_idx);
+
+        join_bitmaps(&layer_below, &layer_top, renderer, framework);
+        self.mutate_layer(layer_below_idx, |layer| layer.mark_dirty());
+    }
+
+    pub fn join_with_layer_below(
+        &mut self,
+        top: &LayerId,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) {
+        let layer = self.tree.find_below(top);
+        if let Some(below) = layer {
+            self.join_layers(&below, top, renderer, framework)
+        }
+    }
+
+    // Moves the selected pixels of the current layer to a new selection layer,
+    // fails when the current layer has no texture to extract them from
+    pub fn extract_selection(
+        &mut self,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> Result<(), ExtractSelectionError> {
+        let current_layer = self.current_layer();
+        let tex = match current_layer.layer_type {
+            LayerType::Image { ref texture, .. } => texture.clone(),
+            LayerType::Chonky(..) => return Err(ExtractSelectionError::NotAnImageLayer),
+            LayerType::Group => return Err(ExtractSelectionError::GroupLayer),
+        };
+        let dims = current_layer.size();
+        let dims = (dims.x, dims.y);
+        let new_texture = framework.allocate_texture2d(
+            RgbaTexture2D::empty(dims),
+            TextureConfiguration {
+                label: Some(&(current_layer.settings().clone().name + " clone texture")),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+        let old_texture_copy = framework.allocate_texture2d(
+            RgbaTexture2D::empty(dims),
+            TextureConfiguration {
+                label: Some(&(current_layer.settings().clone().name + " texture")),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+
+        // 1. Draw layer using the rect stencil buffer, this is the selection. Store it into a new texture
+        renderer.begin(
+            &Self::make_camera_for_layer(&current_layer),
+            Some(wgpu::Color::TRANSPARENT),
+            framework,
+        );
+        renderer.set_draw_debug_name("Selection tool: draw layer with stencil buffer");
+        renderer.set_stencil_clear(None);
+        renderer.set_stencil_reference(SELECTED_STENCIL_VALUE);
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Texture2D {
+                texture_id: tex.clone(),
+                instances: vec![current_layer.pixel_transform()],
+                flip_uv_y: true,
+                multiply_color: wgpu::Color::WHITE,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData::just_shader(Some(if self.selection.inverted {
+                global_selection_data()
+                    .draw_masked_inverted_stencil_buffer_shader_id
+                    .clone()
+            } else {
+                global_selection_data()
+                    .draw_masked_stencil_buffer_shader_id
+                    .clone()
+            })),
+        });
+        renderer.end(
+            &new_texture,
+            Some((&self.stencil_texture, DepthStencilUsage::Stencil)),
+            framework,
+        );
+
+        // 2. Draw the layer using the inverted stencil buffer: this is the remaining part of the texture
+        renderer.begin(
+            &Self::make_camera_for_layer(&current_layer),
+            Some(wgpu::Color::TRANSPARENT),
+            framework,
+        );
+        renderer.set_draw_debug_name("Selection tool: draw layer with inverted stencil buffer");
+        renderer.set_stencil_clear(None);
+        renderer.set_stencil_reference(SELECTED_STENCIL_VALUE);
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Texture2D {
+                texture_id: tex,
+                instances: vec![current_layer.pixel_transform()],
+                flip_uv_y: true,
                 multiply_color: wgpu::Color::WHITE,
             },
             draw_mode: DrawMode::Single,
@@ -380,6 +1187,7 @@ impl Document {
 
         self.selection.clear();
         self.update_selection_buffer(renderer, framework);
+        Ok(())
     }
 
     pub fn selection_layer_mut(&mut self) -> Option<&mut SelectionLayer> {
@@ -396,223 +1204,3064 @@ impl Document {
         let selection = self.selection_layer.take().unwrap();
         let layer_below = self.get_layer(&selection.original_layer);
         join_bitmaps(layer_below, &selection.layer, renderer, framework);
+        self.mutate_layer(&selection.original_layer, |layer| layer.mark_dirty());
     }
 
-    pub fn delete_layer(&mut self, layer_idx: LayerId) {
-        let layer = self.tree.remove_layer(layer_idx);
-        self.rendering_strategy.on_layer_removed(&layer);
+    pub fn delete_layer(&mut self, layer_idx: LayerId, framework: &mut Framework) -> DetachedLayer {
+        // Deleting a group deletes all of its children too
+        let (item, position) = self
+            .tree
+            .detach_item(&layer_idx)
+            .expect("Document: layer to delete not found");
+        let layers = item
+            .ids()
+            .iter()
+            .map(|id| {
+                let layer = self.tree.layers.remove(id).unwrap();
+                self.rendering_strategy.on_layer_removed(&layer, framework);
+                layer
+            })
+            .collect();
+        DetachedLayer {
+            item,
+            position,
+            layers,
+        }
     }
 
-    pub(crate) fn add_layer(
-        &mut self,
-        config: LayerConstructionInfo,
-        framework: &mut Framework,
-    ) -> LayerId {
-        let creation_info = LayerCreationInfo {
-            name: config.name.clone(),
-            position: point2(0.0, 0.0),
-            scale: vec2(1.0, 1.0),
-            rotation_radians: 0.0,
-        };
-        let new_layer = match config.layer_type {
-            LayerCreationType::Image => Layer::new_image(
-                RgbaTexture2D::from_repeated_texel(
-                    RgbaU8(config.initial_color),
-                    (self.document_size.x, self.document_size.y),
-                )
-                .unwrap(),
-                creation_info,
-                framework,
-            ),
-            LayerCreationType::Raster => Layer::new_chonky(creation_info),
-        };
-        let id = new_layer.id().clone();
-        self.rendering_strategy.on_new_layer(&new_layer, framework);
-        self.tree.add_layer(new_layer);
-        id
+    // Drops the references of the document and of its layers to their textures and buffers,
+    // which are freed once no other id references them, e.g. the ones in the undo history
+    pub fn free_gpu_assets(mut self, framework: &mut Framework) {
+        for layer in self.tree.layers.values() {
+            self.rendering_strategy.on_layer_removed(layer, framework);
+        }
+        let layers = self
+            .tree
+            .layers
+            .drain()
+            .map(|(_, layer)| layer)
+            .chain(self.quick_mask.take())
+            .chain(self.selection_layer.take().map(|selection| selection.layer));
+        for layer in layers {
+            layer.free_textures(framework);
+        }
+        for cached in self.composite_cache.drain(..) {
+            framework.free_texture2d(cached.texture);
+        }
+        if let Some(depth_texture) = self.depth_texture.take() {
+            framework.free_depth_stencil_texture(depth_texture);
+        }
+        framework.free_depth_stencil_texture(self.stencil_texture);
+        framework.free_texture2d(self.buffer_texture);
     }
 
-    pub(crate) fn update_layers(&mut self, renderer: &mut Renderer, framework: &mut Framework) {
-        if self.wants_selection_update {
-            self.wants_selection_update = false;
-            self.update_selection_buffer(renderer, framework);
+    // Puts a deleted layer back in the place it was deleted from, and selects it
+    pub fn restore_layer(&mut self, detached: DetachedLayer, framework: &mut Framework) {
+        let id = detached.id().clone();
+        for mut layer in detached.layers {
+            self.rendering_strategy.on_new_layer(&layer, framework);
+            layer.mark_dirty();
+            self.tree.layers.insert(layer.id().clone(), layer);
         }
-        self.rendering_strategy.update(&self.tree.layers, framework);
+        self.tree.attach_item(detached.item, detached.position);
+        self.tree.select_layer(id);
     }
 
-    pub(crate) fn render(&mut self, renderer: &mut Renderer, framework: &mut Framework) {
-        self.rendering_strategy.update_canvases(
-            &self.tree.items,
-            &self.tree.layers,
-            framework,
-            renderer,
-        );
-        self.render_result = self.composite_final_image(
-            self.document_size.x,
-            self.document_size.y,
-            renderer,
-            framework,
-        );
+    // Deleting a group deletes its children too: the layers can't be deleted when nothing but
+    // groups would be left in the document
+    pub fn can_delete(&self, layers: &HashSet<LayerId>) -> bool {
+        let removed_layers: HashSet<LayerId> = layers
+            .iter()
+            .filter_map(|id| self.tree.find_item(id))
+            .flat_map(|item| item.ids())
+            .collect();
+        self.tree
+            .items()
+            .iter()
+            .flat_map(|item| item.ids())
+            .any(|id| !removed_layers.contains(&id) && !self.get_layer(&id).is_group())
     }
 
-    pub fn clear_texture(
-        renderer: &mut Renderer,
-        texture: &TextureId,
-        color: wgpu::Color,
+    // Deletes all the selected layers, unless this would leave the document without layers
+    pub fn delete_selected(&mut self, framework: &mut Framework) -> Vec<DetachedLayer> {
+        if !self.can_delete(self.selected_layers()) {
+            log::warn!("Cannot delete all the layers in the document");
+            return vec![];
+        }
+        let selected: Vec<LayerId> = self.selected_layers().iter().copied().collect();
+        let mut deleted = vec![];
+        for id in selected {
+            // The layer might have been deleted along with its group
+            if self.tree.layers.contains_key(&id) {
+                deleted.push(self.delete_layer(id, framework));
+            }
+        }
+        deleted
+    }
+
+    pub fn layer_position(&self, layer: &LayerId) -> Option<LayerPosition> {
+        self.tree.position_of(layer)
+    }
+
+    pub fn move_layer(&mut self, layer: &LayerId, new_position: LayerPosition) {
+        self.tree.move_item(layer, new_position);
+    }
+
+    // Moves the layer one step up or down in its group, returning the position it had
+    // before moving or None if it couldn't be moved
+    pub fn shift_layer(&mut self, layer: &LayerId, up: bool) -> Option<LayerPosition> {
+        let position = self.tree.position_of(layer)?;
+        let new_index = if up {
+            position.index.checked_sub(1)?
+        } else {
+            position.index + 1
+        };
+        if new_index >= self.tree.children_count(&position.parent) {
+            return None;
+        }
+        self.tree.move_item(
+            layer,
+            LayerPosition {
+                index: new_index,
+                ..position
+            },
+        );
+        Some(position)
+    }
+
+    pub fn set_opacity_all(&mut self, opacity: f32) {
+        let selected: Vec<LayerId> = self.selected_layers().iter().copied().collect();
+        for id in selected {
+            self.mutate_layer(&id, |layer| {
+                layer.set_settings(LayerSettings {
+                    opacity,
+                    ..layer.settings().clone()
+                })
+            });
+        }
+    }
+
+    // Moves the selected layers into a new group, returning the group id
+    pub fn group_selected(&mut self, framework: &mut Framework) -> Option<LayerId> {
+        if self.selected_layers().is_empty() {
+            return None;
+        }
+        let selected = self.selected_layers().clone();
+        Some(self.group_layers(&selected, "Group", framework))
+    }
+
+    // Creates a new group layer containing the given layers, keeping their order
+    pub fn group_layers(
+        &mut self,
+        ids: &HashSet<LayerId>,
+        name: &str,
         framework: &mut Framework,
-    ) {
-        renderer.begin(&Camera2d::default(), Some(color), framework);
-        renderer.end(texture, None, framework);
+    ) -> LayerId {
+        let group = Layer::new_group(LayerCreationInfo {
+            name: name.to_owned(),
+            position: point2(0.0, 0.0),
+            scale: vec2(1.0, 1.0),
+            rotation_radians: 0.0,
+        });
+        let group_id = group.id().clone();
+        self.rendering_strategy.on_new_layer(&group, framework);
+        self.tree.group_layers(ids, group);
+        group_id
     }
 
-    pub fn document_size(&self) -> Vector2<u32> {
-        self.document_size
+    // Moves the group's children into the group's parent and removes the group layer,
+    // which is returned so that it can be put back with restore_group
+    pub fn ungroup(&mut self, group_id: LayerId, framework: &mut Framework) -> Layer {
+        let group = self.tree.ungroup(&group_id);
+        self.rendering_strategy.on_layer_removed(&group, framework);
+        group
     }
 
-    pub fn final_image_bytes(&self, framework: &Framework) -> DynamicImage {
-        let texture = framework.texture2d_read_data(&self.render_result);
-        let width = texture.width();
-        let height = texture.height();
-        let bytes = texture
-            .data()
-            .expect("A texture just read from the GPU doesn'thave any bytes, wtf?");
-        let bytes = bytemuck::cast_slice(bytes).to_owned();
-        let raw_image = ImageBuffer::from_raw(width, height, bytes).unwrap();
-        DynamicImage::ImageRgba8(raw_image)
+    // Groups the layers again in a group removed by ungroup, placing it in the given position
+    pub fn restore_group(
+        &mut self,
+        children: &HashSet<LayerId>,
+        group: Layer,
+        position: LayerPosition,
+        framework: &mut Framework,
+    ) {
+        let group_id = *group.id();
+        self.rendering_strategy.on_new_layer(&group, framework);
+        self.tree.group_layers(children, group);
+        self.tree.move_item(&group_id, position);
+        self.mutate_layer(&group_id, |layer| layer.mark_dirty());
     }
 
-    pub fn for_each_layer<F: FnMut(&Layer, &LayerId)>(&self, mut f: F) {
-        self.tree.for_each_layer(|l| f(l, &l.id().clone()));
+    // The layers directly inside the group, from the topmost one
+    pub fn group_children(&self, group_id: &LayerId) -> Vec<LayerId> {
+        match self.tree.find_item(group_id) {
+            Some(LayerItem::Group(children, _)) => children.iter().map(|item| *item.id()).collect(),
+            _ => vec![],
+        }
     }
 
-    pub fn tree(&self) -> &LayerTree<Layer> {
-        &self.tree
+    // Moves the layers to the given positions, e.g. the ones they had before being grouped.
+    // The positions are listed from the topmost layer
+    pub fn move_layers(&mut self, moves: &[(LayerId, LayerPosition)]) {
+        self.tree.move_items(moves);
     }
 
-    pub fn render_camera(&self) -> Camera2d {
-        let half_w = self.document_size.x as f32 * 0.5;
-        let half_h = self.document_size.y as f32 * 0.5;
-        Camera2d::new(-0.01, 1000.0, [-half_w, half_w, half_h, -half_h])
+    // Composites the layers into a new image layer that replaces them, placed where the topmost
+    // of them was. When only_visible is set the disabled layers and groups are left in the tree.
+    // Returns the new layer and the removed ones in the order they were removed, or None when
+    // there was nothing to flatten
+    pub fn flatten(
+        &mut self,
+        only_visible: bool,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> Option<(LayerId, Vec<DetachedLayer>)> {
+        let flattened = Self::items_to_flatten(&self.tree.items, &self.tree.layers, only_visible);
+        let place = self.tree.position_of(flattened.first()?.id())?.index;
+
+        let flattened_layer = self.composite_to_layer(&flattened, "Flattened", renderer, framework);
+
+        let mut detached = vec![];
+        self.detach_flattened(&flattened, &mut detached, framework);
+        let id = *flattened_layer.id();
+        self.restore_layer(
+            DetachedLayer {
+                item: LayerItem::SingleLayer(id),
+                position: LayerPosition {
+                    parent: None,
+                    index: place,
+                },
+                layers: vec![flattened_layer],
+            },
+            framework,
+        );
+        Some((id, detached))
     }
 
-    pub fn make_camera_for_layer(layer: &Layer) -> Camera2d {
-        let extents = layer.bounds().extents;
-        Camera2d::new(
-            -0.01,
-            1000.0,
-            [-extents.x, extents.x, extents.y, -extents.y],
-        )
+    // The items with the disabled ones filtered out, recursing into the groups
+    fn items_to_flatten(
+        items: &[LayerItem],
+        layers: &HashMap<LayerId, Layer>,
+        only_visible: bool,
+    ) -> Vec<LayerItem> {
+        items
+            .iter()
+            .filter(|item| !only_visible || layers.get(item.id()).unwrap().settings().is_enabled)
+            .map(|item| match item {
+                LayerItem::SingleLayer(_) => item.clone(),
+                LayerItem::Group(children, id) => {
+                    LayerItem::Group(Self::items_to_flatten(children, layers, only_visible), *id)
+                }
+            })
+            .collect()
     }
 
-    pub fn render_result(&self) -> &TextureId {
-        &self.render_result
+    // Whole items are removed when nothing was filtered out of them, otherwise the groups
+    // stay in the tree with the disabled children
+    fn detach_flattened(
+        &mut self,
+        flattened: &[LayerItem],
+        detached: &mut Vec<DetachedLayer>,
+        framework: &mut Framework,
+    ) {
+        for item in flattened {
+            if self.tree.find_item(item.id()) == Some(item) {
+                detached.push(self.delete_layer(*item.id(), framework));
+            } else if let LayerItem::Group(children, _) = item {
+                self.detach_flattened(children, detached, framework);
+            }
+        }
     }
 
-    pub fn composite_final_image(
+    // Renders the items into a new image layer with the size of the document
+    fn composite_to_layer(
         &mut self,
-        width: u32,
-        height: u32,
+        items: &Vec<LayerItem>,
+        name: &str,
         renderer: &mut Renderer,
         framework: &mut Framework,
-    ) -> TextureId {
-        Self::composite_final_image_impl(
+    ) -> Layer {
+        self.rendering_strategy.update(&self.tree.layers, framework);
+        self.rendering_strategy.update_canvases(
             &self.tree.items,
             &self.tree.layers,
-            &self.rendering_strategy,
+            framework,
+            renderer,
+        );
+        let (width, height) = (self.document_size.x, self.document_size.y);
+        let composite = Self::composite_group(
+            items,
+            &self.tree.layers,
+            self.rendering_strategy.as_ref(),
             width,
             height,
+            &mut 0,
             renderer,
             framework,
+        );
+        Layer::new_image(
+            framework
+                .texture2d_read_data(&composite)
+                .expect("Document: failed to read the flattened layers"),
+            LayerCreationInfo {
+                name: name.to_owned(),
+                position: point2(0.0, 0.0),
+                scale: vec2(1.0, 1.0),
+                rotation_radians: 0.0,
+            },
+            framework,
         )
     }
 
-    fn composite_final_image_impl<T: LayerRenderingStrategy<Layer>>(
-        items: &Vec<LayerItem>,
-        layers: &HashMap<LayerId, Layer>,
-        strategy: &T,
-        width: u32,
-        height: u32,
+    // Merges the layer with the one below into a new layer placed above them, without touching
+    // them until the merge is committed. While the preview is shown the two layers are hidden.
+    // Returns None when there's no layer below or another merge is pending
+    pub fn merge_down_preview(
+        &mut self,
+        top: &LayerId,
         renderer: &mut Renderer,
         framework: &mut Framework,
-    ) -> TextureId {
-        let mut ab_render_target = ABRenderTarget::new(width, height, framework);
-        for item in items {
-            match item {
-                LayerItem::SingleLayer(id) => {
-                    ab_render_target.run_render_loop(|result, back| {
-                        strategy.composite_layer_on_target(
-                            layers.get(&id).unwrap(),
-                            back,
-                            &result,
-                            renderer,
-                            framework,
-                        );
-                    });
-                }
-                LayerItem::Group(items, group_layer_id) => {
-                    let rendered_group = Self::composite_final_image_impl(
-                        items, layers, strategy, width, height, renderer, framework,
-                    );
+    ) -> Option<LayerId> {
+        if self.pending_merge.is_some() {
+            return None;
+        }
+        let below = self.tree.find_below(top)?;
+        let position = self.tree.position_of(top)?;
+        let items = vec![
+            self.tree.find_item(top)?.clone(),
+            self.tree.find_item(&below)?.clone(),
+        ];
+        let name = format!("{} merged", self.get_layer(top).settings().name);
+        let merged_layer = self.composite_to_layer(&items, &name, renderer, framework);
+        let merged = *merged_layer.id();
+        self.restore_layer(
+            DetachedLayer {
+                item: LayerItem::SingleLayer(merged),
+                position,
+                layers: vec![merged_layer],
+            },
+            framework,
+        );
+        let sources = [*top, below]
+            .into_iter()
+            .map(|id| (id, self.get_layer(&id).settings().opacity))
+            .collect();
+        self.pending_merge = Some(PendingMerge {
+            merged,
+            sources,
+            preview_shown: false,
+        });
+        self.show_merge_preview(true);
+        Some(merged)
+    }
 
-                    ab_render_target.run_render_loop(|result, back| {
-                        strategy.composite_layer_on_target(
-                            layers.get(&group_layer_id).unwrap(),
-                            back,
-                            &result,
-                            renderer,
-                            framework,
-                        );
-                    });
-                }
+    // The merged layer of the pending merge, if any
+    pub fn pending_merge(&self) -> Option<&LayerId> {
+        self.pending_merge.as_ref().map(|merge| &merge.merged)
+    }
+
+    pub fn is_merge_preview_shown(&self) -> bool {
+        self.pending_merge
+            .as_ref()
+            .map_or(false, |merge| merge.preview_shown)
+    }
+
+    // Shows either the merged layer or the layers it was made from
+    pub fn show_merge_preview(&mut self, show: bool) {
+        let (merged, sources) = match &mut self.pending_merge {
+            Some(merge) => {
+                merge.preview_shown = show;
+                (merge.merged, merge.sources.clone())
             }
+            None => return,
+        };
+        let set_opacity = |layer: &mut Layer, opacity: f32| {
+            layer.set_settings(LayerSettings {
+                opacity,
+                ..layer.settings().clone()
+            })
+        };
+        self.mutate_layer(&merged, |layer| {
+            set_opacity(layer, if show { 1.0 } else { 0.0 })
+        });
+        for (id, opacity) in sources {
+            self.mutate_layer(&id, |layer| {
+                set_opacity(layer, if show { 0.0 } else { opacity })
+            });
         }
-        ab_render_target.result().clone()
     }
-}
 
-fn join_bitmaps(
-    layer_below: &Layer,
-    layer_top: &Layer,
-    renderer: &mut Renderer,
-    framework: &mut Framework,
-) {
-    match (&layer_top.layer_type, &layer_below.layer_type) {
-        (
-            LayerType::Image { texture: top, .. },
+    // Replaces the source layers with the merged one. Returns the merged layer and the removed
+    // ones in the order they were removed, so that the merge can be undone like a flatten
+    pub fn commit_merge(
+        &mut self,
+        framework: &mut Framework,
+    ) -> Option<(LayerId, Vec<DetachedLayer>)> {
+        self.show_merge_preview(false);
+        let merge = self.pending_merge.take()?;
+        self.mutate_layer(&merge.merged, |layer| {
+            layer.set_settings(LayerSettings {
+                opacity: 1.0,
+                ..layer.settings().clone()
+            })
+        });
+        // The merged layer is taken out while the sources are removed, so that their positions
+        // are the ones they had before the merge
+        let merged = self.delete_layer(merge.merged, framework);
+        let detached = merge
+            .sources
+            .iter()
+            .map(|(id, _)| self.delete_layer(*id, framework))
+            .collect();
+        self.restore_layer(merged, framework);
+        Some((merge.merged, detached))
+    }
+
+    // Removes the merged layer, leaving the source layers as they were
+    pub fn discard_merge(&mut self, framework: &mut Framework) {
+        self.show_merge_preview(false);
+        if let Some(merge) = self.pending_merge.take() {
+            self.delete_layer(merge.merged, framework);
+            self.tree.select_layer(merge.sources[0].0);
+        }
+    }
+
+    // Blurs the layer, see filters::apply_gaussian_blur for the progress and cancel arguments.
+    // Like fill_selection_from_edges, the pixels outside the selection are left as they were.
+    // Returns the pixels the layer had before the blur, see Layer::write_pixels to restore them
+    pub fn apply_gaussian_blur(
+        &mut self,
+        layer_id: &LayerId,
+        sigma: f32,
+        progress: Option<&dyn Fn(f32)>,
+        cancel: Option<&AtomicBool>,
+        framework: &mut Framework,
+    ) -> Result<Option<RgbaTexture2D>, FilterError> {
+        let has_selection = self.selection_clip().is_some();
+        let selection = &self.selection;
+
+        let layer = self.tree.get_layer_mut(layer_id);
+        // The pixels are brought into the document by the matrix, starting from the top left one
+        let (source, top_left, pixel_matrix) = match &layer.layer_type {
             LayerType::Image {
-                texture: bottom, ..
+                texture,
+                dimensions,
+            } => {
+                let size = dimensions.cast::<f32>().unwrap();
+                (
+                    framework.texture2d_read_data(texture)?,
+                    point2(-size.x * 0.5, size.y * 0.5),
+                    layer.pixel_transform().matrix()
+                        * Matrix4::from_nonuniform_scale(2.0 / size.x, 2.0 / size.y, 1.0),
+                )
+            }
+            LayerType::Chonky(map) => {
+                let (first_chunk, source) = match map.read_into_texture(framework) {
+                    Some(read) => read,
+                    None => return Ok(None),
+                };
+                let chunk_size = map.chunk_size() as f32;
+                let top_left = point2(
+                    (first_chunk.x as f32 - 0.5) * chunk_size,
+                    first_chunk.y as f32 * chunk_size + source.height() as f32 - chunk_size * 0.5,
+                );
+                (source, top_left, layer.transform().matrix())
+            }
+            LayerType::Group => {
+                log::warn!("Cannot apply a filter to a group layer");
+                return Ok(None);
+            }
+        };
+        let mut blurred = filters::apply_gaussian_blur(&source, sigma, progress, cancel)?;
+        if has_selection {
+            let (width, height) = (source.width(), source.height());
+            let texels = blurred
+                .data()
+                .unwrap()
+                .iter()
+                .zip(source.data().unwrap())
+                .enumerate()
+                .map(|(i, (blurred, original))| {
+                    let x = top_left.x + (i as u32 % width) as f32 + 0.5;
+                    let y = top_left.y - (i as u32 / width) as f32 - 0.5;
+                    let world = pixel_matrix.transform_point(point3(x, y, 0.0));
+                    if selection.contains(point2(world.x, world.y)) {
+                        *blurred
+                    } else {
+                        *original
+                    }
+                })
+                .collect();
+            blurred = RgbaTexture2D::from_texels(texels, (width, height)).unwrap();
+        }
+        layer.write_pixels(&blurred, framework);
+        Ok(Some(source))
+    }
+
+    // Fills the document area of the layer with noise, leaving out what's outside the selection.
+    // Returns the diff of the modified chunks, so that the fill can be undone
+    pub fn fill_with_noise(
+        &mut self,
+        layer_id: &LayerId,
+        kind: NoiseKind,
+        seed: u32,
+        parameters: NoiseParameters,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> Option<ChunkDiff> {
+        let settings = NoiseSettingsUniform::new(
+            kind,
+            seed,
+            parameters,
+            self.selection_clip(),
+            self.document_size,
+        );
+        let settings_buffer = framework.allocate_transient_buffer(BufferConfiguration {
+            initial_setup: BufferInitialSetup::Data(&vec![settings]),
+            buffer_type: BufferType::Uniform,
+            gpu_copy_dest: false,
+            gpu_copy_source: false,
+            cpu_copy_dest: false,
+            cpu_copy_source: false,
+        });
+        let document_rect = Box2d {
+            center: point2(0.0, 0.0),
+            extents: self.document_size.cast::<f32>().unwrap() * 0.5,
+        };
+        let stencil_texture = self.stencil_texture.clone();
+
+        let layer = self.tree.get_layer_mut(layer_id);
+        let inv_layer_matrix = layer.transform().matrix().invert()?;
+        let rendering_camera = layer.rendering_camera()?;
+        let diff = match &mut layer.layer_type {
+            LayerType::Chonky(map) => {
+                let chunk_size = map.chunk_size() as f32;
+                let layer_rect = document_rect.transformed(inv_layer_matrix);
+                map.edit(
+                    layer_rect.clone(),
+                    |chunk, _, chunk_world_position, framework| {
+                        renderer.begin(&rendering_camera, None, framework);
+                        renderer.set_draw_debug_name("Noise fill");
+                        renderer.set_viewport(Some((0.0, 0.0, chunk_size, chunk_size)));
+                        renderer.draw(DrawCommand {
+                            primitives: PrimitiveType::Rect {
+                                rects: vec![Box2d {
+                                    center: layer_rect.center - chunk_world_position.to_vec(),
+                                    extents: layer_rect.extents,
+                                }],
+                                multiply_color: wgpu::Color::WHITE,
+                            },
+                            draw_mode: DrawMode::Single,
+                            additional_data: OptionalDrawData {
+                                additional_vertex_buffers: vec![],
+                                additional_bindable_resource: vec![
+                                    BindableResource::StencilTexture(stencil_texture.clone()),
+                                    BindableResource::UniformBuffer(settings_buffer.clone()),
+                                ],
+                                shader: Some(global_selection_data().noise_shader.clone()),
+                                scissor: None,
+                            },
+                        });
+                        renderer.end(chunk, None, framework);
+                    },
+                    framework,
+                )
+            }
+            LayerType::Image { .. } | LayerType::Group => {
+                log::warn!("Noise can only be generated on raster layers");
+                return None;
+            }
+        };
+        layer.mark_dirty();
+        Some(diff)
+    }
+
+    // Sharpens the layer by adding back the difference between the layer and its blurred version,
+    // see UnsharpMaskParameters. Like fill_with_noise, only what's inside the selection is modified
+    pub fn unsharp_mask(
+        &mut self,
+        layer_id: &LayerId,
+        amount: f32,
+        radius: f32,
+        threshold: u8,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> Option<ChunkDiff> {
+        let selection = self.selection_clip();
+        let document_size = self.document_size;
+        let stencil_texture = self.stencil_texture.clone();
+
+        let layer = self.tree.get_layer_mut(layer_id);
+        let layer_transform = layer.transform().matrix();
+        let rendering_camera = layer.rendering_camera()?;
+        let map = match &mut layer.layer_type {
+            LayerType::Chonky(map) => map,
+            LayerType::Image { .. } | LayerType::Group => {
+                log::warn!("Unsharp mask can only be applied on raster layers");
+                return None;
+            }
+        };
+
+        // The whole layer is uploaded as a single texture, so that the blur can sample across chunks
+        let (first_chunk, source) = map.read_into_texture(framework)?;
+        let chunk_size = map.chunk_size() as f32;
+        let source_size = vec2(source.width() as f32, source.height() as f32);
+        let source_center = point2(
+            first_chunk.x as f32 + (source_size.x / chunk_size - 1.0) * 0.5,
+            first_chunk.y as f32 + (source_size.y / chunk_size - 1.0) * 0.5,
+        ) * chunk_size;
+        let source_texture = framework.allocate_transient_texture2d(
+            source,
+            TextureConfiguration {
+                label: Some("Unsharp mask source"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
-        ) => {
-            let below_inverse_transform = layer_below
-                .transform()
-                .matrix()
-                .invert()
-                .expect("Failed to invert matrix in join layers!");
-            let adjusted_top_transform = layer_top.transform().matrix() * below_inverse_transform;
-            let mut transform = math::helpers::decompose_no_shear_2d(adjusted_top_transform);
-            transform.scale(layer_top.bounds().extents);
-            renderer.begin(
-                &Document::make_camera_for_layer(layer_below),
-                None,
+        );
+        let settings = UnsharpMaskSettingsUniform::new(
+            UnsharpMaskParameters {
+                amount,
+                radius,
+                threshold,
+            },
+            layer_transform,
+            source_center,
+            source_size,
+            selection,
+            document_size,
+        );
+        let settings_buffer = framework.allocate_transient_buffer(BufferConfiguration {
+            initial_setup: BufferInitialSetup::Data(&vec![settings]),
+            buffer_type: BufferType::Uniform,
+            gpu_copy_dest: false,
+            gpu_copy_source: false,
+            cpu_copy_dest: false,
+            cpu_copy_source: false,
+        });
+
+        // Shrink the bounds by a pixel, or edit() would also pick the chunks just outside the source
+        let source_bounds = Box2d {
+            center: source_center,
+            extents: source_size * 0.5 - vec2(1.0, 1.0),
+        };
+        let diff = map.edit(
+            source_bounds,
+            |chunk, _, chunk_world_position, framework| {
+                renderer.begin(&rendering_camera, None, framework);
+                renderer.set_draw_debug_name("Unsharp mask");
+                renderer.set_viewport(Some((0.0, 0.0, chunk_size, chunk_size)));
+                renderer.draw(DrawCommand {
+                    primitives: PrimitiveType::Texture2D {
+                        texture_id: source_texture.clone(),
+                        instances: vec![Transform2d {
+                            position: point3(
+                                source_center.x - chunk_world_position.x,
+                                source_center.y - chunk_world_position.y,
+                                0.0,
+                            ),
+                            scale: source_size * 0.5,
+                            ..Default::default()
+                        }],
+                        flip_uv_y: true,
+                        multiply_color: wgpu::Color::WHITE,
+                    },
+                    draw_mode: DrawMode::Single,
+                    additional_data: OptionalDrawData {
+                        additional_vertex_buffers: vec![],
+                        additional_bindable_resource: vec![
+                            BindableResource::StencilTexture(stencil_texture.clone()),
+                            BindableResource::UniformBuffer(settings_buffer.clone()),
+                        ],
+                        shader: Some(global_selection_data().unsharp_mask_shader.clone()),
+                        scissor: None,
+                    },
+                });
+                renderer.end(chunk, None, framework);
+            },
+            framework,
+        );
+        layer.mark_dirty();
+        Some(diff)
+    }
+
+    // Fills the selected pixels of a raster layer by spreading the colors around them inward,
+    // see filters::fill_from_edges. Without a selection the transparent pixels are filled.
+    // Only the allocated chunks of the layer are read, so the fill can't reach past them
+    pub fn fill_selection_from_edges(
+        &mut self,
+        layer_id: &LayerId,
+        iterations: u32,
+        framework: &mut Framework,
+    ) -> Option<ChunkDiff> {
+        let has_selection = self.selection_clip().is_some();
+        let selection = &self.selection;
+
+        let layer = self.tree.get_layer_mut(layer_id);
+        let layer_matrix = layer.transform().matrix();
+        let map = match &mut layer.layer_type {
+            LayerType::Chonky(map) => map,
+            LayerType::Image { .. } | LayerType::Group => {
+                log::warn!("Only raster layers can be filled");
+                return None;
+            }
+        };
+        let (first_chunk, texture) = map.read_into_texture(framework)?;
+        let chunk_size = map.chunk_size() as f32;
+        let (width, height) = (texture.width(), texture.height());
+        let top_left = point2(
+            (first_chunk.x as f32 - 0.5) * chunk_size,
+            first_chunk.y as f32 * chunk_size + height as f32 - chunk_size * 0.5,
+        );
+        let texels = texture.data().unwrap();
+        let mask: Vec<bool> = texels
+            .iter()
+            .enumerate()
+            .map(|(i, RgbaU8([_, _, _, a]))| {
+                if has_selection {
+                    let x = top_left.x + (i as u32 % width) as f32 + 0.5;
+                    let y = top_left.y - (i as u32 / width) as f32 - 0.5;
+                    let world = layer_matrix.transform_point(point3(x, y, 0.0));
+                    selection.contains(point2(world.x, world.y))
+                } else {
+                    *a == 0
+                }
+            })
+            .collect();
+        let filled = filters::fill_from_edges(texels, width, &mask, iterations);
+        let filled = RgbaTexture2D::from_texels(filled, (width, height)).unwrap();
+        let diff = map.edit_from_texture(first_chunk, &filled, framework);
+        layer.mark_dirty();
+        Some(diff)
+    }
+
+    // None when the whole document is editable, otherwise whether the selection is inverted
+    fn selection_clip(&self) -> Option<bool> {
+        if self.selection.is_empty() && !self.selection.inverted {
+            None
+        } else {
+            Some(self.selection.inverted)
+        }
+    }
+
+    pub(crate) fn add_layer(
+        &mut self,
+        config: LayerConstructionInfo,
+        framework: &mut Framework,
+    ) -> LayerId {
+        let creation_info = LayerCreationInfo {
+            name: config.name.clone(),
+            position: point2(0.0, 0.0),
+            scale: vec2(1.0, 1.0),
+            rotation_radians: 0.0,
+        };
+        let new_layer = match config.layer_type {
+            LayerCreationType::Image => Layer::new_image(
+                RgbaTexture2D::from_repeated_texel(
+                    RgbaU8(config.initial_color),
+                    (self.document_size.x, self.document_size.y),
+                )
+                .unwrap(),
+                creation_info,
                 framework,
-            );
-            renderer.draw(DrawCommand {
-                primitives: PrimitiveType::Texture2D {
-                    texture_id: top.clone(),
-                    instances: vec![transform],
-                    flip_uv_y: false,
-                    multiply_color: wgpu::Color::WHITE,
-                },
-                draw_mode: DrawMode::Single,
-                additional_data: OptionalDrawData::default(),
-            });
+            ),
+            LayerCreationType::Raster => Layer::new_chonky(creation_info),
+        };
+        self.insert_layer(new_layer, framework)
+    }
 
-            renderer.end(&bottom, None, framework);
+    fn insert_layer(&mut self, new_layer: Layer, framework: &mut Framework) -> LayerId {
+        let id = new_layer.id().clone();
+        self.rendering_strategy.on_new_layer(&new_layer, framework);
+        self.tree.add_layer(new_layer);
+        id
+    }
+
+    pub(crate) fn update_layers(&mut self, renderer: &mut Renderer, framework: &mut Framework) {
+        if self.wants_selection_update {
+            self.wants_selection_update = false;
+            self.update_selection_buffer(renderer, framework);
         }
-        _ => log::warn!(
-            "Cannot join layer of type {:?} with layer of type {:?}",
-            layer_below.layer_type,
-            layer_top.layer_type
+        self.rendering_strategy.update(&self.tree.layers, framework);
+    }
+
+    pub(crate) fn render(&mut self, renderer: &mut Renderer, framework: &mut Framework) {
+        self.rendering_strategy.update_canvases(
+            &self.tree.items,
+            &self.tree.layers,
+            framework,
+            renderer,
+        );
+        let layers = self.composite_layers(renderer, framework);
+        self.render_result = if self.background == DocumentBackground::Transparent {
+            layers.clone()
+        } else {
+            let (width, height) = (self.document_size.x, self.document_size.y);
+            let target = framework.allocate_transient_texture2d(
+                RgbaTexture2D::empty((width, height)),
+                TextureConfiguration {
+                    label: Some("Document render result"),
+                    usage: TextureUsage::RWRT,
+                    mip_count: None,
+                    color_space: ColorSpace::Srgb,
+                },
+            );
+            Self::draw_background(
+                &self.background,
+                &layers,
+                &target,
+                width,
+                height,
+                renderer,
+                framework,
+            );
+            target
+        };
+        self.export_result =
+            if self.export_background || self.background == DocumentBackground::Transparent {
+                None
+            } else {
+                Some(layers)
+            };
+    }
+
+    // The number of layers blended by the last render, a group counts as its children plus one
+    pub fn composited_layers(&self) -> usize {
+        self.composited_layers
+    }
+
+    pub fn rendering_strategy(&self) -> RenderingStrategyKind {
+        self.rendering_strategy_kind
+    }
+
+    // Moves all the layers to a new strategy, which draws and composites them from scratch
+    pub fn set_rendering_strategy(
+        &mut self,
+        kind: RenderingStrategyKind,
+        framework: &mut Framework,
+    ) {
+        if kind == self.rendering_strategy_kind {
+            return;
+        }
+        for layer in self.tree.layers.values() {
+            self.rendering_strategy.on_layer_removed(layer, framework);
+        }
+        let document_info = DocumentCreationInfo {
+            width: self.document_size.x,
+            height: self.document_size.y,
+            first_layer_color: [0.0; 4],
+        };
+        self.rendering_strategy = Self::make_rendering_strategy(kind, &document_info, framework);
+        self.rendering_strategy
+            .set_linear_blending(self.linear_blending);
+        self.rendering_strategy_kind = kind;
+        for layer in self.tree.layers.values_mut() {
+            self.rendering_strategy.on_new_layer(layer, framework);
+            // The settings are written again too, the new strategy has its own buffers
+            layer.set_settings(layer.settings().clone());
+        }
+    }
+
+    fn make_rendering_strategy(
+        kind: RenderingStrategyKind,
+        document_info: &DocumentCreationInfo,
+        framework: &mut Framework,
+    ) -> Box<dyn LayerRenderingStrategy<Layer>> {
+        match kind {
+            RenderingStrategyKind::Canvas => {
+                Box::new(CanvasRenderingStrategy::new(framework, document_info))
+            }
+            RenderingStrategyKind::CpuBlitting => {
+                Box::new(CpuBlittingStrategy::new(framework, document_info))
+            }
+        }
+    }
+
+    pub fn depth_compositing(&self) -> bool {
+        self.depth_compositing
+    }
+
+    // When every top level item is a layer using the Normal blend mode with full opacity,
+    // the layers are composited in a single pass using the depth buffer instead of being
+    // blended one by one. The layers' pixels are expected to be either opaque or transparent:
+    // a translucent pixel hides the layers below it. Ignored by the CpuBlitting strategy
+    pub fn set_depth_compositing(&mut self, depth_compositing: bool) {
+        self.depth_compositing = depth_compositing;
+    }
+
+    pub fn linear_blending(&self) -> bool {
+        self.linear_blending
+    }
+
+    // The layers are blended in linear space by default, which keeps the brightness of the
+    // soft edges. Without it the stored sRGB values are blended, like many other editors do
+    pub fn set_linear_blending(&mut self, linear: bool) {
+        if linear == self.linear_blending {
+            return;
+        }
+        self.linear_blending = linear;
+        self.rendering_strategy.set_linear_blending(linear);
+        // Writes the blend settings of every layer again
+        for layer in self.tree.layers.values_mut() {
+            layer.set_settings(layer.settings().clone());
+        }
+    }
+
+    pub fn background(&self) -> DocumentBackground {
+        self.background
+    }
+
+    pub fn set_background(&mut self, background: DocumentBackground) {
+        self.background = background;
+    }
+
+    // Whether the background is part of the exported images
+    pub fn export_background(&self) -> bool {
+        self.export_background
+    }
+
+    pub fn set_export_background(&mut self, export_background: bool) {
+        self.export_background = export_background;
+    }
+
+    // How the alpha is stored in the exported images
+    pub fn export_alpha_mode(&self) -> AlphaMode {
+        self.export_alpha_mode
+    }
+
+    pub fn set_export_alpha_mode(&mut self, alpha_mode: AlphaMode) {
+        self.export_alpha_mode = alpha_mode;
+    }
+
+    // Only used as metadata when exporting, the document is always edited in pixels
+    pub fn dpi(&self) -> f32 {
+        self.dpi
+    }
+
+    pub fn set_dpi(&mut self, dpi: f32) {
+        self.dpi = dpi.clamp(MIN_DPI, MAX_DPI);
+    }
+
+    pub fn view(&self) -> Option<ViewState> {
+        self.view
+    }
+
+    pub fn set_view(&mut self, view: Option<ViewState>) {
+        self.view = view;
+    }
+
+    pub fn gif_dither(&self) -> DitherKind {
+        self.gif_dither
+    }
+
+    pub fn set_gif_dither(&mut self, gif_dither: DitherKind) {
+        self.gif_dither = gif_dither;
+    }
+
+    pub fn palette_mode(&self) -> Option<&Palette> {
+        self.palette.as_ref()
+    }
+
+    // In palette mode the brush only paints with the palette colors, and the layers are snapped
+    // to them after every stroke: the GIF exports then store the palette indices as they are
+    pub fn set_palette_mode(&mut self, palette: Option<Palette>) {
+        self.palette = palette;
+    }
+
+    // Replaces every pixel of the raster layer with the nearest palette color, making the
+    // translucent pixels either opaque or transparent. Does nothing outside of palette mode
+    pub fn snap_layer_to_palette(&mut self, layer_id: &LayerId, framework: &Framework) {
+        let palette = match &self.palette {
+            Some(palette) => palette,
+            None => return,
+        };
+        let layer = self.tree.get_layer_mut(layer_id);
+        if let LayerType::Chonky(chunked_layer) = &layer.layer_type {
+            if let Some((first_chunk, texture)) = chunked_layer.read_into_texture(framework) {
+                let texels = texture
+                    .data()
+                    .unwrap()
+                    .iter()
+                    .map(|texel| palette.snap_texel(*texel))
+                    .collect();
+                let snapped =
+                    RgbaTexture2D::from_texels(texels, (texture.width(), texture.height()))
+                        .unwrap();
+                chunked_layer.write_from_texture(first_chunk, &snapped, framework);
+                layer.mark_dirty();
+            }
+        }
+    }
+
+    // The size of the document in inches, when printed at its DPI
+    pub fn print_size(&self) -> Vector2<f32> {
+        vec2(
+            self.document_size.x as f32 / self.dpi,
+            self.document_size.y as f32 / self.dpi,
+        )
+    }
+
+    pub fn clear_texture(
+        renderer: &mut Renderer,
+        texture: &TextureId,
+        color: wgpu::Color,
+        framework: &mut Framework,
+    ) {
+        renderer.begin(&Camera2d::default(), Some(color), framework);
+        renderer.end(texture, None, framework);
+    }
+
+    pub fn document_size(&self) -> Vector2<u32> {
+        self.document_size
+    }
+
+    pub fn final_image_bytes(
+        &self,
+        alpha_mode: AlphaMode,
+        framework: &Framework,
+    ) -> Result<DynamicImage, TexelConversionError> {
+        let texture = framework.texture2d_read_data(self.export_texture())?;
+        Ok(alpha_mode.convert(Self::texture_into_image(texture)?))
+    }
+
+    pub fn final_image_bytes_async(
+        &self,
+        alpha_mode: AlphaMode,
+        framework: &Framework,
+    ) -> impl Future<Output = Result<DynamicImage, TexelConversionError>> {
+        let texture = framework.texture2d_read_data_async(self.export_texture());
+        async move { Ok(alpha_mode.convert(Self::texture_into_image(texture.await?)?)) }
+    }
+
+    // EXR files are always stored as linear floats: the 8 bit composite is promoted to them
+    pub fn export_exr(&self, path: &Path, framework: &Framework) -> Result<(), exr::error::Error> {
+        let texture = framework
+            .texture2d_read_data(self.export_texture())
+            .map_err(|e| exr::error::Error::Invalid(e.to_string().into()))?;
+        let pixels = exr_export::promote_texels(texture.data().unwrap());
+        exr_export::write_exr(path, texture.width(), texture.height(), &pixels)
+    }
+
+    // The rows of the images go from top to bottom, and the document is centered on the origin
+    fn is_pixel_selected(&self, x: u32, y: u32) -> bool {
+        let (width, height) = (self.document_size.x, self.document_size.y);
+        self.selection.contains(point2(
+            x as f32 + 0.5 - width as f32 * 0.5,
+            height as f32 * 0.5 - y as f32 - 0.5,
+        ))
+    }
+
+    // The first and the last corner of the bounds of the selected pixels
+    fn selected_pixel_bounds(&self) -> Option<((u32, u32), (u32, u32))> {
+        let (width, height) = (self.document_size.x, self.document_size.y);
+        let (mut min, mut max) = ((width, height), None);
+        for y in 0..height {
+            for x in 0..width {
+                if self.is_pixel_selected(x, y) {
+                    min = (min.0.min(x), min.1.min(y));
+                    max = Some(max.map_or((x, y), |(mx, my): (u32, u32)| (mx.max(x), my.max(y))));
+                }
+            }
+        }
+        Some((min, max?))
+    }
+
+    // Crops a document sized image to the selection bounds, the pixels left out are transparent
+    fn crop_to_selection(
+        &self,
+        image: &RgbaImage,
+        (min, max): ((u32, u32), (u32, u32)),
+    ) -> DynamicImage {
+        let cropped = ImageBuffer::from_fn(max.0 - min.0 + 1, max.1 - min.1 + 1, |x, y| {
+            let (x, y) = (min.0 + x, min.1 + y);
+            if self.is_pixel_selected(x, y) {
+                *image.get_pixel(x, y)
+            } else {
+                image::Rgba([0, 0, 0, 0])
+            }
+        });
+        DynamicImage::ImageRgba8(cropped)
+    }
+
+    // The final image cropped to the bounds of the selected pixels, with the pixels left out of
+    // the selection made transparent. None when nothing is selected or the image can't be read
+    pub fn selection_image(&self, framework: &Framework) -> Option<DynamicImage> {
+        let bounds = self.selected_pixel_bounds()?;
+        let image = self
+            .final_image_bytes(self.export_alpha_mode, framework)
+            .ok()?
+            .into_rgba8();
+        Some(self.crop_to_selection(&image, bounds))
+    }
+
+    // Like selection_image, with the pixels of a single layer: its opacity and blend mode
+    // are left out. None for the groups too
+    pub fn layer_selection_image(
+        &mut self,
+        layer_id: &LayerId,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> Option<DynamicImage> {
+        if matches!(self.get_layer(layer_id).layer_type, LayerType::Group) {
+            return None;
+        }
+        let bounds = self.selected_pixel_bounds()?;
+        self.rendering_strategy.update(&self.tree.layers, framework);
+        self.rendering_strategy.update_canvases(
+            &self.tree.items,
+            &self.tree.layers,
+            framework,
+            renderer,
+        );
+        let canvas = framework
+            .texture2d_read_data(self.rendering_strategy.canvas(layer_id))
+            .ok()?;
+        let (width, height) = (canvas.width(), canvas.height());
+        let bytes = bytemuck::cast_slice(canvas.data()?).to_vec();
+        let image = RgbaImage::from_raw(width, height, bytes)?;
+        Some(self.crop_to_selection(&image, bounds))
+    }
+
+    // Saves the selected pixels, see selection_image: the format is picked from the extension
+    pub fn export_selection(&self, path: &Path, framework: &Framework) -> image::ImageResult<()> {
+        match self.selection_image(framework) {
+            Some(image) => image.save(path),
+            None => Err(image::ImageError::Parameter(
+                image::error::ParameterError::from_kind(image::error::ParameterErrorKind::Generic(
+                    "Nothing is selected".to_owned(),
+                )),
+            )),
+        }
+    }
+
+    // Writes each layer as a document sized page named after the layer, from the bottom layer.
+    // The groups are left out, along with the opacity and the blend mode of the layers
+    pub fn export_tiff(
+        &mut self,
+        path: &Path,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> tiff::TiffResult<()> {
+        fn leaf_layers(items: &[LayerItem], ids: &mut Vec<LayerId>) {
+            for item in items {
+                match item {
+                    LayerItem::SingleLayer(id) => ids.push(*id),
+                    LayerItem::Group(children, _) => leaf_layers(children, ids),
+                }
+            }
+        }
+        let mut ids = vec![];
+        leaf_layers(&self.tree.items, &mut ids);
+
+        self.rendering_strategy.update(&self.tree.layers, framework);
+        self.rendering_strategy.update_canvases(
+            &self.tree.items,
+            &self.tree.layers,
+            framework,
+            renderer,
+        );
+        let pages: Vec<TiffPage> = ids
+            .iter()
+            .rev()
+            .map(|id| {
+                let canvas = framework
+                    .texture2d_read_data(self.rendering_strategy.canvas(id))
+                    .map_err(|e| {
+                        tiff::TiffError::IoError(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            e,
+                        ))
+                    })?;
+                let settings = self.get_layer(id).settings();
+                Ok(TiffPage {
+                    name: Some(settings.name.clone()),
+                    label: settings.label.map(|label| label.to_string()),
+                    width: canvas.width(),
+                    height: canvas.height(),
+                    texels: canvas.data().unwrap().to_vec(),
+                })
+            })
+            .collect::<tiff::TiffResult<_>>()?;
+        tiff_io::write_tiff(path, &pages)
+    }
+
+    // Composites the document one tile at a time, assembling the tiles on the CPU: documents
+    // bigger than the biggest texture supported by the device can still be exported this way
+    pub fn final_image_tiled(
+        &self,
+        tile_size: u32,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> Result<DynamicImage, TexelConversionError> {
+        let (width, height) = (self.document_size.x, self.document_size.y);
+        let tile_size = tile_size
+            .min(framework.device.limits().max_texture_dimension_2d)
+            .max(1);
+        // Big documents overflow u32 byte counts, the offsets are computed in usize
+        let mut bytes = vec![0u8; width as usize * height as usize * 4];
+        for tile in image_tiles(width, height, tile_size) {
+            let tile_texture = self.composite_tile(tile, renderer, framework);
+            let tile_texels = framework.texture2d_read_data(&tile_texture);
+            framework.free_texture2d(tile_texture);
+            let tile_texels = tile_texels?;
+            copy_tile_rows(
+                &mut bytes,
+                width,
+                tile,
+                bytemuck::cast_slice(tile_texels.data().unwrap()),
+            );
+        }
+        let raw_image = ImageBuffer::from_raw(width, height, bytes).unwrap();
+        Ok(DynamicImage::ImageRgba8(raw_image))
+    }
+
+    fn composite_tile(
+        &self,
+        tile: (u32, u32, u32, u32),
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> TextureId {
+        let layers = Self::composite_tile_items(
+            &self.tree.items,
+            &self.tree.layers,
+            self.rendering_strategy.as_ref(),
+            tile,
+            renderer,
+            framework,
+        );
+        if !self.export_background || self.background == DocumentBackground::Transparent {
+            return layers;
+        }
+        let target = Self::tile_texture(tile, framework);
+        Self::draw_background(
+            &self.background,
+            &layers,
+            &target,
+            tile.2,
+            tile.3,
+            renderer,
+            framework,
+        );
+        framework.free_texture2d(layers);
+        target
+    }
+
+    // Same as composite_group, but the layers are drawn again on tile sized canvases.
+    // The textures aren't transient, so that they can be freed as soon as a tile is done
+    fn composite_tile_items(
+        items: &Vec<LayerItem>,
+        layers: &HashMap<LayerId, Layer>,
+        strategy: &dyn LayerRenderingStrategy<Layer>,
+        tile: (u32, u32, u32, u32),
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> TextureId {
+        let mut back = Self::tile_texture(tile, framework);
+        let mut result = Self::tile_texture(tile, framework);
+        for item in items.iter().rev() {
+            match item {
+                LayerItem::SingleLayer(id) => {
+                    let layer = layers.get(id).unwrap();
+                    let canvas = Self::tile_texture(tile, framework);
+                    strategy.render_layer_tile(layer, tile, &canvas, framework, renderer);
+                    strategy.composite_texture_on_target(
+                        layer, &canvas, &back, &result, renderer, framework,
+                    );
+                    framework.free_texture2d(canvas);
+                }
+                LayerItem::Group(items, group_layer_id) => {
+                    let rendered_group = Self::composite_tile_items(
+                        items, layers, strategy, tile, renderer, framework,
+                    );
+                    strategy.composite_texture_on_target(
+                        layers.get(group_layer_id).unwrap(),
+                        &rendered_group,
+                        &back,
+                        &result,
+                        renderer,
+                        framework,
+                    );
+                    framework.free_texture2d(rendered_group);
+                }
+            }
+            std::mem::swap(&mut back, &mut result);
+        }
+        framework.free_texture2d(result);
+        back
+    }
+
+    fn tile_texture(tile: (u32, u32, u32, u32), framework: &mut Framework) -> TextureId {
+        framework.allocate_texture2d(
+            RgbaTexture2D::empty((tile.2, tile.3)),
+            TextureConfiguration {
+                label: Some("Export tile"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        )
+    }
+
+    fn export_texture(&self) -> &TextureId {
+        self.export_result.as_ref().unwrap_or(&self.render_result)
+    }
+
+    fn texture_into_image(texture: RgbaTexture2D) -> Result<DynamicImage, TexelConversionError> {
+        let width = texture.width();
+        let height = texture.height();
+        let bytes = texture.data().ok_or(TexelConversionError::NotEnoughData)?;
+        let bytes = bytemuck::cast_slice(bytes).to_owned();
+        let raw_image = ImageBuffer::from_raw(width, height, bytes)
+            .ok_or(TexelConversionError::NotEnoughData)?;
+        Ok(DynamicImage::ImageRgba8(raw_image))
+    }
+
+    pub fn for_each_layer<F: FnMut(&Layer, &LayerId)>(&self, mut f: F) {
+        self.tree.for_each_layer(|l| f(l, &l.id().clone()));
+    }
+
+    pub fn tree(&self) -> &LayerTree<Layer> {
+        &self.tree
+    }
+
+    pub fn render_camera(&self) -> Camera2d {
+        let half_w = self.document_size.x as f32 * 0.5;
+        let half_h = self.document_size.y as f32 * 0.5;
+        Camera2d::new(-0.01, 1000.0, [-half_w, half_w, half_h, -half_h])
+    }
+
+    pub fn make_camera_for_layer(layer: &Layer) -> Camera2d {
+        let extents = layer.bounds().extents;
+        Camera2d::new(
+            -0.01,
+            1000.0,
+            [-extents.x, extents.x, extents.y, -extents.y],
+        )
+    }
+
+    pub fn render_result(&self) -> &TextureId {
+        &self.render_result
+    }
+
+    // The layers are composited from the bottom one up, so each cached texture holds an item
+    // blended over all the items below it: only the items from the lowest changed one up
+    // are blended again, starting from the cached composite of the unchanged items below them.
+    // Painting usually happens on the topmost layers, so most renders blend a single layer
+    fn composite_layers(
+        &mut self,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> TextureId {
+        if self.depth_compositing
+            && self.rendering_strategy_kind == RenderingStrategyKind::Canvas
+            && self.can_composite_with_depth()
+        {
+            return self.composite_layers_with_depth(renderer, framework);
+        }
+        let (width, height) = (self.document_size.x, self.document_size.y);
+        self.composited_layers = 0;
+
+        // All the items are checked, so that every dirty flag is consumed
+        let item_count = self.tree.items.len();
+        let mut first_changed = None;
+        for (index, item) in self.tree.items.iter().rev().enumerate() {
+            let moved = self
+                .composite_cache
+                .get(index)
+                .map_or(true, |cached| &cached.item != item);
+            let changed = Self::item_needs_composite(item, &self.tree.layers);
+            if (moved || changed) && first_changed.is_none() {
+                first_changed = Some(index);
+            }
+        }
+        let removed_items = item_count.min(self.composite_cache.len());
+        for stale in self.composite_cache.drain(removed_items..) {
+            framework.free_texture2d(stale.texture);
+        }
+
+        let first_changed = first_changed.unwrap_or(item_count);
+        for index in first_changed..item_count {
+            let item = &self.tree.items[item_count - 1 - index];
+            if index == self.composite_cache.len() {
+                let texture = framework.allocate_texture2d(
+                    RgbaTexture2D::empty((width, height)),
+                    TextureConfiguration {
+                        label: Some("Cached layer composite"),
+                        usage: TextureUsage::RWRT,
+                        mip_count: None,
+                        color_space: ColorSpace::Srgb,
+                    },
+                );
+                self.composite_cache.push(CachedComposite {
+                    item: item.clone(),
+                    texture,
+                });
+            }
+            let back = match index {
+                0 => Self::empty_texture(width, height, framework),
+                _ => self.composite_cache[index - 1].texture.clone(),
+            };
+            let target = self.composite_cache[index].texture.clone();
+            Self::composite_item(
+                item,
+                &self.tree.layers,
+                self.rendering_strategy.as_ref(),
+                &back,
+                &target,
+                width,
+                height,
+                &mut self.composited_layers,
+                renderer,
+                framework,
+            );
+            self.composite_cache[index].item = item.clone();
+        }
+
+        match self.composite_cache.last() {
+            Some(cached) => cached.texture.clone(),
+            None => Self::empty_texture(width, height, framework),
+        }
+    }
+
+    fn can_composite_with_depth(&self) -> bool {
+        self.tree.items.iter().all(|item| match item {
+            LayerItem::SingleLayer(id) => {
+                hides_the_layers_below(self.tree.layers.get(id).unwrap().settings())
+            }
+            LayerItem::Group(..) => false,
+        })
+    }
+
+    // The layers are drawn from the topmost one down, each one further away than the
+    // previous ones: the depth test discards the pixels hidden by the layers above
+    fn composite_layers_with_depth(
+        &mut self,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> TextureId {
+        let (width, height) = (self.document_size.x, self.document_size.y);
+        // The cache isn't updated by this path, so it must be rebuilt when it's used again
+        for stale in self.composite_cache.drain(..) {
+            framework.free_texture2d(stale.texture);
+        }
+        // The dirty flags are consumed anyway, they're stale once the cache is rebuilt
+        for item in self.tree.items.iter() {
+            Self::item_needs_composite(item, &self.tree.layers);
+        }
+        let depth_texture = self
+            .depth_texture
+            .get_or_insert_with(|| {
+                framework.allocate_depth_stencil_texture(
+                    DepthStencilTexture2D::empty((width, height)),
+                    TextureConfiguration {
+                        label: Some("Layer composite depth texture"),
+                        usage: TextureUsage::RWRT,
+                        mip_count: None,
+                        color_space: ColorSpace::Srgb,
+                    },
+                )
+            })
+            .clone();
+        let target = framework.allocate_transient_texture2d(
+            RgbaTexture2D::empty((width, height)),
+            TextureConfiguration {
+                label: Some("Depth composite"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+
+        renderer.begin(
+            &Camera2d::default(),
+            Some(wgpu::Color::TRANSPARENT),
+            framework,
+        );
+        renderer.set_draw_debug_name("Depth layer composite");
+        renderer.set_depth_clear(Some(1.0));
+        let layer_count = self.tree.items.len();
+        for (index, item) in self.tree.items.iter().enumerate() {
+            let id = match item {
+                LayerItem::SingleLayer(id) => id,
+                LayerItem::Group(..) => unreachable!(),
+            };
+            let depth = layer_depth(index, layer_count);
+            let depth_buffer = framework.allocate_transient_buffer(BufferConfiguration {
+                initial_setup: BufferInitialSetup::Data(&vec![depth]),
+                buffer_type: BufferType::Uniform,
+                gpu_copy_dest: false,
+                gpu_copy_source: false,
+                cpu_copy_dest: false,
+                cpu_copy_source: false,
+            });
+            renderer.draw(DrawCommand {
+                primitives: PrimitiveType::Texture2D {
+                    texture_id: self.rendering_strategy.canvas(id).clone(),
+                    instances: vec![Transform2d::default()],
+                    flip_uv_y: true,
+                    multiply_color: wgpu::Color::WHITE,
+                },
+                draw_mode: DrawMode::Single,
+                additional_data: OptionalDrawData {
+                    additional_vertex_buffers: vec![],
+                    additional_bindable_resource: vec![BindableResource::UniformBuffer(
+                        depth_buffer,
+                    )],
+                    shader: Some(global_selection_data().depth_composite_shader.clone()),
+                    scissor: None,
+                },
+            });
+        }
+        renderer.end(
+            &target,
+            Some((&depth_texture, DepthStencilUsage::Depth)),
+            framework,
+        );
+        renderer.set_depth_clear(None);
+        self.composited_layers = layer_count;
+        target
+    }
+
+    fn item_needs_composite(item: &LayerItem, layers: &HashMap<LayerId, Layer>) -> bool {
+        item.ids().iter().fold(false, |changed, id| {
+            layers.get(id).unwrap().needs_composite() || changed
+        })
+    }
+
+    fn empty_texture(width: u32, height: u32, framework: &mut Framework) -> TextureId {
+        framework.allocate_transient_texture2d(
+            RgbaTexture2D::empty((width, height)),
+            TextureConfiguration {
+                label: Some("Empty composite"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        )
+    }
+
+    // Blends the item over the back texture, storing the result in target
+    fn composite_item(
+        item: &LayerItem,
+        layers: &HashMap<LayerId, Layer>,
+        strategy: &dyn LayerRenderingStrategy<Layer>,
+        back: &TextureId,
+        target: &TextureId,
+        width: u32,
+        height: u32,
+        composited_layers: &mut usize,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) {
+        match item {
+            LayerItem::SingleLayer(id) => {
+                strategy.composite_layer_on_target(
+                    layers.get(id).unwrap(),
+                    back,
+                    target,
+                    renderer,
+                    framework,
+                );
+            }
+            LayerItem::Group(items, group_layer_id) => {
+                let rendered_group = Self::composite_group(
+                    items,
+                    layers,
+                    strategy,
+                    width,
+                    height,
+                    composited_layers,
+                    renderer,
+                    framework,
+                );
+                strategy.composite_texture_on_target(
+                    layers.get(group_layer_id).unwrap(),
+                    &rendered_group,
+                    back,
+                    target,
+                    renderer,
+                    framework,
+                );
+            }
+        }
+        *composited_layers += 1;
+    }
+
+    // The groups aren't cached, their children are always composited from scratch
+    fn composite_group(
+        items: &Vec<LayerItem>,
+        layers: &HashMap<LayerId, Layer>,
+        strategy: &dyn LayerRenderingStrategy<Layer>,
+        width: u32,
+        height: u32,
+        composited_layers: &mut usize,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> TextureId {
+        let mut ab_render_target = ABRenderTarget::new(width, height, framework);
+        for item in items.iter().rev() {
+            ab_render_target.run_render_loop(|result, back| {
+                Self::composite_item(
+                    item,
+                    layers,
+                    strategy,
+                    back,
+                    result,
+                    width,
+                    height,
+                    composited_layers,
+                    renderer,
+                    framework,
+                );
+            });
+        }
+        ab_render_target.result().clone()
+    }
+
+    fn draw_background(
+        background: &DocumentBackground,
+        layers: &TextureId,
+        target: &TextureId,
+        width: u32,
+        height: u32,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) {
+        renderer.begin(
+            &Camera2d::wh(width, height),
+            Some(background.clear_color()),
+            framework,
+        );
+        renderer.set_draw_debug_name("Document background");
+        if background == &DocumentBackground::Checker {
+            renderer.draw(DrawCommand {
+                primitives: PrimitiveType::Rect {
+                    rects: vec![Box2d {
+                        center: point2(0.0, 0.0),
+                        extents: vec2(width as f32, height as f32) * 0.5,
+                    }],
+                    multiply_color: wgpu::Color::WHITE,
+                },
+                draw_mode: DrawMode::Single,
+                additional_data: OptionalDrawData::just_shader(Some(
+                    global_selection_data().checker_background_shader.clone(),
+                )),
+            });
+        }
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Texture2D {
+                texture_id: layers.clone(),
+                instances: vec![Transform2d {
+                    scale: vec2(width as f32, height as f32) * 0.5,
+                    ..Default::default()
+                }],
+                flip_uv_y: true,
+                multiply_color: wgpu::Color::WHITE,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData::just_shader(Some(
+                global_selection_data().alpha_blended_texture_shader.clone(),
+            )),
+        });
+        renderer.end(target, None, framework);
+    }
+}
+
+// Only the layers drawn as they are, without blending them with the ones below, can be
+// composited by the depth test
+fn hides_the_layers_below(settings: &LayerSettings) -> bool {
+    settings.blend_mode == BlendMode::Normal && settings.opacity == 1.0
+}
+
+// The depth of the top level layer at index, the topmost one is the nearest to the camera.
+// The depths stay between the near and the far planes, which are left to the clear value
+fn layer_depth(index: usize, layer_count: usize) -> f32 {
+    (index + 1) as f32 / (layer_count + 1) as f32
+}
+
+// The (x, y, width, height) tiles covering an image, row by row: the last tile of each row and
+// column is cut to fit the image
+fn image_tiles(
+    width: u32,
+    height: u32,
+    tile_size: u32,
+) -> impl Iterator<Item = (u32, u32, u32, u32)> {
+    let tile_size = tile_size.max(1);
+    (0..height).step_by(tile_size as usize).flat_map(move |y| {
+        (0..width)
+            .step_by(tile_size as usize)
+            .map(move |x| (x, y, tile_size.min(width - x), tile_size.min(height - y)))
+    })
+}
+
+// Copies the tightly packed RGBA rows of a tile into the image it was cut from
+fn copy_tile_rows(
+    image: &mut [u8],
+    image_width: u32,
+    tile: (u32, u32, u32, u32),
+    tile_bytes: &[u8],
+) {
+    let (x, y, width, height) = tile;
+    let row_bytes = width as usize * 4;
+    for row in 0..height as usize {
+        let source = row * row_bytes;
+        let dest = ((y as usize + row) * image_width as usize + x as usize) * 4;
+        image[dest..dest + row_bytes].copy_from_slice(&tile_bytes[source..source + row_bytes]);
+    }
+}
+
+fn join_bitmaps(
+    layer_below: &Layer,
+    layer_top: &Layer,
+    renderer: &mut Renderer,
+    framework: &mut Framework,
+) {
+    match (&layer_top.layer_type, &layer_below.layer_type) {
+        (
+            LayerType::Image { texture: top, .. },
+            LayerType::Image {
+                texture: bottom, ..
+            },
+        ) => {
+            let below_inverse_transform = layer_below
+                .transform()
+                .matrix()
+                .invert()
+                .expect("Failed to invert matrix in join layers!");
+            let adjusted_top_transform = layer_top.transform().matrix() * below_inverse_transform;
+            let mut transform = math::helpers::decompose_no_shear_2d(adjusted_top_transform);
+            transform.scale(layer_top.bounds().extents);
+            renderer.begin(
+                &Document::make_camera_for_layer(layer_below),
+                None,
+                framework,
+            );
+            renderer.draw(DrawCommand {
+                primitives: PrimitiveType::Texture2D {
+                    texture_id: top.clone(),
+                    instances: vec![transform],
+                    flip_uv_y: false,
+                    multiply_color: wgpu::Color::WHITE,
+                },
+                draw_mode: DrawMode::Single,
+                additional_data: OptionalDrawData::just_shader(layer_top.interpolation().shader()),
+            });
+
+            renderer.end(&bottom, None, framework);
+        }
+        _ => log::warn!(
+            "Cannot join layer of type {:?} with layer of type {:?}",
+            layer_below.layer_type,
+            layer_top.layer_type
         ),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use cgmath::{point2, vec2, Deg, Rad};
+    use framework::{
+        renderer::renderer::Renderer, Box2d, Camera2d, ColorSpace, Framework, R8Texture2D,
+        RgbaTexture2D, RgbaU8, Texture,
+    };
+
+    use std::collections::HashSet;
+
+    use crate::{
+        document::{AlphaMode, DocumentBackground},
+        filters::{NoiseKind, NoiseParameters},
+        headless_framework,
+        image_editor::exr_export::{promote_texels, read_exr},
+        image_editor::image_editor::LayerCreationType,
+        layers::{
+            Layer, LayerColorLabel, LayerCreationInfo, LayerId, LayerItem, LayerSettings,
+            RenderingStrategyKind,
+        },
+        selection::{SelectionAddition, SelectionDisplay, SelectionShape, Shape},
+        test_support::{
+            background_layer_id, layer_texels, render_target, test_document, texel_channels,
+            texture_texels,
+        },
+        LayerConstructionInfo,
+    };
+
+    use super::{
+        copy_tile_rows, hides_the_layers_below, image_tiles, layer_depth, straighten_angle,
+        BlendMode, Document, DocumentCreationInfo, DocumentSizeError, ExtractSelectionError,
+        StraightenAxis,
+    };
+
+    fn generate_noise(framework: &mut Framework, kind: NoiseKind, seed: u32) -> Vec<u8> {
+        let mut renderer = Renderer::new(framework);
+        let mut document = test_document(300, 200, framework);
+        let layer = document.current_layer_index().copied().unwrap();
+        document
+            .fill_with_noise(
+                &layer,
+                kind,
+                seed,
+                NoiseParameters::default(),
+                &mut renderer,
+                framework,
+            )
+            .unwrap();
+        bytemuck::cast_slice(&layer_texels(&document, &layer, framework)).to_vec()
+    }
+
+    // Opaque where the predicate holds, transparent everywhere else
+    fn add_partial_layer(
+        document: &mut Document,
+        name: &str,
+        color: [u8; 4],
+        f: fn(u32, u32) -> bool,
+        framework: &mut Framework,
+    ) -> LayerId {
+        let (width, height) = (document.document_size().x, document.document_size().y);
+        let texels = (0..width * height)
+            .map(|i| {
+                if f(i % width, i / width) {
+                    RgbaU8(color)
+                } else {
+                    RgbaU8([0; 4])
+                }
+            })
+            .collect();
+        let layer = Layer::new_image(
+            RgbaTexture2D::from_texels(texels, (width, height)).unwrap(),
+            LayerCreationInfo {
+                name: name.into(),
+                position: point2(0.0, 0.0),
+                scale: vec2(1.0, 1.0),
+                rotation_radians: 0.0,
+            },
+            framework,
+        );
+        document.insert_layer(layer, framework)
+    }
+
+    fn render_pixels(
+        document: &mut Document,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> Vec<[u8; 4]> {
+        document.update_layers(renderer, framework);
+        document.render(renderer, framework);
+        let image = document
+            .final_image_bytes(AlphaMode::Straight, framework)
+            .unwrap()
+            .into_rgba8();
+        image.pixels().map(|p| p.0).collect()
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn noise_is_deterministic() {
+        let mut framework = headless_framework();
+
+        for kind in [NoiseKind::White, NoiseKind::Value, NoiseKind::Perlin] {
+            let first = generate_noise(&mut framework, kind, 42);
+            let second = generate_noise(&mut framework, kind, 42);
+            let other_seed = generate_noise(&mut framework, kind, 43);
+            assert_eq!(first, second);
+            assert_ne!(first, other_seed);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn solid_background_shows_through_transparent_layers() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(64, 32, &mut framework);
+        // The opaque background layer hides the document background
+        document.set_background(DocumentBackground::Solid([255, 0, 0, 255]));
+        document.update_layers(&mut renderer, &mut framework);
+        document.render(&mut renderer, &mut framework);
+        let image = document
+            .final_image_bytes(AlphaMode::Straight, &framework)
+            .unwrap()
+            .into_rgba8();
+        assert!(image.pixels().all(|p| p.0 == [255, 255, 255, 255]));
+
+        // Only the transparent "Layer 0" is left
+        document.delete_layer(background_layer_id(&document), &mut framework);
+        document.update_layers(&mut renderer, &mut framework);
+        document.render(&mut renderer, &mut framework);
+        let image = document
+            .final_image_bytes(AlphaMode::Straight, &framework)
+            .unwrap()
+            .into_rgba8();
+        assert!(image.pixels().all(|p| p.0 == [255, 0, 0, 255]));
+
+        // When the background is left out of the exports, the final image stays transparent
+        document.set_export_background(false);
+        document.render(&mut renderer, &mut framework);
+        let image = document
+            .final_image_bytes(AlphaMode::Straight, &framework)
+            .unwrap()
+            .into_rgba8();
+        assert!(image.pixels().all(|p| p.0[3] == 0));
+        assert!(texture_texels(document.render_result(), &framework)
+            .iter()
+            .all(|t| t.0 == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn group_opacity_is_applied_to_the_group() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(32, 32, &mut framework);
+        document.delete_layer(background_layer_id(&document), &mut framework);
+        let red_layer = document.add_layer(
+            LayerConstructionInfo {
+                initial_color: [255, 0, 0, 255],
+                layer_type: LayerCreationType::Image,
+                name: "Red".into(),
+            },
+            &mut framework,
+        );
+        let group = document.group_layers(&HashSet::from([red_layer]), "Group", &mut framework);
+
+        let mut render_alpha = |document: &mut Document| {
+            document.update_layers(&mut renderer, &mut framework);
+            document.render(&mut renderer, &mut framework);
+            let image = document
+                .final_image_bytes(AlphaMode::Straight, &framework)
+                .unwrap()
+                .into_rgba8();
+            let alphas: Vec<u8> = image.pixels().map(|p| p.0[3]).collect();
+            assert!(alphas.windows(2).all(|w| w[0] == w[1]));
+            alphas[0]
+        };
+        assert_eq!(render_alpha(&mut document), 255);
+
+        document.mutate_layer(&group, |layer| {
+            layer.set_settings(LayerSettings {
+                opacity: 0.5,
+                ..layer.settings().clone()
+            })
+        });
+        let alpha = render_alpha(&mut document);
+        assert!((127..=128).contains(&alpha), "alpha was {alpha}");
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn extracting_from_a_group_is_refused() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(32, 32, &mut framework);
+        let red_layer = document.add_layer(
+            LayerConstructionInfo {
+                initial_color: [255, 0, 0, 255],
+                layer_type: LayerCreationType::Image,
+                name: "Red".into(),
+            },
+            &mut framework,
+        );
+        let group = document.group_layers(&HashSet::from([red_layer]), "Group", &mut framework);
+        document.select_layer(group);
+        document.select_all();
+
+        assert!(document.current_layer().is_group());
+        assert_eq!(
+            document.extract_selection(&mut renderer, &mut framework),
+            Err(ExtractSelectionError::GroupLayer)
+        );
+        assert!(document.selection_layer().is_none());
+        assert!(!document.selection().is_empty());
+
+        // The layers of the group can still be extracted
+        document.select_layer(red_layer);
+        assert_eq!(
+            document.extract_selection(&mut renderer, &mut framework),
+            Ok(())
+        );
+        assert!(document.selection_layer().is_some());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn a_group_holding_every_layer_is_not_deleted() {
+        let mut framework = headless_framework();
+        let mut document = test_document(32, 32, &mut framework);
+        let mut all_layers = HashSet::new();
+        document.for_each_layer(|_, id| {
+            all_layers.insert(*id);
+        });
+        let group = document.group_layers(&all_layers, "Group", &mut framework);
+        assert_eq!(document.tree().items().len(), 1);
+
+        assert!(!document.can_delete(&HashSet::from([group])));
+        assert!(document.can_delete(&HashSet::from([*all_layers.iter().next().unwrap()])));
+        assert!(!document.can_delete(&all_layers));
+        document.select_layer(group);
+        assert!(document.delete_selected(&mut framework).is_empty());
+        assert_eq!(document.tree().items().len(), 1);
+        assert!(document.current_layer().is_group());
+
+        // Deleting one of the children leaves the other one
+        let child = *all_layers.iter().next().unwrap();
+        document.select_layer(child);
+        assert_eq!(document.delete_selected(&mut framework).len(), 1);
+        document.select_layer(group);
+        assert!(document.delete_selected(&mut framework).is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn flattening_the_visible_layers_leaves_the_hidden_ones() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(32, 32, &mut framework);
+        let red_layer = document.add_layer(
+            LayerConstructionInfo {
+                initial_color: [255, 0, 0, 255],
+                layer_type: LayerCreationType::Image,
+                name: "Red".into(),
+            },
+            &mut framework,
+        );
+        document.mutate_layer(&red_layer, |layer| {
+            layer.set_settings(LayerSettings {
+                is_enabled: false,
+                ..layer.settings().clone()
+            })
+        });
+        let original_items = document.tree().items().clone();
+
+        let (flattened, detached) = document
+            .flatten(true, &mut renderer, &mut framework)
+            .unwrap();
+        assert_eq!(document.tree().items().len(), 2);
+        assert!(document.tree().find_item(&red_layer).is_some());
+        assert!(layer_texels(&document, &flattened, &framework)
+            .iter()
+            .all(|texel| *texel == RgbaU8([255; 4])));
+
+        // Undoing puts the flattened layers back where they were
+        document.delete_layer(flattened, &mut framework);
+        for layer in detached.into_iter().rev() {
+            document.restore_layer(layer, &mut framework);
+        }
+        assert_eq!(document.tree().items(), &original_items);
+
+        let (flattened, _) = document
+            .flatten(false, &mut renderer, &mut framework)
+            .unwrap();
+        assert_eq!(document.tree().items().len(), 1);
+        assert!(layer_texels(&document, &flattened, &framework)
+            .iter()
+            .all(|texel| *texel == RgbaU8([255, 0, 0, 255])));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn merge_preview_keeps_the_sources_until_committed() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(32, 32, &mut framework);
+        let background = *document.tree().items()[0].id();
+        let red_layer = document.add_layer(
+            LayerConstructionInfo {
+                initial_color: [255, 0, 0, 255],
+                layer_type: LayerCreationType::Image,
+                name: "Red".into(),
+            },
+            &mut framework,
+        );
+        let original_items = document.tree().items().clone();
+
+        let background_texels = layer_texels(&document, &background, &framework);
+
+        let merged = document
+            .merge_down_preview(&red_layer, &mut renderer, &mut framework)
+            .unwrap();
+        assert_eq!(document.pending_merge(), Some(&merged));
+        assert_eq!(document.tree().items().len(), 3);
+        assert_eq!(document.tree().items()[0].id(), &merged);
+        assert!(layer_texels(&document, &red_layer, &framework)
+            .iter()
+            .all(|texel| *texel == RgbaU8([255, 0, 0, 255])));
+        assert_eq!(
+            layer_texels(&document, &background, &framework),
+            background_texels
+        );
+        assert!(layer_texels(&document, &merged, &framework)
+            .iter()
+            .all(|texel| *texel == RgbaU8([255, 0, 0, 255])));
+
+        // Hiding the preview gives the sources their opacity back
+        document.show_merge_preview(false);
+        assert_eq!(document.get_layer(&red_layer).settings().opacity, 1.0);
+        assert_eq!(document.get_layer(&merged).settings().opacity, 0.0);
+        document.show_merge_preview(true);
+        assert_eq!(document.get_layer(&red_layer).settings().opacity, 0.0);
+
+        let (committed, detached) = document.commit_merge(&mut framework).unwrap();
+        assert_eq!(committed, merged);
+        assert!(document.pending_merge().is_none());
+        assert_eq!(
+            document.tree().items(),
+            &vec![LayerItem::SingleLayer(merged)]
+        );
+        assert_eq!(document.get_layer(&merged).settings().opacity, 1.0);
+
+        // Undoing puts the merged layers back where they were
+        document.delete_layer(merged, &mut framework);
+        for layer in detached.into_iter().rev() {
+            document.restore_layer(layer, &mut framework);
+        }
+        assert_eq!(document.tree().items(), &original_items);
+        assert_eq!(document.get_layer(&red_layer).settings().opacity, 1.0);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn select_all_covers_the_document() {
+        let mut framework = headless_framework();
+
+        let mut document = test_document(64, 32, &mut framework);
+        document.select_all();
+        let selection = document.selection();
+        for corner in [(-32.0, -16.0), (32.0, -16.0), (-32.0, 16.0), (32.0, 16.0)] {
+            assert!(selection.contains(point2(corner.0, corner.1)));
+        }
+        for outside in [(-33.0, 0.0), (33.0, 0.0), (0.0, -17.0), (0.0, 17.0)] {
+            assert!(!selection.contains(point2(outside.0, outside.1)));
+        }
+
+        document.deselect();
+        assert!(document.selection().is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn selection_mask_texture() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(64, 32, &mut framework);
+        // Covers the pixels from (24, 12) to (39, 19)
+        document.mutate_selection(|selection| {
+            selection.set(SelectionShape {
+                mode: SelectionAddition::Add,
+                shape: Shape::Rectangle(Box2d {
+                    center: point2(0.0, 0.0),
+                    extents: vec2(8.0, 4.0),
+                }),
+            })
+        });
+        let is_inside = |i: usize| {
+            let (x, y) = (i % 64, i / 64);
+            (24..40).contains(&x) && (12..20).contains(&y)
+        };
+
+        let mask = document.selection_mask_texture(false, &mut renderer, &mut framework);
+        assert_eq!((mask.width(), mask.height()), (64, 32));
+        for (i, texel) in mask.data().unwrap().iter().enumerate() {
+            assert_eq!(texel.0, if is_inside(i) { 255 } else { 0 });
+        }
+
+        document.mutate_selection(|selection| selection.invert());
+        let mask = document.selection_mask_texture(false, &mut renderer, &mut framework);
+        for (i, texel) in mask.data().unwrap().iter().enumerate() {
+            assert_eq!(texel.0, if is_inside(i) { 0 } else { 255 });
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn the_quick_mask_starts_from_the_selection() {
+        let mut framework = headless_framework();
+
+        let mut document = test_document(64, 32, &mut framework);
+        document.mutate_selection(|selection| {
+            selection.set(SelectionShape {
+                mode: SelectionAddition::Add,
+                shape: Shape::Rectangle(Box2d {
+                    center: point2(0.0, 0.0),
+                    extents: vec2(8.0, 4.0),
+                }),
+            })
+        });
+
+        // Leaving the quick mask right away keeps the selection
+        document.toggle_quick_mask(&mut framework);
+        document.toggle_quick_mask(&mut framework);
+        let selection = document.selection();
+        assert!(selection.contains(point2(0.5, 0.5)));
+        assert!(selection.contains(point2(-7.5, 3.5)));
+        assert!(!selection.contains(point2(8.5, 0.5)));
+        assert!(!selection.contains(point2(0.5, -4.5)));
+
+        // Without a selection the mask starts empty
+        document.mutate_selection(|selection| selection.clear());
+        document.toggle_quick_mask(&mut framework);
+        document.toggle_quick_mask(&mut framework);
+        assert!(!document.selection().contains(point2(0.5, 0.5)));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn the_blur_is_clipped_to_the_selection() {
+        let mut framework = headless_framework();
+
+        let mut document = test_document(64, 32, &mut framework);
+        let background_layer = background_layer_id(&document);
+        // Vertical stripes, so that the blur changes every pixel
+        let stripes = (0..64 * 32)
+            .map(|i| {
+                if i % 2 == 0 {
+                    RgbaU8([255; 4])
+                } else {
+                    RgbaU8([0, 0, 0, 255])
+                }
+            })
+            .collect();
+        let stripes = RgbaTexture2D::from_texels(stripes, (64, 32)).unwrap();
+        document.mutate_layer(&background_layer, |layer| {
+            assert!(layer.write_pixels(&stripes, &mut framework))
+        });
+        // Covers the pixels from (24, 12) to (39, 19)
+        document.mutate_selection(|selection| {
+            selection.set(SelectionShape {
+                mode: SelectionAddition::Add,
+                shape: Shape::Rectangle(Box2d {
+                    center: point2(0.0, 0.0),
+                    extents: vec2(8.0, 4.0),
+                }),
+            })
+        });
+
+        let previous = document
+            .apply_gaussian_blur(&background_layer, 2.0, None, None, &mut framework)
+            .unwrap()
+            .unwrap();
+        assert_eq!(texel_channels(&previous), texel_channels(&stripes));
+
+        let blurred = layer_texels(&document, &background_layer, &framework);
+        for (i, original) in stripes.data().unwrap().iter().enumerate() {
+            let (x, y) = (i % 64, i / 64);
+            if (24..40).contains(&x) && (12..20).contains(&y) {
+                assert_ne!(&blurred[i], original);
+            } else {
+                assert_eq!(&blurred[i], original);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn subtracted_shapes_are_removed_from_the_mask() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(64, 32, &mut framework);
+        let rect = |center, extents, mode| SelectionShape {
+            mode,
+            shape: Shape::Rectangle(Box2d { center, extents }),
+        };
+        document.mutate_selection(|selection| {
+            selection.set(rect(
+                point2(-8.0, 0.0),
+                vec2(8.0, 8.0),
+                SelectionAddition::Add,
+            ));
+            selection.extend(rect(
+                point2(4.0, 0.0),
+                vec2(8.0, 8.0),
+                SelectionAddition::Add,
+            ));
+            selection.extend(rect(
+                point2(0.0, 6.0),
+                vec2(6.0, 6.0),
+                SelectionAddition::Add,
+            ));
+            selection.extend(rect(
+                point2(0.0, 0.0),
+                vec2(2.0, 2.0),
+                SelectionAddition::Subtract,
+            ));
+        });
+
+        let mask = document.selection_mask_texture(false, &mut renderer, &mut framework);
+        let texels = mask.data().unwrap();
+        // The rows of the mask go from top to bottom
+        let is_selected = |x: f32, y: f32| {
+            let (column, row) = ((x + 32.0) as usize, (16.0 - y) as usize);
+            texels[row * 64 + column].0 == 255
+        };
+        assert!(is_selected(-12.5, 4.5));
+        assert!(is_selected(10.5, -6.5));
+        assert!(is_selected(0.5, 10.5));
+        assert!(is_selected(2.5, 0.5));
+        assert!(!is_selected(0.5, 0.5));
+        assert!(!is_selected(-1.5, -1.5));
+        assert!(!is_selected(20.5, 0.5));
+        assert!(!is_selected(0.5, -12.5));
+
+        // The mask agrees with the selection on every pixel
+        for (i, texel) in texels.iter().enumerate() {
+            let center = point2((i % 64) as f32 - 31.5, 15.5 - (i / 64) as f32);
+            assert_eq!(texel.0 == 255, document.selection().contains(center));
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn the_masked_overlay_tints_outside_the_selection() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(64, 32, &mut framework);
+        // Covers the pixels from (24, 12) to (39, 19)
+        document.mutate_selection(|selection| {
+            selection.set(SelectionShape {
+                mode: SelectionAddition::Add,
+                shape: Shape::Rectangle(Box2d {
+                    center: point2(0.0, 0.0),
+                    extents: vec2(8.0, 4.0),
+                }),
+            })
+        });
+        document.update_layers(&mut renderer, &mut framework);
+        let is_inside = |i: usize| {
+            let (x, y) = (i % 64, i / 64);
+            (24..40).contains(&x) && (12..20).contains(&y)
+        };
+
+        let target = render_target(64, 32, ColorSpace::Linear, &mut framework);
+        let mut draw_overlay = |mode: SelectionDisplay| {
+            renderer.begin(
+                &Camera2d::wh(64, 32),
+                Some(wgpu::Color::TRANSPARENT),
+                &mut framework,
+            );
+            document.draw_selection_overlay(&mut renderer, mode);
+            renderer.end(&target, None, &mut framework);
+            texture_texels(&target, &framework)
+        };
+
+        let masked = draw_overlay(SelectionDisplay::OverlayMasked);
+        for (i, RgbaU8([r, g, _, a])) in masked.iter().enumerate() {
+            if is_inside(i) {
+                assert_eq!(*a, 0, "pixel {i} is tinted");
+            } else {
+                assert!(*a > 0 && *r > *g, "pixel {i} isn't tinted");
+            }
+        }
+
+        // The other overlay tints the complement
+        let selected = draw_overlay(SelectionDisplay::OverlaySelected);
+        for (i, RgbaU8([.., a])) in selected.iter().enumerate() {
+            assert_eq!(*a > 0, is_inside(i), "pixel {i}");
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn anti_aliased_selection_masks_have_soft_edges() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(64, 32, &mut framework);
+        // The edges go through the middle of the pixels around the rectangle
+        document.mutate_selection(|selection| {
+            selection.set(SelectionShape {
+                mode: SelectionAddition::Add,
+                shape: Shape::Rectangle(Box2d {
+                    center: point2(0.0, 0.0),
+                    extents: vec2(8.5, 4.5),
+                }),
+            })
+        });
+        let texel = |mask: &R8Texture2D, x: usize, y: usize| mask.data().unwrap()[y * 64 + x].0;
+
+        let hard = document.selection_mask_texture(false, &mut renderer, &mut framework);
+        assert!(hard.data().unwrap().iter().all(|t| t.0 == 0 || t.0 == 255));
+
+        let soft = document.selection_mask_texture(true, &mut renderer, &mut framework);
+        assert_eq!(texel(&soft, 32, 16), 255);
+        assert_eq!(texel(&soft, 2, 2), 0);
+        // The pixels on the left and right edges are half covered
+        for x in [23, 40] {
+            let edge = texel(&soft, x, 16);
+            assert!(edge > 64 && edge < 192, "{}", edge);
+        }
+        assert!(soft.data().unwrap().iter().any(|t| t.0 > 0 && t.0 < 255));
+
+        // The inverted mask is the complement of the soft one
+        document.mutate_selection(|selection| selection.invert());
+        let inverted = document.selection_mask_texture(true, &mut renderer, &mut framework);
+        for (soft, inverted) in soft.data().unwrap().iter().zip(inverted.data().unwrap()) {
+            assert!((soft.0 as i32 + inverted.0 as i32 - 255).abs() <= 1);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn layers_can_be_blended_without_linearization() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = Document::new(
+            DocumentCreationInfo {
+                width: 8,
+                height: 8,
+                first_layer_color: [0.0, 0.0, 0.0, 1.0],
+            },
+            &mut framework,
+        )
+        .unwrap();
+        let white = add_partial_layer(
+            &mut document,
+            "White",
+            [255, 255, 255, 255],
+            |_, _| true,
+            &mut framework,
+        );
+        document.mutate_layer(&white, |layer| {
+            layer.set_settings(LayerSettings {
+                opacity: 0.5,
+                ..layer.settings().clone()
+            })
+        });
+        let assert_gray = |pixels: Vec<[u8; 4]>, expected: i32| {
+            for pixel in pixels {
+                assert!(
+                    pixel[..3].iter().all(|c| (*c as i32 - expected).abs() <= 2),
+                    "{pixel:?} isn't close to {expected}"
+                );
+            }
+        };
+
+        // Half the light of white is 188 once it's encoded in sRGB
+        assert!(document.linear_blending());
+        assert_gray(
+            render_pixels(&mut document, &mut renderer, &mut framework),
+            188,
+        );
+
+        document.set_linear_blending(false);
+        assert_gray(
+            render_pixels(&mut document, &mut renderer, &mut framework),
+            128,
+        );
+
+        document.set_rendering_strategy(RenderingStrategyKind::CpuBlitting, &mut framework);
+        assert_gray(
+            render_pixels(&mut document, &mut renderer, &mut framework),
+            128,
+        );
+    }
+
+    #[test]
+    fn only_opaque_normal_layers_are_depth_composited() {
+        let normal = LayerSettings::new(&"Normal".to_owned());
+        assert!(hides_the_layers_below(&normal));
+        assert!(!hides_the_layers_below(&LayerSettings {
+            opacity: 0.5,
+            ..normal.clone()
+        }));
+        assert!(!hides_the_layers_below(&LayerSettings {
+            blend_mode: BlendMode::Multiply,
+            ..normal
+        }));
+    }
+
+    #[test]
+    fn upper_layers_are_nearer() {
+        let depths: Vec<f32> = (0..4).map(|index| layer_depth(index, 4)).collect();
+        assert!(depths.windows(2).all(|pair| pair[0] < pair[1]));
+        // 1.0 is the clear value, nothing is drawn on the near plane either
+        assert!(depths.iter().all(|depth| *depth > 0.0 && *depth < 1.0));
+    }
+
+    #[test]
+    fn tiles_cover_every_pixel_once() {
+        let tiles: Vec<_> = image_tiles(5, 3, 2).collect();
+        assert_eq!(
+            tiles,
+            vec![
+                (0, 0, 2, 2),
+                (2, 0, 2, 2),
+                (4, 0, 1, 2),
+                (0, 2, 2, 1),
+                (2, 2, 2, 1),
+                (4, 2, 1, 1)
+            ]
+        );
+        // A tile bigger than the image covers all of it
+        assert_eq!(
+            image_tiles(5, 3, 64).collect::<Vec<_>>(),
+            vec![(0, 0, 5, 3)]
+        );
+        assert_eq!(image_tiles(0, 3, 2).count(), 0);
+    }
+
+    #[test]
+    fn tiles_are_assembled_into_the_image() {
+        let (width, height) = (5, 3);
+        // Each byte holds the index of its pixel
+        let mut image = vec![0u8; width as usize * height as usize * 4];
+        for tile @ (x, y, tile_width, tile_height) in image_tiles(width, height, 2) {
+            let tile_bytes: Vec<u8> = (0..tile_height)
+                .flat_map(|row| (0..tile_width).map(move |column| (row, column)))
+                .flat_map(|(row, column)| [((y + row) * width + x + column) as u8; 4])
+                .collect();
+            copy_tile_rows(&mut image, width, tile, &tile_bytes);
+        }
+        let expected: Vec<u8> = (0..width * height).flat_map(|i| [i as u8; 4]).collect();
+        assert_eq!(image, expected);
+    }
+
+    #[test]
+    fn straighten_two_points() {
+        let degrees = |angle: Option<Rad<f32>>| Deg::from(angle.unwrap()).0;
+        let (start, angle) = (point2(3.0, -2.0), 10.0_f32.to_radians());
+        let end = start + vec2(angle.cos(), angle.sin()) * 50.0;
+        assert!(
+            (degrees(straighten_angle(start, end, StraightenAxis::Horizontal)) + 10.0).abs() < 1e-3
+        );
+        // The order of the points doesn't matter
+        assert!(
+            (degrees(straighten_angle(end, start, StraightenAxis::Horizontal)) + 10.0).abs() < 1e-3
+        );
+        // 10° from the x axis is 80° from the y axis
+        assert!(
+            (degrees(straighten_angle(start, end, StraightenAxis::Vertical)) - 80.0).abs() < 1e-3
+        );
+
+        let end = start + vec2(-0.2, 1.0);
+        let vertical = degrees(straighten_angle(start, end, StraightenAxis::Vertical));
+        assert!((vertical + 0.2_f32.atan().to_degrees()).abs() < 1e-3);
+        assert_eq!(
+            straighten_angle(start, start, StraightenAxis::Horizontal),
+            None
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn documents_must_fit_in_a_texture() {
+        let mut framework = headless_framework();
+
+        let max_side = framework.device.limits().max_texture_dimension_2d;
+        let mut create = |width: u32, height: u32| {
+            Document::new(
+                DocumentCreationInfo {
+                    width,
+                    height,
+                    first_layer_color: [0.0; 4],
+                },
+                &mut framework,
+            )
+            .map(|document| document.document_size())
+        };
+        assert_eq!(
+            create(0, 64).err(),
+            Some(DocumentSizeError::Empty {
+                width: 0,
+                height: 64
+            })
+        );
+        assert!(matches!(
+            create(64, 0),
+            Err(DocumentSizeError::Empty { .. })
+        ));
+        assert_eq!(
+            create(64, max_side + 1).err(),
+            Some(DocumentSizeError::TooLarge {
+                width: 64,
+                height: max_side + 1,
+                max_side
+            })
+        );
+        assert_eq!(create(32, 16).unwrap(), vec2(32, 16));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn layer_opacity_is_applied_when_compositing() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(8, 8, &mut framework);
+        let red = add_partial_layer(
+            &mut document,
+            "Red",
+            [255, 0, 0, 255],
+            |_, _| true,
+            &mut framework,
+        );
+        let set_opacity = |document: &mut Document, opacity: f32| {
+            document.mutate_layer(&red, |layer| {
+                layer.set_settings(LayerSettings {
+                    opacity,
+                    ..layer.settings().clone()
+                })
+            });
+        };
+
+        // Over a transparent background the layer keeps its color, with half its alpha
+        set_opacity(&mut document, 0.5);
+        for pixel in render_pixels(&mut document, &mut renderer, &mut framework) {
+            assert_eq!(pixel[..3], [255, 0, 0], "{pixel:?}");
+            assert!((pixel[3] as i32 - 128).abs() <= 1, "{pixel:?}");
+        }
+        // Premultiplied, the color is scaled by the alpha too
+        let image = document
+            .final_image_bytes(AlphaMode::Premultiplied, &framework)
+            .unwrap()
+            .into_rgba8();
+        for pixel in image.pixels() {
+            let [r, g, b, a] = pixel.0;
+            assert!((r as i32 - a as i32).abs() <= 1, "{pixel:?}");
+            assert_eq!([g, b], [0, 0], "{pixel:?}");
+        }
+
+        // The new opacity is used as soon as the settings change
+        set_opacity(&mut document, 1.0);
+        for pixel in render_pixels(&mut document, &mut renderer, &mut framework) {
+            assert_eq!(pixel, [255, 0, 0, 255]);
+        }
+        set_opacity(&mut document, 0.0);
+        for pixel in render_pixels(&mut document, &mut renderer, &mut framework) {
+            assert_eq!(pixel[3], 0, "{pixel:?}");
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn only_the_changed_layers_are_composited_again() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(32, 32, &mut framework);
+        let top_layer = document.add_layer(
+            LayerConstructionInfo {
+                initial_color: [255, 0, 0, 128],
+                layer_type: LayerCreationType::Image,
+                name: "Top".into(),
+            },
+            &mut framework,
+        );
+        let background_layer = background_layer_id(&document);
+
+        fn render(
+            document: &mut Document,
+            renderer: &mut Renderer,
+            framework: &mut Framework,
+        ) -> (usize, [u8; 4]) {
+            document.update_layers(renderer, framework);
+            document.render(renderer, framework);
+            let image = document
+                .final_image_bytes(AlphaMode::Straight, framework)
+                .unwrap()
+                .into_rgba8();
+            (document.composited_layers(), image.get_pixel(16, 16).0)
+        }
+        let (composited, first_pixel) = render(&mut document, &mut renderer, &mut framework);
+        assert_eq!(composited, 3);
+
+        // Nothing changed, the cached composite is used as it is
+        let (composited, pixel) = render(&mut document, &mut renderer, &mut framework);
+        assert_eq!(composited, 0);
+        assert_eq!(pixel, first_pixel);
+
+        // The composite of the layers below the top one is reused
+        document.mutate_layer(&top_layer, |layer| layer.mark_dirty());
+        let (composited, pixel) = render(&mut document, &mut renderer, &mut framework);
+        assert_eq!(composited, 1);
+        assert_eq!(pixel, first_pixel);
+
+        // The layers are composited from the bottom one, so all of them are blended again
+        document.mutate_layer(&background_layer, |layer| layer.mark_dirty());
+        let (composited, pixel) = render(&mut document, &mut renderer, &mut framework);
+        assert_eq!(composited, 3);
+        assert_eq!(pixel, first_pixel);
+
+        // The composites of the layers below the deleted one are still valid
+        document.delete_layer(top_layer, &mut framework);
+        let (composited, pixel) = render(&mut document, &mut renderer, &mut framework);
+        assert_eq!(composited, 0);
+        assert_eq!(pixel, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn deleted_layers_release_their_textures() {
+        let mut framework = headless_framework();
+        let mut document = test_document(32, 32, &mut framework);
+        // Frees what the earlier users of the framework dropped
+        framework.update_asset_maps();
+        let (textures, buffers) = (
+            framework.allocated_textures_count(),
+            framework.allocated_buffers_count(),
+        );
+
+        let layer = document.add_layer(
+            LayerConstructionInfo {
+                initial_color: [255, 0, 0, 255],
+                layer_type: LayerCreationType::Image,
+                name: "Deleted".into(),
+            },
+            &mut framework,
+        );
+        assert!(framework.allocated_textures_count() > textures);
+        assert!(framework.allocated_buffers_count() > buffers);
+
+        // The canvas is freed with the layer, its own texture once the layer isn't kept for undo
+        let deleted = document.delete_layer(layer, &mut framework);
+        drop(deleted);
+        framework.update_asset_maps();
+        assert_eq!(framework.allocated_textures_count(), textures);
+        assert_eq!(framework.allocated_buffers_count(), buffers);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn transient_allocations_stabilize_across_frames() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(32, 32, &mut framework);
+        let mut layers = vec![];
+        document.for_each_layer(|_, id| layers.push(*id));
+
+        let mut allocations = vec![];
+        for _ in 0..4 {
+            // All the layers are composited again in every frame
+            for layer in &layers {
+                document.mutate_layer(layer, |layer| layer.mark_dirty());
+            }
+            document.update_layers(&mut renderer, &mut framework);
+            document.render(&mut renderer, &mut framework);
+            framework.end_frame();
+            allocations.push(framework.transient_allocations());
+        }
+        // The targets created by the first frame are reused by the next ones
+        assert!(
+            allocations.iter().all(|count| *count == allocations[0]),
+            "{allocations:?}"
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn depth_composite_matches_the_blended_composite() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let (width, height) = (32, 16);
+        let mut document = test_document(width, height, &mut framework);
+        add_partial_layer(
+            &mut document,
+            "Left",
+            [0, 0, 255, 255],
+            |x, _| x < 16,
+            &mut framework,
+        );
+        add_partial_layer(
+            &mut document,
+            "Top",
+            [0, 255, 0, 255],
+            |_, y| y < 8,
+            &mut framework,
+        );
+
+        let blended = render_pixels(&mut document, &mut renderer, &mut framework);
+        assert_eq!(document.composited_layers(), 4);
+
+        document.set_depth_compositing(true);
+        let with_depth = render_pixels(&mut document, &mut renderer, &mut framework);
+        assert_eq!(document.composited_layers(), 4);
+        assert_eq!(blended.len(), with_depth.len());
+        for (blended, with_depth) in blended.iter().zip(with_depth.iter()) {
+            assert!(blended
+                .iter()
+                .zip(with_depth.iter())
+                .all(|(a, b)| (*a as i32 - *b as i32).abs() <= 1));
+        }
+        let green = with_depth
+            .iter()
+            .filter(|p| **p == [0, 255, 0, 255])
+            .count();
+        let blue = with_depth
+            .iter()
+            .filter(|p| **p == [0, 0, 255, 255])
+            .count();
+        assert_eq!(green, 32 * 8);
+        assert_eq!(blue, 16 * 8);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn cpu_blitting_matches_the_canvas_strategy() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(32, 16, &mut framework);
+        let left = add_partial_layer(
+            &mut document,
+            "Left",
+            [0, 0, 255, 255],
+            |x, _| x < 16,
+            &mut framework,
+        );
+        let top = add_partial_layer(
+            &mut document,
+            "Top",
+            [255, 128, 0, 200],
+            |_, y| y < 8,
+            &mut framework,
+        );
+        document.mutate_layer(&left, |layer| {
+            layer.set_settings(LayerSettings {
+                opacity: 0.5,
+                ..layer.settings().clone()
+            })
+        });
+        document.mutate_layer(&top, |layer| {
+            layer.set_settings(LayerSettings {
+                blend_mode: BlendMode::Screen,
+                ..layer.settings().clone()
+            })
+        });
+
+        let on_gpu = render_pixels(&mut document, &mut renderer, &mut framework);
+        document.set_rendering_strategy(RenderingStrategyKind::CpuBlitting, &mut framework);
+        let on_cpu = render_pixels(&mut document, &mut renderer, &mut framework);
+        assert_eq!(on_gpu.len(), on_cpu.len());
+        for (gpu, cpu) in on_gpu.iter().zip(on_cpu.iter()) {
+            assert!(
+                gpu.iter()
+                    .zip(cpu.iter())
+                    .all(|(a, b)| (*a as i32 - *b as i32).abs() <= 2),
+                "{gpu:?} != {cpu:?}"
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn tiled_export_matches_the_single_pass_export() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(40, 24, &mut framework);
+        let diagonal = add_partial_layer(
+            &mut document,
+            "Diagonal",
+            [0, 0, 255, 255],
+            |x, y| x > y,
+            &mut framework,
+        );
+        let stripes = add_partial_layer(
+            &mut document,
+            "Stripes",
+            [255, 128, 0, 200],
+            |x, _| x % 6 < 3,
+            &mut framework,
+        );
+        add_partial_layer(
+            &mut document,
+            "Corner",
+            [0, 255, 0, 255],
+            |x, y| x >= 30 && y >= 18,
+            &mut framework,
+        );
+        let group =
+            document.group_layers(&HashSet::from([diagonal, stripes]), "Group", &mut framework);
+        document.mutate_layer(&stripes, |layer| {
+            layer.set_settings(LayerSettings {
+                blend_mode: BlendMode::Multiply,
+                ..layer.settings().clone()
+            })
+        });
+        document.mutate_layer(&group, |layer| {
+            layer.set_settings(LayerSettings {
+                opacity: 0.5,
+                ..layer.settings().clone()
+            })
+        });
+        document.set_background(DocumentBackground::Solid([255, 255, 255, 255]));
+
+        let single_pass = render_pixels(&mut document, &mut renderer, &mut framework);
+        // The tiles along the right and bottom edges are smaller than the others
+        let tiled = document
+            .final_image_tiled(16, &mut renderer, &mut framework)
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(tiled.dimensions(), (40, 24));
+        for (single, tiled) in single_pass.iter().zip(tiled.pixels()) {
+            assert!(
+                single
+                    .iter()
+                    .zip(tiled.0.iter())
+                    .all(|(a, b)| (*a as i32 - *b as i32).abs() <= 1),
+                "{single:?} != {:?}",
+                tiled.0
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn eight_bit_documents_are_promoted_when_exported_to_exr() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(8, 4, &mut framework);
+        add_partial_layer(
+            &mut document,
+            "Left",
+            [255, 188, 0, 255],
+            |x, _| x < 4,
+            &mut framework,
+        );
+        let pixels = render_pixels(&mut document, &mut renderer, &mut framework);
+
+        let path =
+            std::env::temp_dir().join(format!("mochi_document_export_{}.exr", std::process::id()));
+        document.export_exr(&path, &framework).unwrap();
+        let exported = read_exr(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(exported.len(), pixels.len());
+        for (pixel, exported) in pixels.iter().zip(exported.iter()) {
+            assert_eq!(&promote_texels(&[RgbaU8(*pixel)])[0], exported);
+        }
+        let [r, g, b, a] = exported[0];
+        assert_eq!((r, b, a), (1.0, 0.0, 1.0));
+        assert!((g - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn exported_selections_are_trimmed_to_the_selected_pixels() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(16, 8, &mut framework);
+        add_partial_layer(
+            &mut document,
+            "Red",
+            [255, 0, 0, 255],
+            |_, _| true,
+            &mut framework,
+        );
+        render_pixels(&mut document, &mut renderer, &mut framework);
+
+        let path = std::env::temp_dir().join(format!(
+            "mochi_document_export_selection_{}.png",
+            std::process::id()
+        ));
+        assert!(document.export_selection(&path, &framework).is_err());
+
+        // Covers the pixels from (4, 2) to (9, 5), but the top left 2x2 pixels
+        document.mutate_selection(|selection| {
+            selection.set(SelectionShape {
+                mode: SelectionAddition::Add,
+                shape: Shape::Rectangle(Box2d {
+                    center: point2(-1.0, 0.0),
+                    extents: vec2(3.0, 2.0),
+                }),
+            });
+            selection.extend(SelectionShape {
+                mode: SelectionAddition::Subtract,
+                shape: Shape::Rectangle(Box2d {
+                    center: point2(-3.0, 1.0),
+                    extents: vec2(1.0, 1.0),
+                }),
+            });
+        });
+        document.export_selection(&path, &framework).unwrap();
+        let exported = image::open(&path).unwrap().into_rgba8();
+        assert_eq!(exported.dimensions(), (6, 4));
+        for (x, y, pixel) in exported.enumerate_pixels() {
+            let expected = if x < 2 && y < 2 {
+                [0, 0, 0, 0]
+            } else {
+                [255, 0, 0, 255]
+            };
+            assert_eq!(pixel.0, expected, "({x}, {y})");
+        }
+
+        // The pixels around the rectangle span the whole document
+        document.mutate_selection(|selection| selection.invert());
+        document.export_selection(&path, &framework).unwrap();
+        let exported = image::open(&path).unwrap().into_rgba8();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(exported.dimensions(), (16, 8));
+        assert_eq!(exported.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(exported.get_pixel(4, 2).0, [255, 0, 0, 255]);
+        assert_eq!(exported.get_pixel(8, 4).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn layers_survive_a_tiff_round_trip() {
+        let mut framework = headless_framework();
+
+        let mut renderer = Renderer::new(&mut framework);
+        let mut document = test_document(8, 4, &mut framework);
+        let mut empty_layer = None;
+        document.for_each_layer(|layer, id| {
+            if layer.settings().name == "Layer 0" {
+                empty_layer = Some(*id);
+            }
+        });
+        document.delete_layer(empty_layer.unwrap(), &mut framework);
+        let is_top_left = |x: u32, y: u32| x < 4 && y < 2;
+        let top_left = add_partial_layer(
+            &mut document,
+            "Top left",
+            [255, 0, 0, 255],
+            is_top_left,
+            &mut framework,
+        );
+        document.mutate_layer(&top_left, |layer| {
+            layer.set_settings(LayerSettings {
+                label: Some(LayerColorLabel::Blue),
+                ..layer.settings().clone()
+            })
+        });
+
+        let path =
+            std::env::temp_dir().join(format!("mochi_document_layers_{}.tiff", std::process::id()));
+        document
+            .export_tiff(&path, &mut renderer, &mut framework)
+            .unwrap();
+        let imported = Document::import_tiff(&path, &mut framework).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(imported.document_size(), vec2(8, 4));
+        let (mut layers, mut labels) = (vec![], vec![]);
+        imported.for_each_layer(|layer, id| {
+            let texels = layer_texels(&imported, id, &framework);
+            layers.push((layer.settings().name.clone(), texels));
+            labels.push(layer.settings().label);
+        });
+        assert_eq!(layers.len(), 2);
+        let (top_name, top_texels) = &layers[0];
+        assert_eq!(top_name, "Top left");
+        for (i, texel) in top_texels.iter().enumerate() {
+            let expected = if is_top_left(i as u32 % 8, i as u32 / 8) {
+                RgbaU8([255, 0, 0, 255])
+            } else {
+                RgbaU8([0; 4])
+            };
+            assert_eq!(*texel, expected, "texel {i}");
+        }
+        let (bottom_name, bottom_texels) = &layers[1];
+        assert_eq!(bottom_name, "Background Layer");
+        assert!(bottom_texels.iter().all(|texel| *texel == RgbaU8([255; 4])));
+        assert_eq!(labels, vec![Some(LayerColorLabel::Blue), None]);
+    }
+}
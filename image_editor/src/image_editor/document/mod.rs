@@ -1,3 +1,12 @@
+pub mod alpha_mode;
+pub mod background;
 pub mod document;
+pub mod palette;
 
-pub use document::{Document, DocumentCreationInfo};
+pub use alpha_mode::AlphaMode;
+pub use background::DocumentBackground;
+pub use document::{
+    check_document_size, straighten_angle, DetachedLayer, Document, DocumentCreationInfo,
+    DocumentSizeError, ExtractSelectionError, StraightenAxis, ViewState, DEFAULT_DPI, MAX_DPI, MIN_DPI,
+};
+pub use palette::{Palette, MAX_PALETTE_COLORS};
@@ -0,0 +1,90 @@
+use framework::RgbaU8;
+
+// One of the 256 indices of the exported GIFs is left for the transparent pixels
+pub const MAX_PALETTE_COLORS: usize = 255;
+// Texels less opaque than this become transparent when snapped to a palette
+pub(crate) const MIN_OPAQUE_ALPHA: u8 = 128;
+
+// The index of the palette color nearest to color, measured in sRGB.
+// The color channels go from 0 to 255, but they can go past that range while dithering
+pub fn nearest_color_index(color: [f32; 3], palette: &[[u8; 3]]) -> usize {
+    let distance =
+        |entry: &[u8; 3]| -> f32 { (0..3).map(|c| (color[c] - entry[c] as f32).powi(2)).sum() };
+    (0..palette.len())
+        .min_by(|a, b| distance(&palette[*a]).total_cmp(&distance(&palette[*b])))
+        .expect("The palette must have at least one color")
+}
+
+// The colors an indexed document is painted with, see Document::set_palette_mode
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    // None when there are no colors, or more than MAX_PALETTE_COLORS
+    pub fn new(colors: Vec<[u8; 3]>) -> Option<Self> {
+        if colors.is_empty() || colors.len() > MAX_PALETTE_COLORS {
+            None
+        } else {
+            Some(Self { colors })
+        }
+    }
+
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+
+    pub fn nearest_index(&self, color: [u8; 3]) -> usize {
+        nearest_color_index(color.map(|c| c as f32), &self.colors)
+    }
+
+    pub fn nearest(&self, color: [u8; 3]) -> [u8; 3] {
+        self.colors[self.nearest_index(color)]
+    }
+
+    // The texels are either transparent or opaque with one of the palette colors,
+    // so that the soft edges of the stamps don't add new colors
+    pub fn snap_texel(&self, texel: RgbaU8) -> RgbaU8 {
+        let RgbaU8([r, g, b, a]) = texel;
+        if a < MIN_OPAQUE_ALPHA {
+            RgbaU8([0, 0, 0, 0])
+        } else {
+            let [r, g, b] = self.nearest([r, g, b]);
+            RgbaU8([r, g, b, 255])
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use framework::RgbaU8;
+
+    use super::{Palette, MAX_PALETTE_COLORS};
+
+    #[test]
+    fn colors_snap_to_the_nearest_entry() {
+        let palette =
+            Palette::new(vec![[0, 0, 0], [255, 0, 0], [0, 0, 255], [255, 255, 255]]).unwrap();
+        assert_eq!(palette.nearest_index([200, 30, 30]), 1);
+        assert_eq!(palette.nearest([20, 10, 150]), [0, 0, 255]);
+        assert_eq!(palette.nearest([180, 190, 200]), [255, 255, 255]);
+        assert_eq!(palette.nearest([255, 0, 0]), [255, 0, 0]);
+
+        assert_eq!(
+            palette.snap_texel(RgbaU8([200, 30, 30, 140])),
+            RgbaU8([255, 0, 0, 255])
+        );
+        assert_eq!(
+            palette.snap_texel(RgbaU8([200, 30, 30, 100])),
+            RgbaU8([0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn palettes_must_fit_in_a_gif() {
+        assert!(Palette::new(vec![]).is_none());
+        assert!(Palette::new(vec![[0, 0, 0]; MAX_PALETTE_COLORS]).is_some());
+        assert!(Palette::new(vec![[0, 0, 0]; MAX_PALETTE_COLORS + 1]).is_none());
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use framework::{srgb_to_linear, RgbaU8};
+
+// The EXR channels are linear, so the 8 bit sRGB colors are converted while promoting them
+pub(crate) fn promote_texels(texels: &[RgbaU8]) -> Vec<[f32; 4]> {
+    texels
+        .iter()
+        .map(|RgbaU8([r, g, b, a])| {
+            [
+                srgb_to_linear(*r) as f32,
+                srgb_to_linear(*g) as f32,
+                srgb_to_linear(*b) as f32,
+                *a as f32 / 255.0,
+            ]
+        })
+        .collect()
+}
+
+// The pixels are stored row by row, from the top left corner.
+// The values are written as they are, so the ones above 1.0 are preserved
+pub(crate) fn write_exr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixels: &[[f32; 4]],
+) -> Result<(), exr::error::Error> {
+    assert_eq!(pixels.len(), (width * height) as usize);
+    exr::prelude::write_rgba_file(path, width as usize, height as usize, |x, y| {
+        let [r, g, b, a] = pixels[y * width as usize + x];
+        (r, g, b, a)
+    })
+}
+
+#[cfg(test)]
+pub(crate) fn read_exr(path: &Path) -> Result<Vec<[f32; 4]>, exr::error::Error> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| {
+            (
+                resolution.width(),
+                vec![[0.0; 4]; resolution.width() * resolution.height()],
+            )
+        },
+        |(width, pixels): &mut (usize, Vec<[f32; 4]>),
+         position,
+         (r, g, b, a): (f32, f32, f32, f32)| {
+            pixels[position.y() * *width + position.x()] = [r, g, b, a];
+        },
+    )?;
+    Ok(image.layer_data.channel_data.pixels.1)
+}
+
+#[cfg(test)]
+mod test {
+    use framework::RgbaU8;
+
+    use super::{promote_texels, read_exr, write_exr};
+
+    #[test]
+    fn values_above_one_survive_the_export() {
+        let path =
+            std::env::temp_dir().join(format!("mochi_exr_export_hdr_{}.exr", std::process::id()));
+        let pixels = [
+            [4.0, 0.5, 0.25, 1.0],
+            [0.0, 0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0, 1.0],
+            [0.1, 12.5, 0.0, 0.5],
+        ];
+        write_exr(&path, 2, 2, &pixels).unwrap();
+        let read = read_exr(&path).unwrap();
+        assert_eq!(read, pixels);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn promoted_texels_are_linear() {
+        let promoted = promote_texels(&[RgbaU8([255, 0, 188, 128])]);
+        let [r, g, b, a] = promoted[0];
+        assert_eq!((r, g), (1.0, 0.0));
+        assert!((b - 0.5).abs() < 0.01);
+        assert!((a - 128.0 / 255.0).abs() < 0.001);
+    }
+}
@@ -0,0 +1,118 @@
+use framework::RgbaU8;
+
+// Fills the masked texels by spreading the colors around them inward: on each iteration
+// a masked texel becomes the average of its known neighbours, weighted by their alpha.
+// The texels outside of the mask are known from the start, the masked ones once they're reached,
+// those that are never reached keep their color. The texels are stored row by row
+pub fn fill_from_edges(
+    texels: &[RgbaU8],
+    width: u32,
+    mask: &[bool],
+    iterations: u32,
+) -> Vec<RgbaU8> {
+    let width = width as usize;
+    let height = texels.len() / width;
+    let mut colors: Vec<[f32; 4]> = texels
+        .iter()
+        .map(|RgbaU8(texel)| texel.map(|c| c as f32 / 255.0))
+        .collect();
+    let mut known: Vec<bool> = mask.iter().map(|masked| !masked).collect();
+    let masked: Vec<usize> = (0..texels.len()).filter(|i| mask[*i]).collect();
+
+    for _ in 0..iterations {
+        let previous_colors = colors.clone();
+        let previous_known = known.clone();
+        for &i in &masked {
+            let (x, y) = (i % width, i / width);
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            let mut premultiplied = [0.0; 3];
+            let mut alpha = 0.0;
+            let mut count = 0;
+            for n in neighbours.into_iter().flatten() {
+                if previous_known[n] {
+                    let [r, g, b, a] = previous_colors[n];
+                    premultiplied = [
+                        premultiplied[0] + r * a,
+                        premultiplied[1] + g * a,
+                        premultiplied[2] + b * a,
+                    ];
+                    alpha += a;
+                    count += 1;
+                }
+            }
+            if count > 0 {
+                let [r, g, b] = if alpha > 0.0 {
+                    premultiplied.map(|c| c / alpha)
+                } else {
+                    [0.0; 3]
+                };
+                colors[i] = [r, g, b, alpha / count as f32];
+                known[i] = true;
+            }
+        }
+    }
+
+    colors
+        .iter()
+        .zip(texels)
+        .zip(&known)
+        .map(|((color, texel), known)| {
+            if *known {
+                RgbaU8(color.map(|c| (c * 255.0).round() as u8))
+            } else {
+                *texel
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use framework::RgbaU8;
+
+    use super::fill_from_edges;
+
+    const RED: RgbaU8 = RgbaU8([255, 0, 0, 255]);
+    const BLUE: RgbaU8 = RgbaU8([0, 0, 255, 255]);
+    const TRANSPARENT: RgbaU8 = RgbaU8([0, 0, 0, 0]);
+
+    #[test]
+    fn holes_surrounded_by_a_color_are_filled_with_it() {
+        // A 5x5 red square with a transparent 3x3 hole in the middle
+        let mask: Vec<bool> = (0..25)
+            .map(|i| (1..4).contains(&(i % 5)) && (1..4).contains(&(i / 5)))
+            .collect();
+        let texels: Vec<RgbaU8> = mask
+            .iter()
+            .map(|masked| if *masked { TRANSPARENT } else { RED })
+            .collect();
+
+        // The center is only reached on the second iteration
+        let filled = fill_from_edges(&texels, 5, &mask, 1);
+        assert_eq!(filled[12], TRANSPARENT);
+        assert_eq!(filled[7], RED);
+
+        let filled = fill_from_edges(&texels, 5, &mask, 2);
+        assert!(filled.iter().all(|texel| *texel == RED));
+    }
+
+    #[test]
+    fn the_fill_blends_the_colors_of_the_edges() {
+        let texels = [RED, TRANSPARENT, TRANSPARENT, TRANSPARENT, BLUE];
+        let mask = [false, true, true, true, false];
+        let filled = fill_from_edges(&texels, 5, &mask, 20);
+
+        assert_eq!(filled[0], RED);
+        assert_eq!(filled[4], BLUE);
+        // The texels close to an edge take more of its color
+        let RgbaU8([r1, _, b1, a1]) = filled[1];
+        let RgbaU8([r3, _, b3, a3]) = filled[3];
+        assert!(r1 > b1 && b3 > r3);
+        assert_eq!((a1, a3), (255, 255));
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use framework::{RgbaTexture2D, RgbaU8, TexelConversionError, Texture};
+
+mod edge_fill;
+mod noise;
+mod unsharp_mask;
+pub use edge_fill::*;
+pub use noise::*;
+pub use unsharp_mask::*;
+
+// Large textures are processed in square tiles, progress is reported once per tile
+const TILE_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterError {
+    Cancelled,
+    // The layer couldn't be read back from the GPU
+    Readback(TexelConversionError),
+}
+
+impl Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterError::Cancelled => f.write_str("The filter operation was cancelled"),
+            FilterError::Readback(e) => write!(f, "Failed to read the layer: {e}"),
+        }
+    }
+}
+impl std::error::Error for FilterError {}
+
+impl From<TexelConversionError> for FilterError {
+    fn from(e: TexelConversionError) -> Self {
+        FilterError::Readback(e)
+    }
+}
+
+struct TileProgress<'a> {
+    total_tiles: u32,
+    done_tiles: u32,
+    progress: Option<&'a dyn Fn(f32)>,
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl<'a> TileProgress<'a> {
+    fn new(
+        total_tiles: u32,
+        progress: Option<&'a dyn Fn(f32)>,
+        cancel: Option<&'a AtomicBool>,
+    ) -> Self {
+        Self {
+            total_tiles,
+            done_tiles: 0,
+            progress,
+            cancel,
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<(), FilterError> {
+        match self.cancel {
+            Some(cancel) if cancel.load(Ordering::Relaxed) => Err(FilterError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    fn tile_done(&mut self) -> Result<(), FilterError> {
+        self.done_tiles += 1;
+        if let Some(progress) = self.progress {
+            progress(self.done_tiles as f32 / self.total_tiles as f32);
+        }
+        self.check_cancelled()
+    }
+}
+
+// Calls f once for each tile, with (first x, first y, last x + 1, last y + 1)
+fn for_each_tile<F: FnMut(u32, u32, u32, u32) -> Result<(), FilterError>>(
+    width: u32,
+    height: u32,
+    mut f: F,
+) -> Result<(), FilterError> {
+    for tile_y in (0..height).step_by(TILE_SIZE as usize) {
+        for tile_x in (0..width).step_by(TILE_SIZE as usize) {
+            let end_x = (tile_x + TILE_SIZE).min(width);
+            let end_y = (tile_y + TILE_SIZE).min(height);
+            f(tile_x, tile_y, end_x, end_y)?;
+        }
+    }
+    Ok(())
+}
+
+fn tile_count(width: u32, height: u32) -> u32 {
+    let tiles_x = (width + TILE_SIZE - 1) / TILE_SIZE;
+    let tiles_y = (height + TILE_SIZE - 1) / TILE_SIZE;
+    tiles_x * tiles_y
+}
+
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    if sigma <= 0.0 {
+        return vec![1.0];
+    }
+    let radius = (sigma * 3.0).ceil() as i32;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|x| (-(x * x) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = weights.iter().sum();
+    weights.into_iter().map(|w| w / sum).collect()
+}
+
+// Runs a 1D convolution over the premultiplied pixels, either horizontally or vertically
+fn convolve_pass(
+    source: &[[f32; 4]],
+    width: u32,
+    height: u32,
+    kernel: &[f32],
+    horizontal: bool,
+    progress: &mut TileProgress,
+) -> Result<Vec<[f32; 4]>, FilterError> {
+    let radius = (kernel.len() / 2) as i64;
+    let mut destination = vec![[0.0; 4]; source.len()];
+    for_each_tile(width, height, |start_x, start_y, end_x, end_y| {
+        for y in start_y..end_y {
+            for x in start_x..end_x {
+                let mut accum = [0.0; 4];
+                for (i, weight) in kernel.iter().enumerate() {
+                    let offset = i as i64 - radius;
+                    let (sx, sy) = if horizontal {
+                        ((x as i64 + offset).clamp(0, width as i64 - 1), y as i64)
+                    } else {
+                        (x as i64, (y as i64 + offset).clamp(0, height as i64 - 1))
+                    };
+                    let texel = source[(sy * width as i64 + sx) as usize];
+                    for c in 0..4 {
+                        accum[c] += texel[c] * weight;
+                    }
+                }
+                destination[(y * width + x) as usize] = accum;
+            }
+        }
+        progress.tile_done()
+    })?;
+    Ok(destination)
+}
+
+// Blurs the texture on the CPU, reporting the progress (0..1) after every tile:
+// when cancel is set, the filter stops at the next tile and returns FilterError::Cancelled
+pub fn apply_gaussian_blur(
+    texture: &RgbaTexture2D,
+    sigma: f32,
+    progress: Option<&dyn Fn(f32)>,
+    cancel: Option<&AtomicBool>,
+) -> Result<RgbaTexture2D, FilterError> {
+    let (width, height) = (texture.width(), texture.height());
+    let mut tile_progress = TileProgress::new(tile_count(width, height) * 2, progress, cancel);
+    tile_progress.check_cancelled()?;
+
+    let texels = match texture.data() {
+        Some(texels) => texels,
+        None => return Ok(RgbaTexture2D::empty((width, height))),
+    };
+
+    // Blurring premultiplied colors avoids dark halos around transparent pixels
+    let premultiplied: Vec<[f32; 4]> = texels
+        .iter()
+        .map(|RgbaU8([r, g, b, a])| {
+            let alpha = *a as f32 / 255.0;
+            [
+                *r as f32 * alpha,
+                *g as f32 * alpha,
+                *b as f32 * alpha,
+                *a as f32,
+            ]
+        })
+        .collect();
+
+    let kernel = gaussian_kernel(sigma);
+    let horizontal = convolve_pass(
+        &premultiplied,
+        width,
+        height,
+        &kernel,
+        true,
+        &mut tile_progress,
+    )?;
+    let blurred = convolve_pass(
+        &horizontal,
+        width,
+        height,
+        &kernel,
+        false,
+        &mut tile_progress,
+    )?;
+
+    let texels = blurred
+        .into_iter()
+        .map(|[r, g, b, a]| {
+            let alpha = a / 255.0;
+            let unpremultiply = |c: f32| {
+                if alpha > 0.0 {
+                    (c / alpha).round().clamp(0.0, 255.0) as u8
+                } else {
+                    0
+                }
+            };
+            RgbaU8([
+                unpremultiply(r),
+                unpremultiply(g),
+                unpremultiply(b),
+                a.round().clamp(0.0, 255.0) as u8,
+            ])
+        })
+        .collect();
+    Ok(RgbaTexture2D::from_texels(texels, (width, height)).unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::RefCell,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use framework::{RgbaTexture2D, RgbaU8, TexelConversionError, Texture};
+
+    use super::{apply_gaussian_blur, FilterError};
+
+    fn test_texture() -> RgbaTexture2D {
+        // 3x2 tiles, the last column and row of tiles are partial
+        let (width, height) = (600, 300);
+        let texels = (0..width * height)
+            .map(|i| RgbaU8([(i % 255) as u8, (i % 7) as u8 * 30, 128, 255]))
+            .collect();
+        RgbaTexture2D::from_texels(texels, (width, height)).unwrap()
+    }
+
+    #[test]
+    fn progress_is_monotonic_up_to_one() {
+        let reported = RefCell::new(vec![]);
+        let progress = |p: f32| reported.borrow_mut().push(p);
+        apply_gaussian_blur(&test_texture(), 1.5, Some(&progress), None).unwrap();
+
+        let reported = reported.into_inner();
+        assert_eq!(reported.len(), 12);
+        assert!(reported.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(*reported.last().unwrap(), 1.0);
+    }
+
+    #[test]
+    fn cancel_stops_the_filter() {
+        let cancel = AtomicBool::new(false);
+        let calls = RefCell::new(0);
+        let progress = |_: f32| {
+            *calls.borrow_mut() += 1;
+            cancel.store(true, Ordering::Relaxed);
+        };
+        let result = apply_gaussian_blur(&test_texture(), 1.5, Some(&progress), Some(&cancel));
+        assert_eq!(result.err(), Some(FilterError::Cancelled));
+        assert_eq!(*calls.borrow(), 1);
+    }
+
+    #[test]
+    fn blurring_a_flat_color_does_nothing() {
+        let texture =
+            RgbaTexture2D::from_repeated_texel(RgbaU8([10, 20, 30, 255]), (40, 40)).unwrap();
+        let blurred = apply_gaussian_blur(&texture, 3.0, None, None).unwrap();
+        assert!(blurred
+            .data()
+            .unwrap()
+            .iter()
+            .all(|t| t.0 == [10, 20, 30, 255]));
+    }
+}
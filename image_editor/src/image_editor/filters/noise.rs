@@ -0,0 +1,78 @@
+use cgmath::Vector2;
+use strum_macros::EnumIter;
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Hash, Debug, EnumIter)]
+pub enum NoiseKind {
+    #[default]
+    White = 0,
+    Value = 1,
+    Perlin = 2,
+}
+
+impl std::fmt::Display for NoiseKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pretty_text = match self {
+            NoiseKind::White => "White",
+            NoiseKind::Value => "Value",
+            NoiseKind::Perlin => "Perlin",
+        };
+        f.write_str(pretty_text)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NoiseParameters {
+    // Size in pixels of a noise cell, ignored by white noise
+    pub scale: f32,
+    // Only used by Perlin noise
+    pub octaves: u32,
+    pub grayscale: bool,
+}
+
+impl Default for NoiseParameters {
+    fn default() -> Self {
+        Self {
+            scale: 32.0,
+            octaves: 4,
+            grayscale: true,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct NoiseSettingsUniform {
+    kind: i32,
+    seed: u32,
+    scale: f32,
+    octaves: u32,
+    grayscale: u32,
+    use_selection: u32,
+    inverted_selection: u32,
+    padding: u32,
+    document_size: [f32; 2],
+    padding_2: [f32; 2],
+}
+
+impl NoiseSettingsUniform {
+    pub(crate) fn new(
+        kind: NoiseKind,
+        seed: u32,
+        parameters: NoiseParameters,
+        selection: Option<bool>,
+        document_size: Vector2<u32>,
+    ) -> Self {
+        Self {
+            kind: kind as i32,
+            seed,
+            scale: parameters.scale.max(1.0),
+            octaves: parameters.octaves.clamp(1, 16),
+            grayscale: parameters.grayscale as u32,
+            use_selection: selection.is_some() as u32,
+            inverted_selection: selection.unwrap_or(false) as u32,
+            padding: 0,
+            document_size: [document_size.x as f32, document_size.y as f32],
+            padding_2: [0.0; 2],
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//@include :common_definitions
+//@include :2d_definitions
+
+struct NoiseSettings {
+    kind: i32,
+    seed: u32,
+    scale: f32,
+    octaves: u32,
+    grayscale: u32,
+    use_selection: u32,
+    inverted_selection: u32,
+    padding: u32,
+    document_size: vec2<f32>,
+    padding_2: vec2<f32>,
+}
+
+@group(2) @binding(0) var diffuse: texture_2d<f32>;
+@group(2) @binding(1) var s_diffuse: sampler;
+
+@group(3) @binding(0) var stencil: texture_2d<u32>;
+
+@group(4) @binding(0) var<uniform> settings: NoiseSettings;
+
+// PCG hash, see https://www.jcgt.org/published/0009/03/02/
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn hash(cell: vec2<i32>, channel: u32) -> f32 {
+    let h = pcg(bitcast<u32>(cell.x) ^ pcg(bitcast<u32>(cell.y) ^ pcg(settings.seed + channel)));
+    return f32(h) / 4294967295.0;
+}
+
+fn value_noise(p: vec2<f32>, channel: u32) -> f32 {
+    let cell = vec2<i32>(floor(p));
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    let a = hash(cell, channel);
+    let b = hash(cell + vec2<i32>(1, 0), channel);
+    let c = hash(cell + vec2<i32>(0, 1), channel);
+    let d = hash(cell + vec2<i32>(1, 1), channel);
+    return mix(mix(a, b, u.x), mix(c, d, u.x), u.y);
+}
+
+fn gradient(cell: vec2<i32>, channel: u32) -> vec2<f32> {
+    let angle = hash(cell, channel) * 6.2831853;
+    return vec2<f32>(cos(angle), sin(angle));
+}
+
+fn perlin_noise(p: vec2<f32>, channel: u32) -> f32 {
+    let cell = vec2<i32>(floor(p));
+    let f = fract(p);
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let g00 = dot(gradient(cell, channel), f);
+    let g10 = dot(gradient(cell + vec2<i32>(1, 0), channel), f - vec2<f32>(1.0, 0.0));
+    let g01 = dot(gradient(cell + vec2<i32>(0, 1), channel), f - vec2<f32>(0.0, 1.0));
+    let g11 = dot(gradient(cell + vec2<i32>(1, 1), channel), f - vec2<f32>(1.0, 1.0));
+    let n = mix(mix(g00, g10, u.x), mix(g01, g11, u.x), u.y);
+    return clamp(0.5 + n * 0.7071, 0.0, 1.0);
+}
+
+fn fractal_perlin_noise(p: vec2<f32>, channel: u32) -> f32 {
+    var amplitude = 0.5;
+    var frequency = 1.0;
+    var total = 0.0;
+    var normalization = 0.0;
+    for (var i = 0u; i < max(settings.octaves, 1u); i = i + 1u) {
+        // Each octave gets its own gradients
+        total = total + amplitude * perlin_noise(p * frequency, channel + i * 3u);
+        normalization = normalization + amplitude;
+        amplitude = amplitude * 0.5;
+        frequency = frequency * 2.0;
+    }
+    return total / normalization;
+}
+
+fn noise(p: vec2<f32>, channel: u32) -> f32 {
+    if (settings.kind == 0) {
+        return hash(vec2<i32>(floor(p)), channel);
+    } else if (settings.kind == 1) {
+        return value_noise(p / settings.scale, channel);
+    }
+    return fractal_perlin_noise(p / settings.scale, channel);
+}
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    if (settings.use_selection != 0u) {
+        let stencil_dimensions = textureDimensions(stencil);
+        let uv = vec2<f32>(in.tex_uv.x, 1.0 - in.tex_uv.y);
+        let texel = vec2<i32>(uv * vec2<f32>(stencil_dimensions));
+        let selected = textureLoad(stencil, texel, 0).r != 0u;
+        if (selected == (settings.inverted_selection != 0u)) {
+            discard;
+        }
+    }
+
+    // Noise is evaluated in document space, so that it's seamless across chunks
+    let p = in.tex_uv * settings.document_size;
+    if (settings.grayscale != 0u) {
+        let value = noise(p, 0u);
+        return vec4<f32>(value, value, value, 1.0);
+    }
+    return vec4<f32>(noise(p, 0u), noise(p, 1u), noise(p, 2u), 1.0);
+}
@@ -0,0 +1,140 @@
+use cgmath::{Matrix4, Point2, Vector2};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct UnsharpMaskSettingsUniform {
+    layer_transform: [[f32; 4]; 4],
+    source_center: [f32; 2],
+    source_size: [f32; 2],
+    document_size: [f32; 2],
+    amount: f32,
+    radius: f32,
+    threshold: f32,
+    use_selection: u32,
+    inverted_selection: u32,
+    padding: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct UnsharpMaskParameters {
+    // How much of the difference between the image and its blurred version is added back
+    pub amount: f32,
+    // Blur radius in pixels
+    pub radius: f32,
+    // Minimum difference (0..255) between a pixel and its surroundings before it gets sharpened
+    pub threshold: u8,
+}
+
+impl Default for UnsharpMaskParameters {
+    fn default() -> Self {
+        Self {
+            amount: 1.0,
+            radius: 2.0,
+            threshold: 0,
+        }
+    }
+}
+
+// Larger radiuses get too expensive, the blur is evaluated in a single pass
+pub const MAX_UNSHARP_MASK_RADIUS: f32 = 16.0;
+
+impl UnsharpMaskSettingsUniform {
+    pub(crate) fn new(
+        parameters: UnsharpMaskParameters,
+        layer_transform: Matrix4<f32>,
+        source_center: Point2<f32>,
+        source_size: Vector2<f32>,
+        selection: Option<bool>,
+        document_size: Vector2<u32>,
+    ) -> Self {
+        Self {
+            layer_transform: layer_transform.into(),
+            source_center: source_center.into(),
+            source_size: source_size.into(),
+            document_size: [document_size.x as f32, document_size.y as f32],
+            amount: parameters.amount.max(0.0),
+            radius: parameters
+                .radius
+                .clamp(1.0, MAX_UNSHARP_MASK_RADIUS)
+                .round(),
+            threshold: parameters.threshold as f32 / 255.0,
+            use_selection: selection.is_some() as u32,
+            inverted_selection: selection.unwrap_or(false) as u32,
+            padding: 0,
+        }
+    }
+}
+
+// CPU version of the unsharp mask formula used by unsharp_mask.wgsl, colors are in the 0..1 range
+pub fn unsharp_mask_texel(
+    original: [f32; 4],
+    blurred: [f32; 4],
+    amount: f32,
+    threshold: f32,
+) -> [f32; 4] {
+    let difference = [
+        original[0] - blurred[0],
+        original[1] - blurred[1],
+        original[2] - blurred[2],
+    ];
+    let contrast = difference.iter().fold(0.0f32, |c, d| c.max(d.abs()));
+    if contrast < threshold {
+        return original;
+    }
+    let sharpen = |c: usize| (original[c] + difference[c] * amount).clamp(0.0, 1.0);
+    [sharpen(0), sharpen(1), sharpen(2), original[3]]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::gaussian_kernel, unsharp_mask_texel};
+
+    fn gray(value: f32) -> [f32; 4] {
+        [value, value, value, 1.0]
+    }
+
+    // Sharpens a single row of pixels, clamping at the borders
+    fn sharpen_row(row: &[f32], amount: f32, threshold: f32) -> Vec<f32> {
+        let kernel = gaussian_kernel(1.0);
+        let radius = (kernel.len() / 2) as i64;
+        (0..row.len() as i64)
+            .map(|i| {
+                let blurred = kernel.iter().enumerate().fold(0.0, |acc, (k, w)| {
+                    let index = (i + k as i64 - radius).clamp(0, row.len() as i64 - 1);
+                    acc + row[index as usize] * w
+                });
+                unsharp_mask_texel(gray(row[i as usize]), gray(blurred), amount, threshold)[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edges_are_enhanced() {
+        let row = [0.2, 0.2, 0.2, 0.2, 0.2, 0.8, 0.8, 0.8, 0.8, 0.8];
+        let sharpened = sharpen_row(&row, 1.0, 0.0);
+
+        // The dark side gets darker and the bright side brighter
+        assert!(sharpened[4] < 0.2);
+        assert!(sharpened[5] > 0.8);
+        // Flat areas are left untouched
+        assert!((sharpened[0] - 0.2).abs() < 1e-5);
+        assert!((sharpened[9] - 0.8).abs() < 1e-5);
+    }
+
+    #[test]
+    fn threshold_skips_low_contrast_areas() {
+        let row = [0.5, 0.5, 0.5, 0.5, 0.52, 0.52, 0.52, 0.52];
+        let sharpened = sharpen_row(&row, 2.0, 5.0 / 255.0);
+        assert_eq!(sharpened, row);
+
+        let row = [0.2, 0.2, 0.2, 0.2, 0.8, 0.8, 0.8, 0.8];
+        let sharpened = sharpen_row(&row, 2.0, 5.0 / 255.0);
+        assert_ne!(sharpened, row);
+    }
+
+    #[test]
+    fn alpha_is_preserved() {
+        let sharpened = unsharp_mask_texel([0.9, 0.5, 0.1, 0.3], [0.1, 0.5, 0.9, 1.0], 1.0, 0.0);
+        assert_eq!(sharpened, [1.0, 0.5, 0.0, 0.3]);
+    }
+}
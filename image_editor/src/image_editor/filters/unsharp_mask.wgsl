@@ -0,0 +1,82 @@
+//@include :common_definitions
+//@include :2d_definitions
+
+struct UnsharpMaskSettings {
+    layer_transform: mat4x4<f32>,
+    source_center: vec2<f32>,
+    source_size: vec2<f32>,
+    document_size: vec2<f32>,
+    amount: f32,
+    radius: f32,
+    threshold: f32,
+    use_selection: u32,
+    inverted_selection: u32,
+    padding: u32,
+}
+
+@group(2) @binding(0) var source: texture_2d<f32>;
+@group(2) @binding(1) var s_source: sampler;
+
+@group(3) @binding(0) var stencil: texture_2d<u32>;
+
+@group(4) @binding(0) var<uniform> settings: UnsharpMaskSettings;
+
+fn load_source(texel: vec2<i32>) -> vec4<f32> {
+    let dimensions = vec2<i32>(textureDimensions(source));
+    let clamped = clamp(texel, vec2<i32>(0, 0), dimensions - vec2<i32>(1, 1));
+    return textureLoad(source, clamped, 0);
+}
+
+fn is_selected(uv: vec2<f32>) -> bool {
+    // uv -> layer space -> document space -> stencil texel
+    let layer_position = settings.source_center + (uv - 0.5) * settings.source_size * vec2<f32>(1.0, -1.0);
+    let document_position = settings.layer_transform * vec4<f32>(layer_position.x, layer_position.y, 0.0, 1.0);
+    let x = document_position.x + settings.document_size.x * 0.5;
+    let y = settings.document_size.y * 0.5 - document_position.y;
+    if (x < 0.0 || y < 0.0 || x >= settings.document_size.x || y >= settings.document_size.y) {
+        return false;
+    }
+    return textureLoad(stencil, vec2<i32>(i32(x), i32(y)), 0).r != 0u;
+}
+
+fn gaussian_blur(center: vec2<i32>) -> vec4<f32> {
+    let radius = i32(settings.radius);
+    let sigma = max(settings.radius * 0.5, 0.5);
+    var accum = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    var total_weight = 0.0;
+    for (var y = -radius; y <= radius; y = y + 1) {
+        for (var x = -radius; x <= radius; x = x + 1) {
+            let weight = exp(-f32(x * x + y * y) / (2.0 * sigma * sigma));
+            let texel = load_source(center + vec2<i32>(x, y));
+            // Blur premultiplied colors, so that transparent pixels don't darken the edges
+            accum = accum + vec4<f32>(texel.rgb * texel.a, texel.a) * weight;
+            total_weight = total_weight + weight;
+        }
+    }
+    let blurred = accum / total_weight;
+    if (blurred.a > 0.0) {
+        return vec4<f32>(blurred.rgb / blurred.a, blurred.a);
+    }
+    return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+}
+
+// Keep in sync with unsharp_mask_texel in unsharp_mask.rs
+fn unsharp_mask(original: vec4<f32>, blurred: vec4<f32>) -> vec4<f32> {
+    let difference = original.rgb - blurred.rgb;
+    let contrast = max(abs(difference.r), max(abs(difference.g), abs(difference.b)));
+    if (contrast < settings.threshold) {
+        return original;
+    }
+    let sharpened = clamp(original.rgb + difference * settings.amount, vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 1.0));
+    return vec4<f32>(sharpened, original.a);
+}
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    if (settings.use_selection != 0u && is_selected(in.tex_uv) == (settings.inverted_selection != 0u)) {
+        discard;
+    }
+    let dimensions = vec2<f32>(textureDimensions(source));
+    let texel = vec2<i32>(in.tex_uv * dimensions);
+    return unsharp_mask(load_source(texel), gaussian_blur(texel));
+}
@@ -0,0 +1,296 @@
+use std::{borrow::Cow, collections::HashSet, error::Error, fs::File, path::Path};
+
+use color_quant::NeuQuant;
+use framework::RgbaU8;
+use image::DynamicImage;
+
+use crate::document::palette::{
+    nearest_color_index, Palette, MAX_PALETTE_COLORS, MIN_OPAQUE_ALPHA,
+};
+
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// How the quantization error is hidden when the colors are reduced to the GIF palette
+#[derive(
+    Clone, Copy, Debug, Default, strum_macros::EnumIter, strum_macros::Display, Eq, PartialEq,
+)]
+pub enum DitherKind {
+    #[default]
+    None,
+    // The error of each pixel is spread on the ones right and below it
+    FloydSteinberg,
+    // Each pixel is offset by a 4x4 Bayer matrix before being quantized
+    Ordered,
+}
+
+// Maps each texel to the index of its palette color, the texels are stored row by row
+// from the top left corner and their alpha is ignored
+pub(crate) fn dither(
+    texels: &[RgbaU8],
+    width: u32,
+    palette: &[[u8; 3]],
+    kind: DitherKind,
+) -> Vec<usize> {
+    let width = width as usize;
+    let color = |i: usize| {
+        let RgbaU8([r, g, b, _]) = texels[i];
+        [r as f32, g as f32, b as f32]
+    };
+    match kind {
+        DitherKind::None => (0..texels.len())
+            .map(|i| nearest_color_index(color(i), palette))
+            .collect(),
+        DitherKind::FloydSteinberg => {
+            let mut errors = vec![[0.0f32; 3]; texels.len()];
+            let mut indices = Vec::with_capacity(texels.len());
+            for i in 0..texels.len() {
+                let (x, y) = (i % width, i / width);
+                let mut value = color(i);
+                for c in 0..3 {
+                    value[c] = (value[c] + errors[i][c]).clamp(0.0, 255.0);
+                }
+                let index = nearest_color_index(value, palette);
+                indices.push(index);
+
+                let mut spread = |x: usize, y: usize, weight: f32| {
+                    let neighbour = y * width + x;
+                    if x < width && neighbour < texels.len() {
+                        for c in 0..3 {
+                            errors[neighbour][c] += (value[c] - palette[index][c] as f32) * weight;
+                        }
+                    }
+                };
+                spread(x + 1, y, 7.0 / 16.0);
+                if x > 0 {
+                    spread(x - 1, y + 1, 3.0 / 16.0);
+                }
+                spread(x, y + 1, 5.0 / 16.0);
+                spread(x + 1, y + 1, 1.0 / 16.0);
+            }
+            indices
+        }
+        DitherKind::Ordered => {
+            // Roughly the distance between two palette colors along a channel
+            let step = 255.0 / (palette.len() as f32).cbrt().max(1.0);
+            (0..texels.len())
+                .map(|i| {
+                    let (x, y) = (i % width, i / width);
+                    let threshold = (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5;
+                    let value = color(i).map(|c| (c + threshold * step).clamp(0.0, 255.0));
+                    nearest_color_index(value, palette)
+                })
+                .collect()
+        }
+    }
+}
+
+// The colors of the opaque texels are used as they are when they fit in the palette,
+// otherwise they're reduced with NeuQuant
+fn build_palette(texels: &[RgbaU8]) -> Vec<[u8; 3]> {
+    let opaque = texels
+        .iter()
+        .filter(|RgbaU8([_, _, _, a])| *a >= MIN_OPAQUE_ALPHA);
+    let unique: HashSet<[u8; 3]> = opaque
+        .clone()
+        .map(|RgbaU8([r, g, b, _])| [*r, *g, *b])
+        .collect();
+    if unique.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+    if unique.len() <= MAX_PALETTE_COLORS {
+        return unique.into_iter().collect();
+    }
+    let bytes: Vec<u8> = opaque
+        .flat_map(|RgbaU8([r, g, b, _])| [*r, *g, *b, 255])
+        .collect();
+    NeuQuant::new(10, MAX_PALETTE_COLORS, &bytes)
+        .color_map_rgb()
+        .chunks_exact(3)
+        .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+        .collect()
+}
+
+// The options of the palette-quantizing export
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct GifOptions {
+    pub dither: DitherKind,
+    // Set for the indexed documents: the pixels keep their palette index, without dithering
+    pub palette: Option<Palette>,
+}
+
+// The last palette index is used by the transparent pixels
+pub(crate) fn write_gif(
+    path: &Path,
+    image: &DynamicImage,
+    options: &GifOptions,
+) -> Result<(), Box<dyn Error>> {
+    let image = image.to_rgba8();
+    let (width, height) = image.dimensions();
+    let (gif_width, gif_height) = match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err("GIF images can't be bigger than 65535x65535 pixels".into()),
+    };
+    let texels: Vec<RgbaU8> = image.pixels().map(|pixel| RgbaU8(pixel.0)).collect();
+    let (palette, dither_kind) = match &options.palette {
+        Some(palette) => (palette.colors().to_vec(), DitherKind::None),
+        None => (build_palette(&texels), options.dither),
+    };
+    let transparent_index = palette.len() as u8;
+    let indices: Vec<u8> = dither(&texels, width, &palette, dither_kind)
+        .into_iter()
+        .zip(texels.iter())
+        .map(|(index, RgbaU8([_, _, _, a]))| {
+            if *a < MIN_OPAQUE_ALPHA {
+                transparent_index
+            } else {
+                index as u8
+            }
+        })
+        .collect();
+    let mut gif_palette = palette.concat();
+    gif_palette.extend_from_slice(&[0, 0, 0]);
+
+    let mut encoder = gif::Encoder::new(File::create(path)?, gif_width, gif_height, &gif_palette)?;
+    encoder.write_frame(&gif::Frame {
+        width: gif_width,
+        height: gif_height,
+        buffer: Cow::Owned(indices),
+        transparent: Some(transparent_index),
+        ..Default::default()
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use framework::RgbaU8;
+
+    use crate::document::Palette;
+
+    use super::{dither, write_gif, DitherKind, GifOptions};
+
+    const BLACK_AND_WHITE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+
+    fn gray(value: u8, count: usize) -> Vec<RgbaU8> {
+        vec![RgbaU8([value, value, value, 255]); count]
+    }
+
+    #[test]
+    fn without_dithering_the_nearest_color_is_picked() {
+        let texels = [
+            RgbaU8([10, 20, 30, 255]),
+            RgbaU8([200, 180, 255, 255]),
+            RgbaU8([127, 127, 127, 0]),
+        ];
+        assert_eq!(
+            dither(&texels, 3, &BLACK_AND_WHITE, DitherKind::None),
+            vec![0, 1, 0]
+        );
+    }
+
+    #[test]
+    fn floyd_steinberg_spreads_the_error_on_the_next_pixels() {
+        // 128 becomes white, leaving an error of -127:
+        // (1, 0) = 128 - 127 * 7/16 = 72.44 -> black, error 72.44
+        // (0, 1) = 128 - 127 * 5/16 + 72.44 * 3/16 = 101.89 -> black, error 101.89
+        // (1, 1) = 128 - 127 * 1/16 + 72.44 * 5/16 + 101.89 * 7/16 = 187.28 -> white
+        assert_eq!(
+            dither(
+                &gray(128, 4),
+                2,
+                &BLACK_AND_WHITE,
+                DitherKind::FloydSteinberg
+            ),
+            vec![1, 0, 0, 1]
+        );
+        // A dark gray row only accumulates error to the right, never reaching white
+        assert_eq!(
+            dither(
+                &gray(64, 4),
+                4,
+                &BLACK_AND_WHITE,
+                DitherKind::FloydSteinberg
+            ),
+            vec![0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn ordered_dithering_follows_the_bayer_matrix() {
+        // Half of the thresholds are above the middle gray, in a checker pattern
+        let indices = dither(&gray(128, 16), 4, &BLACK_AND_WHITE, DitherKind::Ordered);
+        assert_eq!(
+            indices,
+            vec![0, 1, 0, 1, 1, 0, 1, 0, 0, 1, 0, 1, 1, 0, 1, 0]
+        );
+        // The colors of the palette aren't changed
+        let indices = dither(&gray(255, 16), 4, &BLACK_AND_WHITE, DitherKind::Ordered);
+        assert!(indices.iter().all(|i| *i == 1));
+    }
+
+    #[test]
+    fn gifs_keep_few_colors_and_transparency() {
+        let path =
+            std::env::temp_dir().join(format!("mochi_gif_export_{}.gif", std::process::id()));
+        let texels = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [0, 0, 0, 0],
+        ];
+        let image = image::DynamicImage::ImageRgba8(
+            image::ImageBuffer::from_raw(2, 2, texels.concat()).unwrap(),
+        );
+        let options = GifOptions {
+            dither: DitherKind::FloydSteinberg,
+            palette: None,
+        };
+        write_gif(&path, &image, &options).unwrap();
+        let saved = image::open(&path).unwrap().to_rgba8();
+        for (pixel, expected) in saved.pixels().zip(texels) {
+            if expected[3] == 0 {
+                assert_eq!(pixel.0[3], 0);
+            } else {
+                assert_eq!(pixel.0, expected);
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn indexed_gifs_keep_the_palette_indices() {
+        let path = std::env::temp_dir().join(format!(
+            "mochi_gif_export_indexed_{}.gif",
+            std::process::id()
+        ));
+        let palette = Palette::new(vec![[0, 0, 0], [255, 0, 0], [0, 0, 255]]).unwrap();
+        let texels = [
+            [0, 0, 255, 255],
+            [255, 0, 0, 255],
+            [0, 0, 0, 255],
+            [0, 0, 0, 0],
+        ];
+        let image = image::DynamicImage::ImageRgba8(
+            image::ImageBuffer::from_raw(2, 2, texels.concat()).unwrap(),
+        );
+        let options = GifOptions {
+            dither: DitherKind::FloydSteinberg,
+            palette: Some(palette),
+        };
+        write_gif(&path, &image, &options).unwrap();
+
+        let mut decode_options = gif::DecodeOptions::new();
+        decode_options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = decode_options
+            .read_info(File::open(&path).unwrap())
+            .unwrap();
+        let gif_palette = decoder.global_palette().unwrap().to_vec();
+        assert_eq!(&gif_palette[..9], &[0, 0, 0, 255, 0, 0, 0, 0, 255]);
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(frame.buffer.as_ref(), &[2, 1, 0, 3]);
+        assert_eq!(frame.transparent, Some(3));
+        std::fs::remove_file(path).unwrap();
+    }
+}
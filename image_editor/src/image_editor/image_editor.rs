@@ -1,18 +1,29 @@
-use cgmath::{ElementWise, Point2};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::task::Poll;
+use std::time::Duration;
+
+use cgmath::{point2, vec2, ElementWise, Point2, Rad, Vector2};
 use framework::framework::{ShaderId, TextureId};
 use framework::renderer::draw_command::{DrawCommand, DrawMode, OptionalDrawData, PrimitiveType};
 use framework::renderer::renderer::Renderer;
 use framework::scene::Camera2d;
 use framework::shader::ShaderCreationInfo;
 use framework::{
-    Framework, RgbaTexture2D, Texture, TextureConfiguration, TextureUsage, Transform2d,
+    Box2d, ColorSpace, Framework, RgbaTexture2D, TexelConversionError, Texture,
+    TextureConfiguration, TextureUsage, Transform2d,
 };
+use image::DynamicImage;
 use wgpu::{TextureFormat, TextureView};
 
-use crate::document::DocumentCreationInfo;
+use crate::document::{AlphaMode, DetachedLayer, DocumentCreationInfo, ViewState};
 use crate::image_editor;
-use crate::layers::LayerId;
+use crate::layers::{Interpolation, LayerId};
+use crate::selection::SelectionDisplay;
 
+use super::autosave::{self, AutosaveScheduler, AUTOSAVE_BACKUPS};
+use super::gif_export::GifOptions;
+use super::pending_export::PendingExport;
 use super::{document::Document, layers::Layer};
 
 #[derive(Debug, Default)]
@@ -29,12 +40,64 @@ pub struct LayerConstructionInfo {
     pub name: String,
 }
 
+// Where the brush paints when the current layer has a mask
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PaintTarget {
+    #[default]
+    LayerPixels,
+    LayerMask,
+}
+
+// A non editable image drawn above the canvas, e.g. as an onion skin: it's never exported
+pub struct ReferenceOverlay {
+    pub texture: TextureId,
+    pub opacity: f32,
+}
+
+// Stays the same while the document is open, unlike its index in the tabs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DocumentId(pub(crate) u64);
+
+struct AutosaveSettings {
+    interval: Duration,
+    max_edits: Option<u64>,
+    dir: PathBuf,
+}
+
+struct DocumentAutosave {
+    scheduler: AutosaveScheduler,
+    next_slot: usize,
+}
+
+struct OpenDocument {
+    id: DocumentId,
+    document: Document,
+}
+
 pub struct ImageEditor {
     pan_camera: Camera2d,
 
-    document: Document,
+    documents: Vec<OpenDocument>,
+    active_document: usize,
+    next_document_id: u64,
     output_texture: TextureId,
-    final_present_shader: ShaderId,
+    // The shaders drawing the canvas on the output views, by format
+    present_shaders: HashMap<TextureFormat, ShaderId>,
+    // The document being exported is kept, so that only its autosaves are removed once it's saved
+    pending_export: Option<(DocumentId, PendingExport)>,
+    // None while autosaving is disabled: see set_autosave
+    autosave: Option<AutosaveSettings>,
+    // Each document counts its own edits, so it's scheduled on its own
+    document_autosaves: HashMap<DocumentId, DocumentAutosave>,
+    reference_overlay: Option<ReferenceOverlay>,
+    paint_target: PaintTarget,
+    // See set_pixel_art_display
+    pixel_art_display: bool,
+    selection_display: SelectionDisplay,
+    // Shown around the document, where the canvas is transparent
+    canvas_clear_color: wgpu::Color,
+    // Set when something changed since the document was last rendered
+    dirty: bool,
 }
 
 impl ImageEditor {
@@ -50,7 +113,8 @@ impl ImageEditor {
                 first_layer_color: [0.0, 0.0, 0.0, 1.0],
             },
             framework,
-        );
+        )
+        .expect("The first document is too large for the device");
         let left_right_top_bottom = [
             -initial_window_bounds[0] * 0.5,
             initial_window_bounds[0] * 0.5,
@@ -59,71 +123,482 @@ impl ImageEditor {
         ];
         let pan_camera = Camera2d::new(-0.1, 1000.0, left_right_top_bottom);
 
-        let final_present_shader_info =
-            ShaderCreationInfo::using_default_vertex_fragment(framework)
-                .with_output_format(TextureFormat::Bgra8UnormSrgb);
-        let final_present_shader = framework.create_shader(final_present_shader_info);
-
         let output_texture = framework.allocate_texture2d(
             RgbaTexture2D::empty((pan_camera.width() as u32, pan_camera.height() as u32)),
             TextureConfiguration {
                 label: Some("ImageEditor final rendering texture"),
                 usage: TextureUsage::RWRT,
                 mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
         );
 
         ImageEditor {
             pan_camera,
-            document: test_document,
-            final_present_shader,
+            documents: vec![OpenDocument {
+                id: DocumentId(0),
+                document: test_document,
+            }],
+            active_document: 0,
+            next_document_id: 1,
+            present_shaders: HashMap::new(),
             output_texture,
+            pending_export: None,
+            autosave: None,
+            document_autosaves: HashMap::new(),
+            reference_overlay: None,
+            paint_target: PaintTarget::default(),
+            pixel_art_display: false,
+            selection_display: SelectionDisplay::default(),
+            canvas_clear_color: wgpu::Color::TRANSPARENT,
+            dirty: true,
         }
     }
 
     pub fn document(&self) -> &Document {
-        &self.document
+        &self.documents[self.active_document].document
+    }
+
+    fn document_mut(&mut self) -> &mut Document {
+        &mut self.documents[self.active_document].document
+    }
+
+    pub fn active_document_index(&self) -> usize {
+        self.active_document
+    }
+
+    pub fn active_document_id(&self) -> DocumentId {
+        self.documents[self.active_document].id
+    }
+
+    pub fn document_count(&self) -> usize {
+        self.documents.len()
+    }
+
+    // The open documents in tab order
+    pub fn documents(&self) -> impl Iterator<Item = (DocumentId, &Document)> {
+        self.documents.iter().map(|open| (open.id, &open.document))
+    }
+
+    pub fn is_document_open(&self, id: DocumentId) -> bool {
+        self.documents.iter().any(|open| open.id == id)
+    }
+
+    // Adds a tab for the document and makes it the active one
+    pub fn open_document(&mut self, document: Document) -> DocumentId {
+        let id = DocumentId(self.next_document_id);
+        self.next_document_id += 1;
+        self.documents.push(OpenDocument { id, document });
+        self.switch_to(self.documents.len() - 1);
+        id
     }
 
-    pub fn export_current_image(&mut self, framework: &Framework) {
+    // The last open document can't be closed. The GPU assets of the closed document are freed,
+    // so its undo history must be dropped too: the returned id is the one it was kept by
+    pub fn close_document(
+        &mut self,
+        index: usize,
+        framework: &mut Framework,
+    ) -> Option<DocumentId> {
+        if self.documents.len() <= 1 || index >= self.documents.len() {
+            return None;
+        }
+        let closed = self.documents.remove(index);
+        self.document_autosaves.remove(&closed.id);
+        if let Some(settings) = &self.autosave {
+            autosave::remove_autosaves(&settings.dir, autosave::current_session(), closed.id);
+        }
+        closed.document.free_gpu_assets(framework);
+        if index < self.active_document {
+            self.active_document -= 1;
+        } else if index == self.active_document {
+            self.active_document = index.min(self.documents.len() - 1);
+            self.restore_view();
+        }
+        self.dirty = true;
+        Some(closed.id)
+    }
+
+    // The current view is saved in the active document, the new one gets back the view it was
+    // left with, or is fitted to the viewport the first time it's shown
+    pub fn switch_to(&mut self, index: usize) {
+        if index >= self.documents.len() {
+            return;
+        }
+        let view = self.view_state();
+        self.document_mut().set_view(Some(view));
+        self.active_document = index;
+        self.restore_view();
+        self.dirty = true;
+    }
+
+    fn restore_view(&mut self) {
+        match self.document().view() {
+            Some(view) => self.set_view_state(view),
+            None => self.reset_view(vec2(
+                self.pan_camera.width() as u32,
+                self.pan_camera.height() as u32,
+            )),
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    // Returns the path picked by the user, if any
+    pub fn export_current_image(&mut self, framework: &Framework) -> Option<PathBuf> {
+        if self.pending_export.is_some() {
+            log::warn!("An export is already in progress");
+            return None;
+        }
         let file_path = rfd::FileDialog::new()
             .add_filter("PNG Image", &["png"])
             .add_filter("JPG Image", &["jpg", "jpeg"])
             .add_filter("Bitmap", &["bmp"])
+            .add_filter("GIF Image", &["gif"])
             .set_title("Save image")
             .save_file();
-        if let Some(file_path) = file_path {
-            let image = self.get_full_image_bytes(framework);
-            if let Err(e) = image.save(file_path) {
-                log::error!("While saving image: {e}");
+        if let Some(file_path) = &file_path {
+            let image = self
+                .document()
+                .final_image_bytes_async(self.document().export_alpha_mode(), framework);
+            let export = PendingExport::new(
+                file_path.clone(),
+                self.document().dpi(),
+                GifOptions {
+                    dither: self.document().gif_dither(),
+                    palette: self.document().palette_mode().cloned(),
+                },
+                image,
+            );
+            self.pending_export = Some((self.active_document_id(), export));
+        }
+        file_path
+    }
+
+    // Saves each layer as a page of a TIFF image, see Document::export_tiff
+    pub fn export_layers_to_tiff(
+        &mut self,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) -> Option<PathBuf> {
+        let file_path = rfd::FileDialog::new()
+            .add_filter("TIFF Image", &["tif", "tiff"])
+            .set_title("Export layers")
+            .save_file()?;
+        match self
+            .document_mut()
+            .export_tiff(&file_path, renderer, framework)
+        {
+            Ok(()) => Some(file_path),
+            Err(e) => {
+                log::error!("While exporting {}: {e}", file_path.display());
+                None
+            }
+        }
+    }
+
+    // Saves the selected pixels of the final image, see Document::export_selection
+    pub fn export_selection(&self, framework: &Framework) -> Option<PathBuf> {
+        let file_path = rfd::FileDialog::new()
+            .add_filter("PNG Image", &["png"])
+            .add_filter("Bitmap", &["bmp"])
+            .set_title("Export selection")
+            .save_file()?;
+        match self.document().export_selection(&file_path, framework) {
+            Ok(()) => Some(file_path),
+            Err(e) => {
+                log::error!("While exporting {}: {e}", file_path.display());
+                None
+            }
+        }
+    }
+
+    // Opens a new document sized after the image, returns false when the image is too big
+    // or empty for a document
+    pub fn new_document_from_image(
+        &mut self,
+        image: &DynamicImage,
+        framework: &mut Framework,
+    ) -> bool {
+        match Document::from_image(image, framework) {
+            Ok(document) => {
+                self.open_document(document);
+                true
+            }
+            Err(e) => {
+                log::error!("Could not create a document from the image: {e}");
+                false
+            }
+        }
+    }
+
+    // Centers the document and zooms until it fits in the viewport, removing the rotation
+    pub fn reset_view(&mut self, viewport: Vector2<u32>) {
+        let document_size = self.document().document_size().cast::<f32>().unwrap();
+        let scale = (document_size.x / viewport.x.max(1) as f32)
+            .max(document_size.y / viewport.y.max(1) as f32);
+        self.set_view_state(ViewState {
+            position: point2(0.0, 0.0),
+            scale,
+            rotation: Rad(0.0),
+        });
+    }
+
+    pub fn view_state(&self) -> ViewState {
+        ViewState {
+            position: self.pan_camera.position(),
+            scale: self.pan_camera.current_scale(),
+            rotation: self.pan_camera.rotation(),
+        }
+    }
+
+    pub fn set_view_state(&mut self, view: ViewState) {
+        self.pan_camera.set_position(view.position);
+        self.pan_camera.set_scale(view.scale);
+        self.pan_camera.set_rotation(view.rotation);
+        self.dirty = true;
+    }
+
+    // Returns the path of the opened image, if any
+    pub fn new_document_from_file(&mut self, framework: &mut Framework) -> Option<PathBuf> {
+        let file_path = rfd::FileDialog::new()
+            .add_filter("Images", &["png", "jpg", "jpeg", "tif", "tiff"])
+            .set_title("New document from image")
+            .pick_file()?;
+        self.open_image_file(&file_path, framework)
+            .then_some(file_path)
+    }
+
+    // Returns false when the image couldn't be opened. The pages of the TIFF images become layers
+    pub fn open_image_file(&mut self, path: &Path, framework: &mut Framework) -> bool {
+        let is_tiff = path.extension().map_or(false, |ext| {
+            ext.eq_ignore_ascii_case("tif") || ext.eq_ignore_ascii_case("tiff")
+        });
+        if is_tiff {
+            return match Document::import_tiff(path, framework) {
+                Ok(document) => {
+                    self.open_document(document);
+                    true
+                }
+                Err(e) => {
+                    log::error!("While opening {}: {e}", path.display());
+                    false
+                }
             };
         }
+        let image = match image::open(path) {
+            Ok(image) => image,
+            Err(e) => {
+                log::error!("While opening {}: {e}", path.display());
+                return false;
+            }
+        };
+        match Document::from_image(&image, framework) {
+            Ok(document) => {
+                self.open_document(document);
+                true
+            }
+            Err(e) => {
+                log::error!("While opening {}: {e}", path.display());
+                false
+            }
+        }
+    }
+
+    // Finishes the current export, if the image has been read back from the GPU
+    pub fn update_pending_export(&mut self) {
+        if let Some((document, export)) = &mut self.pending_export {
+            if let Poll::Ready(saved) = export.poll() {
+                let document = *document;
+                self.pending_export = None;
+                if let (true, Some(settings)) = (saved, &self.autosave) {
+                    autosave::remove_autosaves(
+                        &settings.dir,
+                        autosave::current_session(),
+                        document,
+                    );
+                }
+            }
+        }
+    }
+
+    // The active document is saved in dir every interval, or every max_edits edits, while it has
+    // unsaved edits: see update_autosave. None disables autosaving
+    pub fn set_autosave(
+        &mut self,
+        interval: Option<Duration>,
+        max_edits: Option<u64>,
+        dir: PathBuf,
+    ) {
+        self.autosave = interval.map(|interval| AutosaveSettings {
+            interval,
+            max_edits,
+            dir,
+        });
+        self.document_autosaves.clear();
+    }
+
+    // Called once per update, edit_count is the number of edits made to the active document.
+    // The saves of each document rotate between AUTOSAVE_BACKUPS files, see autosave::autosave_path
+    pub fn update_autosave(
+        &mut self,
+        elapsed: Duration,
+        edit_count: u64,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) {
+        let settings = match &self.autosave {
+            Some(settings) => settings,
+            None => return,
+        };
+        let id = self.active_document_id();
+        let document_autosave =
+            self.document_autosaves
+                .entry(id)
+                .or_insert_with(|| DocumentAutosave {
+                    scheduler: AutosaveScheduler::new(settings.interval, settings.max_edits),
+                    next_slot: 0,
+                });
+        if !document_autosave.scheduler.tick(elapsed, edit_count) {
+            return;
+        }
+        document_autosave.scheduler.saved(edit_count);
+        let path = autosave::autosave_path(
+            &settings.dir,
+            autosave::current_session(),
+            id,
+            document_autosave.next_slot,
+        );
+        document_autosave.next_slot = (document_autosave.next_slot + 1) % AUTOSAVE_BACKUPS;
+        if let Err(e) = std::fs::create_dir_all(&settings.dir) {
+            log::warn!("Could not create {}: {e}", settings.dir.display());
+            return;
+        }
+        if let Err(e) = self.document_mut().export_tiff(&path, renderer, framework) {
+            log::warn!("Could not autosave {}: {e}", path.display());
+        }
+    }
+
+    // The overlay is only displayed on the canvas, the opacity is clamped between 0 and 1
+    pub fn set_reference_overlay(&mut self, texture: Option<TextureId>, opacity: f32) {
+        self.reference_overlay = texture.map(|texture| ReferenceOverlay {
+            texture,
+            opacity: opacity.clamp(0.0, 1.0),
+        });
+        self.dirty = true;
+    }
+
+    pub fn reference_overlay(&self) -> Option<&ReferenceOverlay> {
+        self.reference_overlay.as_ref()
     }
 
     pub fn mutate_document<F: FnMut(&mut Document)>(&mut self, mut mutate_fn: F) {
-        mutate_fn(&mut self.document);
+        mutate_fn(self.document_mut());
+        self.dirty = true;
     }
 
     pub fn mutate_current_layer<F: FnOnce(&mut Layer)>(&mut self, mutate_fn: F) {
         if let Some(index) = self.document().current_layer_index().copied() {
-            self.document.mutate_layer(&index, mutate_fn);
+            self.document_mut().mutate_layer(&index, mutate_fn);
+            self.dirty = true;
+        }
+    }
+
+    // Paints on the quick mask while it's active, otherwise on the current layer.
+    // Nothing is painted when the current layer is a group
+    pub fn mutate_paint_target<F: FnOnce(&mut Layer)>(&mut self, mutate_fn: F) {
+        match self.document_mut().quick_mask_mut() {
+            Some(mask) => {
+                mutate_fn(mask);
+                self.dirty = true;
+            }
+            None if self.current_layer_is_group() => {
+                log::warn!("Cannot paint on a group layer, select one of its layers");
+            }
+            None => self.mutate_current_layer(mutate_fn),
         }
     }
 
+    pub fn current_layer_is_group(&self) -> bool {
+        self.document()
+            .current_layer_index()
+            .map_or(false, |id| self.document().get_layer(id).is_group())
+    }
+
+    pub fn paint_target(&self) -> PaintTarget {
+        self.paint_target
+    }
+
+    pub fn set_paint_target(&mut self, target: PaintTarget) {
+        self.paint_target = target;
+    }
+
+    pub fn pixel_art_display(&self) -> bool {
+        self.pixel_art_display
+    }
+
+    // When zoomed by a fractional amount the canvas is blurry with bilinear sampling:
+    // in the pixel art display it's sampled with the nearest texels instead
+    pub fn set_pixel_art_display(&mut self, pixel_art_display: bool) {
+        self.pixel_art_display = pixel_art_display;
+        self.dirty = true;
+    }
+
+    // How the document is sampled when it's drawn on the canvas
+    pub fn canvas_interpolation(&self) -> Interpolation {
+        if self.pixel_art_display {
+            Interpolation::Nearest
+        } else {
+            Interpolation::Bilinear
+        }
+    }
+
+    pub fn selection_display(&self) -> SelectionDisplay {
+        self.selection_display
+    }
+
+    pub fn set_selection_display(&mut self, selection_display: SelectionDisplay) {
+        self.selection_display = selection_display;
+        self.dirty = true;
+    }
+
+    pub fn canvas_clear_color(&self) -> wgpu::Color {
+        self.canvas_clear_color
+    }
+
+    pub fn set_canvas_clear_color(&mut self, color: wgpu::Color) {
+        self.canvas_clear_color = color;
+        self.dirty = true;
+    }
+
+    pub fn toggle_quick_mask(&mut self, framework: &mut Framework) {
+        self.document_mut().toggle_quick_mask(framework);
+        self.dirty = true;
+    }
+
     pub fn add_layer_to_document(
         &mut self,
         config: LayerConstructionInfo,
         framework: &mut Framework,
     ) -> LayerId {
-        self.document.add_layer(config, framework)
+        self.dirty = true;
+        self.document_mut().add_layer(config, framework)
     }
 
     pub fn select_new_layer(&mut self, layer_idx: LayerId) {
-        self.document.select_layer(layer_idx);
+        self.document_mut().select_layer(layer_idx);
+        self.dirty = true;
     }
 
-    pub fn delete_layer(&mut self, layer_idx: LayerId) {
-        self.document.delete_layer(layer_idx);
+    pub fn delete_layer(&mut self, layer_idx: LayerId, framework: &mut Framework) -> DetachedLayer {
+        self.dirty = true;
+        self.document_mut().delete_layer(layer_idx, framework)
     }
 
     pub fn on_resize(&mut self, new_bounds: [f32; 4], framework: &mut Framework) {
@@ -137,45 +612,101 @@ impl ImageEditor {
                 label: Some("ImageEditor final rendering texture"),
                 usage: TextureUsage::RWRT,
                 mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
         );
+        self.dirty = true;
     }
 
     pub fn update_layers(&mut self, renderer: &mut Renderer, framework: &mut Framework) {
-        self.mutate_document(|d| d.update_layers(renderer, framework));
+        self.document_mut().update_layers(renderer, framework);
     }
 
+    // Rendering the document clears the dirty flag
     pub fn render_document(&mut self, renderer: &mut Renderer, framework: &mut Framework) {
-        self.document.render(renderer, framework);
+        self.document_mut().render(renderer, framework);
+        self.dirty = false;
     }
 
+    // Draws on the window surface, which is Bgra8UnormSrgb
     pub fn render_canvas(
         &mut self,
         renderer: &mut Renderer,
         output_canvas: &TextureView,
         framework: &mut Framework,
     ) {
+        self.present(
+            renderer,
+            output_canvas,
+            TextureFormat::Bgra8UnormSrgb,
+            framework,
+        );
+    }
+
+    // Composites the document, then draws it along with the overlays on a view owned by the caller,
+    // e.g. when the editor is embedded in another wgpu application. The view must be viewport
+    // sized, use format and have the RENDER_ATTACHMENT usage: its contents are replaced.
+    // The canvas is resized to the viewport when their sizes differ
+    pub fn render_to(
+        &mut self,
+        renderer: &mut Renderer,
+        view: &TextureView,
+        format: TextureFormat,
+        viewport: Vector2<u32>,
+        framework: &mut Framework,
+    ) {
+        if framework.texture2d_dimensions(&self.output_texture) != (viewport.x, viewport.y) {
+            let half_size = viewport.cast::<f32>().unwrap() * 0.5;
+            self.on_resize(
+                [-half_size.x, half_size.x, half_size.y, -half_size.y],
+                framework,
+            );
+        }
+        self.update_layers(renderer, framework);
+        self.render_document(renderer, framework);
+        self.present(renderer, view, format, framework);
+    }
+
+    fn present(
+        &mut self,
+        renderer: &mut Renderer,
+        output_canvas: &TextureView,
+        format: TextureFormat,
+        framework: &mut Framework,
+    ) {
+        let present_shader = self
+            .present_shaders
+            .entry(format)
+            .or_insert_with(|| {
+                let info = ShaderCreationInfo::using_default_vertex_fragment(framework)
+                    .with_output_format(format);
+                framework.create_shader(info)
+            })
+            .clone();
+
         renderer.begin(&self.pan_camera, Some(wgpu::Color::TRANSPARENT), framework);
         renderer.set_draw_debug_name("Canvas rendering");
         renderer.draw(DrawCommand {
             primitives: PrimitiveType::Texture2D {
-                texture_id: self.document.render_result().clone(),
+                texture_id: self.document().render_result().clone(),
                 instances: vec![Transform2d {
-                    scale: self.document.document_size().cast::<f32>().unwrap() * 0.5,
+                    scale: self.document().document_size().cast::<f32>().unwrap() * 0.5,
                     ..Default::default()
                 }],
                 flip_uv_y: true,
                 multiply_color: wgpu::Color::WHITE,
             },
             draw_mode: DrawMode::Single,
-            additional_data: OptionalDrawData::default(),
+            additional_data: OptionalDrawData::just_shader(self.canvas_interpolation().shader()),
         });
+        self.render_reference_overlay(renderer);
+        self.document().draw_quick_mask(renderer);
 
         self.render_ui(renderer);
 
         renderer.end(&self.output_texture, None, framework);
 
-        renderer.begin(&Camera2d::unit(), Some(wgpu::Color::TRANSPARENT), framework);
+        renderer.begin(&Camera2d::unit(), Some(self.canvas_clear_color), framework);
         renderer.draw(DrawCommand {
             primitives: PrimitiveType::Texture2D {
                 texture_id: self.output_texture.clone(),
@@ -187,27 +718,59 @@ impl ImageEditor {
             additional_data: OptionalDrawData {
                 additional_vertex_buffers: vec![],
                 additional_bindable_resource: vec![],
-                shader: Some(self.final_present_shader.clone()),
+                shader: Some(present_shader),
+                scissor: None,
             },
         });
-        renderer.end_on_external_texture(output_canvas, framework);
+        let output_size = framework.texture2d_dimensions(&self.output_texture);
+        renderer.end_on_external_texture(output_canvas, output_size, framework);
+    }
+
+    fn render_reference_overlay(&self, renderer: &mut Renderer) {
+        if let Some(overlay) = &self.reference_overlay {
+            // The canvas is drawn with premultiplied alpha, so the opacity scales all the channels
+            let opacity = overlay.opacity as f64;
+            renderer.draw(DrawCommand {
+                primitives: PrimitiveType::Texture2D {
+                    texture_id: overlay.texture.clone(),
+                    instances: vec![Transform2d {
+                        scale: self.document().document_size().cast::<f32>().unwrap() * 0.5,
+                        ..Default::default()
+                    }],
+                    flip_uv_y: true,
+                    multiply_color: wgpu::Color {
+                        r: opacity,
+                        g: opacity,
+                        b: opacity,
+                        a: opacity,
+                    },
+                },
+                draw_mode: DrawMode::Single,
+                additional_data: OptionalDrawData::default(),
+            });
+        }
     }
 
     fn render_ui(&mut self, renderer: &mut Renderer) {
-        self.document.draw_selection(renderer);
+        self.document()
+            .draw_selection_overlay(renderer, self.selection_display);
     }
 
     pub fn get_full_image_texture(&self) -> &TextureId {
         &self.document().render_result()
     }
 
-    pub fn get_full_image_bytes(&mut self, framework: &Framework) -> image::DynamicImage {
-        self.document().final_image_bytes(framework)
+    pub fn get_full_image_bytes(
+        &mut self,
+        framework: &Framework,
+    ) -> Result<image::DynamicImage, TexelConversionError> {
+        self.document()
+            .final_image_bytes(AlphaMode::Straight, framework)
     }
 
     pub fn pan_camera(&mut self, delta: cgmath::Vector2<f32>) {
         let half_outer_size = self
-            .document
+            .document()
             .document_size()
             .cast::<f32>()
             .expect("Somehow this cast failed")
@@ -217,14 +780,19 @@ impl ImageEditor {
         new_position.x = new_position.x.clamp(-half_outer_size.x, half_outer_size.x);
         new_position.y = new_position.y.clamp(-half_outer_size.y, half_outer_size.y);
         self.pan_camera.set_position(new_position);
+        self.dirty = true;
     }
 
     pub fn scale_view(&mut self, delta: f32) {
-        const SCALE_SPEED: f32 = 100.0; // TODO: Make this customizable
-        self.pan_camera.scale(delta * SCALE_SPEED);
-        if self.pan_camera.current_scale() <= 0.0 {
-            self.pan_camera.set_scale(0.01);
-        }
+        self.pan_camera
+            .set_scale(scaled_view(self.pan_camera.current_scale(), delta));
+        self.dirty = true;
+    }
+
+    // Zooms keeping the document point under screen_point (in pixels, relative to the viewport) in place
+    pub fn zoom_at(&mut self, screen_point: Point2<f32>, delta: f32, viewport: Vector2<u32>) {
+        zoom_camera_at(&mut self.pan_camera, screen_point, delta, viewport);
+        self.dirty = true;
     }
 
     // Transforms according to current camera position and current layer transform
@@ -236,14 +804,474 @@ impl ImageEditor {
         Some(position_into_layer)
     }
 
+    // Maps a point in normalized window coordinates into the document pixel coordinates, with the origin
+    // in the top left corner of the document. Returns None when the point is outside the document
+    pub fn document_pixel_position(&self, point_normalized: Point2<f32>) -> Option<Point2<u32>> {
+        let position_into_canvas = self.camera().ndc_into_world(point_normalized);
+        world_into_document_pixel(position_into_canvas, self.document().document_size())
+    }
+
+    // The part of the document shown in the viewport, in document pixel coordinates like
+    // document_pixel_position. When the view is rotated this is the box around the viewport
+    pub fn viewport_rect_in_document(&self, viewport: Vector2<u32>) -> Box2d {
+        viewport_rect(&self.pan_camera, viewport, self.document().document_size())
+    }
+
+    // Centers the view on a point in document pixel coordinates, e.g. clicked on the navigator
+    pub fn navigate_to(&mut self, document_point: Point2<f32>) {
+        let position = camera_position_centered_on(document_point, self.document().document_size());
+        self.pan_camera.set_position(position);
+        self.dirty = true;
+    }
+
     pub fn selected_layer(&self) -> &Layer {
-        self.document.current_layer()
+        self.document().current_layer()
     }
 
     pub fn camera(&self) -> &Camera2d {
         &self.pan_camera
     }
     pub fn camera_mut(&mut self) -> &mut Camera2d {
+        self.dirty = true;
         &mut self.pan_camera
     }
 }
+
+fn scaled_view(current_scale: f32, delta: f32) -> f32 {
+    const SCALE_SPEED: f32 = 100.0; // TODO: Make this customizable
+    let new_scale = current_scale + delta * SCALE_SPEED;
+    if new_scale <= 0.0 {
+        0.01
+    } else {
+        new_scale
+    }
+}
+
+fn zoom_camera_at(
+    camera: &mut Camera2d,
+    screen_point: Point2<f32>,
+    delta: f32,
+    viewport: Vector2<u32>,
+) {
+    // Same convention as the normalized mouse position, y points down
+    let ndc_point = point2(
+        screen_point.x / viewport.x as f32 * 2.0 - 1.0,
+        screen_point.y / viewport.y as f32 * 2.0 - 1.0,
+    );
+    camera.zoom_around(ndc_point, scaled_view(camera.current_scale(), delta));
+}
+
+fn viewport_rect(camera: &Camera2d, viewport: Vector2<u32>, document_size: Vector2<u32>) -> Box2d {
+    let half_document_size = document_size.cast::<f32>().unwrap() * 0.5;
+    let corners = [
+        (0, 0),
+        (viewport.x, 0),
+        (0, viewport.y),
+        (viewport.x, viewport.y),
+    ]
+    .map(|(x, y)| {
+        let ndc_point = point2(
+            x as f32 / viewport.x.max(1) as f32 * 2.0 - 1.0,
+            y as f32 / viewport.y.max(1) as f32 * 2.0 - 1.0,
+        );
+        camera.ndc_into_world(ndc_point) + half_document_size
+    });
+    let min = corners
+        .into_iter()
+        .reduce(|a, b| point2(a.x.min(b.x), a.y.min(b.y)))
+        .unwrap();
+    let max = corners
+        .into_iter()
+        .reduce(|a, b| point2(a.x.max(b.x), a.y.max(b.y)))
+        .unwrap();
+    Box2d::from_points(min, max)
+}
+
+// The camera moves the world by its position, so the point at the center of the view is
+// the opposite of the position
+fn camera_position_centered_on(
+    document_point: Point2<f32>,
+    document_size: Vector2<u32>,
+) -> Point2<f32> {
+    let half_document_size = document_size.cast::<f32>().unwrap() * 0.5;
+    point2(
+        half_document_size.x - document_point.x,
+        half_document_size.y - document_point.y,
+    )
+}
+
+// The normalized window coordinates have y pointing down, so the world coordinates
+// computed by the pan camera already have the document's orientation
+fn world_into_document_pixel(
+    position: Point2<f32>,
+    document_size: Vector2<u32>,
+) -> Option<Point2<u32>> {
+    let half_document_size = document_size.cast::<f32>().unwrap() * 0.5;
+    let pixel = point2(
+        (position.x + half_document_size.x).floor(),
+        (position.y + half_document_size.y).floor(),
+    );
+    if pixel.x < 0.0
+        || pixel.y < 0.0
+        || pixel.x >= document_size.x as f32
+        || pixel.y >= document_size.y as f32
+    {
+        return None;
+    }
+    pixel.cast::<u32>()
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::{point2, vec2, InnerSpace, Point2, Rad, Vector2};
+    use framework::{
+        renderer::renderer::Renderer, scene::Camera2d, Box2d, ColorSpace, Framework, RgbaTexture2D,
+        RgbaU8, TextureConfiguration, TextureUsage,
+    };
+    use wgpu::TextureFormat;
+
+    use image::{DynamicImage, GrayImage, Luma};
+
+    use crate::document::{Document, ViewState};
+    use crate::headless_framework;
+    use crate::layers::Interpolation;
+    use crate::test_support::{render_target, texture_texels};
+
+    use super::{
+        camera_position_centered_on, viewport_rect, world_into_document_pixel, zoom_camera_at,
+        ImageEditor,
+    };
+
+    #[test]
+    fn world_into_document_pixel_bounds() {
+        let size = vec2(200, 100);
+        assert_eq!(
+            world_into_document_pixel(point2(0.0, 0.0), size),
+            Some(point2(100, 50))
+        );
+        assert_eq!(
+            world_into_document_pixel(point2(-100.0, -50.0), size),
+            Some(point2(0, 0))
+        );
+        assert_eq!(
+            world_into_document_pixel(point2(99.5, 49.5), size),
+            Some(point2(199, 99))
+        );
+        assert_eq!(world_into_document_pixel(point2(100.0, 0.0), size), None);
+        assert_eq!(world_into_document_pixel(point2(0.0, -50.5), size), None);
+    }
+
+    #[test]
+    fn screen_into_document_pixel_with_zoom() {
+        let size = vec2(1000, 1000);
+        let mut camera = Camera2d::wh(500, 500);
+        // At 100% the window corner is 250 pixels away from the center of the document
+        let corner = camera.ndc_into_world(point2(-0.999, -0.999));
+        assert_eq!(
+            world_into_document_pixel(corner, size),
+            Some(point2(250, 250))
+        );
+
+        // At 50% the whole document fits into the window
+        camera.set_scale(2.0);
+        assert_eq!(camera.zoom_percent(), 50.0);
+        let corner = camera.ndc_into_world(point2(-0.999, -0.999));
+        assert_eq!(world_into_document_pixel(corner, size), Some(point2(0, 0)));
+        let outside = camera.ndc_into_world(point2(-1.01, 0.0));
+        assert_eq!(world_into_document_pixel(outside, size), None);
+    }
+
+    #[test]
+    fn zoom_at_keeps_the_document_point_under_the_cursor() {
+        let size = vec2(1000, 1000);
+        let viewport = vec2(800, 600);
+        let mut camera = Camera2d::wh(viewport.x, viewport.y);
+        // Half a pixel away from the pixel borders, so that rounding errors don't matter
+        let cursor = point2(600.5, 149.5);
+        let cursor_ndc = point2(
+            cursor.x / viewport.x as f32 * 2.0 - 1.0,
+            cursor.y / viewport.y as f32 * 2.0 - 1.0,
+        );
+        let before = world_into_document_pixel(camera.ndc_into_world(cursor_ndc), size);
+        assert_eq!(before, Some(point2(700, 349)));
+
+        zoom_camera_at(&mut camera, cursor, -0.005, viewport);
+        assert!((camera.current_scale() - 0.5).abs() < 0.0001);
+        let after = world_into_document_pixel(camera.ndc_into_world(cursor_ndc), size);
+        assert_eq!(after, before);
+
+        zoom_camera_at(&mut camera, cursor, 0.02, viewport);
+        let after = world_into_document_pixel(camera.ndc_into_world(cursor_ndc), size);
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn viewport_rect_follows_zoom_and_pan() {
+        let size = vec2(1000, 1000);
+        let viewport = vec2(800, 600);
+        let mut camera = Camera2d::wh(viewport.x, viewport.y);
+        let assert_near = |rect: Box2d, center: Point2<f32>, extents: Vector2<f32>| {
+            assert!((rect.center - center).magnitude() < 0.01, "{:?}", rect);
+            assert!((rect.extents - extents).magnitude() < 0.01, "{:?}", rect);
+        };
+        // At 100% the viewport covers as many pixels as it has, around the document center
+        let rect = viewport_rect(&camera, viewport, size);
+        assert_near(rect, point2(500.0, 500.0), vec2(400.0, 300.0));
+
+        // At 50% twice as many pixels are visible, around the point the view was moved to
+        camera.set_scale(2.0);
+        camera.set_position(camera_position_centered_on(point2(200.0, 300.0), size));
+        let rect = viewport_rect(&camera, viewport, size);
+        assert_near(rect, point2(200.0, 300.0), vec2(800.0, 600.0));
+        let center = camera.ndc_into_world(point2(0.0, 0.0));
+        assert_eq!(
+            world_into_document_pixel(center, size),
+            Some(point2(200, 300))
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn views_are_reset_and_remembered_per_document() {
+        let mut framework = headless_framework();
+        let mut editor = ImageEditor::new(&mut framework, &[800.0, 600.0]);
+        assert_eq!(editor.document().document_size(), vec2(1800, 1024));
+
+        // The document is twice as wide as the viewport, so it's shown at 50%
+        editor.camera_mut().set_position(point2(120.0, -40.0));
+        editor.camera_mut().set_rotation(Rad(0.5));
+        editor.reset_view(vec2(900, 1024));
+        let fitted = ViewState {
+            position: point2(0.0, 0.0),
+            scale: 2.0,
+            rotation: Rad(0.0),
+        };
+        assert_eq!(editor.view_state(), fitted);
+        // Here the height decides the zoom
+        editor.reset_view(vec2(900, 300));
+        assert_eq!(editor.view_state().scale, 1024.0 / 300.0);
+
+        let moved = ViewState {
+            position: point2(35.0, 12.5),
+            scale: 0.25,
+            rotation: Rad(1.0),
+        };
+        editor.set_view_state(moved);
+        assert_eq!(editor.view_state(), moved);
+
+        // A new document is fitted into the 800x600 viewport
+        let image = DynamicImage::ImageLuma8(GrayImage::new(400, 600));
+        editor.open_document(Document::from_image(&image, &mut framework).unwrap());
+        assert_eq!(editor.view_state().position, point2(0.0, 0.0));
+        assert_eq!(editor.view_state().scale, 1.0);
+
+        // Switching back restores the view the first document was left with
+        editor.switch_to(0);
+        assert_eq!(editor.view_state(), moved);
+        editor.switch_to(1);
+        assert_eq!(editor.view_state().scale, 1.0);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn edits_go_to_the_active_document() {
+        let mut framework = headless_framework();
+        let mut editor = ImageEditor::new(&mut framework, &[800.0, 600.0]);
+        let first_id = editor.active_document_id();
+        let image = DynamicImage::ImageLuma8(GrayImage::new(64, 48));
+        let second_id = editor.open_document(Document::from_image(&image, &mut framework).unwrap());
+        assert_ne!(first_id, second_id);
+        assert_eq!(editor.document_count(), 2);
+        assert_eq!(editor.active_document_index(), 1);
+        assert_eq!(editor.document().document_size(), vec2(64, 48));
+
+        editor.mutate_document(|document| document.set_dpi(300.0));
+        editor.switch_to(0);
+        assert_eq!(editor.active_document_id(), first_id);
+        assert_eq!(editor.document().document_size(), vec2(1800, 1024));
+        assert_ne!(editor.document().dpi(), 300.0);
+
+        editor.mutate_document(|document| document.set_dpi(150.0));
+        let dpis: Vec<f32> = editor.documents().map(|(_, doc)| doc.dpi()).collect();
+        assert_eq!(dpis, vec![150.0, 300.0]);
+
+        // Closing the active document activates the one taking its place, the last can't be closed
+        let textures = framework.allocated_textures_count();
+        assert_eq!(editor.close_document(0, &mut framework), Some(first_id));
+        assert!(framework.allocated_textures_count() < textures);
+        assert!(!editor.is_document_open(first_id));
+        assert_eq!(editor.active_document_id(), second_id);
+        assert_eq!(editor.document().dpi(), 300.0);
+        assert!(editor.close_document(0, &mut framework).is_none());
+        assert_eq!(editor.document_count(), 1);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn reference_overlay_is_not_exported() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut editor = ImageEditor::new(&mut framework, &[800.0, 600.0]);
+        let overlay = framework.allocate_texture2d(
+            RgbaTexture2D::from_repeated_texel(RgbaU8([255, 0, 0, 255]), (4, 4)).unwrap(),
+            TextureConfiguration {
+                label: Some("Reference overlay"),
+                usage: TextureUsage::RWRT,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+
+        editor.set_reference_overlay(Some(overlay.clone()), 1.5);
+        assert_eq!(editor.reference_overlay().unwrap().opacity, 1.0);
+        editor.set_reference_overlay(Some(overlay.clone()), -0.5);
+        assert_eq!(editor.reference_overlay().unwrap().opacity, 0.0);
+        editor.set_reference_overlay(Some(overlay), 0.3);
+        assert_eq!(editor.reference_overlay().unwrap().opacity, 0.3);
+
+        // The white background layer is the only thing in the exported image
+        editor.update_layers(&mut renderer, &mut framework);
+        editor.render_document(&mut renderer, &mut framework);
+        let image = editor
+            .get_full_image_bytes(&framework)
+            .unwrap()
+            .into_rgba8();
+        assert!(image.pixels().all(|p| p.0 == [255, 255, 255, 255]));
+
+        editor.set_reference_overlay(None, 1.0);
+        assert!(editor.reference_overlay().is_none());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn new_document_from_a_grayscale_image() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut editor = ImageEditor::new(&mut framework, &[800.0, 600.0]);
+        // Dark on the left half, light on the right half
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(64, 48, |x, _| {
+            Luma([if x < 32 { 50 } else { 200 }])
+        }));
+
+        assert!(editor.new_document_from_image(&image, &mut framework));
+        assert_eq!(editor.document().document_size(), vec2(64, 48));
+        editor.update_layers(&mut renderer, &mut framework);
+        editor.render_document(&mut renderer, &mut framework);
+        let exported = editor
+            .get_full_image_bytes(&framework)
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(exported.dimensions(), (64, 48));
+        for (x, _, pixel) in exported.enumerate_pixels() {
+            let expected = if x < 32 { 50 } else { 200 };
+            assert_eq!(pixel.0, [expected, expected, expected, 255]);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn no_op_frames_stay_clean() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut image_editor = ImageEditor::new(&mut framework, &[800.0, 600.0]);
+        // The first frame must always be drawn
+        assert!(image_editor.is_dirty());
+
+        let mut frame = |image_editor: &mut ImageEditor| {
+            image_editor.update_pending_export();
+            image_editor.update_layers(&mut renderer, &mut framework);
+            image_editor.render_document(&mut renderer, &mut framework);
+        };
+        frame(&mut image_editor);
+        assert!(!image_editor.is_dirty());
+        for _ in 0..3 {
+            frame(&mut image_editor);
+            assert!(!image_editor.is_dirty());
+        }
+
+        image_editor.pan_camera(vec2(10.0, 0.0));
+        assert!(image_editor.is_dirty());
+        frame(&mut image_editor);
+        assert!(!image_editor.is_dirty());
+        image_editor.mark_dirty();
+        assert!(image_editor.is_dirty());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn the_editor_renders_to_external_views() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut editor = ImageEditor::new(&mut framework, &[800.0, 600.0]);
+        // The black document fits the width of the view, leaving transparent rows above and below
+        let viewport = vec2(64, 64);
+        editor.reset_view(viewport);
+        let target = render_target(viewport.x, viewport.y, ColorSpace::Srgb, &mut framework);
+        let view = framework.texture2d_create_view(&target);
+        editor.render_to(
+            &mut renderer,
+            &view,
+            TextureFormat::Rgba8UnormSrgb,
+            viewport,
+            &mut framework,
+        );
+        assert_eq!(
+            framework.texture2d_dimensions(&editor.output_texture),
+            (64, 64)
+        );
+
+        let rendered = texture_texels(&target, &framework);
+        let texel = |x: u32, y: u32| rendered[(y * viewport.x + x) as usize].0;
+        assert_eq!(texel(32, 32), [0, 0, 0, 255]);
+        assert_eq!(texel(2, 32), [0, 0, 0, 255]);
+        assert_eq!(texel(32, 0), [0, 0, 0, 0]);
+        assert_eq!(texel(32, 63), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn the_pixel_art_display_keeps_the_texels_sharp() {
+        let mut framework = headless_framework();
+        let mut renderer = Renderer::new(&mut framework);
+        let mut editor = ImageEditor::new(&mut framework, &[64.0, 64.0]);
+        let checker =
+            GrayImage::from_fn(2, 2, |x, y| Luma([if (x + y) % 2 == 0 { 0 } else { 255 }]));
+        assert!(editor.new_document_from_image(&DynamicImage::ImageLuma8(checker), &mut framework));
+        let viewport = vec2(64, 64);
+        let target = render_target(viewport.x, viewport.y, ColorSpace::Srgb, &mut framework);
+        let view = framework.texture2d_create_view(&target);
+
+        let mut render_reds = |editor: &mut ImageEditor, framework: &mut Framework| {
+            // Each document pixel covers 9.5 pixels of the view
+            editor.set_view_state(ViewState {
+                position: point2(0.0, 0.0),
+                scale: 1.0 / 9.5,
+                rotation: Rad(0.0),
+            });
+            editor.render_to(
+                &mut renderer,
+                &view,
+                TextureFormat::Rgba8UnormSrgb,
+                viewport,
+                framework,
+            );
+            texture_texels(&target, framework)
+                .iter()
+                .filter(|texel| texel.0[3] == 255)
+                .map(|texel| texel.0[0])
+                .collect::<Vec<u8>>()
+        };
+
+        assert_eq!(editor.canvas_interpolation(), Interpolation::Bilinear);
+        assert!(editor.canvas_interpolation().shader().is_none());
+        let bilinear = render_reds(&mut editor, &mut framework);
+        assert!(bilinear.iter().any(|r| *r > 0 && *r < 255));
+
+        editor.set_pixel_art_display(true);
+        assert_eq!(editor.canvas_interpolation(), Interpolation::Nearest);
+        assert!(editor.canvas_interpolation().shader().is_some());
+        let nearest = render_reds(&mut editor, &mut framework);
+        assert!(!nearest.is_empty());
+        assert!(nearest.iter().all(|r| *r == 0 || *r == 255));
+    }
+}
@@ -1,9 +1,9 @@
 use std::{collections::HashMap, ops::Div};
 
-use cgmath::{point2, ElementWise, Point2};
+use cgmath::{point2, vec2, ElementWise, Point2};
 use framework::{
-    framework::TextureId, Box2d, Framework, RgbaTexture2D, Texture, TextureConfiguration,
-    TextureUsage,
+    framework::TextureId, Box2d, ColorSpace, Framework, RgbaTexture2D, RgbaU8, Texture,
+    TextureConfiguration, TextureUsage,
 };
 
 #[derive(Debug)]
@@ -93,6 +93,25 @@ impl ChunkedLayer {
             bounds: Box2d::default(),
         }
     }
+    // The color of the pixel at the position, None where no chunk was allocated yet
+    pub fn sample(&self, pos: Point2<f32>, framework: &Framework) -> Option<wgpu::Color> {
+        let chunk_size = self.chunk_size as f32;
+        let chunk_index = point2(
+            (pos.x / chunk_size).round() as i64,
+            (pos.y / chunk_size).round() as i64,
+        );
+        let chunk = self.chunks.get(&chunk_index)?;
+        // The chunk rows go from the top down, the world y axis goes up
+        let local = pos - self.index_to_world_position(&chunk_index);
+        let x = (local.x + chunk_size * 0.5).floor().max(0.0) as u32;
+        let y = (chunk_size * 0.5 - local.y).floor().max(0.0) as u32;
+        Some(framework.texture2d_sample_pixel(
+            chunk,
+            x.min(self.chunk_size - 1),
+            y.min(self.chunk_size - 1),
+        ))
+    }
+
     pub fn chunk(&self, pos: Point2<f32>) -> Option<TextureId> {
         let chunk_index = pos.cast::<i64>().unwrap().div(self.chunk_size as i64);
         self.chunks.get(&chunk_index).cloned()
@@ -176,6 +195,7 @@ impl ChunkedLayer {
                 label: Some(format!("Chunked Layer '{:?}' texture {:?}", label, pos).as_str()),
                 usage: TextureUsage::RWRT,
                 mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
         );
         map.insert(pos, chunk_texture);
@@ -203,6 +223,126 @@ impl ChunkedLayer {
         }
     }
 
+    // Reads all the chunks into a single texture, returning it along with the index of the bottom left chunk.
+    // Since the y axis points up, the chunks with the highest y index end up in the first rows.
+    // Holes in the chunk map are left transparent
+    pub(crate) fn read_into_texture(
+        &self,
+        framework: &Framework,
+    ) -> Option<(Point2<i64>, RgbaTexture2D)> {
+        let (first_chunk, last_chunk) = self.chunk_range()?;
+        let size = self.chunk_size as usize;
+        let width = (last_chunk.x - first_chunk.x + 1) as usize * size;
+        let height = (last_chunk.y - first_chunk.y + 1) as usize * size;
+        let mut texels = vec![RgbaU8([0, 0, 0, 0]); width * height];
+        for (index, chunk) in self.chunks.iter() {
+            let chunk_texture = framework.texture2d_read_data(chunk).ok()?;
+            let chunk_texels = chunk_texture
+                .data()
+                .expect("ChunkedLayer: a chunk read from the GPU has no data");
+            let start_x = (index.x - first_chunk.x) as usize * size;
+            let start_y = (last_chunk.y - index.y) as usize * size;
+            for (row, chunk_row) in chunk_texels.chunks(size).enumerate() {
+                let start = (start_y + row) * width + start_x;
+                texels[start..start + size].copy_from_slice(chunk_row);
+            }
+        }
+        let texture = RgbaTexture2D::from_texels(texels, (width as u32, height as u32))
+            .expect("ChunkedLayer: failed to build texture");
+        Some((first_chunk, texture))
+    }
+
+    // The opposite of read_into_texture: only the existing chunks are written
+    pub(crate) fn write_from_texture(
+        &self,
+        first_chunk: Point2<i64>,
+        texture: &RgbaTexture2D,
+        framework: &Framework,
+    ) {
+        let size = self.chunk_size as usize;
+        let width = texture.width() as usize;
+        let last_chunk_y = first_chunk.y + (texture.height() as usize / size) as i64 - 1;
+        let texels = texture
+            .data()
+            .expect("ChunkedLayer: cannot write a texture without data");
+        for (index, chunk) in self.chunks.iter() {
+            let start_x = (index.x - first_chunk.x) as usize * size;
+            let start_y = (last_chunk_y - index.y) as usize * size;
+            let chunk_texels: Vec<RgbaU8> = (0..size)
+                .flat_map(|row| {
+                    let start = (start_y + row) * width + start_x;
+                    texels[start..start + size].iter().copied()
+                })
+                .collect();
+            framework.texture2d_write_data(chunk, &chunk_texels);
+        }
+    }
+
+    // Like write_from_texture, but the chunks are written through edit(), so that the write can be
+    // undone with the returned diff. The chunks missing from the map are allocated
+    pub(crate) fn edit_from_texture(
+        &mut self,
+        first_chunk: Point2<i64>,
+        texture: &RgbaTexture2D,
+        framework: &mut Framework,
+    ) -> ChunkDiff {
+        let size = self.chunk_size as usize;
+        let width = texture.width() as usize;
+        let last_chunk = point2(
+            first_chunk.x + (width / size) as i64 - 1,
+            first_chunk.y + (texture.height() as usize / size) as i64 - 1,
+        );
+        let texels = texture
+            .data()
+            .expect("ChunkedLayer: cannot write a texture without data");
+        // Shrink the bounds by a pixel, or edit() would also pick the chunks around the texture
+        let bounds = Box2d {
+            center: point2(
+                (first_chunk.x + last_chunk.x) as f32 * 0.5,
+                (first_chunk.y + last_chunk.y) as f32 * 0.5,
+            ) * self.chunk_size as f32,
+            extents: vec2(
+                texture.width() as f32 * 0.5 - 1.0,
+                texture.height() as f32 * 0.5 - 1.0,
+            ),
+        };
+        self.edit(
+            bounds,
+            |chunk, index, _, framework| {
+                let start_x = (index.x - first_chunk.x) as usize * size;
+                let start_y = (last_chunk.y - index.y) as usize * size;
+                let chunk_texels: Vec<RgbaU8> = (0..size)
+                    .flat_map(|row| {
+                        let start = (start_y + row) * width + start_x;
+                        texels[start..start + size].iter().copied()
+                    })
+                    .collect();
+                framework.texture2d_write_data(chunk, &chunk_texels);
+            },
+            framework,
+        )
+    }
+
+    // The indices of the bottom left and top right chunks, None when there are no chunks
+    pub(crate) fn chunk_range(&self) -> Option<(Point2<i64>, Point2<i64>)> {
+        let first_chunk = point2(
+            self.chunks.keys().map(|i| i.x).min()?,
+            self.chunks.keys().map(|i| i.y).min()?,
+        );
+        let last_chunk = point2(
+            self.chunks.keys().map(|i| i.x).max()?,
+            self.chunks.keys().map(|i| i.y).max()?,
+        );
+        Some((first_chunk, last_chunk))
+    }
+
+    // The chunks are freed once no other id references them, see Layer::free_textures
+    pub fn free_chunks(self, framework: &mut Framework) {
+        for (_, chunk) in self.chunks {
+            framework.free_texture2d(chunk);
+        }
+    }
+
     pub fn chunk_size(&self) -> u32 {
         self.chunk_size
     }
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use framework::{
+    framework::TextureId, linear_to_srgb, renderer::renderer::Renderer, srgb_to_linear, Framework,
+    RgbaTexture2D, RgbaU8, Texture,
+};
+
+use crate::{blend_settings::BlendMode, document::DocumentCreationInfo};
+
+use super::{CanvasRenderingStrategy, Layer, LayerId, LayerItem, LayerRenderingStrategy};
+
+// The layers are still drawn on their canvases by the GPU, but they're blended on the CPU:
+// slower than CanvasRenderingStrategy, but it doesn't need the compositing shader to work
+pub struct CpuBlittingStrategy {
+    canvases: CanvasRenderingStrategy,
+}
+
+impl LayerRenderingStrategy<Layer> for CpuBlittingStrategy {
+    fn new(framework: &mut Framework, document_info: &DocumentCreationInfo) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            canvases: CanvasRenderingStrategy::new(framework, document_info),
+        }
+    }
+
+    fn on_new_layer(&mut self, layer: &Layer, framework: &mut Framework) {
+        self.canvases.on_new_layer(layer, framework);
+    }
+
+    fn on_layer_removed(&mut self, layer: &Layer, framework: &mut Framework) {
+        self.canvases.on_layer_removed(layer, framework);
+    }
+
+    fn set_linear_blending(&mut self, linear: bool) {
+        self.canvases.set_linear_blending(linear);
+    }
+
+    fn update(&mut self, layers: &HashMap<LayerId, Layer>, framework: &mut Framework) {
+        self.canvases.update(layers, framework);
+    }
+
+    fn update_canvases(
+        &mut self,
+        items: &Vec<LayerItem>,
+        layers: &HashMap<LayerId, Layer>,
+        framework: &mut Framework,
+        renderer: &mut Renderer,
+    ) {
+        self.canvases
+            .update_canvases(items, layers, framework, renderer);
+    }
+
+    fn canvas(&self, layer: &LayerId) -> &TextureId {
+        self.canvases.canvas(layer)
+    }
+
+    fn render_layer_tile(
+        &self,
+        layer: &Layer,
+        tile: (u32, u32, u32, u32),
+        target: &TextureId,
+        framework: &mut Framework,
+        renderer: &mut Renderer,
+    ) {
+        self.canvases
+            .render_layer_tile(layer, tile, target, framework, renderer);
+    }
+
+    fn composite_layer_on_target(
+        &self,
+        layer: &Layer,
+        back: &TextureId,
+        resulting_texture: &TextureId,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) {
+        let canvas = self.canvas(layer.id()).clone();
+        self.composite_texture_on_target(
+            layer,
+            &canvas,
+            back,
+            resulting_texture,
+            renderer,
+            framework,
+        );
+    }
+
+    fn composite_texture_on_target(
+        &self,
+        layer: &Layer,
+        texture: &TextureId,
+        back: &TextureId,
+        resulting_texture: &TextureId,
+        _renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) {
+        let back = framework
+            .texture2d_read_data(back)
+            .expect("CpuBlittingStrategy: failed to read the back texture");
+        let layer_texels = framework
+            .texture2d_read_data(texture)
+            .expect("CpuBlittingStrategy: failed to read the layer");
+        let settings = layer.settings();
+        let blended = blend_textures(
+            &back,
+            &layer_texels,
+            settings.blend_mode,
+            settings.opacity,
+            self.canvases.linear_blending(),
+        );
+        framework.texture2d_write_data(resulting_texture, blended.data().unwrap());
+    }
+}
+
+// Blends the layer over the back texture without touching the GPU, the textures must have
+// the same size: the empty ones are transparent
+fn blend_textures(
+    back: &RgbaTexture2D,
+    layer: &RgbaTexture2D,
+    mode: BlendMode,
+    opacity: f32,
+    linear: bool,
+) -> RgbaTexture2D {
+    let size = (back.width(), back.height());
+    let transparent = vec![RgbaU8([0; 4]); (size.0 * size.1) as usize];
+    let texels = back
+        .data()
+        .unwrap_or(&transparent)
+        .iter()
+        .zip(layer.data().unwrap_or(&transparent).iter())
+        .map(|(back, top)| blend_texels(mode, opacity, linear, back, top))
+        .collect();
+    RgbaTexture2D::from_texels(texels, size)
+        .expect("CpuBlittingStrategy: the layer is smaller than the back texture")
+}
+
+// Same as layer_fragment.wgsl: the back texel holds the layers below the blended one
+fn blend_texels(
+    mode: BlendMode,
+    opacity: f32,
+    linear: bool,
+    back: &RgbaU8,
+    top: &RgbaU8,
+) -> RgbaU8 {
+    let decode = |value: u8| {
+        if linear {
+            srgb_to_linear(value) as f32
+        } else {
+            value as f32 / 255.0
+        }
+    };
+    let encode = |value: f32| {
+        if linear {
+            linear_to_srgb(value as f64)
+        } else {
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        }
+    };
+    let alpha_top = top.0[3] as f32 / 255.0 * opacity;
+    let alpha_bottom = back.0[3] as f32 / 255.0;
+    let alpha = alpha_top + alpha_bottom * (1.0 - alpha_top);
+    if alpha <= 0.0 {
+        return RgbaU8([0; 4]);
+    }
+    let mut result = [0; 4];
+    for (c, channel) in result.iter_mut().take(3).enumerate() {
+        let top_color = decode(top.0[c]);
+        let bottom_color = decode(back.0[c]);
+        let blended = mode.blend(bottom_color, top_color);
+        let color = (blended * alpha_top + bottom_color * alpha_bottom * (1.0 - alpha_top)) / alpha;
+        *channel = if color.is_nan() { 0 } else { encode(color) };
+    }
+    result[3] = (alpha * 255.0).round() as u8;
+    RgbaU8(result)
+}
+
+#[cfg(test)]
+mod test {
+    use framework::{RgbaTexture2D, RgbaU8, Texture};
+
+    use crate::blend_settings::BlendMode;
+
+    use super::{blend_texels, blend_textures};
+
+    #[test]
+    fn blending_follows_the_layer_shader() {
+        let red = RgbaU8([255, 0, 0, 255]);
+        let blue = RgbaU8([0, 0, 255, 255]);
+        let transparent = RgbaU8([0; 4]);
+        // An opaque layer hides the back texel
+        assert_eq!(
+            blend_texels(BlendMode::Normal, 1.0, true, &blue, &red).0,
+            red.0
+        );
+        // The layer is faded by its opacity over a transparent back texel
+        assert_eq!(
+            blend_texels(BlendMode::Normal, 0.5, true, &transparent, &blue).0,
+            [0, 0, 255, 128]
+        );
+        assert_eq!(
+            blend_texels(BlendMode::Multiply, 1.0, true, &red, &blue).0,
+            [0, 0, 0, 255]
+        );
+        assert_eq!(
+            blend_texels(BlendMode::Screen, 1.0, true, &red, &blue).0,
+            [255, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn half_transparent_white_over_black() {
+        let white = RgbaU8([255, 255, 255, 128]);
+        let black = RgbaU8([0, 0, 0, 255]);
+        // Half the light of white is 188 once it's encoded in sRGB
+        let linear = blend_texels(BlendMode::Normal, 1.0, true, &black, &white);
+        assert_eq!(linear.0, [188, 188, 188, 255]);
+        // Blending the stored values gives the darker middle of the 0..255 range
+        let naive = blend_texels(BlendMode::Normal, 1.0, false, &black, &white);
+        assert_eq!(naive.0, [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn textures_are_blended_without_a_gpu() {
+        let black = RgbaU8([0, 0, 0, 255]);
+        let white = RgbaU8([255, 255, 255, 255]);
+        let back = RgbaTexture2D::from_repeated_texel(black, (2, 1)).unwrap();
+        let layer = RgbaTexture2D::from_texels(vec![white, RgbaU8([0; 4])], (2, 1)).unwrap();
+        let blended = blend_textures(&back, &layer, BlendMode::Normal, 0.5, false);
+        assert_eq!((blended.width(), blended.height()), (2, 1));
+        let texels: Vec<[u8; 4]> = blended.data().unwrap().iter().map(|t| t.0).collect();
+        assert_eq!(texels, vec![[128, 128, 128, 255], black.0]);
+
+        // The texels of an empty texture are transparent
+        let empty = RgbaTexture2D::empty((2, 1));
+        let blended = blend_textures(&empty, &layer, BlendMode::Normal, 1.0, true);
+        let texels: Vec<[u8; 4]> = blended.data().unwrap().iter().map(|t| t.0).collect();
+        assert_eq!(texels, vec![white.0, [0; 4]]);
+    }
+}
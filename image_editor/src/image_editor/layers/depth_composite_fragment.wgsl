@@ -0,0 +1,29 @@
+//@include :common_definitions
+//@include :2d_definitions
+
+struct LayerDepth {
+    depth: f32,
+}
+
+@group(2) @binding(0) var diffuse: texture_2d<f32>;
+@group(2) @binding(1) var s_diffuse: sampler;
+
+@group(3) @binding(0) var<uniform> layer_depth: LayerDepth;
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+@fragment
+fn fragment(in: FragmentInput) -> FragmentOutput {
+    let color = textureSample(diffuse, s_diffuse, in.tex_uv);
+    // The transparent pixels must not hide the layers below
+    if (color.a == 0.0) {
+        discard;
+    }
+    var out: FragmentOutput;
+    out.color = color;
+    out.depth = layer_depth.depth;
+    return out;
+}
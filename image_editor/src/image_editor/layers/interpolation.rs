@@ -0,0 +1,142 @@
+use framework::framework::ShaderId;
+
+// How the layer pixels are sampled when the layer is scaled or rotated
+#[derive(
+    Clone, Copy, Debug, Default, strum_macros::EnumIter, strum_macros::Display, Eq, PartialEq,
+)]
+pub enum Interpolation {
+    // Keeps the hard edges, e.g. for pixel art
+    Nearest = 0,
+    #[default]
+    Bilinear = 1,
+    Bicubic = 2,
+}
+
+impl From<usize> for Interpolation {
+    fn from(n: usize) -> Self {
+        match n {
+            0 => Self::Nearest,
+            1 => Self::Bilinear,
+            2 => Self::Bicubic,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl From<Interpolation> for usize {
+    fn from(v: Interpolation) -> Self {
+        v as usize
+    }
+}
+
+impl Interpolation {
+    // None when the default shader can be used, since the texture samplers are bilinear
+    pub(crate) fn shader(&self) -> Option<ShaderId> {
+        match self {
+            Interpolation::Nearest => Some(crate::global_selection_data().nearest_shader.clone()),
+            Interpolation::Bilinear => None,
+            Interpolation::Bicubic => Some(crate::global_selection_data().bicubic_shader.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::vec2;
+    use framework::{
+        renderer::{
+            draw_command::{DrawCommand, DrawMode, OptionalDrawData, PrimitiveType},
+            renderer::Renderer,
+        },
+        Camera2d, ColorSpace, Framework, RgbaTexture2D, RgbaU8, Texture, TextureConfiguration,
+        TextureUsage, Transform2d,
+    };
+
+    use strum::IntoEnumIterator;
+
+    use super::Interpolation;
+    use crate::{
+        headless_framework,
+        test_support::{render_target, texture_texels},
+    };
+
+    #[test]
+    fn interpolations_round_trip_through_the_dropdown_index() {
+        for (index, interpolation) in Interpolation::iter().enumerate() {
+            assert_eq!(usize::from(interpolation), index);
+            assert_eq!(Interpolation::from(index), interpolation);
+        }
+        assert_eq!(Interpolation::default(), Interpolation::Bilinear);
+    }
+
+    // Draws a 2x2 black and white checker scaled up 4x
+    fn scale_checker(interpolation: Interpolation, framework: &mut Framework) -> Vec<RgbaU8> {
+        let black = RgbaU8([0, 0, 0, 255]);
+        let white = RgbaU8([255, 255, 255, 255]);
+        let checker = framework.allocate_texture2d(
+            RgbaTexture2D::from_texels(vec![black, white, white, black], (2, 2)).unwrap(),
+            TextureConfiguration {
+                label: Some("Checker"),
+                usage: TextureUsage::WRITE_ONLY,
+                mip_count: None,
+                color_space: ColorSpace::Srgb,
+            },
+        );
+        let target = render_target(8, 8, ColorSpace::Srgb, framework);
+        let mut renderer = Renderer::new(framework);
+        renderer.begin(
+            &Camera2d::wh(8, 8),
+            Some(wgpu::Color::TRANSPARENT),
+            framework,
+        );
+        renderer.draw(DrawCommand {
+            primitives: PrimitiveType::Texture2D {
+                texture_id: checker,
+                instances: vec![Transform2d {
+                    scale: vec2(4.0, 4.0),
+                    ..Default::default()
+                }],
+                flip_uv_y: true,
+                multiply_color: wgpu::Color::WHITE,
+            },
+            draw_mode: DrawMode::Single,
+            additional_data: OptionalDrawData::just_shader(interpolation.shader()),
+        });
+        renderer.end(&target, None, framework);
+        texture_texels(&target, framework)
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn scaled_checkers_keep_hard_edges_only_with_nearest() {
+        let mut framework = headless_framework();
+
+        // The checker might be drawn upside down, only its cells matter
+        let nearest = scale_checker(Interpolation::Nearest, &mut framework);
+        let (black, white) = ([0, 0, 0, 255], [255, 255, 255, 255]);
+        let first_cell = if nearest[0].0 == black { black } else { white };
+        let other_cell = if first_cell == black { white } else { black };
+        for (i, texel) in nearest.iter().enumerate() {
+            let in_first_cell = (i % 8 < 4) == (i / 8 < 4);
+            let expected = if in_first_cell {
+                first_cell
+            } else {
+                other_cell
+            };
+            assert_eq!(texel.0, expected, "texel {i}");
+        }
+
+        // Next to the edges between the checker cells the colors are mixed,
+        // while the corners are far enough from them to keep the original color
+        let bilinear = scale_checker(Interpolation::Bilinear, &mut framework);
+        for row in 0..8 {
+            for column in [3, 4] {
+                let [r, _, _, a] = bilinear[row * 8 + column].0;
+                assert!(r > 0 && r < 255, "texel ({column}, {row}) is {r}");
+                assert_eq!(a, 255);
+            }
+        }
+        assert_eq!(bilinear[0].0, first_cell);
+        assert_eq!(bilinear[7].0, other_cell);
+    }
+}
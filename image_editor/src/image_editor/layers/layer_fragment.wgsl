@@ -1,16 +1,20 @@
 //@include :common_definitions
 //@include :2d_definitions
-//@include :blend_modes
+#import blend_modes
 
 struct BlendSettings {
     blend_mode: i32,
+    opacity: f32,
+    // When 0 the colors are blended as they're stored, without removing the sRGB curve
+    linear: i32,
 }
 
-@group(2) @binding(0) var top: texture_2d<f32>;
-@group(2) @binding(1) var s_top: sampler;
+// The layers below the blended one
+@group(2) @binding(0) var bottom: texture_2d<f32>;
+@group(2) @binding(1) var s_bottom: sampler;
 
-@group(3) @binding(0) var bottom: texture_2d<f32>;
-@group(3) @binding(1) var s_bottom: sampler;
+@group(3) @binding(0) var top: texture_2d<f32>;
+@group(3) @binding(1) var s_top: sampler;
 
 @group(4) @binding(0) var<uniform> blend_settings: BlendSettings;
 
@@ -21,13 +25,35 @@ fn over(a: vec3<f32>, b: vec3<f32>, alpha_a: f32, alpha_b: f32) -> vec4<f32> {
     return vec4<f32>(color.r, color.g, color.b, a_o);
 }
 
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
 @fragment
 fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // The textures are sRGB, so the samples are already linear
     let top_sample = textureSample(top, s_top, in.tex_uv);
     let bottom_sample = textureSample(bottom, s_bottom, in.tex_uv);
 
-    let top_rgb = vec3<f32>(top_sample.r, top_sample.g, top_sample.b);
-    let bottom_rgb = vec3<f32>(bottom_sample.r, bottom_sample.g, bottom_sample.b);
+    var top_rgb = vec3<f32>(top_sample.r, top_sample.g, top_sample.b);
+    var bottom_rgb = vec3<f32>(bottom_sample.r, bottom_sample.g, bottom_sample.b);
+    if (blend_settings.linear == 0) {
+        top_rgb = linear_to_srgb(top_rgb);
+        bottom_rgb = linear_to_srgb(bottom_rgb);
+    }
     let blend = select_blend_mode(blend_settings.blend_mode, bottom_rgb, top_rgb);
-    return over(blend, bottom_rgb, top_sample.a, bottom_sample.a);
+    let result = over(blend, bottom_rgb, top_sample.a * blend_settings.opacity, bottom_sample.a);
+    if (blend_settings.linear == 0) {
+        let rgb = srgb_to_linear(vec3<f32>(result.r, result.g, result.b));
+        return vec4<f32>(rgb.r, rgb.g, rgb.b, result.a);
+    }
+    return result;
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use cgmath::{point3, Transform, Vector2};
+use cgmath::{point2, point3, vec2, Transform, Vector2};
 use framework::{
     framework::{BufferId, TextureId},
     renderer::{
@@ -12,8 +12,8 @@ use framework::{
         },
         renderer::Renderer,
     },
-    BufferConfiguration, Camera2d, Framework, RgbaTexture2D, Texture, TextureConfiguration,
-    TextureUsage, Transform2d,
+    Box2d, BufferConfiguration, Camera2d, ColorSpace, Framework, RgbaTexture2D, Texture,
+    TextureConfiguration, TextureUsage, Transform2d,
 };
 
 use crate::{
@@ -23,18 +23,53 @@ use crate::{
 
 use super::{Layer, LayerBase, LayerId};
 
-#[derive(Clone, PartialEq, PartialOrd, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Hash)]
 pub enum LayerItem {
     SingleLayer(LayerId),
     Group(Vec<LayerItem>, LayerId),
 }
 
+impl LayerItem {
+    pub fn id(&self) -> &LayerId {
+        match self {
+            LayerItem::SingleLayer(id) | LayerItem::Group(_, id) => id,
+        }
+    }
+
+    // The ids of this item and of all its children
+    pub fn ids(&self) -> Vec<LayerId> {
+        match self {
+            LayerItem::SingleLayer(id) => vec![id.clone()],
+            LayerItem::Group(children, id) => std::iter::once(id.clone())
+                .chain(children.iter().flat_map(|child| child.ids()))
+                .collect(),
+        }
+    }
+}
+
+// Where an item is placed in the tree: parent is None when the item is in the root list
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayerPosition {
+    pub parent: Option<LayerId>,
+    pub index: usize,
+}
+
+// Which LayerRenderingStrategy a document uses
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum RenderingStrategyKind {
+    #[default]
+    Canvas,
+    CpuBlitting,
+}
+
 pub(crate) trait LayerRenderingStrategy<L: LayerBase> {
     fn new(framework: &mut Framework, document_info: &DocumentCreationInfo) -> Self
     where
         Self: Sized;
     fn on_new_layer(&mut self, layer: &L, framework: &mut Framework);
-    fn on_layer_removed(&mut self, layer: &L);
+    fn on_layer_removed(&mut self, layer: &L, framework: &mut Framework);
+    // Used by the layers whose settings are updated from now on
+    fn set_linear_blending(&mut self, linear: bool);
     fn update(&mut self, layers: &HashMap<LayerId, L>, framework: &mut Framework);
     fn update_canvases(
         &mut self,
@@ -44,6 +79,20 @@ pub(crate) trait LayerRenderingStrategy<L: LayerBase> {
         renderer: &mut Renderer,
     );
 
+    // The texture the layer is drawn on by update_canvases
+    fn canvas(&self, layer: &LayerId) -> &TextureId;
+
+    // Draws the (x, y, width, height) part of the layer's canvas on the target, without
+    // touching the canvas: used to composite documents too big for a single texture
+    fn render_layer_tile(
+        &self,
+        layer: &L,
+        tile: (u32, u32, u32, u32),
+        target: &TextureId,
+        framework: &mut Framework,
+        renderer: &mut Renderer,
+    );
+
     fn composite_layer_on_target(
         &self,
         id: &L,
@@ -52,12 +101,26 @@ pub(crate) trait LayerRenderingStrategy<L: LayerBase> {
         renderer: &mut Renderer,
         framework: &mut Framework,
     );
+
+    // Like composite_layer_on_target, but the layer's contents are replaced by the texture:
+    // used by the groups, whose contents are composited from their children
+    fn composite_texture_on_target(
+        &self,
+        layer: &L,
+        texture: &TextureId,
+        back: &TextureId,
+        resulting_texture: &TextureId,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    );
 }
 
 pub struct LayerTree<L: LayerBase> {
     pub(crate) layers: HashMap<LayerId, L>,
     pub(crate) items: Vec<LayerItem>,
     pub(crate) current_layer_id: Option<LayerId>,
+    // Always contains the current layer, if there's one
+    pub(crate) selected_layers: HashSet<LayerId>,
 }
 
 impl<L: LayerBase> LayerTree<L> {
@@ -66,6 +129,7 @@ impl<L: LayerBase> LayerTree<L> {
             layers: HashMap::new(),
             items: Vec::new(),
             current_layer_id: None,
+            selected_layers: HashSet::new(),
         }
     }
 
@@ -82,6 +146,61 @@ impl<L: LayerBase> LayerTree<L> {
     pub fn select_layer(&mut self, id: LayerId) {
         assert!(self.layers.contains_key(&id));
         self.current_layer_id = Some(id);
+        self.selected_layers = HashSet::from([id]);
+    }
+
+    pub fn selected_layers(&self) -> &HashSet<LayerId> {
+        &self.selected_layers
+    }
+
+    // Adds the layer to the selection, or removes it if it was already selected.
+    // The last selected layer can't be deselected
+    pub fn toggle_layer_selection(&mut self, id: LayerId) {
+        assert!(self.layers.contains_key(&id));
+        if !self.selected_layers.contains(&id) {
+            self.selected_layers.insert(id);
+            self.current_layer_id = Some(id);
+        } else if self.selected_layers.len() > 1 {
+            self.selected_layers.remove(&id);
+            if self.current_layer_id == Some(id) {
+                self.current_layer_id = self.first_selected_layer();
+            }
+        }
+    }
+
+    // Selects all the layers between the current layer and the given one, as shown in the layer list
+    pub fn select_layer_range(&mut self, id: LayerId) {
+        assert!(self.layers.contains_key(&id));
+        let anchor = match self.current_layer_id {
+            Some(anchor) => anchor,
+            None => return self.select_layer(id),
+        };
+        let mut in_range = false;
+        let mut range = vec![];
+        for layer_id in self.layer_ids() {
+            let is_edge = layer_id == anchor || layer_id == id;
+            if is_edge || in_range {
+                range.push(layer_id);
+            }
+            if is_edge && (anchor != id) {
+                in_range = !in_range;
+            }
+        }
+        self.selected_layers.extend(range);
+        self.current_layer_id = Some(id);
+    }
+
+    fn first_selected_layer(&self) -> Option<LayerId> {
+        self.layer_ids()
+            .into_iter()
+            .find(|id| self.selected_layers.contains(id))
+    }
+
+    // All the layer ids, in the same order as for_each_layer
+    fn layer_ids(&self) -> Vec<LayerId> {
+        let mut ids = vec![];
+        self.for_each_layer(|l| ids.push(l.id().clone()));
+        ids
     }
 
     fn add_layer_impl(
@@ -114,9 +233,111 @@ impl<L: LayerBase> LayerTree<L> {
             self.items.push(LayerItem::SingleLayer(layer.id().clone()));
         }
         self.current_layer_id = Some(layer.id().clone());
+        self.selected_layers = HashSet::from([layer.id().clone()]);
         self.layers.insert(layer.id().clone(), layer);
     }
 
+    pub(crate) fn find_item(&self, id: &LayerId) -> Option<&LayerItem> {
+        fn find_item_impl<'a>(id: &LayerId, items: &'a Vec<LayerItem>) -> Option<&'a LayerItem> {
+            for item in items {
+                match item {
+                    LayerItem::SingleLayer(layer_id) if layer_id == id => return Some(item),
+                    LayerItem::Group(_, group_id) if group_id == id => return Some(item),
+                    LayerItem::Group(children, _) => {
+                        if let Some(item) = find_item_impl(id, children) {
+                            return Some(item);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            None
+        }
+        find_item_impl(id, &self.items)
+    }
+
+    fn take_items_impl(
+        ids: &HashSet<LayerId>,
+        items: &mut Vec<LayerItem>,
+        taken: &mut Vec<LayerItem>,
+        first_place: &mut Option<usize>,
+        is_root: bool,
+    ) {
+        let mut idx = 0;
+        while idx < items.len() {
+            let item_id = match &items[idx] {
+                LayerItem::SingleLayer(id) | LayerItem::Group(_, id) => id.clone(),
+            };
+            if ids.contains(&item_id) {
+                if is_root && first_place.is_none() {
+                    *first_place = Some(idx);
+                }
+                taken.push(items.remove(idx));
+                continue;
+            }
+            if let LayerItem::Group(children, ..) = &mut items[idx] {
+                let taken_before = taken.len();
+                Self::take_items_impl(ids, children, taken, first_place, false);
+                if is_root && first_place.is_none() && taken.len() > taken_before {
+                    *first_place = Some(idx);
+                }
+            }
+            idx += 1;
+        }
+    }
+
+    // Moves the given layers into a new group, placed where the topmost of them was.
+    // The layers keep their relative order
+    pub fn group_layers(&mut self, ids: &HashSet<LayerId>, group: L) {
+        let mut taken = vec![];
+        let mut first_place = None;
+        Self::take_items_impl(ids, &mut self.items, &mut taken, &mut first_place, true);
+        let group_id = group.id().clone();
+        let place = first_place.unwrap_or(self.items.len());
+        self.items
+            .insert(place, LayerItem::Group(taken, group_id.clone()));
+        self.layers.insert(group_id, group);
+    }
+
+    fn ungroup_impl(group_id: &LayerId, items: &mut Vec<LayerItem>) -> Option<Vec<LayerItem>> {
+        let place = items
+            .iter()
+            .position(|item| matches!(item, LayerItem::Group(_, id) if id == group_id));
+        if let Some(idx) = place {
+            let children = match items.remove(idx) {
+                LayerItem::Group(children, ..) => children,
+                LayerItem::SingleLayer(..) => unreachable!(),
+            };
+            items.splice(idx..idx, children.iter().cloned());
+            return Some(children);
+        }
+        items.iter_mut().find_map(|item| match item {
+            LayerItem::Group(children, ..) => Self::ungroup_impl(group_id, children),
+            LayerItem::SingleLayer(..) => None,
+        })
+    }
+
+    // Moves the children of the group into the group's parent, then removes the group
+    pub fn ungroup(&mut self, group_id: &LayerId) -> L {
+        let children =
+            Self::ungroup_impl(group_id, &mut self.items).expect("LayerTree: group not found");
+        let child_ids = children.iter().map(|item| match item {
+            LayerItem::SingleLayer(id) | LayerItem::Group(_, id) => id.clone(),
+        });
+        if self.selected_layers.remove(group_id) {
+            self.selected_layers.extend(child_ids.clone());
+        }
+        if self.current_layer_id.as_ref() == Some(group_id) {
+            self.current_layer_id = child_ids
+                .clone()
+                .next()
+                .or_else(|| self.first_selected_layer());
+        }
+        self.layers
+            .remove(group_id)
+            .expect("LayerTree: layer not found")
+    }
+
     pub fn get_layer(&self, layer_index: &LayerId) -> &L {
         self.layers.get(layer_index).unwrap()
     }
@@ -194,16 +415,146 @@ impl<L: LayerBase> LayerTree<L> {
                     }
                 }
                 LayerItem::Group(children, id) => {
-                    todo!()
+                    // The children must have been removed by the caller
+                    if id == layer_to_remove_id {
+                        existing_layers.remove(layer_idx);
+                        return;
+                    }
+                    Self::remove_layer_impl(layer_to_remove_id, children);
+                }
+            }
+        }
+    }
+    fn position_of_impl(
+        id: &LayerId,
+        items: &Vec<LayerItem>,
+        parent: Option<LayerId>,
+    ) -> Option<LayerPosition> {
+        for (index, item) in items.iter().enumerate() {
+            if item.id() == id {
+                return Some(LayerPosition { parent, index });
+            }
+            if let LayerItem::Group(children, group_id) = item {
+                if let Some(position) = Self::position_of_impl(id, children, Some(*group_id)) {
+                    return Some(position);
+                }
+            }
+        }
+        None
+    }
+    pub fn position_of(&self, id: &LayerId) -> Option<LayerPosition> {
+        Self::position_of_impl(id, &self.items, None)
+    }
+
+    fn group_children_mut<'a>(
+        group_id: &LayerId,
+        items: &'a mut Vec<LayerItem>,
+    ) -> Option<&'a mut Vec<LayerItem>> {
+        for item in items.iter_mut() {
+            if let LayerItem::Group(children, id) = item {
+                if id == group_id {
+                    return Some(children);
+                }
+                if let Some(children) = Self::group_children_mut(group_id, children) {
+                    return Some(children);
                 }
             }
         }
+        None
+    }
+    fn siblings_mut(&mut self, parent: &Option<LayerId>) -> &mut Vec<LayerItem> {
+        match parent {
+            Some(parent) => Self::group_children_mut(parent, &mut self.items)
+                .expect("LayerTree: parent group not found"),
+            None => &mut self.items,
+        }
     }
+
+    // The number of items in the parent group, or in the root list if parent is None
+    pub fn children_count(&self, parent: &Option<LayerId>) -> usize {
+        match parent {
+            Some(parent) => match self.find_item(parent) {
+                Some(LayerItem::Group(children, ..)) => children.len(),
+                _ => 0,
+            },
+            None => self.items.len(),
+        }
+    }
+
+    // Removes the item (and its children) from the tree, without dropping the layers.
+    // The current layer is moved to a neighbour if it was removed
+    pub(crate) fn detach_item(&mut self, id: &LayerId) -> Option<(LayerItem, LayerPosition)> {
+        let position = self.position_of(id)?;
+        let siblings = self.siblings_mut(&position.parent);
+        let item = siblings.remove(position.index);
+        let neighbour = siblings
+            .get(position.index)
+            .or_else(|| siblings.get(position.index.wrapping_sub(1)))
+            .map(|item| item.id().clone())
+            .or(position.parent);
+
+        let removed_ids = item.ids();
+        self.selected_layers.retain(|id| !removed_ids.contains(id));
+        if self
+            .current_layer_id
+            .map_or(false, |id| removed_ids.contains(&id))
+        {
+            self.current_layer_id = self
+                .first_selected_layer()
+                .or(neighbour)
+                .or_else(|| self.items.first().map(|item| item.id().clone()));
+            if let Some(new_current) = self.current_layer_id {
+                self.selected_layers.insert(new_current);
+            }
+        }
+        Some((item, position))
+    }
+
+    pub(crate) fn attach_item(&mut self, item: LayerItem, position: LayerPosition) {
+        let siblings = self.siblings_mut(&position.parent);
+        let index = position.index.min(siblings.len());
+        siblings.insert(index, item);
+    }
+
+    // Moves the item so that it ends up in the given position, the index is the one
+    // in the parent once the item has been moved
+    pub fn move_item(&mut self, id: &LayerId, new_position: LayerPosition) {
+        let position = self
+            .position_of(id)
+            .expect("LayerTree: item to move not found");
+        let item = self.siblings_mut(&position.parent).remove(position.index);
+        self.attach_item(item, new_position);
+    }
+
+    // Moves several items at once, e.g. back to where they were before being grouped: they're
+    // all taken out of the tree before being put in their positions, from the topmost one
+    pub fn move_items(&mut self, moves: &[(LayerId, LayerPosition)]) {
+        let items: Vec<LayerItem> = moves
+            .iter()
+            .map(|(id, _)| {
+                let position = self
+                    .position_of(id)
+                    .expect("LayerTree: item to move not found");
+                self.siblings_mut(&position.parent).remove(position.index)
+            })
+            .collect();
+        for (item, (_, position)) in items.into_iter().zip(moves) {
+            self.attach_item(item, *position);
+        }
+    }
+
     pub fn remove_layer(&mut self, layer_id: LayerId) -> L {
-        Self::remove_layer_impl(&layer_id, &mut self.items);
+        self.selected_layers.remove(&layer_id);
         if self.current_layer_id.map_or(false, |id| id == layer_id) {
-            self.current_layer_id = self.find_below(&layer_id);
+            self.current_layer_id = self
+                .first_selected_layer()
+                .or_else(|| self.find_below(&layer_id))
+                .or_else(|| self.find_above(&layer_id));
+            if let Some(new_current) = self.current_layer_id {
+                self.selected_layers.insert(new_current);
+            }
         }
+        Self::remove_layer_impl(&layer_id, &mut self.items);
         self.layers
             .remove(&layer_id)
             .expect("LayerTree: layer not found")
@@ -246,6 +597,7 @@ pub struct CanvasRenderingStrategy {
     layer_datas: HashMap<LayerId, LayerCanvasData>,
     document_width: u32,
     document_height: u32,
+    linear_blending: bool,
 }
 
 impl LayerRenderingStrategy<Layer> for CanvasRenderingStrategy {
@@ -257,6 +609,7 @@ impl LayerRenderingStrategy<Layer> for CanvasRenderingStrategy {
             layer_datas: HashMap::new(),
             document_height: document_info.height,
             document_width: document_info.width,
+            linear_blending: true,
         }
     }
 
@@ -267,6 +620,7 @@ impl LayerRenderingStrategy<Layer> for CanvasRenderingStrategy {
                 label: Some(format!("Canvas texture for layer {:?}", layer.id()).as_str()),
                 usage: TextureUsage::RWRT,
                 mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
         );
         let settings_buffer =
@@ -287,14 +641,21 @@ impl LayerRenderingStrategy<Layer> for CanvasRenderingStrategy {
         );
     }
 
-    fn on_layer_removed(&mut self, layer: &Layer) {
-        self.layer_datas
+    fn on_layer_removed(&mut self, layer: &Layer, framework: &mut Framework) {
+        let data = self
+            .layer_datas
             .remove(&layer.id())
             .expect("CanvasRenderingStrategy: layer not found");
+        framework.free_texture2d(data.canvas);
+        framework.free_buffer(data.settings_buffer);
+    }
+
+    fn set_linear_blending(&mut self, linear: bool) {
+        self.linear_blending = linear;
     }
 
     fn update(&mut self, layers: &HashMap<LayerId, Layer>, framework: &mut Framework) {
-        Self::update_impl(layers, &self.layer_datas, framework)
+        Self::update_impl(layers, &self.layer_datas, self.linear_blending, framework)
     }
 
     fn update_canvases(
@@ -314,6 +675,30 @@ impl LayerRenderingStrategy<Layer> for CanvasRenderingStrategy {
             renderer,
         );
     }
+    fn canvas(&self, layer: &LayerId) -> &TextureId {
+        &self.layer_data(layer).canvas
+    }
+
+    fn render_layer_tile(
+        &self,
+        layer: &Layer,
+        tile: (u32, u32, u32, u32),
+        target: &TextureId,
+        framework: &mut Framework,
+        renderer: &mut Renderer,
+    ) {
+        Self::render_layer(
+            self.document_width,
+            self.document_height,
+            layer,
+            None,
+            Some(tile),
+            target,
+            framework,
+            renderer,
+        );
+    }
+
     fn composite_layer_on_target(
         &self,
         layer: &Layer,
@@ -323,7 +708,25 @@ impl LayerRenderingStrategy<Layer> for CanvasRenderingStrategy {
         framework: &mut Framework,
     ) {
         let source = self.layer_data(layer.id());
-        renderer.begin(&Camera2d::default(), None, framework);
+        self.composite_texture_on_target(layer, &source.canvas, back, canvas, renderer, framework);
+    }
+
+    fn composite_texture_on_target(
+        &self,
+        layer: &Layer,
+        texture: &TextureId,
+        back: &TextureId,
+        canvas: &TextureId,
+        renderer: &mut Renderer,
+        framework: &mut Framework,
+    ) {
+        let source = self.layer_data(layer.id());
+        // The shader computes the whole result, so any stale content in the target must go
+        renderer.begin(
+            &Camera2d::default(),
+            Some(wgpu::Color::TRANSPARENT),
+            framework,
+        );
         renderer.set_draw_debug_name(
             format!("Compositing layer '{:?}'", layer.settings().name).as_str(),
         );
@@ -338,10 +741,11 @@ impl LayerRenderingStrategy<Layer> for CanvasRenderingStrategy {
             additional_data: OptionalDrawData {
                 additional_vertex_buffers: vec![],
                 additional_bindable_resource: vec![
-                    BindableResource::Texture(source.canvas.clone()),
+                    BindableResource::Texture(texture.clone()),
                     BindableResource::UniformBuffer(source.settings_buffer.clone()),
                 ],
                 shader: Some(crate::global_selection_data().blended_shader.clone()),
+                scissor: None,
             },
         });
         renderer.end(&canvas, None, framework);
@@ -349,6 +753,10 @@ impl LayerRenderingStrategy<Layer> for CanvasRenderingStrategy {
 }
 
 impl CanvasRenderingStrategy {
+    pub(crate) fn linear_blending(&self) -> bool {
+        self.linear_blending
+    }
+
     pub(crate) fn layer_data(&self, id: &LayerId) -> &LayerCanvasData {
         self.layer_datas
             .get(id)
@@ -358,6 +766,7 @@ impl CanvasRenderingStrategy {
     fn update_impl(
         layers: &HashMap<LayerId, Layer>,
         datas: &HashMap<LayerId, LayerCanvasData>,
+        linear_blending: bool,
         framework: &mut Framework,
     ) {
         for layer in layers.values() {
@@ -369,6 +778,8 @@ impl CanvasRenderingStrategy {
                     &data.settings_buffer,
                     vec![BlendSettingsUniform::from(BlendSettings {
                         blend_mode: layer.settings().blend_mode,
+                        opacity: layer.settings().opacity,
+                        linear: linear_blending,
                     })],
                 );
             }
@@ -385,14 +796,11 @@ impl CanvasRenderingStrategy {
         owning_layer: &Layer,
         target: &TextureId,
         transform: &Transform2d,
+        camera: &Camera2d,
         framework: &mut Framework,
         renderer: &mut Renderer,
     ) {
-        renderer.begin(
-            &Self::make_camera_for_layer(owning_layer),
-            Some(wgpu::Color::TRANSPARENT),
-            framework,
-        );
+        renderer.begin(camera, Some(wgpu::Color::TRANSPARENT), framework);
         renderer.set_draw_debug_name(
             format!(
                 "Updating canvas for image layer '{:?}'",
@@ -400,41 +808,74 @@ impl CanvasRenderingStrategy {
             )
             .as_str(),
         );
-        renderer.draw(DrawCommand {
-            primitives: Texture2D {
-                texture_id: image_texture.clone(),
-                instances: vec![transform.clone()],
-                flip_uv_y: true,
-                multiply_color: wgpu::Color::WHITE,
+        // The pinned corners replace the layer transform, the image is always sampled bilinearly
+        let command = match owning_layer.corner_pin() {
+            Some(corners) => DrawCommand {
+                primitives: PrimitiveType::ProjectedTexture2D {
+                    texture_id: image_texture.clone(),
+                    corners,
+                    multiply_color: wgpu::Color::WHITE,
+                },
+                draw_mode: Single,
+                additional_data: OptionalDrawData::default(),
             },
-            draw_mode: Single,
-            additional_data: OptionalDrawData::default(),
-        });
+            None => DrawCommand {
+                primitives: Texture2D {
+                    texture_id: image_texture.clone(),
+                    instances: vec![transform.clone()],
+                    flip_uv_y: true,
+                    multiply_color: wgpu::Color::WHITE,
+                },
+                draw_mode: Single,
+                additional_data: OptionalDrawData::just_shader(
+                    owning_layer.interpolation().shader(),
+                ),
+            },
+        };
+        renderer.draw(command);
         renderer.end(target, None, framework);
     }
+    // When there's a region only the part of the canvas inside it is drawn again
+    // When tile is set, only that part of the canvas is rendered on the target, which must be
+    // as big as the tile
     fn render_layer(
         width: u32,
         height: u32,
         layer: &Layer,
+        region: Option<Box2d>,
+        tile: Option<(u32, u32, u32, u32)>,
         target: &TextureId,
         framework: &mut Framework,
         renderer: &mut Renderer,
     ) {
+        let crop = |camera: Camera2d| match tile {
+            Some(tile) => camera.cropped(tile, (width, height)),
+            None => camera,
+        };
         match &layer.layer_type {
             super::LayerType::Image { texture, .. } => Self::render_image(
                 texture,
                 layer,
                 target,
                 &layer.pixel_transform(),
+                &crop(Self::make_camera_for_layer(layer)),
                 framework,
                 renderer,
             ),
             super::LayerType::Group => {
                 unreachable!() // LayerType Group aren't supposed to be rendered directly
             }
-            super::LayerType::Chonky(map) => {
-                Self::render_chunk_map(width, height, map, layer, target, framework, renderer)
-            }
+            super::LayerType::Chonky(map) => Self::render_chunk_map(
+                width,
+                height,
+                map,
+                layer,
+                region,
+                &crop(Camera2d::wh(width, height)),
+                target,
+                framework,
+                renderer,
+            ),
         }
     }
 
@@ -456,7 +897,22 @@ impl CanvasRenderingStrategy {
                     let data = datas
                         .get(id)
                         .expect("CanvasRenderingStrategy: could not find data for rendering");
-                    Self::render_layer(width, height, layer, &data.canvas, framework, renderer);
+                    // Both flags are taken, a whole update makes the region useless
+                    let needs_bitmap_update = layer.needs_bitmap_update();
+                    let region = layer.dirty_region();
+                    if needs_bitmap_update || region.is_some() {
+                        let region = if needs_bitmap_update { None } else { region };
+                        Self::render_layer(
+                            width,
+                            height,
+                            layer,
+                            region,
+                            None,
+                            &data.canvas,
+                            framework,
+                            renderer,
+                        );
+                    }
                 }
                 LayerItem::Group(items, ..) => {
                     Self::render_impl(width, height, items, layers, datas, framework, renderer);
@@ -470,14 +926,27 @@ impl CanvasRenderingStrategy {
         height: u32,
         map: &super::ChunkedLayer,
         layer: &Layer,
+        region: Option<Box2d>,
+        layer_camera: &Camera2d,
         target: &TextureId,
         framework: &mut Framework,
         renderer: &mut Renderer,
     ) {
+        let scissor = match region {
+            Some(region) => match Self::canvas_rect(width, height, &region) {
+                Some(rect) => Some(rect),
+                None => return,
+            },
+            None => None,
+        };
         let layer_transform = layer.transform();
         let layer_transform_mat = layer_transform.matrix();
-        let layer_camera = Camera2d::wh(width, height);
-        renderer.begin(&layer_camera, Some(wgpu::Color::TRANSPARENT), framework);
+        // The clear color would clear the whole canvas, so the region is cleared by drawing on it
+        let clear_color = match scissor {
+            Some(_) => None,
+            None => Some(wgpu::Color::TRANSPARENT),
+        };
+        renderer.begin(layer_camera, clear_color, framework);
         renderer.set_draw_debug_name(
             format!(
                 "Update canvas for chunk map layer '{}'",
@@ -485,6 +954,23 @@ impl CanvasRenderingStrategy {
             )
             .as_str(),
         );
+        if let Some(rect) = scissor {
+            renderer.set_viewport(Some((0.0, 0.0, width as f32, height as f32)));
+            renderer.set_scissor(Some(rect));
+            renderer.draw(DrawCommand {
+                primitives: PrimitiveType::Rect {
+                    rects: vec![Box2d {
+                        center: point2(0.0, 0.0),
+                        extents: vec2(width as f32, height as f32) * 0.5,
+                    }],
+                    multiply_color: wgpu::Color::TRANSPARENT,
+                },
+                draw_mode: Single,
+                additional_data: OptionalDrawData::just_shader(Some(
+                    crate::global_selection_data().clear_region_shader.clone(),
+                )),
+            });
+        }
         map.iterate(|chunk, index, wpos| {
             log::trace!("Rendering chunk {index:?} at world position {wpos:?}");
             let transformed_position =
@@ -493,6 +979,15 @@ impl CanvasRenderingStrategy {
                 x: layer_transform.scale.x * map.chunk_size() as f32 * 0.5,
                 y: layer_transform.scale.y * map.chunk_size() as f32 * 0.5,
             };
+            if let Some(region) = &region {
+                // The chunk fits in a circle as wide as its diagonal, whatever its rotation
+                let radius = transformed_scale.x.abs().max(transformed_scale.y.abs()) * 2f32.sqrt();
+                if (transformed_position.x - region.center.x).abs() > region.extents.x + radius
+                    || (transformed_position.y - region.center.y).abs() > region.extents.y + radius
+                {
+                    return;
+                }
+            }
             let transform = Transform2d {
                 position: transformed_position,
                 scale: transformed_scale,
@@ -507,19 +1002,41 @@ impl CanvasRenderingStrategy {
                     multiply_color: wgpu::Color::WHITE,
                 },
                 draw_mode: Single,
-                additional_data: OptionalDrawData::default(),
+                additional_data: OptionalDrawData::just_shader(layer.interpolation().shader()),
             });
         });
         renderer.end(target, None, framework);
     }
+
+    // The pixels of the canvas covered by the world space region, as (x, y, width, height):
+    // the world origin is in the center of the canvas, with the y axis going up
+    fn canvas_rect(width: u32, height: u32, region: &Box2d) -> Option<(u32, u32, u32, u32)> {
+        let half_width = width as f32 * 0.5;
+        let half_height = height as f32 * 0.5;
+        let clamp_x = |x: f32| (x + half_width).clamp(0.0, width as f32);
+        let clamp_y = |y: f32| (half_height - y).clamp(0.0, height as f32);
+        let left = clamp_x(region.left()).floor() as u32;
+        let right = clamp_x(region.right()).ceil() as u32;
+        let top = clamp_y(region.bottom()).floor() as u32;
+        let bottom = clamp_y(region.top()).ceil() as u32;
+        if left < right && top < bottom {
+            Some((left, top, right - left, bottom - top))
+        } else {
+            None
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashSet;
 
-    use crate::layers::{LayerBase, LayerId};
+    use cgmath::{point2, vec2};
+    use framework::Box2d;
 
-    use super::LayerTree;
+    use crate::layers::{LayerBase, LayerId, LayerItem, LayerPosition};
+
+    use super::{CanvasRenderingStrategy, LayerTree};
 
     struct DummyLayer {
         id: LayerId,
@@ -582,4 +1099,178 @@ mod test {
         assert!(tree.find_above(&id).is_some());
         assert_eq!(tree.find_above(&id).unwrap(), target);
     }
+
+    fn three_layers() -> (TestTree, [LayerId; 3]) {
+        let mut tree = TestTree::new();
+        let ids = ["1", "2", "3"].map(|info| {
+            let layer = DummyLayer::new(info);
+            let id = layer.id.clone();
+            tree.add_layer(layer);
+            id
+        });
+        (tree, ids)
+    }
+
+    #[test]
+    fn select_multiple_layers() {
+        let (mut tree, [first, second, third]) = three_layers();
+        assert_eq!(tree.selected_layers().len(), 1);
+
+        tree.select_layer(second);
+        tree.toggle_layer_selection(first);
+        assert_eq!(tree.selected_layers().len(), 2);
+        assert_eq!(tree.current_layer_id(), Some(&first));
+
+        tree.toggle_layer_selection(first);
+        assert!(!tree.selected_layers().contains(&first));
+        assert_eq!(tree.current_layer_id(), Some(&second));
+
+        // The last selected layer stays selected
+        tree.toggle_layer_selection(second);
+        assert!(tree.selected_layers().contains(&second));
+
+        tree.select_layer(third);
+        tree.select_layer_range(first);
+        assert_eq!(tree.selected_layers().len(), 3);
+    }
+
+    #[test]
+    fn group_selected_layers() {
+        let (mut tree, [first, second, third]) = three_layers();
+        tree.select_layer(second);
+        tree.toggle_layer_selection(first);
+
+        let group = DummyLayer::new("group");
+        let group_id = group.id.clone();
+        let selected = tree.selected_layers().clone();
+        tree.group_layers(&selected, group);
+
+        // The layers are listed from the topmost one
+        assert_eq!(
+            tree.items(),
+            &vec![
+                LayerItem::SingleLayer(third),
+                LayerItem::Group(
+                    vec![
+                        LayerItem::SingleLayer(second),
+                        LayerItem::SingleLayer(first)
+                    ],
+                    group_id
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_then_ungroup() {
+        let (mut tree, [first, second, third]) = three_layers();
+        let flat_items = tree.items().clone();
+
+        let group = DummyLayer::new("group");
+        let group_id = group.id.clone();
+        tree.select_layer(third);
+        tree.group_layers(&HashSet::from([second, third]), group);
+        tree.select_layer(group_id);
+        assert_eq!(tree.items().len(), 2);
+
+        let group = tree.ungroup(&group_id);
+        assert_eq!(group.info, "group");
+        assert_eq!(tree.items(), &flat_items);
+        assert_eq!(tree.current_layer_id(), Some(&third));
+        assert!(tree.selected_layers().contains(&second));
+        assert!(!tree.selected_layers().contains(&first));
+    }
+
+    #[test]
+    fn ungrouped_layers_are_moved_back() {
+        let (mut tree, ids) = three_layers();
+        let [fourth, fifth] = ["4", "5"].map(|info| {
+            let layer = DummyLayer::new(info);
+            let id = layer.id.clone();
+            tree.add_layer(layer);
+            id
+        });
+        let flat_items = tree.items().clone();
+        let grouped = [fifth, ids[2], ids[0]];
+        let positions: Vec<_> = grouped
+            .iter()
+            .map(|id| (*id, tree.position_of(id).unwrap()))
+            .collect();
+
+        let group = DummyLayer::new("group");
+        let group_id = group.id.clone();
+        tree.group_layers(&HashSet::from(grouped), group);
+        tree.ungroup(&group_id);
+        assert_ne!(tree.items(), &flat_items);
+        tree.move_items(&positions);
+        assert_eq!(tree.items(), &flat_items);
+        assert_eq!(tree.position_of(&fourth).unwrap().index, 1);
+    }
+
+    #[test]
+    fn detach_and_attach_restore_the_position() {
+        let (mut tree, [first, second, third]) = three_layers();
+        let flat_items = tree.items().clone();
+        tree.select_layer(second);
+
+        let (item, position) = tree.detach_item(&second).unwrap();
+        assert_eq!(
+            position,
+            LayerPosition {
+                parent: None,
+                index: 1
+            }
+        );
+        assert_eq!(tree.current_layer_id(), Some(&first));
+        tree.attach_item(item, position);
+        assert_eq!(tree.items(), &flat_items);
+
+        tree.move_item(
+            &third,
+            LayerPosition {
+                parent: None,
+                index: 2,
+            },
+        );
+        assert_eq!(tree.position_of(&third).unwrap().index, 2);
+        tree.move_item(
+            &third,
+            LayerPosition {
+                parent: None,
+                index: 0,
+            },
+        );
+        assert_eq!(tree.items(), &flat_items);
+    }
+
+    #[test]
+    fn canvas_rect_of_a_region() {
+        // 100x50 canvas: the world point (-50, 25) is the top left pixel
+        let region = Box2d {
+            center: point2(-40.0, 20.0),
+            extents: vec2(5.0, 3.0),
+        };
+        assert_eq!(
+            CanvasRenderingStrategy::canvas_rect(100, 50, &region),
+            Some((5, 2, 10, 6))
+        );
+
+        let partially_outside = Box2d {
+            center: point2(50.0, -25.0),
+            extents: vec2(4.5, 4.5),
+        };
+        assert_eq!(
+            CanvasRenderingStrategy::canvas_rect(100, 50, &partially_outside),
+            Some((95, 45, 5, 5))
+        );
+
+        let outside = Box2d {
+            center: point2(200.0, 0.0),
+            extents: vec2(4.0, 4.0),
+        };
+        assert_eq!(
+            CanvasRenderingStrategy::canvas_rect(100, 50, &outside),
+            None
+        );
+    }
 }
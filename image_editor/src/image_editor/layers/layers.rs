@@ -1,17 +1,54 @@
 use std::cell::RefCell;
 
-use cgmath::{point2, point3, vec2, ElementWise, Point2, Rad, Vector2};
+use cgmath::{point2, point3, vec2, ElementWise, Point2, Rad, Transform, Vector2};
 use framework::framework::TextureId;
 use framework::renderer::renderer::Renderer;
 use framework::scene::Transform2d;
 use framework::{
-    Box2d, Camera2d, Framework, RgbaTexture2D, Texture, TextureConfiguration, TextureUsage,
+    Box2d, Camera2d, ColorSpace, Framework, RgbaTexture2D, Texture, TextureConfiguration,
+    TextureUsage,
 };
 use uuid::Uuid;
 
 use crate::blend_settings::BlendMode;
 
-use super::ChunkedLayer;
+use super::{ChunkedLayer, Interpolation};
+
+// Shown next to the layers in the layer list to group them visually, it doesn't change how
+// they're drawn. Stored by name, e.g. in the exported TIFF pages
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Debug,
+    strum_macros::EnumIter,
+    strum_macros::Display,
+    strum_macros::EnumString,
+)]
+pub enum LayerColorLabel {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Blue,
+    Violet,
+    Gray,
+}
+
+impl LayerColorLabel {
+    pub fn color_srgb(&self) -> [u8; 3] {
+        match self {
+            LayerColorLabel::Red => [230, 70, 70],
+            LayerColorLabel::Orange => [240, 150, 50],
+            LayerColorLabel::Yellow => [235, 210, 60],
+            LayerColorLabel::Green => [90, 190, 90],
+            LayerColorLabel::Blue => [70, 130, 230],
+            LayerColorLabel::Violet => [160, 100, 220],
+            LayerColorLabel::Gray => [150, 150, 150],
+        }
+    }
+}
 
 #[derive(Clone, PartialEq)]
 pub struct LayerSettings {
@@ -19,8 +56,11 @@ pub struct LayerSettings {
     pub blend_mode: BlendMode,
     pub is_enabled: bool,
     pub is_locked: bool,
+    // The brushes only paint over the existing pixels, keeping their alpha
+    pub is_alpha_locked: bool,
     pub is_mask: bool,
     pub opacity: f32,
+    pub label: Option<LayerColorLabel>,
 }
 
 impl LayerSettings {
@@ -30,8 +70,10 @@ impl LayerSettings {
             blend_mode: BlendMode::Normal,
             is_enabled: true,
             is_locked: false,
+            is_alpha_locked: false,
             is_mask: false,
             opacity: 1.0,
+            label: None,
         }
     }
 }
@@ -60,6 +102,16 @@ pub struct Layer {
     pub layer_type: LayerType,
     needs_settings_update: RefCell<bool>,
     needs_bitmap_update: RefCell<bool>,
+    // Set by any change that affects the composited document, see Document::render
+    needs_composite: RefCell<bool>,
+    // The part of the canvas to draw again, when the bitmap doesn't need a whole update
+    dirty_region: RefCell<Option<Box2d>>,
+    // The transparent pixels of a mask leave the layer visible, the black ones hide it
+    mask: Option<ChunkedLayer>,
+    interpolation: Interpolation,
+    // Where the corners of an image layer are drawn when they're moved independently,
+    // see set_corner_pin
+    corner_pin: Option<[Point2<f32>; 4]>,
 }
 
 impl LayerBase for Layer {
@@ -91,6 +143,8 @@ pub enum LayerType {
 #[derive(Clone, Copy, Debug)]
 pub enum OperationResult {
     Rerender,
+    // Only the region, in world space, must be drawn again on the canvas
+    RerenderRegion(Box2d),
     Update,
     RenderAndUpdate,
     None,
@@ -156,6 +210,8 @@ pub trait ChunkedLayerOperation {
 }
 
 impl Layer {
+    const CHUNK_SIZE: u32 = 256;
+
     pub fn new_image(
         image: RgbaTexture2D,
         creation_info: LayerCreationInfo,
@@ -168,6 +224,7 @@ impl Layer {
                 label: Some(format!("Layer \"{}\" texture", creation_info.name).as_str()),
                 usage: TextureUsage::RWRT,
                 mip_count: None,
+                color_space: ColorSpace::Srgb,
             },
         );
         Self {
@@ -182,12 +239,19 @@ impl Layer {
                 blend_mode: BlendMode::Normal,
                 is_enabled: true,
                 is_locked: false,
+                is_alpha_locked: false,
                 is_mask: false,
                 opacity: 1.0,
+                label: None,
             },
 
             needs_settings_update: RefCell::new(true),
             needs_bitmap_update: RefCell::new(true),
+            needs_composite: RefCell::new(true),
+            dirty_region: RefCell::new(None),
+            mask: None,
+            interpolation: Interpolation::default(),
+            corner_pin: None,
             transform: Transform2d {
                 position: point3(creation_info.position.x, creation_info.position.y, 0.0),
                 scale: creation_info.scale,
@@ -197,7 +261,6 @@ impl Layer {
     }
 
     pub fn new_chonky(creation_info: LayerCreationInfo) -> Self {
-        static CHUNK_SIZE: u32 = 256;
         Self {
             id: LayerId::new(),
             transform: Transform2d {
@@ -206,9 +269,34 @@ impl Layer {
                 rotation_radians: Rad(creation_info.rotation_radians),
             },
             settings: LayerSettings::new(&creation_info.name),
-            layer_type: LayerType::Chonky(ChunkedLayer::new(&creation_info.name, CHUNK_SIZE)),
+            layer_type: LayerType::Chonky(ChunkedLayer::new(&creation_info.name, Self::CHUNK_SIZE)),
             needs_settings_update: RefCell::new(false),
             needs_bitmap_update: RefCell::new(false),
+            needs_composite: RefCell::new(true),
+            dirty_region: RefCell::new(None),
+            mask: None,
+            interpolation: Interpolation::default(),
+            corner_pin: None,
+        }
+    }
+
+    pub fn new_group(creation_info: LayerCreationInfo) -> Self {
+        Self {
+            id: LayerId::new(),
+            transform: Transform2d {
+                position: point3(creation_info.position.x, creation_info.position.y, 0.0),
+                scale: creation_info.scale,
+                rotation_radians: Rad(creation_info.rotation_radians),
+            },
+            settings: LayerSettings::new(&creation_info.name),
+            layer_type: LayerType::Group,
+            needs_settings_update: RefCell::new(true),
+            needs_bitmap_update: RefCell::new(false),
+            needs_composite: RefCell::new(true),
+            dirty_region: RefCell::new(None),
+            mask: None,
+            interpolation: Interpolation::default(),
+            corner_pin: None,
         }
     }
 
@@ -224,6 +312,16 @@ impl Layer {
         ret
     }
 
+    pub fn dirty_region(&self) -> Option<Box2d> {
+        self.dirty_region.borrow_mut().take()
+    }
+
+    pub fn needs_composite(&self) -> bool {
+        let ret = self.needs_composite.borrow().clone();
+        *self.needs_composite.borrow_mut() = false;
+        ret
+    }
+
     pub fn execute_operation<O: LayerOperation>(
         &mut self,
         op: &mut O,
@@ -234,25 +332,79 @@ impl Layer {
         if op.accept(&self) {
             match op.execute(self, bounds, renderer, framework) {
                 OperationResult::Rerender => *self.needs_bitmap_update.borrow_mut() = true,
+                OperationResult::RerenderRegion(region) => self.mark_region_dirty(region),
                 OperationResult::Update => *self.needs_settings_update.borrow_mut() = true,
                 OperationResult::RenderAndUpdate => {
                     *self.needs_bitmap_update.borrow_mut() = true;
                     *self.needs_settings_update.borrow_mut() = true;
                 }
-                OperationResult::None => {}
+                OperationResult::None => return,
             }
+            *self.needs_composite.borrow_mut() = true;
         }
     }
 
+    pub fn mask(&self) -> Option<&ChunkedLayer> {
+        self.mask.as_ref()
+    }
+
+    pub fn mask_mut(&mut self) -> Option<&mut ChunkedLayer> {
+        self.mask.as_mut()
+    }
+
+    // Does nothing when the layer already has a mask
+    pub fn add_mask(&mut self) {
+        if self.mask.is_none() {
+            let label = format!("{} mask", self.settings.name);
+            self.mask = Some(ChunkedLayer::new(&label, Self::CHUNK_SIZE));
+        }
+    }
+
+    // Swaps the mask of the layer, e.g. to remove the one added by add_mask
+    pub fn replace_mask(&mut self, mask: Option<ChunkedLayer>) -> Option<ChunkedLayer> {
+        self.mark_dirty();
+        std::mem::replace(&mut self.mask, mask)
+    }
+
     pub fn mark_dirty(&mut self) {
         *self.needs_bitmap_update.borrow_mut() = true;
+        *self.needs_composite.borrow_mut() = true;
+    }
+
+    // Like mark_dirty, but only the region (in world space) is drawn again on the canvas
+    pub fn mark_region_dirty(&mut self, region: Box2d) {
+        let mut dirty_region = self.dirty_region.borrow_mut();
+        *dirty_region = Some(match *dirty_region {
+            Some(old) => Box2d::from_points(
+                point2(old.left().min(region.left()), old.top().min(region.top())),
+                point2(
+                    old.right().max(region.right()),
+                    old.bottom().max(region.bottom()),
+                ),
+            ),
+            None => region,
+        });
+        *self.needs_composite.borrow_mut() = true;
     }
 
     pub fn translate(&mut self, delta: Vector2<f32>) {
         self.transform.translate(delta.extend(0.0));
+        if let Some(pins) = &mut self.corner_pin {
+            pins.iter_mut().for_each(|pin| *pin += delta);
+        }
         self.mark_dirty();
     }
 
+    // Used when the layer is gone for good, e.g. when its document is closed: the textures
+    // are freed once no other id references them
+    pub fn free_textures(self, framework: &mut Framework) {
+        match self.layer_type {
+            LayerType::Image { texture, .. } => framework.free_texture2d(texture),
+            LayerType::Chonky(chunked_layer) => chunked_layer.free_chunks(framework),
+            LayerType::Group => {}
+        }
+    }
+
     pub fn replace_texture(&mut self, new_texture: TextureId) {
         match &mut self.layer_type {
             LayerType::Image { texture, .. } => *texture = new_texture,
@@ -284,6 +436,43 @@ impl Layer {
         self.mark_dirty();
     }
 
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        if self.interpolation != interpolation {
+            self.interpolation = interpolation;
+            self.mark_dirty();
+        }
+    }
+
+    pub fn corner_pin(&self) -> Option<[Point2<f32>; 4]> {
+        self.corner_pin
+    }
+
+    // Pins the top left, top right, bottom right and bottom left corners of the image on the
+    // given points, drawing it with a perspective transform instead of the layer transform.
+    // None goes back to the layer transform, only the image layers can be pinned
+    pub fn set_corner_pin(&mut self, pins: Option<[Point2<f32>; 4]>) {
+        if let LayerType::Image { .. } = self.layer_type {
+            self.corner_pin = pins;
+            self.mark_dirty();
+        }
+    }
+
+    // Where the corners of the layer are drawn, in the same order as the corner pin
+    pub fn corners(&self) -> [Point2<f32>; 4] {
+        if let Some(pins) = self.corner_pin {
+            return pins;
+        }
+        let matrix = self.pixel_transform().matrix();
+        [(-1.0, 1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)].map(|(x, y)| {
+            let corner = matrix.transform_point(point3(x, y, 0.0));
+            point2(corner.x, corner.y)
+        })
+    }
+
     pub fn pixel_transform(&self) -> Transform2d {
         let bounds = self.bounds();
         Transform2d {
@@ -316,7 +505,139 @@ impl Layer {
         &self.id
     }
 
+    // The groups have no pixels: they can't be painted on or have their pixels moved
+    pub fn is_group(&self) -> bool {
+        matches!(self.layer_type, LayerType::Group)
+    }
+
     pub(crate) fn size(&self) -> Vector2<u32> {
         self.bounds().extents.cast::<u32>().unwrap() * 2
     }
+
+    // A CPU copy of the layer pixels, the first row is the top one. The pixels of a chunked
+    // layer cover all its chunks, the holes between them are transparent.
+    // None for groups, for chunked layers that weren't painted yet and when the readback fails
+    pub fn read_pixels(&self, framework: &Framework) -> Option<RgbaTexture2D> {
+        match &self.layer_type {
+            LayerType::Image { texture, .. } => framework.texture2d_read_data(texture).ok(),
+            LayerType::Chonky(map) => map.read_into_texture(framework).map(|(_, pixels)| pixels),
+            LayerType::Group => None,
+        }
+    }
+
+    // Uploads pixels with the same size as the ones given by read_pixels, e.g. after editing
+    // them, and draws the layer again. False when the size is different or when the layer
+    // has no pixels, in that case the layer isn't changed
+    pub fn write_pixels(&mut self, pixels: &RgbaTexture2D, framework: &mut Framework) -> bool {
+        let size = vec2(pixels.width(), pixels.height());
+        let data = match pixels.data() {
+            Some(data) => data,
+            None => return false,
+        };
+        match &self.layer_type {
+            LayerType::Image {
+                texture,
+                dimensions,
+            } => {
+                if *dimensions != size {
+                    return false;
+                }
+                framework.texture2d_write_data(texture, data);
+            }
+            LayerType::Chonky(map) => {
+                let (first_chunk, last_chunk) = match map.chunk_range() {
+                    Some(range) => range,
+                    None => return false,
+                };
+                let chunks = vec2(
+                    (last_chunk.x - first_chunk.x + 1) as u32,
+                    (last_chunk.y - first_chunk.y + 1) as u32,
+                );
+                if chunks * map.chunk_size() != size {
+                    return false;
+                }
+                map.write_from_texture(first_chunk, pixels, framework);
+            }
+            LayerType::Group => return false,
+        }
+        self.mark_dirty();
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::{point2, vec2};
+    use framework::{RgbaTexture2D, RgbaU8, Texture};
+
+    use strum::IntoEnumIterator;
+
+    use super::{Layer, LayerColorLabel, LayerCreationInfo};
+    use crate::{headless_framework, test_support::texel_channels};
+
+    fn creation_info() -> LayerCreationInfo {
+        LayerCreationInfo {
+            name: "Pixels".into(),
+            position: point2(0.0, 0.0),
+            scale: vec2(1.0, 1.0),
+            rotation_radians: 0.0,
+        }
+    }
+
+    #[test]
+    fn color_labels_are_stored_by_name() {
+        for label in LayerColorLabel::iter() {
+            assert_eq!(label.to_string().parse::<LayerColorLabel>(), Ok(label));
+        }
+        assert!("Pink".parse::<LayerColorLabel>().is_err());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn pixels_round_trip() {
+        let mut framework = headless_framework();
+        let texels = (0..16 * 8)
+            .map(|i| RgbaU8([i as u8, 0, 255 - i as u8, 255]))
+            .collect();
+        let mut layer = Layer::new_image(
+            RgbaTexture2D::from_texels(texels, (16, 8)).unwrap(),
+            creation_info(),
+            &mut framework,
+        );
+        let pixels = layer.read_pixels(&framework).unwrap();
+        assert_eq!((pixels.width(), pixels.height()), (16, 8));
+        assert_eq!(texel_channels(&pixels)[17], [17, 0, 238, 255]);
+
+        // Inverting the colors
+        let inverted: Vec<RgbaU8> = pixels
+            .data()
+            .unwrap()
+            .iter()
+            .map(|RgbaU8([r, g, b, a])| RgbaU8([255 - r, 255 - g, 255 - b, *a]))
+            .collect();
+        let inverted = RgbaTexture2D::from_texels(inverted, (16, 8)).unwrap();
+        // Taking the flag set by the creation of the layer
+        layer.needs_bitmap_update();
+        assert!(layer.write_pixels(&inverted, &mut framework));
+        assert!(layer.needs_bitmap_update());
+        let read_again = layer.read_pixels(&framework).unwrap();
+        assert_eq!(texel_channels(&read_again), texel_channels(&inverted));
+        assert_eq!(texel_channels(&read_again)[17], [238, 255, 17, 255]);
+
+        // The size must match the layer
+        let wrong_size = RgbaTexture2D::from_repeated_texel(RgbaU8([0; 4]), (8, 8)).unwrap();
+        assert!(!layer.write_pixels(&wrong_size, &mut framework));
+        assert_eq!(
+            texel_channels(&layer.read_pixels(&framework).unwrap()),
+            texel_channels(&inverted)
+        );
+
+        // Groups and empty chunked layers have no pixels
+        let mut empty = Layer::new_chonky(creation_info());
+        assert!(empty.read_pixels(&framework).is_none());
+        assert!(!empty.write_pixels(&wrong_size, &mut framework));
+        assert!(Layer::new_group(creation_info())
+            .read_pixels(&framework)
+            .is_none());
+    }
 }
@@ -1,7 +1,11 @@
 mod chunked_layer;
+mod cpu_blitting_strategy;
+mod interpolation;
 mod layer_tree;
 mod layers;
 
 pub use chunked_layer::*;
+pub use cpu_blitting_strategy::*;
+pub use interpolation::*;
 pub use layer_tree::*;
 pub use layers::*;
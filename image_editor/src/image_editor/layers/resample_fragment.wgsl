@@ -0,0 +1,59 @@
+//@include :common_definitions
+//@include :2d_definitions
+
+@group(2) @binding(0) var source: texture_2d<f32>;
+@group(2) @binding(1) var s_source: sampler;
+
+fn load_source(texel: vec2<i32>) -> vec4<f32> {
+    let dimensions = vec2<i32>(textureDimensions(source));
+    let clamped = clamp(texel, vec2<i32>(0, 0), dimensions - vec2<i32>(1, 1));
+    return textureLoad(source, clamped, 0);
+}
+
+fn nearest(uv: vec2<f32>) -> vec4<f32> {
+    let dimensions = vec2<f32>(textureDimensions(source));
+    return load_source(vec2<i32>(floor(uv * dimensions)));
+}
+
+// Catmull-Rom weights of the four texels around the sampled position
+fn cubic_weights(t: f32) -> vec4<f32> {
+    return vec4<f32>(
+        ((-0.5 * t + 1.0) * t - 0.5) * t,
+        (1.5 * t - 2.5) * t * t + 1.0,
+        ((-1.5 * t + 2.0) * t + 0.5) * t,
+        (0.5 * t - 0.5) * t * t,
+    );
+}
+
+fn bicubic(uv: vec2<f32>) -> vec4<f32> {
+    let dimensions = vec2<f32>(textureDimensions(source));
+    let position = uv * dimensions - 0.5;
+    let first = vec2<i32>(floor(position)) - vec2<i32>(1, 1);
+    let t = fract(position);
+    var wx = cubic_weights(t.x);
+    var wy = cubic_weights(t.y);
+
+    var color = vec4<f32>(0.0);
+    for (var y = 0; y < 4; y = y + 1) {
+        var row = vec4<f32>(0.0);
+        for (var x = 0; x < 4; x = x + 1) {
+            row = row + load_source(first + vec2<i32>(x, y)) * wx[x];
+        }
+        color = color + row * wy[y];
+    }
+    // The negative lobes can overshoot near the hard edges
+    return clamp(color, vec4<f32>(0.0), vec4<f32>(1.0));
+}
+
+// INTERPOLATION is replaced when the shader is compiled: 0 is nearest, 2 is bicubic
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let interpolation = INTERPOLATION;
+    var color: vec4<f32>;
+    if (interpolation == 0) {
+        color = nearest(in.tex_uv);
+    } else {
+        color = bicubic(in.tex_uv);
+    }
+    return color * in.multiply_color;
+}
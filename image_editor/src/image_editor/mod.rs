@@ -1,19 +1,34 @@
 mod ab_render_target;
+pub mod autosave;
 pub mod blend_settings;
 pub mod document;
+mod exr_export;
+pub mod filters;
+mod gif_export;
 pub mod image_editor;
 pub mod image_editor_event;
 pub mod layers;
+mod pending_export;
 pub mod selection;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_support;
+mod tiff_io;
 
 use framework::framework::ShaderId;
 use framework::shader::BindElement;
 use framework::shader::ShaderCreationInfo;
 use framework::Framework;
+pub use gif_export::DitherKind;
+pub use image_editor::DocumentId;
 pub use image_editor::ImageEditor;
 pub use image_editor::LayerConstructionInfo;
+pub use image_editor::PaintTarget;
+pub use image_editor::ReferenceOverlay;
 pub use image_editor_event::ImageEditorEvent;
 use once_cell::sync::OnceCell;
+#[cfg(any(test, feature = "test-utils"))]
+pub use test_support::headless_framework;
+use wgpu::BlendState;
 use wgpu::DepthBiasState;
 use wgpu::DepthStencilState;
 use wgpu::StencilFaceState;
@@ -24,10 +39,33 @@ pub struct ImageEditorGlobals {
     pub draw_on_stencil_buffer_shader_id: ShaderId,
     pub draw_masked_stencil_buffer_shader_id: ShaderId,
     pub draw_masked_inverted_stencil_buffer_shader_id: ShaderId,
+    // The same as the two above, drawing on the R8 mask textures
+    pub draw_masked_stencil_buffer_r8_shader_id: ShaderId,
+    pub draw_masked_inverted_stencil_buffer_r8_shader_id: ShaderId,
     pub dotted_shader: ShaderId,
     pub blended_shader: ShaderId,
+    pub noise_shader: ShaderId,
+    pub unsharp_mask_shader: ShaderId,
+    pub checker_background_shader: ShaderId,
+    pub alpha_blended_texture_shader: ShaderId,
+    pub quick_mask_shader: ShaderId,
+    pub clear_region_shader: ShaderId,
+    pub depth_composite_shader: ShaderId,
+    pub nearest_shader: ShaderId,
+    pub bicubic_shader: ShaderId,
+    pub selection_coverage_shader: ShaderId,
+    pub selection_coverage_subtract_shader: ShaderId,
+    pub selection_coverage_r8_shader: ShaderId,
+    pub selection_coverage_subtract_r8_shader: ShaderId,
+    pub selected_overlay_shader: ShaderId,
+    pub masked_overlay_shader: ShaderId,
 }
 
+// The selection lives in the 8 stencil bits of a Depth24PlusStencil8 texture: each shape replaces
+// the value of the pixels it covers, so any number of shapes composes in order without overflowing
+pub(crate) const SELECTION_STENCIL_MASK: u32 = 0xFF;
+pub(crate) const SELECTED_STENCIL_VALUE: u32 = 0xFF;
+
 static INSTANCE: OnceCell<ImageEditorGlobals> = OnceCell::new();
 fn make_globals(framework: &mut Framework) -> ImageEditorGlobals {
     let info = ShaderCreationInfo::using_default_vertex_fragment(framework).with_depth_state(Some(
@@ -48,64 +86,58 @@ fn make_globals(framework: &mut Framework) -> ImageEditorGlobals {
                     fail_op: wgpu::StencilOperation::Keep,
                     depth_fail_op: wgpu::StencilOperation::Keep,
                 },
-                read_mask: 0xFFFFFFF,
-                write_mask: 0xFFFFFFF,
+                read_mask: SELECTION_STENCIL_MASK,
+                write_mask: SELECTION_STENCIL_MASK,
             },
             bias: DepthBiasState::default(),
         },
     ));
     let draw_on_stencil_buffer_shader_id = framework.create_shader(info);
-    let info = ShaderCreationInfo::using_default_vertex_fragment(framework).with_depth_state(Some(
-        DepthStencilState {
-            format: wgpu::TextureFormat::Depth24PlusStencil8,
-            depth_write_enabled: false,
-            depth_compare: wgpu::CompareFunction::Always,
-            stencil: StencilState {
-                front: StencilFaceState {
-                    compare: wgpu::CompareFunction::Equal,
-                    pass_op: wgpu::StencilOperation::Keep,
-                    fail_op: wgpu::StencilOperation::Keep,
-                    depth_fail_op: wgpu::StencilOperation::Keep,
-                },
-                back: StencilFaceState {
-                    compare: wgpu::CompareFunction::Equal,
-                    pass_op: wgpu::StencilOperation::Keep,
-                    fail_op: wgpu::StencilOperation::Keep,
-                    depth_fail_op: wgpu::StencilOperation::Keep,
-                },
-                read_mask: 0xFFFFFFF,
-                write_mask: 0xFFFFFFF,
-            },
-            bias: DepthBiasState::default(),
-        },
-    ));
-    let draw_masked_stencil_buffer_shader_id = framework.create_shader(info);
-
-    let info = ShaderCreationInfo::using_default_vertex_fragment(framework).with_depth_state(Some(
-        DepthStencilState {
-            format: wgpu::TextureFormat::Depth24PlusStencil8,
-            depth_write_enabled: false,
-            depth_compare: wgpu::CompareFunction::Always,
-            stencil: StencilState {
-                front: StencilFaceState {
-                    compare: wgpu::CompareFunction::NotEqual,
-                    pass_op: wgpu::StencilOperation::Keep,
-                    fail_op: wgpu::StencilOperation::Keep,
-                    depth_fail_op: wgpu::StencilOperation::Keep,
-                },
-                back: StencilFaceState {
-                    compare: wgpu::CompareFunction::NotEqual,
-                    pass_op: wgpu::StencilOperation::Keep,
-                    fail_op: wgpu::StencilOperation::Keep,
-                    depth_fail_op: wgpu::StencilOperation::Keep,
-                },
-                read_mask: 0xFFFFFFF,
-                write_mask: 0xFFFFFFF,
-            },
-            bias: DepthBiasState::default(),
-        },
-    ));
-    let draw_masked_inverted_stencil_buffer_shader_id = framework.create_shader(info);
+    // Draws only where the stencil value compares with the reference, on textures of the format
+    let masked_stencil_shader =
+        |compare: wgpu::CompareFunction, format: wgpu::TextureFormat, framework: &mut Framework| {
+            let face = StencilFaceState {
+                compare,
+                pass_op: wgpu::StencilOperation::Keep,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+            };
+            let info = ShaderCreationInfo::using_default_vertex_fragment(framework)
+                .with_output_format(format)
+                .with_depth_state(Some(DepthStencilState {
+                    format: wgpu::TextureFormat::Depth24PlusStencil8,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: StencilState {
+                        front: face,
+                        back: face,
+                        read_mask: SELECTION_STENCIL_MASK,
+                        write_mask: 0,
+                    },
+                    bias: DepthBiasState::default(),
+                }));
+            framework.create_shader(info)
+        };
+    let draw_masked_stencil_buffer_shader_id = masked_stencil_shader(
+        wgpu::CompareFunction::Equal,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        framework,
+    );
+    let draw_masked_inverted_stencil_buffer_shader_id = masked_stencil_shader(
+        wgpu::CompareFunction::NotEqual,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        framework,
+    );
+    let draw_masked_stencil_buffer_r8_shader_id = masked_stencil_shader(
+        wgpu::CompareFunction::Equal,
+        wgpu::TextureFormat::R8Unorm,
+        framework,
+    );
+    let draw_masked_inverted_stencil_buffer_r8_shader_id = masked_stencil_shader(
+        wgpu::CompareFunction::NotEqual,
+        wgpu::TextureFormat::R8Unorm,
+        framework,
+    );
 
     let dotted_module_descriptor = framework.shader_compiler.compile_into_shader_description(
         "Dotted shader",
@@ -126,12 +158,183 @@ fn make_globals(framework: &mut Framework) -> ImageEditorGlobals {
         .with_bind_element(BindElement::UniformBuffer); // Blend settings
     let blended_shader = framework.create_shader(blended_shader_info);
 
+    let noise_shader = framework
+        .shader_compiler
+        .compile_into_shader_description("Noise fill shader", include_str!("filters/noise.wgsl"));
+    let noise_shader_info = ShaderCreationInfo::using_default_vertex(noise_shader, framework)
+        .with_bind_element(BindElement::Texture) // 2: diffuse texture + sampler, unused
+        .with_bind_element(BindElement::StencilTexture) // 3: Selection stencil texture
+        .with_bind_element(BindElement::UniformBuffer); // 4: Noise settings
+    let noise_shader = framework.create_shader(noise_shader_info);
+
+    let unsharp_mask_shader = framework.shader_compiler.compile_into_shader_description(
+        "Unsharp mask shader",
+        include_str!("filters/unsharp_mask.wgsl"),
+    );
+    let unsharp_mask_shader_info =
+        ShaderCreationInfo::using_default_vertex(unsharp_mask_shader, framework)
+            .with_bind_element(BindElement::Texture) // 2: Source texture + sampler
+            .with_bind_element(BindElement::StencilTexture) // 3: Selection stencil texture
+            .with_bind_element(BindElement::UniformBuffer) // 4: Unsharp mask settings
+            // The result replaces the chunk contents, discarded fragments keep the original pixels
+            .with_blend_state(BlendState::REPLACE);
+    let unsharp_mask_shader = framework.create_shader(unsharp_mask_shader_info);
+
+    let checker_background_shader = framework.shader_compiler.compile_into_shader_description(
+        "Checker background shader",
+        include_str!("shaders/checker_background.wgsl"),
+    );
+    let checker_background_shader_info =
+        ShaderCreationInfo::using_default_vertex(checker_background_shader, framework)
+            .with_bind_element(BindElement::Texture); // 2: diffuse texture + sampler, unused
+    let checker_background_shader = framework.create_shader(checker_background_shader_info);
+
+    // The composited layers aren't premultiplied
+    let alpha_blended_texture_shader_info =
+        ShaderCreationInfo::using_default_vertex_fragment(framework)
+            .with_blend_state(BlendState::ALPHA_BLENDING);
+    let alpha_blended_texture_shader = framework.create_shader(alpha_blended_texture_shader_info);
+
+    let quick_mask_shader = framework.shader_compiler.compile_into_shader_description(
+        "Quick mask shader",
+        include_str!("shaders/quick_mask.wgsl"),
+    );
+    let quick_mask_shader_info =
+        ShaderCreationInfo::using_default_vertex(quick_mask_shader, framework)
+            .with_bind_element(BindElement::Texture); // 2: mask chunk + sampler
+    let quick_mask_shader = framework.create_shader(quick_mask_shader_info);
+
+    // Draws the multiply color as it is, used to clear only a part of a texture
+    let clear_region_shader_info = ShaderCreationInfo::using_default_vertex_fragment(framework)
+        .with_blend_state(BlendState::REPLACE);
+    let clear_region_shader = framework.create_shader(clear_region_shader_info);
+
+    // Opaque layers drawn front to back: the nearest layer wins, the ones below are skipped
+    let depth_composite_shader = framework.shader_compiler.compile_into_shader_description(
+        "Depth composite shader",
+        include_str!("layers/depth_composite_fragment.wgsl"),
+    );
+    let depth_composite_shader_info =
+        ShaderCreationInfo::using_default_vertex(depth_composite_shader, framework)
+            .with_bind_element(BindElement::Texture) // 2: layer canvas + sampler
+            .with_bind_element(BindElement::UniformBuffer) // 3: layer depth
+            .with_blend_state(BlendState::REPLACE)
+            .with_depth_state(Some(DepthStencilState {
+                format: wgpu::TextureFormat::Depth24PlusStencil8,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }));
+    let depth_composite_shader = framework.create_shader(depth_composite_shader_info);
+
+    // The layers are drawn with these when they aren't interpolated bilinearly,
+    // the same fragment shader is compiled once for each interpolation
+    let resample_shader = |label: &str, interpolation: &str, framework: &mut Framework| {
+        let source =
+            include_str!("layers/resample_fragment.wgsl").replace("INTERPOLATION", interpolation);
+        let fragment = framework
+            .shader_compiler
+            .compile_into_shader_description(label, &source);
+        let info = ShaderCreationInfo::using_default_vertex(fragment, framework)
+            .with_bind_element(BindElement::Texture); // 2: source texture, read without the sampler
+        framework.create_shader(info)
+    };
+    let nearest_shader = resample_shader("Nearest resample shader", "0", framework);
+    let bicubic_shader = resample_shader("Bicubic resample shader", "2", framework);
+
+    // Anti-aliased selection rectangles, the added ones are blended over the mask and
+    // the subtracted ones scale it down by their coverage
+    let coverage_shader = |label: &str,
+                           blend_state: BlendState,
+                           format: wgpu::TextureFormat,
+                           framework: &mut Framework| {
+        let fragment = framework.shader_compiler.compile_into_shader_description(
+            label,
+            include_str!("shaders/selection_coverage.wgsl"),
+        );
+        let info = ShaderCreationInfo::using_default_vertex(fragment, framework)
+            .with_bind_element(BindElement::Texture) // 2: diffuse texture + sampler, unused
+            .with_blend_state(blend_state)
+            .with_output_format(format);
+        framework.create_shader(info)
+    };
+    let selection_coverage_subtract_blend = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+        operation: wgpu::BlendOperation::Add,
+    };
+    let selection_coverage_subtract_blend_state = BlendState {
+        color: selection_coverage_subtract_blend,
+        alpha: selection_coverage_subtract_blend,
+    };
+    let selection_coverage_shader = coverage_shader(
+        "Selection coverage shader",
+        BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        framework,
+    );
+    let selection_coverage_subtract_shader = coverage_shader(
+        "Selection coverage subtract shader",
+        selection_coverage_subtract_blend_state,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        framework,
+    );
+    let selection_coverage_r8_shader = coverage_shader(
+        "Selection coverage R8 shader",
+        BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+        wgpu::TextureFormat::R8Unorm,
+        framework,
+    );
+    let selection_coverage_subtract_r8_shader = coverage_shader(
+        "Selection coverage subtract R8 shader",
+        selection_coverage_subtract_blend_state,
+        wgpu::TextureFormat::R8Unorm,
+        framework,
+    );
+
+    // Tints either the selected pixels or the other ones, reading the selection stencil buffer
+    let overlay_shader = |label: &str, tinted_value: &str, framework: &mut Framework| {
+        let source =
+            include_str!("shaders/selection_overlay.wgsl").replace("TINTED_VALUE", tinted_value);
+        let fragment = framework
+            .shader_compiler
+            .compile_into_shader_description(label, &source);
+        let info = ShaderCreationInfo::using_default_vertex(fragment, framework)
+            .with_bind_element(BindElement::Texture) // 2: diffuse texture + sampler, unused
+            .with_bind_element(BindElement::StencilTexture); // 3: Selection stencil texture
+        framework.create_shader(info)
+    };
+    let selected_overlay_shader = overlay_shader(
+        "Selected overlay shader",
+        &SELECTED_STENCIL_VALUE.to_string(),
+        framework,
+    );
+    let masked_overlay_shader = overlay_shader("Masked overlay shader", "0", framework);
+
     ImageEditorGlobals {
         draw_on_stencil_buffer_shader_id,
         draw_masked_stencil_buffer_shader_id,
         draw_masked_inverted_stencil_buffer_shader_id,
+        draw_masked_stencil_buffer_r8_shader_id,
+        draw_masked_inverted_stencil_buffer_r8_shader_id,
         dotted_shader,
         blended_shader,
+        noise_shader,
+        unsharp_mask_shader,
+        checker_background_shader,
+        alpha_blended_texture_shader,
+        quick_mask_shader,
+        clear_region_shader,
+        depth_composite_shader,
+        nearest_shader,
+        bicubic_shader,
+        selection_coverage_shader,
+        selection_coverage_subtract_shader,
+        selection_coverage_r8_shader,
+        selection_coverage_subtract_r8_shader,
+        selected_overlay_shader,
+        masked_overlay_shader,
     }
 }
 
@@ -144,3 +347,13 @@ pub(crate) fn init_globals(framework: &mut Framework) {
 pub fn global_selection_data() -> &'static ImageEditorGlobals {
     INSTANCE.get().unwrap()
 }
+
+// The blend modes shared by the layer compositing shaders and the application's brushes
+pub const BLEND_MODES_SHADER: &str = include_str!("blend_modes.wgsl");
+
+pub fn define_editor_shaders(framework: &mut Framework) {
+    framework
+        .shader_compiler
+        .define("blend_modes", BLEND_MODES_SHADER)
+        .expect("Failed to compile the blend modes");
+}
@@ -0,0 +1,206 @@
+use std::{
+    fs::File,
+    future::Future,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use framework::TexelConversionError;
+use image::DynamicImage;
+
+use super::gif_export::{self, GifOptions};
+
+const METERS_PER_INCH: f32 = 0.0254;
+
+// An export waiting for the GPU readback of the final image:
+// it's polled once per update, so that the event loop never blocks on the GPU
+pub(crate) struct PendingExport {
+    path: PathBuf,
+    dpi: f32,
+    gif_options: GifOptions,
+    image: Pin<Box<dyn Future<Output = Result<DynamicImage, TexelConversionError>>>>,
+}
+
+impl PendingExport {
+    pub(crate) fn new<F: Future<Output = Result<DynamicImage, TexelConversionError>> + 'static>(
+        path: PathBuf,
+        dpi: f32,
+        gif_options: GifOptions,
+        image: F,
+    ) -> Self {
+        Self {
+            path,
+            dpi,
+            gif_options,
+            image: Box::pin(image),
+        }
+    }
+
+    // Saves the image as soon as the readback is done, Ready(true) when it was written
+    pub(crate) fn poll(&mut self) -> Poll<bool> {
+        let waker = noop_waker();
+        let mut context = Context::from_waker(&waker);
+        match self.image.as_mut().poll(&mut context) {
+            Poll::Ready(Ok(image)) => {
+                if let Err(e) = save_image(&image, &self.path, self.dpi, &self.gif_options) {
+                    log::error!("While saving image: {e}");
+                    return Poll::Ready(false);
+                }
+                Poll::Ready(true)
+            }
+            Poll::Ready(Err(e)) => {
+                log::error!("While reading back {}: {e}", self.path.display());
+                Poll::Ready(false)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn save_image(
+    image: &DynamicImage,
+    path: &Path,
+    dpi: f32,
+    gif_options: &GifOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let has_extension = |extension: &str| {
+        path.extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case(extension))
+    };
+    if has_extension("gif") {
+        return gif_export::write_gif(path, image, gif_options);
+    }
+    if !has_extension("png") {
+        image.save(path)?;
+        return Ok(());
+    }
+    // The image crate can't store the DPI, the png encoder writes it in the pHYs chunk
+    let image = image.to_rgba8();
+    let pixels_per_meter = (dpi / METERS_PER_INCH).round() as u32;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width(),
+        image.height(),
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: pixels_per_meter,
+        yppu: pixels_per_meter,
+        unit: png::Unit::Meter,
+    }));
+    encoder.write_header()?.write_image_data(&image)?;
+    Ok(())
+}
+
+// The readback future is polled every update anyways, so there's nothing to wake
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+    unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::task::Poll;
+
+    use framework::TexelConversionError;
+    use image::{DynamicImage, ImageBuffer};
+
+    use super::{GifOptions, PendingExport, METERS_PER_INCH};
+
+    fn test_image() -> DynamicImage {
+        DynamicImage::ImageRgba8(ImageBuffer::from_raw(2, 2, vec![255; 16]).unwrap())
+    }
+
+    #[test]
+    fn pending_readback_does_not_block() {
+        let path = std::env::temp_dir().join(format!(
+            "mochi_pending_export_never_{}.png",
+            std::process::id()
+        ));
+        let mut export = PendingExport::new(path.clone(), 72.0, GifOptions::default(), async {
+            std::future::pending::<()>().await;
+            Ok(test_image())
+        });
+        assert_eq!(export.poll(), Poll::Pending);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn completed_readback_is_saved() {
+        let path = std::env::temp_dir().join(format!(
+            "mochi_pending_export_ready_{}.png",
+            std::process::id()
+        ));
+        let mut export = PendingExport::new(
+            path.clone(),
+            72.0,
+            GifOptions::default(),
+            std::future::ready(Ok(test_image())),
+        );
+        assert_eq!(export.poll(), Poll::Ready(true));
+        let saved = image::open(&path).unwrap();
+        assert_eq!(
+            saved.to_rgba8().into_raw(),
+            test_image().to_rgba8().into_raw()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failed_readback_is_not_saved() {
+        let path = std::env::temp_dir().join(format!(
+            "mochi_pending_export_failed_{}.png",
+            std::process::id()
+        ));
+        let mut export = PendingExport::new(
+            path.clone(),
+            72.0,
+            GifOptions::default(),
+            std::future::ready(Err(TexelConversionError::NotEnoughData)),
+        );
+        assert_eq!(export.poll(), Poll::Ready(false));
+        assert!(!path.exists());
+    }
+
+    // Returns the DPI stored in the pHYs chunk
+    fn read_png_dpi(png: &[u8]) -> Option<f32> {
+        let reader = png::Decoder::new(png).read_info().unwrap();
+        let dims = reader.info().pixel_dims?;
+        assert_eq!(dims.xppu, dims.yppu);
+        assert_eq!(dims.unit, png::Unit::Meter);
+        Some(dims.xppu as f32 * METERS_PER_INCH)
+    }
+
+    #[test]
+    fn png_exports_store_the_dpi() {
+        let path = std::env::temp_dir().join(format!(
+            "mochi_pending_export_dpi_{}.png",
+            std::process::id()
+        ));
+        let mut export = PendingExport::new(
+            path.clone(),
+            300.0,
+            GifOptions::default(),
+            std::future::ready(Ok(test_image())),
+        );
+        assert_eq!(export.poll(), Poll::Ready(true));
+
+        let bytes = std::fs::read(&path).unwrap();
+        let dpi = read_png_dpi(&bytes).unwrap();
+        assert!((dpi - 300.0).abs() < 0.01);
+        let saved = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(
+            saved.to_rgba8().into_raw(),
+            test_image().to_rgba8().into_raw()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use cgmath::{point2, EuclideanSpace, MetricSpace, Point2};
+use framework::{Framework, RgbaU8};
+
+use crate::layers::{Layer, LayerType};
+
+// Edges weaker than this don't attract the points, the magnitudes go from 0 to 1
+const STRONG_EDGE: f32 = 0.25;
+
+// The gradient magnitude of each pixel, computed with a Sobel filter over the luminance
+pub struct EdgeMap {
+    width: u32,
+    height: u32,
+    // World position of the top left corner, the rows go from the top down
+    top_left: Point2<f32>,
+    magnitudes: Vec<f32>,
+}
+
+impl EdgeMap {
+    // The luminance goes from 0 to 1, the pixels outside the map repeat the ones on the border
+    pub fn from_luminance(luminance: &[f32], width: u32, top_left: Point2<f32>) -> Self {
+        let height = if width == 0 {
+            0
+        } else {
+            luminance.len() as u32 / width
+        };
+        let at = |x: i64, y: i64| {
+            let x = x.clamp(0, width as i64 - 1);
+            let y = y.clamp(0, height as i64 - 1);
+            luminance[(y * width as i64 + x) as usize]
+        };
+        let mut magnitudes = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                    - at(x - 1, y - 1)
+                    - 2.0 * at(x - 1, y)
+                    - at(x - 1, y + 1);
+                let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                    - at(x - 1, y - 1)
+                    - 2.0 * at(x, y - 1)
+                    - at(x + 1, y - 1);
+                // A sharp step from black to white gives a magnitude of 4
+                magnitudes.push(((gx * gx + gy * gy).sqrt() / 4.0).min(1.0));
+            }
+        }
+        Self {
+            width,
+            height,
+            top_left,
+            magnitudes,
+        }
+    }
+
+    // Only the raster layers have an edge map, the transparent pixels count as black.
+    // The map follows the layer position, but not its rotation and scale
+    pub fn from_layer(layer: &Layer, framework: &Framework) -> Option<Self> {
+        let map = match &layer.layer_type {
+            LayerType::Chonky(map) => map,
+            LayerType::Image { .. } | LayerType::Group => return None,
+        };
+        let (first_chunk, texture) = map.read_into_texture(framework)?;
+        let chunk_size = map.chunk_size() as f32;
+        let position = layer.transform().position;
+        let top_left = point2(
+            (first_chunk.x as f32 - 0.5) * chunk_size + position.x,
+            first_chunk.y as f32 * chunk_size + texture.height() as f32 - chunk_size * 0.5
+                + position.y,
+        );
+        let luminance: Vec<f32> = texture
+            .data()?
+            .iter()
+            .map(|RgbaU8([r, g, b, a])| {
+                let luma = 0.2126 * *r as f32 + 0.7152 * *g as f32 + 0.0722 * *b as f32;
+                luma * *a as f32 / (255.0 * 255.0)
+            })
+            .collect();
+        Some(Self::from_luminance(&luminance, texture.width(), top_left))
+    }
+
+    fn pixel_center(&self, x: u32, y: u32) -> Point2<f32> {
+        point2(
+            self.top_left.x + x as f32 + 0.5,
+            self.top_left.y - y as f32 - 0.5,
+        )
+    }
+}
+
+// Moves the point on the center of the nearest strong edge pixel within the radius,
+// the point is left where it is when there are none
+pub fn snap_to_edge(point: Point2<f32>, edge_map: &EdgeMap, radius: f32) -> Point2<f32> {
+    let local = point.to_vec() - edge_map.top_left.to_vec();
+    let column_range = |center: f32, size: u32| {
+        let first = (center - radius).floor().max(0.0) as u32;
+        let last = ((center + radius).ceil().max(0.0) as u32).min(size);
+        first..last
+    };
+    let mut nearest: Option<(f32, f32, Point2<f32>)> = None;
+    for y in column_range(-local.y, edge_map.height) {
+        for x in column_range(local.x, edge_map.width) {
+            let magnitude = edge_map.magnitudes[(y * edge_map.width + x) as usize];
+            if magnitude < STRONG_EDGE {
+                continue;
+            }
+            let center = edge_map.pixel_center(x, y);
+            let distance = center.distance(point);
+            if distance > radius {
+                continue;
+            }
+            // Between pixels at the same distance, the strongest edge wins
+            let is_nearer = match nearest {
+                Some((best_distance, best_magnitude, _)) => {
+                    distance < best_distance
+                        || (distance == best_distance && magnitude > best_magnitude)
+                }
+                None => true,
+            };
+            if is_nearer {
+                nearest = Some((distance, magnitude, center));
+            }
+        }
+    }
+    nearest.map_or(point, |(_, _, center)| center)
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::point2;
+
+    use super::{snap_to_edge, EdgeMap, STRONG_EDGE};
+
+    // A 10x10 map, with the left half black and the right half white
+    fn vertical_step() -> EdgeMap {
+        let luminance: Vec<f32> = (0..100)
+            .map(|i| if i % 10 < 5 { 0.0 } else { 1.0 })
+            .collect();
+        EdgeMap::from_luminance(&luminance, 10, point2(0.0, 10.0))
+    }
+
+    #[test]
+    fn edges_are_found_on_both_sides_of_a_step() {
+        let edge_map = vertical_step();
+        for (i, magnitude) in edge_map.magnitudes.iter().enumerate() {
+            let is_edge = i % 10 == 4 || i % 10 == 5;
+            assert_eq!(*magnitude >= STRONG_EDGE, is_edge, "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn points_snap_to_the_nearest_edge_within_the_radius() {
+        let edge_map = vertical_step();
+        assert_eq!(
+            snap_to_edge(point2(2.2, 4.5), &edge_map, 3.0),
+            point2(4.5, 4.5)
+        );
+        assert_eq!(
+            snap_to_edge(point2(8.0, 7.6), &edge_map, 3.0),
+            point2(5.5, 7.5)
+        );
+        // Too far from the edge
+        assert_eq!(
+            snap_to_edge(point2(2.2, 4.5), &edge_map, 1.0),
+            point2(2.2, 4.5)
+        );
+        // Outside the map
+        assert_eq!(
+            snap_to_edge(point2(-20.0, 4.5), &edge_map, 3.0),
+            point2(-20.0, 4.5)
+        );
+    }
+
+    #[test]
+    fn flat_maps_leave_the_points_where_they_are() {
+        let edge_map = EdgeMap::from_luminance(&[0.5; 100], 10, point2(0.0, 10.0));
+        assert_eq!(
+            snap_to_edge(point2(5.0, 5.0), &edge_map, 5.0),
+            point2(5.0, 5.0)
+        );
+    }
+}
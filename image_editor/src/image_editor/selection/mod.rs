@@ -1,6 +1,11 @@
+mod edge_map;
 mod selection_shape;
 
-use cgmath::{ElementWise, Point2, Vector2};
+use std::collections::BTreeMap;
+
+use cgmath::{point2, vec2, ElementWise, Point2, Vector2};
+pub use edge_map::{snap_to_edge, EdgeMap};
+use framework::Box2d;
 pub use selection_shape::SelectionShape;
 
 pub use self::selection_shape::Shape;
@@ -33,7 +38,106 @@ impl From<SelectionAddition> for usize {
     }
 }
 
+// How the selection is shown on the canvas, see Document::draw_selection_overlay
+#[derive(
+    Clone, Copy, Debug, Default, strum_macros::EnumIter, strum_macros::Display, Eq, PartialEq,
+)]
+pub enum SelectionDisplay {
+    // The marching ants along the edges
+    #[default]
+    Outline = 0,
+    // A translucent tint over the selected pixels
+    OverlaySelected = 1,
+    // A translucent tint over the pixels outside the selection
+    OverlayMasked = 2,
+}
+
+impl From<usize> for SelectionDisplay {
+    fn from(n: usize) -> Self {
+        match n {
+            0 => Self::Outline,
+            1 => Self::OverlaySelected,
+            2 => Self::OverlayMasked,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl From<SelectionDisplay> for usize {
+    fn from(v: SelectionDisplay) -> Self {
+        v as usize
+    }
+}
+
 impl Selection {
+    // Builds a selection out of a mask, where every true value is a selected pixel.
+    // The rows go from top to bottom, top_left is the world position of the top left corner of the mask.
+    // Each run of selected pixels becomes a rectangle, which grows down as long as the rows below have the same run
+    pub fn from_mask(mask: &[bool], width: u32, top_left: Point2<f32>) -> Self {
+        let mut shapes = vec![];
+        let mut add_rect = |(start, end): (u32, u32), first_row: u32, last_row: u32| {
+            let extents = vec2((end - start) as f32, (last_row - first_row) as f32) * 0.5;
+            shapes.push(SelectionShape {
+                mode: SelectionAddition::Add,
+                shape: Shape::Rectangle(Box2d {
+                    center: point2(
+                        top_left.x + start as f32 + extents.x,
+                        top_left.y - first_row as f32 - extents.y,
+                    ),
+                    extents,
+                }),
+            });
+        };
+
+        // Maps each run of the previous row to the row where its rectangle begins
+        let mut open_runs: BTreeMap<(u32, u32), u32> = BTreeMap::new();
+        let rows = mask.chunks(width as usize);
+        let row_count = rows.len() as u32;
+        for (row, pixels) in rows.enumerate() {
+            let row = row as u32;
+            let mut row_runs = BTreeMap::new();
+            let mut run_start = None;
+            for x in 0..=pixels.len() {
+                let selected = pixels.get(x).copied().unwrap_or(false);
+                match (selected, run_start) {
+                    (true, None) => run_start = Some(x as u32),
+                    (false, Some(start)) => {
+                        let run = (start, x as u32);
+                        let first_row = open_runs.remove(&run).unwrap_or(row);
+                        row_runs.insert(run, first_row);
+                        run_start = None;
+                    }
+                    _ => {}
+                }
+            }
+            for (run, first_row) in std::mem::replace(&mut open_runs, row_runs) {
+                add_rect(run, first_row, row);
+            }
+        }
+        for (run, first_row) in open_runs {
+            add_rect(run, first_row, row_count);
+        }
+
+        Self {
+            shapes,
+            inverted: false,
+        }
+    }
+
+    // Builds a selection out of a closed path in world space, the last point is joined to the first one.
+    // The path is filled with the even-odd rule, so the areas enclosed twice by a self intersecting path are left out
+    pub fn from_path(path: &[Point2<f32>], mode: SelectionAddition) -> Self {
+        let (mask, width, top_left) = rasterize_path(path);
+        if width == 0 {
+            return Self::default();
+        }
+        let mut selection = Self::from_mask(&mask, width, top_left);
+        for shape in selection.shapes.iter_mut() {
+            shape.mode = mode;
+        }
+        selection
+    }
+
     pub fn set(&mut self, new_selection: SelectionShape) {
         self.shapes = vec![new_selection];
         self.inverted = false;
@@ -69,14 +173,24 @@ impl Selection {
             }
         }
     }
+    pub fn shapes(&self) -> &[SelectionShape] {
+        &self.shapes
+    }
+
     pub fn is_empty(&self) -> bool {
         self.shapes.is_empty()
     }
 
+    // Like the stencil buffer, the last shape covering the point decides whether it's selected
     pub fn contains(&self, point: Point2<f32>) -> bool {
-        let inside_selection = self.shapes.iter().any(|shape| match shape.shape {
-            Shape::Rectangle(area) => area.contains_point(point.clone()),
-        });
+        let inside_selection = self
+            .shapes
+            .iter()
+            .rev()
+            .find(|shape| match shape.shape {
+                Shape::Rectangle(area) => area.contains_point(point.clone()),
+            })
+            .map_or(false, |shape| shape.mode == SelectionAddition::Add);
 
         if self.inverted {
             return !inside_selection;
@@ -86,6 +200,46 @@ impl Selection {
     }
 }
 
+// Returns the mask of the pixels whose center is inside the path, along with its width and the world
+// position of its top left corner, in the same layout used by Selection::from_mask
+fn rasterize_path(path: &[Point2<f32>]) -> (Vec<bool>, u32, Point2<f32>) {
+    if path.len() < 3 {
+        return (vec![], 0, point2(0.0, 0.0));
+    }
+    let (mut min, mut max) = (path[0], path[0]);
+    for point in path {
+        min = point2(min.x.min(point.x), min.y.min(point.y));
+        max = point2(max.x.max(point.x), max.y.max(point.y));
+    }
+    let top_left = point2(min.x.floor(), max.y.ceil());
+    let width = (max.x.ceil() - top_left.x) as u32;
+    let height = (top_left.y - min.y.floor()) as u32;
+
+    let mut mask = vec![false; (width * height) as usize];
+    let mut crossings = vec![];
+    for row in 0..height {
+        let y = top_left.y - row as f32 - 0.5;
+        crossings.clear();
+        for (i, a) in path.iter().enumerate() {
+            let b = path[(i + 1) % path.len()];
+            if (a.y > y) != (b.y > y) {
+                crossings.push(a.x + (y - a.y) * (b.x - a.x) / (b.y - a.y));
+            }
+        }
+        crossings.sort_by(|a, b| a.total_cmp(b));
+
+        // Even-odd rule: the pixels between the first and the second crossing are inside,
+        // the ones between the second and the third are outside, and so on
+        let first_pixel = |x: f32| (x - top_left.x - 0.5).ceil().clamp(0.0, width as f32) as u32;
+        for span in crossings.chunks_exact(2) {
+            for column in first_pixel(span[0])..first_pixel(span[1]) {
+                mask[(row * width + column) as usize] = true;
+            }
+        }
+    }
+    (mask, width, top_left)
+}
+
 #[cfg(test)]
 mod test {
     use cgmath::{point2, vec2};
@@ -93,7 +247,7 @@ mod test {
 
     use crate::selection::{selection_shape::Shape, SelectionAddition::*};
 
-    use super::{Selection, SelectionShape};
+    use super::{rasterize_path, Selection, SelectionShape};
 
     #[test]
     pub fn assert_two_rect_contains_point() {
@@ -138,4 +292,82 @@ mod test {
         assert!(!selection.contains(point2(12.5, 12.5)));
         assert!(selection.contains(point2(17.5, 12.5)));
     }
+
+    #[test]
+    pub fn subtracted_shapes_compose_in_order() {
+        let rect = |x: f32, y: f32, mode| SelectionShape {
+            shape: Shape::Rectangle(Box2d {
+                center: point2(x, y),
+                extents: vec2(5.0, 5.0),
+            }),
+            mode,
+        };
+        let mut selection = Selection::default();
+        selection.extend(rect(0.0, 0.0, Add));
+        selection.extend(rect(6.0, 0.0, Add));
+        selection.extend(rect(3.0, 6.0, Add));
+        selection.extend(rect(3.0, 0.0, Subtract));
+
+        assert!(selection.contains(point2(-4.0, 0.0)));
+        assert!(selection.contains(point2(10.0, 0.0)));
+        assert!(selection.contains(point2(3.0, 8.0)));
+        assert!(!selection.contains(point2(3.0, 0.0)));
+        assert!(!selection.contains(point2(3.0, -4.0)));
+        assert!(!selection.contains(point2(20.0, 20.0)));
+
+        // A shape added after the subtraction selects the pixels again
+        selection.extend(rect(3.0, -2.0, Add));
+        assert!(selection.contains(point2(3.0, 0.0)));
+    }
+
+    #[test]
+    pub fn selection_from_mask() {
+        // A 2x2 square with a pixel sticking out below it
+        let mask = [
+            false, false, false, false, //
+            false, true, true, false, //
+            false, true, true, false, //
+            false, false, true, false, //
+        ];
+        let selection = Selection::from_mask(&mask, 4, point2(-2.0, 2.0));
+        assert_eq!(selection.shapes.len(), 2);
+
+        for (x, y) in [(1, 1), (2, 1), (1, 2), (2, 2), (2, 3)] {
+            let pixel_center = point2(-2.0 + x as f32 + 0.5, 2.0 - y as f32 - 0.5);
+            assert!(selection.contains(pixel_center));
+        }
+        for (x, y) in [(0, 0), (3, 1), (1, 3), (0, 2)] {
+            let pixel_center = point2(-2.0 + x as f32 + 0.5, 2.0 - y as f32 - 0.5);
+            assert!(!selection.contains(pixel_center));
+        }
+    }
+
+    #[test]
+    pub fn concave_paths_are_rasterized_with_the_notch_left_out() {
+        // A 6x6 U shape, open at the top, with a 2x4 notch in the middle
+        let path = [
+            point2(0.0, 0.0),
+            point2(6.0, 0.0),
+            point2(6.0, 6.0),
+            point2(4.0, 6.0),
+            point2(4.0, 2.0),
+            point2(2.0, 2.0),
+            point2(2.0, 6.0),
+            point2(0.0, 6.0),
+        ];
+        let (mask, width, top_left) = rasterize_path(&path);
+        assert_eq!((width, mask.len()), (6, 36));
+        assert_eq!(top_left, point2(0.0, 6.0));
+        for (i, selected) in mask.iter().enumerate() {
+            let (x, y) = (i % 6, i / 6);
+            let in_notch = (2..4).contains(&x) && y < 4;
+            assert_eq!(*selected, !in_notch, "pixel ({x}, {y})");
+        }
+
+        let selection = Selection::from_path(&path, Subtract);
+        assert!(selection.shapes.iter().all(|shape| shape.mode == Subtract));
+        assert!(selection.contains(point2(1.0, 5.0)));
+        assert!(!selection.contains(point2(3.0, 5.0)));
+        assert!(selection.contains(point2(3.0, 1.0)));
+    }
 }
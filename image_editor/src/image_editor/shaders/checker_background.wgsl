@@ -0,0 +1,16 @@
+//@include :common_definitions
+//@include :2d_definitions
+
+@group(2) @binding(0) var diffuse: texture_2d<f32>;
+@group(2) @binding(1) var s_diffuse: sampler;
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let square_size = 8.0;
+    let pixel = in.tex_uv * vec2<f32>(in.scale.x, in.scale.y) * 2.0;
+    let square = vec2<i32>(floor(pixel / square_size));
+    if ((square.x + square.y) % 2 == 0) {
+        return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    }
+    return vec4<f32>(0.8, 0.8, 0.8, 1.0);
+}
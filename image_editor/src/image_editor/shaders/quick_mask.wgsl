@@ -0,0 +1,12 @@
+//@include :common_definitions
+//@include :2d_definitions
+
+@group(2) @binding(0) var diffuse: texture_2d<f32>;
+@group(2) @binding(1) var s_diffuse: sampler;
+
+// Only the alpha of the mask matters: it's tinted with the multiply color, premultiplied
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let alpha = textureSample(diffuse, s_diffuse, in.tex_uv).a * in.multiply_color.a;
+    return vec4<f32>(in.multiply_color.rgb * alpha, alpha);
+}
@@ -0,0 +1,19 @@
+//@include :common_definitions
+//@include :2d_definitions
+
+@group(2) @binding(0) var diffuse: texture_2d<f32>;
+@group(2) @binding(1) var s_diffuse: sampler;
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // The quad is drawn one pixel bigger than the rectangle, so that the pixels partially
+    // covered by its edges get a fragment too
+    let local = in.position.xy * in.scale.xy;
+    let half_size = in.scale.xy - vec2<f32>(1.0, 1.0);
+    // Negative inside the rectangle, positive outside
+    let q = abs(local) - half_size;
+    let signed_distance = length(max(q, vec2<f32>(0.0, 0.0))) + min(max(q.x, q.y), 0.0);
+    let pixel_size = max(fwidth(local.x), 0.0001);
+    let coverage = clamp(0.5 - signed_distance / pixel_size, 0.0, 1.0);
+    return in.multiply_color * coverage;
+}
@@ -0,0 +1,20 @@
+//@include :common_definitions
+//@include :2d_definitions
+
+@group(2) @binding(0) var diffuse: texture_2d<f32>;
+@group(2) @binding(1) var s_diffuse: sampler;
+
+@group(3) @binding(0) var stencil: texture_2d<u32>;
+
+// TINTED_VALUE is replaced when the shader is compiled: the pixels with this stencil value are
+// tinted with the multiply color, 255 for the selected ones and 0 for the others
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let dimensions = vec2<i32>(textureDimensions(stencil));
+    let texel = vec2<i32>(vec2<f32>(in.tex_uv.x, 1.0 - in.tex_uv.y) * vec2<f32>(dimensions));
+    let value = textureLoad(stencil, clamp(texel, vec2<i32>(0), dimensions - vec2<i32>(1)), 0).r;
+    if (value != u32(TINTED_VALUE)) {
+        discard;
+    }
+    return vec4<f32>(in.multiply_color.rgb * in.multiply_color.a, in.multiply_color.a);
+}
@@ -0,0 +1,104 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use framework::{
+    framework::TextureId, ColorSpace, Framework, RgbaTexture2D, RgbaU8, Texture,
+    TextureConfiguration, TextureUsage,
+};
+use once_cell::sync::OnceCell;
+
+use super::{
+    define_editor_shaders,
+    document::{Document, DocumentCreationInfo},
+    init_globals,
+    layers::LayerId,
+};
+
+static HEADLESS_FRAMEWORK: OnceCell<Mutex<Framework>> = OnceCell::new();
+
+// A framework without a window, ready to be used by the editor in the tests.
+// The globals are created on the first framework that asks for them, so every headless user in the
+// process shares this one instance: panics when there's no GPU adapter.
+pub fn headless_framework() -> MutexGuard<'static, Framework> {
+    HEADLESS_FRAMEWORK
+        .get_or_init(|| {
+            let mut framework =
+                Framework::new(&Default::default()).expect("No GPU adapter is available");
+            define_editor_shaders(&mut framework);
+            init_globals(&mut framework);
+            Mutex::new(framework)
+        })
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+// A document with the white "Background Layer" and the transparent "Layer 0" above it
+pub fn test_document(width: u32, height: u32, framework: &mut Framework) -> Document {
+    Document::new(
+        DocumentCreationInfo {
+            width,
+            height,
+            first_layer_color: [0.0; 4],
+        },
+        framework,
+    )
+    .unwrap()
+}
+
+// The white layer every new document starts with
+pub fn background_layer_id(document: &Document) -> LayerId {
+    let mut background_layer = None;
+    document.for_each_layer(|layer, id| {
+        if layer.settings().name == "Background Layer" {
+            background_layer = Some(*id);
+        }
+    });
+    background_layer.expect("The document has no background layer")
+}
+
+// The pixels of a layer read back from the GPU, see Layer::read_pixels
+pub fn layer_texels(document: &Document, layer: &LayerId, framework: &Framework) -> Vec<RgbaU8> {
+    document
+        .get_layer(layer)
+        .read_pixels(framework)
+        .expect("The layer has no pixels")
+        .data()
+        .unwrap()
+        .to_vec()
+}
+
+// A transparent texture to render on, read back with texture_texels
+pub fn render_target(
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    framework: &mut Framework,
+) -> TextureId {
+    framework.allocate_texture2d(
+        RgbaTexture2D::empty((width, height)),
+        TextureConfiguration {
+            label: Some("Test render target"),
+            usage: TextureUsage::RWRT,
+            mip_count: None,
+            color_space,
+        },
+    )
+}
+
+// The channels of each texel, easier to compare than the texels themselves
+pub fn texel_channels(texture: &RgbaTexture2D) -> Vec<[u8; 4]> {
+    texture
+        .data()
+        .unwrap()
+        .iter()
+        .map(|texel| texel.0)
+        .collect()
+}
+
+pub fn texture_texels(texture: &TextureId, framework: &Framework) -> Vec<RgbaU8> {
+    framework
+        .texture2d_read_data(texture)
+        .unwrap()
+        .data()
+        .unwrap()
+        .to_vec()
+}
@@ -0,0 +1,167 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use framework::RgbaU8;
+use tiff::{
+    decoder::{Decoder, DecodingResult},
+    encoder::{colortype, TiffEncoder},
+    tags::Tag,
+    ColorType, TiffError, TiffResult,
+};
+
+// The tiff crate has no name for the PageName tag
+const PAGE_NAME: Tag = Tag::Unknown(285);
+// A private tag for the color label of the layer: the public ones, like ImageDescription,
+// are filled by other programs with text that isn't a label
+const PAGE_LABEL: Tag = Tag::Unknown(65000);
+
+// One page of a multi-page TIFF, the texels are stored row by row from the top left corner
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TiffPage {
+    pub name: Option<String>,
+    pub label: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<RgbaU8>,
+}
+
+// The pages are written uncompressed as 8 bit RGBA
+pub(crate) fn write_tiff(path: &Path, pages: &[TiffPage]) -> TiffResult<()> {
+    let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path)?))?;
+    for page in pages {
+        let mut image = encoder.new_image::<colortype::RGBA8>(page.width, page.height)?;
+        if let Some(name) = &page.name {
+            image.encoder().write_tag(PAGE_NAME, name.as_str())?;
+        }
+        if let Some(label) = &page.label {
+            image.encoder().write_tag(PAGE_LABEL, label.as_str())?;
+        }
+        image.write_data(bytemuck::cast_slice(&page.texels))?;
+    }
+    Ok(())
+}
+
+// The 8 bit gray and RGB pages are converted to RGBA. The pages that can't be decoded,
+// e.g. because of an unsupported compression, become transparent so that the others are kept
+pub(crate) fn read_tiff(path: &Path) -> TiffResult<Vec<TiffPage>> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+    let mut pages = vec![];
+    loop {
+        let (width, height) = decoder.dimensions()?;
+        // The size of the page doesn't fit in memory on this platform
+        let texel_count = (width as usize)
+            .checked_mul(height as usize)
+            .ok_or(TiffError::IntSizeError)?;
+        let name = decoder.get_tag_ascii_string(PAGE_NAME).ok();
+        let label = decoder.get_tag_ascii_string(PAGE_LABEL).ok();
+        let color_type = decoder.colortype()?;
+        let texels = match decoder.read_image() {
+            Ok(DecodingResult::U8(data)) => convert_texels(&data, color_type),
+            Ok(_) => None,
+            Err(TiffError::UnsupportedError(e)) => {
+                log::warn!("TIFF page {}: {e}", pages.len());
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        let texels = texels.unwrap_or_else(|| {
+            log::warn!(
+                "TIFF page {} ({color_type:?}) can't be read, it's replaced by a transparent page",
+                pages.len()
+            );
+            vec![RgbaU8([0; 4]); texel_count]
+        });
+        pages.push(TiffPage {
+            name,
+            label,
+            width,
+            height,
+            texels,
+        });
+        if !decoder.more_images() {
+            return Ok(pages);
+        }
+        decoder.next_image()?;
+    }
+}
+
+fn convert_texels(data: &[u8], color_type: ColorType) -> Option<Vec<RgbaU8>> {
+    let texels = match color_type {
+        ColorType::RGBA(8) => data
+            .chunks_exact(4)
+            .map(|c| RgbaU8([c[0], c[1], c[2], c[3]]))
+            .collect(),
+        ColorType::RGB(8) => data
+            .chunks_exact(3)
+            .map(|c| RgbaU8([c[0], c[1], c[2], 255]))
+            .collect(),
+        ColorType::GrayA(8) => data
+            .chunks_exact(2)
+            .map(|c| RgbaU8([c[0], c[0], c[0], c[1]]))
+            .collect(),
+        ColorType::Gray(8) => data.iter().map(|g| RgbaU8([*g, *g, *g, 255])).collect(),
+        _ => return None,
+    };
+    Some(texels)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, io::BufWriter};
+
+    use framework::RgbaU8;
+    use tiff::{
+        encoder::{colortype, TiffEncoder},
+        tags::Tag,
+    };
+
+    use super::{read_tiff, write_tiff, TiffPage};
+
+    #[test]
+    fn pages_keep_their_names_and_pixels() {
+        let path =
+            std::env::temp_dir().join(format!("mochi_tiff_pages_{}.tiff", std::process::id()));
+        let pages = vec![
+            TiffPage {
+                name: Some("Background".to_owned()),
+                label: Some("Green".to_owned()),
+                width: 2,
+                height: 1,
+                texels: vec![RgbaU8([255, 0, 0, 255]), RgbaU8([0, 255, 0, 128])],
+            },
+            TiffPage {
+                name: None,
+                label: None,
+                width: 1,
+                height: 2,
+                texels: vec![RgbaU8([0, 0, 255, 255]), RgbaU8([0; 4])],
+            },
+        ];
+        write_tiff(&path, &pages).unwrap();
+        assert_eq!(read_tiff(&path).unwrap(), pages);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn the_image_description_is_not_a_label() {
+        let path = std::env::temp_dir().join(format!(
+            "mochi_tiff_description_{}.tiff",
+            std::process::id()
+        ));
+        let mut encoder = TiffEncoder::new(BufWriter::new(File::create(&path).unwrap())).unwrap();
+        let mut image = encoder.new_image::<colortype::RGBA8>(1, 1).unwrap();
+        image
+            .encoder()
+            .write_tag(Tag::ImageDescription, "Scanned with a flatbed scanner")
+            .unwrap();
+        image.write_data(&[255, 255, 255, 255]).unwrap();
+        drop(encoder);
+
+        let pages = read_tiff(&path).unwrap();
+        assert_eq!(pages[0].label, None);
+        std::fs::remove_file(path).unwrap();
+    }
+}